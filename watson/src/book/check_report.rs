@@ -0,0 +1,485 @@
+use crate::{
+    book::{
+        Badge, badge_title,
+        html::{Html, html},
+        statement::statement_html,
+        template::TEMPLATES,
+    },
+    context::Ctx,
+    diagnostics::{Diagnostic, DiagnosticLevel},
+    parse::ParseReport,
+    report::ProofReport,
+    semant::{
+        fragment::formal_frag,
+        proof_kernel::{AuditEntry, AuditStep},
+        proof_status::{ProofStatus, ProofStatuses},
+        theorems::TheoremId,
+    },
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RESET},
+        plural,
+    },
+};
+use rustc_hash::FxHashMap;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+    time::Duration,
+};
+
+/// Write a static HTML report of a check to `dir`: an index listing every
+/// theorem and a page for each theorem with its statement, dependencies,
+/// diagnostics and proof outline.
+///
+/// Unlike the book this is keyed by theorem rather than by the prose, and it
+/// is written even when there are errors since that is when it is most
+/// useful.
+pub fn write_check_report<'ctx>(
+    dir: &Path,
+    parse_report: &ParseReport<'ctx>,
+    report: &ProofReport<'ctx>,
+    elapsed: Option<Duration>,
+    ctx: &Ctx<'ctx>,
+) -> PathBuf {
+    LazyLock::force(&TEMPLATES);
+    if dir.exists() {
+        fs::remove_dir_all(dir).expect("Failed to remove old report directory");
+    }
+    let theorems_dir = dir.join("theorems");
+    fs::create_dir_all(&theorems_dir).expect("Failed to create report directory");
+
+    fs::write(dir.join("styles.css"), include_str!("templates/styles.css")).unwrap();
+    fs::write(dir.join("report.css"), include_str!("templates/report.css")).unwrap();
+
+    let mut theorems: Vec<_> = report.statuses.iter().map(|(&thm, _)| thm).collect();
+    theorems.sort_by_key(|&thm| {
+        let order = match Badge::for_theorem(thm, &report.statuses) {
+            Badge::Failed => 0,
+            Badge::Incomplete => 1,
+            Badge::Fragile => 2,
+            _ => 3,
+        };
+        (order, thm.name())
+    });
+
+    let diagnostics = diagnostics_by_theorem(parse_report, ctx);
+    let dependents = dependents(report);
+
+    let index = render_index(&theorems, report, &diagnostics, elapsed, ctx);
+    let index = page("Check report", "", &index);
+    fs::write(dir.join("index.html"), index.as_str()).unwrap();
+
+    for &thm in &theorems {
+        let status = &report.statuses[thm];
+        let no_diags = Vec::new();
+        let diags = diagnostics.get(&thm).unwrap_or(&no_diags);
+        let no_dependents = Vec::new();
+        let dependents = dependents.get(&thm).unwrap_or(&no_dependents);
+
+        let content = render_theorem(thm, status, report, dependents, diags, ctx);
+        let title = format!("{} - Check report", thm.name());
+        let content = page(&title, "../", &content);
+        fs::write(theorems_dir.join(theorem_file(thm)), content.as_str()).unwrap();
+    }
+
+    let full_path = dir.canonicalize().unwrap();
+    println!(
+        "{ANSI_GREEN}{ANSI_BOLD}Created report{ANSI_RESET} at {}",
+        full_path.display()
+    );
+
+    full_path
+}
+
+fn page(title: &str, base_path: &str, content: &Html) -> Html {
+    TEMPLATES.report_layout.fill(&[
+        ("PAGE_TITLE", &Html::text(title)),
+        ("BASE_PATH", &Html::raw(base_path)),
+        ("CONTENT", content),
+    ])
+}
+
+/// Names can only contain letters, numbers, `_`, `'` and `.` so they are
+/// safe to use as file names.
+fn theorem_file(thm: TheoremId) -> String {
+    format!("{}.html", thm.name())
+}
+
+/// Find the diagnostics for each theorem. A diagnostic belongs to a theorem
+/// if it was reported while checking its proof or if it points into the
+/// command which declares it.
+fn diagnostics_by_theorem<'a, 'ctx>(
+    parse_report: &ParseReport<'ctx>,
+    ctx: &'a Ctx<'ctx>,
+) -> FxHashMap<TheoremId<'ctx>, Vec<&'a Diagnostic<'ctx>>> {
+    let commands: Vec<_> = parse_report
+        .command_theorems
+        .iter()
+        .map(|(tree, &thm)| (tree.span(), thm))
+        .collect();
+
+    let mut by_theorem: FxHashMap<_, Vec<_>> = FxHashMap::default();
+    for diag in ctx.diags.iter() {
+        let thm = diag.theorem().or_else(|| {
+            let span = diag.primary_span()?;
+            commands
+                .iter()
+                .find(|(command, _)| {
+                    command.source() == span.source()
+                        && command.start().byte_offset() <= span.start().byte_offset()
+                        && span.end().byte_offset() <= command.end().byte_offset()
+                })
+                .map(|&(_, thm)| thm)
+        });
+
+        if let Some(thm) = thm {
+            by_theorem.entry(thm).or_default().push(diag);
+        }
+    }
+
+    by_theorem
+}
+
+/// The theorems which use each theorem, sorted by name.
+fn dependents<'ctx>(
+    report: &ProofReport<'ctx>,
+) -> FxHashMap<TheoremId<'ctx>, Vec<TheoremId<'ctx>>> {
+    let mut dependents: FxHashMap<_, Vec<_>> = FxHashMap::default();
+    for (&thm, status) in report.statuses.iter() {
+        for &used in status.theorems_used() {
+            dependents.entry(used).or_default().push(thm);
+        }
+    }
+    for users in dependents.values_mut() {
+        users.sort_by_key(|thm| thm.name());
+    }
+    dependents
+}
+
+fn render_index<'ctx>(
+    theorems: &[TheoremId<'ctx>],
+    report: &ProofReport<'ctx>,
+    diagnostics: &FxHashMap<TheoremId<'ctx>, Vec<&Diagnostic<'ctx>>>,
+    elapsed: Option<Duration>,
+    ctx: &Ctx<'ctx>,
+) -> Html {
+    let statuses = &report.statuses;
+    let mut out = Html::default();
+
+    out += html!("<h1>Check report</h1>\n");
+    out += html!(r#"<div class="summary">"#);
+    out += html!(
+        "<span>{} theorem{}</span><span>{} correct</span><span>{} with todo</span><span>{} fragile</span><span>{} with errors</span>",
+        statuses.total_cnt(),
+        Html::raw(plural(statuses.total_cnt())),
+        statuses.correct_cnt(),
+        statuses.todo_cnt(),
+        statuses.fragile_cnt(),
+        statuses.error_cnt()
+    );
+    if !report.circularities.is_empty() {
+        out += html!(
+            "<span>{} circular dependency group{}</span>",
+            report.circularities.len(),
+            Html::raw(plural(report.circularities.len()))
+        );
+    }
+    if let Some(elapsed) = elapsed {
+        out += html!("<span>{}ms</span>", elapsed.as_millis());
+    }
+    out += html!("</div>\n");
+
+    out += html!(r#"<table class="theorem-table">"#);
+    out += html!(
+        "<tr><th>Theorem</th><th>Kind</th><th>Status</th><th>Diagnostics</th><th>Location</th></tr>\n"
+    );
+    for &thm in theorems {
+        let diag_cnt = diagnostics.get(&thm).map_or(0, Vec::len);
+        out += html!(
+            "<tr><td><a href=\"theorems/{}\">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td class=\"location\">{}</td></tr>\n",
+            Html::text(&theorem_file(thm)),
+            Html::text(&thm.name()),
+            Html::raw(thm.kind().keyword()),
+            render_badge(thm, statuses),
+            diag_cnt,
+            Html::text(&location(thm, ctx))
+        );
+    }
+    out += html!("</table>\n");
+
+    out
+}
+
+fn render_theorem<'ctx>(
+    thm: TheoremId<'ctx>,
+    status: &ProofStatus<'ctx>,
+    report: &ProofReport<'ctx>,
+    dependents: &[TheoremId<'ctx>],
+    diagnostics: &[&Diagnostic<'ctx>],
+    ctx: &Ctx<'ctx>,
+) -> Html {
+    let mut out = Html::default();
+
+    out += html!(r#"<p><a href="../index.html">← All theorems</a></p>"#);
+    out += html!(
+        "<h1>{} {}</h1>\n",
+        Html::raw(thm.kind().label()),
+        Html::text(&thm.name())
+    );
+    out += html!(
+        "<div class=\"status\"><span class=\"location\">{}</span>{}</div>\n",
+        Html::text(&location(thm, ctx)),
+        render_badge(thm, &report.statuses)
+    );
+
+    out += html!(
+        "<div class=\"statement\">{}</div>\n",
+        statement_html(thm, status.frag_renderings())
+    );
+    if let Some(reason) = status.fragile() {
+        // Slow tactics are the usual reason a proof is fragile.
+        let duration = status
+            .duration()
+            .map(|duration| {
+                html!(
+                    " <span class=\"duration\">({}ms)</span>",
+                    duration.as_millis()
+                )
+            })
+            .unwrap_or_default();
+        out += html!(
+            "<p class=\"fragile\">Fragile: {}{}</p>\n",
+            Html::text(reason),
+            duration
+        );
+    }
+    // A prover can settle one goal of a proof which still fails.
+    if status.correct()
+        && let Some((strategy, duration)) = status.prover()
+    {
+        out += html!(
+            "<p class=\"prover\">Proved by {} in {:.1}s</p>\n",
+            Html::text(strategy),
+            duration.as_secs_f64()
+        );
+    }
+    out += html!(
+        "<p class=\"content-hash\">Content hash <code>{}</code></p>\n",
+        Html::text(&thm.content_hash().to_string())
+    );
+
+    if !diagnostics.is_empty() {
+        out += html!("<h2>Diagnostics</h2>\n");
+        for diag in diagnostics {
+            let class = match diag.level() {
+                DiagnosticLevel::Error => "error",
+                DiagnosticLevel::Warning => "warning",
+                DiagnosticLevel::Info => "info",
+            };
+            out += html!(
+                "<div class=\"diagnostic {}\">{}</div>\n",
+                Html::raw(class),
+                Html::text(&diag.render_plain(ctx))
+            );
+        }
+    }
+
+    let mut dependencies: Vec<_> = status.theorems_used().iter().copied().collect();
+    dependencies.sort_by_key(|thm| thm.name());
+    render_theorem_list(&mut out, "Uses", &dependencies);
+    render_theorem_list(&mut out, "Used by", dependents);
+    if !status.is_axiom() {
+        render_theorem_list(&mut out, "Axioms", status.axioms_used());
+    }
+
+    if !status.audit_log().is_empty() {
+        out += html!("<h2>Proof outline</h2>\n");
+        out += html!(r#"<ol class="outline">"#);
+        for entry in status.audit_log() {
+            render_audit_entry(&mut out, entry, ctx);
+        }
+        out += html!("</ol>\n");
+    }
+
+    out
+}
+
+fn render_theorem_list(out: &mut Html, title: &str, theorems: &[TheoremId]) {
+    if theorems.is_empty() {
+        return;
+    }
+
+    *out += html!("<h2>{}</h2>\n<ul>", Html::text(title));
+    for &thm in theorems {
+        *out += html!(
+            r#"<li><a href="{}">{}</a></li>"#,
+            Html::text(&theorem_file(thm)),
+            Html::text(&thm.name())
+        );
+    }
+    *out += html!("</ul>\n");
+}
+
+fn render_audit_entry<'ctx>(out: &mut Html, entry: &AuditEntry<'ctx>, ctx: &Ctx<'ctx>) {
+    let print = |frag| formal_frag(frag, ctx).print();
+
+    *out += html!(
+        r#"<li style="padding-left: {}em"><span class="step">"#,
+        2 * entry.depth()
+    );
+    let fact = match entry.step() {
+        AuditStep::Assume(assumption) => {
+            *out += html!("assume</span> ");
+            print(*assumption)
+        }
+        AuditStep::Discharge {
+            assumption,
+            justifying,
+        } => {
+            *out += html!("discharge</span> ");
+            format!("{} |- {}", print(*assumption), print(*justifying))
+        }
+        AuditStep::ApplyTheorem {
+            theorem,
+            conclusion,
+            ..
+        } => {
+            *out += html!(
+                r#"by</span> <a href="{}">{}</a>: "#,
+                Html::text(&theorem_file(*theorem)),
+                Html::text(&theorem.name())
+            );
+            print(*conclusion)
+        }
+        AuditStep::Todo { justifying, note } => {
+            *out += html!("todo</span> ");
+            if let Some(reason) = &note.reason {
+                *out += html!("({}) ", Html::text(reason));
+            }
+            if let Some(ticket) = &note.ticket {
+                *out += html!(r#"<span class="ticket">{}</span> "#, Html::text(ticket));
+            }
+            print(*justifying)
+        }
+        AuditStep::Error(justifying) => {
+            *out += html!("error</span> ");
+            print(*justifying)
+        }
+        AuditStep::Unfold {
+            abbreviation,
+            unfolded,
+            ..
+        } => {
+            *out += html!("unfold</span> {}: ", Html::text(&abbreviation.name()));
+            print(*unfolded)
+        }
+        AuditStep::Fold {
+            abbreviation,
+            justifying,
+        } => {
+            *out += html!("fold</span> {}: ", Html::text(&abbreviation.name()));
+            print(*justifying)
+        }
+        AuditStep::Subgoal(goal) => {
+            *out += html!("subgoal</span> ");
+            print(*goal)
+        }
+    };
+    out.push_text(&fact);
+    if let Some(span) = entry.span()
+        && ctx.sources.has_source(span.source())
+    {
+        *out += html!(
+            r#" <span class="location">{}:{}</span>"#,
+            Html::text(&span.source().name()),
+            ctx.sources.line_col(span.start()).0
+        );
+    }
+    *out += html!("</li>\n");
+}
+
+fn render_badge<'ctx>(thm: TheoremId<'ctx>, statuses: &ProofStatuses<'ctx>) -> Html {
+    let badge = Badge::for_theorem(thm, statuses);
+    let text = Html::raw(badge.text());
+    match badge_title(thm, statuses) {
+        Some(title) => html!(
+            r#"<span class="badge {}" title="{}">{}</span>"#,
+            text,
+            Html::text(&title),
+            text
+        ),
+        None => html!(r#"<span class="badge {}">{}</span>"#, text, text),
+    }
+}
+
+fn location(thm: TheoremId, ctx: &Ctx) -> String {
+    let span = thm.name_span();
+    format!(
+        "{}:{}",
+        span.source().name(),
+        ctx.sources.line_col(span.start()).0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_check_report;
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+    use std::{fs, path::Path};
+
+    /// The targets of the relative links in the HTML file at `path`.
+    fn relative_links(path: &Path) -> Vec<String> {
+        let page = fs::read_to_string(path).unwrap();
+        (page.split("href=\"").skip(1))
+            .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+            .filter(|href| !href.contains("://"))
+            .collect()
+    }
+
+    #[test]
+    fn failed_theorems_come_first_with_their_diagnostics() {
+        let project = TestProject::with_logic(
+            "module logic\n\n\
+             axiom one :\n    |- ⊥ → ⊥\nend\n\n\
+             theorem good :\n    |- ⊥ → ⊥\nproof\n    by one\nqed\n\n\
+             theorem bad :\n    |- ⊥\nproof\n    by one\nqed\n\n\
+             theorem uses.good :\n    |- ⊥ → ⊥\nproof\n    by good\nqed\n",
+        );
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let errors: Vec<_> = (checked.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title())
+            .collect();
+        assert_eq!(errors, ["tactic for theorem `bad` did not prove goal"]);
+
+        let dir = write_check_report(
+            &project.path("build/report"),
+            checked.parse_report(),
+            checked.proof_report(),
+            None,
+            checked.ctx(),
+        );
+
+        let index = fs::read_to_string(dir.join("index.html")).unwrap();
+        let first = index.find("theorems/").unwrap();
+        assert!(index[first..].starts_with("theorems/bad.html"), "{index}");
+
+        let bad = fs::read_to_string(dir.join("theorems/bad.html")).unwrap();
+        assert!(bad.contains(errors[0]), "{bad}");
+
+        let mut pages = vec![dir.join("index.html")];
+        for entry in fs::read_dir(dir.join("theorems")).unwrap() {
+            pages.push(entry.unwrap().path());
+        }
+        for page in pages {
+            for link in relative_links(&page) {
+                let target = page.parent().unwrap().join(&link);
+                assert!(target.exists(), "{} links to {link}", page.display());
+            }
+        }
+    }
+}
@@ -0,0 +1,647 @@
+use crate::{
+    book::{
+        DocState,
+        html::{Html, html},
+        is_plausible_language_tag, is_plausible_url,
+    },
+    context::Ctx,
+    diagnostics::{Diagnostic, print_diagnostics},
+    util::ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RESET},
+};
+use rustc_hash::FxHashSet;
+use std::{
+    env, fmt, fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use zip::{CompressionMethod, DateTime, ZipWriter, result::ZipResult, write::SimpleFileOptions};
+
+/// How math is written in the EPUB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EpubMath {
+    /// Only KaTeX's MathML.
+    Mathml,
+    /// Math rendered to images.
+    Image,
+    /// KaTeX's HTML, as in the website. Most readers show it well enough
+    /// without KaTeX's fonts.
+    KatexHtml,
+}
+
+impl FromStr for EpubMath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mathml" => Ok(Self::Mathml),
+            "image" => Ok(Self::Image),
+            "katex-html" => Ok(Self::KatexHtml),
+            _ => Err(format!(
+                "expected `mathml`, `image` or `katex-html`, found `{s}`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for EpubMath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Mathml => "mathml",
+            Self::Image => "image",
+            Self::KatexHtml => "katex-html",
+        })
+    }
+}
+
+impl EpubMath {
+    /// Only KaTeX's HTML can be written so far.
+    pub fn is_supported(self) -> bool {
+        self == Self::KatexHtml
+    }
+}
+
+/// Write the rendered book to an EPUB 3 file at `path`. Each chapter is an
+/// XHTML file and the table of contents is built from the outline. Links
+/// between chapters are rewritten to point into the package and any which
+/// don't resolve are reported as warnings.
+pub(super) fn write_epub(doc: &DocState, path: &Path, ctx: &Ctx) -> PathBuf {
+    let book = ctx.config.book();
+    let language = book
+        .language()
+        .filter(|lang| is_plausible_language_tag(lang))
+        .unwrap_or("en");
+    let language = Html::text(language);
+
+    let chapters: Vec<_> = doc
+        .chapter_contents
+        .iter()
+        .map(|content| rewrite_links(content.as_str()))
+        .collect();
+    let diags = check_links(&chapters);
+    print_diagnostics(&diags, ctx);
+
+    // The mimetype has to be the first entry so readers can identify the
+    // file from its first bytes.
+    let mut files = vec![
+        ("mimetype".to_string(), "application/epub+zip".to_string()),
+        (
+            "META-INF/container.xml".to_string(),
+            CONTAINER_XML.to_string(),
+        ),
+        (
+            "OEBPS/content.opf".to_string(),
+            render_package(doc, &chapters, &language, ctx)
+                .as_str()
+                .to_string(),
+        ),
+        (
+            "OEBPS/nav.xhtml".to_string(),
+            render_nav(doc, &language, ctx).as_str().to_string(),
+        ),
+        (
+            "OEBPS/toc.ncx".to_string(),
+            render_ncx(doc, ctx).as_str().to_string(),
+        ),
+    ];
+    // KaTeX's stylesheet isn't included, so its MathML is hidden the way
+    // that stylesheet does it, which keeps it for screen readers.
+    let styles = format!(
+        "{}\n.katex-mathml {{ position: absolute; clip: rect(1px, 1px, 1px, 1px); padding: 0; border: 0; height: 1px; width: 1px; overflow: hidden; }}\n",
+        doc.templates.styles
+    );
+    files.push(("OEBPS/styles.css".to_string(), styles));
+
+    for (chapter, content) in doc.outline.chapters().iter().zip(&chapters) {
+        let page = html!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\" lang=\"{}\">\n<head>\n  <meta charset=\"UTF-8\" />\n  <title>{}</title>\n  <link rel=\"stylesheet\" type=\"text/css\" href=\"styles.css\" />\n</head>\n<body>\n<section epub:type=\"chapter\">\n{}\n</section>\n</body>\n</html>\n",
+            language,
+            language,
+            Html::text(chapter.title()),
+            Html::raw(content)
+        );
+        files.push((
+            format!("OEBPS/chapter-{}.xhtml", chapter.number()),
+            page.as_str().to_string(),
+        ));
+    }
+
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).expect("Failed to create EPUB directory");
+    }
+    let package = zip_package(&files).expect("Failed to write EPUB");
+    fs::write(path, package).expect("Failed to write EPUB");
+
+    let full_path = path.canonicalize().unwrap();
+    println!(
+        "{ANSI_GREEN}{ANSI_BOLD}Created EPUB{ANSI_RESET} at {}",
+        full_path.display()
+    );
+    full_path
+}
+
+const CONTAINER_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml" />
+  </rootfiles>
+</container>
+"#;
+
+/// The identifier of the book. Readers use it to tell books apart, so the
+/// repository is used if there is one since it doesn't change between builds.
+fn identifier(ctx: &Ctx) -> String {
+    let book = ctx.config.book();
+    match book.repository_url().filter(|url| is_plausible_url(url)) {
+        Some(url) => url.to_string(),
+        None => format!("urn:watson:{}", book.title().unwrap_or("untitled")),
+    }
+}
+
+fn book_title<'a>(ctx: &'a Ctx) -> &'a str {
+    ctx.config.book().title().unwrap_or("Untitled")
+}
+
+fn render_package(doc: &DocState, chapters: &[String], language: &Html, ctx: &Ctx) -> Html {
+    let book = ctx.config.book();
+
+    let mut metadata = html!(
+        "    <dc:identifier id=\"book-id\">{}</dc:identifier>\n    <dc:title>{}</dc:title>\n    <dc:language>{}</dc:language>\n    <meta property=\"dcterms:modified\">{}</meta>\n",
+        Html::text(&identifier(ctx)),
+        Html::text(book_title(ctx)),
+        language,
+        Html::text(&modified_timestamp())
+    );
+    for author in book.authors() {
+        metadata += html!("    <dc:creator>{}</dc:creator>\n", Html::text(author));
+    }
+    if let Some(license) = book.license() {
+        metadata += html!("    <dc:rights>{}</dc:rights>\n", Html::text(license));
+    }
+    if let Some(description) = book.description() {
+        metadata += html!(
+            "    <dc:description>{}</dc:description>\n",
+            Html::text(description)
+        );
+    }
+
+    let mut manifest = html!(
+        "    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\" />\n    <item id=\"ncx\" href=\"toc.ncx\" media-type=\"application/x-dtbncx+xml\" />\n    <item id=\"styles\" href=\"styles.css\" media-type=\"text/css\" />\n"
+    );
+    let mut spine = Html::default();
+    for (chapter, content) in doc.outline.chapters().iter().zip(chapters) {
+        let num = chapter.number();
+        // Readers need to be told which chapters have MathML in them.
+        let properties = if content.contains("<math") {
+            html!(" properties=\"mathml\"")
+        } else {
+            Html::default()
+        };
+        manifest += html!(
+            "    <item id=\"chapter-{}\" href=\"chapter-{}.xhtml\" media-type=\"application/xhtml+xml\"{} />\n",
+            num,
+            num,
+            properties
+        );
+        spine += html!("    <itemref idref=\"chapter-{}\" />\n", num);
+    }
+
+    html!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{}\">\n  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}  </metadata>\n  <manifest>\n{}  </manifest>\n  <spine toc=\"ncx\">\n{}  </spine>\n</package>\n",
+        language,
+        metadata,
+        manifest,
+        spine
+    )
+}
+
+fn render_nav(doc: &DocState, language: &Html, ctx: &Ctx) -> Html {
+    let mut list = html!("    <ol>\n");
+    for chapter in doc.outline.chapters() {
+        let num = chapter.number();
+        list += html!(
+            "      <li><a href=\"chapter-{}.xhtml\">{}</a>",
+            num,
+            Html::text(chapter.title())
+        );
+        if !chapter.sections().is_empty() {
+            list += html!("\n        <ol>\n");
+            for section in chapter.sections() {
+                list += html!(
+                    "          <li><a href=\"chapter-{}.xhtml#section-{}\">{}</a></li>\n",
+                    num,
+                    section.number(),
+                    Html::text(section.title())
+                );
+            }
+            list += html!("        </ol>\n      ");
+        }
+        list += html!("</li>\n");
+    }
+    list += html!("    </ol>\n");
+
+    html!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!DOCTYPE html>\n<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" xml:lang=\"{}\" lang=\"{}\">\n<head>\n  <meta charset=\"UTF-8\" />\n  <title>{}</title>\n</head>\n<body>\n  <nav epub:type=\"toc\" id=\"toc\">\n    <h1>Contents</h1>\n{}  </nav>\n</body>\n</html>\n",
+        language,
+        language,
+        Html::text(book_title(ctx)),
+        list
+    )
+}
+
+/// The EPUB 2 table of contents, for older readers.
+fn render_ncx(doc: &DocState, ctx: &Ctx) -> Html {
+    let mut points = Html::default();
+    let mut order = 0usize;
+    for chapter in doc.outline.chapters() {
+        let num = chapter.number();
+        order += 1;
+        points += html!(
+            "    <navPoint id=\"chapter-{}\" playOrder=\"{}\">\n      <navLabel><text>{}</text></navLabel>\n      <content src=\"chapter-{}.xhtml\" />\n",
+            num,
+            order,
+            Html::text(chapter.title()),
+            num
+        );
+        for section in chapter.sections() {
+            order += 1;
+            points += html!(
+                "      <navPoint id=\"chapter-{}-section-{}\" playOrder=\"{}\">\n        <navLabel><text>{}</text></navLabel>\n        <content src=\"chapter-{}.xhtml#section-{}\" />\n      </navPoint>\n",
+                num,
+                section.number(),
+                order,
+                Html::text(section.title()),
+                num,
+                section.number()
+            );
+        }
+        points += html!("    </navPoint>\n");
+    }
+
+    html!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<ncx xmlns=\"http://www.daisy.org/z3986/2005/ncx/\" version=\"2005-1\">\n  <head>\n    <meta name=\"dtb:uid\" content=\"{}\" />\n  </head>\n  <docTitle><text>{}</text></docTitle>\n  <navMap>\n{}  </navMap>\n</ncx>\n",
+        Html::text(&identifier(ctx)),
+        Html::text(book_title(ctx)),
+        points
+    )
+}
+
+/// When the package was last modified as `CCYY-MM-DDThh:mm:ssZ`, which it has
+/// to give. This is `SOURCE_DATE_EPOCH` if it is set, and otherwise the same
+/// fixed date as the zip entries, so building the same book twice gives the
+/// same file.
+fn modified_timestamp() -> String {
+    let secs = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.trim().parse::<u64>().ok())
+        .unwrap_or(ZIP_EPOCH);
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Howard Hinnant's days to civil date conversion.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// The `href` attributes in some markup, with where their values are.
+fn hrefs(content: &str) -> impl Iterator<Item = (usize, &str)> {
+    content.match_indices("href=\"").filter_map(|(i, attr)| {
+        let start = i + attr.len();
+        let len = content[start..].find('"')?;
+        Some((start, &content[start..start + len]))
+    })
+}
+
+/// Point links to chapters of the website, like `/chapter-2/#section-1` or
+/// `../chapter-2/`, at the chapter's file in the package instead.
+fn rewrite_links(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut last = 0;
+    for (start, href) in hrefs(content) {
+        if let Some(target) = chapter_link(href) {
+            out.push_str(&content[last..start]);
+            out.push_str(&target);
+            last = start + href.len();
+        }
+    }
+    out.push_str(&content[last..]);
+    out
+}
+
+fn chapter_link(href: &str) -> Option<String> {
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(fragment)),
+        None => (href, None),
+    };
+    let mut path = path.trim_start_matches('/');
+    while let Some(rest) = path.strip_prefix("../") {
+        path = rest;
+    }
+    let path = path.strip_suffix("index.html").unwrap_or(path);
+    let num = path.strip_prefix("chapter-")?.strip_suffix('/')?;
+    if num.is_empty() || !num.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(match fragment {
+        Some(fragment) => format!("chapter-{num}.xhtml#{fragment}"),
+        None => format!("chapter-{num}.xhtml"),
+    })
+}
+
+/// Check that links within the package point at a chapter which exists and
+/// at an element of it with the given id.
+fn check_links<'ctx>(chapters: &[String]) -> Vec<Diagnostic<'ctx>> {
+    let ids: Vec<FxHashSet<&str>> = chapters
+        .iter()
+        .map(|content| {
+            content
+                .match_indices(" id=\"")
+                .filter_map(|(i, attr)| {
+                    let start = i + attr.len();
+                    let len = content[start..].find('"')?;
+                    Some(&content[start..start + len])
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut diags = Vec::new();
+    for (i, content) in chapters.iter().enumerate() {
+        for (_, href) in hrefs(content) {
+            if href.contains("://") || href.starts_with("mailto:") {
+                continue;
+            }
+
+            let (file, fragment) = href.split_once('#').unwrap_or((href, ""));
+            let target = if file.is_empty() {
+                Some(i)
+            } else {
+                file.strip_prefix("chapter-")
+                    .and_then(|file| file.strip_suffix(".xhtml"))
+                    .and_then(|num| num.parse::<usize>().ok())
+                    .and_then(|num| num.checked_sub(1))
+                    .filter(|&num| num < chapters.len())
+            };
+
+            let resolves =
+                target.is_some_and(|target| fragment.is_empty() || ids[target].contains(fragment));
+            if !resolves {
+                diags.push(Diagnostic::warn_broken_epub_link(i + 1, href));
+            }
+        }
+    }
+    diags
+}
+
+/// 1980-01-01 00:00 UTC, the earliest time a zip entry can have.
+const ZIP_EPOCH: u64 = 315_532_800;
+
+/// Zip the files of the package. The `mimetype` entry has to be stored
+/// uncompressed, and the rest are deflated. Every entry has the same
+/// timestamp so that building the same book twice gives the same file.
+fn zip_package(files: &[(String, String)]) -> ZipResult<Vec<u8>> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in files {
+        let method = if name == "mimetype" {
+            CompressionMethod::Stored
+        } else {
+            CompressionMethod::Deflated
+        };
+        let options = SimpleFileOptions::default()
+            .compression_method(method)
+            .last_modified_time(DateTime::default());
+        zip.start_file(name.as_str(), options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+    Ok(zip.finish()?.into_inner())
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_broken_epub_link(chapter: usize, href: &str) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("link to `{href}` in chapter {chapter} doesn't resolve within the EPUB"),
+            vec![],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EpubMath, chapter_link, check_links, write_epub};
+    use crate::{
+        book::render_book, context::Arenas, driver::Driver, util::test_project::TestProject,
+    };
+    use rustc_hash::FxHashMap;
+    use std::{fs, io::Read};
+    use zip::{CompressionMethod, ZipArchive};
+
+    /// Check that `xml` is well formed, as far as every tag being closed in
+    /// the order it was opened and every `&` starting an entity.
+    fn assert_well_formed(name: &str, xml: &str) {
+        let mut open = Vec::new();
+        let mut rest = xml;
+        while let Some(at) = rest.find('<') {
+            let text = &rest[..at];
+            for (amp, _) in text.match_indices('&') {
+                let entity = &text[amp + 1..];
+                let len = entity.find(';').unwrap_or(usize::MAX);
+                assert!(
+                    len < 10
+                        && entity[..len]
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '#'),
+                    "bare `&` in {name}: {}",
+                    &text[amp..]
+                );
+            }
+
+            let tag = &rest[at..];
+            let end = if tag.starts_with("<!--") {
+                tag.find("-->").unwrap() + 3
+            } else {
+                tag.find('>').unwrap() + 1
+            };
+            let tag = &tag[..end];
+            rest = &rest[at + end..];
+
+            if tag.starts_with("<?") || tag.starts_with("<!") || tag.ends_with("/>") {
+                continue;
+            }
+            let tag_name = |tag: &str| {
+                let tag = tag.trim_start_matches(['<', '/']).trim_end_matches('>');
+                tag.split_whitespace().next().unwrap().to_string()
+            };
+            if tag.starts_with("</") {
+                assert_eq!(
+                    open.pop(),
+                    Some(tag_name(tag)),
+                    "unbalanced {tag} in {name}"
+                );
+            } else {
+                open.push(tag_name(tag));
+            }
+        }
+        assert_eq!(open, Vec::<String>::new(), "unclosed tags in {name}");
+    }
+
+    /// The values of `attr` in `xml`, in order.
+    fn attrs<'a>(xml: &'a str, attr: &str) -> Vec<&'a str> {
+        let attr = format!(" {attr}=\"");
+        (xml.match_indices(&attr))
+            .map(|(at, attr)| {
+                let value = &xml[at + attr.len()..];
+                &value[..value.find('"').unwrap()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_epub_is_a_well_formed_package() {
+        let project = TestProject::with_logic(
+            "# Intro & outline\n\nmodule logic\n\n\
+             # Results\n\n## Basics\n\n\
+             Math like $a < b$ and a [link back](../chapter-1/).\n\n\
+             axiom one :\n    |- ⊥ → ⊥\nend\n\n\
+             ## More\n\nSee [the basics](/chapter-3/#section-1).\n",
+        );
+        let arenas = Arenas::new();
+        let (mut ctx, parse_report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+        let path = write_epub(&doc.unwrap(), &project.path("out/book.epub"), &ctx);
+
+        let mut zip = ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+        let mut files = Vec::new();
+        for i in 0..zip.len() {
+            let mut file = zip.by_index(i).unwrap();
+            let mut content = String::new();
+            file.read_to_string(&mut content).unwrap();
+            files.push((file.name().to_string(), file.compression(), content));
+        }
+
+        // The mimetype comes first, uncompressed, so readers can identify
+        // the file.
+        assert_eq!(
+            (files[0].0.as_str(), files[0].1, files[0].2.as_str()),
+            (
+                "mimetype",
+                CompressionMethod::Stored,
+                "application/epub+zip"
+            )
+        );
+        let names: Vec<_> = files.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "mimetype",
+                "META-INF/container.xml",
+                "OEBPS/content.opf",
+                "OEBPS/nav.xhtml",
+                "OEBPS/toc.ncx",
+                "OEBPS/styles.css",
+                "OEBPS/chapter-1.xhtml",
+                "OEBPS/chapter-2.xhtml",
+                "OEBPS/chapter-3.xhtml",
+            ]
+        );
+        for (name, _, content) in &files {
+            if name.ends_with(".xml")
+                || name.ends_with(".opf")
+                || name.ends_with("html")
+                || name.ends_with(".ncx")
+            {
+                assert_well_formed(name, content);
+            }
+        }
+
+        // Every file in the package is in the manifest, and the chapters are
+        // read in order.
+        let file = |name: &str| &files.iter().find(|(n, _, _)| n == name).unwrap().2;
+        let package = file("OEBPS/content.opf");
+        let mut manifest = attrs(package, "href");
+        manifest.sort();
+        let mut in_package: Vec<_> = (names.iter())
+            .filter_map(|name| name.strip_prefix("OEBPS/"))
+            .filter(|&name| name != "content.opf")
+            .collect();
+        in_package.sort();
+        assert_eq!(manifest, in_package);
+        assert_eq!(
+            attrs(package, "idref"),
+            ["chapter-1", "chapter-2", "chapter-3"]
+        );
+
+        let nav = attrs(file("OEBPS/nav.xhtml"), "href");
+        assert_eq!(nav[0], "chapter-1.xhtml");
+        assert_eq!(nav.last(), Some(&"chapter-3.xhtml#section-2"));
+
+        // Links to other chapters point into the package.
+        let results = file("OEBPS/chapter-3.xhtml");
+        assert!(results.contains("<math"));
+        let links = attrs(results, "href");
+        assert!(links.contains(&"chapter-1.xhtml"), "{links:?}");
+        assert!(links.contains(&"chapter-3.xhtml#section-1"), "{links:?}");
+    }
+
+    #[test]
+    fn links_to_chapters_are_rewritten() {
+        assert_eq!(
+            chapter_link("/chapter-2/#section-1").as_deref(),
+            Some("chapter-2.xhtml#section-1")
+        );
+        assert_eq!(
+            chapter_link("../../chapter-10/index.html").as_deref(),
+            Some("chapter-10.xhtml")
+        );
+        assert_eq!(chapter_link("https://example.com/chapter-1/"), None);
+        assert_eq!(chapter_link("/chapter-x/"), None);
+    }
+
+    #[test]
+    fn broken_links_are_reported() {
+        let chapters = [
+            "<h2 id=\"section-1\"></h2><a href=\"#section-1\"></a>\
+             <a href=\"chapter-2.xhtml#section-9\"></a>"
+                .to_string(),
+            "<a href=\"chapter-3.xhtml\"></a><a href=\"https://example.com\"></a>".to_string(),
+        ];
+        let titles: Vec<_> = (check_links(&chapters).iter())
+            .map(|diag| diag.title().to_string())
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                "link to `chapter-2.xhtml#section-9` in chapter 1 doesn't resolve within the EPUB",
+                "link to `chapter-3.xhtml` in chapter 2 doesn't resolve within the EPUB",
+            ]
+        );
+    }
+
+    #[test]
+    fn math_formats_parse() {
+        assert_eq!("katex-html".parse(), Ok(EpubMath::KatexHtml));
+        assert!(!"mathml".parse::<EpubMath>().unwrap().is_supported());
+        assert_eq!(
+            "svg".parse::<EpubMath>(),
+            Err("expected `mathml`, `image` or `katex-html`, found `svg`".to_string())
+        );
+    }
+}
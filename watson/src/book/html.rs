@@ -0,0 +1,114 @@
+use std::{fmt, ops::AddAssign};
+
+/// Markup which is safe to put in a page. Text only becomes `Html` by being
+/// escaped and markup only by being marked as trusted, so the type keeps
+/// track of what has been escaped and nothing is escaped twice or forgotten.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Html(String);
+
+impl Html {
+    /// Text from a document or the config, escaped so that it shows as
+    /// written.
+    pub fn text(text: &str) -> Self {
+        let mut out = Self(String::with_capacity(text.len()));
+        out.push_text(text);
+        out
+    }
+
+    /// Markup we trust, like our own tags, the templates or KaTeX's output.
+    /// It is included as is.
+    pub fn raw(html: &str) -> Self {
+        Self(html.to_string())
+    }
+
+    /// Escape `text` onto the end in a single pass.
+    pub fn push_text(&mut self, text: &str) {
+        let mut last = 0;
+        for (i, c) in text.char_indices() {
+            let escaped = match c {
+                '&' => "&amp;",
+                '<' => "&lt;",
+                '>' => "&gt;",
+                '"' => "&quot;",
+                '\'' => "&#39;",
+                _ => continue,
+            };
+            self.0.push_str(&text[last..i]);
+            self.0.push_str(escaped);
+            last = i + c.len_utf8();
+        }
+        self.0.push_str(&text[last..]);
+    }
+
+    /// Join markup with a separator between each part.
+    pub fn join(parts: &[Html], separator: &Html) -> Self {
+        let mut out = Self::default();
+        for (i, part) in parts.iter().enumerate() {
+            if i > 0 {
+                out += separator;
+            }
+            out += part;
+        }
+        out
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.0.reserve(additional);
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AddAssign<&Html> for Html {
+    fn add_assign(&mut self, rhs: &Html) {
+        self.0.push_str(&rhs.0);
+    }
+}
+
+impl AddAssign<Html> for Html {
+    fn add_assign(&mut self, rhs: Html) {
+        self.0.push_str(&rhs.0);
+    }
+}
+
+impl fmt::Display for Html {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Values [`html!`] can put in markup without escaping them.
+pub trait HtmlArg: fmt::Display {}
+
+impl HtmlArg for Html {}
+impl HtmlArg for usize {}
+impl HtmlArg for u32 {}
+impl HtmlArg for u128 {}
+impl HtmlArg for f64 {}
+impl<T: HtmlArg + ?Sized> HtmlArg for &T {}
+
+pub fn html_arg<T: HtmlArg>(arg: &T) -> &T {
+    arg
+}
+
+/// Like `format!` but for trusted markup. Every argument has to be [`Html`]
+/// or a number, so text can only be included by escaping it. Arguments
+/// can't be captured by the format string since they wouldn't be checked.
+macro_rules! html {
+    ($fmt:literal) => {
+        $crate::book::html::Html::raw($fmt)
+    };
+    ($fmt:literal $(, $arg:expr)+ $(,)?) => {
+        $crate::book::html::Html::raw(&format!(
+            $fmt
+            $(, $crate::book::html::html_arg(&$arg))+
+        ))
+    };
+}
+pub(crate) use html;
@@ -0,0 +1,369 @@
+use crate::{
+    book::html::{Html, html},
+    config::BookConfig,
+};
+use rustc_hash::FxHashMap;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Renders the LaTeX in the prose with KaTeX, falling back to a placeholder
+/// showing the LaTeX as written when a formula can't be rendered, so one bad
+/// formula doesn't stop the build or bloat the page.
+#[derive(Debug)]
+pub struct MathRenderer {
+    size_limit: usize,
+    time_budget: Duration,
+    /// The time spent rendering the math of the current chapter.
+    spent: Duration,
+    /// The formulas of the current chapter which weren't rendered because the
+    /// time budget ran out.
+    skipped: usize,
+    /// Every formula rendered during the build, by its LaTeX and whether it
+    /// is in display mode, so a formula used again isn't rendered again.
+    cache: FxHashMap<(String, bool), Rendered>,
+    /// Renders the formulas which weren't prerendered, started when the
+    /// first one is needed.
+    worker: Option<Worker>,
+}
+
+/// A thread which renders formulas one at a time, so waiting for a formula
+/// can be given up on when the time budget runs out. KaTeX can't be
+/// stopped, so a thread stuck on a slow formula is left to finish it on its
+/// own and a new one takes its place.
+#[derive(Debug)]
+struct Worker {
+    formulas: mpsc::Sender<(String, bool)>,
+    rendered: mpsc::Receiver<Rendered>,
+}
+
+#[derive(Debug)]
+struct Rendered {
+    result: Result<String, MathFailure>,
+    /// How long rendering took, until it is charged to the chapter which
+    /// first uses the formula.
+    took: Option<Duration>,
+}
+
+thread_local! {
+    /// KaTeX's context, shared by everything one thread renders.
+    static KATEX: katex::KatexContext = katex::KatexContext::default();
+}
+
+/// Why a formula wasn't rendered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MathFailure {
+    Error(String),
+    Panic,
+    TooLarge(usize),
+    /// The chapter's time budget ran out before the formula. It isn't
+    /// reported on its own.
+    OutOfTime,
+}
+
+/// A chapter whose math took longer than the time budget.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlowMath {
+    pub spent: Duration,
+    pub skipped: usize,
+}
+
+impl MathRenderer {
+    pub fn new(config: &BookConfig) -> Self {
+        Self {
+            size_limit: config.math_size_limit(),
+            time_budget: config.math_time_budget(),
+            spent: Duration::ZERO,
+            skipped: 0,
+            cache: FxHashMap::default(),
+            worker: None,
+        }
+    }
+
+    /// Render the formulas which haven't been rendered yet, spread over the
+    /// available threads, so that [`Self::render`] only has to look them up.
+    /// Each formula is only rendered once however often it is given.
+    ///
+    /// The threads stop taking formulas once a chapter's time budget has
+    /// passed, and a thread still rendering a formula then isn't waited for,
+    /// so slow math can't stall the build here. The formulas left are
+    /// rendered by [`Self::render`] within the budget of the chapter using
+    /// them.
+    pub fn prerender(&mut self, formulas: impl IntoIterator<Item = (String, bool)>) {
+        let mut formulas: Vec<_> = formulas
+            .into_iter()
+            .filter(|formula| !self.cache.contains_key(formula))
+            .collect();
+        formulas.sort_unstable();
+        formulas.dedup();
+        if formulas.is_empty() {
+            return;
+        }
+
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let deadline = Instant::now() + self.time_budget;
+        let formulas = Arc::new(formulas);
+        let next = Arc::new(AtomicUsize::new(0));
+        let (send, rendered) = mpsc::channel();
+        for _ in 0..threads.min(formulas.len()) {
+            let (formulas, next, send) = (formulas.clone(), next.clone(), send.clone());
+            thread::spawn(move || {
+                while Instant::now() < deadline {
+                    let idx = next.fetch_add(1, Ordering::Relaxed);
+                    let Some((latex, display_mode)) = formulas.get(idx) else {
+                        break;
+                    };
+                    if send
+                        .send((idx, timed_render(latex, *display_mode)))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(send);
+
+        // Stop waiting at the deadline, or once every thread has finished and
+        // closed the channel.
+        let mut left = deadline.saturating_duration_since(Instant::now());
+        while let Ok((idx, rendered)) = rendered.recv_timeout(left) {
+            self.cache.insert(formulas[idx].clone(), rendered);
+            left = deadline.saturating_duration_since(Instant::now());
+        }
+    }
+
+    pub fn render(&mut self, latex: &str, display_mode: bool) -> Result<Html, MathFailure> {
+        if self.spent > self.time_budget {
+            self.skipped += 1;
+            return Err(MathFailure::OutOfTime);
+        }
+
+        let formula = (latex.to_string(), display_mode);
+        if !self.cache.contains_key(&formula) {
+            // A formula only gets the time left in the chapter's budget.
+            let left = self.time_budget - self.spent;
+            let waiting = Instant::now();
+            let worker = self.worker.get_or_insert_with(Worker::new);
+            let _ = worker.formulas.send(formula.clone());
+            let rendered = match worker.rendered.recv_timeout(left) {
+                Ok(rendered) => rendered,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.worker = None;
+                    self.spent += waiting.elapsed();
+                    self.skipped += 1;
+                    return Err(MathFailure::OutOfTime);
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.worker = None;
+                    Rendered {
+                        result: Err(MathFailure::Panic),
+                        took: None,
+                    }
+                }
+            };
+            self.cache.insert(formula.clone(), rendered);
+        }
+
+        let rendered = self.cache.get_mut(&formula).unwrap();
+        self.spent += rendered.took.take().unwrap_or_default();
+
+        let rendered = rendered.result.as_ref().map_err(Clone::clone)?;
+        if rendered.len() > self.size_limit {
+            return Err(MathFailure::TooLarge(rendered.len()));
+        }
+
+        // KaTeX escapes the LaTeX it puts in its output.
+        Ok(Html::raw(rendered))
+    }
+
+    /// Start the next chapter, returning how the last one went over the time
+    /// budget if it did.
+    pub fn finish_chapter(&mut self) -> Option<SlowMath> {
+        let slow = SlowMath {
+            spent: std::mem::take(&mut self.spent),
+            skipped: std::mem::take(&mut self.skipped),
+        };
+        (slow.spent > self.time_budget || slow.skipped > 0).then_some(slow)
+    }
+}
+
+impl Worker {
+    fn new() -> Self {
+        let (formulas, to_render) = mpsc::channel::<(String, bool)>();
+        let (send, rendered) = mpsc::channel();
+        thread::spawn(move || {
+            for (latex, display_mode) in to_render {
+                if send.send(timed_render(&latex, display_mode)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self { formulas, rendered }
+    }
+}
+
+impl MathFailure {
+    pub fn message(&self) -> String {
+        match self {
+            MathFailure::Error(err) => err.clone(),
+            MathFailure::Panic => "KaTeX crashed while rendering it".to_string(),
+            MathFailure::TooLarge(size) => {
+                format!(
+                    "formula too large: it renders to {} KB",
+                    size.div_ceil(1024)
+                )
+            }
+            MathFailure::OutOfTime => "the chapter's time for math ran out".to_string(),
+        }
+    }
+
+    /// Shows the LaTeX as written, with why it wasn't rendered as a tooltip.
+    pub fn placeholder(&self, latex: &str, display_mode: bool) -> Html {
+        let delimiter = Html::raw(if display_mode { "$$" } else { "$" });
+        html!(
+            r#"<span class="math-error" title="{}">{}{}{}</span>"#,
+            Html::text(&self.message()),
+            delimiter,
+            Html::text(latex),
+            delimiter
+        )
+    }
+}
+
+fn timed_render(latex: &str, display_mode: bool) -> Rendered {
+    let start = Instant::now();
+    let rendered = panic::catch_unwind(AssertUnwindSafe(|| render_latex(latex, display_mode)));
+    let result = match rendered {
+        Ok(Ok(rendered)) => Ok(rendered),
+        Ok(Err(err)) => Err(MathFailure::Error(err)),
+        Err(_) => Err(MathFailure::Panic),
+    };
+    Rendered {
+        result,
+        took: Some(start.elapsed()),
+    }
+}
+
+/// Render with KaTeX, which reports invalid LaTeX as an error rather than
+/// rendering it in red.
+fn render_latex(latex: &str, display_mode: bool) -> Result<String, String> {
+    let settings = katex::Settings::builder()
+        .display_mode(display_mode)
+        .throw_on_error(true)
+        .build();
+
+    KATEX.with(|ctx| katex::render_to_string(ctx, latex, &settings).map_err(|err| err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        book::build_book, context::Arenas, driver::Driver, util::test_project::TestProject,
+    };
+
+    fn renderer(time_budget: Duration) -> MathRenderer {
+        MathRenderer {
+            size_limit: usize::MAX,
+            time_budget,
+            spent: Duration::ZERO,
+            skipped: 0,
+            cache: FxHashMap::default(),
+            worker: None,
+        }
+    }
+
+    #[test]
+    fn prerenders_each_formula_once() {
+        let mut math = renderer(Duration::from_secs(60));
+        let formulas = ["x^2", "\\frac{1}{2}", "x^2"].map(|latex| (latex.to_string(), false));
+        math.prerender(formulas);
+        assert_eq!(math.cache.len(), 2);
+        assert!(math.render("x^2", false).is_ok());
+    }
+
+    #[test]
+    fn prerendering_stops_at_the_time_budget() {
+        let mut math = renderer(Duration::ZERO);
+        math.prerender([("x^2".to_string(), false)]);
+        assert!(math.cache.is_empty());
+        // The formula is still rendered when a chapter with time uses it.
+        math.time_budget = Duration::from_secs(60);
+        assert!(math.render("x^2", false).is_ok());
+    }
+
+    #[test]
+    fn a_formula_only_gets_the_time_left_in_the_budget() {
+        let mut math = renderer(Duration::from_nanos(1));
+        assert_eq!(
+            math.render("x^2", false).unwrap_err(),
+            MathFailure::OutOfTime
+        );
+        let slow = math.finish_chapter().unwrap();
+        assert_eq!(slow.skipped, 1);
+    }
+
+    #[test]
+    fn broken_formulas_are_errors() {
+        let mut math = renderer(Duration::from_secs(60));
+        let failure = math.render("\\frac{1", false).unwrap_err();
+        assert!(matches!(failure, MathFailure::Error(_)), "{failure:?}");
+    }
+
+    #[test]
+    fn formulas_over_the_size_limit_are_too_large() {
+        let mut math = renderer(Duration::from_secs(60));
+        math.size_limit = 100;
+        let failure = math.render("x+y", false).unwrap_err();
+        assert!(matches!(failure, MathFailure::TooLarge(size) if size > 100));
+    }
+
+    #[test]
+    fn placeholder_shows_the_latex_as_written() {
+        let failure = MathFailure::Error("bad \"input\"".to_string());
+        assert_eq!(
+            failure.placeholder("a<b", false).as_str(),
+            r#"<span class="math-error" title="bad &quot;input&quot;">$a&lt;b$</span>"#
+        );
+        assert_eq!(
+            failure.placeholder("x", true).as_str(),
+            r#"<span class="math-error" title="bad &quot;input&quot;">$$x$$</span>"#
+        );
+    }
+
+    #[test]
+    fn book_builds_around_broken_and_huge_formulas() {
+        let huge = format!("{}x", "x+".repeat(500));
+        // Markdown only reads math with balanced braces, so the broken
+        // formula is missing an argument instead.
+        let project = TestProject::new(&format!(
+            "# Formulas\n\nBroken $\\frac{{1}}$, huge ${huge}$ and fine $y$.\n"
+        ));
+        project.write("watson.toml", "[book]\nmath_size_limit = 2000\n");
+
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        assert!(build_book(&mut ctx, parse_report, None, false, "").is_some());
+
+        let notes: Vec<_> = (ctx.diags.iter())
+            .filter(|diag| diag.title() == "couldn't render a formula in chapter 1")
+            .map(|diag| diag.parts().nth(1).unwrap().title())
+            .collect();
+        assert_eq!(notes.len(), 2);
+        assert!(notes[1].starts_with("formula too large"), "{}", notes[1]);
+
+        let page = project.read("build/book/chapter-1/index.html");
+        assert_eq!(page.matches(r#"class="math-error""#).count(), 2);
+        assert!(page.contains("$\\frac{1}$"));
+        assert!(page.contains(r#"class="katex""#));
+    }
+}
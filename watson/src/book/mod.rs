@@ -1,36 +1,193 @@
 use crate::{
+    config::BadgeStyle,
     context::Ctx,
     diagnostics::{Diagnostic, DiagnosticSpan, WResult},
     parse::{
-        ParseEntry, ParseReport, Span,
+        Location, ParseEntry, ParseReport, SourceId, Span,
+        elaborator::elaborate_name,
+        parse_example,
         parse_state::ParseRuleSource,
         parse_tree::{ParseAtomKind, ParseTreeId, ParseTreePart},
+        source_cache::SourceDecl,
     },
     report::ProofReport,
-    util::ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RESET},
+    semant::{
+        attributes::BuiltinAttributeKind,
+        custom_grammar::syntax::CustomGrammarPatPartCore,
+        proof_status::ProofStatuses,
+        theorems::{TheoremId, TheoremKind},
+    },
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RESET, ANSI_YELLOW},
+        plural, similar_names,
+    },
 };
-use aho_corasick::AhoCorasick;
-use pulldown_cmark::{Event, HeadingLevel, Options, Parser, Tag, TagEnd};
-use std::{fs, path::PathBuf};
-
+use html::{Html, html};
+use itertools::Itertools;
+use math::{MathFailure, MathRenderer, SlowMath};
+use outline::{Outline, OutlineSource};
+use pulldown_cmark::{
+    CodeBlockKind, Event, HeadingLevel, Options, Parser, Tag, TagEnd, TextMergeWithOffset,
+};
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use std::{
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use table::{Alignment, ProseBlock, Table, split_tables};
+use template::{TemplateError, Templates};
+use ustr::Ustr;
+
+pub mod check_report;
+pub mod epub;
+mod html;
+mod math;
+mod outline;
 pub mod server;
-
+pub mod statement;
+mod table;
+mod template;
+
+/// Build the book from a parsed project. Without a `proof_report` the proofs
+/// weren't checked, so every theorem is marked unchecked and every page says
+/// what is missing. Returns `None` if the book wasn't built, leaving the
+/// errors which stopped it in the context.
 pub fn build_book<'ctx>(
     ctx: &mut Ctx<'ctx>,
     parse_report: ParseReport<'ctx>,
-    _proof_report: ProofReport<'ctx>,
+    proof_report: Option<ProofReport<'ctx>>,
     watch: bool,
     base_path: &str,
-) -> PathBuf {
-    ctx.diags.clear_errors();
+) -> Option<PathBuf> {
+    let theorems = book_theorems(&parse_report, proof_report.as_ref(), ctx);
+    let kind = if proof_report.is_some() {
+        BuildKind::Checked
+    } else {
+        BuildKind::Unchecked
+    };
+    write_book(
+        ctx,
+        &parse_report.entries,
+        &theorems,
+        watch,
+        kind,
+        base_path,
+    )
+}
 
-    let mut doc = DocState::new(base_path.to_string());
-    doc.process_entries(&parse_report.entries, ctx);
+/// Build the book as an EPUB file at `path` rather than as a website.
+pub fn build_epub<'ctx>(
+    ctx: &mut Ctx<'ctx>,
+    parse_report: ParseReport<'ctx>,
+    proof_report: Option<ProofReport<'ctx>>,
+    path: &Path,
+) -> Option<PathBuf> {
+    let theorems = book_theorems(&parse_report, proof_report.as_ref(), ctx);
+    let doc = render_book(ctx, &parse_report.entries, &theorems, "")?;
+    Some(epub::write_epub(&doc, path, ctx))
+}
 
-    if ctx.diags.has_errors() {
-        ctx.diags.print_errors(ctx);
-        std::process::exit(1);
-    }
+/// Build an EPUB of the prose, like [`build_draft_book`].
+pub fn build_draft_epub<'ctx>(
+    ctx: &mut Ctx<'ctx>,
+    entries: &[ParseEntry<'ctx>],
+    path: &Path,
+) -> Option<PathBuf> {
+    let doc = render_book(ctx, entries, &FxHashMap::default(), "")?;
+    Some(epub::write_epub(&doc, path, ctx))
+}
+
+/// Find what to show next to the command declaring each theorem. Without a
+/// proof report there is no status or axiom closure to show.
+fn book_theorems<'ctx>(
+    parse_report: &ParseReport<'ctx>,
+    proof_report: Option<&ProofReport<'ctx>>,
+    ctx: &Ctx<'ctx>,
+) -> FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>> {
+    let show_axiom_closure = ctx.config.book().show_axiom_closure();
+    parse_report
+        .command_theorems
+        .iter()
+        .map(|(&tree, &thm)| {
+            let Some(statuses) = proof_report.map(|report| &report.statuses) else {
+                let theorem = BookTheorem {
+                    theorem: thm,
+                    kind: thm.kind(),
+                    badge: Badge::Unchecked,
+                    badge_title: None,
+                    axiom_closure: None,
+                };
+                return (tree, theorem);
+            };
+
+            // Axioms only depend on themselves so they don't list their
+            // closure.
+            let axiom_closure = statuses
+                .get(thm)
+                .filter(|status| show_axiom_closure && !status.is_axiom())
+                .map(|status| status.axioms_used().to_vec());
+
+            let theorem = BookTheorem {
+                theorem: thm,
+                kind: thm.kind(),
+                badge: Badge::for_theorem(thm, statuses),
+                badge_title: badge_title(thm, statuses),
+                axiom_closure,
+            };
+            (tree, theorem)
+        })
+        .collect()
+}
+
+/// Write the built-in templates and stylesheet to `dir` for a project to
+/// start its own from, leaving any which are already there. Returns the
+/// files written.
+pub fn write_default_templates(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    template::write_builtin(dir)
+}
+
+/// Build a preview of the book from entries found by [`crate::parse::scan`].
+/// Nothing has been checked, so commands are shown as plain text and every
+/// page is marked as a draft.
+pub fn build_draft_book<'ctx>(
+    ctx: &mut Ctx<'ctx>,
+    entries: &[ParseEntry<'ctx>],
+    base_path: &str,
+) -> Option<PathBuf> {
+    write_book(
+        ctx,
+        entries,
+        &FxHashMap::default(),
+        false,
+        BuildKind::Draft,
+        base_path,
+    )
+}
+
+/// How much of the project was checked before the book was built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildKind {
+    Checked,
+    /// Parsed, but the proofs weren't checked.
+    Unchecked,
+    /// Only the prose was found, by `watson book --prose-only`.
+    Draft,
+}
+
+fn write_book<'ctx>(
+    ctx: &mut Ctx<'ctx>,
+    entries: &[ParseEntry<'ctx>],
+    theorems: &FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>>,
+    watch: bool,
+    kind: BuildKind,
+    base_path: &str,
+) -> Option<PathBuf> {
+    let doc = render_book(ctx, entries, theorems, base_path)?;
+    let badge_style = ctx.config.book().badge_style();
 
     // Delete existing book directory to ensure clean build
     let book_dir = ctx.config.build_dir().join("book");
@@ -40,250 +197,1022 @@ pub fn build_book<'ctx>(
     fs::create_dir_all(&book_dir).unwrap();
 
     let css_path = book_dir.join("styles.css");
-    fs::write(css_path, include_str!("templates/styles.css")).expect("TODO");
+    fs::write(css_path, &doc.templates.styles).expect("TODO");
 
     // Include auto-reload script only for watch mode
     let auto_reload_script = if watch {
-        include_str!("templates/auto_reload.js")
+        Html::raw(include_str!("templates/auto_reload.js"))
     } else {
-        ""
+        Html::default()
+    };
+
+    let language = ctx
+        .config
+        .book()
+        .language()
+        .filter(|lang| is_plausible_language_tag(lang))
+        .unwrap_or("en");
+    let footer = render_footer(ctx);
+    let sidebar = doc.outline.render_sidebar(&doc.base_path, badge_style);
+    let banner = match kind {
+        BuildKind::Checked => Html::default(),
+        BuildKind::Unchecked => Html::raw(include_str!("templates/unchecked_banner.html")),
+        BuildKind::Draft => Html::raw(include_str!("templates/draft_banner.html")),
     };
 
+    let chapter_titles: Vec<_> = doc.outline.chapter_titles().collect();
     for (i, chapter_contents) in doc.chapter_contents.iter().enumerate() {
         let chapter_num = i + 1;
-        let chapter_title = &doc.chapter_titles[i];
+        let chapter_title = chapter_titles[i];
         let page_title = match ctx.config.book().title() {
             Some(book_title) => format!("{} - {}", chapter_title, book_title),
             None => chapter_title.to_string(),
         };
+        let meta_tags = render_meta_tags(&page_title, ctx);
 
         let chapter_dir = book_dir.join(format!("chapter-{chapter_num}"));
         fs::create_dir_all(&chapter_dir).expect("Failed to create chapter directory");
         let path = chapter_dir.join("index.html");
-        let content = replace_patterns(
-            include_str!("templates/layout.html"),
-            &[
-                "{{PAGE_TITLE}}",
-                "{{SIDEBAR}}",
-                "{{CHAPTER_CONTENT}}",
-                "{{CHAPTER_NUM}}",
-                "{{AUTO_RELOAD_SCRIPT}}",
-                "{{BASE_PATH}}",
-            ],
-            &[
-                &page_title,
-                &doc.sidebar_content,
-                chapter_contents,
-                &chapter_num.to_string(),
-                auto_reload_script,
-                base_path,
-            ],
-        );
-        fs::write(path, content).expect("TODO");
+        let content = doc.templates.layout.fill(&[
+            ("PAGE_TITLE", &Html::text(&page_title)),
+            ("SIDEBAR", &sidebar),
+            ("CHAPTER_CONTENT", chapter_contents),
+            ("CHAPTER_NUM", &html!("{}", chapter_num)),
+            ("AUTO_RELOAD_SCRIPT", &auto_reload_script),
+            ("BASE_PATH", &Html::text(base_path)),
+            ("LANG", &Html::text(language)),
+            ("META_TAGS", &meta_tags),
+            ("FOOTER", &footer),
+            ("BANNER", &banner),
+        ]);
+        fs::write(path, content.as_str()).expect("TODO");
     }
 
+    let manifest = BookManifest::new(&chapter_titles, language, kind, base_path, ctx);
+    let manifest = serde_json::to_string_pretty(&manifest).unwrap();
+    fs::write(book_dir.join("book.json"), manifest).expect("TODO");
+
+    let outline = serde_json::to_string_pretty(&doc.outline).unwrap();
+    fs::write(book_dir.join("outline.json"), outline).expect("TODO");
+
     let full_path = book_dir.canonicalize().unwrap();
     println!(
         "{ANSI_GREEN}{ANSI_BOLD}Created book{ANSI_RESET} at {}",
         full_path.display()
     );
 
-    full_path
+    Some(full_path)
+}
+
+/// Render the prose of the book into chapters, or return `None` if there is
+/// none or the templates, the prose or the metadata have errors. The errors
+/// are printed and left in the context for the caller to act on.
+fn render_book<'ctx>(
+    ctx: &mut Ctx<'ctx>,
+    entries: &[ParseEntry<'ctx>],
+    theorems: &FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>>,
+    base_path: &str,
+) -> Option<DocState> {
+    // Load the templates before doing any work, so a broken one fails
+    // straight away.
+    ctx.diags.clear_errors();
+    let templates = match Templates::load(ctx.config.book().templates_dir()) {
+        Ok(templates) => templates,
+        Err(err) => {
+            let diags = Diagnostic::err_book_template::<()>(&err).unwrap_err();
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(ctx);
+            return None;
+        }
+    };
+    check_metadata(ctx, &MISSING_METADATA_NOTED);
+
+    let badge_style = ctx.config.book().badge_style();
+    let separate_corollaries = ctx.config.book().separate_corollary_numbering();
+    let math = MathRenderer::new(ctx.config.book());
+    let mut doc = DocState::new(
+        templates,
+        Html::text(base_path),
+        badge_style,
+        separate_corollaries,
+        math,
+    );
+    doc.process_entries(entries, theorems, ctx);
+
+    // Print warnings about the metadata even if the book builds.
+    ctx.diags.print_errors(ctx);
+    if ctx.diags.has_errors() {
+        return None;
+    }
+
+    // Prose before the first chapter is an error, so without chapters there
+    // is no prose at all and the book would be empty.
+    if doc.chapter_contents.is_empty() {
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}Skipped book{ANSI_RESET}: there is no prose. Start a chapter with a `# Title` heading."
+        );
+        return None;
+    }
+
+    Some(doc)
+}
+
+/// Describes the book for tools which consume it, like site generators and
+/// search indexes. Written to `book.json` in the root of the book.
+#[derive(Debug, Serialize)]
+struct BookManifest<'a> {
+    title: Option<&'a str>,
+    authors: &'a [String],
+    license: Option<&'a str>,
+    repository_url: Option<&'a str>,
+    description: Option<&'a str>,
+    language: &'a str,
+    /// Whether the book was built without checking, by `watson book --prose-only`.
+    draft: bool,
+    /// Whether the proofs were checked, so the statuses shown are real. Not
+    /// when built with `watson book --no-check` or as a draft.
+    proofs_checked: bool,
+    chapters: Vec<ManifestChapter<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ManifestChapter<'a> {
+    number: usize,
+    title: &'a str,
+    url: String,
+}
+
+impl<'a> BookManifest<'a> {
+    fn new(
+        chapter_titles: &[&'a str],
+        language: &'a str,
+        kind: BuildKind,
+        base_path: &str,
+        ctx: &'a Ctx,
+    ) -> Self {
+        let book = ctx.config.book();
+        let chapters = chapter_titles
+            .iter()
+            .enumerate()
+            .map(|(i, &title)| ManifestChapter {
+                number: i + 1,
+                title,
+                url: format!("{base_path}chapter-{}/", i + 1),
+            })
+            .collect();
+
+        Self {
+            title: book.title(),
+            authors: book.authors(),
+            license: book.license(),
+            repository_url: book.repository_url().filter(|url| is_plausible_url(url)),
+            description: book.description(),
+            language,
+            draft: kind == BuildKind::Draft,
+            proofs_checked: kind == BuildKind::Checked,
+            chapters,
+        }
+    }
+}
+
+/// Whether missing metadata has been noted already. Watch mode rebuilds the
+/// book after every change and the note would be repeated each time.
+static MISSING_METADATA_NOTED: AtomicBool = AtomicBool::new(false);
+
+/// Check the metadata in the `[book]` config, noting any recommended fields
+/// which are missing unless `noted` says that has been done already.
+fn check_metadata(ctx: &mut Ctx, noted: &AtomicBool) {
+    let book = ctx.config.book();
+    let mut diags = Vec::new();
+
+    if let Some(language) = book.language()
+        && !is_plausible_language_tag(language)
+    {
+        diags.push(Diagnostic::warn_invalid_book_config(
+            "language",
+            language,
+            "expected a BCP-47 language tag like `en` or `pt-BR`",
+        ));
+    }
+
+    if let Some(url) = book.repository_url()
+        && !is_plausible_url(url)
+    {
+        diags.push(Diagnostic::warn_invalid_book_config(
+            "repository_url",
+            url,
+            "expected an `http://` or `https://` URL",
+        ));
+    }
+
+    let missing = [
+        ("title", book.title().is_none()),
+        ("authors", book.authors().is_empty()),
+        ("license", book.license().is_none()),
+        ("description", book.description().is_none()),
+        ("language", book.language().is_none()),
+    ]
+    .into_iter()
+    .filter(|(_, missing)| *missing)
+    .map(|(key, _)| key)
+    .collect::<Vec<_>>();
+    if !missing.is_empty() && !noted.swap(true, Ordering::Relaxed) {
+        diags.push(Diagnostic::info_missing_book_metadata(&missing));
+    }
+
+    ctx.diags.add_diags(diags);
+}
+
+/// Whether a string looks like a BCP-47 tag. This only checks the shape of the
+/// subtags, not that they are registered.
+fn is_plausible_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let primary = subtags.next().unwrap_or("");
+    let primary_ok =
+        matches!(primary.len(), 2..=3 | 5..=8) && primary.chars().all(|c| c.is_ascii_alphabetic());
+    primary_ok
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+fn is_plausible_url(url: &str) -> bool {
+    let Some(rest) = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+    else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    !host.is_empty() && !url.chars().any(char::is_whitespace)
+}
+
+fn render_meta_tags(page_title: &str, ctx: &Ctx) -> Html {
+    let book = ctx.config.book();
+    let mut tags = Vec::new();
+
+    let mut meta = |attr: &str, key: &str, value: &str| {
+        tags.push(html!(
+            r#"    <meta {}="{}" content="{}" />"#,
+            Html::raw(attr),
+            Html::raw(key),
+            Html::text(value)
+        ));
+    };
+
+    if !book.authors().is_empty() {
+        meta("name", "author", &book.authors().join(", "));
+    }
+    if let Some(description) = book.description() {
+        meta("name", "description", description);
+        meta("property", "og:description", description);
+    }
+    meta("property", "og:title", page_title);
+    meta("property", "og:type", "book");
+    if let Some(title) = book.title() {
+        meta("property", "og:site_name", title);
+    }
+
+    let mut out = Html::join(&tags, &Html::raw("\n"));
+    out += Html::raw("\n");
+    out
+}
+
+/// The "© authors — license" line at the bottom of every page.
+fn render_footer(ctx: &Ctx) -> Html {
+    let book = ctx.config.book();
+    let mut parts = Vec::new();
+    if !book.authors().is_empty() {
+        parts.push(Html::text(&format!("© {}", book.authors().join(", "))));
+    }
+    if let Some(license) = book.license() {
+        parts.push(Html::text(license));
+    }
+    if let Some(url) = book.repository_url().filter(|url| is_plausible_url(url)) {
+        parts.push(html!(r#"<a href="{}">source</a>"#, Html::text(url)));
+    }
+
+    if parts.is_empty() {
+        return Html::default();
+    }
+    html!(
+        r#"<footer class="book-footer">{}</footer>"#,
+        Html::join(&parts, &Html::raw(" — "))
+    )
 }
 
 impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_invalid_book_config(key: &str, value: &str, expected: &str) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("invalid `{key}` in the `[book]` section of watson.toml: `{value}`"),
+            vec![],
+        )
+        .with_info(expected, Vec::new())
+    }
+
+    pub fn warn_ragged_table_row(cells: usize, expected: usize, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!(
+                "table row has {cells} cell{} but the header has {expected}",
+                plural(cells)
+            ),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info("the short rows are padded with empty cells", Vec::new())
+    }
+
+    pub fn warn_command_interrupts_paragraph(span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            "command interrupts a paragraph",
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info(
+            "add a blank line before it so the paragraph ends where it looks like it does",
+            Vec::new(),
+        )
+    }
+
+    pub fn warn_unrendered_math(
+        failure: &MathFailure,
+        chapter: Option<usize>,
+        span: Span,
+    ) -> Diagnostic<'ctx> {
+        let title = match chapter {
+            Some(chapter) => format!("couldn't render a formula in chapter {chapter}"),
+            None => "couldn't render a formula".to_string(),
+        };
+        Diagnostic::new_warning(&title, vec![DiagnosticSpan::new_warning("", span)])
+            .with_info(&failure.message(), Vec::new())
+            .with_info("it is shown as written instead", Vec::new())
+    }
+
+    pub fn warn_slow_math(chapter: usize, slow: SlowMath, budget: Duration) -> Diagnostic<'ctx> {
+        let diag = Diagnostic::new_warning(
+            &format!(
+                "rendering the math in chapter {chapter} took {:.1}s, over the budget of {}s",
+                slow.spent.as_secs_f64(),
+                budget.as_secs()
+            ),
+            vec![],
+        );
+        if slow.skipped == 0 {
+            return diag;
+        }
+        diag.with_info(
+            &format!(
+                "{} formula{} after that {} shown as written",
+                slow.skipped,
+                plural(slow.skipped),
+                if slow.skipped == 1 { "is" } else { "are" }
+            ),
+            Vec::new(),
+        )
+        .with_info(
+            "raise `math_time_budget_secs` in the `[book]` section of watson.toml to render them",
+            Vec::new(),
+        )
+    }
+
+    pub fn warn_heading_after_command(span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            "heading immediately follows a command",
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info("add a blank line between them", Vec::new())
+    }
+
+    pub fn info_missing_book_metadata(keys: &[&str]) -> Diagnostic<'ctx> {
+        let keys = keys.iter().map(|key| format!("`{key}`")).join(", ");
+        Diagnostic::new_info(
+            &format!("the `[book]` section of watson.toml doesn't set {keys}"),
+            vec![],
+        )
+        .with_info(
+            "these are used for page metadata and the book.json manifest",
+            Vec::new(),
+        )
+    }
+
+    pub fn err_book_template<T>(err: &TemplateError) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(&err.to_string(), vec![]).with_info(
+            "`watson book --default-templates <dir>` writes the built-in templates to start from",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_unknown_theorem_ref<T>(
+        name: &str,
+        span: Span,
+        shown: impl IntoIterator<Item = Ustr>,
+    ) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!("no theorem named `{name}` is shown in the book"),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+        for candidate in similar_names(name, shown) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+        Err(vec![diag])
+    }
+
     pub fn err_content_outside_chapter<T>(span: Span) -> WResult<'ctx, T> {
         let diag = Diagnostic::new(
             "content must be inside a chapter",
             vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "start a chapter with a `# Title` heading before it",
+            Vec::new(),
         );
         Err(vec![diag])
     }
 }
 
+/// The proof status of a theorem as shown in the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Badge {
+    Axiom,
+    Proved,
+    Incomplete,
+    /// Proved, but the tactic thinks the proof might break.
+    Fragile,
+    Failed,
+    Unchecked,
+    /// The consistency sentinel, which is expected to fail.
+    Sentinel,
+}
+
+impl Badge {
+    fn for_theorem<'ctx>(thm: TheoremId<'ctx>, statuses: &ProofStatuses<'ctx>) -> Self {
+        match statuses.get(thm) {
+            None => Badge::Unchecked,
+            Some(status) if status.is_sentinel() => Badge::Sentinel,
+            Some(status) if status.is_axiom() => Badge::Axiom,
+            Some(status) if !status.correct() => Badge::Failed,
+            Some(status) if status.todo_used() => Badge::Incomplete,
+            Some(status) if status.fragile().is_some() => Badge::Fragile,
+            Some(_) => Badge::Proved,
+        }
+    }
+
+    fn text(self) -> &'static str {
+        match self {
+            Badge::Axiom => "axiom",
+            Badge::Proved => "proved",
+            Badge::Incomplete => "incomplete",
+            Badge::Fragile => "fragile",
+            Badge::Failed => "failed",
+            Badge::Unchecked => "unchecked",
+            Badge::Sentinel => "sentinel",
+        }
+    }
+
+    fn icon(self) -> &'static str {
+        match self {
+            Badge::Axiom => "∗",
+            Badge::Proved => "✓",
+            Badge::Incomplete => "⚠",
+            Badge::Fragile => "≈",
+            Badge::Failed => "✗",
+            Badge::Unchecked => "?",
+            Badge::Sentinel => "⊥",
+        }
+    }
+
+    /// The row above a statement with its label, like "Lemma 2.1", and the
+    /// badge. The badge's tooltip is `title` or else its text.
+    fn render(self, label: &str, title: Option<&str>, style: BadgeStyle) -> Html {
+        let mut out = html!(
+            r#"<div class="status"><span class="statement-label">{}</span>"#,
+            Html::text(label)
+        );
+        let badge = match style {
+            BadgeStyle::Icons => Some(self.icon()),
+            BadgeStyle::Text => Some(self.text()),
+            BadgeStyle::None => None,
+        };
+        if let Some(badge) = badge {
+            let text = Html::raw(self.text());
+            out += html!(
+                r#"<span class="badge {}" title="{}">{}</span>"#,
+                text,
+                Html::text(title.unwrap_or(self.text())),
+                Html::raw(badge)
+            );
+        }
+        out += Html::raw("</div>\n");
+        out
+    }
+}
+
+/// The tooltip for a theorem's badge when it says more than the badge's
+/// text, which is the reasons and tickets for the todos it uses.
+fn badge_title<'ctx>(thm: TheoremId<'ctx>, statuses: &ProofStatuses<'ctx>) -> Option<String> {
+    let status = statuses.get(thm).filter(|status| status.todo_used())?;
+    let todos = status
+        .todos()
+        .iter()
+        .map(|todo| {
+            let reason = todo.reason.as_deref().unwrap_or("unimplemented");
+            match &todo.ticket {
+                Some(ticket) => format!("{reason} [{ticket}]"),
+                None => reason.to_string(),
+            }
+        })
+        .unique()
+        .join("; ");
+    Some(format!("incomplete: {todos}"))
+}
+
+/// What the book shows about the theorem declared by a command.
+struct BookTheorem<'ctx> {
+    theorem: TheoremId<'ctx>,
+    kind: TheoremKind,
+    badge: Badge,
+    /// Replaces the badge's text as its tooltip.
+    badge_title: Option<String>,
+    /// The axioms the theorem depends on, if they should be listed.
+    axiom_closure: Option<Vec<TheoremId<'ctx>>>,
+}
+
+/// The counters for numbering statements within a chapter. Theorems, lemmas
+/// and propositions share a counter and axioms have their own. Corollaries
+/// share the theorem counter unless the book config separates them.
+#[derive(Debug, Default)]
+struct StatementNumbers {
+    separate_corollaries: bool,
+    theorems: usize,
+    corollaries: usize,
+    axioms: usize,
+}
+
+impl StatementNumbers {
+    fn new(separate_corollaries: bool) -> Self {
+        Self {
+            separate_corollaries,
+            ..Self::default()
+        }
+    }
+
+    fn next(&mut self, kind: TheoremKind) -> usize {
+        let counter = match kind {
+            TheoremKind::Axiom => &mut self.axioms,
+            TheoremKind::Corollary if self.separate_corollaries => &mut self.corollaries,
+            TheoremKind::Theorem
+            | TheoremKind::Lemma
+            | TheoremKind::Proposition
+            | TheoremKind::Corollary => &mut self.theorems,
+        };
+        *counter += 1;
+        *counter
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new(self.separate_corollaries);
+    }
+}
+
+/// How many theorems in a chapter or section have each status.
+#[derive(Debug, Default, Serialize)]
+struct ChapterCounts {
+    /// Every statement, including axioms.
+    statements: usize,
+    proved: usize,
+    incomplete: usize,
+    failed: usize,
+}
+
+impl ChapterCounts {
+    fn add(&mut self, badge: Badge) {
+        self.statements += 1;
+        match badge {
+            // Fragile proofs still count as proved.
+            Badge::Proved | Badge::Fragile => self.proved += 1,
+            Badge::Incomplete => self.incomplete += 1,
+            Badge::Failed => self.failed += 1,
+            Badge::Axiom | Badge::Unchecked | Badge::Sentinel => {}
+        }
+    }
+
+    fn render(&self, style: BadgeStyle) -> Html {
+        if style == BadgeStyle::None {
+            return Html::default();
+        }
+
+        let counts = [
+            (self.proved, Badge::Proved),
+            (self.incomplete, Badge::Incomplete),
+            (self.failed, Badge::Failed),
+        ];
+        let mut out = Html::default();
+        for (count, badge) in counts {
+            if count > 0 {
+                let text = Html::raw(badge.text());
+                out += html!(
+                    r#" <span class="count {}" title="{} {}">{} {}</span>"#,
+                    text,
+                    count,
+                    text,
+                    count,
+                    Html::raw(badge.icon())
+                );
+            }
+        }
+        out
+    }
+}
+
 #[derive(Debug)]
 struct DocState {
-    chapter_contents: Vec<String>,
-    chapter_titles: Vec<String>,
-    current_chapter_content: String,
-    sidebar_content: String,
-    base_path: String,
+    templates: Templates,
+    /// The chapters and sections, found before any of them is processed.
+    /// The sidebar is built from it once every chapter has been processed.
+    outline: Outline,
+    chapter_contents: Vec<Html>,
+    current_chapter_content: Html,
+    base_path: Html,
+    badge_style: BadgeStyle,
 
     chapter: Option<usize>,
     section: Option<usize>,
+    statement_numbers: StatementNumbers,
+    /// Only the first content outside a chapter is reported since everything
+    /// after it up to the first chapter is outside one too.
+    outside_chapter_reported: bool,
+    math: MathRenderer,
+    /// The chapters whose math went over the time budget, reported once
+    /// every chapter has been processed.
+    slow_math: Vec<(usize, SlowMath)>,
+    /// Where each shown theorem is, for `@thm(name)` in the prose. A draft's
+    /// commands were never elaborated, so it has none and its references
+    /// aren't linked.
+    theorem_anchors: Option<FxHashMap<Ustr, TheoremAnchor>>,
+}
+
+#[derive(Debug)]
+struct TheoremAnchor {
+    chapter: usize,
+    /// The statement, shown when hovering over a reference.
+    statement: String,
 }
 
 impl DocState {
-    fn new(base_path: String) -> Self {
+    fn new(
+        templates: Templates,
+        base_path: Html,
+        badge_style: BadgeStyle,
+        separate_corollaries: bool,
+        math: MathRenderer,
+    ) -> Self {
         Self {
+            templates,
+            outline: Outline::default(),
             chapter_contents: Vec::new(),
-            chapter_titles: Vec::new(),
-            current_chapter_content: String::new(),
-            sidebar_content: String::new(),
+            current_chapter_content: Html::default(),
             base_path,
+            badge_style,
             chapter: None,
             section: None,
+            statement_numbers: StatementNumbers::new(separate_corollaries),
+            outside_chapter_reported: false,
+            math,
+            slow_math: Vec::new(),
+            theorem_anchors: None,
         }
     }
 
     fn commit_chapter(&mut self) {
-        if self.current_chapter_content.is_empty() {
+        let slow_math = self.math.finish_chapter();
+
+        // Content before the first chapter has been reported and isn't shown.
+        let Some(chapter) = self.chapter else {
+            self.current_chapter_content.clear();
             return;
+        };
+        if let Some(slow_math) = slow_math {
+            self.slow_math.push((chapter, slow_math));
         }
 
         self.close_section();
 
         let current = std::mem::take(&mut self.current_chapter_content);
         self.chapter_contents.push(current);
-
-        self.sidebar_content += "</ol>\n";
-        self.sidebar_content += "</li>\n";
     }
 
-    fn next_chapter<'ctx>(&mut self, title: &str) -> WResult<'ctx, ()> {
+    /// Start a chapter. `heading` is shown in the page and `title` is the
+    /// plain text of it.
+    fn next_chapter<'ctx>(&mut self, heading: &Html, title: &str) -> WResult<'ctx, ()> {
         self.commit_chapter();
 
         let next_chapter_num = self.chapter.unwrap_or(0) + 1;
-        self.current_chapter_content += &replace_patterns(
-            include_str!("templates/chapter_header.html"),
-            &["{{CHAPTER_TITLE}}", "{{CHAPTER_NUM}}"],
-            &[title, &next_chapter_num.to_string()],
-        );
+        self.outline
+            .set_heading(next_chapter_num, None, heading.clone(), title.to_string());
+        self.current_chapter_content += self.templates.chapter_header.fill(&[
+            ("CHAPTER_TITLE", heading),
+            ("CHAPTER_NUM", &html!("{}", next_chapter_num)),
+        ]);
 
         self.chapter = Some(next_chapter_num);
-        self.chapter_titles.push(title.to_string());
         self.section = None;
-
-        self.sidebar_content += "<li>\n";
-        self.sidebar_content += &format!(
-            "<a href=\"{}chapter-{}/\" class=\"chapter\" data-chapter=\"{}\"><span class=\"num\">{}</span> {}</a>\n",
-            self.base_path, next_chapter_num, next_chapter_num, next_chapter_num, title
-        );
-        self.sidebar_content += "<ol class=\"section-list\">\n";
+        self.statement_numbers.reset();
 
         Ok(())
     }
 
     fn close_section(&mut self) {
         if self.section.is_some() {
-            self.current_chapter_content += "</section>\n";
+            self.current_chapter_content += html!("</section>\n");
         }
     }
 
-    fn next_section<'ctx>(&mut self, title: &str) -> WResult<'ctx, ()> {
+    fn next_section<'ctx>(&mut self, heading: &Html, title: &str) -> WResult<'ctx, ()> {
         self.close_section();
 
         let Some(chapter_num) = self.chapter else {
-            // TODO
+            // Reported as content outside a chapter when the text was
+            // processed.
             return Ok(());
         };
         let next_section_num = self.section.unwrap_or(0) + 1;
-        self.current_chapter_content += &format!("<section id=\"section-{}\">\n", next_section_num);
-        self.current_chapter_content += &replace_patterns(
-            include_str!("templates/section_header.html"),
-            &["{{SECTION_TITLE}}", "{{CHAPTER_NUM}}", "{{SECTION_NUM}}"],
-            &[
-                title,
-                &chapter_num.to_string(),
-                &next_section_num.to_string(),
-            ],
-        );
-        self.section = Some(next_section_num);
-
-        self.sidebar_content += &format!(
-            "<li class=\"section\"><a href=\"{}chapter-{}/#section-{}\" data-chapter=\"{}\" data-section=\"{}\"><span class=\"num\">{}.{}</span> {}</a></li>\n",
-            self.base_path,
-            chapter_num,
-            next_section_num,
-            chapter_num,
-            next_section_num,
+        self.outline.set_heading(
             chapter_num,
-            next_section_num,
-            title
+            Some(next_section_num),
+            heading.clone(),
+            title.to_string(),
         );
+        self.current_chapter_content += html!("<section id=\"section-{}\">\n", next_section_num);
+        self.current_chapter_content += self.templates.section_header.fill(&[
+            ("SECTION_TITLE", heading),
+            ("CHAPTER_NUM", &html!("{}", chapter_num)),
+            ("SECTION_NUM", &html!("{}", next_section_num)),
+        ]);
+        self.section = Some(next_section_num);
 
         Ok(())
     }
 
-    fn process_entries<'ctx>(&mut self, entries: &[ParseEntry<'ctx>], ctx: &mut Ctx<'ctx>) {
-        self.sidebar_content += r#"<ol class="chapter-list">"#;
-        self.sidebar_content += "\n";
-
-        for &entry in entries {
-            match self.process_entry(entry, ctx) {
+    fn process_entries<'ctx>(
+        &mut self,
+        entries: &[ParseEntry<'ctx>],
+        theorems: &FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>>,
+        ctx: &mut Ctx<'ctx>,
+    ) {
+        self.outline = book_outline(entries, theorems, ctx);
+        self.theorem_anchors = theorem_anchors(entries, theorems, &self.outline);
+        self.math.prerender(book_formulas(entries, ctx));
+
+        let check_layout = ctx.config.book().prose_layout_warnings();
+        for (i, &entry) in entries.iter().enumerate() {
+            if !ctx.diags.should_continue() {
+                break;
+            }
+            if check_layout && i > 0 {
+                check_prose_layout(entries[i - 1], entry, ctx);
+            }
+            match self.process_entry(entry, theorems, ctx) {
                 Ok(_) => {}
                 Err(err) => ctx.diags.add_diags(err),
             }
         }
 
-        self.sidebar_content += r#"</ol>"#;
-        self.sidebar_content += "\n";
         self.commit_chapter();
+
+        for (chapter, slow_math) in self.slow_math.drain(..) {
+            let budget = ctx.config.book().math_time_budget();
+            ctx.diags
+                .add_diag(Diagnostic::warn_slow_math(chapter, slow_math, budget));
+        }
+    }
+
+    fn err_outside_chapter<'ctx>(&mut self, span: Span) -> WResult<'ctx, ()> {
+        if std::mem::replace(&mut self.outside_chapter_reported, true) {
+            return Ok(());
+        }
+        Diagnostic::err_content_outside_chapter(span)
     }
 
     fn process_entry<'ctx>(
         &mut self,
         entry: ParseEntry<'ctx>,
-        ctx: &Ctx<'ctx>,
+        theorems: &FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>>,
+        ctx: &mut Ctx<'ctx>,
     ) -> WResult<'ctx, ()> {
         match entry {
             ParseEntry::Text(span) => {
                 let text = ctx.sources.get_text(span.source());
                 let text = &text[span.bytes()];
-                self.process_markdown_text(text)?;
+                if self.chapter.is_none()
+                    && let Some(line) = content_before_chapter(text)
+                {
+                    let line = Span::new(
+                        span.start().forward(line.start),
+                        span.start().forward(line.end),
+                    );
+                    self.err_outside_chapter(line)?;
+                }
+
+                let to_span = |range: Range<usize>| {
+                    Span::new(
+                        span.start().forward(range.start),
+                        span.start().forward(range.end),
+                    )
+                };
+                for block in split_tables(text) {
+                    match block {
+                        ProseBlock::Text(range) => {
+                            let start = span.start().forward(range.start);
+                            self.process_markdown_text(&text[range], start, ctx)?;
+                        }
+                        ProseBlock::Table(table) => {
+                            let expected = table.header().cell_cnt();
+                            for (cells, row) in table.ragged_rows() {
+                                let diag = Diagnostic::warn_ragged_table_row(
+                                    cells,
+                                    expected,
+                                    to_span(row),
+                                );
+                                ctx.diags.add_diag(diag);
+                            }
+                            self.push_table(&table, span.start(), ctx)?;
+                        }
+                    }
+                }
                 Ok(())
             }
-            ParseEntry::Command(parse_tree) => {
+            ParseEntry::Command(parse_tree, cmd) => {
+                if ctx
+                    .command_attributes
+                    .has(cmd, BuiltinAttributeKind::Hidden)
+                {
+                    return Ok(());
+                }
                 if self.chapter.is_none() {
-                    return Diagnostic::err_content_outside_chapter(parse_tree.0.span());
+                    return self.err_outside_chapter(parse_tree.span());
                 }
 
-                let span = parse_tree.0.span();
+                let span = parse_tree.span();
                 let source_text = ctx.sources.get_text(span.source());
-                let command_text = &source_text[span.bytes()];
-
-                // Get starting line number using the optimized method
-                let start_line = ctx.sources.get_line_number(span.start());
 
                 // Collect syntax highlighting information
                 let highlights = collect_highlights(
                     parse_tree,
                     span.start().byte_offset(),
                     source_text.as_str(),
+                    ctx,
                 );
 
-                // Add code block with line numbers and syntax highlighting
-                self.current_chapter_content += r#"<pre><code class="code-block">"#;
-                let mut byte_offset = 0;
-                for (i, line) in command_text.lines().enumerate() {
-                    let line_num = start_line + i;
-                    self.current_chapter_content += r#"<span class="line">"#;
-                    self.current_chapter_content += &line_num.to_string();
-                    self.current_chapter_content += r#"</span>"#;
-
-                    // Calculate byte offsets for this line within the command
-                    let line_start = byte_offset;
-                    let line_end = byte_offset + line.len();
-
-                    self.current_chapter_content += r#"<span>"#;
-                    self.current_chapter_content +=
-                        &render_highlighted_line(line, line_start, line_end, &highlights);
-                    self.current_chapter_content += r#"</span>"#;
-                    self.current_chapter_content += "\n";
-
-                    // Move to next line (line length + newline)
-                    byte_offset = line_end + 1;
+                let theorem = theorems.get(&parse_tree);
+                if let Some(theorem) = theorem {
+                    self.current_chapter_content += html!(
+                        r#"<span id="{}"></span>"#,
+                        Html::text(&theorem_anchor_id(theorem.theorem.name()))
+                    );
+                    self.outline
+                        .count(self.chapter.unwrap(), self.section, theorem.badge);
+                    let number = self.statement_numbers.next(theorem.kind);
+                    let label = format!(
+                        "{} {}.{}",
+                        theorem.kind.label(),
+                        self.chapter.unwrap(),
+                        number
+                    );
+                    self.current_chapter_content += theorem.badge.render(
+                        &label,
+                        theorem.badge_title.as_deref(),
+                        self.badge_style,
+                    );
+                }
+
+                self.push_code_block(span, &highlights, ctx);
+
+                if let Some(axioms) = theorem.and_then(|t| t.axiom_closure.as_ref()) {
+                    render_axiom_closure(&mut self.current_chapter_content, axioms);
+                }
+                Ok(())
+            }
+            ParseEntry::RawCommand(span) => {
+                if self.chapter.is_none() {
+                    return self.err_outside_chapter(span);
                 }
-                self.current_chapter_content += "</code></pre>\n";
 
+                // The command was never parsed so there is nothing to
+                // highlight.
+                self.push_code_block(span, &[], ctx);
                 Ok(())
             }
         }
     }
 
-    fn process_markdown_text<'ctx>(&mut self, text: &str) -> WResult<'ctx, ()> {
+    /// Render a table found in prose starting at `start`.
+    fn push_table<'ctx>(
+        &mut self,
+        table: &Table,
+        start: Location,
+        ctx: &mut Ctx<'ctx>,
+    ) -> WResult<'ctx, ()> {
+        let width = table.width();
+
+        self.current_chapter_content += html!("<table>\n<thead>\n<tr>");
+        let header = table.header();
+        for column in 0..width {
+            let (text, row_start) = (header.cell(column), start.forward(header.start()));
+            self.push_table_cell("th", text, table.alignment(column), row_start, ctx)?;
+        }
+        self.current_chapter_content += html!("</tr>\n</thead>\n<tbody>\n");
+        for row in table.rows() {
+            self.current_chapter_content += html!("<tr>");
+            for column in 0..width {
+                let (text, row_start) = (row.cell(column), start.forward(row.start()));
+                self.push_table_cell("td", text, table.alignment(column), row_start, ctx)?;
+            }
+            self.current_chapter_content += html!("</tr>\n");
+        }
+        self.current_chapter_content += html!("</tbody>\n</table>\n");
+
+        Ok(())
+    }
+
+    /// Render a cell of the row starting at `row_start`. The cell is found in
+    /// the row as written to say where its math is, or else the math is
+    /// placed at the start of the row.
+    fn push_table_cell<'ctx>(
+        &mut self,
+        tag: &str,
+        text: &str,
+        alignment: Alignment,
+        row_start: Location,
+        ctx: &mut Ctx<'ctx>,
+    ) -> WResult<'ctx, ()> {
+        let row = &ctx.sources.get_text(row_start.source())[row_start.byte_offset()..];
+        let row = row.lines().next().unwrap_or_default();
+        let cell_start = row.find(text).map_or(row_start, |at| row_start.forward(at));
+
+        // Render the cell on its own so its markdown can't reach outside it.
+        let outer = std::mem::take(&mut self.current_chapter_content);
+        let result = self.process_markdown_text(text, cell_start, ctx);
+        let rendered = std::mem::replace(&mut self.current_chapter_content, outer);
+        result?;
+
+        // Markdown wraps the text in a paragraph, which the cell doesn't need.
+        // Taking tags off the ends of markup leaves markup.
+        let rendered = rendered.as_str().trim();
+        let rendered = rendered
+            .strip_prefix("<p>")
+            .and_then(|cell| cell.strip_suffix("</p>"))
+            .unwrap_or(rendered);
+
+        let tag = Html::raw(tag);
+        self.current_chapter_content += match alignment.class() {
+            Some(class) => html!(r#"<{} class="{}">"#, tag, Html::raw(class)),
+            None => html!("<{}>", tag),
+        };
+        self.current_chapter_content += Html::raw(rendered);
+        self.current_chapter_content += html!("</{}>", tag);
+
+        Ok(())
+    }
+
+    /// Show the command at `span` as a code block. Theorems it refers to link
+    /// to where they are shown, if they are.
+    fn push_code_block(&mut self, span: Span, highlights: &[Highlight], ctx: &Ctx) {
+        let source_text = ctx.sources.get_text(span.source());
+        let command_text = &source_text[span.bytes()];
+
+        let (start_line, _) = ctx.sources.line_col(span.start());
+
+        // Where the command is, relative to the math directory.
+        let origin = ctx.module_graph.file_of(span.source()).map(|file| {
+            let file = file.strip_prefix(ctx.config.math_dir()).unwrap_or(file);
+            format!("{}:{start_line}", file.display())
+        });
+
+        let (anchors, base_path) = (&self.theorem_anchors, &self.base_path);
+        let thm_href = |name| {
+            let anchor = anchors.as_ref()?.get(&name)?;
+            Some(theorem_href(base_path, anchor, name))
+        };
+        render_code_block(
+            &mut self.current_chapter_content,
+            command_text,
+            start_line as usize,
+            highlights,
+            origin.as_deref(),
+            &thm_href,
+        );
+    }
+
+    /// Render markdown prose which starts at `start` in the sources.
+    fn process_markdown_text<'ctx>(
+        &mut self,
+        text: &str,
+        start: Location,
+        ctx: &mut Ctx<'ctx>,
+    ) -> WResult<'ctx, ()> {
         // Remove Watson-style -- comments before processing markdown
-        let text_without_comments = strip_watson_comments(text);
+        let (text_without_comments, line_starts) = strip_watson_comments(text);
+        // Where an offset into the stripped text is in the sources.
+        let loc_at = |offset: usize| unstripped_location(&line_starts, start, offset);
 
         // Enable math support in pulldown-cmark
         let mut options = Options::empty();
@@ -291,27 +1220,36 @@ impl DocState {
         let parser = Parser::new_ext(&text_without_comments, options);
 
         let mut in_heading: Option<HeadingLevel> = None;
+        let mut heading = Html::default();
+        // The plain text of the heading, without any markup.
         let mut heading_text = String::new();
+        // The text of the `watson` code block we are in, if any.
+        let mut example: Option<String> = None;
 
-        for event in parser {
+        // Merging the text lets a theorem reference be found in one piece.
+        for (event, range) in TextMergeWithOffset::new(parser.into_offset_iter()) {
             match event {
                 Event::Start(tag) => match tag {
                     Tag::Heading { level, .. } => {
                         in_heading = Some(level);
+                        heading.clear();
                         heading_text.clear();
                     }
                     _ if in_heading.is_none() => match tag {
-                        Tag::Paragraph => self.current_chapter_content += "<p>",
-                        Tag::BlockQuote(_) => self.current_chapter_content += "<blockquote>",
-                        Tag::CodeBlock(_) => self.current_chapter_content += "<pre><code>",
-                        Tag::List(None) => self.current_chapter_content += "<ul>",
-                        Tag::List(Some(_)) => self.current_chapter_content += "<ol>",
-                        Tag::Item => self.current_chapter_content += "<li>",
-                        Tag::Emphasis => self.current_chapter_content += "<em>",
-                        Tag::Strong => self.current_chapter_content += "<strong>",
+                        Tag::Paragraph => self.current_chapter_content += html!("<p>"),
+                        Tag::BlockQuote(_) => self.current_chapter_content += html!("<blockquote>"),
+                        Tag::CodeBlock(CodeBlockKind::Fenced(lang)) if &*lang == "watson" => {
+                            example = Some(String::new());
+                        }
+                        Tag::CodeBlock(_) => self.current_chapter_content += html!("<pre><code>"),
+                        Tag::List(None) => self.current_chapter_content += html!("<ul>"),
+                        Tag::List(Some(_)) => self.current_chapter_content += html!("<ol>"),
+                        Tag::Item => self.current_chapter_content += html!("<li>"),
+                        Tag::Emphasis => self.current_chapter_content += html!("<em>"),
+                        Tag::Strong => self.current_chapter_content += html!("<strong>"),
                         Tag::Link { dest_url, .. } => {
                             self.current_chapter_content +=
-                                &format!(r#"<a href="{}">"#, html_escape(&dest_url));
+                                html!(r#"<a href="{}">"#, Html::text(&dest_url));
                         }
                         _ => {}
                     },
@@ -323,35 +1261,47 @@ impl DocState {
                             // Handle the heading based on its level
                             match level {
                                 HeadingLevel::H1 => {
-                                    self.next_chapter(&heading_text)?;
+                                    self.next_chapter(&heading, &heading_text)?;
                                 }
                                 HeadingLevel::H2 => {
-                                    self.next_section(&heading_text)?;
+                                    self.next_section(&heading, &heading_text)?;
                                 }
                                 _ => {
                                     // Regular headings
+                                    let tag = Html::raw(heading_tag(level));
                                     self.current_chapter_content +=
-                                        &format!("<{}>", heading_tag(level));
-                                    self.current_chapter_content += &heading_text;
-                                    self.current_chapter_content +=
-                                        &format!("</{}>", heading_tag(level));
+                                        html!("<{}>{}</{}>", tag, heading, tag);
                                 }
                             }
                             in_heading = None;
+                            heading.clear();
                             heading_text.clear();
                         }
                         _ if in_heading.is_none() => match tag_end {
-                            TagEnd::Paragraph => self.current_chapter_content += "</p>\n",
+                            TagEnd::Paragraph => self.current_chapter_content += html!("</p>\n"),
                             TagEnd::BlockQuote(_) => {
-                                self.current_chapter_content += "</blockquote>\n"
+                                self.current_chapter_content += html!("</blockquote>\n")
                             }
-                            TagEnd::CodeBlock => self.current_chapter_content += "</code></pre>\n",
-                            TagEnd::List(false) => self.current_chapter_content += "</ul>\n",
-                            TagEnd::List(true) => self.current_chapter_content += "</ol>\n",
-                            TagEnd::Item => self.current_chapter_content += "</li>\n",
-                            TagEnd::Emphasis => self.current_chapter_content += "</em>",
-                            TagEnd::Strong => self.current_chapter_content += "</strong>",
-                            TagEnd::Link => self.current_chapter_content += "</a>",
+                            TagEnd::CodeBlock => match example.take() {
+                                Some(code) => {
+                                    let highlights = example_highlights(&code, ctx);
+                                    render_code_block(
+                                        &mut self.current_chapter_content,
+                                        &code,
+                                        1,
+                                        &highlights,
+                                        None,
+                                        &|_| None,
+                                    );
+                                }
+                                None => self.current_chapter_content += html!("</code></pre>\n"),
+                            },
+                            TagEnd::List(false) => self.current_chapter_content += html!("</ul>\n"),
+                            TagEnd::List(true) => self.current_chapter_content += html!("</ol>\n"),
+                            TagEnd::Item => self.current_chapter_content += html!("</li>\n"),
+                            TagEnd::Emphasis => self.current_chapter_content += html!("</em>"),
+                            TagEnd::Strong => self.current_chapter_content += html!("</strong>"),
+                            TagEnd::Link => self.current_chapter_content += html!("</a>"),
                             _ => {}
                         },
                         _ => {}
@@ -360,52 +1310,81 @@ impl DocState {
                 Event::Text(text) => {
                     if in_heading.is_some() {
                         // Accumulate heading text
+                        heading.push_text(&text);
                         heading_text.push_str(&text);
+                    } else if let Some(example) = &mut example {
+                        example.push_str(&text);
                     } else {
-                        self.current_chapter_content += &html_escape(&text);
+                        // The text may have had escapes, so each reference
+                        // is looked for again as written to say where it is.
+                        let written_text = &text_without_comments[range.clone()];
+                        let mut searched = 0;
+                        let mut last = 0;
+                        for (at, name) in theorem_refs(&text) {
+                            self.current_chapter_content
+                                .push_text(&text[last..at.start]);
+                            let written = &text[at.clone()];
+                            let offset = match written_text[searched..].find(written) {
+                                Some(found) => searched + found,
+                                None => searched,
+                            };
+                            searched = (offset + written.len()).min(written_text.len());
+                            let ref_start = loc_at(range.start + offset);
+                            let span = Span::new(ref_start, ref_start.forward(written.len()));
+                            self.current_chapter_content +=
+                                self.theorem_link(name, written, span, ctx);
+                            last = at.end;
+                        }
+                        self.current_chapter_content.push_text(&text[last..]);
                     }
                 }
                 Event::Code(code) => {
                     if in_heading.is_some() {
+                        heading.push_text(&code);
                         heading_text.push_str(&code);
                     } else {
-                        self.current_chapter_content += "<code>";
-                        self.current_chapter_content += &html_escape(&code);
-                        self.current_chapter_content += "</code>";
+                        self.current_chapter_content += html!("<code>{}</code>", Html::text(&code));
                     }
                 }
                 Event::Html(html) => {
+                    // HTML written in the prose is trusted like the rest of it.
                     if in_heading.is_some() {
-                        // Strip HTML tags from headings
-                        heading_text.push_str(&html);
+                        // Keep HTML tags out of the plain title
+                        heading += Html::raw(&html);
                     } else {
                         // Preserve HTML (including rendered math from KaTeX)
-                        self.current_chapter_content += &html;
+                        self.current_chapter_content += Html::raw(&html);
                     }
                 }
                 Event::InlineMath(latex) => {
+                    let math_span = Span::new(loc_at(range.start), loc_at(range.end));
+                    let math = self.render_math(&latex, false, math_span, ctx);
                     if in_heading.is_some() {
-                        // Include math in heading text (rendered)
-                        heading_text.push_str(&render_latex(&latex, false));
+                        // Include math in the heading rendered and in the
+                        // plain title as written
+                        heading += math;
+                        heading_text.push_str(&latex);
                     } else {
-                        let rendered = render_latex(&latex, false);
-                        self.current_chapter_content += &rendered;
+                        self.current_chapter_content += math;
                     }
                 }
                 Event::DisplayMath(latex) => {
+                    let math_span = Span::new(loc_at(range.start), loc_at(range.end));
                     if in_heading.is_some() {
-                        // Include math in heading text (rendered)
-                        heading_text.push_str(&render_latex(&latex, false));
+                        // Include math in the heading rendered and in the
+                        // plain title as written
+                        heading += self.render_math(&latex, false, math_span, ctx);
+                        heading_text.push_str(&latex);
                     } else {
-                        let rendered = render_latex(&latex, true);
-                        self.current_chapter_content += &rendered;
+                        let math = self.render_math(&latex, true, math_span, ctx);
+                        self.current_chapter_content += math;
                     }
                 }
                 Event::SoftBreak if in_heading.is_none() => {
-                    self.current_chapter_content += "\n";
+                    self.current_chapter_content += html!("\n");
                 }
                 Event::HardBreak if in_heading.is_none() => {
-                    self.current_chapter_content += "<br/>\n";
+                    self.current_chapter_content += html!("<br/>\n");
                 }
                 _ => {}
             }
@@ -413,16 +1392,441 @@ impl DocState {
 
         Ok(())
     }
+
+    /// Link to where the theorem `name` is shown. An unknown theorem is
+    /// reported and the reference is shown as written.
+    fn theorem_link(&self, name: &str, written: &str, span: Span, ctx: &mut Ctx) -> Html {
+        let Some(anchors) = &self.theorem_anchors else {
+            return html!(r#"<span class="thm-link">{}</span>"#, Html::text(name));
+        };
+        let Some(anchor) = anchors.get(&Ustr::from(name)) else {
+            let shown = anchors.keys().copied();
+            let diags = Diagnostic::err_unknown_theorem_ref::<()>(name, span, shown).unwrap_err();
+            ctx.diags.add_diags(diags);
+            return Html::text(written);
+        };
+        html!(
+            r#"<a class="thm-link" href="{}" title="{}">{}</a>"#,
+            theorem_href(&self.base_path, anchor, Ustr::from(name)),
+            Html::text(&anchor.statement),
+            Html::text(name)
+        )
+    }
+
+    /// Render a formula written at `span`, delimiters included. One which
+    /// can't be rendered is reported and shown as written.
+    fn render_math(&mut self, latex: &str, display_mode: bool, span: Span, ctx: &mut Ctx) -> Html {
+        match self.math.render(latex, display_mode) {
+            Ok(rendered) => rendered,
+            Err(failure) => {
+                if failure != MathFailure::OutOfTime {
+                    ctx.diags.add_diag(Diagnostic::warn_unrendered_math(
+                        &failure,
+                        self.chapter,
+                        span,
+                    ));
+                }
+                failure.placeholder(latex, display_mode)
+            }
+        }
+    }
+}
+
+/// Every formula in the prose with whether it is rendered in display mode, in
+/// the order they are written, so they can all be rendered up front.
+fn book_formulas(entries: &[ParseEntry], ctx: &Ctx) -> Vec<(String, bool)> {
+    let mut formulas = Vec::new();
+    for entry in entries {
+        let ParseEntry::Text(span) = entry else {
+            continue;
+        };
+        let text = ctx.sources.get_text(span.source());
+        let text = &text[span.bytes()];
+        for block in split_tables(text) {
+            match block {
+                ProseBlock::Text(range) => collect_formulas(&text[range], &mut formulas),
+                ProseBlock::Table(table) => {
+                    for row in std::iter::once(table.header()).chain(table.rows()) {
+                        for column in 0..table.width() {
+                            collect_formulas(row.cell(column), &mut formulas);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    formulas
+}
+
+/// Add the formulas in the markdown `text`, read the way
+/// `DocState::process_markdown_text` reads it.
+fn collect_formulas(text: &str, formulas: &mut Vec<(String, bool)>) {
+    let (text, _) = strip_watson_comments(text);
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_MATH);
+
+    let mut in_heading = false;
+    for event in Parser::new_ext(&text, options) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => in_heading = true,
+            Event::End(TagEnd::Heading(_)) => in_heading = false,
+            Event::InlineMath(latex) => formulas.push((latex.to_string(), false)),
+            // Headings show display math inline.
+            Event::DisplayMath(latex) => formulas.push((latex.to_string(), !in_heading)),
+            _ => {}
+        }
+    }
+}
+
+/// Find the chapters and sections of the book and the theorems shown in
+/// each before any chapter is rendered.
+fn book_outline<'ctx>(
+    entries: &[ParseEntry<'ctx>],
+    theorems: &FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>>,
+    ctx: &Ctx<'ctx>,
+) -> Outline {
+    enum Found {
+        Heading(HeadingLevel, Location),
+        Theorem(Ustr),
+    }
+
+    let mut found = Vec::new();
+    for &entry in entries {
+        match entry {
+            ParseEntry::Text(span) => {
+                let text = &ctx.sources.get_text(span.source()).as_str()[span.bytes()];
+                for block in split_tables(text) {
+                    if let ProseBlock::Text(range) = block {
+                        let start = span.start().forward(range.start);
+                        let headings = outline_headings(&text[range], start);
+                        found.extend(
+                            headings
+                                .into_iter()
+                                .map(|(level, loc)| Found::Heading(level, loc)),
+                        );
+                    }
+                }
+            }
+            ParseEntry::Command(tree, cmd) => {
+                let hidden = ctx
+                    .command_attributes
+                    .has(cmd, BuiltinAttributeKind::Hidden);
+                if let Some(theorem) = theorems.get(&tree)
+                    && !hidden
+                {
+                    found.push(Found::Theorem(theorem.theorem.name()));
+                }
+            }
+            ParseEntry::RawCommand(_) => {}
+        }
+    }
+
+    let locs: Vec<_> = found
+        .iter()
+        .filter_map(|found| match found {
+            Found::Heading(_, loc) => Some(*loc),
+            Found::Theorem(_) => None,
+        })
+        .collect();
+    let mut sources = OutlineSource::for_locations(&locs, ctx).into_iter();
+
+    let mut outline = Outline::default();
+    for found in found {
+        match found {
+            Found::Heading(level, _) => {
+                // There is a source for each heading.
+                let source = sources.next().unwrap();
+                if level == HeadingLevel::H1 {
+                    outline.add_chapter(source);
+                } else {
+                    outline.add_section(source);
+                }
+            }
+            Found::Theorem(name) => outline.add_theorem(name),
+        }
+    }
+    outline
+}
+
+/// The level and location of each `#` and `##` heading in the markdown
+/// `text`, which starts at `start` in the sources. The text is read the way
+/// `DocState::process_markdown_text` reads it, so the headings are the ones
+/// it finds.
+fn outline_headings(text: &str, start: Location) -> Vec<(HeadingLevel, Location)> {
+    let (text, line_starts) = strip_watson_comments(text);
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_MATH);
+
+    Parser::new_ext(&text, options)
+        .into_offset_iter()
+        .filter_map(|(event, range)| match event {
+            Event::Start(Tag::Heading {
+                level: level @ (HeadingLevel::H1 | HeadingLevel::H2),
+                ..
+            }) => Some((level, unstripped_location(&line_starts, start, range.start))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Find the chapter each shown theorem is in from the outline, so prose can
+/// refer to theorems in later chapters.
+fn theorem_anchors<'ctx>(
+    entries: &[ParseEntry<'ctx>],
+    theorems: &FxHashMap<ParseTreeId<'ctx>, BookTheorem<'ctx>>,
+    outline: &Outline,
+) -> Option<FxHashMap<Ustr, TheoremAnchor>> {
+    if entries
+        .iter()
+        .any(|entry| matches!(entry, ParseEntry::RawCommand(_)))
+    {
+        return None;
+    }
+
+    let statements: FxHashMap<_, _> = theorems
+        .values()
+        .map(|theorem| (theorem.theorem.name(), theorem.theorem))
+        .collect();
+    let mut anchors = FxHashMap::default();
+    for chapter in outline.chapters() {
+        for &name in chapter.theorems() {
+            let anchor = TheoremAnchor {
+                chapter: chapter.number(),
+                statement: statements[&name].print(),
+            };
+            anchors.insert(name, anchor);
+        }
+    }
+    Some(anchors)
+}
+
+/// The id of the element a theorem's command is shown after.
+fn theorem_anchor_id(name: Ustr) -> String {
+    format!("thm-{name}")
+}
+
+/// The link to the theorem `name`, which is shown at `anchor`.
+fn theorem_href(base_path: &Html, anchor: &TheoremAnchor, name: Ustr) -> Html {
+    html!(
+        "{}chapter-{}/#{}",
+        base_path,
+        anchor.chapter,
+        Html::text(&theorem_anchor_id(name))
+    )
+}
+
+/// Each `@thm(name)` in some prose, with the name.
+fn theorem_refs(text: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    text.match_indices("@thm(").filter_map(|(start, open)| {
+        let name_start = start + open.len();
+        let len = text[name_start..].find(')')?;
+        let name = text[name_start..name_start + len].trim();
+        let valid = !name.is_empty() && !name.contains(char::is_whitespace);
+        valid.then_some((start..name_start + len + 1, name))
+    })
+}
+
+/// Warn about prose and a command which touch without a blank line between
+/// them. A command ends the paragraph before it, and a heading right after a
+/// command looks like part of it in the source.
+fn check_prose_layout<'ctx>(prev: ParseEntry<'ctx>, entry: ParseEntry<'ctx>, ctx: &mut Ctx<'ctx>) {
+    let command_span = |entry: ParseEntry<'ctx>| match entry {
+        ParseEntry::Text(_) => None,
+        ParseEntry::Command(tree, _) => Some(tree.span()),
+        ParseEntry::RawCommand(span) => Some(span),
+    };
+
+    if let (ParseEntry::Text(text_span), Some(command)) = (prev, command_span(entry))
+        && text_span.end() == command.start()
+    {
+        let text = &ctx.sources.get_text(text_span.source()).as_str()[text_span.bytes()];
+        let last_line = text.lines().last().unwrap_or("");
+        if is_paragraph_line(last_line) {
+            let command_text = &ctx.sources.get_text(command.source()).as_str()[command.bytes()];
+            let first_line = command_text.lines().next().unwrap_or("");
+            let span = Span::new(command.start(), command.start().forward(first_line.len()));
+            ctx.diags
+                .add_diag(Diagnostic::warn_command_interrupts_paragraph(span));
+        }
+        return;
+    }
+
+    let (Some(command), ParseEntry::Text(text_span)) = (command_span(prev), entry) else {
+        return;
+    };
+    if command.end() != text_span.start() {
+        return;
+    }
+    let source_text = ctx.sources.get_text(text_span.source()).as_str();
+    let text = &source_text[text_span.bytes()];
+
+    // A command can end partway through a line, in which case the text
+    // starts with the rest of that line.
+    let mut lines = text.split_inclusive('\n');
+    let mut offset = 0;
+    if !source_text[..text_span.start().byte_offset()].ends_with('\n') {
+        offset += lines.next().map_or(0, str::len);
+    }
+    let Some(line) = lines.next() else {
+        return;
+    };
+    if is_heading_line(line) {
+        let line = line.trim_end();
+        let span = Span::new(
+            text_span.start().forward(offset),
+            text_span.start().forward(offset + line.len()),
+        );
+        ctx.diags
+            .add_diag(Diagnostic::warn_heading_after_command(span));
+    }
+}
+
+/// Whether a line of prose is part of a paragraph, rather than being blank,
+/// a comment, a heading, a table row or a code fence.
+fn is_paragraph_line(line: &str) -> bool {
+    let content = match line.find("--") {
+        Some(comment_pos) => &line[..comment_pos],
+        None => line,
+    };
+    let content = content.trim();
+    !content.is_empty()
+        && !is_heading_line(content)
+        && !content.starts_with('|')
+        && !content.starts_with("```")
+}
+
+fn is_heading_line(line: &str) -> bool {
+    heading_level(line).is_some()
+}
+
+/// The level of a `#` heading.
+fn heading_level(line: &str) -> Option<usize> {
+    let line = line.trim();
+    let rest = line.trim_start_matches('#');
+    let level = line.len() - rest.len();
+    ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with(' '))).then_some(level)
+}
+
+/// Add a code block with line numbers and syntax highlighting onto the end of
+/// `out`. The `origin` of the code is shown when hovering over it, and
+/// theorem references link to `thm_href` of the theorem if it has one.
+fn render_code_block(
+    out: &mut Html,
+    text: &str,
+    start_line: usize,
+    highlights: &[Highlight],
+    origin: Option<&str>,
+    thm_href: &dyn Fn(Ustr) -> Option<Html>,
+) {
+    // The markup roughly doubles the size of the text.
+    out.reserve(2 * text.len());
+    match origin {
+        Some(origin) => {
+            *out += html!(
+                r#"<pre title="{}"><code class="code-block">"#,
+                Html::text(origin)
+            )
+        }
+        None => *out += html!(r#"<pre><code class="code-block">"#),
+    }
+    let mut byte_offset = 0;
+    for (i, raw_line) in text.split_inclusive('\n').enumerate() {
+        let line_num = start_line + i;
+        *out += html!(r#"<span class="line">{}</span>"#, line_num);
+
+        // Calculate byte offsets for this line within the block. The line
+        // ending can be `\r\n` so it isn't always one byte.
+        let line = raw_line
+            .strip_suffix('\n')
+            .map_or(raw_line, |line| line.strip_suffix('\r').unwrap_or(line));
+        let line_start = byte_offset;
+        let line_end = byte_offset + line.len();
+
+        *out += html!("<span>");
+        render_highlighted_line(out, line, line_start, line_end, highlights, thm_href);
+        *out += html!("</span>\n");
+
+        byte_offset += raw_line.len();
+    }
+    *out += html!("</code></pre>\n");
+}
+
+/// A collapsed list of the axioms a theorem depends on.
+fn render_axiom_closure(out: &mut Html, axioms: &[TheoremId]) {
+    *out += html!(
+        "<details class=\"axiom-closure\"><summary>Axioms used ({})</summary><ul>\n",
+        axioms.len()
+    );
+    for axiom in axioms {
+        *out += html!("<li><code>{}</code></li>\n", Html::text(&axiom.name()));
+    }
+    *out += html!("</ul></details>\n");
+}
+
+/// Highlight an example from a `watson` code block in the prose. The example
+/// is parsed as best we can with the final syntax of the project but never
+/// elaborated, so it can contain mistakes or syntax which isn't defined yet.
+fn example_highlights(code: &str, ctx: &Ctx) -> Vec<Highlight> {
+    let source = SourceId::new_snippet();
+    ctx.sources
+        .add(source, code.to_string(), SourceDecl::BookExample);
+
+    let mut highlights = Vec::new();
+    for tree in parse_example(source, ctx) {
+        highlights.extend(collect_highlights(tree, 0, code, ctx));
+    }
+    highlights
+}
+
+/// The byte range of the first line of prose in `text` which comes before a
+/// chapter heading, ignoring blank lines and comments.
+fn content_before_chapter(text: &str) -> Option<Range<usize>> {
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        let line_start = start;
+        start += line.len();
+
+        let content = match line.find("--") {
+            Some(comment_pos) => &line[..comment_pos],
+            None => line,
+        };
+        let trimmed = content.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed == "#" || trimmed.starts_with("# ") {
+            return None;
+        }
+
+        let offset = line_start + (content.len() - content.trim_start().len());
+        return Some(offset..offset + trimmed.len());
+    }
+    None
 }
 
 /// Strip Watson-style -- comments from text
 /// Comments start with -- and continue to the end of the line
-fn strip_watson_comments(text: &str) -> String {
+fn strip_watson_comments(text: &str) -> (String, Vec<(usize, usize)>) {
     let mut result = String::with_capacity(text.len());
+    // Where each line starts in the result and in `text`. A line with a
+    // comment is joined to the next so they don't line up.
+    let mut line_starts = Vec::new();
+    let mut offset = 0;
+    let mut in_code_block = false;
+
+    for line in text.split_inclusive('\n') {
+        line_starts.push((result.len(), offset));
+        offset += line.len();
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        // Code blocks are shown as written.
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
 
-    for line in text.lines() {
         // Find the position of -- comment starter
-        if let Some(comment_pos) = line.find("--") {
+        if !in_code_block && let Some(comment_pos) = line.find("--") {
             // Keep everything before the comment
             result.push_str(&line[..comment_pos]);
             result.push(' ');
@@ -433,13 +1837,15 @@ fn strip_watson_comments(text: &str) -> String {
         }
     }
 
-    result
+    (result, line_starts)
 }
 
-fn replace_patterns(template: &str, patterns: &[&str], replacements: &[&str]) -> String {
-    AhoCorasick::new(patterns)
-        .unwrap()
-        .replace_all(template, replacements)
+/// Where an offset into text stripped by `strip_watson_comments` is in the
+/// sources, given where each line starts and where the text starts.
+fn unstripped_location(line_starts: &[(usize, usize)], start: Location, offset: usize) -> Location {
+    let line = line_starts.partition_point(|&(stripped, _)| stripped <= offset);
+    let (stripped, source) = line_starts[line.max(1) - 1];
+    start.forward(source + offset - stripped)
 }
 
 fn heading_tag(level: HeadingLevel) -> &'static str {
@@ -453,24 +1859,6 @@ fn heading_tag(level: HeadingLevel) -> &'static str {
     }
 }
 
-fn render_latex(latex: &str, display_mode: bool) -> String {
-    let ctx = katex::KatexContext::default();
-    let settings = katex::Settings::builder()
-        .display_mode(display_mode)
-        .throw_on_error(false)
-        .build();
-
-    katex::render_to_string(&ctx, latex, &settings).unwrap()
-}
-
-fn html_escape(text: &str) -> String {
-    text.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&#39;")
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum HighlightKind {
     Keyword,
@@ -479,6 +1867,11 @@ enum HighlightKind {
     Num,
     Lit,
     Comment,
+    /// A name in a tactic script which refers to a known theorem, and the
+    /// theorem's name.
+    ThmRef(Ustr),
+    /// An object-language fragment embedded in a tactic script.
+    Fragment,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -493,26 +1886,52 @@ fn collect_highlights<'ctx>(
     parse_tree: ParseTreeId<'ctx>,
     offset: usize,
     source_text: &str,
+    ctx: &Ctx<'ctx>,
 ) -> Vec<Highlight> {
     let mut highlights = Vec::new();
 
     fn visit_tree<'ctx>(
         tree: ParseTreeId<'ctx>,
+        in_proof: bool,
         highlights: &mut Vec<Highlight>,
         offset: usize,
         source_text: &str,
+        ctx: &Ctx<'ctx>,
     ) {
         // Just use the first possibility for highlighting
-        if let Some(possibility) = tree.0.possibilities().first() {
-            // Check if this rule is from a notation (fragment) - if so, skip highlighting
+        if let Some(possibility) = tree.possibilities().next() {
+            // Check if this rule is from a notation (fragment) - if so, skip highlighting.
+            // Inside a proof we still mark where the fragment starts and ends so
+            // the object language stands out from the tactic script.
             if matches!(possibility.rule().0.source(), ParseRuleSource::Notation(_)) {
+                if in_proof {
+                    let start = first_atom_start(tree).unwrap_or(tree.span().start().byte_offset());
+                    highlights.push(Highlight {
+                        start: start - offset,
+                        end: tree.span().end().byte_offset() - offset,
+                        kind: HighlightKind::Fragment,
+                    });
+                }
                 return;
             }
-            if possibility.rule().name() == "template" {
+            if matches!(
+                possibility.rule().name().as_str(),
+                "template" | "template_default"
+            ) {
                 return;
             }
 
-            for child in possibility.children() {
+            // Tactic rules are user defined so we need the rule's pattern to
+            // know what each of the children represents.
+            let tactic_parts = match possibility.rule().0.source() {
+                ParseRuleSource::TacticRule(rule) => Some(rule.pattern().parts()),
+                _ => None,
+            };
+            let in_proof = in_proof || tactic_parts.is_some();
+
+            for (i, child) in possibility.children().iter().enumerate() {
+                let tactic_part = tactic_parts.map(|parts| parts[i].part());
+
                 match child {
                     ParseTreePart::Atom(atom) => {
                         let full_span = atom.full_span();
@@ -534,6 +1953,9 @@ fn collect_highlights<'ctx>(
                         }
 
                         let kind = match atom.kind() {
+                            _ if matches!(tactic_part, Some(CustomGrammarPatPartCore::Kw(_))) => {
+                                Some(HighlightKind::Keyword)
+                            }
                             ParseAtomKind::Kw(_) => Some(HighlightKind::Keyword),
                             ParseAtomKind::Name(_) => Some(HighlightKind::Name),
                             ParseAtomKind::StrLit(_) => Some(HighlightKind::StrLit),
@@ -561,34 +1983,58 @@ fn collect_highlights<'ctx>(
                         }
                     }
                     ParseTreePart::Node { id, .. } => {
-                        visit_tree(*id, highlights, offset, source_text);
+                        let first_new = highlights.len();
+                        visit_tree(*id, in_proof, highlights, offset, source_text, ctx);
+
+                        // Names in tactics which refer to a theorem get their own
+                        // class so that references stand out.
+                        if let Some(CustomGrammarPatPartCore::Name) = tactic_part
+                            && let Ok(name) = elaborate_name(*id, ctx)
+                            && ctx.arenas.theorem_stmts.get(name).is_some()
+                        {
+                            for highlight in &mut highlights[first_new..] {
+                                if highlight.kind == HighlightKind::Name {
+                                    highlight.kind = HighlightKind::ThmRef(name);
+                                }
+                            }
+                        }
                     }
                 }
             }
         }
     }
 
-    visit_tree(parse_tree, &mut highlights, offset, source_text);
+    /// The start of the first atom in the tree, skipping leading whitespace.
+    fn first_atom_start(tree: ParseTreeId) -> Option<usize> {
+        match tree.possibilities().next()?.children().first()? {
+            ParseTreePart::Atom(atom) => Some(atom.span().start().byte_offset()),
+            ParseTreePart::Node { id, .. } => first_atom_start(*id),
+        }
+    }
+
+    visit_tree(parse_tree, false, &mut highlights, offset, source_text, ctx);
     highlights.sort_by_key(|h| h.start);
     highlights
 }
 
-/// Render a line with syntax highlighting
+/// Render a line with syntax highlighting onto the end of `result`
 fn render_highlighted_line(
+    result: &mut Html,
     line: &str,
     line_start: usize,
     line_end: usize,
     highlights: &[Highlight],
-) -> String {
-    let mut result = String::new();
+    thm_href: &dyn Fn(Ustr) -> Option<Html>,
+) {
     let mut pos = line_start;
 
+    // Highlights don't overlap so their ends are sorted as well as their
+    // starts. Skip those that end before this line without scanning them all,
+    // which would be quadratic in the size of a command.
+    let first = highlights.partition_point(|h| h.end <= line_start);
+
     // Find highlights that overlap with this line
-    for highlight in highlights {
-        // Skip highlights that end before this line
-        if highlight.end <= line_start {
-            continue;
-        }
+    for highlight in &highlights[first..] {
         // Stop processing highlights that start after this line
         if highlight.start >= line_end {
             break;
@@ -601,7 +2047,7 @@ fn render_highlighted_line(
         // Add any unhighlighted text before this highlight
         if pos < hl_start {
             let text = &line[(pos - line_start)..(hl_start - line_start)];
-            result.push_str(&html_escape(text));
+            result.push_text(text);
         }
 
         // Add the highlighted text
@@ -612,12 +2058,28 @@ fn render_highlighted_line(
             HighlightKind::Num => "number",
             HighlightKind::Lit => "lit",
             HighlightKind::Comment => "comment",
+            HighlightKind::ThmRef(_) => "thm-ref",
+            HighlightKind::Fragment => "frag",
         };
 
-        result.push_str(&format!(r#"<span class="{}">"#, class));
         let text = &line[(hl_start - line_start)..(hl_end - line_start)];
-        result.push_str(&html_escape(text));
-        result.push_str("</span>");
+        let href = match highlight.kind {
+            HighlightKind::ThmRef(name) => thm_href(name),
+            _ => None,
+        };
+        *result += match href {
+            Some(href) => html!(
+                r#"<a class="{}" href="{}">{}</a>"#,
+                Html::raw(class),
+                href,
+                Html::text(text)
+            ),
+            None => html!(
+                r#"<span class="{}">{}</span>"#,
+                Html::raw(class),
+                Html::text(text)
+            ),
+        };
 
         pos = hl_end;
     }
@@ -625,8 +2087,693 @@ fn render_highlighted_line(
     // Add any remaining unhighlighted text
     if pos < line_end {
         let text = &line[(pos - line_start)..];
-        result.push_str(&html_escape(text));
+        result.push_text(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Arenas,
+        driver::Driver,
+        util::{diff::assert_text_eq, test_project::TestProject},
+    };
+    use std::time::{Duration, Instant};
+
+    /// The rendered chapters of a project with only prose in `main`.
+    fn render_prose(main: &str) -> Vec<String> {
+        let project = TestProject::new(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+        let chapters = doc.unwrap().chapter_contents;
+        chapters
+            .iter()
+            .map(|chapter| chapter.as_str().to_string())
+            .collect()
+    }
+
+    /// Render a project with only prose in `main`, returning the rendered
+    /// book, if any, and the title and text of each error.
+    fn render_prose_errors(main: &str) -> (Option<DocState>, Vec<(String, String)>) {
+        let project = TestProject::new(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+        let errors = (ctx.diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| {
+                let span = diag.parts().flat_map(|part| part.spans()).next().unwrap();
+                let text = ctx.sources.get_text(span.span().source());
+                (
+                    diag.title().to_string(),
+                    text[span.span().bytes()].to_string(),
+                )
+            })
+            .collect();
+        (doc, errors)
+    }
+
+    /// Check that every `ol`, `li` and `section` tag in `html` is closed, in
+    /// the order they were opened.
+    fn assert_balanced(html: &str) {
+        let mut open = Vec::new();
+        for (at, _) in html.match_indices('<') {
+            let tag = &html[at + 1..];
+            let tag = &tag[..tag.find(['>', ' ']).unwrap()];
+            match tag.strip_prefix('/') {
+                Some(closed) if ["ol", "li", "section"].contains(&closed) => {
+                    assert_eq!(open.pop(), Some(closed), "unbalanced at {at} in {html}");
+                }
+                None if ["ol", "li", "section"].contains(&tag) => open.push(tag),
+                _ => {}
+            }
+        }
+        assert_eq!(open, Vec::<&str>::new(), "unclosed tags in {html}");
+    }
+
+    /// A chapter of `paragraphs` paragraphs of prose, with a section every
+    /// hundred paragraphs.
+    fn large_prose(paragraphs: usize) -> String {
+        let mut text = "# Generated\n\n".to_string();
+        for i in 0..paragraphs {
+            if i % 100 == 0 {
+                text += &format!("## Section {i}\n\n");
+            }
+            text += "Some *emphasised* and **strong** prose with `code`, \
+                a [link](https://example.com) & \"quotes\"\n\
+                which carries on over a second line and a third one to make a\n\
+                paragraph about as long as one in a real book.\n\n";
+        }
+        text
+    }
+
+    #[test]
+    fn prose_renders_as_before() {
+        let chapters = render_prose(
+            "# Prose\n\n\
+             Some *emphasis*, **strong** and `a < b` with fish & chips 'n' \"quotes\".\n\
+             A [link](https://example.com?a=1&b=2) on the next line.\n\n\
+             ## Lists\n\n\
+             - one\n- two\n\n\
+             ### Aside\n\n\
+             > 1. first\n\
+             > 2. second\n\n\
+             ```\nplain <code>\n```\n",
+        );
+        let expected = "<h1 id=\"chapter-header\">\n    Prose\n    \
+            <div class=\"chapter-num\">1</div>\n</h1>\n\n\
+            <p>Some <em>emphasis</em>, <strong>strong</strong> and \
+            <code>a &lt; b</code> with fish &amp; chips &#39;n&#39; &quot;quotes&quot;.\n\
+            A <a href=\"https://example.com?a=1&amp;b=2\">link</a> on the next line.</p>\n\
+            <section id=\"section-1\">\n<h2 id=\"section-1\">\n  \
+            <a href=\"#section-1\" class=\"header-link\">Lists</a>\n  \
+            <span class=\"num\">1.1</span>\n</h2>\n\n\
+            <ul><li>one</li>\n<li>two</li>\n</ul>\n\
+            <h3>Aside</h3>\
+            <blockquote><ol><li>first</li>\n<li>second</li>\n</ol>\n</blockquote>\n\
+            <pre><code>plain &lt;code&gt;\n</code></pre>\n\
+            </section>\n";
+        assert_eq!(chapters.len(), 1);
+        assert_text_eq(expected, &chapters[0]);
+    }
+
+    #[test]
+    fn prose_before_the_first_chapter_is_reported_once() {
+        let (doc, errors) = render_prose_errors(
+            "-- a comment\n\nSome intro.\n\nMore intro.\n\n# Chapter\n\nBody.\n",
+        );
+        assert!(doc.is_none());
+        assert_eq!(
+            errors,
+            [(
+                "content must be inside a chapter".to_string(),
+                "Some intro.".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn a_section_before_the_first_chapter_is_reported_once() {
+        let (doc, errors) = render_prose_errors("## Section\n\nText.\n\n# Chapter\n");
+        assert!(doc.is_none());
+        assert_eq!(
+            errors,
+            [(
+                "content must be inside a chapter".to_string(),
+                "## Section".to_string()
+            )]
+        );
     }
 
-    result
+    #[test]
+    fn a_book_without_prose_is_skipped() {
+        let (doc, errors) = render_prose_errors("-- only a comment\n");
+        assert!(doc.is_none());
+        assert_eq!(errors, []);
+    }
+
+    #[test]
+    fn chapters_without_sections_have_no_section_list() {
+        let (doc, errors) =
+            render_prose_errors("# One\n\nText.\n\n# Two\n\n## Part\n\nText.\n\n# Three\n");
+        assert_eq!(errors, []);
+        let doc = doc.unwrap();
+        let sidebar = doc
+            .outline
+            .render_sidebar(&doc.base_path, BadgeStyle::Icons);
+        let sidebar = sidebar.as_str();
+        assert_eq!(sidebar.matches("<ol class=\"section-list\">").count(), 1);
+        assert_balanced(sidebar);
+        for chapter in &doc.chapter_contents {
+            assert_balanced(chapter.as_str());
+        }
+    }
+
+    #[test]
+    fn tables_render_with_alignment() {
+        let chapters = render_prose(
+            "# Tables\n\n\
+             | Name | Left | Centre | Right |\n\
+             | --- | :--- | :---: | ---: |\n\
+             | *a* \\| b | `x` | 1 | & |\n",
+        );
+        let expected = "<h1 id=\"chapter-header\">\n    Tables\n    \
+            <div class=\"chapter-num\">1</div>\n</h1>\n\n\
+            <table>\n<thead>\n\
+            <tr><th>Name</th><th class=\"align-left\">Left</th>\
+            <th class=\"align-center\">Centre</th><th class=\"align-right\">Right</th></tr>\n\
+            </thead>\n<tbody>\n\
+            <tr><td><em>a</em> | b</td><td class=\"align-left\"><code>x</code></td>\
+            <td class=\"align-center\">1</td><td class=\"align-right\">&amp;</td></tr>\n\
+            </tbody>\n</table>\n";
+        assert_text_eq(expected, &chapters[0]);
+    }
+
+    #[test]
+    fn ragged_table_rows_are_padded_with_a_warning() {
+        let project = TestProject::new(
+            "# Tables\n\n\
+             | Formula | Meaning |\n\
+             | --- | --- |\n\
+             | $|x|$ |\n",
+        );
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+        let chapter = doc.unwrap().chapter_contents[0].as_str().to_string();
+
+        // The note about the missing `[book]` section has no span.
+        let warnings: Vec<_> = (ctx.diags.iter())
+            .filter_map(|diag| {
+                let span = diag.parts().flat_map(|part| part.spans()).next()?;
+                let text = ctx.sources.get_text(span.span().source());
+                Some((
+                    diag.title(),
+                    text[span.span().bytes()].to_string(),
+                    diag.is_error(),
+                ))
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            [(
+                "table row has 1 cell but the header has 2",
+                "| $|x|$ |".to_string(),
+                false
+            )]
+        );
+
+        // The `|`s in the math don't split the cell, and the row is padded.
+        let row = chapter.lines().find(|line| line.contains("katex")).unwrap();
+        assert!(row.starts_with("<tr><td><span class=\"katex\">"), "{row}");
+        assert!(row.contains("<annotation encoding=\"application/x-tex\">|x|</annotation>"));
+        assert!(row.ends_with("</td><td></td></tr>"), "{row}");
+    }
+
+    /// Every character HTML treats specially, in each place prose puts text.
+    const SPECIAL: &str = "# A < b & \"c\" 'd'\n\n\
+        Prose < & \"q\" 'a' with **bold <&>** and `code <&>\"'` \
+        and [link <&>](https://e.com?a=1&b=<2>).\n\n\
+        ## S <&> \"'\n\n\
+        | H <&> | \"'\" |\n| --- | --- |\n| **<&>** | `<&>` |\n\n\
+        ```\nblock <&> \"'\n```\n";
+
+    #[test]
+    fn special_characters_are_escaped_once() {
+        let project = TestProject::new(SPECIAL);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+        let doc = doc.unwrap();
+
+        let expected = "<h1 id=\"chapter-header\">\n    \
+            A &lt; b &amp; &quot;c&quot; &#39;d&#39;\n    \
+            <div class=\"chapter-num\">1</div>\n</h1>\n\n\
+            <p>Prose &lt; &amp; &quot;q&quot; &#39;a&#39; with <strong>bold &lt;&amp;&gt;</strong> \
+            and <code>code &lt;&amp;&gt;&quot;&#39;</code> \
+            and <a href=\"https://e.com?a=1&amp;b=&lt;2&gt;\">link &lt;&amp;&gt;</a>.</p>\n\
+            <section id=\"section-1\">\n<h2 id=\"section-1\">\n  \
+            <a href=\"#section-1\" class=\"header-link\">S &lt;&amp;&gt; &quot;&#39;</a>\n  \
+            <span class=\"num\">1.1</span>\n</h2>\n\n\
+            <table>\n<thead>\n\
+            <tr><th>H &lt;&amp;&gt;</th><th>&quot;&#39;&quot;</th></tr>\n\
+            </thead>\n<tbody>\n\
+            <tr><td><strong>&lt;&amp;&gt;</strong></td><td><code>&lt;&amp;&gt;</code></td></tr>\n\
+            </tbody>\n</table>\n\
+            <pre><code>block &lt;&amp;&gt; &quot;&#39;\n</code></pre>\n\
+            </section>\n";
+        assert_text_eq(expected, doc.chapter_contents[0].as_str());
+
+        let sidebar = doc
+            .outline
+            .render_sidebar(&doc.base_path, BadgeStyle::Icons);
+        let expected = "<ol class=\"chapter-list\">\n<li>\n\
+            <a href=\"/chapter-1/\" class=\"chapter\" data-chapter=\"1\">\
+            <span class=\"num\">1</span> A &lt; b &amp; &quot;c&quot; &#39;d&#39;</a>\n\
+            <ol class=\"section-list\">\n\
+            <li class=\"section\"><a href=\"/chapter-1/#section-1\" data-chapter=\"1\" data-section=\"1\">\
+            <span class=\"num\">1.1</span> S &lt;&amp;&gt; &quot;&#39;</a></li>\n\
+            </ol>\n</li>\n</ol>\n";
+        assert_text_eq(expected, sidebar.as_str());
+    }
+
+    /// The title and text of each prose layout warning from rendering `main`
+    /// with `watson.toml` set to `config`.
+    fn layout_warnings(main: &str, config: &str) -> Vec<(String, String)> {
+        let project = TestProject::with_logic(&format!("# Layout\n\nmodule logic\n\n{main}"));
+        project.write("watson.toml", config);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+        assert!(doc.is_some());
+
+        (ctx.diags.iter())
+            .filter(|diag| !diag.is_error())
+            .filter_map(|diag| {
+                let span = diag.parts().flat_map(|part| part.spans()).next()?;
+                let text = ctx.sources.get_text(span.span().source());
+                let text = text[span.span().bytes()].to_string();
+                Some((diag.title().to_string(), text))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn a_command_interrupting_a_paragraph_is_reported() {
+        let main = "Some prose.\naxiom a :\n    |- ⊥ → ⊥\nend\n";
+        assert_eq!(
+            layout_warnings(main, ""),
+            [(
+                "command interrupts a paragraph".to_string(),
+                "axiom a :".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn a_heading_right_after_a_command_is_reported() {
+        let main = "axiom a :\n    |- ⊥ → ⊥\nend\n## Next\n\nMore prose.\n";
+        assert_eq!(
+            layout_warnings(main, ""),
+            [(
+                "heading immediately follows a command".to_string(),
+                "## Next".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn blank_lines_around_commands_are_fine() {
+        let main = "Some prose.\n\naxiom a :\n    |- ⊥ → ⊥\nend\n\n## Next\n\nMore prose.\n";
+        assert_eq!(layout_warnings(main, ""), []);
+    }
+
+    #[test]
+    fn layout_warnings_can_be_turned_off() {
+        let main = "Some prose.\naxiom a :\n    |- ⊥ → ⊥\nend\n## Next\n";
+        let config = "[book]\nprose_layout_warnings = false\n";
+        assert_eq!(layout_warnings(main, config), []);
+    }
+
+    #[test]
+    fn the_outline_describes_nested_chapters_and_sections() {
+        let project = TestProject::with_logic(
+            "# Intro\n\nmodule logic\n\n\
+             # Orders\n\n## Basics\n\n\
+             axiom one :\n    |- ⊥ → ⊥\nend\n\n\
+             theorem good :\n    |- ⊥ → ⊥\nproof\n    by one\nqed\n\n\
+             ## More\n\n\
+             theorem bad :\n    |- ⊥\nproof\n    by one\nqed\n\n\
+             # Empty\n\nText.\n",
+        );
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let (mut ctx, parse_report, proof_report) = checked.into_parts();
+        let theorems = book_theorems(&parse_report, Some(&proof_report), &ctx);
+        let doc = render_book(&mut ctx, &parse_report.entries, &theorems, "/").unwrap();
+
+        let outline = serde_json::to_value(&doc.outline).unwrap();
+        let chapters = outline["chapters"].as_array().unwrap();
+        let titles: Vec<_> = chapters.iter().map(|chapter| &chapter["title"]).collect();
+        assert_eq!(titles, ["Intro", "Propositional logic", "Orders", "Empty"]);
+
+        let counts = |statements, proved, failed| {
+            serde_json::json!({
+                "statements": statements,
+                "proved": proved,
+                "incomplete": 0,
+                "failed": failed,
+            })
+        };
+        let expected = serde_json::json!([
+            {
+                "number": 3,
+                "title": "Orders",
+                "slug": "chapter-3",
+                "source": { "file": "main", "line": 5 },
+                "counts": counts(3, 1, 1),
+                "sections": [
+                    {
+                        "number": 1,
+                        "title": "Basics",
+                        "slug": "section-1",
+                        "source": { "file": "main", "line": 7 },
+                        "counts": counts(2, 1, 0),
+                    },
+                    {
+                        "number": 2,
+                        "title": "More",
+                        "slug": "section-2",
+                        "source": { "file": "main", "line": 19 },
+                        "counts": counts(1, 0, 1),
+                    },
+                ],
+            },
+            {
+                "number": 4,
+                "title": "Empty",
+                "slug": "chapter-4",
+                "source": { "file": "main", "line": 27 },
+                "counts": counts(0, 0, 0),
+                "sections": [],
+            },
+        ]);
+        assert_eq!(chapters[2..], expected.as_array().unwrap()[..]);
+    }
+
+    #[test]
+    fn large_prose_renders_every_paragraph() {
+        let chapters = render_prose(&large_prose(1000));
+        assert_eq!(chapters[0].matches("<p>").count(), 1000);
+        assert_eq!(chapters[0].matches("<h2").count(), 10);
+    }
+
+    /// Render generated prose of two sizes. The time should grow about as
+    /// fast as the prose, not quadratically. Run with
+    /// `cargo test --release bench_large_prose -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_large_prose() {
+        let time = |paragraphs: usize| {
+            let text = large_prose(paragraphs);
+            let project = TestProject::new(&text);
+            let arenas = Arenas::new();
+            let parsed = Driver::for_project(project.config(), &arenas).parse();
+            let (mut ctx, parse_report) = parsed.into_parts();
+
+            let start = Instant::now();
+            let doc = render_book(&mut ctx, &parse_report.entries, &FxHashMap::default(), "/");
+            let elapsed = start.elapsed();
+            assert!(doc.is_some());
+            println!(
+                "{paragraphs} paragraphs ({} KiB): {elapsed:?}",
+                text.len() / 1024
+            );
+            elapsed
+        };
+        let small = time(5_000);
+        let large = time(20_000);
+        assert!(large < small * 8 + Duration::from_millis(100));
+    }
+
+    /// An axiom, a proved theorem and one left as a todo by `later`.
+    const STATUSES: &str = "# Intro\n\nmodule logic\n\n# Statuses\n\n\
+        tactic later\n    tactic ::= @kw\"later\"\nend\n\n\
+        axiom excluded.middle [p : sentence] :\n    |- p ∨ ¬p\nend\n\n\
+        theorem mp.again [p q : sentence] : (p → q) (p)\n    |- q\nproof\n    by mp [p] [q]\nqed\n\n\
+        theorem refl [p : sentence] :\n    |- p → p\nproof\n    later\nqed\n";
+
+    /// The badge of each statement in the chapter of `STATUSES`, and the
+    /// sidebar, with the proofs checked or not.
+    fn badges(checked: bool) -> (Vec<String>, String) {
+        let project = TestProject::with_logic(STATUSES);
+        let handler = include_str!("../cli/new_templates/main.luau").replace(
+            "M.tactics = {} :: TacticHandlers",
+            "M.tactics = {\n\
+             \x20   later = function(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20       return proofState:applyTodo(proofState.goal)\n\
+             \x20   end,\n\
+             } :: TacticHandlers",
+        );
+        project.write("script/main.luau", &handler);
+        project.write("watson.toml", "[book]\nbadge_style = \"icons\"\n");
+
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report, proof_report) = if checked {
+            let (ctx, parse_report, proof_report) = parsed.check().into_parts();
+            (ctx, parse_report, Some(proof_report))
+        } else {
+            let (ctx, parse_report) = parsed.into_parts();
+            (ctx, parse_report, None)
+        };
+        assert!(!ctx.diags.has_errors());
+
+        let theorems = book_theorems(&parse_report, proof_report.as_ref(), &ctx);
+        let doc = render_book(&mut ctx, &parse_report.entries, &theorems, "/").unwrap();
+        let badges = (doc.chapter_contents[2]
+            .as_str()
+            .split(r#"class="badge "#)
+            .skip(1))
+        .map(|rest| rest[..rest.find('"').unwrap()].to_string())
+        .collect();
+        let sidebar = doc
+            .outline
+            .render_sidebar(&doc.base_path, BadgeStyle::Icons);
+        (badges, sidebar.as_str().to_string())
+    }
+
+    #[test]
+    fn statements_show_their_status() {
+        let (badges, sidebar) = badges(true);
+        assert_eq!(badges, ["axiom", "proved", "incomplete"]);
+        let chapter = sidebar
+            .lines()
+            .find(|line| line.contains("Statuses"))
+            .unwrap();
+        assert!(
+            chapter.ends_with(
+                r#"Statuses <span class="count proved" title="1 proved">1 ✓</span> <span class="count incomplete" title="1 incomplete">1 ⚠</span></a>"#
+            ),
+            "{chapter}"
+        );
+    }
+
+    #[test]
+    fn statements_are_unchecked_without_checking() {
+        let (badges, sidebar) = badges(false);
+        assert_eq!(badges, ["unchecked", "unchecked", "unchecked"]);
+        assert!(!sidebar.contains("class=\"count"), "{sidebar}");
+    }
+
+    /// The statement labels in the chapter after `module logic` of a book
+    /// with one statement of each kind, with corollaries numbered separately
+    /// or not.
+    fn statement_labels(separate_corollaries: bool) -> Vec<String> {
+        let project = TestProject::with_logic(
+            "# Intro\n\nmodule logic\n\n# Kinds\n\n\
+             axiom one :\n    |- ⊥ → ⊥\nend\n\n\
+             postulate two :\n    |- ¬⊥\nend\n\n\
+             theorem t :\n    |- ⊥ → ⊥\nproof\n    by one\nqed\n\n\
+             lemma l :\n    |- ⊥ → ⊥\nproof\n    by t\nqed\n\n\
+             proposition p :\n    |- ⊥ → ⊥\nproof\n    by l\nqed\n\n\
+             corollary c :\n    |- ¬⊥\nproof\n    by two\nqed\n\n\
+             theorem t2 :\n    |- ⊥ → ⊥\nproof\n    by p\nqed\n",
+        );
+        project.write(
+            "watson.toml",
+            &format!("[book]\nseparate_corollary_numbering = {separate_corollaries}\n"),
+        );
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let (mut ctx, parse_report, proof_report) = checked.into_parts();
+        assert!(!ctx.diags.has_errors());
+        assert!(
+            proof_report
+                .statuses
+                .iter()
+                .all(|(_, status)| status.correct())
+        );
+
+        let theorems = book_theorems(&parse_report, Some(&proof_report), &ctx);
+        let doc = render_book(&mut ctx, &parse_report.entries, &theorems, "/").unwrap();
+        (doc.chapter_contents[2].as_str())
+            .split(r#"<span class="statement-label">"#)
+            .skip(1)
+            .map(|rest| rest[..rest.find('<').unwrap()].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn statements_are_labelled_by_kind() {
+        assert_eq!(
+            statement_labels(false),
+            [
+                "Axiom 3.1",
+                "Axiom 3.2",
+                "Theorem 3.1",
+                "Lemma 3.2",
+                "Proposition 3.3",
+                "Corollary 3.4",
+                "Theorem 3.5",
+            ]
+        );
+        assert_eq!(
+            statement_labels(true),
+            [
+                "Axiom 3.1",
+                "Axiom 3.2",
+                "Theorem 3.1",
+                "Lemma 3.2",
+                "Proposition 3.3",
+                "Corollary 3.1",
+                "Theorem 3.4",
+            ]
+        );
+    }
+
+    #[test]
+    fn proofs_link_theorem_references() {
+        let project = TestProject::with_logic(
+            "# Intro\n\nmodule logic\n\n# Modus ponens\n\n\
+             theorem mp2 [p q : sentence] : (p → q) (p)\n    |- q\n\
+             proof\n    by mp [p] [q]\nqed\n",
+        );
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        assert!(!ctx.diags.has_errors());
+        assert!(build_book(&mut ctx, parse_report, None, false, "/").is_some());
+
+        let page = project.read("build/book/chapter-3/index.html");
+        let start = page.find(r#"<pre title="main.wats:7">"#).unwrap();
+        let end = start + page[start..].find("</pre>").unwrap() + "</pre>".len();
+        let expected = r#"<pre title="main.wats:7"><code class="code-block"><span class="line">7</span><span><span class="kw">theorem</span> <span class="name">mp2</span> [p q : sentence] : (p → q) (p)</span>
+<span class="line">8</span><span>    <span class="lit">|-</span> q</span>
+<span class="line">9</span><span><span class="kw">proof</span></span>
+<span class="line">10</span><span>    <span class="kw">by</span> <a class="thm-ref" href="/chapter-2/#thm-mp">mp</a> [<span class="frag">p</span>] [<span class="frag">q</span>]</span>
+<span class="line">11</span><span><span class="kw">qed</span></span>
+</code></pre>"#;
+        assert_text_eq(expected, &page[start..end]);
+    }
+
+    #[test]
+    fn pages_carry_the_book_metadata() {
+        let project = TestProject::new("# Intro\n\nHello.\n");
+        project.write(
+            "watson.toml",
+            "[book]\n\
+             title = \"Logic\"\n\
+             authors = [\"Ada\", \"Kurt & co\"]\n\
+             license = \"CC-BY-4.0\"\n\
+             repository_url = \"https://example.com/logic\"\n\
+             description = \"A book about \\\"logic\\\"\"\n\
+             language = \"pt-BR\"\n",
+        );
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let (mut ctx, parse_report) = parsed.into_parts();
+        assert!(build_book(&mut ctx, parse_report, None, false, "/").is_some());
+        assert_eq!(ctx.diags.count(), 0);
+
+        let page = project.read("build/book/chapter-1/index.html");
+        let head = &page[..page.find("</head>").unwrap()];
+        let head = &head[..head.find("\n\n").unwrap()];
+        let expected = r#"<!DOCTYPE html>
+<html lang="pt-BR">
+  <head>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+    <title>Intro - Logic</title>
+    <meta name="author" content="Ada, Kurt &amp; co" />
+    <meta name="description" content="A book about &quot;logic&quot;" />
+    <meta property="og:description" content="A book about &quot;logic&quot;" />
+    <meta property="og:title" content="Intro - Logic" />
+    <meta property="og:type" content="book" />
+    <meta property="og:site_name" content="Logic" />
+    <link rel="stylesheet" href="/styles.css" />"#;
+        assert_text_eq(expected, head);
+        let footer = &page[page.find("<footer").unwrap()..];
+        let footer = &footer[..footer.find("</footer>").unwrap() + "</footer>".len()];
+        assert_eq!(
+            footer,
+            r#"<footer class="book-footer">© Ada, Kurt &amp; co — CC-BY-4.0 — <a href="https://example.com/logic">source</a></footer>"#
+        );
+    }
+
+    /// Check the metadata of a project whose `[book]` section is `book`
+    /// twice, as watch mode would, returning the titles of the diagnostics
+    /// from each time.
+    fn check_metadata_twice(book: &str) -> [Vec<String>; 2] {
+        let project = TestProject::new("# Intro\n");
+        project.write("watson.toml", &format!("[book]\n{book}"));
+        let noted = AtomicBool::new(false);
+        [(); 2].map(|_| {
+            let arenas = Arenas::new();
+            let parsed = Driver::for_project(project.config(), &arenas).parse();
+            let (mut ctx, _) = parsed.into_parts();
+            check_metadata(&mut ctx, &noted);
+            (ctx.diags.iter())
+                .map(|diag| diag.title().to_string())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn missing_metadata_is_noted_once() {
+        let [first, second] = check_metadata_twice("title = \"Logic\"\nlicense = \"MIT\"\n");
+        assert_eq!(
+            first,
+            [
+                "the `[book]` section of watson.toml doesn't set `authors`, `description`, `language`"
+            ]
+        );
+        assert_eq!(second, Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_metadata_is_warned_about_every_time() {
+        let book = "title = \"Logic\"\n\
+            authors = [\"Ada\"]\n\
+            license = \"MIT\"\n\
+            description = \"Logic\"\n\
+            language = \"english please\"\n\
+            repository_url = \"example.com/logic\"\n";
+        let warnings = [
+            "invalid `language` in the `[book]` section of watson.toml: `english please`",
+            "invalid `repository_url` in the `[book]` section of watson.toml: `example.com/logic`",
+        ];
+        assert_eq!(check_metadata_twice(book), [warnings, warnings]);
+    }
 }
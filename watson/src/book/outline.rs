@@ -0,0 +1,244 @@
+use crate::{
+    book::{
+        Badge, BadgeStyle, ChapterCounts,
+        html::{Html, html},
+    },
+    context::Ctx,
+    parse::Location,
+};
+use serde::Serialize;
+use ustr::Ustr;
+
+/// The chapters and sections of the book, for tools which need its structure
+/// without scraping the HTML. It is found before any chapter is rendered,
+/// so prose can link to theorems in later chapters, and the headings, titles
+/// and counts are filled in as the chapters are rendered. Written to
+/// `outline.json` in the root of the book and used to build the sidebar.
+///
+/// ```json
+/// {
+///   "chapters": [
+///     {
+///       "number": 1,
+///       "title": "Orders",
+///       "slug": "chapter-1",
+///       "source": { "file": "orders", "line": 1 },
+///       "counts": { "statements": 4, "proved": 3, "incomplete": 1, "failed": 0 },
+///       "sections": [
+///         {
+///           "number": 1,
+///           "title": "Total orders",
+///           "slug": "section-1",
+///           "source": { "file": "orders", "line": 12 },
+///           "counts": { "statements": 2, "proved": 2, "incomplete": 0, "failed": 0 }
+///         }
+///       ]
+///     }
+///   ]
+/// }
+/// ```
+///
+/// Titles are plain text. A chapter is at `chapter-N/` under the book and a
+/// section at `#section-M` in its chapter's page. The counts of a chapter
+/// include its sections, and statements in a draft book aren't counted since
+/// they haven't been checked.
+#[derive(Debug, Default, Serialize)]
+pub struct Outline {
+    chapters: Vec<OutlineChapter>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutlineChapter {
+    number: usize,
+    title: String,
+    slug: String,
+    source: OutlineSource,
+    counts: ChapterCounts,
+    sections: Vec<OutlineSection>,
+    /// The title as it is shown, with any formatting and math.
+    #[serde(skip)]
+    heading: Html,
+    /// The theorems shown in the chapter.
+    #[serde(skip)]
+    theorems: Vec<Ustr>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OutlineSection {
+    number: usize,
+    title: String,
+    slug: String,
+    source: OutlineSource,
+    counts: ChapterCounts,
+    #[serde(skip)]
+    heading: Html,
+}
+
+/// Where a heading is in the sources.
+#[derive(Debug, Clone, Serialize)]
+pub struct OutlineSource {
+    file: String,
+    line: usize,
+}
+
+impl OutlineSource {
+    /// Where each of `locs` is, in the same order.
+    pub fn for_locations(locs: &[Location], ctx: &Ctx) -> Vec<Self> {
+        let line_cols = ctx.sources.line_cols(locs);
+        locs.iter()
+            .zip(line_cols)
+            .map(|(loc, (line, _))| Self {
+                file: loc.source().name().to_string(),
+                line: line as usize,
+            })
+            .collect()
+    }
+}
+
+impl Outline {
+    pub fn chapters(&self) -> &[OutlineChapter] {
+        &self.chapters
+    }
+
+    pub fn chapter_titles(&self) -> impl Iterator<Item = &str> {
+        self.chapters.iter().map(|chapter| chapter.title.as_str())
+    }
+
+    /// Add a chapter and return its number.
+    pub fn add_chapter(&mut self, source: OutlineSource) -> usize {
+        let number = self.chapters.len() + 1;
+        self.chapters.push(OutlineChapter {
+            number,
+            title: String::new(),
+            slug: format!("chapter-{number}"),
+            source,
+            counts: ChapterCounts::default(),
+            sections: Vec::new(),
+            heading: Html::default(),
+            theorems: Vec::new(),
+        });
+        number
+    }
+
+    /// Add a section to the last chapter and return its number, or `None` if
+    /// there is no chapter yet.
+    pub fn add_section(&mut self, source: OutlineSource) -> Option<usize> {
+        let chapter = self.chapters.last_mut()?;
+        let number = chapter.sections.len() + 1;
+        chapter.sections.push(OutlineSection {
+            number,
+            title: String::new(),
+            slug: format!("section-{number}"),
+            source,
+            counts: ChapterCounts::default(),
+            heading: Html::default(),
+        });
+        Some(number)
+    }
+
+    /// Add a theorem to the last chapter. Theorems before the first chapter
+    /// aren't shown.
+    pub fn add_theorem(&mut self, name: Ustr) {
+        if let Some(chapter) = self.chapters.last_mut() {
+            chapter.theorems.push(name);
+        }
+    }
+
+    /// Set the heading of a chapter, or of one of its sections, once it has
+    /// been rendered. `title` is the plain text of it.
+    pub fn set_heading(
+        &mut self,
+        chapter: usize,
+        section: Option<usize>,
+        heading: Html,
+        title: String,
+    ) {
+        let chapter = &mut self.chapters[chapter - 1];
+        match section {
+            Some(section) => {
+                let section = &mut chapter.sections[section - 1];
+                section.heading = heading;
+                section.title = title;
+            }
+            None => {
+                chapter.heading = heading;
+                chapter.title = title;
+            }
+        }
+    }
+
+    /// Count a statement in a chapter and in the section it is in, if any.
+    pub fn count(&mut self, chapter: usize, section: Option<usize>, badge: Badge) {
+        let chapter = &mut self.chapters[chapter - 1];
+        chapter.counts.add(badge);
+        if let Some(section) = section {
+            chapter.sections[section - 1].counts.add(badge);
+        }
+    }
+
+    pub fn render_sidebar(&self, base_path: &Html, style: BadgeStyle) -> Html {
+        let mut out = html!("<ol class=\"chapter-list\">\n");
+        for chapter in &self.chapters {
+            let num = chapter.number;
+            out += html!("<li>\n");
+            out += html!(
+                "<a href=\"{}chapter-{}/\" class=\"chapter\" data-chapter=\"{}\"><span class=\"num\">{}</span> {}{}</a>\n",
+                base_path,
+                num,
+                num,
+                num,
+                chapter.heading,
+                chapter.counts.render(style)
+            );
+
+            if !chapter.sections.is_empty() {
+                out += html!("<ol class=\"section-list\">\n");
+                for section in &chapter.sections {
+                    out += html!(
+                        "<li class=\"section\"><a href=\"{}chapter-{}/#section-{}\" data-chapter=\"{}\" data-section=\"{}\"><span class=\"num\">{}.{}</span> {}</a></li>\n",
+                        base_path,
+                        num,
+                        section.number,
+                        num,
+                        section.number,
+                        num,
+                        section.number,
+                        section.heading
+                    );
+                }
+                out += html!("</ol>\n");
+            }
+            out += html!("</li>\n");
+        }
+        out += html!("</ol>\n");
+        out
+    }
+}
+
+impl OutlineChapter {
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn sections(&self) -> &[OutlineSection] {
+        &self.sections
+    }
+
+    pub fn theorems(&self) -> &[Ustr] {
+        &self.theorems
+    }
+}
+
+impl OutlineSection {
+    pub fn number(&self) -> usize {
+        self.number
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
@@ -1,31 +1,24 @@
+use crate::book::html::{Html, html};
 use std::fs;
-use std::path::Path;
-use tiny_http::{Header, Response, Server};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tiny_http::{Header, Request, Response, Server};
+
+/// Where pages served by [`serve_live`] ask which build is the latest.
+const BUILD_URL: &str = "/__watson/build";
 
 pub fn serve(book_dir: &Path, port: u16) {
     let server = Server::http(("127.0.0.1", port)).expect("Failed to start server");
     let book_dir = book_dir.to_path_buf();
 
     for request in server.incoming_requests() {
-        let url_path = request.url().trim_start_matches('/');
-
-        // Build the file path
-        let mut file_path = book_dir.join(url_path);
-
-        // If the path is a directory, append index.html
-        if file_path.is_dir() {
-            file_path = file_path.join("index.html");
-        }
+        let file_path = book_file(&book_dir, request.url());
 
         // Try to read and serve the file
         match fs::read(&file_path) {
             Ok(content) => {
-                let content_type = get_content_type(&file_path);
-                let content_type_header =
-                    Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
-                        .expect("Failed to create header");
-
-                let mut response = Response::from_data(content).with_header(content_type_header);
+                let mut response =
+                    Response::from_data(content).with_header(content_type_header(&file_path));
 
                 // Add Last-Modified header for auto-reload support
                 if let Ok(metadata) = fs::metadata(&file_path)
@@ -56,6 +49,136 @@ pub fn serve(book_dir: &Path, port: u16) {
     }
 }
 
+/// The book served by `watson book --serve`, which is rebuilt while it is
+/// served. Shared between the server and the thread rebuilding the book.
+#[derive(Debug, Clone, Default)]
+pub struct LiveBook {
+    state: Arc<Mutex<LiveState>>,
+}
+
+#[derive(Debug, Default)]
+struct LiveState {
+    /// Counts the builds, so open pages can tell when to reload.
+    build: u64,
+    /// Shown in place of every page while the book can't be built.
+    errors: Option<Vec<String>>,
+}
+
+impl LiveBook {
+    /// Serve the book as it is now built.
+    pub fn rebuilt(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.build += 1;
+        state.errors = None;
+    }
+
+    /// Show why the book couldn't be built until it is built again.
+    pub fn failed(&self, errors: Vec<String>) {
+        let mut state = self.state.lock().unwrap();
+        state.build += 1;
+        state.errors = Some(errors);
+    }
+}
+
+/// Serve the book like [`serve`], but with a script in every page which
+/// reloads it when the book is rebuilt.
+pub fn serve_live(book_dir: &Path, port: u16, live: LiveBook) {
+    let server = Server::http(("127.0.0.1", port)).expect("Failed to start server");
+
+    for request in server.incoming_requests() {
+        let (build, errors) = {
+            let state = live.state.lock().unwrap();
+            (state.build, state.errors.clone())
+        };
+        if request.url() == BUILD_URL {
+            respond(request, Response::from_string(build.to_string()));
+            continue;
+        }
+
+        let file_path = book_file(book_dir, request.url());
+        let is_page = file_path.extension().is_some_and(|ext| ext == "html");
+        let script = live_reload_script(build);
+        let response = match (errors, fs::read(&file_path)) {
+            (Some(errors), _) if is_page => html_response(&error_page(&errors, &script), 200),
+            (_, Ok(content)) if is_page => {
+                // The script goes at the end of the body so the page shows
+                // before it runs.
+                let page = String::from_utf8_lossy(&content);
+                let page = match page.rfind("</body>") {
+                    Some(at) => format!("{}{script}{}", &page[..at], &page[at..]),
+                    None => format!("{page}{script}"),
+                };
+                html_response(&page, 200)
+            }
+            (_, Ok(content)) => {
+                Response::from_data(content).with_header(content_type_header(&file_path))
+            }
+            // The page may appear once the book is rebuilt.
+            (_, Err(_)) => html_response(&format!("404 Not Found{script}"), 404),
+        };
+        respond(request, response);
+    }
+}
+
+fn live_reload_script(build: u64) -> Html {
+    html!(
+        "<script>const watsonBuild = {};\n{}</script>",
+        Html::raw(&build.to_string()),
+        Html::raw(include_str!("templates/live_reload.js"))
+    )
+}
+
+fn error_page(errors: &[String], script: &Html) -> String {
+    let errors: Vec<_> = errors
+        .iter()
+        .map(|error| html!("<pre>{}</pre>", Html::text(error)))
+        .collect();
+    let page = html!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>The book couldn't be built</title></head>
+<body>
+<h1>The book couldn't be built</h1>
+{}
+{}
+</body>
+</html>
+"#,
+        Html::join(&errors, &Html::raw("\n")),
+        script
+    );
+    page.as_str().to_string()
+}
+
+/// The file a URL asks for, which is the `index.html` in it for a directory.
+fn book_file(book_dir: &Path, url: &str) -> PathBuf {
+    let file_path = book_dir.join(url.trim_start_matches('/'));
+    if file_path.is_dir() {
+        file_path.join("index.html")
+    } else {
+        file_path
+    }
+}
+
+fn html_response(page: &str, status: u16) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"text/html; charset=utf-8"[..])
+        .expect("Failed to create header");
+    Response::from_string(page)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn respond(request: Request, response: Response<std::io::Cursor<Vec<u8>>>) {
+    if let Err(e) = request.respond(response) {
+        eprintln!("Failed to send response: {}", e);
+    }
+}
+
+fn content_type_header(path: &Path) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], get_content_type(path).as_bytes())
+        .expect("Failed to create header")
+}
+
 fn get_content_type(path: &Path) -> String {
     match path.extension().and_then(|s| s.to_str()) {
         Some("html") => "text/html; charset=utf-8",
@@ -0,0 +1,204 @@
+use crate::{
+    book::html::{Html, html},
+    diagnostics::FragRenderings,
+    semant::{
+        presentation::PresFrag,
+        theorems::{StatementPart, Template, TheoremId},
+    },
+    strings,
+    util::ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_RESET},
+};
+use std::fmt::Write;
+
+/// How math is written in markdown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarkdownMath {
+    /// Always as inline code, so it shows exactly as written.
+    Code,
+    /// As `$...$` when it has symbols beyond ASCII, which render better as
+    /// math, and as inline code otherwise.
+    Dollars,
+}
+
+/// The statement of a theorem in the form the proof would be written: each
+/// template, hypothesis and the conclusion on their own line.
+pub fn statement_ansi(thm: TheoremId) -> String {
+    let mut out = format!(
+        "{ANSI_BOLD}{} {}{ANSI_RESET}",
+        thm.kind().keyword(),
+        thm.name()
+    );
+    for part in thm.parts() {
+        let line = match part {
+            StatementPart::Template(template) => {
+                let (binding, cat) = template_parts(template);
+                format!("[{binding} : {ANSI_GRAY}{cat}{ANSI_RESET}]")
+            }
+            StatementPart::Hypothesis(fact) => format!("({})", fact.pretty()),
+            StatementPart::Conclusion(frag) => {
+                format!("{} {}", *strings::TURNSTILE, frag.pretty())
+            }
+        };
+        write!(out, "\n    {line}").unwrap();
+    }
+    out
+}
+
+/// The statement of a theorem as markdown, for pasting into places like a pull
+/// request description.
+pub fn statement_markdown(thm: TheoremId, math: MarkdownMath) -> String {
+    let mut out = String::new();
+    writeln!(out, "**{}** {}", thm.kind().label(), code(&thm.name())).unwrap();
+    writeln!(out).unwrap();
+    for part in thm.parts() {
+        match part {
+            StatementPart::Template(template) => {
+                let (binding, cat) = template_parts(template);
+                writeln!(out, "- Template {}", code(&format!("{binding} : {cat}"))).unwrap();
+            }
+            StatementPart::Hypothesis(fact) => {
+                writeln!(out, "- Hypothesis {}", markdown_math(&fact.pretty(), math)).unwrap();
+            }
+            StatementPart::Conclusion(frag) => {
+                writeln!(out, "- Conclusion {}", markdown_math(&frag.pretty(), math)).unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// The statement of a theorem for the check report, laid out like
+/// [`statement_ansi`] without the heading.
+pub fn statement_html<'ctx>(thm: TheoremId<'ctx>, renderings: &FragRenderings<'ctx>) -> Html {
+    // Fragments the project's `renderFrag` hook rendered are shown that way.
+    let pretty = |frag: PresFrag<'ctx>| match renderings.get(&frag) {
+        Some(rendered) => rendered.clone(),
+        None => frag.pretty(),
+    };
+    let lines: Vec<_> = thm
+        .parts()
+        .map(|part| match part {
+            StatementPart::Template(template) => {
+                let (binding, cat) = template_parts(template);
+                html!(
+                    "[{} : <span class=\"cat\">{}</span>]",
+                    Html::text(&binding),
+                    Html::text(&cat)
+                )
+            }
+            StatementPart::Hypothesis(fact) => {
+                let conclusion = pretty(fact.conclusion());
+                let fact = match fact.assumption() {
+                    Some(assumption) => format!(
+                        "{} {} {} {conclusion}",
+                        *strings::ASSUME,
+                        pretty(assumption),
+                        *strings::TURNSTILE
+                    ),
+                    None => conclusion,
+                };
+                html!("({})", Html::text(&fact))
+            }
+            StatementPart::Conclusion(frag) => html!(
+                "{} {}",
+                Html::text(&strings::TURNSTILE),
+                Html::text(&pretty(frag))
+            ),
+        })
+        .collect();
+    Html::join(&lines, &Html::raw("\n"))
+}
+
+/// The binding of a template, with its default if it has one, and its
+/// category.
+pub fn template_parts(template: &Template) -> (String, String) {
+    let binding = match template.default() {
+        Some(default) => format!(
+            "{} {} {}",
+            template.binding().print(),
+            *strings::ASSIGN,
+            default.pretty()
+        ),
+        None => template.binding().print(),
+    };
+    (binding, template.cat().name().to_string())
+}
+
+fn markdown_math(text: &str, math: MarkdownMath) -> String {
+    if math == MarkdownMath::Dollars && !text.is_ascii() {
+        format!("${}$", text.replace('$', "\\$"))
+    } else {
+        code(text)
+    }
+}
+
+/// `text` as an inline code span, fenced with enough backticks that any in
+/// the text don't end it.
+fn code(text: &str) -> String {
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run + 1);
+    if longest_run == 0 {
+        format!("{fence}{text}{fence}")
+    } else {
+        format!("{fence} {text} {fence}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Arenas,
+        driver::Driver,
+        util::{diff::assert_text_eq, test_project::TestProject},
+    };
+    use ustr::Ustr;
+
+    /// Run `test` on `mp` from the propositional library, which has two
+    /// templates and two hypotheses.
+    fn with_mp(test: impl for<'ctx> FnOnce(TheoremId<'ctx>)) {
+        let project = TestProject::with_logic("module logic\n");
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let mp = parsed.ctx().arenas.theorem_stmts.get(Ustr::from("mp"));
+        test(mp.unwrap());
+    }
+
+    #[test]
+    fn ansi_statement() {
+        with_mp(|mp| {
+            let expected = format!(
+                "{ANSI_BOLD}axiom mp{ANSI_RESET}\n\
+                 \x20   [p : {ANSI_GRAY}sentence{ANSI_RESET}]\n\
+                 \x20   [q : {ANSI_GRAY}sentence{ANSI_RESET}]\n\
+                 \x20   (p → q)\n\
+                 \x20   (p)\n\
+                 \x20   |- q"
+            );
+            assert_text_eq(&expected, &statement_ansi(mp));
+        });
+    }
+
+    #[test]
+    fn markdown_statement() {
+        with_mp(|mp| {
+            let expected = "**Axiom** `mp`\n\
+                \n\
+                - Template `p : sentence`\n\
+                - Template `q : sentence`\n\
+                - Hypothesis `p → q`\n\
+                - Hypothesis `p`\n\
+                - Conclusion `q`\n";
+            assert_text_eq(expected, &statement_markdown(mp, MarkdownMath::Code));
+
+            let expected = "**Axiom** `mp`\n\
+                \n\
+                - Template `p : sentence`\n\
+                - Template `q : sentence`\n\
+                - Hypothesis $p → q$\n\
+                - Hypothesis `p`\n\
+                - Conclusion `q`\n";
+            assert_text_eq(expected, &statement_markdown(mp, MarkdownMath::Dollars));
+        });
+    }
+}
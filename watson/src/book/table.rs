@@ -0,0 +1,250 @@
+use std::ops::Range;
+
+/// A piece of prose, split so that pipe tables can be rendered separately
+/// from the markdown around them.
+pub enum ProseBlock {
+    Text(Range<usize>),
+    Table(Table),
+}
+
+/// A pipe table. Consecutive lines starting with `|` form a table if the
+/// second one is a delimiter row of dashes and colons.
+pub struct Table {
+    header: TableRow,
+    alignments: Vec<Alignment>,
+    /// How many cells the delimiter row has and where it is.
+    delimiter: (usize, Range<usize>),
+    rows: Vec<TableRow>,
+}
+
+pub struct TableRow {
+    cells: Vec<String>,
+    /// The byte range of the row in the prose.
+    span: Range<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alignment {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+impl Table {
+    /// How many columns the table has. Rows with fewer cells are padded
+    /// rather than rows with more cells being cut short.
+    pub fn width(&self) -> usize {
+        self.rows
+            .iter()
+            .map(|row| row.cells.len())
+            .chain([self.header.cells.len(), self.delimiter.0])
+            .max()
+            .unwrap_or(0)
+    }
+
+    pub fn header(&self) -> &TableRow {
+        &self.header
+    }
+
+    pub fn rows(&self) -> &[TableRow] {
+        &self.rows
+    }
+
+    pub fn alignment(&self, column: usize) -> Alignment {
+        self.alignments
+            .get(column)
+            .copied()
+            .unwrap_or(Alignment::None)
+    }
+
+    /// The rows, including the delimiter row, whose number of cells is
+    /// different from the header's, with how many cells they have.
+    pub fn ragged_rows(&self) -> Vec<(usize, Range<usize>)> {
+        let expected = self.header.cell_cnt();
+        let delimiter = [self.delimiter.clone()];
+        let rows = self
+            .rows
+            .iter()
+            .map(|row| (row.cells.len(), row.span.clone()));
+        delimiter
+            .into_iter()
+            .chain(rows)
+            .filter(|(cells, _)| *cells != expected)
+            .collect()
+    }
+}
+
+impl TableRow {
+    /// The text of a cell, which is empty for the cells a short row is
+    /// padded with.
+    pub fn cell(&self, column: usize) -> &str {
+        self.cells.get(column).map_or("", String::as_str)
+    }
+
+    pub fn cell_cnt(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Where the row starts in the prose.
+    pub fn start(&self) -> usize {
+        self.span.start
+    }
+}
+
+impl Alignment {
+    pub fn class(self) -> Option<&'static str> {
+        match self {
+            Alignment::None => None,
+            Alignment::Left => Some("align-left"),
+            Alignment::Center => Some("align-center"),
+            Alignment::Right => Some("align-right"),
+        }
+    }
+}
+
+/// Split prose into the tables it contains and the text between them.
+/// Tables inside fenced code blocks are left alone.
+pub fn split_tables(text: &str) -> Vec<ProseBlock> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in text.split_inclusive('\n') {
+        lines.push(offset..offset + line.trim_end().len());
+        offset += line.len();
+    }
+
+    let mut blocks = Vec::new();
+    let mut text_start = 0;
+    let mut in_code_block = false;
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &text[lines[i].clone()];
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+
+        let delimiter = lines
+            .get(i + 1)
+            .and_then(|next| parse_delimiter_row(&text[next.clone()]));
+        let (false, true, Some(alignments)) = (in_code_block, is_table_line(line), delimiter)
+        else {
+            i += 1;
+            continue;
+        };
+
+        let table_start = lines[i].start;
+        if text_start < table_start {
+            blocks.push(ProseBlock::Text(text_start..table_start));
+        }
+
+        let row = |range: &Range<usize>| TableRow {
+            cells: split_row(&text[range.clone()]),
+            span: range.clone(),
+        };
+        let header = row(&lines[i]);
+        let delimiter = (alignments.len(), lines[i + 1].clone());
+        i += 2;
+
+        let mut rows = Vec::new();
+        while i < lines.len() && is_table_line(&text[lines[i].clone()]) {
+            rows.push(row(&lines[i]));
+            i += 1;
+        }
+
+        text_start = lines[i - 1].end;
+        blocks.push(ProseBlock::Table(Table {
+            header,
+            alignments,
+            delimiter,
+            rows,
+        }));
+    }
+
+    if text_start < text.len() {
+        blocks.push(ProseBlock::Text(text_start..text.len()));
+    }
+    blocks
+}
+
+fn is_table_line(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// The alignment of each column if `line` is a delimiter row like
+/// `| :--- | :---: | ---: |`.
+fn parse_delimiter_row(line: &str) -> Option<Vec<Alignment>> {
+    if !is_table_line(line) {
+        return None;
+    }
+
+    split_row(line)
+        .iter()
+        .map(|cell| {
+            let left = cell.starts_with(':');
+            let right = cell.ends_with(':');
+            let dashes = cell.trim_start_matches(':').trim_end_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (left, right) {
+                (true, true) => Alignment::Center,
+                (true, false) => Alignment::Left,
+                (false, true) => Alignment::Right,
+                (false, false) => Alignment::None,
+            })
+        })
+        .collect()
+}
+
+/// Split a row into its cells. Math is found before splitting, so `|` inside
+/// `$...$` or `$$...$$` never ends a cell and is left for LaTeX. Elsewhere
+/// `\|` is a literal `|`.
+fn split_row(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+
+    let mut cells = Vec::new();
+    let mut cell = String::new();
+    let mut math: Option<&str> = None;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if math.is_none() && chars.peek() == Some(&'|') => {
+                cell.push(chars.next().unwrap());
+            }
+            '\\' => {
+                // Keep the escape, and whatever it escapes, for the inline
+                // markdown or LaTeX.
+                cell.push(c);
+                if let Some(escaped) = chars.next() {
+                    cell.push(escaped);
+                }
+            }
+            '$' => {
+                let delim = if chars.peek() == Some(&'$') {
+                    cell.push(chars.next().unwrap());
+                    "$$"
+                } else {
+                    "$"
+                };
+                cell.push(c);
+                math = match math {
+                    None => Some(delim),
+                    Some(open) if open == delim => None,
+                    Some(open) => Some(open),
+                };
+            }
+            '|' if math.is_none() => cells.push(std::mem::take(&mut cell)),
+            _ => cell.push(c),
+        }
+    }
+
+    // A row usually ends with `|`, which doesn't start another cell.
+    if !cell.trim().is_empty() || cells.is_empty() {
+        cells.push(cell);
+    }
+    cells
+        .into_iter()
+        .map(|cell| cell.trim().to_string())
+        .collect()
+}
@@ -0,0 +1,332 @@
+use crate::book::html::Html;
+use aho_corasick::AhoCorasick;
+use std::{
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+/// The built-in HTML templates pages are built from. They are checked when
+/// they are first loaded, so a misspelt placeholder fails the first build
+/// rather than showing up in a page.
+pub static TEMPLATES: LazyLock<Templates> =
+    LazyLock::new(|| Templates::load(None).unwrap_or_else(|err| panic!("{err}")));
+
+/// The files of the book's look which a project can override, with the
+/// built-in version of each.
+const BOOK_FILES: &[(&str, &str)] = &[
+    ("layout.html", include_str!("templates/layout.html")),
+    (
+        "chapter_header.html",
+        include_str!("templates/chapter_header.html"),
+    ),
+    (
+        "section_header.html",
+        include_str!("templates/section_header.html"),
+    ),
+    ("styles.css", include_str!("templates/styles.css")),
+];
+
+#[derive(Debug)]
+pub struct Templates {
+    pub layout: Template,
+    pub chapter_header: Template,
+    pub section_header: Template,
+    pub report_layout: Template,
+    /// The stylesheet every page links to.
+    pub styles: String,
+}
+
+impl Templates {
+    /// Load the templates, preferring the files in `dir` over the built-in
+    /// ones. Each file is overridden on its own, so a project can restyle
+    /// the book without copying the HTML.
+    pub fn load(dir: Option<&Path>) -> Result<Self, TemplateError> {
+        let file = |name: &str| book_file(dir, name);
+        Ok(Self {
+            layout: Template::load(
+                file("layout.html")?,
+                &[
+                    "PAGE_TITLE",
+                    "SIDEBAR",
+                    "CHAPTER_CONTENT",
+                    "CHAPTER_NUM",
+                    "BASE_PATH",
+                    "LANG",
+                    "META_TAGS",
+                    "FOOTER",
+                ],
+                // A layout doesn't have to reload itself or mark drafts.
+                &["AUTO_RELOAD_SCRIPT", "BANNER"],
+            )?,
+            chapter_header: Template::load(
+                file("chapter_header.html")?,
+                &["CHAPTER_TITLE", "CHAPTER_NUM"],
+                &[],
+            )?,
+            section_header: Template::load(
+                file("section_header.html")?,
+                &["SECTION_TITLE", "CHAPTER_NUM", "SECTION_NUM"],
+                &[],
+            )?,
+            // The check report isn't part of the book, so it keeps its look.
+            report_layout: Template::load(
+                (
+                    "report_layout.html".to_string(),
+                    include_str!("templates/report_layout.html").to_string(),
+                ),
+                &["PAGE_TITLE", "BASE_PATH", "CONTENT"],
+                &[],
+            )?,
+            styles: file("styles.css")?.1,
+        })
+    }
+}
+
+/// The name to report a book file by and its text, from `dir` if it is there.
+fn book_file(dir: Option<&Path>, name: &str) -> Result<(String, String), TemplateError> {
+    let builtin = BOOK_FILES
+        .iter()
+        .find(|(file, _)| *file == name)
+        .map(|(_, text)| *text)
+        .unwrap();
+    let Some(path) = dir.map(|dir| dir.join(name)).filter(|path| path.exists()) else {
+        return Ok((name.to_string(), builtin.to_string()));
+    };
+    match fs::read_to_string(&path) {
+        Ok(text) => Ok((path.display().to_string(), text)),
+        Err(err) => Err(TemplateError::Unreadable {
+            template: path.display().to_string(),
+            reason: err.to_string(),
+        }),
+    }
+}
+
+/// Write the built-in book files to `dir` as a starting point for a
+/// project's own, returning the paths written. Existing files are left as
+/// they are.
+pub fn write_builtin(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    fs::create_dir_all(dir)?;
+    let mut written = Vec::new();
+    for (name, text) in BOOK_FILES {
+        let path = dir.join(name);
+        if path.exists() {
+            continue;
+        }
+        fs::write(&path, text)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+/// A template with `{{NAME}}` placeholders.
+#[derive(Debug)]
+pub struct Template {
+    name: String,
+    text: String,
+    /// The placeholders the code gives values for, without the braces.
+    placeholders: Vec<&'static str>,
+    matcher: AhoCorasick,
+}
+
+impl Template {
+    /// Load the template `name` with the text `text`, which must use each
+    /// of `required` and may use those in `optional`. Any other placeholder
+    /// in the template is an error since nothing would fill it in.
+    ///
+    /// The template is checked rather than the filled in page since the
+    /// values, like a book's prose, may contain braces of their own.
+    fn load(
+        (name, text): (String, String),
+        required: &[&'static str],
+        optional: &[&'static str],
+    ) -> Result<Self, TemplateError> {
+        let placeholders: Vec<_> = required.iter().chain(optional).copied().collect();
+
+        let used = find_placeholders(&text);
+        if let Some(unknown) = used.iter().find(|&used| !placeholders.contains(used)) {
+            return Err(TemplateError::Unknown {
+                placeholder: unknown.to_string(),
+                template: name,
+            });
+        }
+        if let Some(missing) = required.iter().find(|&required| !used.contains(required)) {
+            return Err(TemplateError::Missing {
+                template: name,
+                placeholder: missing,
+            });
+        }
+
+        let patterns = placeholders.iter().map(|name| format!("{{{{{name}}}}}"));
+        Ok(Self {
+            name,
+            text,
+            matcher: AhoCorasick::new(patterns).unwrap(),
+            placeholders,
+        })
+    }
+
+    /// Fill in the placeholders. Every placeholder the template was loaded
+    /// with must be given a value, and only those.
+    pub fn fill(&self, values: &[(&str, &Html)]) -> Html {
+        debug_assert!(
+            values
+                .iter()
+                .all(|(name, _)| self.placeholders.contains(name)),
+            "a value is given for a placeholder which `{}` doesn't have",
+            self.name
+        );
+
+        let replacements: Vec<_> = self
+            .placeholders
+            .iter()
+            .map(|placeholder| {
+                let value = values.iter().find(|(name, _)| name == placeholder);
+                match value {
+                    Some((_, html)) => html.as_str(),
+                    None => panic!(
+                        "no value is given for `{{{{{placeholder}}}}}` in `{}`",
+                        self.name
+                    ),
+                }
+            })
+            .collect();
+        Html::raw(&self.matcher.replace_all(&self.text, &replacements))
+    }
+}
+
+/// The names of the `{{NAME}}` placeholders in `text`.
+fn find_placeholders(text: &str) -> Vec<&str> {
+    let mut placeholders = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+        let Some(end) = rest.find("}}") else {
+            break;
+        };
+        let name = &rest[..end];
+        let is_placeholder = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+        if is_placeholder {
+            placeholders.push(name);
+            rest = &rest[end + 2..];
+        }
+    }
+    placeholders
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// The template has a placeholder nothing fills in, usually a typo.
+    Unknown {
+        template: String,
+        placeholder: String,
+    },
+    /// The template doesn't use a placeholder it has to.
+    Missing {
+        template: String,
+        placeholder: &'static str,
+    },
+    /// A project's template couldn't be read.
+    Unreadable { template: String, reason: String },
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Unknown {
+                template,
+                placeholder,
+            } => write!(
+                f,
+                "template `{template}` has the placeholder `{{{{{placeholder}}}}}` which is never filled in"
+            ),
+            TemplateError::Missing {
+                template,
+                placeholder,
+            } => write!(
+                f,
+                "template `{template}` is missing the placeholder `{{{{{placeholder}}}}}`"
+            ),
+            TemplateError::Unreadable { template, reason } => {
+                write!(f, "can't read template `{template}`: {reason}")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Template, TemplateError, Templates};
+    use crate::{book::html::Html, util::test_project::TestProject};
+
+    fn load(
+        text: &str,
+        required: &[&'static str],
+        optional: &[&'static str],
+    ) -> Result<Template, TemplateError> {
+        Template::load(
+            ("page.html".to_string(), text.to_string()),
+            required,
+            optional,
+        )
+    }
+
+    #[test]
+    fn the_builtin_templates_load() {
+        assert!(Templates::load(None).is_ok());
+    }
+
+    #[test]
+    fn misspelt_placeholders_are_errors() {
+        let err = load("<h1>{{CHAPTER_TITEL}}</h1>", &["CHAPTER_TITLE"], &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "template `page.html` has the placeholder `{{CHAPTER_TITEL}}` which is never filled in"
+        );
+    }
+
+    #[test]
+    fn required_placeholders_must_be_used() {
+        let err = load("<h1></h1>", &["CHAPTER_TITLE"], &[]).unwrap_err();
+        assert_eq!(
+            err,
+            TemplateError::Missing {
+                template: "page.html".to_string(),
+                placeholder: "CHAPTER_TITLE",
+            }
+        );
+    }
+
+    #[test]
+    fn optional_placeholders_may_be_left_out() {
+        let template = load("<p>{{TEXT}}</p>", &["TEXT"], &["BANNER"]).unwrap();
+        // The values may contain braces of their own.
+        let text = Html::text("{{BANNER}}");
+        let banner = Html::raw("<div>draft</div>");
+        let page = template.fill(&[("TEXT", &text), ("BANNER", &banner)]);
+        assert_eq!(page.as_str(), "<p>{{BANNER}}</p>");
+    }
+
+    #[test]
+    fn a_projects_template_is_checked_too() {
+        let project = TestProject::new("");
+        project.write(
+            "book/chapter_header.html",
+            "<h1>{{CHAPTER_TITLE}} {{CHAPTER_NUMBER}}</h1>",
+        );
+        let err = Templates::load(Some(&project.path("book"))).unwrap_err();
+        assert_eq!(
+            err,
+            TemplateError::Unknown {
+                template: project
+                    .path("book/chapter_header.html")
+                    .display()
+                    .to_string(),
+                placeholder: "CHAPTER_NUMBER".to_string(),
+            }
+        );
+    }
+}
@@ -0,0 +1,13 @@
+// Reload the page when `watson book --serve` rebuilds the book. The server
+// sets `watsonBuild` to the build the page was served from.
+setInterval(async () => {
+  try {
+    const response = await fetch("/__watson/build", { cache: "no-cache" });
+    const build = Number(await response.text());
+    if (build !== watsonBuild) {
+      window.location.reload();
+    }
+  } catch (e) {
+    // Ignore errors (e.g., if server is temporarily down)
+  }
+}, 1000);
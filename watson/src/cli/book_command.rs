@@ -1,21 +1,32 @@
 use crate::{
-    book,
-    cli::check_command,
+    book::{
+        self,
+        epub::EpubMath,
+        server::{self, LiveBook},
+    },
     config::{WatsonConfig, find_config_file},
-    context::Arenas,
+    context::{Arenas, Ctx},
+    diagnostics::print_diagnostics,
+    driver::Driver,
     util::ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_GREEN, ANSI_RED, ANSI_RESET},
 };
 use argh::FromArgs;
-use std::path::PathBuf;
+use notify::Watcher;
+use std::{path::PathBuf, sync::mpsc, thread};
 
 /// Build the book for a Watson project.
 #[derive(FromArgs)]
 #[argh(subcommand, name = "book")]
 pub struct BookCommand {
-    /// serve the book on a local web server after building.
+    /// serve the book on a local web server, rebuilding it and reloading
+    /// open pages whenever the project changes.
     #[argh(switch, short = 's')]
     serve: bool,
 
+    /// the port to serve the book on. Defaults to the one in watson.toml.
+    #[argh(option)]
+    port: Option<u16>,
+
     /// base path for URLs (e.g., "/repo-name/" for GitHub Pages).
     #[argh(option, short = 'b', default = "String::from(\"/\")")]
     base_path: String,
@@ -23,30 +34,334 @@ pub struct BookCommand {
     /// path to watson.toml config file.
     #[argh(option, short = 'c')]
     config: Option<PathBuf>,
+
+    /// build a draft of the prose only, without parsing or checking commands.
+    #[argh(switch)]
+    prose_only: bool,
+
+    /// parse the project but don't check proofs. The book marks every
+    /// theorem as unchecked.
+    #[argh(switch)]
+    no_check: bool,
+
+    /// build the book even if parsing or checking reported errors. Failed
+    /// theorems are marked as failed.
+    #[argh(switch)]
+    allow_errors: bool,
+
+    /// write the book to this EPUB file instead of building the website.
+    #[argh(option)]
+    epub: Option<PathBuf>,
+
+    /// how math is written in the EPUB: katex-html (the default), mathml or
+    /// image.
+    #[argh(option, default = "EpubMath::KatexHtml")]
+    epub_math: EpubMath,
+
+    /// write the built-in templates and stylesheet to this directory and
+    /// exit. Copied to `book/templates/` in the project, they override the
+    /// built-in ones file by file.
+    #[argh(option)]
+    default_templates: Option<PathBuf>,
 }
 
 pub fn run_book(cmd: BookCommand) {
+    if let Some(dir) = &cmd.default_templates {
+        match book::write_default_templates(dir) {
+            Ok(written) => {
+                for path in &written {
+                    println!(
+                        "{ANSI_GREEN}{ANSI_BOLD}wrote{ANSI_RESET} {}",
+                        path.display()
+                    );
+                }
+                if written.is_empty() {
+                    println!("every template is already in {}", dir.display());
+                }
+            }
+            Err(err) => {
+                eprintln!(
+                    "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} can't write the templates to {}: {err}",
+                    dir.display()
+                );
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if cmd.serve && cmd.epub.is_some() {
+        eprintln!(
+            "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --serve and --epub can't be used together"
+        );
+        std::process::exit(1);
+    }
+    if cmd.epub.is_some() && !cmd.epub_math.is_supported() {
+        eprintln!(
+            "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --epub-math {} isn't supported yet, use katex-html",
+            cmd.epub_math
+        );
+        std::process::exit(1);
+    }
+
     // Find watson.toml config file
-    let config_file_path = match cmd.config {
+    let config_file_path = match &cmd.config {
         Some(file) => file.canonicalize().unwrap(),
         None => find_config_file().unwrap(),
     };
 
+    if cmd.port.is_some() && !cmd.serve {
+        eprintln!("{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --port can only be used with --serve");
+        std::process::exit(1);
+    }
+
     let config = WatsonConfig::from_file(&config_file_path).unwrap();
+    if cmd.serve {
+        serve_book(&cmd, config);
+        return;
+    }
 
     let arenas = Arenas::new();
-    let (mut ctx, parse_report, proof_report) = check_command::check(config, &arenas);
+    let (ctx, book_path) = build(&cmd, config, &arenas);
+    if book_path.is_none() && ctx.diags.has_errors() {
+        std::process::exit(1);
+    }
+}
 
-    if ctx.diags.has_errors() {
-        println!("{ANSI_RED}{ANSI_BOLD}Errors reported.{ANSI_RESET} Building book anyway.")
+/// Build the book, serve it and rebuild it whenever the project changes.
+/// While the book can't be built its pages show the errors instead.
+fn serve_book(cmd: &BookCommand, config: WatsonConfig) {
+    let port = cmd.port.unwrap_or(config.book().port());
+    let live = LiveBook::default();
+    {
+        let book_dir = config.build_dir().join("book");
+        let live = live.clone();
+        thread::spawn(move || server::serve_live(&book_dir, port, live));
     }
 
-    let book_path = book::build_book(&mut ctx, parse_report, proof_report, false, &cmd.base_path);
+    let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(tx).unwrap();
+    watcher
+        .watch(config.math_dir(), notify::RecursiveMode::Recursive)
+        .unwrap();
+    watcher
+        .watch(config.lua_dir(), notify::RecursiveMode::Recursive)
+        .unwrap();
+    if let Some(templates_dir) = config.book().templates_dir()
+        && templates_dir.exists()
+    {
+        watcher
+            .watch(templates_dir, notify::RecursiveMode::Recursive)
+            .unwrap();
+    }
 
-    if cmd.serve {
-        let port = ctx.config.book().port();
-        book::server::serve(&book_path, port);
-        println!("{ANSI_BOLD}{ANSI_GREEN}Serving book{ANSI_RESET} at http://localhost:{port}");
-        println!("{ANSI_GRAY}Press Ctrl+C to stop the server{ANSI_RESET}");
+    loop {
+        let _ = rx.try_iter().count();
+        let arenas = Arenas::new();
+        let (ctx, book_path) = build(cmd, config.clone(), &arenas);
+        match book_path {
+            Some(_) => live.rebuilt(),
+            None if ctx.diags.has_errors() => live.failed(rendered_errors(&ctx)),
+            None => live.failed(vec![
+                "There is no prose. Start a chapter with a `# Title` heading.".to_string(),
+            ]),
+        }
+        println!(
+            "{ANSI_BOLD}{ANSI_GREEN}Serving book{ANSI_RESET} at http://localhost:{port}/chapter-1/"
+        );
+        println!("{ANSI_GRAY}Watching for changes. Press Ctrl+C to stop the server{ANSI_RESET}");
+
+        while let Ok(e) = rx.recv().unwrap() {
+            if !matches!(e.kind, notify::EventKind::Access(_)) {
+                break;
+            }
+        }
+        println!();
+    }
+}
+
+/// The errors in the context as they are printed, without colors.
+fn rendered_errors(ctx: &Ctx) -> Vec<String> {
+    ctx.diags
+        .iter()
+        .filter(|diag| diag.is_error())
+        .map(|diag| diag.render_plain(ctx))
+        .collect()
+}
+
+/// Build the book as `cmd` asks, returning where it was written if it was.
+/// Errors which stopped it from being built are left in the context.
+fn build<'ctx>(
+    cmd: &BookCommand,
+    config: WatsonConfig,
+    arenas: &'ctx Arenas<'ctx>,
+) -> (Ctx<'ctx>, Option<PathBuf>) {
+    if cmd.prose_only {
+        let (mut ctx, entries) = Driver::for_project(config, arenas).scan();
+        let book_path = match &cmd.epub {
+            Some(path) => book::build_draft_epub(&mut ctx, &entries, path),
+            None => book::build_draft_book(&mut ctx, &entries, &cmd.base_path),
+        };
+        (ctx, book_path)
+    } else {
+        // Check the project just like `watson check` so the book is only
+        // built from the same results unless asked otherwise.
+        let (mut ctx, parse_report, proof_report) = if cmd.no_check {
+            let parsed = Driver::for_project(config, arenas).parse();
+            print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+            let (ctx, parse_report) = parsed.into_parts();
+            (ctx, parse_report, None)
+        } else {
+            let (checked, _) = Driver::for_project(config, arenas).check_and_report(None);
+            let (ctx, parse_report, proof_report) = checked.into_parts();
+            (ctx, parse_report, Some(proof_report))
+        };
+
+        if ctx.diags.has_errors() {
+            if !cmd.allow_errors {
+                println!(
+                    "{ANSI_RED}{ANSI_BOLD}Errors reported.{ANSI_RESET} Not building book. Pass --allow-errors to build it anyway."
+                );
+                return (ctx, None);
+            }
+            println!("{ANSI_RED}{ANSI_BOLD}Errors reported.{ANSI_RESET} Building book anyway.")
+        }
+
+        let book_path = match &cmd.epub {
+            Some(path) => book::build_epub(&mut ctx, parse_report, proof_report, path),
+            None => book::build_book(&mut ctx, parse_report, proof_report, false, &cmd.base_path),
+        };
+        (ctx, book_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BookCommand, build};
+    use crate::{book::epub::EpubMath, context::Arenas, util::test_project::TestProject};
+    use std::fs;
+
+    /// A chapter with a theorem whose proof checks and one whose proof fails.
+    const MAIN: &str = "# Proofs\n\n\
+        module logic\n\n\
+        theorem good [p : sentence] :\n    |- p → p\nproof\nqed\n\n\
+        theorem bad [p : sentence] :\n    |- p → p\nproof\nqed\n";
+
+    /// A handler which leaves every proof as a todo, except that of `bad`.
+    const HANDLER: &str = "local M = {}\n\n\
+        function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+        \x20   if proofState.theorem.name == \"bad\" then\n\
+        \x20       error(\"stuck\")\n\
+        \x20   end\n\
+        \x20   return proofState:applyTodo(proofState.goal)\n\
+        end\n\n\
+        return M\n";
+
+    fn command(no_check: bool, allow_errors: bool) -> BookCommand {
+        BookCommand {
+            serve: false,
+            port: None,
+            base_path: "/".to_string(),
+            config: None,
+            prose_only: false,
+            no_check,
+            allow_errors,
+            epub: None,
+            epub_math: EpubMath::KatexHtml,
+            default_templates: None,
+        }
+    }
+
+    /// Build the book of the fixture as `cmd` asks, returning whether there
+    /// were errors and, if the book was built, its chapters' pages joined.
+    fn build_fixture(cmd: BookCommand) -> (bool, Option<String>) {
+        build_main(MAIN, cmd)
+    }
+
+    /// Build the book of a project with `main` as `cmd` asks, like
+    /// [`build_fixture`].
+    fn build_main(main: &str, cmd: BookCommand) -> (bool, Option<String>) {
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", HANDLER);
+        let arenas = Arenas::new();
+        let (ctx, book_path) = build(&cmd, project.config(), &arenas);
+        let pages = book_path.map(|book| {
+            (1..)
+                .map(|chapter| book.join(format!("chapter-{chapter}/index.html")))
+                .take_while(|page| page.exists())
+                .map(|page| fs::read_to_string(page).unwrap())
+                .collect()
+        });
+        (ctx.diags.has_errors(), pages)
+    }
+
+    #[test]
+    fn failed_proofs_stop_the_book() {
+        assert_eq!(build_fixture(command(false, false)), (true, None));
+    }
+
+    #[test]
+    fn allow_errors_marks_failed_theorems() {
+        let (errors, pages) = build_fixture(command(false, true));
+        // The checking errors were printed, and the book clears them before
+        // rendering so only its own errors can stop it.
+        assert!(!errors);
+        let pages = pages.unwrap();
+        assert_eq!(pages.matches(r#"class="badge failed""#).count(), 1);
+        assert_eq!(pages.matches(r#"class="badge incomplete""#).count(), 1);
+        assert!(!pages.contains("draft-banner"));
+    }
+
+    #[test]
+    fn no_check_marks_every_theorem_unchecked() {
+        let (errors, pages) = build_fixture(command(true, false));
+        assert!(!errors);
+        let pages = pages.unwrap();
+        // The axioms of `logic` aren't checked either.
+        assert_eq!(pages.matches(r#"class="badge unchecked""#).count(), 5);
+        assert!(!pages.contains(r#"class="badge failed""#));
+        assert!(pages.contains("Proofs not checked"));
+    }
+
+    #[test]
+    fn prose_only_keeps_all_the_prose() {
+        let main = "# Intro\n\n\
+            Some *prose* with a [link](https://example.com).\n\n\
+            module logic\n\n\
+            ## Lists\n\n\
+            - one\n- two\n\n\
+            # Proofs\n\n\
+            A theorem, then a block of code.\n\n\
+            theorem good [p : sentence] :\n    |- p → p\nproof\nqed\n\n\
+            ```\nplain code\n```\n\n\
+            > A quote.\n";
+        let (errors, full) = build_main(main, command(true, false));
+        assert!(!errors);
+        let (errors, draft) = build_main(
+            main,
+            BookCommand {
+                prose_only: true,
+                ..command(false, false)
+            },
+        );
+        assert!(!errors);
+
+        let (full, draft) = (full.unwrap(), draft.unwrap());
+        let counts = |pages: &str| {
+            [
+                "<h1",
+                "<h2",
+                "<p>",
+                "<em>",
+                "<a href",
+                "<li>",
+                "<pre",
+                "<blockquote>",
+            ]
+            .map(|element| (element, pages.matches(element).count()))
+        };
+        assert_eq!(counts(&draft), counts(&full));
+        assert!(draft.contains("Draft preview"));
+        assert!(!full.contains("Draft preview"));
     }
 }
@@ -1,11 +1,16 @@
 use crate::{
-    book,
-    config::{WatsonConfig, find_config_file},
-    context::{Arenas, Ctx},
-    parse::{ParseReport, SourceCache, SourceId, parse, source_cache::SourceDecl},
-    report::{ProofReport, display_report},
-    semant::{check_circularity::find_circular_dependency_groups, check_proofs::check_proofs},
-    util::ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RESET},
+    book::{self, check_report::write_check_report},
+    config::{MessageFormat, ProofStateDisplay, WatsonConfig, find_config_file},
+    context::Arenas,
+    driver::{Checked, Driver},
+    parse::{SourceCache, SourceId, source_cache::SourceDecl},
+    report::{
+        DepsFormat, display_axiom_audit, display_todo_taint, display_todos, write_deps,
+        write_profile,
+    },
+    semant::theorem_filter::TheoremFilter,
+    strings,
+    util::ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_GREEN, ANSI_RED, ANSI_RESET},
 };
 use argh::FromArgs;
 use crossterm::{
@@ -14,7 +19,18 @@ use crossterm::{
     terminal::{BeginSynchronizedUpdate, Clear, ClearType, EndSynchronizedUpdate},
 };
 use notify::Watcher;
-use std::{io, path::PathBuf, sync::mpsc, thread, time::Instant};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::{
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
 use ustr::Ustr;
 
 /// Check proofs in a Watson project.
@@ -32,131 +48,490 @@ pub struct CheckCommand {
     /// path to watson.toml config file.
     #[argh(option, short = 'c')]
     config: Option<PathBuf>,
+
+    /// read a single source from stdin instead of a project.
+    #[argh(switch)]
+    stdin: bool,
+
+    /// name of the stdin source used in diagnostics.
+    #[argh(option)]
+    stdin_name: Option<String>,
+
+    /// lua tactic handler to use when reading from stdin.
+    #[argh(option)]
+    lua: Option<PathBuf>,
+
+    /// allow `module` commands to load files when reading from stdin.
+    #[argh(switch)]
+    allow_fs: bool,
+
+    /// how much of the proof state to show for failed tactics: full, recent
+    /// or none.
+    #[argh(option)]
+    proof_state: Option<ProofStateDisplay>,
+
+    /// hide the logs of tactics, except for theorems which fail.
+    #[argh(switch)]
+    quiet_tactics: bool,
+
+    /// stop at the first error in parsing or checking.
+    #[argh(switch)]
+    fail_fast: bool,
+
+    /// report as many errors as possible. This is the default.
+    #[argh(switch)]
+    keep_going: bool,
+
+    /// treat proofs which a tactic marked as fragile as errors.
+    #[argh(switch)]
+    deny_fragile: bool,
+
+    /// treat proofs which use todo as errors.
+    #[argh(switch)]
+    deny_todo: bool,
+
+    /// treat proofs which use todo as errors, except for todos tracked by
+    /// this ticket. Can be repeated.
+    #[argh(option)]
+    deny_todo_except: Vec<String>,
+
+    /// treat warnings, such as unused hypotheses, as errors.
+    #[argh(switch)]
+    deny_warnings: bool,
+
+    /// how to print diagnostics: human (the default) or json, which prints
+    /// each one as a JSON object on its own line instead of the summary.
+    #[argh(option, default = "MessageFormat::Human")]
+    message_format: MessageFormat,
+
+    /// list every todo with its theorem, location, reason and ticket.
+    #[argh(switch)]
+    todos: bool,
+
+    /// list every theorem which depends on todo, directly or through the
+    /// theorems it uses, with where each todo it depends on is used.
+    #[argh(switch)]
+    list_todo: bool,
+
+    /// list the axioms the given theorem depends on.
+    #[argh(option)]
+    audit: Option<String>,
+
+    /// list the slowest proofs with the time spent in their tactic and in
+    /// the kernel. Printed to stderr with --message-format json.
+    #[argh(switch)]
+    profile: bool,
+
+    /// how many proofs --profile lists. Defaults to 20.
+    #[argh(option, default = "20")]
+    profile_limit: usize,
+
+    /// write the graph of which theorems each proof uses to this file, with
+    /// axioms and proofs using todo marked.
+    #[argh(option)]
+    emit_deps: Option<PathBuf>,
+
+    /// the format --emit-deps writes: dot (the default) or json.
+    #[argh(option, default = "DepsFormat::Dot")]
+    emit_deps_format: DepsFormat,
+
+    /// write an HTML report of the check to this directory, even if there
+    /// are errors.
+    #[argh(option)]
+    report: Option<PathBuf>,
+
+    /// only check theorems whose names match this glob, where `*` matches
+    /// any run of characters and `?` any one character. Can be repeated.
+    #[argh(option)]
+    only: Vec<String>,
+
+    /// only check theorems declared in this file. Can be repeated.
+    #[argh(option)]
+    file: Vec<PathBuf>,
+
+    /// only check theorems declared in files changed since the last git
+    /// commit.
+    #[argh(switch)]
+    changed: bool,
+
+    /// also check the theorems used by the theorems selected by --only,
+    /// --file and --changed.
+    #[argh(switch)]
+    with_deps: bool,
+
+    /// parse every command again rather than reusing the parse trees of
+    /// unchanged sources from the last run.
+    #[argh(switch)]
+    no_cache: bool,
 }
 
 pub fn run_check(cmd: CheckCommand) {
+    if cmd.fail_fast && cmd.keep_going {
+        eprintln!(
+            "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --fail-fast and --keep-going can't be used together"
+        );
+        std::process::exit(1);
+    }
+
+    if cmd.stdin {
+        run_check_stdin(cmd);
+        return;
+    }
+
     // Find watson.toml config file
-    let config_file_path = match cmd.config {
+    let config_file_path = match &cmd.config {
         Some(file) => file.canonicalize().unwrap(),
         None => find_config_file().unwrap(),
     };
 
+    let mut config = WatsonConfig::from_file(&config_file_path).unwrap();
+    if let Some(proof_state) = cmd.proof_state {
+        config.set_proof_state(proof_state);
+    }
+    config.set_quiet_tactics(cmd.quiet_tactics);
+    config.set_fail_fast(cmd.fail_fast);
+    config.set_parse_cache(!cmd.no_cache);
+    config.set_deny_fragile(cmd.deny_fragile);
+    config.set_deny_warnings(cmd.deny_warnings);
+    config.set_message_format(cmd.message_format);
+    if cmd.deny_todo || !cmd.deny_todo_except.is_empty() {
+        config.set_deny_todo(cmd.deny_todo_except.clone());
+    }
+    if let Some(filter) = theorem_filter(&cmd) {
+        if cmd.book {
+            eprintln!(
+                "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --book needs every theorem checked, so it can't be used with --only, --file or --changed"
+            );
+            std::process::exit(1);
+        }
+        config.set_theorem_filter(filter);
+    }
+
     if cmd.watch {
-        let config = WatsonConfig::from_file(&config_file_path).unwrap();
+        watch_check(&cmd, config);
+    } else {
+        check_once(&cmd, config);
+    }
+}
 
-        let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
-        let mut watcher = notify::recommended_watcher(tx).unwrap();
-        watcher
-            .watch(config.math_dir(), notify::RecursiveMode::Recursive)
-            .unwrap();
+fn check_once(cmd: &CheckCommand, config: WatsonConfig) {
+    let arenas = Arenas::new();
+    let (checked, elapsed) = Driver::for_project(config.clone(), &arenas).check_and_report(None);
+    let printed = print_check_results(&checked, cmd, elapsed);
+
+    if checked.ctx().diags.has_errors() || !printed {
+        std::process::exit(1)
+    } else if cmd.book {
+        // Build and serve book after successful check
+        let (mut ctx, parse_report, report) = checked.into_parts();
+        match book::build_book(&mut ctx, parse_report, Some(report), false, "/") {
+            Some(book_path) => {
+                let port = config.book().port();
+                println!();
+                book::server::serve(&book_path, port);
+            }
+            None if ctx.diags.has_errors() => std::process::exit(1),
+            None => {}
+        }
+    }
+}
+
+/// How long the sources have to stay unchanged before they are checked
+/// again, so that saving many files at once only triggers one check.
+const WATCH_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Check the project, then check it again from scratch every time one of
+/// its sources changes until interrupted.
+fn watch_check(cmd: &CheckCommand, config: WatsonConfig) {
+    exit_on_interrupt();
+
+    let (tx, rx) = mpsc::channel::<notify::Result<notify::Event>>();
+    let mut watcher = notify::recommended_watcher(tx).unwrap();
+    watcher
+        .watch(config.math_dir(), notify::RecursiveMode::Recursive)
+        .unwrap();
+    watcher
+        .watch(config.lua_dir(), notify::RecursiveMode::Recursive)
+        .unwrap();
+    if let Some(lua_entry) = config.lua_entry()
+        && !lua_entry.starts_with(config.lua_dir())
+    {
         watcher
-            .watch(config.lua_dir(), notify::RecursiveMode::Recursive)
+            .watch(lua_entry, notify::RecursiveMode::NonRecursive)
             .unwrap();
+    }
 
-        // Build book initially and start server in background if book flag is set
-        if cmd.book {
-            let book_path = config.build_dir().join("book");
-            let book_port = config.book().port();
-            thread::spawn(move || {
-                book::server::serve(&book_path, book_port);
-            });
-        }
+    // Build book initially and start server in background if book flag is set
+    if cmd.book {
+        let book_path = config.build_dir().join("book");
+        let book_port = config.book().port();
+        thread::spawn(move || {
+            book::server::serve(&book_path, book_port);
+        });
+    }
 
-        for i in 1.. {
-            let _ = rx.try_iter().count();
-            let arenas = Arenas::new();
-
-            // Clear the screen to print the new info
-            _ = execute!(
-                io::stdout(),
-                BeginSynchronizedUpdate,
-                Clear(ClearType::All),
-                Clear(ClearType::Purge),
-                MoveTo(0, 0)
-            );
+    for i in 1.. {
+        // The context borrows from the arenas, so each check gets new ones
+        // which are dropped with everything else from it before the next.
+        let arenas = Arenas::new();
 
-            let start = Instant::now();
-            let (mut ctx, parse_report, report) = check(config.clone(), &arenas);
-            let elapsed = start.elapsed();
+        // Clear the screen to print the new info
+        _ = execute!(
+            io::stdout(),
+            BeginSynchronizedUpdate,
+            Clear(ClearType::All),
+            Clear(ClearType::Purge),
+            MoveTo(0, 0)
+        );
 
-            display_report(&report, ctx.diags.has_errors(), Some(i), Some(elapsed));
-            if ctx.diags.has_errors() {
-                ctx.diags.print_errors(&ctx);
-            } else if cmd.book {
-                let book_port = config.book().port();
+        let (checked, elapsed) =
+            Driver::for_project(config.clone(), &arenas).check_and_report(Some(i));
+        print_check_results(&checked, cmd, elapsed);
+        if !checked.ctx().diags.has_errors() && cmd.book {
+            let book_port = config.book().port();
+            let (mut ctx, parse_report, report) = checked.into_parts();
 
-                // Rebuild book on successful check
-                println!();
-                book::build_book(&mut ctx, parse_report, report, true, "/");
+            // Rebuild book on successful check
+            println!();
+            if book::build_book(&mut ctx, parse_report, Some(report), true, "/").is_some() {
                 println!(
                     "{ANSI_BOLD}{ANSI_GREEN}Serving book{ANSI_RESET} at http://localhost:{book_port}"
                 );
             }
+        }
+        if cmd.message_format == MessageFormat::Human {
+            println!("{ANSI_GRAY}Watching for changes. Press Ctrl+C to stop{ANSI_RESET}");
+        }
 
-            // Display what has been printed.
-            _ = execute!(io::stdout(), EndSynchronizedUpdate,);
+        // Display what has been printed.
+        _ = execute!(io::stdout(), EndSynchronizedUpdate);
 
-            while let Ok(e) = rx.recv().unwrap() {
-                if !matches!(e.kind, notify::EventKind::Access(_)) {
-                    break;
-                }
-            }
+        wait_for_change(&rx, &config);
+    }
+}
+
+/// Block until a source of the project changes and then stops changing for
+/// [`WATCH_DEBOUNCE`]. A change made during the last check counts too.
+/// Changes to other files, like an editor's swap files or what the check
+/// itself writes to the build directory, are ignored.
+fn wait_for_change(rx: &mpsc::Receiver<notify::Result<notify::Event>>, config: &WatsonConfig) {
+    let is_change = |event: &notify::Result<notify::Event>| match event {
+        Ok(event) => {
+            !matches!(event.kind, notify::EventKind::Access(_))
+                && event.paths.iter().any(|path| is_source(path, config))
         }
-    } else {
-        let config = WatsonConfig::from_file(&config_file_path).unwrap();
+        // Something may have been missed, so check again to be safe.
+        Err(_) => true,
+    };
 
-        let arenas = Arenas::new();
-        let (mut ctx, parse_report, report) = check(config.clone(), &arenas);
+    loop {
+        let event = rx.recv().expect("the file watcher stopped");
+        if is_change(&event) {
+            break;
+        }
+    }
+    while rx.recv_timeout(WATCH_DEBOUNCE).is_ok() {}
+}
 
-        display_report(&report, ctx.diags.has_errors(), None, None);
+fn is_source(path: &Path, config: &WatsonConfig) -> bool {
+    if path.starts_with(config.build_dir()) {
+        return false;
+    }
+    let extension = path.extension().and_then(|ext| ext.to_str());
+    extension == Some(strings::FILE_EXTENSION.as_str())
+        || matches!(extension, Some("luau" | "lua"))
+        || config.lua_entry().is_some_and(|entry| entry == path)
+}
 
-        if ctx.diags.has_errors() {
-            ctx.diags.print_errors(&ctx);
-            std::process::exit(1)
-        } else if cmd.book {
-            // Build and serve book after successful check
-            let book_path = book::build_book(&mut ctx, parse_report, report, false, "/");
-            let port = config.book().port();
-            println!();
-            book::server::serve(&book_path, port);
+/// Exit when interrupted rather than being killed, so the terminal isn't
+/// left waiting for the end of a synchronized update.
+fn exit_on_interrupt() {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register(signal, interrupted.clone()).unwrap();
+    }
+    thread::spawn(move || {
+        while !interrupted.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(50));
         }
+        _ = execute!(io::stdout(), EndSynchronizedUpdate);
+        println!();
+        std::process::exit(0);
+    });
+}
+
+fn run_check_stdin(cmd: CheckCommand) {
+    if cmd.watch || cmd.book {
+        eprintln!(
+            "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --watch and --book can't be used with --stdin"
+        );
+        std::process::exit(1);
+    }
+    if !cmd.file.is_empty() || cmd.changed {
+        eprintln!(
+            "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} --file and --changed can't be used with --stdin"
+        );
+        std::process::exit(1);
+    }
+
+    let name = Ustr::from(cmd.stdin_name.as_deref().unwrap_or("stdin"));
+    let lua_entry = cmd.lua.as_ref().map(|lua| lua.canonicalize().unwrap());
+    let mut config = WatsonConfig::for_stdin(name, lua_entry, cmd.allow_fs);
+    if let Some(proof_state) = cmd.proof_state {
+        config.set_proof_state(proof_state);
+    }
+    config.set_quiet_tactics(cmd.quiet_tactics);
+    config.set_fail_fast(cmd.fail_fast);
+    config.set_deny_fragile(cmd.deny_fragile);
+    config.set_deny_warnings(cmd.deny_warnings);
+    config.set_message_format(cmd.message_format);
+    if cmd.deny_todo || !cmd.deny_todo_except.is_empty() {
+        config.set_deny_todo(cmd.deny_todo_except.clone());
+    }
+    if let Some(filter) = theorem_filter(&cmd) {
+        config.set_theorem_filter(filter);
+    }
+
+    let mut text = String::new();
+    io::stdin().read_to_string(&mut text).unwrap();
+
+    let arenas = Arenas::new();
+    let (checked, elapsed) = check_source(name, text, config, &arenas);
+    let printed = print_check_results(&checked, &cmd, elapsed);
+
+    if checked.ctx().diags.has_errors() || !printed {
+        std::process::exit(1)
     }
 }
 
-pub fn check<'ctx>(
+/// Check `text` as the only source of a project, under the name `name`.
+fn check_source<'ctx>(
+    name: Ustr,
+    text: String,
     config: WatsonConfig,
     arenas: &'ctx Arenas<'ctx>,
-) -> (Ctx<'ctx>, ParseReport<'ctx>, ProofReport<'ctx>) {
-    let (source_cache, root_id) = make_source_cache(&config);
-    let mut ctx = Ctx::new(source_cache, config, arenas);
-    let (parse_report, proof_report) = compile(root_id, &mut ctx);
-    (ctx, parse_report, proof_report)
-}
-
-fn make_source_cache(config: &WatsonConfig) -> (SourceCache, SourceId) {
+) -> (Driver<'ctx, Checked<'ctx>>, Duration) {
     let source_cache = SourceCache::new();
+    let root = SourceId::new(name);
+    source_cache.add(root, text, SourceDecl::Root);
+    Driver::new(source_cache, vec![root], config, arenas).check_and_report(None)
+}
 
-    let root_path = config.math_dir().join("main.wats");
-    let root_text = std::fs::read_to_string(&root_path).unwrap();
-    let root_id = SourceId::new(Ustr::from("main"));
-    source_cache.add(root_id, root_text, SourceDecl::Root);
+fn theorem_filter(cmd: &CheckCommand) -> Option<TheoremFilter> {
+    TheoremFilter::new(
+        cmd.only.clone(),
+        cmd.file.clone(),
+        cmd.changed,
+        cmd.with_deps,
+    )
+}
 
-    (source_cache, root_id)
+/// Print everything the options asked for after the summary and
+/// diagnostics of a check. Returns false if something asked for couldn't be
+/// printed, like the audit of a theorem which doesn't exist.
+fn print_check_results<'ctx>(
+    checked: &Driver<'ctx, Checked<'ctx>>,
+    cmd: &CheckCommand,
+    elapsed: Duration,
+) -> bool {
+    let mut printed = true;
+    if let Some(audit) = &cmd.audit {
+        printed &= display_axiom_audit(Ustr::from(audit), checked.proof_report(), checked.ctx());
+    }
+    if cmd.todos {
+        display_todos(checked.proof_report(), checked.ctx());
+    }
+    if cmd.list_todo {
+        display_todo_taint(checked.proof_report(), checked.ctx());
+    }
+    if cmd.profile {
+        let mut profile = String::new();
+        write_profile(
+            checked.proof_report(),
+            cmd.profile_limit,
+            elapsed,
+            &mut profile,
+        );
+        // Tools read the JSON diagnostics from stdout, so the profile goes
+        // to stderr rather than in between them.
+        match cmd.message_format {
+            MessageFormat::Human => print!("{profile}"),
+            MessageFormat::Json => eprint!("{profile}"),
+        }
+    }
+    if let Some(path) = &cmd.emit_deps {
+        println!();
+        match write_deps(path, checked.proof_report(), cmd.emit_deps_format) {
+            Ok(()) => println!(
+                "{ANSI_GREEN}{ANSI_BOLD}Wrote{ANSI_RESET} theorem dependencies to {}",
+                path.display()
+            ),
+            Err(err) => eprintln!(
+                "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} can't write theorem dependencies to {}: {err}",
+                path.display()
+            ),
+        }
+    }
+    if let Some(dir) = &cmd.report {
+        println!();
+        write_check_report(
+            dir,
+            checked.parse_report(),
+            checked.proof_report(),
+            Some(elapsed),
+            checked.ctx(),
+        );
+    }
+    printed
 }
 
-fn compile<'ctx>(root: SourceId, ctx: &mut Ctx<'ctx>) -> (ParseReport<'ctx>, ProofReport<'ctx>) {
-    let parse_report = parse(root, ctx);
-    let statuses = check_proofs(
-        &parse_report.theorems,
-        parse_report.scope.clone(),
-        parse_report.attributes.clone(),
-        ctx,
-    );
-    let circularities = find_circular_dependency_groups(&statuses);
-
-    let proof_report = ProofReport {
-        statuses,
-        circularities,
-    };
-    (parse_report, proof_report)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check `text` as if it were read from stdin without a tactic handler.
+    fn check_stdin(text: &str, test: impl for<'ctx> FnOnce(&Driver<'ctx, Checked<'ctx>>)) {
+        let name = Ustr::from("playground");
+        let config = WatsonConfig::for_stdin(name, None, false);
+        let arenas = Arenas::new();
+        let (checked, _) = check_source(name, text.to_string(), config, &arenas);
+        test(&checked);
+    }
+
+    /// A source with only the axiom `k`.
+    const AXIOM_ONLY: &str = "syntax false\n    sentence ::= \"⊥\"\nend\n\n\
+        syntax implies\n    sentence (100>) ::= sentence \"→\" sentence\nend\n\n\
+        axiom k [p q : sentence] :\n    |- p → q → p\nend\n";
+
+    #[test]
+    fn an_axiom_only_source_checks_from_stdin() {
+        check_stdin(AXIOM_ONLY, |checked| {
+            assert!(!checked.ctx().diags.has_errors());
+            let statuses: Vec<_> = checked.proof_report().statuses.iter().collect();
+            assert_eq!(statuses.len(), 1);
+            assert!(statuses[0].1.correct());
+        });
+    }
+
+    #[test]
+    fn modules_are_rejected_from_stdin() {
+        check_stdin("module logic\n", |checked| {
+            let errors: Vec<_> = (checked.ctx().diags.iter())
+                .filter(|diag| diag.is_error())
+                .map(|diag| diag.title())
+                .collect();
+            assert_eq!(errors, ["can't load modules when reading from stdin"]);
+            let span = checked.ctx().diags.iter().next().unwrap().primary_span();
+            assert_eq!(span.unwrap().source().name().as_str(), "playground");
+        });
+    }
+
+    #[test]
+    fn auditing_a_missing_theorem_fails() {
+        let audit = |name: &str| CheckCommand::from_args(&["check"], &["--audit", name]).unwrap();
+        check_stdin(AXIOM_ONLY, |checked| {
+            assert!(print_check_results(checked, &audit("k"), Duration::ZERO));
+            assert!(!print_check_results(checked, &audit("kk"), Duration::ZERO));
+        });
+    }
 }
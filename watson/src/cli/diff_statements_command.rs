@@ -0,0 +1,813 @@
+use crate::{
+    book::statement::template_parts,
+    config::{WatsonConfig, find_config_file},
+    context::Arenas,
+    diagnostics::{Diagnostic, WResult, print_diagnostics},
+    driver::Driver,
+    parse::{ParseEntry, ParseReport},
+    semant::{
+        content_hash::ContentHash,
+        fragment::FragHead,
+        presentation::{PresFrag, PresHead},
+        theorems::{PresFact, TheoremId},
+    },
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RED, ANSI_RESET, ANSI_YELLOW},
+        diff::{Edit, diff},
+        plural,
+    },
+};
+use argh::FromArgs;
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Write,
+    fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+/// Compare the statements of the theorems with an export written by an
+/// earlier revision, ignoring proofs and prose.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "diff-statements")]
+pub struct DiffStatementsCommand {
+    /// the export to compare against, written by `--save`.
+    #[argh(positional)]
+    old: Option<PathBuf>,
+
+    /// write the statements of the project to this file, to compare a later
+    /// revision against.
+    #[argh(option)]
+    save: Option<PathBuf>,
+
+    /// how to print the differences: human (the default) or json.
+    #[argh(option, default = "DiffFormat::Human")]
+    format: DiffFormat,
+
+    /// exit with an error if a statement was added, removed or changed.
+    /// Renaming a theorem without changing it is allowed.
+    #[argh(switch)]
+    deny_changes: bool,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for DiffFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("expected `human` or `json`, found `{s}`")),
+        }
+    }
+}
+
+/// Bumped whenever the layout of the export changes.
+const EXPORT_VERSION: u32 = 2;
+
+/// The statements of every theorem in a project.
+#[derive(Serialize, Deserialize)]
+struct StatementExport {
+    version: u32,
+    theorems: Vec<ExportedTheorem>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ExportedTheorem {
+    name: String,
+    kind: String,
+    /// The content hash of the statement.
+    hash: String,
+    templates: Vec<ExportedTemplate>,
+    hypotheses: Vec<Term>,
+    conclusion: Term,
+}
+
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ExportedTemplate {
+    text: String,
+    /// The content hash of the template, which changes with its default
+    /// even if that is written the same.
+    hash: String,
+}
+
+/// A fragment as a tree of its subterms, so the part of it which changed can
+/// be found without the project the export was written from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Term {
+    /// The notation or syntax rule at the root.
+    head: String,
+    /// The fragment as written.
+    text: String,
+    /// The content hash of the fragment, so a change to what it means shows
+    /// up even if it is written the same. Facts only have the hashes of
+    /// their parts.
+    hash: Option<String>,
+    children: Vec<Term>,
+}
+
+#[derive(Serialize)]
+struct StatementDiff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    renamed: Vec<Renamed>,
+    changed: Vec<Changed>,
+    unchanged: usize,
+}
+
+#[derive(Serialize)]
+struct Renamed {
+    old: String,
+    new: String,
+}
+
+#[derive(Serialize)]
+struct Changed {
+    name: String,
+    changes: Vec<PartChange>,
+}
+
+/// How one part of a statement changed. Hypotheses are counted from 1.
+#[derive(Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PartChange {
+    Templates {
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    HypothesisAdded {
+        index: usize,
+        new: String,
+    },
+    HypothesisRemoved {
+        index: usize,
+        old: String,
+    },
+    HypothesisModified {
+        old_index: usize,
+        new_index: usize,
+        #[serde(flatten)]
+        change: TermChange,
+    },
+    Conclusion {
+        #[serde(flatten)]
+        change: TermChange,
+    },
+}
+
+/// A fragment which changed, with the smallest subterm of each version
+/// containing every difference.
+#[derive(Serialize)]
+struct TermChange {
+    old: String,
+    new: String,
+    old_subterm: String,
+    new_subterm: String,
+    #[serde(skip)]
+    old_range: Range<usize>,
+    #[serde(skip)]
+    new_range: Range<usize>,
+}
+
+pub fn run_diff_statements(cmd: DiffStatementsCommand) {
+    // Find watson.toml config file
+    let config_file_path = match &cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // A statement which failed to elaborate would look removed, so the
+    // comparison can't be trusted.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so some theorems may be missing"
+        );
+    }
+
+    let (mut ctx, parse_report) = parsed.into_parts();
+    let current = export_statements(&parse_report);
+
+    let result = diff_against(&cmd, &current);
+    let diff = match result {
+        Ok(diff) => diff,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+
+    let Some(diff) = diff else {
+        return;
+    };
+    match cmd.format {
+        DiffFormat::Human => print!("{}", render_human(&diff)),
+        DiffFormat::Json => println!("{}", serde_json::to_string_pretty(&diff).unwrap()),
+    }
+
+    let has_changes =
+        !diff.added.is_empty() || !diff.removed.is_empty() || !diff.changed.is_empty();
+    if cmd.deny_changes && has_changes {
+        std::process::exit(1);
+    }
+}
+
+/// Save the current statements if asked to and compare them with the old
+/// export if there is one.
+fn diff_against<'ctx>(
+    cmd: &DiffStatementsCommand,
+    current: &StatementExport,
+) -> WResult<'ctx, Option<StatementDiff>> {
+    if cmd.old.is_none() && cmd.save.is_none() {
+        return Diagnostic::err_nothing_to_diff();
+    }
+
+    if let Some(save) = &cmd.save {
+        let json = serde_json::to_string_pretty(current).unwrap();
+        if let Err(err) = fs::write(save, json + "\n") {
+            return Diagnostic::err_statement_export(save, &err.to_string());
+        }
+    }
+
+    let Some(old) = &cmd.old else {
+        return Ok(None);
+    };
+    let old = read_export(old)?;
+    Ok(Some(diff_statements(&old, current)))
+}
+
+fn read_export<'ctx>(path: &Path) -> WResult<'ctx, StatementExport> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) => return Diagnostic::err_statement_export(path, &err.to_string()),
+    };
+    let export: StatementExport = match serde_json::from_str(&text) {
+        Ok(export) => export,
+        Err(err) => return Diagnostic::err_statement_export(path, &err.to_string()),
+    };
+    if export.version != EXPORT_VERSION {
+        let reason = format!(
+            "it has version {} but this version of Watson writes version {EXPORT_VERSION}",
+            export.version
+        );
+        return Diagnostic::err_statement_export(path, &reason);
+    }
+    Ok(export)
+}
+
+/// The statements of the project's theorems, in the order they are declared.
+fn export_statements(parse_report: &ParseReport) -> StatementExport {
+    let theorems = parse_report
+        .entries
+        .iter()
+        .filter_map(|entry| match entry {
+            ParseEntry::Command(tree, _) => parse_report.command_theorems.get(tree),
+            ParseEntry::Text(_) | ParseEntry::RawCommand(_) => None,
+        })
+        .map(|&thm| export_theorem(thm))
+        .collect();
+    StatementExport {
+        version: EXPORT_VERSION,
+        theorems,
+    }
+}
+
+fn export_theorem(thm: TheoremId) -> ExportedTheorem {
+    let templates = thm
+        .templates()
+        .iter()
+        .map(|template| {
+            let (binding, cat) = template_parts(template);
+            ExportedTemplate {
+                text: format!("{binding} : {cat}"),
+                hash: ContentHash::of_template(template).to_string(),
+            }
+        })
+        .collect();
+    ExportedTheorem {
+        name: thm.name().to_string(),
+        kind: thm.kind().keyword().to_string(),
+        hash: thm.content_hash().to_string(),
+        templates,
+        hypotheses: thm.hypotheses().iter().map(fact_term).collect(),
+        conclusion: frag_term(thm.conclusion()),
+    }
+}
+
+fn fact_term(fact: &PresFact) -> Term {
+    let head = match fact.assumption() {
+        Some(_) => "assume",
+        None => "fact",
+    };
+    Term {
+        head: head.to_string(),
+        text: fact.pretty(),
+        hash: None,
+        children: fact
+            .assumption()
+            .into_iter()
+            .chain([fact.conclusion()])
+            .map(frag_term)
+            .collect(),
+    }
+}
+
+fn frag_term(frag: PresFrag) -> Term {
+    let pres = frag.pres();
+    let head = match pres.head() {
+        PresHead::Notation { binding, .. } => binding.print(),
+        PresHead::FormalFrag(FragHead::RuleApplication(app)) => app.rule().name().to_string(),
+        PresHead::FormalFrag(FragHead::Var(idx)) => format!("'{idx}"),
+        PresHead::FormalFrag(FragHead::TemplateRef(idx)) => format!("${idx}"),
+        PresHead::FormalFrag(FragHead::Hole(idx)) => format!("_{idx}"),
+        PresHead::FormalFrag(FragHead::Abbreviation(abbreviation)) => {
+            abbreviation.name().to_string()
+        }
+    };
+    Term {
+        head,
+        text: frag.pretty(),
+        hash: Some(ContentHash::of_frag(frag.frag()).to_string()),
+        children: pres
+            .children()
+            .iter()
+            .map(|&child| frag_term(child))
+            .collect(),
+    }
+}
+
+/// Match the theorems up by name, or by content hash for those whose name is
+/// only in one of the exports, and compare the statements of each pair.
+fn diff_statements(old: &StatementExport, new: &StatementExport) -> StatementDiff {
+    let old_by_name: FxHashMap<_, _> = old.theorems.iter().map(|t| (&t.name, t)).collect();
+    let new_names: FxHashSet<_> = new.theorems.iter().map(|t| &t.name).collect();
+
+    let mut diff = StatementDiff {
+        added: Vec::new(),
+        removed: Vec::new(),
+        renamed: Vec::new(),
+        changed: Vec::new(),
+        unchanged: 0,
+    };
+
+    let mut gone: Vec<_> = old
+        .theorems
+        .iter()
+        .filter(|thm| !new_names.contains(&thm.name))
+        .collect();
+    for thm in &new.theorems {
+        match old_by_name.get(&thm.name) {
+            Some(old) if old.hash == thm.hash => diff.unchanged += 1,
+            Some(old) => diff.changed.push(Changed {
+                name: thm.name.clone(),
+                changes: diff_theorem(old, thm),
+            }),
+            None => match gone.iter().position(|old| old.hash == thm.hash) {
+                Some(idx) => diff.renamed.push(Renamed {
+                    old: gone.remove(idx).name.clone(),
+                    new: thm.name.clone(),
+                }),
+                None => diff.added.push(thm.name.clone()),
+            },
+        }
+    }
+    diff.removed = gone.into_iter().map(|thm| thm.name.clone()).collect();
+
+    diff
+}
+
+fn diff_theorem(old: &ExportedTheorem, new: &ExportedTheorem) -> Vec<PartChange> {
+    let mut changes = Vec::new();
+    if old.templates != new.templates {
+        let texts =
+            |templates: &[ExportedTemplate]| templates.iter().map(|t| t.text.clone()).collect();
+        changes.push(PartChange::Templates {
+            old: texts(&old.templates),
+            new: texts(&new.templates),
+        });
+    }
+    changes.extend(diff_hypotheses(&old.hypotheses, &new.hypotheses));
+    if old.conclusion != new.conclusion {
+        changes.push(PartChange::Conclusion {
+            change: term_change(&old.conclusion, &new.conclusion),
+        });
+    }
+    changes
+}
+
+/// Line up the hypotheses which stayed the same. Between those, hypotheses
+/// which were removed and added in the same place are paired up as modified.
+fn diff_hypotheses(old: &[Term], new: &[Term]) -> Vec<PartChange> {
+    let mut changes = Vec::new();
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    for edit in diff(old, new) {
+        match edit {
+            Edit::Keep(..) => pair_up(&mut removed, &mut added, old, new, &mut changes),
+            Edit::Delete(i) => removed.push(i),
+            Edit::Insert(j) => added.push(j),
+        }
+    }
+    pair_up(&mut removed, &mut added, old, new, &mut changes);
+
+    changes
+}
+
+fn pair_up(
+    removed: &mut Vec<usize>,
+    added: &mut Vec<usize>,
+    old: &[Term],
+    new: &[Term],
+    changes: &mut Vec<PartChange>,
+) {
+    let paired = removed.len().min(added.len());
+    for (&i, &j) in removed.iter().zip(added.iter()) {
+        changes.push(PartChange::HypothesisModified {
+            old_index: i + 1,
+            new_index: j + 1,
+            change: term_change(&old[i], &new[j]),
+        });
+    }
+    for &i in &removed[paired..] {
+        changes.push(PartChange::HypothesisRemoved {
+            index: i + 1,
+            old: old[i].text.clone(),
+        });
+    }
+    for &j in &added[paired..] {
+        changes.push(PartChange::HypothesisAdded {
+            index: j + 1,
+            new: new[j].text.clone(),
+        });
+    }
+    removed.clear();
+    added.clear();
+}
+
+fn term_change(old: &Term, new: &Term) -> TermChange {
+    let path = differing_path(old, new);
+    let old_range = subterm_range(old, &path);
+    let new_range = subterm_range(new, &path);
+    TermChange {
+        old: old.text.clone(),
+        new: new.text.clone(),
+        old_subterm: old.text[old_range.clone()].to_string(),
+        new_subterm: new.text[new_range.clone()].to_string(),
+        old_range,
+        new_range,
+    }
+}
+
+/// The path of child indices to the smallest subterm containing every
+/// difference between `old` and `new`, like
+/// [`crate::semant::presentation::differing_subterm`] but for exported terms.
+fn differing_path(old: &Term, new: &Term) -> Vec<usize> {
+    let mut path = Vec::new();
+    let (mut old, mut new) = (old, new);
+    while old.head == new.head && old.children.len() == new.children.len() {
+        let mut differing = old
+            .children
+            .iter()
+            .zip(&new.children)
+            .enumerate()
+            .filter(|(_, (o, n))| o != n);
+
+        match (differing.next(), differing.next()) {
+            (Some((idx, (o, n))), None) => {
+                path.push(idx);
+                old = o;
+                new = n;
+            }
+            _ => break,
+        }
+    }
+    path
+}
+
+/// Where the subterm at `path` is written in the text of `term`. Each child
+/// is looked for in its parent's text after its earlier siblings, so the
+/// place is only a guess when the printer reorders children.
+fn subterm_range(term: &Term, path: &[usize]) -> Range<usize> {
+    let mut range = 0..term.text.len();
+    let mut node = term;
+    for &idx in path {
+        let mut cursor = range.start;
+        let mut found = None;
+        for child in &node.children[..=idx] {
+            let Some(offset) = term.text[cursor..range.end].find(&child.text) else {
+                return range;
+            };
+            let start = cursor + offset;
+            found = Some(start..start + child.text.len());
+            cursor = start + child.text.len();
+        }
+        range = found.unwrap();
+        node = &node.children[idx];
+    }
+    range
+}
+
+fn render_human(diff: &StatementDiff) -> String {
+    let mut out = String::new();
+
+    let mut section = |title: &str, lines: Vec<String>| {
+        if lines.is_empty() {
+            return;
+        }
+        writeln!(out, "{ANSI_BOLD}{title}{ANSI_RESET} ({})", lines.len()).unwrap();
+        for line in lines {
+            writeln!(out, "  {line}").unwrap();
+        }
+        writeln!(out).unwrap();
+    };
+    section(
+        "added",
+        diff.added
+            .iter()
+            .map(|name| format!("{ANSI_GREEN}+{ANSI_RESET} {name}"))
+            .collect(),
+    );
+    section(
+        "removed",
+        diff.removed
+            .iter()
+            .map(|name| format!("{ANSI_RED}-{ANSI_RESET} {name}"))
+            .collect(),
+    );
+    section(
+        "renamed",
+        diff.renamed
+            .iter()
+            .map(|renamed| format!("{} -> {}", renamed.old, renamed.new))
+            .collect(),
+    );
+    section(
+        "changed",
+        diff.changed
+            .iter()
+            .map(|changed| {
+                let mut lines = changed.name.clone();
+                for change in &changed.changes {
+                    lines += "\n";
+                    lines += &render_part_change(change);
+                }
+                lines
+            })
+            .collect(),
+    );
+
+    let differences =
+        diff.added.len() + diff.removed.len() + diff.renamed.len() + diff.changed.len();
+    if differences == 0 {
+        write!(out, "no statements changed, ").unwrap();
+    }
+    writeln!(
+        out,
+        "{} statement{} unchanged",
+        diff.unchanged,
+        plural(diff.unchanged)
+    )
+    .unwrap();
+    out
+}
+
+fn render_part_change(change: &PartChange) -> String {
+    match change {
+        PartChange::Templates { old, new } => {
+            let mut out = format!(
+                "    templates changed:\n      {ANSI_RED}-{ANSI_RESET} [{}]\n      {ANSI_GREEN}+{ANSI_RESET} [{}]",
+                old.join(", "),
+                new.join(", ")
+            );
+            if old == new {
+                out += SAME_TEXT_NOTE;
+            }
+            out
+        }
+        PartChange::HypothesisAdded { index, new } => {
+            format!("    hypothesis {index} added: {new}")
+        }
+        PartChange::HypothesisRemoved { index, old } => {
+            format!("    hypothesis {index} removed: {old}")
+        }
+        PartChange::HypothesisModified {
+            old_index,
+            new_index,
+            change,
+        } => {
+            let title = if old_index == new_index {
+                format!("hypothesis {new_index}")
+            } else {
+                format!("hypothesis {old_index} (now {new_index})")
+            };
+            format!("    {title} changed:\n{}", render_term_change(change))
+        }
+        PartChange::Conclusion { change } => {
+            format!("    conclusion changed:\n{}", render_term_change(change))
+        }
+    }
+}
+
+/// Shown under a change which is written the same in both versions, so
+/// something it refers to changed.
+const SAME_TEXT_NOTE: &str =
+    "\n      written the same, but a definition or syntax rule it uses changed";
+
+/// Both versions with the part that differs in bold.
+fn render_term_change(change: &TermChange) -> String {
+    let highlight = |text: &str, range: &Range<usize>, color: &str| {
+        format!(
+            "{color}{}{ANSI_BOLD}{}{ANSI_RESET}{color}{}{ANSI_RESET}",
+            &text[..range.start],
+            &text[range.clone()],
+            &text[range.end..]
+        )
+    };
+    let mut out = format!(
+        "      {ANSI_RED}-{ANSI_RESET} {}\n      {ANSI_GREEN}+{ANSI_RESET} {}",
+        highlight(&change.old, &change.old_range, ANSI_RED),
+        highlight(&change.new, &change.new_range, ANSI_GREEN)
+    );
+    if change.old == change.new {
+        out += SAME_TEXT_NOTE;
+    }
+    out
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_nothing_to_diff<T>() -> WResult<'ctx, T> {
+        let diag = Diagnostic::new("nothing to compare the statements with", vec![]).with_info(
+            "give an export to compare with, or write one with `--save`",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_statement_export<T>(path: &Path, reason: &str) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("can't use the statement export `{}`", path.display()),
+            vec![],
+        )
+        .with_info(reason, Vec::new());
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_project::TestProject;
+
+    const OLD: &str = "module logic
+
+theorem same [p : sentence] :
+    |- p → p
+proof
+qed
+
+theorem old_name [p q : sentence] : (p)
+    |- q → p
+proof
+qed
+
+theorem dropped [p : sentence] : (p)
+    |- p
+proof
+qed
+
+theorem hyps [p q : sentence] : (p) (q)
+    |- p
+proof
+qed
+
+theorem concl [p q : sentence] :
+    |- p → q
+proof
+qed
+";
+
+    const NEW: &str = "module logic
+
+theorem same [p : sentence] :
+    |- p → p
+proof
+qed
+
+theorem new_name [p q : sentence] : (p)
+    |- q → p
+proof
+qed
+
+theorem fresh [p : sentence] :
+    |- ¬¬p → p
+proof
+qed
+
+theorem hyps [p q : sentence] : (q) (p → q)
+    |- p
+proof
+qed
+
+theorem concl [p q : sentence] :
+    |- p → ¬q
+proof
+qed
+";
+
+    fn export(main: &str) -> StatementExport {
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let (ctx, parse_report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        assert!(!ctx.diags.has_errors());
+        export_statements(&parse_report)
+    }
+
+    fn changes<'a>(diff: &'a StatementDiff, name: &str) -> &'a [PartChange] {
+        let changed = diff.changed.iter().find(|changed| changed.name == name);
+        &changed.unwrap().changes
+    }
+
+    #[test]
+    fn each_kind_of_difference_is_found() {
+        let diff = diff_statements(&export(OLD), &export(NEW));
+        assert_eq!(diff.added, ["fresh"]);
+        assert_eq!(diff.removed, ["dropped"]);
+        assert_eq!(diff.renamed.len(), 1);
+        assert_eq!(
+            (diff.renamed[0].old.as_str(), diff.renamed[0].new.as_str()),
+            ("old_name", "new_name")
+        );
+        let changed: Vec<_> = diff.changed.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(changed, ["hyps", "concl"]);
+
+        assert!(matches!(
+            changes(&diff, "hyps"),
+            [
+                PartChange::HypothesisRemoved { index: 1, .. },
+                PartChange::HypothesisAdded { index: 2, .. },
+            ]
+        ));
+        let [PartChange::Conclusion { change }] = changes(&diff, "concl") else {
+            panic!("expected only the conclusion to change");
+        };
+        assert_eq!(
+            (change.old_subterm.as_str(), change.new_subterm.as_str()),
+            ("q", "¬q")
+        );
+    }
+
+    #[test]
+    fn an_export_matches_itself() {
+        let diff = diff_statements(&export(OLD), &export(OLD));
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert!(diff.renamed.is_empty() && diff.changed.is_empty());
+    }
+
+    #[test]
+    fn a_change_written_the_same_is_shown() {
+        let term = |hash: &str| Term {
+            head: "not".to_string(),
+            text: "¬p".to_string(),
+            hash: Some(hash.to_string()),
+            children: Vec::new(),
+        };
+        let theorem = |hash: &str| ExportedTheorem {
+            name: "t".to_string(),
+            kind: "theorem".to_string(),
+            hash: hash.to_string(),
+            templates: Vec::new(),
+            hypotheses: Vec::new(),
+            conclusion: term(hash),
+        };
+        let export = |hash: &str| StatementExport {
+            version: EXPORT_VERSION,
+            theorems: vec![theorem(hash)],
+        };
+
+        let diff = diff_statements(&export("1"), &export("2"));
+        let [PartChange::Conclusion { change }] = changes(&diff, "t") else {
+            panic!("expected the conclusion to change");
+        };
+        assert_eq!(change.old, change.new);
+        assert!(render_human(&diff).contains(SAME_TEXT_NOTE));
+    }
+}
@@ -1,13 +1,29 @@
 use crate::cli::{
     book_command::{BookCommand, run_book},
     check_command::{CheckCommand, run_check},
+    diff_statements_command::{DiffStatementsCommand, run_diff_statements},
+    modules_command::{ModulesCommand, run_modules},
     new_command::{NewCommand, run_new},
+    new_tactic_command::{NewTacticCommand, run_new_tactic},
+    print_command::{PrintCommand, run_print},
+    rename_command::{RenameTheoremCommand, run_rename_theorem},
+    repl_command::{ReplCommand, run_repl},
+    search_command::{SearchCommand, run_search},
+    uses_command::{UsesCommand, run_uses},
 };
 use argh::FromArgs;
 
 mod book_command;
 mod check_command;
+mod diff_statements_command;
+mod modules_command;
 mod new_command;
+mod new_tactic_command;
+mod print_command;
+mod rename_command;
+mod repl_command;
+mod search_command;
+mod uses_command;
 
 /// The Watson proof assistant.
 #[derive(FromArgs)]
@@ -22,6 +38,14 @@ enum Command {
     New(NewCommand),
     Check(CheckCommand),
     Book(BookCommand),
+    RenameTheorem(RenameTheoremCommand),
+    Uses(UsesCommand),
+    Search(SearchCommand),
+    Modules(ModulesCommand),
+    Repl(ReplCommand),
+    Print(PrintCommand),
+    DiffStatements(DiffStatementsCommand),
+    NewTactic(NewTacticCommand),
 }
 
 pub fn run_cli() {
@@ -31,5 +55,13 @@ pub fn run_cli() {
         Command::New(cmd) => run_new(cmd),
         Command::Check(cmd) => run_check(cmd),
         Command::Book(cmd) => run_book(cmd),
+        Command::RenameTheorem(cmd) => run_rename_theorem(cmd),
+        Command::Uses(cmd) => run_uses(cmd),
+        Command::Search(cmd) => run_search(cmd),
+        Command::Modules(cmd) => run_modules(cmd),
+        Command::Repl(cmd) => run_repl(cmd),
+        Command::Print(cmd) => run_print(cmd),
+        Command::DiffStatements(cmd) => run_diff_statements(cmd),
+        Command::NewTactic(cmd) => run_new_tactic(cmd),
     }
 }
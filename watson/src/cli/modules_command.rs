@@ -0,0 +1,285 @@
+use crate::{
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::print_diagnostics,
+    driver::Driver,
+    parse::{ParseEntry, ParseReport, SourceId, module_graph::ModuleImport},
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_RESET, ANSI_YELLOW},
+        plural,
+    },
+};
+use argh::FromArgs;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use std::{fmt::Write, path::PathBuf, str::FromStr};
+
+/// Show which modules each source loads.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "modules")]
+pub struct ModulesCommand {
+    /// print the import graph as `dot` or `json` instead of a tree.
+    #[argh(option)]
+    graph: Option<GraphFormat>,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Self::Dot),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("expected `dot` or `json`, found `{s}`")),
+        }
+    }
+}
+
+/// What a single source contains.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+struct ModuleStats {
+    bytes: usize,
+    commands: usize,
+    theorems: usize,
+    prose_lines: usize,
+}
+
+#[derive(Serialize)]
+struct JsonGraph {
+    modules: Vec<JsonModule>,
+    imports: Vec<JsonImport>,
+}
+
+#[derive(Serialize)]
+struct JsonModule {
+    name: String,
+    root: bool,
+    #[serde(flatten)]
+    stats: ModuleStats,
+}
+
+#[derive(Serialize)]
+struct JsonImport {
+    from: String,
+    to: String,
+    line: u32,
+    /// Whether this import loaded the module rather than reusing it.
+    loaded: bool,
+}
+
+pub fn run_modules(cmd: ModulesCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // A cycle or missing module is reported as an error but the modules
+    // which did load can still be shown.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so some modules may be missing"
+        );
+    }
+
+    let (ctx, parse_report) = parsed.into_parts();
+    let stats = module_stats(&parse_report, &ctx);
+    let roots: Vec<_> = parse_report.roots.iter().map(|root| root.source).collect();
+
+    match cmd.graph {
+        None => print!("{}", render_tree(&roots, &stats, &ctx)),
+        Some(GraphFormat::Dot) => print!("{}", render_dot(&roots, &ctx)),
+        Some(GraphFormat::Json) => println!("{}", render_json(&roots, &stats, &ctx)),
+    }
+}
+
+fn module_stats<'ctx>(
+    parse_report: &ParseReport<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> FxHashMap<SourceId, ModuleStats> {
+    let mut stats: FxHashMap<SourceId, ModuleStats> = FxHashMap::default();
+
+    for entry in &parse_report.entries {
+        match entry {
+            ParseEntry::Command(tree, _) => {
+                stats.entry(tree.span().source()).or_default().commands += 1
+            }
+            ParseEntry::RawCommand(span) => stats.entry(span.source()).or_default().commands += 1,
+            ParseEntry::Text(span) => {
+                let text = &ctx.sources.get_text(span.source()).as_str()[span.bytes()];
+                let lines = text.lines().filter(|line| !line.trim().is_empty()).count();
+                stats.entry(span.source()).or_default().prose_lines += lines;
+            }
+        }
+    }
+
+    for root in &parse_report.roots {
+        for (theorem, _) in &root.theorems {
+            stats
+                .entry(theorem.name_span().source())
+                .or_default()
+                .theorems += 1;
+        }
+    }
+
+    for (source, stats) in &mut stats {
+        stats.bytes = ctx.sources.get_text(*source).len();
+    }
+
+    stats
+}
+
+/// The roots followed by the modules they load, in the order they were
+/// parsed.
+fn sources_in_order(roots: &[SourceId], ctx: &Ctx) -> Vec<SourceId> {
+    fn visit(source: SourceId, ctx: &Ctx, out: &mut Vec<SourceId>) {
+        out.push(source);
+        for import in ctx.module_graph.imports_of(source) {
+            if import.loaded {
+                visit(import.imported, ctx, out);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    for &root in roots {
+        visit(root, ctx, &mut out);
+    }
+    out
+}
+
+fn render_tree(roots: &[SourceId], stats: &FxHashMap<SourceId, ModuleStats>, ctx: &Ctx) -> String {
+    fn describe(source: SourceId, stats: &FxHashMap<SourceId, ModuleStats>) -> String {
+        let stats = stats.get(&source).copied().unwrap_or_default();
+        format!(
+            "{ANSI_BOLD}{}{ANSI_RESET} {ANSI_GRAY}{}, {} command{}, {} theorem{}, {} line{} of prose{ANSI_RESET}",
+            source.name(),
+            format_size(stats.bytes),
+            stats.commands,
+            plural(stats.commands),
+            stats.theorems,
+            plural(stats.theorems),
+            stats.prose_lines,
+            plural(stats.prose_lines),
+        )
+    }
+
+    fn visit(
+        source: SourceId,
+        prefix: &str,
+        stats: &FxHashMap<SourceId, ModuleStats>,
+        ctx: &Ctx,
+        out: &mut String,
+    ) {
+        let imports: Vec<&ModuleImport> = ctx.module_graph.imports_of(source).collect();
+        for (i, import) in imports.iter().enumerate() {
+            let last = i + 1 == imports.len();
+            let branch = if last { "└── " } else { "├── " };
+            if import.loaded {
+                writeln!(out, "{prefix}{branch}{}", describe(import.imported, stats)).unwrap();
+                let prefix = format!("{prefix}{}", if last { "    " } else { "│   " });
+                visit(import.imported, &prefix, stats, ctx, out);
+            } else {
+                // Modules are only listed in full under the source which
+                // loaded them.
+                let note = match ctx.module_graph.loaded_by(import.imported) {
+                    Some(loader) => format!("loaded by `{}`", loader.importer.name()),
+                    None => "a root".to_string(),
+                };
+                writeln!(
+                    out,
+                    "{prefix}{branch}{} {ANSI_GRAY}({note}){ANSI_RESET}",
+                    import.imported.name()
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    let mut out = String::new();
+    for &root in roots {
+        writeln!(out, "{}", describe(root, stats)).unwrap();
+        visit(root, "", stats, ctx, &mut out);
+    }
+    out
+}
+
+fn render_dot(roots: &[SourceId], ctx: &Ctx) -> String {
+    let mut out = String::from("digraph modules {\n");
+    for source in sources_in_order(roots, ctx) {
+        let shape = if roots.contains(&source) {
+            "box"
+        } else {
+            "ellipse"
+        };
+        writeln!(out, "    \"{}\" [shape={shape}];", source.name()).unwrap();
+    }
+    // Imports of modules which were already loaded are dashed.
+    for import in ctx.module_graph.imports() {
+        let style = if import.loaded { "solid" } else { "dashed" };
+        writeln!(
+            out,
+            "    \"{}\" -> \"{}\" [style={style}];",
+            import.importer.name(),
+            import.imported.name()
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_json(roots: &[SourceId], stats: &FxHashMap<SourceId, ModuleStats>, ctx: &Ctx) -> String {
+    let modules = sources_in_order(roots, ctx)
+        .into_iter()
+        .map(|source| JsonModule {
+            name: source.name().to_string(),
+            root: roots.contains(&source),
+            stats: stats.get(&source).copied().unwrap_or_default(),
+        })
+        .collect();
+    let starts: Vec<_> = ctx
+        .module_graph
+        .imports()
+        .iter()
+        .map(|import| import.span.start())
+        .collect();
+    let imports = ctx
+        .module_graph
+        .imports()
+        .iter()
+        .zip(ctx.sources.line_cols(&starts))
+        .map(|(import, (line, _))| JsonImport {
+            from: import.importer.name().to_string(),
+            to: import.imported.name().to_string(),
+            line,
+            loaded: import.loaded,
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&JsonGraph { modules, imports }).unwrap()
+}
+
+fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{bytes} B")
+    } else {
+        format!("{:.1} KiB", bytes as f64 / 1024.0)
+    }
+}
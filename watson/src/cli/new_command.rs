@@ -1,6 +1,6 @@
 use crate::util::ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RED, ANSI_RESET};
 use argh::FromArgs;
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, str::FromStr};
 
 /// Create a new Watson project.
 #[derive(FromArgs)]
@@ -9,6 +9,83 @@ pub struct NewCommand {
     /// the name of the project to create
     #[argh(positional)]
     name: String,
+
+    /// what the project starts with: minimal (the default) for nothing,
+    /// propositional for a library of propositional logic or fol for one of
+    /// first-order logic, each with tactics and an example theorem.
+    #[argh(option, default = "Template::Minimal")]
+    template: Template,
+}
+
+/// The starting points for a new project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Template {
+    Minimal,
+    Propositional,
+    Fol,
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "minimal" => Ok(Self::Minimal),
+            "propositional" => Ok(Self::Propositional),
+            "fol" => Ok(Self::Fol),
+            _ => Err(format!(
+                "expected `minimal`, `propositional` or `fol`, found `{s}`"
+            )),
+        }
+    }
+}
+
+/// The propositional library, which first-order logic is built on too.
+const LOGIC_WATS: &str = include_str!("new_templates/logic.wats");
+
+impl Template {
+    /// The files written to the math directory, by name. Each has `{{name}}`
+    /// in place of the project's name.
+    fn math_files(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Minimal => &[("main.wats", "\n")],
+            Self::Propositional => &[
+                (
+                    "main.wats",
+                    include_str!("new_templates/propositional/main.wats"),
+                ),
+                ("logic.wats", LOGIC_WATS),
+            ],
+            Self::Fol => &[
+                ("main.wats", include_str!("new_templates/fol/main.wats")),
+                ("fol.wats", include_str!("new_templates/fol/fol.wats")),
+                ("logic.wats", LOGIC_WATS),
+            ],
+        }
+    }
+
+    /// The main Lua module. The libraries come with a handler for the tactics
+    /// they declare.
+    fn main_luau(self) -> &'static str {
+        match self {
+            Self::Minimal => {
+                r#"local M = {}
+    
+function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)
+    local thm = proofState.theorem
+    return proofState:applyTodo(thm.conclusion)
+end
+
+-- Handlers for specific tactic rules can be registered here. Tactics without
+-- a handler in this table fall back to `M.handleTactic`.
+M.tactics = {} :: TacticHandlers
+
+return M
+"#
+            }
+            Self::Propositional | Self::Fol => include_str!("new_templates/main.luau"),
+        }
+    }
 }
 
 pub fn run_new(cmd: NewCommand) {
@@ -41,36 +118,33 @@ fn run(cmd: NewCommand) -> Result<(), std::io::Error> {
         std::process::exit(1);
     });
 
-    // Create watson.toml (empty file)
+    // The project is named after the directory it is created in.
+    let name = project_path
+        .file_name()
+        .map_or(cmd.name.clone(), |name| name.to_string_lossy().into_owned());
+
+    // Create watson.toml with the name as the book's title
     let toml_path = project_path.join("watson.toml");
-    fs::write(&toml_path, "")?;
+    let toml_name = name.replace('\\', "\\\\").replace('"', "\\\"");
+    let toml_content = include_str!("new_templates/watson.toml").replace("{{name}}", &toml_name);
+    fs::write(&toml_path, toml_content)?;
 
     // Create script directory
     let script_path = project_path.join("script");
     fs::create_dir(&script_path)?;
 
-    // Create script/main.luau with default content
+    // Create script/main.luau with the template's handler
     let main_luau_path = script_path.join("main.luau");
-    let main_luau_content = r#"local M = {}
-    
-function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)
-    local thm = proofState.theorem
-    return proofState:applyTodo(thm.conclusion)
-end
-
-return M
-"#;
-    fs::write(&main_luau_path, main_luau_content)?;
+    fs::write(&main_luau_path, cmd.template.main_luau())?;
 
     // Create math directory
     let math_path = project_path.join("math");
     fs::create_dir(&math_path)?;
 
-    // Create math/main.wats with default content
-    let main_wats_path = math_path.join("main.wats");
-    let main_wats_content = r#"
-"#;
-    fs::write(&main_wats_path, main_wats_content)?;
+    // Create math/main.wats and the rest of the template's library
+    for (file, content) in cmd.template.math_files() {
+        fs::write(math_path.join(file), content.replace("{{name}}", &name))?;
+    }
 
     // Create .vscode directory
     let vscode_path = project_path.join(".vscode");
@@ -117,3 +191,45 @@ return M
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Template;
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// Check a project with the files of `template`, returning the rendered
+    /// diagnostics and the names of the theorems which aren't correct.
+    fn check_template(template: Template) -> (Vec<String>, Vec<String>) {
+        let project = TestProject::new("");
+        project.write("script/main.luau", template.main_luau());
+        for (file, content) in template.math_files() {
+            let content = content.replace("{{name}}", "example");
+            project.write(&format!("math/{file}"), &content);
+        }
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let ctx = checked.ctx();
+        let diags = ctx
+            .diags
+            .iter()
+            .map(|diag| diag.render_plain(ctx))
+            .collect();
+        let incorrect = (checked.proof_report().statuses.iter())
+            .filter(|(_, status)| !status.correct())
+            .map(|(thm, _)| thm.name().to_string())
+            .collect();
+        (diags, incorrect)
+    }
+
+    #[test]
+    fn templates_check_cleanly() {
+        for template in [Template::Minimal, Template::Propositional, Template::Fol] {
+            let (diags, incorrect) = check_template(template);
+            assert_eq!(diags, Vec::<String>::new(), "{template:?}");
+            assert_eq!(incorrect, Vec::<String>::new(), "{template:?}");
+        }
+    }
+}
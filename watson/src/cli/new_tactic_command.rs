@@ -0,0 +1,392 @@
+use crate::{
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::{Diagnostic, DiagnosticSpan, WResult, print_diagnostics},
+    driver::Driver,
+    parse::{
+        SourceId, Span, check_command,
+        elaborator::ElaborateAction,
+        source_cache::{SourceDecl, source_id_to_path},
+    },
+    semant::{
+        check_proofs::{rule_luau_fields, rule_luau_type},
+        custom_grammar::syntax::CustomGrammarRuleId,
+        scope::Scope,
+    },
+    strings,
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RESET, ANSI_YELLOW},
+        name_to_lua,
+    },
+};
+use argh::FromArgs;
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+use ustr::Ustr;
+
+/// Add a tactic rule to a tactic category along with a stub Lua handler for
+/// it.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "new-tactic")]
+pub struct NewTacticCommand {
+    /// the tactic category the rule belongs to.
+    #[argh(positional)]
+    category: String,
+
+    /// the name of the new rule.
+    #[argh(positional)]
+    rule: String,
+
+    /// the pattern of the rule, written as in a `tactic` command.
+    #[argh(option, default = "String::new()")]
+    parts: String,
+
+    /// the source file to add the rule to. Defaults to the file declaring
+    /// the category.
+    #[argh(option)]
+    file: Option<PathBuf>,
+
+    /// write the handler to a new module in the script directory with this
+    /// name rather than to the main module.
+    #[argh(option)]
+    module: Option<String>,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+pub fn run_new_tactic(cmd: NewTacticCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(ref file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // The rule is checked against what did elaborate, so a broken project
+    // only matters if the rule uses something that failed.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so the new rule may not be checked fully"
+        );
+    }
+
+    let (mut ctx, parse_report) = parsed.into_parts();
+    let project_scope = parse_report
+        .roots
+        .iter()
+        .fold(Scope::new(), |scope, root| scope.with_imported(&root.scope));
+
+    let (wats, handler) = match add_rule(&cmd, &project_scope, &mut ctx) {
+        Ok(written) => written,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+
+    println!(
+        "{ANSI_GREEN}{ANSI_BOLD}added{ANSI_RESET} tactic `{}` to {}",
+        cmd.rule,
+        wats.display()
+    );
+    match &handler {
+        Handler::Main { path, .. } => println!(
+            "{ANSI_GREEN}{ANSI_BOLD}added{ANSI_RESET} its handler to {}",
+            path.display()
+        ),
+        Handler::Module { path, hint, .. } => {
+            println!(
+                "{ANSI_GREEN}{ANSI_BOLD}created{ANSI_RESET} its handler in {}",
+                path.display()
+            );
+            println!("register it in the main module with\n\n    {hint}\n");
+        }
+    }
+    if !is_tactic_cat(&cmd.category) {
+        println!(
+            "rules of `{}` are only run on their own in proofs stated `using` it, so otherwise call the handler from the handler of a tactic which uses the category",
+            cmd.category
+        );
+    }
+    println!("run `watson check` to update the Luau types for the new rule");
+}
+
+/// Add the rule and its handler to the project, returning the file the rule
+/// went in and the handler. Nothing is written until both the rule and the
+/// handler are known to fit where they go.
+fn add_rule<'ctx>(
+    cmd: &NewTacticCommand,
+    scope: &Scope<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, (PathBuf, Handler)> {
+    let (command, rule) = check_rule(cmd, scope, ctx)?;
+    let wats = rule_file(cmd, rule, ctx)?;
+    let handler = plan_handler(cmd, rule, ctx)?;
+    write_files(&wats, &command, &handler)?;
+    Ok((wats, handler))
+}
+
+/// The `tactic` command for the rule, checked as if it were in the project,
+/// and the rule it declares.
+fn check_rule<'ctx>(
+    cmd: &NewTacticCommand,
+    scope: &Scope<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, (String, CustomGrammarRuleId<'ctx>)> {
+    let pattern = format!("{} ::= {}", cmd.category, cmd.parts.trim());
+    let command = format!("tactic {}\n    {}\nend\n", cmd.rule, pattern.trim_end());
+
+    let source = SourceId::new_snippet();
+    ctx.sources
+        .add(source, command.clone(), SourceDecl::Generated);
+    let (tree, action) = check_command(source, scope, ctx)?;
+
+    // The parts could close the command early and start another one, which
+    // would then be written to the file unchecked.
+    let end = command.trim_end().len();
+    if tree.span().end().byte_offset() < end {
+        let rest = Span::new(tree.span().end(), source.start_loc().forward(end));
+        return Diagnostic::err_parts_end_tactic(rest);
+    }
+
+    let ElaborateAction::NewGrammarRule(rule) = action else {
+        unreachable!("a `tactic` command declares a grammar rule");
+    };
+    Ok((command, rule))
+}
+
+/// The source file the rule is added to: the one given or else the one
+/// declaring its category.
+fn rule_file<'ctx>(
+    cmd: &NewTacticCommand,
+    rule: CustomGrammarRuleId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, PathBuf> {
+    if let Some(file) = &cmd.file {
+        return Ok(file.clone());
+    }
+
+    let Some(decl) = rule.cat().provenance().span() else {
+        return Diagnostic::err_new_tactic_builtin_cat(rule.cat().name());
+    };
+    let (standalone, dir) = source_id_to_path(decl.source(), ctx.config.math_dir());
+    if standalone.exists() {
+        Ok(standalone)
+    } else {
+        Ok(dir)
+    }
+}
+
+/// Where the handler goes and what is written there.
+enum Handler {
+    /// Inserted into the main module before it returns its module table.
+    Main { path: PathBuf, text: String },
+    /// A new module, which the main module has to be told about.
+    Module {
+        path: PathBuf,
+        text: String,
+        hint: String,
+    },
+}
+
+fn plan_handler<'ctx>(
+    cmd: &NewTacticCommand,
+    rule: CustomGrammarRuleId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, Handler> {
+    let fn_name = format!("handle{}", name_to_lua(&rule.name()));
+
+    if let Some(module) = &cmd.module {
+        let mut path = ctx.config.lua_dir().join(module);
+        path.set_extension("luau");
+        if path.exists() {
+            return Diagnostic::err_new_tactic_file(&path, "it already exists");
+        }
+
+        let mut text = String::from("local M = {}\n\n");
+        write_handler(&mut text, &format!("function M.{fn_name}"), rule);
+        text.push_str("\nreturn M\n");
+        let hint = if is_tactic_cat(&cmd.category) {
+            format!(
+                "M.tactics[\"{}\"] = require(\"./{module}\").{fn_name}",
+                rule.name()
+            )
+        } else {
+            format!("local {module} = require(\"./{module}\")")
+        };
+        return Ok(Handler::Module { path, text, hint });
+    }
+
+    let Some(entry) = ctx.config.lua_entry() else {
+        return Diagnostic::err_new_tactic_no_lua_entry();
+    };
+    let main = match fs::read_to_string(entry) {
+        Ok(main) => main,
+        Err(err) => return Diagnostic::err_new_tactic_file(entry, &err.to_string()),
+    };
+    let Some((at, module)) = module_return(&main) else {
+        return Diagnostic::err_new_tactic_file(
+            entry,
+            "it doesn't end by returning its module table",
+        );
+    };
+
+    let mut handler = String::new();
+    if is_tactic_cat(&cmd.category) {
+        let target = format!("{module}.tactics[\"{}\"] = function", rule.name());
+        write_handler(&mut handler, &target, rule);
+    } else {
+        write_handler(&mut handler, &format!("function {module}.{fn_name}"), rule);
+    }
+    let text = format!("{}{handler}\n{}", &main[..at], &main[at..]);
+    Ok(Handler::Main {
+        path: entry.to_path_buf(),
+        text,
+    })
+}
+
+/// Write a stub handler for `rule` whose signature matches the generated
+/// types, with a typed local for each labeled part.
+fn write_handler(out: &mut String, target: &str, rule: CustomGrammarRuleId<'_>) {
+    writeln!(
+        out,
+        "{target}(tactic: {}, proofState: ProofState, tacticInfo: TacticInfo): ProofState",
+        rule_luau_type(rule)
+    )
+    .unwrap();
+    for (label, luau_type) in rule_luau_fields(rule) {
+        writeln!(out, "    local {label}: {luau_type} = tactic.{label}").unwrap();
+    }
+    writeln!(out, "    return proofState:applyTodo(proofState.goal)").unwrap();
+    writeln!(out, "end").unwrap();
+}
+
+/// The offset of the last `return` of the module and the name of the table
+/// it returns.
+fn module_return(main: &str) -> Option<(usize, &str)> {
+    let at = if main.starts_with("return ") {
+        0
+    } else {
+        main.rfind("\nreturn ")? + 1
+    };
+    let module = main[at..].strip_prefix("return ")?.lines().next()?.trim();
+    let is_name = !module.is_empty()
+        && module
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_');
+    is_name.then_some((at, module))
+}
+
+fn write_files<'ctx>(wats: &Path, command: &str, handler: &Handler) -> WResult<'ctx, ()> {
+    let mut text = match fs::read_to_string(wats) {
+        Ok(text) => text,
+        Err(err) => return Diagnostic::err_new_tactic_file(wats, &err.to_string()),
+    };
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text.push('\n');
+    text.push_str(command);
+    if let Err(err) = fs::write(wats, text) {
+        return Diagnostic::err_new_tactic_file(wats, &err.to_string());
+    }
+
+    let (Handler::Main { path, text } | Handler::Module { path, text, .. }) = handler;
+    if let Err(err) = fs::write(path, text) {
+        return Diagnostic::err_new_tactic_file(path, &err.to_string());
+    }
+    Ok(())
+}
+
+fn is_tactic_cat(category: &str) -> bool {
+    category == strings::TACTIC.as_str()
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_parts_end_tactic<T>(rest: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "the parts end the `tactic` command early",
+            vec![DiagnosticSpan::new_error("not part of the rule", rest)],
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_new_tactic_builtin_cat<T>(cat: Ustr) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("the category `{cat}` is built in, so no file declares it"),
+            vec![],
+        )
+        .with_info(
+            "choose the file to add the rule to with `--file`",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_new_tactic_no_lua_entry<T>() -> WResult<'ctx, T> {
+        let diag = Diagnostic::new("the project has no main Lua module", vec![]).with_info(
+            "write the handler to a new module with `--module`",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_new_tactic_file<T>(path: &Path, reason: &str) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(&format!("can't write to `{}`", path.display()), vec![])
+            .with_info(reason, Vec::new());
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_project::TestProject;
+
+    #[test]
+    fn generated_handler_checks() {
+        let project = TestProject::with_logic("module logic\n");
+        let cmd = NewTacticCommand {
+            category: "tactic".to_string(),
+            rule: "trivially".to_string(),
+            parts: "@kw\"trivially\"".to_string(),
+            file: Some(project.path("math/main.wats")),
+            module: None,
+            config: None,
+        };
+
+        let arenas = Arenas::new();
+        let (mut ctx, parse_report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        let scope = (parse_report.roots.iter())
+            .fold(Scope::new(), |scope, root| scope.with_imported(&root.scope));
+        assert!(add_rule(&cmd, &scope, &mut ctx).is_ok());
+
+        // The stub leaves the goal as a todo, which is enough for a proof.
+        let main = project.read("math/main.wats");
+        let theorem = "\ntheorem refl [p : sentence] :\n    |- p → p\nproof\n    trivially\nqed\n";
+        project.write("math/main.wats", &format!("{main}{theorem}"));
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        assert!(!checked.ctx().diags.has_errors());
+        let (_, status) = (checked.proof_report().statuses.iter())
+            .find(|(thm, _)| thm.name() == "refl")
+            .unwrap();
+        assert!(status.correct());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{
+    book::statement::{MarkdownMath, statement_ansi, statement_markdown},
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::{Diagnostic, WResult, print_diagnostics},
+    driver::Driver,
+    parse::{ParseEntry, ParseReport, SourceId, source_cache::source_id_to_path},
+    semant::theorems::TheoremId,
+    util::{
+        ansi::{ANSI_BOLD, ANSI_RESET, ANSI_YELLOW},
+        similar_names,
+    },
+};
+use argh::FromArgs;
+use rustc_hash::FxHashMap;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use ustr::Ustr;
+
+/// Print the statements of theorems: their templates, hypotheses and
+/// conclusion.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "print")]
+pub struct PrintCommand {
+    /// the names of the theorems to print.
+    #[argh(positional)]
+    names: Vec<String>,
+
+    /// print every theorem and axiom in this source file, in order.
+    #[argh(option)]
+    file: Option<PathBuf>,
+
+    /// how to print the statements: ansi (the default) or markdown.
+    #[argh(option, default = "PrintFormat::Ansi")]
+    format: PrintFormat,
+
+    /// in markdown, write math with symbols beyond ASCII as `$...$` rather
+    /// than as code.
+    #[argh(switch)]
+    dollar_math: bool,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PrintFormat {
+    Ansi,
+    Markdown,
+}
+
+impl FromStr for PrintFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ansi" => Ok(Self::Ansi),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(format!("expected `ansi` or `markdown`, found `{s}`")),
+        }
+    }
+}
+
+pub fn run_print(cmd: PrintCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // Statements which failed to elaborate can't be printed, but the rest
+    // still can.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so some theorems may be missing"
+        );
+    }
+
+    let (mut ctx, parse_report) = parsed.into_parts();
+
+    let theorems = match find_theorems(&cmd.names, cmd.file.as_deref(), &parse_report, &ctx) {
+        Ok(theorems) => theorems,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+
+    let math = if cmd.dollar_math {
+        MarkdownMath::Dollars
+    } else {
+        MarkdownMath::Code
+    };
+    let statements: Vec<_> = theorems
+        .into_iter()
+        .map(|thm| match cmd.format {
+            PrintFormat::Ansi => statement_ansi(thm),
+            PrintFormat::Markdown => statement_markdown(thm, math),
+        })
+        .collect();
+    match cmd.format {
+        PrintFormat::Ansi => println!("{}", statements.join("\n\n")),
+        // Each markdown statement already ends with a newline.
+        PrintFormat::Markdown => print!("{}", statements.join("\n")),
+    }
+}
+
+/// The theorems named in `names` followed by those in `file`. Every unknown
+/// name is reported, not just the first.
+fn find_theorems<'ctx>(
+    names: &[String],
+    file: Option<&Path>,
+    parse_report: &ParseReport<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, Vec<TheoremId<'ctx>>> {
+    if names.is_empty() && file.is_none() {
+        return Diagnostic::err_nothing_to_print();
+    }
+
+    let mut theorems = Vec::new();
+    let mut errors = Vec::new();
+    for name in names {
+        let name = Ustr::from(name);
+        match ctx.arenas.theorem_stmts.get(name) {
+            Some(theorem) => theorems.push(theorem),
+            None => errors
+                .extend(Diagnostic::err_unknown_theorem_to_print::<()>(name, ctx).unwrap_err()),
+        }
+    }
+
+    if let Some(file) = file {
+        match theorems_in_file(file, parse_report, ctx) {
+            Ok(in_file) => theorems.extend(in_file),
+            Err(diags) => errors.extend(diags),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(theorems)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The theorems and axioms declared in the source at `file`, in the order
+/// they are declared.
+fn theorems_in_file<'ctx>(
+    file: &Path,
+    parse_report: &ParseReport<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, Vec<TheoremId<'ctx>>> {
+    let Ok(file) = file.canonicalize() else {
+        return Diagnostic::err_print_file_not_in_project(file);
+    };
+    let mut known: FxHashMap<SourceId, bool> = FxHashMap::default();
+    let mut is_file = |source: SourceId| {
+        *known.entry(source).or_insert_with(|| {
+            let (standalone, dir) = source_id_to_path(source, ctx.config.math_dir());
+            [standalone, dir]
+                .iter()
+                .any(|path| path.canonicalize().is_ok_and(|path| path == file))
+        })
+    };
+
+    let mut loaded = false;
+    let mut theorems = Vec::new();
+    for entry in &parse_report.entries {
+        let (span, tree) = match entry {
+            ParseEntry::Text(span) | ParseEntry::RawCommand(span) => (*span, None),
+            ParseEntry::Command(tree, _) => (tree.span(), Some(tree)),
+        };
+        if !is_file(span.source()) {
+            continue;
+        }
+
+        loaded = true;
+        if let Some(&theorem) = tree.and_then(|tree| parse_report.command_theorems.get(tree)) {
+            theorems.push(theorem);
+        }
+    }
+
+    if !loaded {
+        return Diagnostic::err_print_file_not_in_project(&file);
+    }
+    Ok(theorems)
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_nothing_to_print<T>() -> WResult<'ctx, T> {
+        let diag = Diagnostic::new("nothing to print", vec![]).with_info(
+            "give the names of theorems or a file with `--file`",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_unknown_theorem_to_print<T>(name: Ustr, ctx: &Ctx<'ctx>) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(&format!("no theorem named `{name}` to print"), vec![]);
+
+        let theorems = ctx.arenas.theorem_stmts.names();
+        for candidate in similar_names(&name, theorems) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn err_print_file_not_in_project<T>(file: &Path) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("`{}` isn't a source of the project", file.display()),
+            vec![],
+        );
+        Err(vec![diag])
+    }
+}
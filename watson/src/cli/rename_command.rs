@@ -0,0 +1,324 @@
+use crate::{
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::{Diagnostic, DiagnosticSpan, WResult, print_diagnostics},
+    driver::Driver,
+    parse::{
+        ParseReport, SourceId, Span, earley::parse_name, location::SourceOffset, rewrite::Rewrites,
+        source_cache::source_id_to_path,
+    },
+    semant::tactic::unresolved_proof::UnresolvedProof,
+    strings,
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GREEN, ANSI_RED, ANSI_RESET},
+        diff::DiffOptions,
+        plural, similar_names,
+    },
+};
+use argh::FromArgs;
+use itertools::Itertools;
+use rustc_hash::FxHashSet;
+use std::{
+    fs,
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+use ustr::Ustr;
+
+/// Rename a theorem and every reference to it in proofs.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "rename-theorem")]
+pub struct RenameTheoremCommand {
+    /// the current name of the theorem.
+    #[argh(positional)]
+    old: String,
+
+    /// the new name of the theorem.
+    #[argh(positional)]
+    new: String,
+
+    /// print a diff of the changes instead of writing them.
+    #[argh(switch)]
+    dry_run: bool,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+pub fn run_rename_theorem(cmd: RenameTheoremCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+    let project_dir = config_file_path.parent().unwrap().to_path_buf();
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // The spans of references are only known for commands which elaborated,
+    // so renaming in a project with errors could miss some of them.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        eprintln!(
+            "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} not renaming since the project has errors"
+        );
+        std::process::exit(1);
+    }
+
+    let (mut ctx, parse_report) = parsed.into_parts();
+
+    let old = Ustr::from(&cmd.old);
+    let new = Ustr::from(&cmd.new);
+    let (rewrites, ambiguous) = match find_rewrites(old, new, &parse_report, &ctx) {
+        Ok(found) => found,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+    if !ambiguous.is_empty() {
+        let warning = Diagnostic::warn_ambiguous_theorem_references(old, &ambiguous);
+        print_diagnostics(&[warning], &ctx);
+    }
+
+    let count = rewrites.replacement_count();
+    let rewritten = match rewrites.apply(&ctx.sources) {
+        Ok(rewritten) => rewritten,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+
+    // Colors would get in the way of applying the diff as a patch.
+    let diff_options = DiffOptions {
+        color: io::stdout().is_terminal(),
+        ..DiffOptions::default()
+    };
+    for source in &rewritten {
+        let path = source_path(source.source(), &ctx);
+        if cmd.dry_run {
+            let display_path = path.strip_prefix(&project_dir).unwrap_or(&path);
+            print!(
+                "{}",
+                source.unified_diff(&display_path.display().to_string(), &diff_options)
+            );
+        } else {
+            fs::write(&path, source.new_text()).unwrap_or_else(|e| {
+                eprintln!(
+                    "{ANSI_RED}{ANSI_BOLD}error{ANSI_RESET} writing '{}': {}",
+                    path.display(),
+                    e
+                );
+                std::process::exit(1);
+            });
+        }
+    }
+
+    if !cmd.dry_run {
+        // One of the rewrites is the declaration.
+        let references = count - 1;
+        let files = rewritten.len();
+        println!(
+            "{ANSI_GREEN}{ANSI_BOLD}Renamed{ANSI_RESET} `{old}` to `{new}` and {references} reference{} in {files} file{}",
+            plural(references),
+            plural(files)
+        );
+    }
+}
+
+/// The rewrites renaming `old` to `new`, and the names in proofs which are
+/// spelled `old` but may not refer to the theorem, so were left alone.
+fn find_rewrites<'ctx>(
+    old: Ustr,
+    new: Ustr,
+    parse_report: &ParseReport<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, (Rewrites, Vec<Span>)> {
+    let is_name = parse_name(&new, SourceOffset::new(0))
+        .is_some_and(|(end, _)| end.byte_offset() == new.len());
+    if !is_name || strings::is_reserved_keyword(&new) {
+        return Diagnostic::err_invalid_theorem_name(new);
+    }
+
+    let Some(theorem) = ctx.arenas.theorem_stmts.get(old) else {
+        return Diagnostic::err_unknown_theorem_to_rename(old, ctx);
+    };
+    if let Some(existing) = ctx.arenas.theorem_stmts.get(new) {
+        return Diagnostic::err_rename_target_exists(new, existing.name_span());
+    }
+
+    let mut rewrites = Rewrites::new();
+    rewrites.replace(theorem.name_span(), &new);
+
+    let names = parse_report
+        .roots
+        .iter()
+        .flat_map(|root| &root.theorems)
+        .filter_map(|(_, proof)| match proof {
+            UnresolvedProof::Theorem(tactic) => Some(tactic),
+            _ => None,
+        })
+        .flat_map(|tactic| tactic.names())
+        .collect_vec();
+
+    // The tactic grammar doesn't say which names refer to theorems, so a part
+    // of a rule is taken to refer to them only if every name written for it
+    // in the project is a theorem. Elsewhere, like where a hypothesis is
+    // named, a name spelled like the theorem may mean something else.
+    let other_positions: FxHashSet<_> = names
+        .iter()
+        .filter(|(_, name)| ctx.arenas.theorem_stmts.get(name.str()).is_none())
+        .map(|(position, _)| *position)
+        .collect();
+
+    let mut ambiguous = Vec::new();
+    for (position, name) in names {
+        if name.str() != old {
+            continue;
+        }
+        if other_positions.contains(&position) {
+            ambiguous.push(name.span());
+        } else {
+            rewrites.replace(name.span(), &new);
+        }
+    }
+
+    Ok((rewrites, ambiguous))
+}
+
+fn source_path(source: SourceId, ctx: &Ctx) -> PathBuf {
+    let (standalone, dir) = source_id_to_path(source, ctx.config.math_dir());
+    if standalone.exists() { standalone } else { dir }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_invalid_theorem_name<T>(name: Ustr) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(&format!("`{name}` is not a valid theorem name"), vec![]);
+        let diag = if strings::is_reserved_keyword(&name) {
+            diag.with_info(&format!("`{name}` is a keyword"), Vec::new())
+        } else {
+            diag
+        };
+        Err(vec![diag])
+    }
+
+    pub fn err_unknown_theorem_to_rename<T>(name: Ustr, ctx: &Ctx<'ctx>) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(&format!("no theorem named `{name}` to rename"), vec![]);
+
+        let theorems = ctx.arenas.theorem_stmts.names();
+        for candidate in similar_names(&name, theorems) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn warn_ambiguous_theorem_references(name: Ustr, spans: &[Span]) -> Diagnostic<'ctx> {
+        let spans = spans
+            .iter()
+            .map(|span| DiagnosticSpan::new_warning("", *span))
+            .collect();
+        Diagnostic::new_warning(
+            &format!("not renaming names which may not refer to `{name}`"),
+            spans,
+        )
+        .with_info(
+            "other names written in the same place aren't theorems",
+            Vec::new(),
+        )
+    }
+
+    pub fn err_rename_target_exists<T>(name: Ustr, existing: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("can't rename to `{name}` since a theorem with that name already exists"),
+            vec![DiagnosticSpan::new_error("declared here", existing)],
+        );
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::test_project::TestProject;
+
+    /// Rename `old` to `new` in the project, writing the changes to its
+    /// files. Returns the names which were left alone.
+    fn rename(project: &TestProject, old: &str, new: &str) -> Vec<Span> {
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        assert!(!parsed.ctx().diags.has_errors());
+        let (ctx, parse_report) = parsed.into_parts();
+
+        let (rewrites, ambiguous) =
+            find_rewrites(Ustr::from(old), Ustr::from(new), &parse_report, &ctx).unwrap();
+        for source in rewrites.apply(&ctx.sources).unwrap() {
+            fs::write(source_path(source.source(), &ctx), source.new_text()).unwrap();
+        }
+        ambiguous
+    }
+
+    fn checks_without_errors(project: &TestProject) -> bool {
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        !checked.ctx().diags.has_errors()
+    }
+
+    #[test]
+    fn renames_across_modules() {
+        let project = TestProject::with_logic(
+            "module logic\nmodule lemmas\n\n\
+             theorem imp.trans3 [p q r s : sentence] : (p → q) (q → r) (r → s)\n    |- p → s\n\
+             proof\n    have p → r by imp.trans [p] [q] [r];\n    by imp.trans [p] [r] [s]\nqed\n",
+        );
+        project.write(
+            "math/lemmas.wats",
+            "module logic\n\n\
+             theorem imp.trans [p q r : sentence] : (p → q) (q → r)\n    |- p → r\n\
+             proof\n    suppose p show r {\n        have q by mp [p] [q];\n        by mp [q] [r]\n    }\n\
+             \x20   by imp.intro [p] [r]\nqed\n",
+        );
+        assert!(checks_without_errors(&project));
+
+        assert!(rename(&project, "imp.trans", "imp.chain").is_empty());
+
+        assert!(
+            project
+                .read("math/lemmas.wats")
+                .contains("theorem imp.chain [p q r")
+        );
+        let main = project.read("math/main.wats");
+        assert!(main.contains("have p → r by imp.chain [p] [q] [r];"));
+        assert!(main.contains("by imp.chain [p] [r] [s]"));
+        assert!(main.contains("theorem imp.trans3"));
+        assert!(checks_without_errors(&project));
+    }
+
+    #[test]
+    fn leaves_names_which_may_not_be_theorems() {
+        let project = TestProject::new(
+            "syntax true\n    sentence ::= \"⊤\"\nend\n\n\
+             tactic by\n    tactic ::= @kw\"by\" thm:@name\nend\n\n\
+             tactic note\n    tactic ::= @kw\"note\" label:@name \";\" rest:tactic\nend\n\n\
+             axiom top :\n    |- ⊤\nend\n\n\
+             theorem top.again :\n    |- ⊤\nproof\n    note top;\n    note h;\n    by top\nqed\n",
+        );
+
+        let ambiguous = rename(&project, "top", "truth");
+
+        assert_eq!(ambiguous.len(), 1);
+        let main = project.read("math/main.wats");
+        assert!(main.contains("axiom truth :"));
+        assert!(main.contains("note top;"));
+        assert!(main.contains("by truth\n"));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::{
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::{Diagnostic, DiagnosticSpan, WResult, print_diagnostics, write_diagnostics},
+    driver::Driver,
+    parse::{
+        ParseReport, SourceId, Span,
+        earley::{self, escape_str},
+        parse_state::{Associativity, CategoryId, ParseAtomPattern, RuleId, RulePatternPart},
+        source_cache::SourceDecl,
+    },
+    semant::{
+        formal_syntax::FormalSyntaxCatId,
+        fragment::{FragHead, FragmentId},
+        parse_fragment::{ParseResultErr, UnresolvedFrag, parse_fragment_with_warnings},
+        scope::Scope,
+        theorems::TheoremId,
+    },
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_RED, ANSI_RESET, ANSI_YELLOW},
+        plural, similar_names,
+    },
+};
+use argh::FromArgs;
+use rustyline::{DefaultEditor, error::ReadlineError};
+use std::{fmt::Write, fs, ops::ControlFlow, path::PathBuf};
+use ustr::Ustr;
+
+/// Parse fragments against the project's notation interactively.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "repl")]
+pub struct ReplCommand {
+    /// read the lines from a file instead of the terminal.
+    #[argh(option)]
+    script: Option<PathBuf>,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+const HELP: &str = "\
+Lines are parsed as a fragment of the current category. Commands:
+  :cat <name>        parse lines in another formal syntax category
+  :scope <theorem>   parse lines in the scope of a theorem, or the project's without one
+  :grammar <cat>     list the rules of a category
+  :help              show this message
+  :quit              exit";
+
+pub fn run_repl(cmd: ReplCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // Notation which failed to elaborate can't be used, but the rest still
+    // can.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so some notation may be missing"
+        );
+    }
+
+    let (ctx, parse_report) = parsed.into_parts();
+    let mut repl = Repl::new(&parse_report, &ctx);
+
+    match cmd.script {
+        Some(path) => {
+            let script = match fs::read_to_string(&path) {
+                Ok(script) => script,
+                Err(err) => {
+                    eprintln!(
+                        "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} failed to read `{}`: {err}",
+                        path.display()
+                    );
+                    std::process::exit(1);
+                }
+            };
+
+            let mut out = String::new();
+            repl.run_script(&script, &ctx, &mut out);
+            print!("{out}");
+        }
+        None => run_interactive(&mut repl, &ctx),
+    }
+}
+
+fn run_interactive<'ctx>(repl: &mut Repl<'ctx>, ctx: &Ctx<'ctx>) {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!(
+                "{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} failed to start the line editor: {err}"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    let history = ctx.config.build_dir().join("repl_history");
+    _ = editor.load_history(&history);
+
+    println!("Type a fragment to parse it, or :help for commands.");
+    loop {
+        let line = match editor.readline(&repl.prompt()) {
+            Ok(line) => line,
+            // Ctrl-C only abandons the current line.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} failed to read a line: {err}");
+                break;
+            }
+        };
+
+        _ = editor.add_history_entry(line.as_str());
+        let mut out = String::new();
+        let flow = repl.run_line(&line, ctx, &mut out);
+        print!("{out}");
+        if flow.is_break() {
+            break;
+        }
+    }
+
+    _ = fs::create_dir_all(ctx.config.build_dir());
+    _ = editor.save_history(&history);
+}
+
+struct Repl<'ctx> {
+    /// The category lines are parsed in.
+    cat: FormalSyntaxCatId<'ctx>,
+    /// The scope lines are parsed in.
+    scope: Scope<'ctx>,
+    /// The theorem `scope` belongs to, if it isn't the project's scope.
+    theorem: Option<TheoremId<'ctx>>,
+    /// Every definition and notation of the project's roots.
+    project_scope: Scope<'ctx>,
+}
+
+impl<'ctx> Repl<'ctx> {
+    fn new(parse_report: &ParseReport<'ctx>, ctx: &Ctx<'ctx>) -> Self {
+        let project_scope = parse_report
+            .roots
+            .iter()
+            .fold(Scope::new(), |scope, root| scope.with_imported(&root.scope));
+        Self {
+            cat: ctx.sentence_cat,
+            scope: project_scope.clone(),
+            theorem: None,
+            project_scope,
+        }
+    }
+
+    fn prompt(&self) -> String {
+        match self.theorem {
+            Some(theorem) => format!("{} [{}]> ", self.cat.name(), theorem.name()),
+            None => format!("{}> ", self.cat.name()),
+        }
+    }
+
+    /// Run each line of `script`, echoing it after the prompt so the output
+    /// reads like a session.
+    fn run_script(&mut self, script: &str, ctx: &Ctx<'ctx>, out: &mut String) {
+        for line in script.lines() {
+            writeln!(out, "{}{line}", self.prompt()).unwrap();
+            if self.run_line(line, ctx, out).is_break() {
+                break;
+            }
+        }
+    }
+
+    fn run_line(&mut self, line: &str, ctx: &Ctx<'ctx>, out: &mut String) -> ControlFlow<()> {
+        let line = line.trim();
+        let result = match line.strip_prefix(':') {
+            Some(command) => self.run_command(command, ctx, out),
+            None if line.is_empty() => Ok(ControlFlow::Continue(())),
+            None => self.parse_line(line, ctx, out).map(ControlFlow::Continue),
+        };
+
+        match result {
+            Ok(flow) => flow,
+            Err(diags) => {
+                write_diagnostics(&diags, ctx, out);
+                ControlFlow::Continue(())
+            }
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        command: &str,
+        ctx: &Ctx<'ctx>,
+        out: &mut String,
+    ) -> WResult<'ctx, ControlFlow<()>> {
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+
+        match name {
+            "quit" | "q" => return Ok(ControlFlow::Break(())),
+            "help" => writeln!(out, "{HELP}").unwrap(),
+            "cat" if arg.is_empty() => return Diagnostic::err_repl_missing_arg(name, "a category"),
+            "cat" => {
+                let Some(cat) = ctx.arenas.formal_cats.get(Ustr::from(arg)) else {
+                    let names = ctx.arenas.formal_cats.names();
+                    return Diagnostic::err_repl_unknown("formal syntax category", arg, names);
+                };
+                self.cat = cat;
+            }
+            "scope" if arg.is_empty() => {
+                self.scope = self.project_scope.clone();
+                self.theorem = None;
+            }
+            "scope" => {
+                let Some(theorem) = ctx.arenas.theorem_stmts.get(Ustr::from(arg)) else {
+                    let names = ctx.arenas.theorem_stmts.names();
+                    return Diagnostic::err_repl_unknown("theorem", arg, names);
+                };
+                self.scope = ctx.scopes.get(theorem.scope());
+                self.theorem = Some(theorem);
+            }
+            "grammar" if arg.is_empty() => {
+                return Diagnostic::err_repl_missing_arg(name, "a category");
+            }
+            "grammar" => {
+                let Some(cat) = ctx.arenas.parse_cats.get(Ustr::from(arg)) else {
+                    let names = ctx.arenas.parse_cats.names();
+                    return Diagnostic::err_repl_unknown("category", arg, names);
+                };
+                print_grammar(cat, ctx, out);
+            }
+            _ => return Diagnostic::err_repl_unknown_command(name),
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Parse `line` as a fragment and print what it became.
+    fn parse_line(&self, line: &str, ctx: &Ctx<'ctx>, out: &mut String) -> WResult<'ctx, ()> {
+        let source = SourceId::new_snippet();
+        ctx.sources
+            .add(source, line.to_string(), SourceDecl::ReplInput);
+
+        let parse_cat = ctx.parse_state.cat_for_formal_cat(self.cat);
+        let tree = earley::parse(source.start_loc(), parse_cat, self.scope.preferences(), ctx)?;
+        let end = source.start_loc().forward(line.len());
+        if tree.span().end() != end {
+            return Diagnostic::err_repl_trailing_input(Span::new(tree.span().end(), end));
+        }
+
+        match parse_fragment_with_warnings(UnresolvedFrag(tree), &self.scope, ctx)? {
+            Ok((frag, warnings)) => {
+                write_diagnostics(&warnings, ctx, out);
+                writeln!(out, "{ANSI_BOLD}{}{ANSI_RESET}", frag.print()).unwrap();
+                let mut tree = String::new();
+                print_frag_tree(frag.frag(), 1, &mut tree);
+                write!(out, "{ANSI_GRAY}{tree}{ANSI_RESET}").unwrap();
+                Ok(())
+            }
+            Err(err) => {
+                if let ParseResultErr::MultipleSolutions { solutions, .. } = &err {
+                    writeln!(out, "ambiguous — {} parses", solutions.len()).unwrap();
+                    for solution in solutions {
+                        writeln!(out, "  {}", solution.notation).unwrap();
+                    }
+                }
+                Err(vec![Diagnostic::err_frag_parse_failure(tree.span(), err)])
+            }
+        }
+    }
+}
+
+/// Print the formal structure of `frag` with one node per line, indented by
+/// depth.
+fn print_frag_tree(frag: FragmentId, depth: usize, out: &mut String) {
+    let head = match frag.head() {
+        FragHead::RuleApplication(app) => {
+            format!("{} : {}", app.rule().name(), frag.cat().name())
+        }
+        FragHead::Var(idx) => format!("'{idx}"),
+        FragHead::TemplateRef(idx) => format!("${idx}"),
+        FragHead::Hole(idx) => format!("_{idx}"),
+        FragHead::Abbreviation(abbr) => format!("{} (abbreviation)", abbr.name()),
+    };
+    writeln!(out, "{:indent$}{head}", "", indent = 2 * depth).unwrap();
+
+    for &child in frag.children() {
+        print_frag_tree(child, depth + 1, out);
+    }
+}
+
+fn print_grammar(cat: CategoryId, ctx: &Ctx, out: &mut String) {
+    let rules = ctx.parse_state.rules_for_cat(cat);
+    writeln!(
+        out,
+        "{ANSI_BOLD}{}{ANSI_RESET} has {} rule{}:",
+        cat.name(),
+        rules.len(),
+        plural(rules.len())
+    )
+    .unwrap();
+    for &rule in rules {
+        writeln!(out, "  {}", print_rule(rule)).unwrap();
+    }
+}
+
+/// Print a rule the way the grammar is written in the source.
+fn print_rule(rule: RuleId) -> String {
+    let mut out = format!("({})", rule.name());
+    for part in rule.pattern().parts() {
+        out.push(' ');
+        match part {
+            RulePatternPart::Atom(ParseAtomPattern::Lit(lit)) => {
+                write!(out, "\"{}\"", escape_str(lit)).unwrap()
+            }
+            RulePatternPart::Atom(ParseAtomPattern::Kw(kw)) => write!(out, "kw\"{kw}\"").unwrap(),
+            RulePatternPart::Atom(ParseAtomPattern::Name) => out.push_str("@name"),
+            RulePatternPart::Atom(ParseAtomPattern::Str) => out.push_str("str"),
+            RulePatternPart::Atom(ParseAtomPattern::Num) => out.push_str("number"),
+            RulePatternPart::Cat(cat) => out.push_str(&cat.name()),
+        }
+    }
+
+    let pattern = rule.pattern();
+    let assoc = match pattern.associativity() {
+        Associativity::Left => " <",
+        Associativity::Right => " >",
+        Associativity::NonAssoc => "",
+    };
+    if pattern.precedence().0 != 0 || !assoc.is_empty() {
+        write!(
+            out,
+            "  {ANSI_GRAY}({}{assoc}){ANSI_RESET}",
+            pattern.precedence().0
+        )
+        .unwrap();
+    }
+    out
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    fn err_repl_unknown_command<T>(name: &str) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(&format!("unknown command `:{name}`"), vec![])
+            .with_info("use `:help` to list the commands", Vec::new());
+        Err(vec![diag])
+    }
+
+    fn err_repl_missing_arg<T>(command: &str, what: &str) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(&format!("`:{command}` needs {what}"), vec![]);
+        Err(vec![diag])
+    }
+
+    fn err_repl_unknown<T>(kind: &str, name: &str, names: Vec<Ustr>) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(&format!("no {kind} named `{name}`"), vec![]);
+        for candidate in similar_names(name, names) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+        Err(vec![diag])
+    }
+
+    fn err_repl_trailing_input<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "unexpected input after the fragment",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Repl;
+    use crate::{
+        context::Arenas,
+        driver::Driver,
+        util::{ansi::strip_ansi, test_project::TestProject},
+    };
+
+    /// The transcript of running `script` in a project with the
+    /// propositional library, without colors.
+    fn run(script: &str) -> String {
+        let project = TestProject::with_logic("module logic\n");
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        assert!(!parsed.ctx().diags.has_errors());
+        let (ctx, parse_report) = parsed.into_parts();
+
+        let mut repl = Repl::new(&parse_report, &ctx);
+        let mut out = String::new();
+        repl.run_script(script, &ctx, &mut out);
+        strip_ansi(&out)
+    }
+
+    #[test]
+    fn lines_are_parsed_as_sentences() {
+        let out = run("⊥ → ⊥\n");
+        assert!(out.starts_with("sentence> ⊥ → ⊥\n"), "{out}");
+        assert!(
+            out.ends_with("\n  implies : sentence\n    false : sentence\n    false : sentence\n"),
+            "{out}"
+        );
+    }
+
+    #[test]
+    fn scope_makes_templates_available() {
+        let out = run("p → q\n:scope mp\np → q\n:scope\n");
+        let (outside, inside) = out.split_once("sentence [mp]> p → q\n").unwrap();
+        assert!(outside.contains("error"), "{outside}");
+        assert!(
+            inside.contains("\n  implies : sentence\n    $0\n    $1\n"),
+            "{inside}"
+        );
+        assert!(inside.ends_with("sentence [mp]> :scope\n"), "{inside}");
+    }
+
+    #[test]
+    fn grammar_lists_the_rules() {
+        let out = run(":grammar sentence\n");
+        assert!(out.contains("sentence has "), "{out}");
+        assert!(out.contains("sentence \"→\" sentence"), "{out}");
+        assert!(out.contains("\"¬\" sentence"), "{out}");
+    }
+
+    #[test]
+    fn mistakes_are_reported_and_the_session_goes_on() {
+        let out = run(":cat sentenc\n:frobnicate\n:scope nope\n⊥ ⊥\n:help\n");
+        assert!(
+            out.contains("no formal syntax category named `sentenc`"),
+            "{out}"
+        );
+        assert!(out.contains("did you mean `sentence`?"), "{out}");
+        assert!(out.contains("unknown command `:frobnicate`"), "{out}");
+        assert!(out.contains("no theorem named `nope`"), "{out}");
+        assert!(out.contains("unexpected input after the fragment"), "{out}");
+        assert!(out.contains(":quit              exit"), "{out}");
+    }
+
+    #[test]
+    fn quit_stops_the_script() {
+        let out = run(":cat sentence\n:quit\n⊥\n");
+        assert_eq!(out, "sentence> :cat sentence\nsentence> :quit\n");
+    }
+}
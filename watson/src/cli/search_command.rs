@@ -0,0 +1,305 @@
+use crate::{
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::{Diagnostic, DiagnosticSpan, WResult, print_diagnostics},
+    driver::Driver,
+    parse::{
+        SourceId, Span,
+        earley::{self, parse_name},
+        location::SourceOffset,
+        source_cache::SourceDecl,
+    },
+    semant::{
+        notation::NotationBinding,
+        parse_fragment::{UnresolvedFrag, parse_fragment},
+        presentation::{PresFrag, match_presentation_with_wildcard},
+        scope::{DefinitionSource, Scope, ScopeEntry},
+        theorems::TheoremId,
+    },
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_RESET, ANSI_YELLOW},
+        plural,
+    },
+};
+use argh::FromArgs;
+use rustc_hash::FxHashSet;
+use std::path::PathBuf;
+use ustr::Ustr;
+
+/// Find the theorems whose conclusion or hypotheses have the shape of a
+/// pattern.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "search")]
+pub struct SearchCommand {
+    /// a sentence where `_` matches anything and a name starting with `_`,
+    /// like `_a`, matches the same thing everywhere it appears.
+    #[argh(positional)]
+    pattern: String,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+/// The hole `_` stands for. Named holes are numbered after it.
+const WILDCARD: usize = 0;
+
+pub fn run_search(cmd: SearchCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // Statements which failed to elaborate can't be searched, but the rest
+    // still can.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so some matches may be missing"
+        );
+    }
+
+    let (mut ctx, parse_report) = parsed.into_parts();
+    let project_scope = parse_report
+        .roots
+        .iter()
+        .fold(Scope::new(), |scope, root| scope.with_imported(&root.scope));
+
+    let pattern = match parse_pattern(&cmd.pattern, &project_scope, &ctx) {
+        Ok(pattern) => pattern,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+
+    let mut seen = FxHashSet::default();
+    let matches: Vec<_> = parse_report
+        .roots
+        .iter()
+        .flat_map(|root| &root.theorems)
+        .map(|(theorem, _)| *theorem)
+        .filter(|theorem| seen.insert(*theorem))
+        .filter_map(|theorem| {
+            let parts = matching_parts(theorem, pattern);
+            (!parts.is_empty()).then_some((theorem, parts))
+        })
+        .collect();
+
+    if matches.is_empty() {
+        println!(
+            "No theorem statement matches {ANSI_BOLD}{}{ANSI_RESET}",
+            cmd.pattern
+        );
+        return;
+    }
+
+    println!(
+        "{} theorem{} match {ANSI_BOLD}{}{ANSI_RESET}:",
+        matches.len(),
+        plural(matches.len()),
+        cmd.pattern
+    );
+    let starts: Vec<_> = matches
+        .iter()
+        .map(|(theorem, _)| theorem.name_span().start())
+        .collect();
+    let line_cols = ctx.sources.line_cols(&starts);
+    for ((theorem, parts), (line, col)) in matches.into_iter().zip(line_cols) {
+        let span = theorem.name_span();
+        println!(
+            "     - {ANSI_BOLD}{}{ANSI_RESET} {ANSI_GRAY}{}:{line}:{col} (in {}){ANSI_RESET}",
+            theorem.name(),
+            span.source().name(),
+            parts.join(", ")
+        );
+        println!("         {}", theorem.print());
+    }
+}
+
+/// Parse the pattern as a sentence in the project's scope, with its holes
+/// bound in every formal syntax category.
+fn parse_pattern<'ctx>(
+    pattern: &str,
+    scope: &Scope<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, PresFrag<'ctx>> {
+    let source = SourceId::new_snippet();
+    ctx.sources
+        .add(source, pattern.to_string(), SourceDecl::ReplInput);
+
+    let mut scope = scope.clone();
+    for (idx, name) in hole_names(pattern).into_iter().enumerate() {
+        for (&cat, &notation) in &ctx.single_name_notations {
+            let binding = NotationBinding::new(notation, vec![name]);
+            let binding = ctx.arenas.notation_bindings.intern(binding);
+            let entry = ScopeEntry::new_hole(cat, idx, DefinitionSource::DefinitionHole);
+            scope = scope.child_with(binding, entry);
+        }
+    }
+
+    let parse_cat = ctx.parse_state.cat_for_formal_cat(ctx.sentence_cat);
+    let tree = earley::parse(source.start_loc(), parse_cat, scope.preferences(), ctx)?;
+    let end = source.start_loc().forward(pattern.len());
+    if tree.span().end() != end {
+        return Diagnostic::err_search_trailing_input(Span::new(tree.span().end(), end));
+    }
+
+    match parse_fragment(UnresolvedFrag(tree), &scope, ctx)? {
+        Ok(frag) => Ok(frag),
+        Err(err) => Err(vec![Diagnostic::err_frag_parse_failure(tree.span(), err)]),
+    }
+}
+
+/// `_` followed by the other names in the pattern starting with `_`, in the
+/// order they first appear, so each name's hole is its index.
+fn hole_names(pattern: &str) -> Vec<Ustr> {
+    let mut names = vec![Ustr::from("_")];
+    let mut at = 0;
+    while let Some(c) = pattern[at..].chars().next() {
+        let Some((end, name)) = parse_name(pattern, SourceOffset::new(at)) else {
+            at += c.len_utf8();
+            continue;
+        };
+        let name = Ustr::from(name);
+        if name.starts_with('_') && !names.contains(&name) {
+            names.push(name);
+        }
+        at = end.byte_offset();
+    }
+    names
+}
+
+/// The parts of the theorem's statement which match the pattern. A hole
+/// matches a reference to one of the theorem's templates like any other
+/// fragment, so `_ -> _` finds a statement `p -> q` about templates `p` and
+/// `q`.
+fn matching_parts<'ctx>(theorem: TheoremId<'ctx>, pattern: PresFrag<'ctx>) -> Vec<String> {
+    let matches = |frag: PresFrag<'ctx>| {
+        match_presentation_with_wildcard(frag, pattern, Some(WILDCARD)).is_some()
+    };
+
+    let mut parts = Vec::new();
+    for (i, hypothesis) in theorem.hypotheses().iter().enumerate() {
+        let assumption_matches = hypothesis.assumption().is_some_and(&matches);
+        if assumption_matches || matches(hypothesis.conclusion()) {
+            parts.push(format!("hypothesis {}", i + 1));
+        }
+    }
+    if matches(theorem.conclusion()) {
+        parts.push("conclusion".to_string());
+    }
+    parts
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    fn err_search_trailing_input<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "unexpected input after the pattern",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{matching_parts, parse_pattern};
+    use crate::{
+        context::Arenas, driver::Driver, semant::scope::Scope, util::test_project::TestProject,
+    };
+
+    /// Two theorems concluding implications, one of them `p → p`, and one
+    /// with a hypothesis `⊥`. The logic library adds `imp.intro`, `mp` and
+    /// `dne`.
+    const MAIN: &str = "module logic\n\n\
+        axiom self [p : sentence] :\n    |- p → p\nend\n\n\
+        axiom weaken [p q : sentence] : (q)\n    |- p → q\nend\n\n\
+        axiom explode [p : sentence] : (⊥)\n    |- p\nend\n";
+
+    /// The name, line and matching parts of each theorem matching a
+    /// pattern, or the titles of the errors from parsing it.
+    type Found = Result<Vec<(String, u32, Vec<String>)>, Vec<String>>;
+
+    fn search(pattern: &str) -> Found {
+        let project = TestProject::with_logic(MAIN);
+        let arenas = Arenas::new();
+        let (ctx, parse_report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        assert!(!ctx.diags.has_errors());
+
+        let scope = (parse_report.roots.iter())
+            .fold(Scope::new(), |scope, root| scope.with_imported(&root.scope));
+        let pattern = parse_pattern(pattern, &scope, &ctx).map_err(|diags| {
+            diags
+                .iter()
+                .map(|diag| diag.title().to_string())
+                .collect::<Vec<_>>()
+        })?;
+        let found: Vec<_> = (parse_report.roots.iter())
+            .flat_map(|root| &root.theorems)
+            .map(|(theorem, _)| *theorem)
+            .filter_map(|theorem| {
+                let parts = matching_parts(theorem, pattern);
+                (!parts.is_empty()).then_some((theorem, parts))
+            })
+            .collect();
+        let starts: Vec<_> = (found.iter())
+            .map(|(theorem, _)| theorem.name_span().start())
+            .collect();
+        let lines = ctx.sources.line_cols(&starts);
+        Ok((found.into_iter().zip(lines))
+            .map(|((theorem, parts), (line, _))| (theorem.name().to_string(), line, parts))
+            .collect())
+    }
+
+    fn found(matches: &[(&str, u32, &[&str])]) -> Found {
+        Ok((matches.iter())
+            .map(|&(name, line, parts)| {
+                let parts = parts.iter().map(|part| part.to_string()).collect();
+                (name.to_string(), line, parts)
+            })
+            .collect())
+    }
+
+    #[test]
+    fn holes_match_any_implication() {
+        assert_eq!(
+            search("_ → _"),
+            found(&[
+                ("imp.intro", 53, &["conclusion"]),
+                ("mp", 57, &["hypothesis 1"]),
+                ("self", 3, &["conclusion"]),
+                ("weaken", 7, &["conclusion"]),
+            ])
+        );
+    }
+
+    #[test]
+    fn named_holes_match_the_same_fragment() {
+        assert_eq!(search("_a → _a"), found(&[("self", 3, &["conclusion"])]));
+    }
+
+    #[test]
+    fn patterns_without_holes_match_exactly() {
+        assert_eq!(search("⊥"), found(&[("explode", 11, &["hypothesis 1"])]));
+    }
+
+    #[test]
+    fn broken_patterns_are_errors() {
+        assert_eq!(
+            search("_ → → _"),
+            Err(vec!["unexpected input after the pattern".to_string()])
+        );
+        let errors = search("→ _").unwrap_err();
+        assert_eq!(errors, vec!["error while parsing command".to_string()]);
+    }
+}
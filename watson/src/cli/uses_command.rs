@@ -0,0 +1,335 @@
+use crate::{
+    config::{WatsonConfig, find_config_file},
+    context::{Arenas, Ctx},
+    diagnostics::{Diagnostic, WResult, print_diagnostics},
+    driver::Driver,
+    parse::ParseReport,
+    semant::{occurrences::UseTarget, scope::DefinitionSource},
+    util::{
+        ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_RESET, ANSI_YELLOW},
+        plural, similar_names,
+    },
+};
+use argh::FromArgs;
+use itertools::Itertools;
+use std::{fmt, path::PathBuf, str::FromStr};
+use ustr::Ustr;
+
+/// List the theorems whose statements use a definition, notation or syntax
+/// rule.
+#[derive(FromArgs)]
+#[argh(subcommand, name = "uses")]
+pub struct UsesCommand {
+    /// the name of the definition, notation or syntax rule.
+    #[argh(positional)]
+    name: String,
+
+    /// what the name refers to when several things share it: definition,
+    /// notation or rule.
+    #[argh(option)]
+    kind: Option<UseKind>,
+
+    /// path to watson.toml config file.
+    #[argh(option, short = 'c')]
+    config: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UseKind {
+    Definition,
+    Notation,
+    Rule,
+}
+
+impl FromStr for UseKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "definition" => Ok(Self::Definition),
+            "notation" => Ok(Self::Notation),
+            "rule" => Ok(Self::Rule),
+            _ => Err(format!(
+                "expected `definition`, `notation` or `rule`, found `{s}`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for UseKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Definition => "definition",
+            Self::Notation => "notation",
+            Self::Rule => "rule",
+        })
+    }
+}
+
+pub fn run_uses(cmd: UsesCommand) {
+    // Find watson.toml config file
+    let config_file_path = match cmd.config {
+        Some(file) => file.canonicalize().unwrap(),
+        None => find_config_file().unwrap(),
+    };
+
+    let config = WatsonConfig::from_file(&config_file_path).unwrap();
+
+    let arenas = Arenas::new();
+    let parsed = Driver::for_project(config, &arenas).parse();
+
+    // Statements which failed to elaborate can't be searched, but the rest
+    // still can.
+    if parsed.ctx().diags.has_errors() {
+        print_diagnostics(parsed.parse_diagnostics(), parsed.ctx());
+        println!(
+            "{ANSI_YELLOW}{ANSI_BOLD}warning:{ANSI_RESET} the project has errors, so some uses may be missing"
+        );
+    }
+
+    let (mut ctx, parse_report) = parsed.into_parts();
+
+    let name = Ustr::from(&cmd.name);
+    let (kind, target) = match resolve_target(name, cmd.kind, &parse_report, &ctx) {
+        Ok(target) => target,
+        Err(diags) => {
+            ctx.diags.add_diags(diags);
+            ctx.diags.print_errors(&ctx);
+            std::process::exit(1);
+        }
+    };
+
+    let occurrences = target.find_occurrences(&parse_report);
+    if occurrences.is_empty() {
+        println!("The {kind} {ANSI_BOLD}{name}{ANSI_RESET} isn't used in any theorem statement");
+        return;
+    }
+
+    let starts: Vec<_> = occurrences
+        .iter()
+        .map(|occ| occ.theorem())
+        .dedup()
+        .map(|theorem| theorem.name_span().start())
+        .collect();
+    let theorems = starts.len();
+    println!(
+        "The {kind} {ANSI_BOLD}{name}{ANSI_RESET} is used {} time{} in {theorems} theorem{}:",
+        occurrences.len(),
+        plural(occurrences.len()),
+        plural(theorems)
+    );
+    let line_cols = ctx.sources.line_cols(&starts);
+    let chunks = occurrences.iter().chunk_by(|occ| occ.theorem());
+    for ((theorem, occurrences), (line, _)) in chunks.into_iter().zip(line_cols) {
+        println!(
+            "     - {ANSI_BOLD}{}{ANSI_RESET} {ANSI_GRAY}{}:{line}{ANSI_RESET}",
+            theorem.name(),
+            theorem.name_span().source().name(),
+        );
+        for occ in occurrences {
+            let text = occ.context();
+            let highlight = occ.highlight();
+            println!(
+                "         {}{ANSI_YELLOW}{ANSI_BOLD}{}{ANSI_RESET}{}",
+                &text[..highlight.start],
+                &text[highlight.clone()],
+                &text[highlight.end..]
+            );
+        }
+    }
+}
+
+/// Find what `name` refers to. It is an error if it refers to nothing or, when
+/// `kind` isn't given, to more than one kind of thing.
+fn resolve_target<'ctx>(
+    name: Ustr,
+    kind: Option<UseKind>,
+    parse_report: &ParseReport<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, (UseKind, UseTarget<'ctx>)> {
+    let definition_entries: Vec<_> = parse_report
+        .roots
+        .iter()
+        .flat_map(|root| root.scope.entries())
+        .filter(|(_, entry)| {
+            matches!(
+                entry.source(),
+                DefinitionSource::DefinitionCmd(_) | DefinitionSource::AbbreviationCmd(_)
+            )
+        })
+        .collect();
+
+    let mut definitions = Vec::new();
+    for (binding, entry) in &definition_entries {
+        if binding.name_instantiations().contains(&name) && !definitions.contains(&entry.source()) {
+            definitions.push(entry.source());
+        }
+    }
+
+    let mut candidates = Vec::new();
+    if !definitions.is_empty() {
+        candidates.push((UseKind::Definition, UseTarget::Definition(definitions)));
+    }
+    if let Some(&notation) = ctx.notations.get(&name) {
+        candidates.push((UseKind::Notation, UseTarget::Notation(notation)));
+    }
+    if let Some(rule) = ctx.arenas.formal_rules.get(name) {
+        candidates.push((UseKind::Rule, UseTarget::Rule(rule)));
+    }
+
+    let kinds: Vec<_> = candidates.iter().map(|(kind, _)| *kind).collect();
+    if let Some(kind) = kind {
+        candidates.retain(|(candidate, _)| *candidate == kind);
+    }
+
+    match candidates.len() {
+        0 => {
+            let names = definition_entries
+                .iter()
+                .flat_map(|(binding, _)| binding.name_instantiations().iter().copied())
+                .chain(ctx.notations.keys().copied())
+                .chain(ctx.arenas.formal_rules.names())
+                .unique();
+            let similar = similar_names(&name, names);
+            Diagnostic::err_unknown_use_target(name, kind, &kinds, &similar)
+        }
+        1 => Ok(candidates.pop().unwrap()),
+        _ => Diagnostic::err_ambiguous_use_target(name, &kinds),
+    }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    fn err_unknown_use_target<T>(
+        name: Ustr,
+        kind: Option<UseKind>,
+        kinds: &[UseKind],
+        similar: &[Ustr],
+    ) -> WResult<'ctx, T> {
+        let title = match kind {
+            Some(kind) => format!("no {kind} named `{name}`"),
+            None => format!("no definition, notation or rule named `{name}`"),
+        };
+        let mut diag = Diagnostic::new(&title, vec![]);
+
+        if !kinds.is_empty() {
+            let kinds = kinds.iter().map(|kind| format!("a {kind}")).join(" and ");
+            diag = diag.with_info(&format!("`{name}` names {kinds}"), Vec::new());
+        }
+        for candidate in similar {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+
+        Err(vec![diag])
+    }
+
+    fn err_ambiguous_use_target<T>(name: Ustr, kinds: &[UseKind]) -> WResult<'ctx, T> {
+        let names = kinds.iter().map(|kind| format!("a {kind}")).join(" and ");
+        let options = kinds
+            .iter()
+            .map(|kind| format!("`--kind {kind}`"))
+            .join(" or ");
+        let diag = Diagnostic::new(&format!("`{name}` names {names}"), vec![])
+            .with_info(&format!("use {options} to choose one"), Vec::new());
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{UseKind, resolve_target};
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+    use ustr::Ustr;
+
+    /// A sentence `q` defined as `⊥`, used in two theorems and shadowed by a
+    /// template in a third.
+    const MAIN: &str = "module logic\n\n\
+        definition\n    q := ⊥\nend\n\n\
+        axiom twice :\n    |- q → ¬q\nend\n\n\
+        axiom once : (q)\n    |- ⊥\nend\n\n\
+        axiom shadow [q : sentence] :\n    |- q\nend\n";
+
+    /// The theorem, context and highlighted text of each use of `name`, or
+    /// the titles of the errors from resolving it.
+    fn uses(
+        name: &str,
+        kind: Option<UseKind>,
+    ) -> Result<Vec<(String, String, String)>, Vec<String>> {
+        let project = TestProject::with_logic(MAIN);
+        let arenas = Arenas::new();
+        let (ctx, parse_report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        assert!(!ctx.diags.has_errors());
+
+        match resolve_target(Ustr::from(name), kind, &parse_report, &ctx) {
+            Ok((_, target)) => Ok((target.find_occurrences(&parse_report).iter())
+                .map(|occ| {
+                    let highlight = occ.context()[occ.highlight()].to_string();
+                    (
+                        occ.theorem().name().to_string(),
+                        occ.context().to_string(),
+                        highlight,
+                    )
+                })
+                .collect()),
+            Err(diags) => Err(diags.iter().map(|diag| diag.title().to_string()).collect()),
+        }
+    }
+
+    fn found(uses: &[(&str, &str, &str)]) -> Result<Vec<(String, String, String)>, Vec<String>> {
+        Ok((uses.iter())
+            .map(|&(thm, context, highlight)| {
+                (thm.to_string(), context.to_string(), highlight.to_string())
+            })
+            .collect())
+    }
+
+    #[test]
+    fn uses_of_a_definition_skip_templates_shadowing_it() {
+        assert_eq!(
+            uses("q", None),
+            found(&[
+                ("twice", "q→¬q", "q"),
+                ("twice", "¬q", "q"),
+                ("once", "q", "q")
+            ])
+        );
+    }
+
+    #[test]
+    fn uses_of_a_notation_include_the_library() {
+        assert_eq!(
+            uses("not", None),
+            found(&[
+                ("dne", "¬¬p", "¬¬p"),
+                ("dne", "¬¬p", "¬p"),
+                ("twice", "q→¬q", "¬q")
+            ])
+        );
+    }
+
+    #[test]
+    fn names_of_several_kinds_need_a_kind() {
+        assert_eq!(
+            uses("implies", None),
+            Err(vec!["`implies` names a notation and a rule".to_string()])
+        );
+        let rule_uses = uses("implies", Some(UseKind::Rule)).unwrap();
+        assert!(rule_uses.iter().any(|(thm, _, _)| thm == "twice"));
+        assert!(rule_uses.iter().all(|(thm, _, _)| thm != "shadow"));
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert_eq!(
+            uses("nope", None),
+            Err(vec![
+                "no definition, notation or rule named `nope`".to_string()
+            ])
+        );
+        assert_eq!(
+            uses("q", Some(UseKind::Notation)),
+            Err(vec!["no notation named `q`".to_string()])
+        );
+    }
+}
@@ -1,10 +1,17 @@
+use crate::{semant::theorem_filter::TheoremFilter, strings};
 use serde::Deserialize;
 use std::{
     env, fs,
     path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
 };
+use ustr::Ustr;
 
 const CONFIG_FILE_NAME: &str = "watson.toml";
+const DEFAULT_SPLIT_STEP_LIMIT: usize = 100_000;
+const DEFAULT_MATH_SIZE_LIMIT: usize = 64 * 1024;
+const DEFAULT_MATH_TIME_BUDGET: Duration = Duration::from_secs(10);
 
 /// Search for watson.toml starting from the given directory and moving up the directory tree
 pub fn find_config_file() -> Result<PathBuf, ConfigError> {
@@ -34,13 +41,180 @@ pub struct WatsonConfig {
     build_dir: PathBuf,
     math_dir: PathBuf,
     lua_dir: PathBuf,
+    /// The root sources of the project, checked in order. Each root starts
+    /// with an empty scope and only sees earlier roots it imports with `module`.
+    /// The grammar is shared though, so syntax an earlier root declared can
+    /// still make a later root's text ambiguous.
+    roots: Vec<Ustr>,
+    /// The Lua file which is loaded to handle tactics. Without one only
+    /// axioms can be checked.
+    lua_entry: Option<PathBuf>,
+    /// The parts of the Lua standard library tactics can use.
+    lua_stdlib: Vec<LuaStdLib>,
+    /// Whether `module` commands may load sources from the file system.
+    allow_fs: bool,
+    /// How much of the proof state to show in diagnostics from failed tactics.
+    proof_state: ProofStateDisplay,
+    /// A theorem which is expected to fail. If it is ever proved the
+    /// axioms or tactics are unsound.
+    sentinel_theorem: Option<Ustr>,
+    /// Whether to hide the logs of tactics, except for theorems which fail.
+    quiet_tactics: bool,
+    /// Whether to stop at the first error instead of reporting as many as
+    /// possible.
+    fail_fast: bool,
+    /// Whether to reuse the parse trees of unchanged sources from the cache
+    /// in the build directory.
+    parse_cache: bool,
+    /// Whether proofs which a tactic marked as fragile are errors.
+    deny_fragile: bool,
+    /// When todos are errors, the tickets whose todos are still allowed.
+    deny_todo: Option<Vec<String>>,
+    /// Whether warnings are errors.
+    deny_warnings: bool,
+    /// How diagnostics are printed.
+    message_format: MessageFormat,
+    /// Which theorems to check. Without a filter every theorem is checked.
+    theorem_filter: Option<TheoremFilter>,
+    /// Whether to note where a `prefer` command resolved an ambiguity.
+    preference_notes: bool,
+    /// How many steps the parser may take to split a span between the parts
+    /// of a rule before giving up.
+    split_step_limit: usize,
+    /// A Vampire binary to run prover portfolios with. Without one they use
+    /// the embedded prover.
+    vampire_binary: Option<PathBuf>,
     book: BookConfig,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProofStateDisplay {
+    /// Show every fact, with the most recent ones listed first.
+    Full,
+    /// Only show the facts added by the most recent steps.
+    Recent,
+    /// Don't show the proof state.
+    None,
+}
+
+impl FromStr for ProofStateDisplay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(Self::Full),
+            "recent" => Ok(Self::Recent),
+            "none" => Ok(Self::None),
+            _ => Err(format!("expected `full`, `recent` or `none`, found `{s}`")),
+        }
+    }
+}
+
+/// How diagnostics are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFormat {
+    /// Rendered with the source they point at, for people to read.
+    Human,
+    /// One JSON object per line, for editors and other tools.
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("expected `human` or `json`, found `{s}`")),
+        }
+    }
+}
+
+/// A part of the Lua standard library which can be exposed to tactics. None
+/// of them can reach the file system or other processes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LuaStdLib {
+    Table,
+    String,
+    Utf8,
+    Bit,
+    Math,
+    /// Only `os.clock`, for timing tactics, rather than the whole `os`
+    /// library.
+    OsClock,
+}
+
+impl LuaStdLib {
+    const DEFAULT: &[LuaStdLib] = &[
+        LuaStdLib::Table,
+        LuaStdLib::String,
+        LuaStdLib::Utf8,
+        LuaStdLib::Bit,
+        LuaStdLib::Math,
+    ];
+}
+
 #[derive(Debug, Clone)]
 pub struct BookConfig {
     title: Option<String>,
     port: u16,
+    badge_style: BadgeStyle,
+    authors: Vec<String>,
+    license: Option<String>,
+    repository_url: Option<String>,
+    description: Option<String>,
+    /// The language of the book's prose as a BCP-47 tag.
+    language: Option<String>,
+    /// Whether to list the axioms each theorem depends on below it.
+    show_axiom_closure: bool,
+    /// Whether corollaries are numbered separately from theorems.
+    separate_corollary_numbering: bool,
+    /// Whether to warn about prose and commands with no blank line between
+    /// them.
+    prose_layout_warnings: bool,
+    /// The most bytes of HTML a single formula may render to before it is
+    /// shown as written instead.
+    math_size_limit: usize,
+    /// How long rendering the math of one chapter may take before the rest
+    /// of it is shown as written.
+    math_time_budget: Duration,
+    /// Where the project's own templates and stylesheet are, if it can have
+    /// any.
+    templates_dir: Option<PathBuf>,
+}
+
+impl Default for BookConfig {
+    fn default() -> Self {
+        Self {
+            title: None,
+            port: 4747,
+            badge_style: BadgeStyle::default(),
+            authors: Vec::new(),
+            license: None,
+            repository_url: None,
+            description: None,
+            language: None,
+            show_axiom_closure: false,
+            separate_corollary_numbering: false,
+            prose_layout_warnings: true,
+            math_size_limit: DEFAULT_MATH_SIZE_LIMIT,
+            math_time_budget: DEFAULT_MATH_TIME_BUDGET,
+            templates_dir: None,
+        }
+    }
+}
+
+/// How the proof status of theorems is shown in the book.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BadgeStyle {
+    Icons,
+    #[default]
+    Text,
+    None,
 }
 
 impl WatsonConfig {
@@ -51,25 +225,140 @@ impl WatsonConfig {
         let math_dir = project_dir.join("math");
         let lua_dir = project_dir.join("script");
 
-        let book = match config_file.book {
+        let roots = match config_file.project.and_then(|p| p.roots) {
+            Some(roots) => roots.iter().map(|root| Ustr::from(root)).collect(),
+            None => vec![*strings::DIR_MOD_NAME],
+        };
+
+        let lua_entry = Some(
+            match config_file.check.as_ref().and_then(|c| c.lua_entry.clone()) {
+                Some(lua_entry) => project_dir.join(lua_entry),
+                None => lua_dir.join("main.luau"),
+            },
+        );
+
+        let lua_stdlib = config_file
+            .check
+            .as_ref()
+            .and_then(|c| c.lua_stdlib.clone())
+            .unwrap_or_else(|| LuaStdLib::DEFAULT.to_vec());
+
+        let proof_state = config_file
+            .check
+            .as_ref()
+            .and_then(|c| c.proof_state)
+            .unwrap_or(ProofStateDisplay::Full);
+
+        let sentinel_theorem = config_file
+            .check
+            .as_ref()
+            .and_then(|c| c.sentinel_theorem.as_deref())
+            .map(Ustr::from);
+
+        let preference_notes = config_file
+            .check
+            .as_ref()
+            .and_then(|c| c.preference_notes)
+            .unwrap_or(true);
+
+        let split_step_limit = config_file
+            .check
+            .as_ref()
+            .and_then(|c| c.split_step_limit)
+            .unwrap_or(DEFAULT_SPLIT_STEP_LIMIT);
+
+        let vampire_binary = config_file
+            .check
+            .as_ref()
+            .and_then(|c| c.vampire_binary.as_ref())
+            .map(|binary| project_dir.join(binary));
+
+        let mut book = match config_file.book {
             Some(book_config) => BookConfig {
                 title: book_config.title,
                 port: book_config.port.unwrap_or(4747),
+                badge_style: book_config.badge_style.unwrap_or_default(),
+                authors: book_config.authors.unwrap_or_default(),
+                license: book_config.license,
+                repository_url: book_config.repository_url,
+                description: book_config.description,
+                language: book_config.language,
+                show_axiom_closure: book_config.show_axiom_closure.unwrap_or(false),
+                separate_corollary_numbering: book_config
+                    .separate_corollary_numbering
+                    .unwrap_or(false),
+                prose_layout_warnings: book_config.prose_layout_warnings.unwrap_or(true),
+                math_size_limit: book_config
+                    .math_size_limit
+                    .unwrap_or(DEFAULT_MATH_SIZE_LIMIT),
+                math_time_budget: book_config
+                    .math_time_budget_secs
+                    .map_or(DEFAULT_MATH_TIME_BUDGET, Duration::from_secs),
+                templates_dir: None,
             },
-            None => BookConfig {
-                title: None,
-                port: 4747,
-            },
+            None => BookConfig::default(),
         };
+        book.templates_dir = Some(project_dir.join("book").join("templates"));
 
         Ok(Self {
             build_dir,
             math_dir,
             lua_dir,
+            roots,
+            lua_entry,
+            lua_stdlib,
+            allow_fs: true,
+            proof_state,
+            sentinel_theorem,
+            quiet_tactics: false,
+            fail_fast: false,
+            parse_cache: true,
+            deny_fragile: false,
+            deny_todo: None,
+            deny_warnings: false,
+            message_format: MessageFormat::Human,
+            theorem_filter: None,
+            preference_notes,
+            split_step_limit,
+            vampire_binary,
             book,
         })
     }
 
+    /// Config for checking a single source read from stdin rather than a
+    /// project on disk. Build artifacts go to a temporary directory.
+    pub fn for_stdin(root: Ustr, lua_entry: Option<PathBuf>, allow_fs: bool) -> Self {
+        let current_dir = env::current_dir().unwrap();
+        let lua_dir = match &lua_entry {
+            Some(lua_entry) => lua_entry.parent().unwrap().to_path_buf(),
+            None => current_dir.clone(),
+        };
+
+        Self {
+            build_dir: env::temp_dir().join("watson"),
+            math_dir: current_dir,
+            lua_dir,
+            roots: vec![root],
+            lua_entry,
+            lua_stdlib: LuaStdLib::DEFAULT.to_vec(),
+            allow_fs,
+            proof_state: ProofStateDisplay::Full,
+            sentinel_theorem: None,
+            quiet_tactics: false,
+            fail_fast: false,
+            parse_cache: false,
+            deny_fragile: false,
+            deny_todo: None,
+            deny_warnings: false,
+            message_format: MessageFormat::Human,
+            theorem_filter: None,
+            preference_notes: true,
+            split_step_limit: DEFAULT_SPLIT_STEP_LIMIT,
+            vampire_binary: None,
+            book: BookConfig::default(),
+        }
+    }
+
     pub fn math_dir(&self) -> &Path {
         &self.math_dir
     }
@@ -78,6 +367,115 @@ impl WatsonConfig {
         &self.lua_dir
     }
 
+    pub fn roots(&self) -> &[Ustr] {
+        &self.roots
+    }
+
+    pub fn lua_entry(&self) -> Option<&Path> {
+        self.lua_entry.as_deref()
+    }
+
+    pub fn lua_stdlib(&self) -> &[LuaStdLib] {
+        &self.lua_stdlib
+    }
+
+    pub fn allow_fs(&self) -> bool {
+        self.allow_fs
+    }
+
+    pub fn proof_state(&self) -> ProofStateDisplay {
+        self.proof_state
+    }
+
+    pub fn set_proof_state(&mut self, proof_state: ProofStateDisplay) {
+        self.proof_state = proof_state;
+    }
+
+    pub fn sentinel_theorem(&self) -> Option<Ustr> {
+        self.sentinel_theorem
+    }
+
+    pub fn quiet_tactics(&self) -> bool {
+        self.quiet_tactics
+    }
+
+    pub fn set_quiet_tactics(&mut self, quiet_tactics: bool) {
+        self.quiet_tactics = quiet_tactics;
+    }
+
+    pub fn fail_fast(&self) -> bool {
+        self.fail_fast
+    }
+
+    pub fn set_fail_fast(&mut self, fail_fast: bool) {
+        self.fail_fast = fail_fast;
+    }
+
+    pub fn parse_cache(&self) -> bool {
+        self.parse_cache
+    }
+
+    pub fn set_parse_cache(&mut self, parse_cache: bool) {
+        self.parse_cache = parse_cache;
+    }
+
+    pub fn deny_fragile(&self) -> bool {
+        self.deny_fragile
+    }
+
+    pub fn set_deny_fragile(&mut self, deny_fragile: bool) {
+        self.deny_fragile = deny_fragile;
+    }
+
+    /// Whether a todo tracked by `ticket` is an error.
+    pub fn todo_denied(&self, ticket: Option<&str>) -> bool {
+        self.deny_todo
+            .as_ref()
+            .is_some_and(|allowed| ticket.is_none_or(|t| !allowed.iter().any(|a| a == t)))
+    }
+
+    /// Make todos errors, except for those tracked by one of the `allowed`
+    /// tickets.
+    pub fn set_deny_todo(&mut self, allowed: Vec<String>) {
+        self.deny_todo = Some(allowed);
+    }
+
+    pub fn deny_warnings(&self) -> bool {
+        self.deny_warnings
+    }
+
+    pub fn set_deny_warnings(&mut self, deny_warnings: bool) {
+        self.deny_warnings = deny_warnings;
+    }
+
+    pub fn message_format(&self) -> MessageFormat {
+        self.message_format
+    }
+
+    pub fn set_message_format(&mut self, message_format: MessageFormat) {
+        self.message_format = message_format;
+    }
+
+    pub fn theorem_filter(&self) -> Option<&TheoremFilter> {
+        self.theorem_filter.as_ref()
+    }
+
+    pub fn set_theorem_filter(&mut self, theorem_filter: TheoremFilter) {
+        self.theorem_filter = Some(theorem_filter);
+    }
+
+    pub fn preference_notes(&self) -> bool {
+        self.preference_notes
+    }
+
+    pub fn split_step_limit(&self) -> usize {
+        self.split_step_limit
+    }
+
+    pub fn vampire_binary(&self) -> Option<&Path> {
+        self.vampire_binary.as_deref()
+    }
+
     pub fn build_dir(&self) -> &Path {
         &self.build_dir
     }
@@ -95,17 +493,94 @@ impl BookConfig {
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    pub fn badge_style(&self) -> BadgeStyle {
+        self.badge_style
+    }
+
+    pub fn authors(&self) -> &[String] {
+        &self.authors
+    }
+
+    pub fn license(&self) -> Option<&str> {
+        self.license.as_deref()
+    }
+
+    pub fn repository_url(&self) -> Option<&str> {
+        self.repository_url.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+
+    pub fn show_axiom_closure(&self) -> bool {
+        self.show_axiom_closure
+    }
+
+    pub fn templates_dir(&self) -> Option<&Path> {
+        self.templates_dir.as_deref()
+    }
+
+    pub fn separate_corollary_numbering(&self) -> bool {
+        self.separate_corollary_numbering
+    }
+
+    pub fn prose_layout_warnings(&self) -> bool {
+        self.prose_layout_warnings
+    }
+
+    pub fn math_size_limit(&self) -> usize {
+        self.math_size_limit
+    }
+
+    pub fn math_time_budget(&self) -> Duration {
+        self.math_time_budget
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct WatsonConfigFile {
+    project: Option<ProjectConfigFile>,
+    check: Option<CheckConfigFile>,
     book: Option<BookConfigFile>,
 }
 
+#[derive(Debug, Deserialize)]
+struct ProjectConfigFile {
+    roots: Option<Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckConfigFile {
+    lua_entry: Option<String>,
+    lua_stdlib: Option<Vec<LuaStdLib>>,
+    proof_state: Option<ProofStateDisplay>,
+    sentinel_theorem: Option<String>,
+    preference_notes: Option<bool>,
+    split_step_limit: Option<usize>,
+    vampire_binary: Option<String>,
+}
+
 #[derive(Debug, Deserialize)]
 struct BookConfigFile {
     title: Option<String>,
     port: Option<u16>,
+    badge_style: Option<BadgeStyle>,
+    authors: Option<Vec<String>>,
+    license: Option<String>,
+    repository_url: Option<String>,
+    description: Option<String>,
+    language: Option<String>,
+    show_axiom_closure: Option<bool>,
+    separate_corollary_numbering: Option<bool>,
+    prose_layout_warnings: Option<bool>,
+    math_size_limit: Option<usize>,
+    math_time_budget_secs: Option<u64>,
 }
 
 impl WatsonConfigFile {
@@ -55,6 +55,15 @@ impl<'ctx, Data, Handle> InternedArena<Data, Handle> {
             handle
         }
     }
+
+    /// How many distinct values have been interned.
+    pub fn len(&self) -> usize {
+        self.cache.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 pub struct NamedArena<Data, Handle> {
@@ -86,6 +95,10 @@ impl<'ctx, Data, Handle> NamedArena<Data, Handle> {
     {
         self.by_name.lock().unwrap().get(&name).copied()
     }
+
+    pub fn names(&self) -> Vec<Ustr> {
+        self.by_name.lock().unwrap().keys().copied().collect()
+    }
 }
 
 pub trait InternerHandle<'ctx, Data> {
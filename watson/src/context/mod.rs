@@ -1,16 +1,20 @@
-use rustc_hash::FxHashMap;
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::cell::RefCell;
+use ustr::Ustr;
 
 use crate::{
     config::WatsonConfig,
     context::arena::{InternedArena, NamedArena, PlainArena, ScopeArena},
     diagnostics::DiagManager,
     parse::{
-        SourceCache, add_formal_cat,
+        SourceCache, SourceId, add_formal_cat,
         grammar::{BuiltinCats, BuiltinRules, add_builtin_rules},
+        module_graph::ModuleGraph,
         parse_state::{self, Category, CategoryId, ParseState, Rule, RuleId},
-        parse_tree::{ParseTree, ParseTreeId},
+        parse_tree::{ParseTreeShape, ParseTreeShapeId},
     },
     semant::{
+        attributes::BuiltinAttributes,
         commands::{CommandId, CommandInfo},
         custom_grammar::{
             manager::CustomGrammarManager,
@@ -19,9 +23,11 @@ use crate::{
             },
         },
         formal_syntax::{FormalSyntaxCat, FormalSyntaxCatId, FormalSyntaxRule, FormalSyntaxRuleId},
-        fragment::{Fragment, FragmentId},
+        fragment::{Abbreviation, AbbreviationId, Fragment, FragmentId},
         notation::{NotationBinding, NotationBindingId, NotationPattern, NotationPatternId},
         presentation::{BindingNameHints, BindingNameHintsId, Pres, PresId},
+        provenance::Provenance,
+        scope::Scope,
         theorems::{TheoremId, TheoremStatement},
     },
     strings,
@@ -53,6 +59,24 @@ pub struct Ctx<'ctx> {
     pub builtin_rules: BuiltinRules<'ctx>,
     pub single_name_notations: FxHashMap<FormalSyntaxCatId<'ctx>, NotationPatternId<'ctx>>,
     pub annotated_name_cats: FxHashMap<FormalSyntaxCatId<'ctx>, parse_state::CategoryId<'ctx>>,
+
+    /// Every notation declared so far by name, used to find the target of
+    /// an `alias`.
+    pub notations: FxHashMap<Ustr, NotationPatternId<'ctx>>,
+
+    /// The notations used by a definition or a fragment so far. Aliases are
+    /// recorded as the notation they spell.
+    pub used_notations: RefCell<FxHashSet<NotationPatternId<'ctx>>>,
+
+    /// The final scopes of the roots which have been parsed so far. Later
+    /// roots can import these with `module`.
+    pub root_scopes: FxHashMap<SourceId, Scope<'ctx>>,
+
+    /// Which sources import which with `module`.
+    pub module_graph: ModuleGraph,
+
+    /// The builtin attributes like `@[hidden]` given to each command.
+    pub command_attributes: BuiltinAttributes<'ctx>,
 }
 
 impl<'ctx> Ctx<'ctx> {
@@ -60,14 +84,17 @@ impl<'ctx> Ctx<'ctx> {
         let mut parse_state = ParseState::new();
         let mut custom_grammar_manager = CustomGrammarManager::new();
 
-        let sentence_formal_cat = arenas
-            .formal_cats
-            .alloc(*strings::SENTENCE, FormalSyntaxCat::new(*strings::SENTENCE));
+        let sentence_formal_cat = arenas.formal_cats.alloc(
+            *strings::SENTENCE,
+            FormalSyntaxCat::new(*strings::SENTENCE, Provenance::Builtin),
+        );
 
-        let tactic_grammar_cat = arenas
-            .grammar_cats
-            .alloc(*strings::TACTIC, CustomGrammarCat::new(*strings::TACTIC));
+        let tactic_grammar_cat = arenas.grammar_cats.alloc(
+            *strings::TACTIC,
+            CustomGrammarCat::new(*strings::TACTIC, Provenance::Builtin),
+        );
         custom_grammar_manager.use_cat(tactic_grammar_cat);
+        custom_grammar_manager.use_proof_cat(tactic_grammar_cat);
 
         // Create the tactic parse category before calling add_builtin_rules
         let tactic_parse_cat = crate::parse::parse_state::Category::new(
@@ -81,7 +108,7 @@ impl<'ctx> Ctx<'ctx> {
 
         let attribute_grammar_cat = arenas.grammar_cats.alloc(
             *strings::ATTRIBUTE,
-            CustomGrammarCat::new(*strings::ATTRIBUTE),
+            CustomGrammarCat::new(*strings::ATTRIBUTE, Provenance::Builtin),
         );
         custom_grammar_manager.use_cat(attribute_grammar_cat);
 
@@ -111,7 +138,7 @@ impl<'ctx> Ctx<'ctx> {
             scopes: ScopeArena::new(),
             parse_state,
             custom_grammar_manager,
-            diags: DiagManager::new(),
+            diags: DiagManager::new(config.fail_fast(), config.deny_warnings()),
             sources,
             config,
             sentence_cat: sentence_formal_cat,
@@ -119,6 +146,11 @@ impl<'ctx> Ctx<'ctx> {
             builtin_rules,
             single_name_notations: FxHashMap::default(),
             annotated_name_cats: FxHashMap::default(),
+            notations: FxHashMap::default(),
+            used_notations: RefCell::default(),
+            root_scopes: FxHashMap::default(),
+            module_graph: ModuleGraph::new(),
+            command_attributes: BuiltinAttributes::default(),
         };
 
         add_formal_cat(sentence_formal_cat, &mut ctx);
@@ -128,7 +160,7 @@ impl<'ctx> Ctx<'ctx> {
 }
 
 pub struct Arenas<'ctx> {
-    pub parse_forest: InternedArena<ParseTree<'ctx>, ParseTreeId<'ctx>>,
+    pub parse_forest: InternedArena<ParseTreeShape<'ctx>, ParseTreeShapeId<'ctx>>,
     pub parse_cats: NamedArena<Category<'ctx>, CategoryId<'ctx>>,
     pub parse_rules: PlainArena<Rule<'ctx>, RuleId<'ctx>>,
     pub formal_cats: NamedArena<FormalSyntaxCat, FormalSyntaxCatId<'ctx>>,
@@ -136,6 +168,7 @@ pub struct Arenas<'ctx> {
     pub notations: PlainArena<NotationPattern<'ctx>, NotationPatternId<'ctx>>,
     pub notation_bindings: InternedArena<NotationBinding<'ctx>, NotationBindingId<'ctx>>,
     pub fragments: InternedArena<Fragment<'ctx>, FragmentId<'ctx>>,
+    pub abbreviations: PlainArena<Abbreviation<'ctx>, AbbreviationId<'ctx>>,
     pub presentations: InternedArena<Pres<'ctx>, PresId<'ctx>>,
     pub binding_name_hints: InternedArena<BindingNameHints, BindingNameHintsId<'ctx>>,
     pub grammar_cats: NamedArena<CustomGrammarCat, CustomGrammarCatId<'ctx>>,
@@ -155,6 +188,7 @@ impl<'ctx> Arenas<'ctx> {
             notations: PlainArena::new(),
             notation_bindings: InternedArena::new(),
             fragments: InternedArena::new(),
+            abbreviations: PlainArena::new(),
             presentations: InternedArena::new(),
             binding_name_hints: InternedArena::new(),
             grammar_cats: NamedArena::new(),
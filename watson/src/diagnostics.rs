@@ -1,36 +1,102 @@
+use crate::config::{MessageFormat, ProofStateDisplay};
 use crate::context::Ctx;
+use crate::parse::earley::escape_str;
 use crate::parse::elaborator::BindingResolution;
+use crate::parse::module_graph::ModuleImport;
+use crate::parse::parse_state::CategoryId;
 use crate::parse::parse_state::ParseAtomPattern;
-use crate::parse::source_cache::SourceDecl;
+use crate::parse::parse_state::RuleId;
+use crate::parse::source_cache::{SourceDecl, source_id_to_path};
 use crate::parse::{Location, SourceCache, SourceId, Span};
-use crate::semant::notation::{NotationBindingId, NotationPatternSource};
+use crate::semant::attributes::{AttributeValue, BuiltinAttributeKind};
+use crate::semant::custom_grammar::syntax::CustomGrammarRuleId;
+use crate::semant::notation::{NotationBindingId, NotationPatternId};
 use crate::semant::parse_fragment;
-use crate::semant::tactic::tactic_info::{TacticInfo, TacticInfoStep};
-use crate::semant::theorems::TheoremId;
-use crate::util::ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_RESET, ANSI_YELLOW};
+use crate::semant::presentation::{PresFrag, PresId, differing_subterm};
+use crate::semant::provenance::Provenance;
+use crate::semant::tactic::tactic_info::{StepOrigin, TacticInfo, TacticInfoStep};
+use crate::semant::theorems::{PresFact, TheoremId};
+use crate::strings;
+use crate::util::ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_GREEN, ANSI_RESET, ANSI_YELLOW, strip_ansi};
+use crate::util::plural;
+use crate::util::{edit_distance, similar_names};
 use annotate_snippets::{Level, Message, Renderer, Snippet};
 use itertools::Itertools;
-use std::path::Path;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::vec;
 use ustr::Ustr;
 
 pub type WResult<'ctx, T> = Result<T, Vec<Diagnostic<'ctx>>>;
 
+/// How the project's `renderFrag` hook rendered the fragments shown in a
+/// proof state. Fragments without an entry are printed as usual.
+pub type FragRenderings<'ctx> = FxHashMap<PresFrag<'ctx>, String>;
+
+/// The stage of the pipeline a diagnostic was reported in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DiagStage {
+    Parse,
+    Check,
+}
+
 pub struct DiagManager<'ctx> {
+    /// The diagnostics grouped by stage, each group in the order they were
+    /// reported.
     diags: Vec<Diagnostic<'ctx>>,
+    /// The stage new diagnostics are tagged with.
+    stage: DiagStage,
+    /// Whether to stop at the first error rather than carrying on to find as
+    /// many as possible.
+    fail_fast: bool,
+    /// Whether warnings are reported as errors.
+    deny_warnings: bool,
 }
 
 impl<'ctx> DiagManager<'ctx> {
-    pub fn new() -> Self {
-        Self { diags: Vec::new() }
+    pub fn new(fail_fast: bool, deny_warnings: bool) -> Self {
+        Self {
+            diags: Vec::new(),
+            stage: DiagStage::Parse,
+            fail_fast,
+            deny_warnings,
+        }
+    }
+
+    pub fn add_diag(&mut self, mut diag: Diagnostic<'ctx>) {
+        // With `--fail-fast` only the first error is reported, even when
+        // the command or proof it is in reports more.
+        if self.fail_fast && self.has_errors() {
+            return;
+        }
+        if self.deny_warnings && diag.level() == DiagnosticLevel::Warning {
+            diag = diag.denied();
+        }
+        diag.stage = self.stage;
+        let at = self
+            .diags
+            .partition_point(|other| other.stage <= diag.stage);
+        self.diags.insert(at, diag);
     }
 
-    pub fn add_diag(&mut self, diag: Diagnostic<'ctx>) {
-        self.diags.push(diag);
+    /// Tag the diagnostics reported from now on with `stage`.
+    pub fn set_stage(&mut self, stage: DiagStage) {
+        self.stage = stage;
     }
 
     pub fn add_diags(&mut self, diags: Vec<Diagnostic<'ctx>>) {
-        self.diags.extend(diags);
+        for diag in diags {
+            self.add_diag(diag);
+        }
+    }
+
+    /// Take back the diagnostics reported after the first `count`. Only
+    /// diagnostics of the latest stage can have been reported since then.
+    pub fn split_off(&mut self, count: usize) -> Vec<Diagnostic<'ctx>> {
+        self.diags.split_off(count)
     }
 
     pub fn clear_errors(&mut self) {
@@ -38,16 +104,62 @@ impl<'ctx> DiagManager<'ctx> {
     }
 
     pub fn print_errors(&self, ctx: &Ctx) {
-        let renderer = Renderer::styled();
-        for diag in &self.diags {
-            let msg = diag.to_message(&ctx.sources);
-            println!();
-            println!("{}", renderer.render(msg));
-        }
+        print_diagnostics(&self.diags, ctx);
     }
 
+    /// Whether any error has been reported. Warnings don't count.
     pub fn has_errors(&self) -> bool {
-        !self.diags.is_empty()
+        self.diags.iter().any(|diag| diag.is_error())
+    }
+
+    /// Whether to keep parsing, checking or building after the diagnostics
+    /// reported so far. Every phase checks this between the things it
+    /// processes, so with `--fail-fast` nothing runs after the first error.
+    pub fn should_continue(&self) -> bool {
+        !self.fail_fast || !self.has_errors()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic<'ctx>> {
+        self.diags.iter()
+    }
+
+    /// The diagnostics reported in `stage`, in the order they were reported.
+    pub fn of_stage(&self, stage: DiagStage) -> &[Diagnostic<'ctx>] {
+        let start = self.diags.partition_point(|diag| diag.stage < stage);
+        let end = self.diags.partition_point(|diag| diag.stage <= stage);
+        &self.diags[start..end]
+    }
+
+    pub fn count(&self) -> usize {
+        self.diags.len()
+    }
+}
+
+pub fn print_diagnostics(diags: &[Diagnostic], ctx: &Ctx) {
+    let mut out = String::new();
+    write_diagnostics(diags, ctx, &mut out);
+    print!("{out}");
+}
+
+/// Render diagnostics to `out` the way [`print_diagnostics`] prints them.
+pub fn write_diagnostics(diags: &[Diagnostic], ctx: &Ctx, out: &mut String) {
+    if ctx.config.message_format() == MessageFormat::Json {
+        for diag in diags {
+            writeln!(
+                out,
+                "{}",
+                serde_json::to_string(&diag.to_json(ctx)).unwrap()
+            )
+            .unwrap();
+        }
+        return;
+    }
+
+    let renderer = Renderer::styled();
+    for diag in diags {
+        let msg = diag.to_message(&ctx.sources, ctx.config.proof_state());
+        writeln!(out).unwrap();
+        writeln!(out, "{}", renderer.render(msg)).unwrap();
     }
 }
 
@@ -56,12 +168,16 @@ pub struct Diagnostic<'ctx> {
     main: DiagnosticPart,
     parts: Vec<DiagnosticPart>,
     proof: Option<DiagnosticInProof<'ctx>>,
+    stage: DiagStage,
 }
 
 #[derive(Debug, Clone)]
 pub struct DiagnosticInProof<'ctx> {
     thm: TheoremId<'ctx>,
     tactic_info: TacticInfo<'ctx>,
+    /// The steps recorded by the kernel, if the proof state was available.
+    kernel_steps: Option<im::Vector<TacticInfoStep<'ctx>>>,
+    renderings: FragRenderings<'ctx>,
 }
 
 #[derive(Debug, Clone)]
@@ -80,15 +196,16 @@ impl DiagnosticPart {
         }
     }
 
-    pub fn to_message<'a>(&self, sources: &'a SourceCache) -> Message<'a> {
-        let level = match self.level {
-            DiagnosticLevel::Error => Level::Error,
-            DiagnosticLevel::Warning => Level::Warning,
-            DiagnosticLevel::Info => Level::Info,
-        };
-        level
-            .title(self.title)
-            .snippets(self.spans.iter().map(|s| s.to_snippet(sources)))
+    pub fn level(&self) -> DiagnosticLevel {
+        self.level
+    }
+
+    pub fn title(&self) -> &'static str {
+        self.title
+    }
+
+    pub fn spans(&self) -> &[DiagnosticSpan] {
+        &self.spans
     }
 }
 
@@ -124,20 +241,23 @@ impl DiagnosticSpan {
         }
     }
 
-    pub fn to_snippet<'a>(&self, sources: &'a SourceCache) -> Snippet<'a> {
-        let anno = self
-            .level
-            .to_level()
-            .span(self.span.bytes())
-            .label(self.msg);
-        Snippet::source(sources.get_text(self.span.source()).as_str())
-            .origin(self.span.source().name().as_str())
-            .annotation(anno)
-            .fold(true)
+    pub fn level(&self) -> DiagnosticLevel {
+        self.level
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// The label shown under the span. Empty if the span is labelled with
+    /// the title of its part.
+    pub fn label(&self) -> &'static str {
+        self.msg
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum DiagnosticLevel {
     Error,
     Warning,
@@ -145,7 +265,7 @@ pub enum DiagnosticLevel {
 }
 
 impl DiagnosticLevel {
-    pub fn to_level(&self) -> Level {
+    pub fn to_level(self) -> Level {
         match self {
             DiagnosticLevel::Error => Level::Error,
             DiagnosticLevel::Warning => Level::Warning,
@@ -161,6 +281,7 @@ impl<'ctx> Diagnostic<'ctx> {
             main: DiagnosticPart::new(DiagnosticLevel::Error, title, spans),
             parts: Vec::new(),
             proof: None,
+            stage: DiagStage::Parse,
         }
     }
 
@@ -170,6 +291,17 @@ impl<'ctx> Diagnostic<'ctx> {
             main: DiagnosticPart::new(DiagnosticLevel::Warning, title, spans),
             parts: Vec::new(),
             proof: None,
+            stage: DiagStage::Parse,
+        }
+    }
+
+    pub fn new_info(title: &str, spans: Vec<DiagnosticSpan>) -> Self {
+        let title = Ustr::from(title).as_str();
+        Self {
+            main: DiagnosticPart::new(DiagnosticLevel::Info, title, spans),
+            parts: Vec::new(),
+            proof: None,
+            stage: DiagStage::Parse,
         }
     }
 
@@ -180,6 +312,66 @@ impl<'ctx> Diagnostic<'ctx> {
         self
     }
 
+    pub fn is_error(&self) -> bool {
+        self.main.level == DiagnosticLevel::Error
+    }
+
+    /// The warning as an error, for `--deny-warnings`.
+    fn denied(mut self) -> Self {
+        self.main.level = DiagnosticLevel::Error;
+        for span in &mut self.main.spans {
+            if span.level == DiagnosticLevel::Warning {
+                span.level = DiagnosticLevel::Error;
+            }
+        }
+        self.with_info("warnings are errors with `--deny-warnings`", Vec::new())
+    }
+
+    pub fn level(&self) -> DiagnosticLevel {
+        self.main.level
+    }
+
+    pub fn title(&self) -> &'static str {
+        self.main.title
+    }
+
+    /// The first span of the main message, if it has one.
+    pub fn primary_span(&self) -> Option<Span> {
+        self.main.spans.first().map(|span| span.span)
+    }
+
+    /// The main message followed by the notes added to it.
+    pub fn parts(&self) -> impl Iterator<Item = &DiagnosticPart> {
+        std::iter::once(&self.main).chain(&self.parts)
+    }
+
+    /// The theorem whose proof this diagnostic was reported while checking.
+    pub fn theorem(&self) -> Option<TheoremId<'ctx>> {
+        self.proof.as_ref().map(|in_proof| in_proof.thm)
+    }
+
+    /// The proof state when the diagnostic was reported, if it was reported
+    /// in a proof and the proof state is shown at all. It is colored for the
+    /// terminal.
+    pub fn proof_state(&self, sources: &SourceCache, display: ProofStateDisplay) -> Option<String> {
+        let in_proof = self.proof.as_ref()?;
+        render_tactic_info(
+            &in_proof.tactic_info,
+            in_proof.kernel_steps.as_ref(),
+            display,
+            &in_proof.renderings,
+            sources,
+        )
+    }
+
+    /// Render the diagnostic the same way as on the terminal but without
+    /// colors.
+    pub fn render_plain(&self, ctx: &Ctx) -> String {
+        let msg = self.to_message(&ctx.sources, ctx.config.proof_state());
+        // The proof state is colored by hand rather than by the renderer.
+        strip_ansi(&Renderer::plain().render(msg).to_string())
+    }
+
     pub fn with_info(mut self, msg: &str, spans: Vec<DiagnosticSpan>) -> Self {
         let msg = Ustr::from(msg).as_str();
         self.parts
@@ -187,76 +379,569 @@ impl<'ctx> Diagnostic<'ctx> {
         self
     }
 
-    pub fn in_proof(mut self, thm: TheoremId<'ctx>, tactic_info: TacticInfo<'ctx>) -> Self {
-        self.proof = Some(DiagnosticInProof { thm, tactic_info });
+    /// Add an info part pointing at where a grammar item was declared.
+    pub fn with_decl_info(self, msg: &str, provenance: Provenance) -> Self {
+        match (provenance.span(), provenance.kind()) {
+            (Some(span), Some(kind)) => {
+                let label = format!("declared by this `{}` command", kind.keyword());
+                self.with_info(msg, vec![DiagnosticSpan::new_info(&label, span)])
+            }
+            _ => self.with_info(&format!("{msg} (builtin)"), Vec::new()),
+        }
+    }
+
+    pub fn in_proof(
+        mut self,
+        thm: TheoremId<'ctx>,
+        tactic_info: TacticInfo<'ctx>,
+        kernel_steps: Option<im::Vector<TacticInfoStep<'ctx>>>,
+    ) -> Self {
+        self.proof = Some(DiagnosticInProof {
+            thm,
+            tactic_info,
+            kernel_steps,
+            renderings: FragRenderings::default(),
+        });
         self
     }
 
-    pub fn to_message<'a>(&self, sources: &'a SourceCache) -> Message<'a> {
-        let mut msg = self.main.to_message(sources);
+    /// Print the fragments in the proof state the way the project's
+    /// `renderFrag` hook rendered them.
+    pub fn with_frag_renderings(mut self, renderings: FragRenderings<'ctx>) -> Self {
+        if let Some(in_proof) = &mut self.proof {
+            in_proof.renderings = renderings;
+        }
+        self
+    }
 
+    pub fn to_message<'a>(
+        &self,
+        sources: &'a SourceCache,
+        proof_state: ProofStateDisplay,
+    ) -> Message<'a> {
+        // Every span is shown under the main message with one snippet per
+        // file, so a file several parts point into is only shown once. A span
+        // without its own label is labelled with the title of its part.
+        let mut annotations = Vec::new();
+        let mut footers = Vec::new();
+        annotations.extend(self.main.spans.iter().map(|span| (*span, span.msg)));
         for part in &self.parts {
-            msg = msg.footer(part.to_message(sources))
+            let mut title_shown = false;
+            for span in &part.spans {
+                if span.msg.is_empty() {
+                    title_shown = true;
+                    annotations.push((*span, part.title));
+                } else {
+                    annotations.push((*span, span.msg));
+                }
+            }
+            if !title_shown {
+                footers.push(part.level.to_level().title(part.title));
+            }
+        }
+
+        // Spans in sources that aren't loaded can't be shown, so their labels
+        // are given as footers instead.
+        let (shown, missing): (Vec<_>, Vec<_>) = annotations
+            .into_iter()
+            .partition(|(span, _)| sources.has_source(span.span.source()));
+
+        // The main spans come first, so the primary file is shown first and
+        // the rest follow in the order they are first mentioned.
+        let files = shown.iter().map(|(span, _)| span.span.source()).unique();
+        let snippets = files.map(|source| {
+            let text = sources.get_text(source).as_str();
+            let annotations = shown
+                .iter()
+                .filter(|(span, _)| span.span.source() == source)
+                .sorted_by_key(|(span, _)| span.span.bytes().start)
+                .map(|(span, label)| {
+                    let (range, at_end) = renderable_range(span.span, text);
+                    let label = match (at_end, *label) {
+                        (false, label) => label,
+                        (true, "") => "end of input",
+                        (true, label) => Ustr::from(&format!("{label} (at end of input)")).as_str(),
+                    };
+                    span.level.to_level().span(range).label(label)
+                });
+            Snippet::source(text)
+                .origin(source.name().as_str())
+                .annotations(annotations)
+                .fold(true)
+        });
+        let mut msg = self
+            .main
+            .level
+            .to_level()
+            .title(self.main.title)
+            .snippets(snippets);
+
+        for (span, label) in missing {
+            let file = span.span.source().name();
+            let title = match label {
+                "" => format!("in `{file}`"),
+                label => format!("{label} (in `{file}`)"),
+            };
+            let title = Ustr::from(&title);
+            msg = msg.footer(span.level.to_level().title(title.as_str()));
         }
+        msg = msg.footers(footers);
 
         if let Some(in_proof) = &self.proof {
             let title = format!("While checking theorem `{}`", in_proof.thm.name());
             let title = Ustr::from(&title);
             msg = msg.footer(Level::Help.title(title.as_str()));
 
-            let title = render_tactic_info(&in_proof.tactic_info);
-            let title = Ustr::from(&title);
-            msg = msg.footer(Level::Help.title(title.as_str()));
+            if let Some(title) = self.proof_state(sources, proof_state) {
+                let title = Ustr::from(&title);
+                msg = msg.footer(Level::Help.title(title.as_str()));
+            }
         }
 
         msg
     }
+
+    /// The diagnostic as printed with `--message-format json`.
+    fn to_json(&self, ctx: &Ctx) -> JsonDiagnostic {
+        let parts = self
+            .parts()
+            .map(|part| JsonPart {
+                severity: part.level(),
+                title: part.title(),
+                spans: part
+                    .spans()
+                    .iter()
+                    .map(|span| JsonSpan::new(span, ctx))
+                    .collect(),
+            })
+            .collect();
+        let proof = self.theorem().map(|thm| JsonProof {
+            theorem: thm.name().to_string(),
+            proof_state: self
+                .proof_state(&ctx.sources, ctx.config.proof_state())
+                .map(|state| strip_ansi(&state)),
+        });
+
+        JsonDiagnostic {
+            title: self.title(),
+            severity: self.level(),
+            parts,
+            proof,
+        }
+    }
+}
+
+/// A diagnostic as one line of JSON, for editors and other tools.
+#[derive(Serialize)]
+struct JsonDiagnostic {
+    title: &'static str,
+    severity: DiagnosticLevel,
+    /// The main message first, then the notes added to it.
+    parts: Vec<JsonPart>,
+    proof: Option<JsonProof>,
+}
+
+#[derive(Serialize)]
+struct JsonPart {
+    severity: DiagnosticLevel,
+    title: &'static str,
+    spans: Vec<JsonSpan>,
+}
+
+#[derive(Serialize)]
+struct JsonSpan {
+    severity: DiagnosticLevel,
+    /// The name of the source the span is in.
+    source: String,
+    /// The file the source was loaded from, if it came from one.
+    file: Option<PathBuf>,
+    /// The byte range of the span in the source.
+    start: usize,
+    end: usize,
+    /// Where the span starts and ends, if the source is loaded.
+    position: Option<JsonPosition>,
+    /// Empty if the span is labelled with the title of its part.
+    label: &'static str,
+}
+
+/// Lines and columns count from 1, and columns count characters.
+#[derive(Serialize)]
+struct JsonPosition {
+    line: u32,
+    column: u32,
+    end_line: u32,
+    end_column: u32,
+}
+
+#[derive(Serialize)]
+struct JsonProof {
+    theorem: String,
+    /// The proof state as printed on the terminal, without colors.
+    proof_state: Option<String>,
+}
+
+impl JsonSpan {
+    fn new(span: &DiagnosticSpan, ctx: &Ctx) -> Self {
+        let source = span.span().source();
+        let loaded = ctx.sources.has_source(source);
+        let position = loaded.then(|| {
+            let line_cols = ctx
+                .sources
+                .line_cols(&[span.span().start(), span.span().end()]);
+            JsonPosition {
+                line: line_cols[0].0,
+                column: line_cols[0].1,
+                end_line: line_cols[1].0,
+                end_column: line_cols[1].1,
+            }
+        });
+        let file = match loaded.then(|| ctx.sources.get_decl(source)) {
+            Some(SourceDecl::Root | SourceDecl::Module(_)) => {
+                let (standalone, dir) = source_id_to_path(source, ctx.config.math_dir());
+                [standalone, dir].into_iter().find(|path| path.exists())
+            }
+            _ => None,
+        };
+
+        Self {
+            severity: span.level(),
+            source: source.name().to_string(),
+            file,
+            start: span.span().bytes().start,
+            end: span.span().bytes().end,
+            position,
+            label: span.label(),
+        }
+    }
+}
+
+/// Literals which are easy to mistype, with what gets typed instead and a
+/// note on why it's wrong.
+const LITERAL_TYPOS: &[(&str, &str, &str)] = &[
+    (
+        "::=",
+        ":=",
+        "`:=` is only used in definitions and template defaults",
+    ),
+    ("|-", "|=", "the turnstile is written `|-`"),
+    ("|-", "⊢", "the turnstile is written `|-`"),
+    ("->", "→", "arrows are written `->`"),
+];
+
+/// Unicode characters which look like the ASCII ones literals are written
+/// with.
+const CONFUSABLES: &[(char, char, &str)] = &[
+    ('–', '-', "an en dash"),
+    ('—', '-', "an em dash"),
+    ('−', '-', "a minus sign"),
+    ('‐', '-', "a Unicode hyphen"),
+    ('：', ':', "a full-width colon"),
+    ('∶', ':', "a ratio sign"),
+    ('＝', '=', "a full-width equals sign"),
+    ('∣', '|', "a divides sign"),
+];
+
+/// A suggestion for when the text the parser failed at looks like a
+/// mistyped version of one of the literals it expected. Known typos win over
+/// Unicode lookalikes, which win over anything else one edit away.
+fn literal_typo_help(found: &str, possible_atoms: &[ParseAtomPattern]) -> Option<String> {
+    let literals = possible_atoms
+        .iter()
+        .filter_map(|atom| match atom {
+            ParseAtomPattern::Lit(lit) => Some(lit.as_str()),
+            _ => None,
+        })
+        // If the literal is there the problem is somewhere else.
+        .filter(|lit| !found.starts_with(lit))
+        .collect_vec();
+
+    for &lit in &literals {
+        if let Some((_, _, note)) = LITERAL_TYPOS
+            .iter()
+            .find(|(typo_of, typo, _)| *typo_of == lit && found.starts_with(typo))
+        {
+            return Some(format!("did you mean `{lit}`? note {note}"));
+        }
+    }
+
+    for &lit in &literals {
+        let mut notes = Vec::new();
+        let replaced: String = found
+            .chars()
+            .take(lit.chars().count())
+            .map(
+                |c| match CONFUSABLES.iter().find(|(confusable, ..)| *confusable == c) {
+                    Some(&(_, ascii, name)) => {
+                        notes.push(format!("`{c}` is {name}"));
+                        ascii
+                    }
+                    None => c,
+                },
+            )
+            .collect();
+        if !notes.is_empty() && replaced == lit {
+            let notes = notes.into_iter().unique().join(" and ");
+            return Some(format!("did you mean `{lit}`? note {notes}"));
+        }
+    }
+
+    // Single characters are one edit away from any other punctuation.
+    let token: String = found
+        .chars()
+        .take_while(|c| !c.is_whitespace() && !c.is_alphanumeric())
+        .collect();
+    literals
+        .iter()
+        .find(|lit| lit.chars().count() > 1 && edit_distance(&token, lit) == 1)
+        .map(|lit| format!("did you mean `{lit}`?"))
+}
+
+/// The bytes of `text` to annotate for `span`, and whether that is the end of
+/// the input. The renderer panics or points at the wrong line for spans which
+/// are backwards or past the end of the text, so those are fixed here rather
+/// than hiding the actual error behind a crash. Empty spans are widened to
+/// the next character, or the last one at the end of the input.
+fn renderable_range(span: Span, text: &str) -> (Range<usize>, bool) {
+    let (from, to) = (span.start().byte_offset(), span.end().byte_offset());
+    let len = text.len();
+
+    let mut start = from.min(to).min(len);
+    let mut end = from.max(to).min(len);
+    while !text.is_char_boundary(start) {
+        start -= 1;
+    }
+    while !text.is_char_boundary(end) {
+        end += 1;
+    }
+
+    if start < end {
+        return (start..end, false);
+    }
+
+    match text[start..].chars().next() {
+        Some(next) => (start..start + next.len_utf8(), false),
+        None => {
+            let last = text.chars().next_back().map_or(0, char::len_utf8);
+            (len - last..len, true)
+        }
+    }
 }
 
-fn render_tactic_info<'ctx>(tactic: &TacticInfo<'ctx>) -> String {
+/// The number of most recent steps whose facts are listed first in the proof
+/// state.
+const RECENT_STEPS: usize = 2;
+
+/// How many characters of unchanged text to keep on either side of the changed
+/// part of a goal.
+const GOAL_DIFF_CONTEXT: usize = 16;
+
+fn render_tactic_info<'ctx>(
+    tactic: &TacticInfo<'ctx>,
+    kernel_steps: Option<&im::Vector<TacticInfoStep<'ctx>>>,
+    display: ProofStateDisplay,
+    renderings: &FragRenderings<'ctx>,
+    sources: &SourceCache,
+) -> Option<String> {
+    if display == ProofStateDisplay::None {
+        return None;
+    }
+
+    // The kernel's record can't be missing steps so prefer it when we have it.
+    let steps = kernel_steps.unwrap_or(tactic.steps());
+    let tags = origin_tags(steps, sources);
+    // Hypotheses aren't added by a step, so they are never recent.
+    let recent_cnt = steps
+        .iter()
+        .rev()
+        .take(RECENT_STEPS)
+        .take_while(|step| !matches!(step, TacticInfoStep::Hypothesis(_)))
+        .count();
+    let earlier_cnt = steps.len() - recent_cnt;
+
     let mut res = String::new();
 
     res += "Proof state:\n";
 
-    for step in tactic.steps() {
-        match step {
-            TacticInfoStep::Hypothesis(f) => {
-                res += ANSI_GRAY;
-                res += "> ";
-                res += ANSI_RESET;
-                res += &f.print();
+    if recent_cnt > 0 {
+        res += &format!("{ANSI_GRAY}recent:{ANSI_RESET}\n");
+        for (step, tag) in steps.iter().zip(&tags).skip(earlier_cnt) {
+            res += &format!("{ANSI_GREEN}*{ANSI_RESET}");
+            res += &render_tactic_step(step, tag.as_deref(), renderings);
+            res += "\n";
+        }
+        if earlier_cnt > 0 && display == ProofStateDisplay::Full {
+            res += &format!("{ANSI_GRAY}earlier:{ANSI_RESET}\n");
+        }
+    }
+
+    match display {
+        ProofStateDisplay::Full => {
+            for (step, tag) in steps.iter().zip(&tags).take(earlier_cnt) {
+                res += " ";
+                res += &render_tactic_step(step, tag.as_deref(), renderings);
+                res += "\n";
             }
-            TacticInfoStep::Assume(f) => {
-                res += ANSI_GRAY;
-                res += "? ";
-                res += ANSI_RESET;
-                res += &f.print()
+        }
+        ProofStateDisplay::Recent if earlier_cnt > 0 => {
+            res += &format!(
+                "{ANSI_GRAY}({earlier_cnt} earlier fact{} hidden){ANSI_RESET}\n",
+                plural(earlier_cnt)
+            );
+        }
+        _ => {}
+    }
+
+    res += &format!("{ANSI_YELLOW}{ANSI_BOLD}⊢{ANSI_RESET} ");
+
+    // If a step changed the goal, highlight what changed. A goal the hook
+    // rendered can't be highlighted since its text may not match the tree.
+    let goal = tactic.goal();
+    let diff = tactic
+        .previous_goal()
+        .filter(|prev| !renderings.contains_key(prev) && !renderings.contains_key(&goal))
+        .and_then(|prev| Some((prev, differing_subterm(prev.pres(), goal.pres())?)));
+    match diff {
+        Some((prev, path)) => {
+            res += &render_goal_diff(goal.pres(), &path);
+            res += &format!("\n{ANSI_GRAY}was{ANSI_RESET} ");
+            res += &render_goal_diff(prev.pres(), &path);
+        }
+        None => res += &print_frag(goal, renderings),
+    }
+
+    Some(res)
+}
+
+/// A short tag for where each step's fact came from, like `hyp 2`, `by
+/// lemma_assoc` or `from line 41`, if that is known.
+fn origin_tags(steps: &im::Vector<TacticInfoStep>, sources: &SourceCache) -> Vec<Option<String>> {
+    // The tactics which can be tagged with their line, in order.
+    let tactic_span = |step: &TacticInfoStep| match step {
+        TacticInfoStep::Assume(_, Some(StepOrigin::Tactic(span)))
+        | TacticInfoStep::Deduce(_, Some(StepOrigin::Tactic(span)))
+            if sources.has_source(span.source()) =>
+        {
+            Some(*span)
+        }
+        _ => None,
+    };
+    let starts: Vec<_> = (steps.iter())
+        .filter_map(|step| tactic_span(step).map(|span| span.start()))
+        .collect();
+    let mut lines = sources.line_cols(&starts).into_iter().map(|(line, _)| line);
+
+    let mut hypotheses = 0;
+    steps
+        .iter()
+        .map(|step| match step {
+            TacticInfoStep::Hypothesis(_) => {
+                hypotheses += 1;
+                Some(format!("hyp {hypotheses}"))
+            }
+            _ if tactic_span(step).is_some() => {
+                Some(format!("from line {}", lines.next().unwrap()))
             }
-            TacticInfoStep::Deduce(f) => {
-                res += "  ";
-                res += &f.print()
+            TacticInfoStep::Assume(_, origin) | TacticInfoStep::Deduce(_, origin) => {
+                match (*origin)? {
+                    StepOrigin::Theorem(theorem) => Some(format!("by {}", theorem.name())),
+                    StepOrigin::Tactic(_) => None,
+                }
             }
-            TacticInfoStep::Let(binding, replacement) => {
-                res += "  ";
-                res += &binding.print();
+            TacticInfoStep::Let(..) => None,
+        })
+        .collect()
+}
+
+fn render_tactic_step<'ctx>(
+    step: &TacticInfoStep<'ctx>,
+    tag: Option<&str>,
+    renderings: &FragRenderings<'ctx>,
+) -> String {
+    let mut res = String::new();
+
+    match step {
+        TacticInfoStep::Hypothesis(f) => {
+            res += ANSI_GRAY;
+            res += "> ";
+            res += ANSI_RESET;
+            res += &print_fact(f, renderings);
+        }
+        TacticInfoStep::Assume(f, _) => {
+            res += ANSI_GRAY;
+            res += "? ";
+            res += ANSI_RESET;
+            res += &print_frag(*f, renderings)
+        }
+        TacticInfoStep::Deduce(f, _) => {
+            res += "  ";
+            res += &print_fact(f, renderings)
+        }
+        TacticInfoStep::Let(binding, replacement) => {
+            res += "  ";
+            res += &binding.print();
+            res += ANSI_GRAY;
+            res += " : ";
+            res += &binding.pattern().cat().name();
+            res += ANSI_RESET;
+            if let Some(replacement) = replacement {
                 res += ANSI_GRAY;
-                res += " : ";
-                res += &binding.pattern().cat().name();
+                res += " := ";
                 res += ANSI_RESET;
-                if let Some(replacement) = replacement {
-                    res += ANSI_GRAY;
-                    res += " := ";
-                    res += ANSI_RESET;
-                    res += &replacement.print();
-                }
+                res += &print_frag(*replacement, renderings);
             }
         }
+    }
+
+    if let Some(tag) = tag {
+        res += &format!(" {ANSI_GRAY}[{tag}]{ANSI_RESET}");
+    }
+    res
+}
 
-        res += "\n";
+/// Print `frag` the way the project's `renderFrag` hook rendered it, if it
+/// did.
+pub fn print_frag<'ctx>(frag: PresFrag<'ctx>, renderings: &FragRenderings<'ctx>) -> String {
+    match renderings.get(&frag) {
+        Some(rendered) => rendered.clone(),
+        None => frag.print(),
     }
+}
 
-    res += &format!("{ANSI_YELLOW}{ANSI_BOLD}⊢{ANSI_RESET} ");
-    res += &tactic.goal().print();
+fn print_fact<'ctx>(fact: &PresFact<'ctx>, renderings: &FragRenderings<'ctx>) -> String {
+    let conclusion = print_frag(fact.conclusion(), renderings);
+    match fact.assumption() {
+        Some(assumption) => format!("{} |- {conclusion}", print_frag(assumption, renderings)),
+        None => conclusion,
+    }
+}
+
+/// Print a goal with the subterm at `path` highlighted, eliding long stretches
+/// of unchanged text around it.
+fn render_goal_diff(goal: PresId, path: &[usize]) -> String {
+    let mut changed = 0..0;
+    let text = goal.print_with(&mut |node_path, range| {
+        if node_path == path {
+            changed = range;
+        }
+    });
+
+    let prefix: Vec<char> = text[..changed.start].chars().collect();
+    let suffix: Vec<char> = text[changed.end..].chars().collect();
+
+    let mut res = String::new();
+    if prefix.len() > GOAL_DIFF_CONTEXT {
+        res += "…";
+        res.extend(&prefix[prefix.len() - GOAL_DIFF_CONTEXT..]);
+    } else {
+        res.extend(&prefix);
+    }
+    res += &format!("{ANSI_YELLOW}{}{ANSI_RESET}", &text[changed]);
+    if suffix.len() > GOAL_DIFF_CONTEXT {
+        res.extend(&suffix[..GOAL_DIFF_CONTEXT]);
+        res += "…";
+    } else {
+        res.extend(&suffix);
+    }
 
     res
 }
@@ -266,40 +951,177 @@ impl<'ctx> Diagnostic<'ctx> {
         source_id: SourceId,
         decl: Span,
         previous_decl: SourceDecl,
+        same_file: Option<(SourceId, &Path)>,
     ) -> WResult<'ctx, T> {
         let mut diag = Diagnostic::new(
             &format!("redeclaration of module `{}`", source_id.name()),
             vec![DiagnosticSpan::new_error("", decl)],
         );
 
-        if let SourceDecl::Module(prev_span) = previous_decl {
+        if let Some((alias, path)) = same_file {
+            diag = diag.with_info(
+                &format!(
+                    "`{}` is also read from `{}`, so it names the same module",
+                    alias.name(),
+                    path.display()
+                ),
+                Vec::new(),
+            );
+        }
+
+        match previous_decl {
+            SourceDecl::Module(prev_span) => {
+                diag = diag.with_info(
+                    "module previously declared here",
+                    vec![DiagnosticSpan::new_error("", prev_span)],
+                );
+            }
+            SourceDecl::Root => {
+                diag = diag.with_info(
+                    "this is a project root. roots can only import roots listed before them",
+                    Vec::new(),
+                );
+            }
+            SourceDecl::LuaSnippet
+            | SourceDecl::BookExample
+            | SourceDecl::ReplInput
+            | SourceDecl::Generated => {}
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn err_module_cycle<T>(chain: &[ModuleImport], import: ModuleImport) -> WResult<'ctx, T> {
+        let title = if chain.is_empty() {
+            format!("module `{}` imports itself", import.imported.name())
+        } else {
+            let names = [import.imported]
+                .into_iter()
+                .chain(chain.iter().map(|step| step.imported))
+                .chain([import.imported])
+                .map(|source| format!("`{}`", source.name()))
+                .join(" -> ");
+            format!("cyclic module imports: {names}")
+        };
+        let mut diag = Diagnostic::new(
+            &title,
+            vec![DiagnosticSpan::new_error(
+                "this module is still being loaded",
+                import.span,
+            )],
+        );
+
+        for step in chain {
+            diag = diag.with_info(
+                &format!(
+                    "`{}` imports `{}` here",
+                    step.importer.name(),
+                    step.imported.name()
+                ),
+                vec![DiagnosticSpan::new_info("", step.span)],
+            );
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn warn_repeated_module_import(
+        source_id: SourceId,
+        decl: Span,
+        previous: Span,
+    ) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("module `{}` is already imported", source_id.name()),
+            vec![DiagnosticSpan::new_warning("", decl)],
+        )
+        .with_info(
+            "it was imported here, so importing it again does nothing",
+            vec![DiagnosticSpan::new_info("", previous)],
+        )
+    }
+
+    pub fn err_module_in_scope<T>(module: Span, open: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "a module can't be loaded inside a `scope` block",
+            vec![
+                DiagnosticSpan::new_error("", module),
+                DiagnosticSpan::new_info("the block starts here", open),
+            ],
+        )
+        .with_info(
+            "a module's definitions are visible to the rest of the root, so load it before the block",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_unmatched_scope_end<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "`end` without a matching `scope`",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_unclosed_scope<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "`scope` block is never closed",
+            vec![DiagnosticSpan::new_error("this block has no `end`", span)],
+        )
+        .with_info(
+            "the block ends with the file, so its definitions aren't visible after it",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_non_existent_file<T>(
+        standalone: &Path,
+        dir: &Path,
+        decl: Option<Span>,
+        similar: &[(SourceId, PathBuf)],
+    ) -> WResult<'ctx, T> {
+        let spans = decl.map(|decl| DiagnosticSpan::new_error("", decl));
+        let mut diag = Diagnostic::new("source does not exist", spans.into_iter().collect())
+            .with_info(&format!("checked `{}`", standalone.display()), Vec::new())
+            .with_info(&format!("checked `{}`", dir.display()), Vec::new());
+
+        for (source, path) in similar {
             diag = diag.with_info(
-                "module previously declared here",
-                vec![DiagnosticSpan::new_error("", prev_span)],
+                &format!(
+                    "did you mean `module {}`? found `{}`",
+                    source.name(),
+                    path.display()
+                ),
+                Vec::new(),
             );
         }
 
         Err(vec![diag])
     }
 
-    pub fn err_non_existent_file<T>(standalone: &Path, dir: &Path, decl: Span) -> WResult<'ctx, T> {
+    pub fn err_module_without_fs<T>(decl: Span) -> WResult<'ctx, T> {
         let diag = Diagnostic::new(
-            &format!("source does not exist"),
+            "can't load modules when reading from stdin",
             vec![DiagnosticSpan::new_error("", decl)],
         )
-        .with_info(&format!("checked `{}`", standalone.display()), Vec::new())
-        .with_info(&format!("checked `{}`", dir.display()), Vec::new());
+        .with_info(
+            "pass `--allow-fs` to load modules from the current directory",
+            Vec::new(),
+        );
 
         Err(vec![diag])
     }
 
+    /// `found` is the text from `location` to the end of the source.
     pub fn err_parse_failure<T>(
         location: Location,
+        found: &str,
         possible_atoms: &[ParseAtomPattern],
     ) -> WResult<'ctx, T> {
         fn format_atom(atom: &ParseAtomPattern) -> String {
             match atom {
-                ParseAtomPattern::Lit(lit) => format!("\"{lit}\""),
+                ParseAtomPattern::Lit(lit) => format!("\"{}\"", escape_str(lit)),
                 ParseAtomPattern::Kw(kw) => format!("\"{kw}\""),
                 ParseAtomPattern::Name => "a name".to_string(),
                 ParseAtomPattern::Str => "a string literal".to_string(),
@@ -326,36 +1148,136 @@ impl<'ctx> Diagnostic<'ctx> {
         let expected = Ustr::from(&expected);
 
         let span = Span::new(location, location);
-        let diag = Diagnostic::new(
+        let mut diag = Diagnostic::new(
             "error while parsing command",
             vec![DiagnosticSpan::new_error(expected.as_str(), span)],
         );
+        if let Some(help) = literal_typo_help(found, possible_atoms) {
+            diag = diag.with_info(&help, Vec::new());
+        }
 
         Err(vec![diag])
     }
 
-    pub fn err_duplicate_formal_syntax_cat<T>() -> WResult<'ctx, T> {
-        let diag = Diagnostic::new("err_duplicate_formal_syntax_cat", vec![]);
+    pub fn err_duplicate_formal_syntax_cat<T>(
+        name: Ustr,
+        span: Span,
+        existing: Provenance,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("duplicate syntax category `{name}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_decl_info(&format!("`{name}` is already declared"), existing);
 
         Err(vec![diag])
     }
 
-    pub fn err_duplicate_formal_syntax_rule<T>() -> WResult<'ctx, T> {
-        let diag = Diagnostic::new("err_duplicate_formal_syntax_rule", vec![]);
+    pub fn err_duplicate_formal_syntax_rule<T>(
+        name: Ustr,
+        span: Span,
+        existing: Provenance,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("duplicate syntax rule `{name}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_decl_info(&format!("`{name}` is already declared"), existing);
 
         Err(vec![diag])
     }
 
-    pub fn err_unknown_formal_syntax_cat<T>(name: Ustr, span: Span) -> WResult<'ctx, T> {
-        let diag = Diagnostic::new(
-            &format!("unknown formal syntax category `{name}`"),
+    pub fn err_unknown_notation<T>(name: Ustr, span: Span, ctx: &Ctx<'ctx>) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!("unknown notation `{name}`"),
             vec![DiagnosticSpan::new_error("", span)],
         );
 
+        for candidate in similar_names(&name, ctx.notations.keys().copied()) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+
         Err(vec![diag])
     }
 
-    pub fn err_duplicate_pattern_binding<T>(name: Ustr, span: Span) -> WResult<'ctx, T> {
+    pub fn err_alias_slot_mismatch<T>(
+        alias: Ustr,
+        target: NotationPatternId<'ctx>,
+        span: Span,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!(
+                "alias `{alias}` doesn't have the same holes as `{}`",
+                target.name()
+            ),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "an alias must have the same names, bindings and holes in the same order",
+            Vec::new(),
+        )
+        .with_decl_info(
+            &format!("`{}` is declared here", target.name()),
+            target.provenance(),
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn err_cyclic_notation_preference<T>(
+        preferred: Ustr,
+        other: Ustr,
+        span: Span,
+        chain: Vec<(Ustr, Ustr, Span)>,
+    ) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!("preferring `{preferred}` over `{other}` would create a cycle"),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        for (from, to, span) in chain {
+            diag = diag.with_info(
+                &format!("`{from}` is already preferred over `{to}`"),
+                vec![DiagnosticSpan::new_info("", span)],
+            );
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn info_notation_preference_applied(
+        preferred: Ustr,
+        other: Ustr,
+        span: Span,
+    ) -> Diagnostic<'ctx> {
+        Diagnostic::new_info(
+            &format!(
+                "read using `{preferred}` rather than `{other}` because of a `prefer` command"
+            ),
+            vec![DiagnosticSpan::new_info("", span)],
+        )
+    }
+
+    pub fn err_unknown_formal_syntax_cat<T>(
+        name: Ustr,
+        span: Span,
+        ctx: &Ctx<'ctx>,
+    ) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!("unknown formal syntax category `{name}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        let cats = &ctx.arenas.formal_cats;
+        for candidate in similar_names(&name, cats.names()) {
+            let provenance = cats.get(candidate).unwrap().provenance();
+            diag = diag.with_decl_info(&format!("did you mean `{candidate}`?"), provenance);
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn err_duplicate_pattern_binding<T>(name: Ustr, span: Span) -> WResult<'ctx, T> {
         let diag: Diagnostic<'_> = Diagnostic::new(
             &format!("duplicate pattern binding `{name}`"),
             vec![DiagnosticSpan::new_error("", span)],
@@ -373,8 +1295,16 @@ impl<'ctx> Diagnostic<'ctx> {
         Err(vec![diag])
     }
 
-    pub fn err_duplicate_grammar_cat<T>() -> WResult<'ctx, T> {
-        let diag = Diagnostic::new("err_duplicate_grammar_cat", vec![]);
+    pub fn err_duplicate_grammar_cat<T>(
+        name: Ustr,
+        span: Span,
+        existing: Provenance,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("duplicate grammar category `{name}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_decl_info(&format!("`{name}` is already declared"), existing);
 
         Err(vec![diag])
     }
@@ -390,21 +1320,189 @@ impl<'ctx> Diagnostic<'ctx> {
         Err(vec![diag])
     }
 
-    pub fn err_duplicate_grammar_rule<T>() -> WResult<'ctx, T> {
-        let diag = Diagnostic::new("err_duplicate_grammar_rule", vec![]);
+    pub fn err_duplicate_grammar_rule<T>(
+        name: Ustr,
+        span: Span,
+        existing: Provenance,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("duplicate grammar rule `{name}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_decl_info(&format!("`{name}` is already declared"), existing);
 
         Err(vec![diag])
     }
 
-    pub fn err_unknown_grammar_cat<T>(name: Ustr, span: Span) -> WResult<'ctx, T> {
-        let diag = Diagnostic::new(
+    pub fn err_unknown_grammar_cat<T>(name: Ustr, span: Span, ctx: &Ctx<'ctx>) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
             &format!("unknown grammar category `{name}`"),
             vec![DiagnosticSpan::new_error("", span)],
         );
 
+        let cats = &ctx.arenas.grammar_cats;
+        for candidate in similar_names(&name, cats.names()) {
+            let provenance = cats.get(candidate).unwrap().provenance();
+            diag = diag.with_decl_info(&format!("did you mean `{candidate}`?"), provenance);
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn err_tactic_pattern_always_empty<T>(name: Ustr, span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("tactic `{name}` only matches empty input"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "every part of the pattern is a category which can be empty",
+            Vec::new(),
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn warn_ambiguous_tactic_rule(
+        name: Ustr,
+        span: Span,
+        existing: CustomGrammarRuleId<'ctx>,
+    ) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!(
+                "tactic `{name}` starts with the same tokens as `{}`",
+                existing.name()
+            ),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_decl_info(
+            &format!(
+                "uses of `{name}` will likely be ambiguous with `{}`",
+                existing.name()
+            ),
+            existing.provenance(),
+        )
+    }
+
+    pub fn warn_name_is_keyword(name: Ustr, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("`{name}` is a keyword"),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info(
+            "it can be used as a name, but commands using it are easy to misread",
+            Vec::new(),
+        )
+    }
+
+    pub fn warn_miscapitalized_command(word: &str, kw: Ustr, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("this line looks like a `{kw}` command but was treated as prose"),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info(
+            &format!("commands are case-sensitive, so write `{kw}` rather than `{word}`"),
+            Vec::new(),
+        )
+        .with_info(
+            "end the line with `-- watson: ignore` if it is meant to be prose",
+            Vec::new(),
+        )
+    }
+
+    pub fn warn_keyword_not_command(word: &str, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            "this line looks like a command but was treated as prose",
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info(
+            &format!("`{word}` is a keyword, but no command starts with it here"),
+            Vec::new(),
+        )
+        .with_info(
+            "end the line with `-- watson: ignore` if it is meant to be prose",
+            Vec::new(),
+        )
+    }
+
+    pub fn warn_unknown_attribute(name: Ustr, span: Span, ctx: &Ctx<'ctx>) -> Diagnostic<'ctx> {
+        let mut diag = Diagnostic::new_warning(
+            &format!("unknown attribute `{name}`"),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info("the attribute is ignored", Vec::new());
+
+        let attribute_cat = ctx.arenas.grammar_cats.get(*strings::ATTRIBUTE).unwrap();
+        let declared = ctx
+            .custom_grammar_manager
+            .rules_for_cat(attribute_cat)
+            .iter()
+            .map(|rule| rule.name());
+        for candidate in similar_names(&name, BuiltinAttributeKind::names().chain(declared)) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+
+        diag
+    }
+
+    pub fn err_attribute_value<T>(
+        kind: BuiltinAttributeKind,
+        value: AttributeValue,
+        span: Span,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!(
+                "attribute `{}` takes {}, but was given {}",
+                kind.name(),
+                kind.expects(),
+                value.describe()
+            ),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
         Err(vec![diag])
     }
 
+    pub fn err_attribute_target<T>(
+        kind: BuiltinAttributeKind,
+        target: &str,
+        span: Span,
+    ) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("attribute `{}` can only be used on {target}", kind.name()),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn err_local_notation_unsupported<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "local notation is not yet supported",
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "notation is visible everywhere after it is declared",
+            Vec::new(),
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn warn_frag_cat_without_rules(
+        cat: Ustr,
+        span: Span,
+        provenance: Provenance,
+    ) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("category `{cat}` has no rules"),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_decl_info(
+            &format!("nothing can be parsed as `{cat}` so this tactic can never match"),
+            provenance,
+        )
+    }
+
     pub fn err_reserved_grammar_label<T>(label: Ustr, span: Span) -> WResult<'ctx, T> {
         let diag = Diagnostic::new(
             &format!("label `{label}` is reserved"),
@@ -414,8 +1512,105 @@ impl<'ctx> Diagnostic<'ctx> {
         Err(vec![diag])
     }
 
-    pub fn err_ambiguous_parse<T>(span: Span) -> WResult<'ctx, T> {
-        let diag = Diagnostic::new("ambiguous parse", vec![DiagnosticSpan::new_error("", span)]);
+    /// `cat` is the category which can be empty, or `None` if the part is
+    /// itself an `@many`.
+    pub fn err_many_of_empty<T>(span: Span, cat: Option<Ustr>) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "`@many` can't repeat a part which can be empty",
+            vec![DiagnosticSpan::new_error("this can match nothing", span)],
+        );
+        let diag = match cat {
+            Some(cat) => diag.with_info(&format!("`{cat}` can match nothing"), Vec::new()),
+            None => diag.with_info("`@many` already matches any number of the part", Vec::new()),
+        };
+
+        Err(vec![diag])
+    }
+
+    pub fn err_ambiguous_parse<T>(span: Span, candidates: &[RuleId<'ctx>]) -> WResult<'ctx, T> {
+        let mut diag =
+            Diagnostic::new("ambiguous parse", vec![DiagnosticSpan::new_error("", span)]);
+
+        for rule in candidates {
+            diag = diag.with_decl_info(
+                &format!("could be parsed as `{}`", rule.name()),
+                rule.source().provenance(),
+            );
+        }
+
+        Err(vec![diag])
+    }
+
+    pub fn err_precedence_parse<T>(span: Span, candidates: &[RuleId<'ctx>]) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            "the operators here can't be grouped by their precedence",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        for rule in candidates {
+            diag = diag.with_decl_info(
+                &format!("could be parsed as `{}`", rule.name()),
+                rule.source().provenance(),
+            );
+        }
+
+        Err(vec![diag.with_info(
+            "add parentheses to choose how they group",
+            Vec::new(),
+        )])
+    }
+
+    /// `escape` is the escape as written, starting with the backslash.
+    pub fn err_invalid_str_escape<T>(span: Span, escape: &str) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("invalid escape `{escape}` in string literal"),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+        let info = if escape.starts_with("\\u") {
+            "a unicode escape is written `\\u{XXXX}` with 1 to 6 hex digits naming a character"
+        } else {
+            "the escapes are `\\\"`, `\\\\`, `\\n`, `\\t` and `\\u{XXXX}`"
+        };
+        Err(vec![diag.with_info(info, Vec::new())])
+    }
+
+    pub fn err_split_step_limit<T>(
+        rule: RuleId<'ctx>,
+        span: Span,
+        steps: usize,
+        worst: Option<(Location, CategoryId<'ctx>, usize)>,
+    ) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!(
+                "gave up splitting `{}` into its parts after {steps} steps",
+                rule.name()
+            ),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        if let Some((at, cat, count)) = worst {
+            diag = diag.with_info(
+                &format!("there are {count} ways to parse `{}` here", cat.name()),
+                vec![DiagnosticSpan::new_info("", Span::new(at, at))],
+            );
+        }
+
+        diag = diag.with_decl_info(
+            &format!(
+                "making `{}` left or right associative would remove the ambiguity",
+                rule.name()
+            ),
+            rule.source().provenance(),
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn err_notation_too_deep<T>(span: Span, max_depth: usize) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("notations are nested more than {max_depth} deep"),
+            vec![DiagnosticSpan::new_error("", span)],
+        );
 
         Err(vec![diag])
     }
@@ -447,20 +1642,10 @@ impl<'ctx> Diagnostic<'ctx> {
 
         for resolution in matching_notations {
             let pattern = resolution.binding.pattern();
-            match pattern.source() {
-                NotationPatternSource::UserDeclared(decl_span) => {
-                    diag = diag.with_info(
-                        &format!("binding `{}` matches", pattern.name()),
-                        vec![DiagnosticSpan::new_info("declared here", decl_span)],
-                    );
-                }
-                NotationPatternSource::Builtin => {
-                    diag = diag.with_info(
-                        &format!("builtin binding `{}` matches", pattern.name()),
-                        vec![],
-                    );
-                }
-            }
+            diag = diag.with_decl_info(
+                &format!("binding `{}` matches", pattern.name()),
+                pattern.provenance(),
+            );
         }
 
         Err(vec![diag])
@@ -526,9 +1711,315 @@ impl<'ctx> Diagnostic<'ctx> {
         Err(vec![diag])
     }
 
+    pub fn err_abbreviation_with_binders<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "abbreviations can't take arguments which bind variables",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn err_template_default_with_holes<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "templates which take arguments can't have defaults",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn err_template_default_wrong_cat<T>(
+        expected: Ustr,
+        found: Vec<Ustr>,
+        span: Span,
+    ) -> WResult<'ctx, T> {
+        let found = found.iter().map(|cat| format!("`{cat}`")).join(" or ");
+        let diag = Diagnostic::new(
+            &format!("default for a `{expected}` template isn't a `{expected}`"),
+            vec![DiagnosticSpan::new_error(
+                &format!("this is a {found}"),
+                span,
+            )],
+        );
+
+        Err(vec![diag])
+    }
+
+    pub fn err_template_default_refers_forward<T>(span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "a template's default can only refer to the templates before it",
+            vec![DiagnosticSpan::new_error("", span)],
+        );
+
+        Err(vec![diag])
+    }
+
     // pub fn _err_todo_real_error_later<T>(span: Span, msg: &str) -> WResult<'ctx, T> {
     //     let diag = Diagnostic::new(msg, vec![DiagnosticSpan::new_error("", span)]);
 
     //     Err(vec![diag])
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Arenas,
+        driver::Driver,
+        parse::location::SourceOffset,
+        util::{diff::assert_text_eq, test_project::TestProject},
+    };
+
+    /// Render an error with a single span from `start` to `end` in `text`.
+    fn render_span(text: &str, start: usize, end: usize) -> String {
+        let sources = SourceCache::new();
+        let source = SourceId::new(Ustr::from("broken_spans"));
+        sources.add(source, text.to_string(), SourceDecl::Root);
+        let at = |offset| Location::new(source, SourceOffset::new(offset));
+        let span = Span::new(at(start), at(end));
+        let diag = Diagnostic::new("broken", vec![DiagnosticSpan::new_error("here", span)]);
+        let msg = diag.to_message(&sources, ProofStateDisplay::None);
+        Renderer::plain().render(msg).to_string()
+    }
+
+    /// An error in `a` with infos in `b`, back in `a`, in `c` and in a source
+    /// that was never loaded, in that order.
+    #[test]
+    fn parts_are_grouped_by_file() {
+        let sources = SourceCache::new();
+        let files = [
+            ("a", "first a\nsecond a\n"),
+            ("b", "in b\n"),
+            ("c", "one\nin c\n"),
+        ];
+        for (name, text) in files {
+            let source = SourceId::new(Ustr::from(name));
+            sources.add(source, text.to_string(), SourceDecl::Root);
+        }
+        let span_of = |name: &str, line: &str| {
+            let source = SourceId::new(Ustr::from(name));
+            let text = files
+                .iter()
+                .find(|(file, _)| *file == name)
+                .map_or("", |f| f.1);
+            let start = text.find(line).unwrap_or(0);
+            let at = |offset| Location::new(source, SourceOffset::new(offset));
+            Span::new(at(start), at(start + line.len()))
+        };
+        let info = |title: &str, name: &str, line: &str| {
+            (
+                title.to_string(),
+                vec![DiagnosticSpan::new_info("", span_of(name, line))],
+            )
+        };
+        let mut diag = Diagnostic::new(
+            "defined twice",
+            vec![DiagnosticSpan::new_error("", span_of("a", "second a"))],
+        );
+        for (title, spans) in [
+            info("used here", "b", "in b"),
+            info("first defined here", "a", "first a"),
+            info("also here", "c", "in c"),
+            info("made up", "synthetic", "x"),
+        ] {
+            diag = diag.with_info(&title, spans);
+        }
+
+        let msg = diag.to_message(&sources, ProofStateDisplay::None);
+        let rendered = Renderer::plain().render(msg).to_string();
+        let expected = "error: defined twice\n \
+            --> a:1:1\n  |\n\
+            1 | first a\n  | ------- info: first defined here\n\
+            2 | second a\n  | ^^^^^^^^\n  |\n \
+            ::: b:1:1\n  |\n\
+            1 | in b\n  | ---- info: used here\n  |\n \
+            ::: c:2:1\n  |\n\
+            2 | in c\n  | ---- info: also here\n  |\n  \
+            = info: made up (in `synthetic`)";
+        assert_text_eq(expected, &rendered);
+    }
+
+    #[test]
+    fn empty_spans_point_at_the_next_character() {
+        let rendered = render_span("ab\ncd\n", 3, 3);
+        assert!(rendered.contains("2 | cd\n  | ^ here"), "{rendered}");
+    }
+
+    #[test]
+    fn backwards_spans_are_swapped() {
+        let rendered = render_span("ab\ncd\n", 5, 3);
+        assert!(rendered.contains("2 | cd\n  | ^^ here"), "{rendered}");
+    }
+
+    #[test]
+    fn spans_past_the_end_point_at_the_end_of_input() {
+        let rendered = render_span("ab\ncd", 7, 9);
+        assert!(rendered.contains("end of input"), "{rendered}");
+        assert!(rendered.contains("2 | cd"), "{rendered}");
+    }
+
+    /// The proof state shown for a failure after three scripted steps: `p`
+    /// is deduced, `p → q` is assumed and the goal changes to `p → q`.
+    fn three_step_failure(display: ProofStateDisplay) -> String {
+        let main = "module logic\n\n\
+            theorem three [p q : sentence] : (p → q) (p)\n    |- ¬p → q\nproof\nqed\n";
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local hyps = proofState.theorem.hypotheses\n\
+            \x20   local info = tacticInfo:withDeduce(hyps[2])\n\
+            \x20   info = info:withAssume(hyps[1].conclusion)\n\
+            \x20   info = info:withGoal(hyps[1].conclusion)\n\
+            \x20   Diagnostic:new(\"stuck\", tactic._span:label(\"\")):withTacticInfo(info):report()\n\
+            \x20   return proofState:applyError(proofState.goal, tactic._span)\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let ctx = checked.ctx();
+        let stuck = ctx
+            .diags
+            .iter()
+            .find(|diag| diag.title() == "stuck")
+            .unwrap();
+        let state = stuck.proof_state(&ctx.sources, display);
+        state.map(|state| strip_ansi(&state)).unwrap_or_default()
+    }
+
+    #[test]
+    fn recent_steps_and_goal_changes_are_shown_first() {
+        let expected = "Proof state:\n\
+            recent:\n\
+            *  p\n\
+            *? p→q\n\
+            earlier:\n\
+            \x20> p→q [hyp 1]\n\
+            \x20> p [hyp 2]\n\
+            ⊢ p→q\n\
+            was ¬p→q";
+        assert_text_eq(expected, &three_step_failure(ProofStateDisplay::Full));
+    }
+
+    #[test]
+    fn kernel_steps_are_tagged_with_their_origin() {
+        let main = "module logic\n\n\
+            theorem tagged [p q : sentence] : (p → q) (p)\n    |- q\nproof\nqed\n";
+        // Apply `mp` without a span, then assume its antecedent again at the
+        // tactic, and report the state the kernel is in.
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local hyps = proofState.theorem.hypotheses\n\
+            \x20   local mp = Theorem.lookup(\"mp\")\n\
+            \x20   local found = Frag.match(mp.hypotheses[1].conclusion, hyps[1].conclusion)\n\
+            \x20   local state = proofState:applyTheorem(mp, { found[\"$0\"], found[\"$1\"] })\n\
+            \x20   state = state:addAssumption(found[\"$0\"], tactic._span)\n\
+            \x20   Diagnostic:new(\"stuck\", tactic._span:label(\"\")):withTacticInfo(tacticInfo, state):report()\n\
+            \x20   return proofState:applyError(proofState.goal, tactic._span)\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let ctx = checked.ctx();
+        let stuck = (ctx.diags.iter())
+            .find(|diag| diag.title() == "stuck")
+            .unwrap();
+        // Facts from the kernel are printed in formal syntax, where the
+        // templates are numbered. The empty proof's tactic is at `proof`.
+        let expected = "Proof state:\n\
+            recent:\n\
+            *  $1 [by mp]\n\
+            *? $0 [from line 5]\n\
+            earlier:\n\
+            \x20> p→q [hyp 1]\n\
+            \x20> p [hyp 2]\n\
+            ⊢ q";
+        let state = stuck.proof_state(&ctx.sources, ProofStateDisplay::Full);
+        assert_text_eq(expected, &strip_ansi(&state.unwrap()));
+    }
+
+    #[test]
+    fn recent_display_hides_earlier_facts() {
+        let expected = "Proof state:\n\
+            recent:\n\
+            *  p\n\
+            *? p→q\n\
+            (2 earlier facts hidden)\n\
+            ⊢ p→q\n\
+            was ¬p→q";
+        assert_text_eq(expected, &three_step_failure(ProofStateDisplay::Recent));
+        assert_eq!(three_step_failure(ProofStateDisplay::None), "");
+    }
+
+    fn help(found: &str, lit: &str) -> Option<String> {
+        literal_typo_help(found, &[ParseAtomPattern::Lit(Ustr::from(lit))])
+    }
+
+    #[test]
+    fn definition_assign_for_bnf_replace() {
+        assert_eq!(
+            help(":= \"foo\"", "::=").as_deref(),
+            Some("did you mean `::=`? note `:=` is only used in definitions and template defaults")
+        );
+    }
+
+    #[test]
+    fn mistyped_turnstiles() {
+        let note = Some("did you mean `|-`? note the turnstile is written `|-`");
+        assert_eq!(help("|= p", "|-").as_deref(), note);
+        assert_eq!(help("⊢ p", "|-").as_deref(), note);
+        assert_eq!(
+            help("|– p", "|-").as_deref(),
+            Some("did you mean `|-`? note `–` is an en dash")
+        );
+    }
+
+    #[test]
+    fn mistyped_arrows() {
+        assert_eq!(
+            help("–> q", "->").as_deref(),
+            Some("did you mean `->`? note `–` is an en dash")
+        );
+        assert_eq!(
+            help("→ q", "->").as_deref(),
+            Some("did you mean `->`? note arrows are written `->`")
+        );
+    }
+
+    #[test]
+    fn other_near_misses_get_a_generic_suggestion() {
+        assert_eq!(help(":-= x", "::=").as_deref(), Some("did you mean `::=`?"));
+    }
+
+    #[test]
+    fn no_suggestion_without_a_near_miss() {
+        assert_eq!(help("::= x", "::="), None);
+        assert_eq!(help("foo", "::="), None);
+        assert_eq!(literal_typo_help(":=", &[ParseAtomPattern::Name]), None);
+    }
+
+    #[test]
+    fn parse_failure_shows_the_suggestion() {
+        let main = "module logic\n\nsyntax top\n    sentence := \"⊤\"\nend\n";
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let suggested = parsed.ctx().diags.iter().any(|diag| {
+            diag.is_error()
+                && diag
+                    .parts()
+                    .any(|part| part.title().starts_with("did you mean `::=`?"))
+        });
+        assert!(suggested);
+    }
+}
@@ -0,0 +1,353 @@
+use crate::{
+    config::{MessageFormat, WatsonConfig},
+    context::{Arenas, Ctx},
+    diagnostics::{DiagStage, Diagnostic, print_diagnostics},
+    parse::{
+        ParseEntry, ParseReport, SourceCache, SourceId, parse, scan,
+        source_cache::{SourceDecl, read_source_file, source_id_to_path},
+        source_files::warn_unloaded_sources,
+    },
+    report::{ProofReport, display_report},
+    semant::{
+        axiom_closure::{compute_axiom_closures, compute_todo_closures, warn_deprecated_axioms},
+        check_circularity::find_circular_dependency_groups,
+        check_proofs::check_proofs,
+        notation::warn_unused_notations,
+    },
+};
+use std::time::{Duration, Instant};
+
+/// Runs the check pipeline one stage at a time so that callers can use the
+/// results of parsing before the much slower proof checking has run. Moving
+/// to the next stage consumes the driver since it needs to mutate the
+/// context, and the new stage keeps everything the previous one computed.
+pub struct Driver<'ctx, S> {
+    ctx: Ctx<'ctx>,
+    roots: Vec<SourceId>,
+    stage: S,
+}
+
+/// The root sources have been loaded but nothing has been parsed.
+pub struct Loaded {
+    /// Whether the roots were read from the math directory, which can then
+    /// be searched for sources the project never loads.
+    from_math_dir: bool,
+}
+
+/// Every source has been parsed and every command elaborated.
+pub struct Parsed<'ctx> {
+    parse_report: ParseReport<'ctx>,
+}
+
+/// Every proof has been checked.
+pub struct Checked<'ctx> {
+    parse_report: ParseReport<'ctx>,
+    proof_report: ProofReport<'ctx>,
+}
+
+impl<'ctx, S> Driver<'ctx, S> {
+    pub fn ctx(&self) -> &Ctx<'ctx> {
+        &self.ctx
+    }
+}
+
+impl<'ctx> Driver<'ctx, Loaded> {
+    pub fn new(
+        source_cache: SourceCache,
+        roots: Vec<SourceId>,
+        config: WatsonConfig,
+        arenas: &'ctx Arenas<'ctx>,
+    ) -> Self {
+        Self {
+            ctx: Ctx::new(source_cache, config, arenas),
+            roots,
+            stage: Loaded {
+                from_math_dir: false,
+            },
+        }
+    }
+
+    /// Load the roots of the project described by `config`.
+    pub fn for_project(config: WatsonConfig, arenas: &'ctx Arenas<'ctx>) -> Self {
+        let roots: Vec<_> = config
+            .roots()
+            .iter()
+            .map(|&root| SourceId::new(root))
+            .collect();
+        let mut driver = Self::new(SourceCache::new(), Vec::new(), config, arenas);
+        driver.stage.from_math_dir = true;
+        for root_id in roots {
+            let math_dir = driver.ctx.config.math_dir();
+            let Some((root_file, root_text)) = read_source_file(root_id, math_dir) else {
+                // A missing root is reported and the others are still parsed.
+                let (path1, path2) = source_id_to_path(root_id, math_dir);
+                let diags =
+                    Diagnostic::err_non_existent_file::<()>(&path1, &path2, None, &[]).unwrap_err();
+                driver.ctx.diags.add_diags(diags);
+                continue;
+            };
+            driver.ctx.sources.add(root_id, root_text, SourceDecl::Root);
+            driver.roots.push(root_id);
+            // Recorded so a module naming a root's file is caught. The
+            // roots themselves are distinct names from the config.
+            let _ = driver.ctx.module_graph.add_file(root_id, &root_file);
+        }
+        driver
+    }
+
+    /// Find the prose and commands of the sources without parsing or
+    /// checking anything.
+    pub fn scan(mut self) -> (Ctx<'ctx>, Vec<ParseEntry<'ctx>>) {
+        let entries = scan::scan(&self.roots, &mut self.ctx);
+        (self.ctx, entries)
+    }
+
+    /// Parse the roots in order. They share one grammar, so syntax an
+    /// earlier root declared still parses in a later root which doesn't
+    /// import it. Only elaboration, which looks at scopes, rejects it.
+    pub fn parse(mut self) -> Driver<'ctx, Parsed<'ctx>> {
+        let parse_report = parse(&self.roots, &mut self.ctx);
+
+        // Only a parse which ran to the end knows every source the project
+        // loads.
+        if self.stage.from_math_dir && self.ctx.diags.should_continue() {
+            warn_unloaded_sources(&mut self.ctx);
+        }
+
+        Driver {
+            ctx: self.ctx,
+            roots: self.roots,
+            stage: Parsed { parse_report },
+        }
+    }
+
+    /// Parse and check every proof, then print the summary of the check and
+    /// its diagnostics. This is what `watson check` and `watson book` both
+    /// run, so they agree on what counts as an error. Also returns how long
+    /// parsing and checking took.
+    pub fn check_and_report(
+        self,
+        iteration: Option<usize>,
+    ) -> (Driver<'ctx, Checked<'ctx>>, Duration) {
+        let start = Instant::now();
+        let checked = self.parse().check();
+        let elapsed = start.elapsed();
+
+        // Only watch mode shows the time, next to the iteration. Tools reading
+        // JSON diagnostics only get the diagnostics.
+        if checked.ctx.config.message_format() == MessageFormat::Human {
+            display_report(
+                checked.proof_report(),
+                checked.ctx.diags.has_errors(),
+                iteration,
+                iteration.map(|_| elapsed),
+            );
+        }
+        checked.print_diagnostics();
+        (checked, elapsed)
+    }
+}
+
+impl<'ctx> Driver<'ctx, Parsed<'ctx>> {
+    pub fn parse_diagnostics(&self) -> &[Diagnostic<'ctx>] {
+        self.ctx.diags.of_stage(DiagStage::Parse)
+    }
+
+    pub fn check(mut self) -> Driver<'ctx, Checked<'ctx>> {
+        self.ctx.diags.set_stage(DiagStage::Check);
+        let parse_report = self.stage.parse_report;
+        let mut statuses = check_proofs(
+            &parse_report.roots,
+            parse_report.attributes.clone(),
+            &mut self.ctx,
+        );
+        compute_axiom_closures(&mut statuses);
+        compute_todo_closures(&mut statuses);
+        warn_deprecated_axioms(&statuses, &mut self.ctx);
+        // Proofs use notations too, so a notation only looks unused when every
+        // proof was checked.
+        if !self.ctx.diags.has_errors() && self.ctx.config.theorem_filter().is_none() {
+            warn_unused_notations(&mut self.ctx);
+        }
+        let circularities = find_circular_dependency_groups(&statuses);
+
+        let roots = parse_report
+            .roots
+            .iter()
+            .map(|root| {
+                let theorems = root.theorems.iter().map(|(thm, _)| *thm).collect();
+                (root.source, theorems)
+            })
+            .collect();
+
+        let proof_report = ProofReport {
+            statuses,
+            circularities,
+            roots,
+        };
+
+        Driver {
+            ctx: self.ctx,
+            roots: self.roots,
+            stage: Checked {
+                parse_report,
+                proof_report,
+            },
+        }
+    }
+
+    pub fn into_parts(self) -> (Ctx<'ctx>, ParseReport<'ctx>) {
+        (self.ctx, self.stage.parse_report)
+    }
+}
+
+impl<'ctx> Driver<'ctx, Checked<'ctx>> {
+    pub fn parse_report(&self) -> &ParseReport<'ctx> {
+        &self.stage.parse_report
+    }
+
+    pub fn proof_report(&self) -> &ProofReport<'ctx> {
+        &self.stage.proof_report
+    }
+
+    pub fn parse_diagnostics(&self) -> &[Diagnostic<'ctx>] {
+        self.ctx.diags.of_stage(DiagStage::Parse)
+    }
+
+    pub fn proof_diagnostics(&self) -> &[Diagnostic<'ctx>] {
+        self.ctx.diags.of_stage(DiagStage::Check)
+    }
+
+    /// Print the diagnostics from every stage in the order they were
+    /// reported.
+    pub fn print_diagnostics(&self) {
+        print_diagnostics(self.parse_diagnostics(), &self.ctx);
+        print_diagnostics(self.proof_diagnostics(), &self.ctx);
+    }
+
+    pub fn into_parts(self) -> (Ctx<'ctx>, ParseReport<'ctx>, ProofReport<'ctx>) {
+        (self.ctx, self.stage.parse_report, self.stage.proof_report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Driver;
+    use crate::{context::Arenas, diagnostics::Diagnostic, util::test_project::TestProject};
+
+    /// Declares syntax for `⊤` and the notation `↑`, and imports the
+    /// propositional library.
+    const MAIN: &str = "module logic\n\n\
+        syntax top\n    sentence ::= \"⊤\"\nend\n\n\
+        notation nand\n    sentence (300) ::= sentence \"↑\" sentence\nend\n\n\
+        definition\n    p ↑ q := ¬(p ∧ q)\nend\n\n\
+        axiom top.intro :\n    |- ⊤\nend\n";
+
+    /// A project with `main` and `other` as its roots, in that order.
+    fn two_roots(other: &str) -> TestProject {
+        let project = TestProject::with_logic(MAIN);
+        project.write("watson.toml", "[project]\nroots = [\"main\", \"other\"]\n");
+        project.write("math/other.wats", other);
+        project
+    }
+
+    /// The titles of the errors from parsing the project.
+    fn parse_errors(project: &TestProject) -> Vec<String> {
+        let arenas = Arenas::new();
+        let driver = Driver::for_project(project.config(), &arenas).parse();
+        (driver.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn a_root_can_import_an_earlier_root() {
+        let other = "module main\n\n\
+            axiom nand.top :\n    |- ⊤ ↑ ⊥\nend\n";
+        let project = two_roots(other);
+        assert_eq!(parse_errors(&project), Vec::<String>::new());
+
+        let arenas = Arenas::new();
+        let driver = Driver::for_project(project.config(), &arenas).parse();
+        let (_, report) = driver.into_parts();
+        let theorems: Vec<_> = (report.roots.iter())
+            .map(|root| {
+                let names = root.theorems.iter().map(|(thm, _)| thm.name().as_str());
+                (root.source.name().as_str(), names.collect::<Vec<_>>())
+            })
+            .collect();
+        assert_eq!(
+            theorems,
+            [
+                ("main", vec!["imp.intro", "mp", "dne", "top.intro"]),
+                ("other", vec!["nand.top"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_root_does_not_see_notation_it_does_not_import() {
+        let other = "axiom nand.bot :\n    |- ⊥ ↑ ⊥\nend\n";
+        assert!(!parse_errors(&two_roots(other)).is_empty());
+    }
+
+    /// The grammar is shared by every root, so syntax another root declared
+    /// still parses even though it isn't in scope. It is only rejected when
+    /// the fragment is elaborated, unlike syntax nobody declared.
+    #[test]
+    fn syntax_from_another_root_is_still_in_the_grammar() {
+        let other = "axiom top.again :\n    |- ⊤\nend\n";
+        assert_eq!(
+            parse_errors(&two_roots(other)),
+            ["failed to parse fragment: no notation in scope matched"]
+        );
+        let other = "axiom box :\n    |- ⊠\nend\n";
+        assert_eq!(
+            parse_errors(&two_roots(other)),
+            ["error while parsing command"]
+        );
+    }
+
+    #[test]
+    fn diagnostics_are_split_by_stage() {
+        let project = TestProject::with_logic(
+            "module logic\n\n\
+             tactic twice.fact\n    tactic ::= @kw\"twice\" f:@fact\nend\n\n\
+             tactic twice.name\n    tactic ::= @kw\"twice\" n:@name\nend\n\n\
+             axiom one :\n    |- ⊥ → ⊥\nend\n\n\
+             theorem bad :\n    |- ⊥\nproof\n    by one\nqed\n",
+        );
+        let arenas = Arenas::new();
+        let titles = |diags: &[Diagnostic]| -> Vec<String> {
+            diags.iter().map(|diag| diag.title().to_string()).collect()
+        };
+
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let parse_titles = titles(parsed.parse_diagnostics());
+        assert_eq!(
+            parse_titles,
+            ["tactic `twice.name` starts with the same tokens as `twice.fact`"]
+        );
+
+        let checked = parsed.check();
+        assert_eq!(titles(checked.parse_diagnostics()), parse_titles);
+        let proof_diagnostics = checked.proof_diagnostics();
+        assert!(!proof_diagnostics.is_empty());
+        assert!(proof_diagnostics.iter().all(|diag| diag.is_error()));
+    }
+
+    #[test]
+    fn missing_root_is_reported() {
+        let project = TestProject::new("");
+        project.write(
+            "watson.toml",
+            "[project]\nroots = [\"main\", \"missing\"]\n",
+        );
+        let arenas = Arenas::new();
+        let driver = Driver::for_project(project.config(), &arenas).parse();
+
+        let titles: Vec<_> = driver.ctx().diags.iter().map(|diag| diag.title()).collect();
+        assert_eq!(titles, ["source does not exist"]);
+    }
+}
@@ -3,6 +3,7 @@ mod cli;
 mod config;
 mod context;
 mod diagnostics;
+mod driver;
 mod parse;
 mod report;
 mod semant;
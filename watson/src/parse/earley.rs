@@ -4,18 +4,25 @@ use crate::{
     parse::{
         Location, SourceId, Span,
         location::SourceOffset,
-        parse_state::{Associativity, CategoryId, ParseAtomPattern, RuleId, RulePatternPart},
-        parse_tree::{
-            ParseAtom, ParseAtomKind, ParseTree, ParseTreeChildren, ParseTreeId, ParseTreePart,
+        parse_state::{
+            Associativity, CategoryId, ParseAtomPattern, ParseRuleSource, Precedence, RuleId,
+            RulePatternPart,
         },
+        parse_tree::{ParseAtom, ParseAtomKind, ParseTreeChildren, ParseTreeId, ParseTreePart},
     },
+    semant::notation::NotationPreferences,
 };
 use rustc_hash::{FxHashMap, FxHashSet};
 use std::{char, cmp::Reverse, collections::VecDeque};
+use ustr::Ustr;
 
+/// Parse the text at `start` as the category. `preferences` are the `prefer`
+/// commands in scope there, which can let two notations compete whatever
+/// their precedence.
 pub fn parse<'ctx>(
     start: Location,
     category: CategoryId<'ctx>,
+    preferences: &NotationPreferences,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, ParseTreeId<'ctx>> {
     let chart = build_chart(start, category, ctx);
@@ -25,7 +32,7 @@ pub fn parse<'ctx>(
         return make_parse_error(&chart, start.source(), ctx);
     }
 
-    read_chart(start, category, &trimmed, ctx)
+    read_chart(start, category, &trimmed, preferences, ctx)
 }
 
 fn build_chart<'ctx>(start: Location, category: CategoryId<'ctx>, ctx: &Ctx<'ctx>) -> Chart<'ctx> {
@@ -102,7 +109,12 @@ fn build_chart<'ctx>(start: Location, category: CategoryId<'ctx>, ctx: &Ctx<'ctx
             }
         }
 
-        current_position = current_position.forward(1);
+        // Move on to the next character. Atoms always end on a character
+        // boundary so there are never any items inside a character.
+        let Some(next_char) = text[current_position.byte_offset()..].chars().next() else {
+            break;
+        };
+        current_position = current_position.forward(next_char.len_utf8());
     }
 
     chart
@@ -131,7 +143,7 @@ fn _debug_chart(chart: &Chart) {
                         ParseAtomPattern::Num => pattern.push_str("num "),
                     },
                     RulePatternPart::Cat(id) => {
-                        let cat_name = id._name();
+                        let cat_name = id.name();
                         pattern.push_str(&format!("<{cat_name}> "));
                     }
                 }
@@ -142,7 +154,7 @@ fn _debug_chart(chart: &Chart) {
             let origin = item.origin;
             println!(
                 "  {} -> {} (from {:?})",
-                item.rule.cat()._name(),
+                item.rule.cat().name(),
                 pattern,
                 origin
             );
@@ -241,21 +253,32 @@ fn make_parse_error<'ctx, T>(chart: &Chart, source: SourceId, ctx: &Ctx<'ctx>) -
 
     let text = ctx.sources.get_text(source).as_str();
     let location = skip_ws_and_comments(text, latest_pos);
+
+    // Each `using` rule names its category with a keyword, so a name which
+    // isn't a tactic category stops all of them right after `using`.
+    let names_proof_cat = latest_items.iter().any(|item| {
+        item.dot == 1 && matches!(item.rule.source(), ParseRuleSource::ProofCategory(_))
+    });
+    if names_proof_cat && let Some((end, name)) = parse_name(text, location) {
+        let span = Span::new(Location::new(source, location), Location::new(source, end));
+        return Diagnostic::err_unknown_grammar_cat(Ustr::from(name), span, ctx);
+    }
+
     let location = Location::new(source, location);
 
     let mut possible_atoms = possible_next_atoms.into_iter().collect::<Vec<_>>();
     possible_atoms.sort();
 
-    Diagnostic::err_parse_failure(
-        Location::new(location.source(), latest_pos),
-        &possible_atoms,
-    )
+    // Point at the text which couldn't be parsed rather than the whitespace
+    // before it, which may be on an earlier line.
+    Diagnostic::err_parse_failure(location, &text[location.byte_offset()..], &possible_atoms)
 }
 
 fn read_chart<'ctx>(
     start: Location,
     cat: CategoryId<'ctx>,
     chart: &TrimmedChart<'ctx>,
+    preferences: &NotationPreferences,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, ParseTreeId<'ctx>> {
     // First we are going to find the length of the longest parse. Our recursive
@@ -268,11 +291,14 @@ fn read_chart<'ctx>(
         .unwrap();
     let span = Span::new(start, Location::new(start.source(), SourceOffset::new(end)));
 
-    // Now we can recursively read the parse tree.
+    // Now we can recursively read the parse tree. `parent` is the rule and
+    // part the span is a child of, which limits the rules it can match.
     fn search<'ctx>(
         span: Span,
         cat: CategoryId<'ctx>,
+        parent: Option<(RuleId<'ctx>, usize)>,
         chart: &TrimmedChart<'ctx>,
+        preferences: &NotationPreferences,
         ctx: &Ctx<'ctx>,
     ) -> WResult<'ctx, ParseTreeId<'ctx>> {
         let text = ctx.sources.get_text(span.source()).as_str();
@@ -280,75 +306,91 @@ fn read_chart<'ctx>(
         // The idea here is to check which rules we have for the given span and
         // category. We then choose which among those rules is best. If there
         // is still a tie the parse is ambiguous.
-        let rules = chart[&(span.start().offset(), cat)]
+        let rules: Vec<_> = chart[&(span.start().offset(), cat)]
             .iter()
             .filter(|(_, end)| *end == span.end().offset())
-            .map(|(rule, _end)| *rule);
-        let best_rules = choose_best_rule(rules);
+            .map(|(rule, _end)| *rule)
+            .filter(|rule| parent.is_none_or(|(parent, part)| parent.admits(part, rule)))
+            .collect();
 
         // Each rule gives us a pattern which we can use to split the span
-        // into parts. We then recursively search for each part.
+        // into parts. We then recursively search for each part. A rule whose
+        // operands can't be split in a way their precedence allows is
+        // skipped for the rules with the next precedence.
         let mut possibilities = Vec::new();
-        for &rule in &best_rules {
-            let split = split_with_pattern(
-                text,
-                span,
-                rule.pattern().parts(),
-                rule.pattern().associativity(),
-                &mut vec![],
-                chart,
-            );
+        for best_rules in choose_best_rule(&rules, preferences) {
+            for rule in best_rules {
+                let split =
+                    split_with_pattern(text, span, rule, chart, ctx.config.split_step_limit());
 
-            match split {
-                Ok(split) => {
-                    let children = split_to_children(
-                        rule.0.pattern().parts(),
-                        &split,
-                        span.start(),
-                        chart,
-                        ctx,
-                    )?;
-                    possibilities.push(ParseTreeChildren::new(rule, children));
+                match split {
+                    Ok(split) => {
+                        let children =
+                            split_to_children(rule, &split, span.start(), chart, preferences, ctx)?;
+                        possibilities.push(ParseTreeChildren::new(rule, children));
+                    }
+                    // We don't allow any ambiguity within a single rule, only
+                    // between rules. So this is an immediate error.
+                    Err(SplitError::Ambiguous) => {
+                        return Diagnostic::err_ambiguous_parse(span, &[rule]);
+                    }
+                    Err(SplitError::TooManySteps { steps, worst }) => {
+                        let worst = worst
+                            .map(|(at, cat, count)| (Location::new(span.source(), at), cat, count));
+                        return Diagnostic::err_split_step_limit(rule, span, steps, worst);
+                    }
+                    Err(SplitError::NoMatch) => {}
                 }
-                // We don't allow any ambiguity within a single rule, only between
-                // rules. So this is an immediate error.
-                Err(SplitError::Ambiguous) => return Diagnostic::err_ambiguous_parse(span),
-                // The chart guaranteed that there is at least one match so
-                // this should never happen.
-                Err(SplitError::NoMatch) => unreachable!(),
             }
+            if !possibilities.is_empty() {
+                break;
+            }
+        }
+
+        // The chart guaranteed that each rule matches the span, so if none
+        // of them split it is because of their precedence.
+        if possibilities.is_empty() {
+            return Diagnostic::err_precedence_parse(span, &rules);
         }
 
         // For the parse tree don't include any whitespace in the span.
         let start = skip_ws_and_comments(text, span.start().offset());
         let start = Location::new(span.source(), start);
-        // If the span is empty skipping the whitespace might put us past the end.
-        let end = if start.byte_offset() < span.end().byte_offset() {
-            span.end()
+        // If the span is empty or only whitespace, skipping the whitespace
+        // might put us past the end, so the tree is left empty at the end.
+        let span = if start.byte_offset() < span.end().byte_offset() {
+            Span::new(start, span.end())
         } else {
-            start
+            Span::new(span.end(), span.end())
         };
-        let span = Span::new(start, end);
-
-        let tree = ParseTree::new(span, cat, possibilities);
-        Ok(ctx.arenas.parse_forest.intern(tree))
+        ctx.sources.debug_assert_char_boundaries(span);
+
+        Ok(ParseTreeId::new(
+            span,
+            cat,
+            possibilities,
+            &ctx.arenas.parse_forest,
+        ))
     }
 
     fn split_to_children<'ctx>(
-        pattern: &[RulePatternPart<'ctx>],
+        rule: RuleId<'ctx>,
         offsets: &[SourceOffset],
         start: Location,
         chart: &TrimmedChart<'ctx>,
+        preferences: &NotationPreferences,
         ctx: &Ctx<'ctx>,
     ) -> WResult<'ctx, Vec<ParseTreePart<'ctx>>> {
+        let pattern = rule.0.pattern().parts();
         debug_assert_eq!(pattern.len(), offsets.len());
 
         let text = ctx.sources.get_text(start.source()).as_str();
 
         let mut start = start;
         let mut parts = Vec::new();
-        for (pat, offset) in pattern.iter().zip(offsets.iter()) {
+        for (i, (pat, offset)) in pattern.iter().zip(offsets.iter()).enumerate() {
             let span = Span::new(start, Location::new(start.source(), *offset));
+            ctx.sources.debug_assert_char_boundaries(span);
 
             match pat {
                 RulePatternPart::Atom(atom_pat) => {
@@ -362,8 +404,9 @@ fn read_chart<'ctx>(
                         ParseAtomPattern::Lit(lit) => ParseAtomKind::Lit(*lit),
                         ParseAtomPattern::Str => {
                             let start = skip_ws_and_comments(text, span.start().offset());
-                            let name = &text[start.byte_offset() + 1..span.end().byte_offset() - 1];
-                            ParseAtomKind::StrLit(name.into())
+                            let (_, contents) = parse_str(text, start).unwrap();
+                            let contents_start = Location::new(span.source(), start.forward(1));
+                            ParseAtomKind::StrLit(unescape_str(contents, contents_start)?.into())
                         }
                         ParseAtomPattern::Num => {
                             let start = skip_ws_and_comments(text, span.start().offset());
@@ -378,7 +421,7 @@ fn read_chart<'ctx>(
                     parts.push(ParseTreePart::Atom(atom));
                 }
                 RulePatternPart::Cat(id) => {
-                    let tree_id = search(span, *id, chart, ctx)?;
+                    let tree_id = search(span, *id, Some((rule, i)), chart, preferences, ctx)?;
                     parts.push(ParseTreePart::Node {
                         id: tree_id,
                         span: tree_id.span(),
@@ -393,114 +436,212 @@ fn read_chart<'ctx>(
         Ok(parts)
     }
 
-    search(span, cat, chart, ctx)
+    search(span, cat, None, chart, preferences, ctx)
 }
 
-fn choose_best_rule<'ctx>(rules: impl Iterator<Item = RuleId<'ctx>>) -> Vec<RuleId<'ctx>> {
-    // We pick the rules with the lowest precedence value.
-    let mut best_rules = Vec::new();
-    let mut best_precedence = None;
+/// The rules grouped by precedence, best first. We prefer the rules with the
+/// lowest precedence value, since they bind loosest and so are the outermost.
+///
+/// When the only two rules are notations with a `prefer` between them they
+/// are tried together, so the preference decides between them rather than
+/// their precedence. With any other rule in the running the precedence
+/// decides as usual, so a preference never hides a three-way ambiguity.
+fn choose_best_rule<'ctx>(
+    rules: &[RuleId<'ctx>],
+    preferences: &NotationPreferences,
+) -> Vec<Vec<RuleId<'ctx>>> {
+    if let [a, b] = rules
+        && let (ParseRuleSource::Notation(a_notation), ParseRuleSource::Notation(b_notation)) =
+            (a.source(), b.source())
+        && preferences.relates(a_notation.primary().name(), b_notation.primary().name())
+    {
+        return vec![vec![*a, *b]];
+    }
 
-    for rule in rules {
+    let mut by_precedence: Vec<(Precedence, Vec<RuleId<'ctx>>)> = Vec::new();
+    for &rule in rules {
         let precedence = rule.pattern().precedence();
-        if best_precedence.is_none_or(|bp| precedence < bp) {
-            best_precedence = Some(precedence);
-            best_rules = vec![rule];
-        } else if best_precedence == Some(precedence) {
-            best_rules.push(rule);
+        match by_precedence.iter_mut().find(|(p, _)| *p == precedence) {
+            Some((_, rules)) => rules.push(rule),
+            None => by_precedence.push((precedence, vec![rule])),
         }
     }
 
-    best_rules
+    by_precedence.sort_by_key(|(precedence, _)| *precedence);
+    by_precedence.into_iter().map(|(_, rules)| rules).collect()
 }
 
-enum SplitError {
+enum SplitError<'ctx> {
     NoMatch,
     Ambiguous,
+    /// The search took more than the configured number of steps.
+    TooManySteps {
+        steps: usize,
+        /// Where the most continuations had to be tried, which category they
+        /// were for and how many there were.
+        worst: Option<(SourceOffset, CategoryId<'ctx>, usize)>,
+    },
 }
 
-fn split_with_pattern(
-    text: &str,
+/// The state of one search for how a rule's pattern splits a span.
+struct Splitter<'a, 'ctx> {
+    text: &'a str,
     span: Span,
-    pattern: &[RulePatternPart],
+    rule: RuleId<'ctx>,
+    pattern: &'a [RulePatternPart<'ctx>],
     associativity: Associativity,
-    stack: &mut Vec<SourceOffset>,
-    chart: &TrimmedChart,
-) -> Result<Vec<SourceOffset>, SplitError> {
-    let at = stack.last().copied().unwrap_or(span.start().offset());
+    chart: &'a TrimmedChart<'ctx>,
+    /// Pattern indices and positions from which the rest of the pattern is
+    /// known not to match. Whether the rest of the pattern matches doesn't
+    /// depend on how we got there, so this stops the search from exploring
+    /// the same failing suffix over and over.
+    failures: FxHashSet<(usize, SourceOffset)>,
+    steps: usize,
+    step_limit: usize,
+    worst: Option<(SourceOffset, CategoryId<'ctx>, usize)>,
+}
 
-    if stack.len() == pattern.len() && at == span.end().offset() {
-        // We have successfully matched the entire pattern.
-        return Ok(stack.clone());
-    }
+fn split_with_pattern<'ctx>(
+    text: &str,
+    span: Span,
+    rule: RuleId<'ctx>,
+    chart: &TrimmedChart<'ctx>,
+    step_limit: usize,
+) -> Result<Vec<SourceOffset>, SplitError<'ctx>> {
+    let mut splitter = Splitter {
+        text,
+        span,
+        rule,
+        pattern: rule.pattern().parts(),
+        associativity: rule.pattern().associativity(),
+        chart,
+        failures: FxHashSet::default(),
+        steps: 0,
+        step_limit,
+        worst: None,
+    };
+    splitter.split(&mut vec![])
+}
 
-    if at.byte_offset() > span.end().byte_offset() {
-        // We have reached the end of the span and not matched so this path is
-        // a failure.
-        return Err(SplitError::NoMatch);
-    }
+impl<'ctx> Splitter<'_, 'ctx> {
+    fn split(
+        &mut self,
+        stack: &mut Vec<SourceOffset>,
+    ) -> Result<Vec<SourceOffset>, SplitError<'ctx>> {
+        let at = stack.last().copied().unwrap_or(self.span.start().offset());
+        let key = (stack.len(), at);
+
+        if self.failures.contains(&key) {
+            return Err(SplitError::NoMatch);
+        }
 
-    if stack.len() == pattern.len() {
-        // We have matched the entire pattern but not reached the end of the
-        // span so this path is a failure.
-        return Err(SplitError::NoMatch);
+        self.steps += 1;
+        if self.steps > self.step_limit {
+            return Err(SplitError::TooManySteps {
+                steps: self.steps,
+                worst: self.worst,
+            });
+        }
+
+        let result = self.split_uncached(stack, at);
+        if matches!(result, Err(SplitError::NoMatch)) {
+            self.failures.insert(key);
+        }
+        result
     }
 
-    match pattern[stack.len()] {
-        RulePatternPart::Atom(atom) => {
-            // Check if the text has the atom at the current position.
-            let Some(atom_end) = parse_atom(atom, text, at) else {
-                return Err(SplitError::NoMatch);
-            };
-            stack.push(atom_end);
-            let result = split_with_pattern(text, span, pattern, associativity, stack, chart);
-            stack.pop();
+    fn split_uncached(
+        &mut self,
+        stack: &mut Vec<SourceOffset>,
+        at: SourceOffset,
+    ) -> Result<Vec<SourceOffset>, SplitError<'ctx>> {
+        if stack.len() == self.pattern.len() && at == self.span.end().offset() {
+            // We have successfully matched the entire pattern.
+            return Ok(stack.clone());
+        }
 
-            result
+        if at.byte_offset() > self.span.end().byte_offset() {
+            // We have reached the end of the span and not matched so this path is
+            // a failure.
+            return Err(SplitError::NoMatch);
+        }
+
+        if stack.len() == self.pattern.len() {
+            // We have matched the entire pattern but not reached the end of the
+            // span so this path is a failure.
+            return Err(SplitError::NoMatch);
         }
-        RulePatternPart::Cat(cat) => {
-            let continuations = chart.get(&(at, cat)).ok_or(SplitError::NoMatch)?;
-            let mut continuations = continuations.clone();
-
-            // Now we need to decide in which order to try the continuations. This
-            // depends on the associativity of the rule. If it is left associative
-            // we try the longest continuations first. If it is right associative we
-            // try the shortest continuations first. If it is non-associative we
-            // try them in the order they appear.
-            match associativity {
-                Associativity::Left => continuations.sort_by_key(|c| Reverse(c.1)),
-                Associativity::Right => continuations.sort_by_key(|c| c.1),
-                Associativity::NonAssoc => {}
-            }
 
-            // Now we search through the continuations. If the pattern is
-            // associative we take the first match. If it is non-associative
-            // we need to ensure there is only one match.
-            let mut solution = None;
-            for continuation in continuations {
-                stack.push(continuation.1);
-                let result = split_with_pattern(text, span, pattern, associativity, stack, chart);
+        match self.pattern[stack.len()] {
+            RulePatternPart::Atom(atom) => {
+                // Check if the text has the atom at the current position.
+                let Some(atom_end) = parse_atom(atom, self.text, at) else {
+                    return Err(SplitError::NoMatch);
+                };
+                stack.push(atom_end);
+                let result = self.split(stack);
                 stack.pop();
 
-                match result {
-                    Ok(split) => {
-                        if matches!(associativity, Associativity::NonAssoc) {
-                            if solution.is_some() && solution.as_ref() != Some(&split) {
-                                // We have found more than one match so this
-                                // is ambiguous.
-                                return Err(SplitError::Ambiguous);
+                result
+            }
+            RulePatternPart::Cat(cat) => {
+                // Only the rules the operand's precedence allows can continue
+                // the pattern.
+                let continuations = self.chart.get(&(at, cat)).ok_or(SplitError::NoMatch)?;
+                let part = stack.len();
+                let mut continuations: Vec<_> = continuations
+                    .iter()
+                    .filter(|(child, _)| self.rule.admits(part, child))
+                    .copied()
+                    .collect();
+
+                if self
+                    .worst
+                    .is_none_or(|(_, _, count)| continuations.len() > count)
+                {
+                    self.worst = Some((at, cat, continuations.len()));
+                }
+
+                // Now we need to decide in which order to try the continuations. This
+                // depends on the associativity of the rule. If it is left associative
+                // we try the longest continuations first. If it is right associative we
+                // try the shortest continuations first. If it is non-associative we
+                // try them in the order they appear.
+                match self.associativity {
+                    Associativity::Left => continuations.sort_by_key(|c| Reverse(c.1)),
+                    Associativity::Right => continuations.sort_by_key(|c| c.1),
+                    Associativity::NonAssoc => {}
+                }
+
+                // Now we search through the continuations. If the pattern is
+                // associative we take the first match. If it is non-associative
+                // we need to ensure there is only one match.
+                let mut solution = None;
+                for continuation in continuations {
+                    stack.push(continuation.1);
+                    let result = self.split(stack);
+                    stack.pop();
+
+                    match result {
+                        Ok(split) => {
+                            if matches!(self.associativity, Associativity::NonAssoc) {
+                                if solution.is_some() && solution.as_ref() != Some(&split) {
+                                    // We have found more than one match so this
+                                    // is ambiguous.
+                                    return Err(SplitError::Ambiguous);
+                                }
+                                solution = Some(split);
+                            } else {
+                                return Ok(split);
                             }
-                            solution = Some(split);
-                        } else {
-                            return Ok(split);
                         }
+                        Err(SplitError::NoMatch) => continue,
+                        Err(err) => return Err(err),
                     }
-                    Err(SplitError::NoMatch) => continue,
-                    Err(SplitError::Ambiguous) => return Err(SplitError::Ambiguous),
                 }
-            }
 
-            solution.ok_or(SplitError::NoMatch)
+                solution.ok_or(SplitError::NoMatch)
+            }
         }
     }
 }
@@ -534,7 +675,7 @@ fn _debug_trimmed_chart<'ctx>(trimmed: &TrimmedChart<'ctx>) {
     trimmed.sort_by_key(|(key, _)| *key);
 
     for ((offset, cat), completions) in trimmed {
-        println!("At {offset:?}, completed <{}>:", cat._name());
+        println!("At {offset:?}, completed <{}>:", cat.name());
         for (rule, end) in completions {
             let mut pattern = String::new();
             for part in rule.pattern().parts() {
@@ -547,12 +688,12 @@ fn _debug_trimmed_chart<'ctx>(trimmed: &TrimmedChart<'ctx>) {
                         ParseAtomPattern::Num => pattern.push_str("num "),
                     },
                     RulePatternPart::Cat(id) => {
-                        let cat_name = id._name();
+                        let cat_name = id.name();
                         pattern.push_str(&format!("<{cat_name}> "));
                     }
                 }
             }
-            println!("  {} -> {} (to {:?})", rule.cat()._name(), pattern, end);
+            println!("  {} -> {} (to {:?})", rule.cat().name(), pattern, end);
         }
         println!();
     }
@@ -606,7 +747,10 @@ pub fn parse_name(text: &str, from: SourceOffset) -> Option<(SourceOffset, &str)
     Some((at, &text[from.byte_offset()..at.byte_offset()]))
 }
 
-fn parse_str(text: &str, from: SourceOffset) -> Option<(SourceOffset, &str)> {
+/// Find the string literal starting at `from` and return where it ends and
+/// its contents as written, with any escapes still in them. A backslash
+/// escapes the character after it, so `\"` doesn't end the literal.
+pub fn parse_str(text: &str, from: SourceOffset) -> Option<(SourceOffset, &str)> {
     let mut chars = text[from.byte_offset()..].chars();
 
     let first_char = chars.next()?;
@@ -615,14 +759,17 @@ fn parse_str(text: &str, from: SourceOffset) -> Option<(SourceOffset, &str)> {
     }
     let mut at = from.forward(first_char.len_utf8());
 
+    let mut escaped = false;
     for next_char in chars {
-        if next_char == '"' {
+        if next_char == '"' && !escaped {
             // We have reached the end of the string.
+            let contents_start = from.forward(first_char.len_utf8());
             return Some((
                 at.forward(next_char.len_utf8()),
-                &text[from.byte_offset() + 1..at.byte_offset()],
+                &text[contents_start.byte_offset()..at.byte_offset()],
             ));
         }
+        escaped = next_char == '\\' && !escaped;
         at = at.forward(next_char.len_utf8());
     }
 
@@ -630,6 +777,77 @@ fn parse_str(text: &str, from: SourceOffset) -> Option<(SourceOffset, &str)> {
     None
 }
 
+/// The value of a string literal whose contents, as returned by
+/// [`parse_str`], start at `start`.
+pub fn unescape_str<'ctx>(contents: &str, start: Location) -> WResult<'ctx, String> {
+    let mut value = String::with_capacity(contents.len());
+    let mut chars = contents.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            value.push(c);
+            continue;
+        }
+
+        // `parse_str` never ends a literal right after a backslash.
+        let (j, escape) = chars.next().unwrap();
+        let unescaped = match escape {
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'u' => unescape_unicode(&contents[j + 1..]).map(|(c, len)| {
+                while chars.peek().is_some_and(|&(k, _)| k < j + 1 + len) {
+                    chars.next();
+                }
+                c
+            }),
+            _ => None,
+        };
+        let Some(unescaped) = unescaped else {
+            // Point at the braces of a bad `\u{...}` too, if it has them.
+            let mut end = j + escape.len_utf8();
+            if escape == 'u'
+                && contents[end..].starts_with('{')
+                && let Some(close) = contents[end..].find('}')
+            {
+                end += close + 1;
+            }
+            let span = Span::new(start.forward(i), start.forward(end));
+            return Diagnostic::err_invalid_str_escape(span, &contents[i..end]);
+        };
+        value.push(unescaped);
+    }
+    Ok(value)
+}
+
+/// Write `value` as the contents of a string literal, escaping what
+/// [`unescape_str`] unescapes.
+pub fn escape_str(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The character named by the `{XXXX}` of a `\u{XXXX}` escape at the start
+/// of `text`, and the length of the braces.
+fn unescape_unicode(text: &str) -> Option<(char, usize)> {
+    let close = text.strip_prefix('{')?.find('}')? + 1;
+    let hex = &text[1..close];
+    if hex.is_empty() || hex.len() > 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let c = char::from_u32(u32::from_str_radix(hex, 16).ok()?)?;
+    Some((c, close + 1))
+}
+
 fn parse_num(text: &str, from: SourceOffset) -> Option<SourceOffset> {
     let mut chars = text[from.byte_offset()..].chars();
     let mut at = from;
@@ -651,7 +869,7 @@ fn char_can_continue_name(char: char) -> bool {
     char_can_start_name(char) || char.is_numeric() || char == '.'
 }
 
-fn skip_ws_and_comments(text: &str, mut at: SourceOffset) -> SourceOffset {
+pub fn skip_ws_and_comments(text: &str, mut at: SourceOffset) -> SourceOffset {
     let mut chars = text[at.byte_offset()..].chars().peekable();
 
     while let Some(next_char) = chars.next() {
@@ -661,7 +879,7 @@ fn skip_ws_and_comments(text: &str, mut at: SourceOffset) -> SourceOffset {
         }
 
         if next_char == '-' && chars.peek() == Some(&'-') {
-            at = at.forward(1);
+            at = at.forward(next_char.len_utf8());
             for next_char in chars.by_ref() {
                 at = at.forward(next_char.len_utf8());
                 if next_char == '\n' {
@@ -677,3 +895,58 @@ fn skip_ws_and_comments(text: &str, mut at: SourceOffset) -> SourceOffset {
 
     at
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_str, parse_str, unescape_str};
+    use crate::parse::{
+        Location, SourceId,
+        location::{SourceOffset, Span},
+    };
+    use ustr::Ustr;
+
+    fn at(offset: usize) -> Location {
+        Location::new(
+            SourceId::new(Ustr::from("escapes")),
+            SourceOffset::new(offset),
+        )
+    }
+
+    /// The value of a literal whose contents start at offset 1, or the title
+    /// and span of the error for it.
+    fn unescape(contents: &str) -> Result<String, (String, Option<Span>)> {
+        unescape_str(contents, at(1))
+            .map_err(|diags| (diags[0].title().to_string(), diags[0].primary_span()))
+    }
+
+    #[test]
+    fn an_escaped_quote_round_trips() {
+        let (end, contents) = parse_str(r#""\"" rest"#, SourceOffset::new(0)).unwrap();
+        assert_eq!(end, SourceOffset::new(4));
+        assert_eq!(contents, r#"\""#);
+        assert_eq!(unescape(contents), Ok("\"".to_string()));
+        assert_eq!(escape_str("\""), contents);
+    }
+
+    #[test]
+    fn every_escape_round_trips() {
+        let value = "a \"b\" \\ c\nd\te";
+        assert_eq!(unescape(&escape_str(value)), Ok(value.to_string()));
+        assert_eq!(unescape(r"\u{22A4}"), Ok("⊤".to_string()));
+    }
+
+    #[test]
+    fn an_invalid_escape_is_named_and_pointed_at() {
+        let title = "invalid escape `\\q` in string literal".to_string();
+        assert_eq!(
+            unescape(r"a\qb"),
+            Err((title, Some(Span::new(at(2), at(4)))))
+        );
+
+        let title = "invalid escape `\\u{zz}` in string literal".to_string();
+        assert_eq!(
+            unescape(r"\u{zz}"),
+            Err((title, Some(Span::new(at(1), at(7)))))
+        );
+    }
+}
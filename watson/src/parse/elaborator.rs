@@ -2,13 +2,17 @@ use crate::{
     context::Ctx,
     diagnostics::{Diagnostic, DiagnosticSpan, WResult},
     parse::{
-        SourceId,
-        parse_state::{Associativity, ParseRuleSource, Precedence, SyntaxCategorySource},
-        parse_tree::{ParseTreeChildren, ParseTreeId},
-        source_cache::{SourceDecl, source_id_to_path},
+        SourceId, Span, grammar,
+        module_graph::ModuleImport,
+        parse_state::{
+            Associativity, ParseRuleSource, Precedence, RulePatternPart, SyntaxCategorySource,
+        },
+        parse_tree::{ParseTreeChildren, ParseTreeId, ParseTreePart},
+        source_cache::{SourceDecl, read_source_file, source_id_to_path},
+        source_files::similar_sources,
     },
     semant::{
-        attributes::Attribute,
+        attributes::{Attribute, AttributeValue, BuiltinAttribute, BuiltinAttributeKind},
         commands::CommandId,
         custom_grammar::{
             inst::{CustomGrammarInst, CustomGrammarInstPart, SpannedStr},
@@ -21,16 +25,21 @@ use crate::{
             FormalSyntaxCat, FormalSyntaxCatId, FormalSyntaxPat, FormalSyntaxPatPart,
             FormalSyntaxRule, FormalSyntaxRuleId,
         },
+        fragment::{Abbreviation, FragHead, FragmentId, abbreviation_frag, hole_frag},
         notation::{
             NotationBinding, NotationBindingId, NotationPattern, NotationPatternId,
-            NotationPatternPart, NotationPatternPartCat, NotationPatternSource,
-            NotationSignatureHole,
+            NotationPatternPart, NotationPatternPartCat, NotationSignatureHole,
+        },
+        parse_fragment::{
+            UnresolvedAnyFrag, UnresolvedFact, UnresolvedFrag, parse_fragment_with_warnings,
         },
-        parse_fragment::{UnresolvedAnyFrag, UnresolvedFact, UnresolvedFrag, parse_fragment},
         presentation::PresFrag,
+        provenance::{DeclKind, Provenance},
         scope::{DefinitionSource, Scope, ScopeEntry},
         tactic::unresolved_proof::UnresolvedProof,
-        theorems::{PresFact, Template, TheoremId, TheoremStatement, add_templates_to_scope},
+        theorems::{
+            PresFact, Template, TheoremId, TheoremKind, TheoremStatement, add_templates_to_scope,
+        },
     },
     strings,
 };
@@ -56,11 +65,16 @@ const RESERVED_LUAU_TYPES: &[&str] = &[
     "BindingMeta",
     "Scope",
     "ScopeMeta",
+    "Source",
+    "SourceMeta",
+    "LineCol",
+    "AuditEntry",
     "ThmTemplate",
     "Theorem",
     "TheoremMeta",
     "ProofState",
     "TacticInfo",
+    "TacticHandlers",
     "DiagnosticSpan",
     "Diagnostic",
     "DiagnosticMeta",
@@ -108,42 +122,60 @@ macro_rules! match_rule {
 #[allow(clippy::enum_variant_names)]
 pub enum ElaborateAction<'ctx> {
     NewSource(SourceId),
+    ImportRoot(SourceId),
+    /// A module which another source in the same root already loaded, so
+    /// its definitions are already in scope.
+    ImportLoaded,
     NewFormalCat(FormalSyntaxCatId<'ctx>),
     NewFormalRule(FormalSyntaxRuleId<'ctx>),
     NewNotation(NotationPatternId<'ctx>),
+    /// A preference for the first notation over the second, declared at the span.
+    NewPreference(Ustr, Ustr, Span),
     NewDefinition(Scope<'ctx>),
     NewTheorem(TheoremId<'ctx>, UnresolvedProof<'ctx>),
     NewGrammarCat(CustomGrammarCatId<'ctx>),
     NewGrammarRule(CustomGrammarRuleId<'ctx>),
+    /// A `scope` command at the span, opening a block whose definitions are
+    /// forgotten at its `end`.
+    PushScope(Span),
+    /// An `end` command at the span, closing the innermost `scope` block.
+    PopScope(Span),
 }
 
+/// Elaborate a command. `open_scope` is the innermost `scope` block the
+/// command is in, if any.
 pub fn elaborate_command_decl<'ctx>(
     command_decl: ParseTreeId<'ctx>,
     cmd: CommandId<'ctx>,
     scope: &Scope<'ctx>,
+    open_scope: Option<Span>,
     ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, (ElaborateAction<'ctx>, Vec<Attribute<'ctx>>)> {
     // command_decl ::= (command_decl) maybe_attribute_anno command
 
     match_rule! { (ctx, command_decl) =>
         command_decl ::= [maybe_attr_anno, command] => {
-            let attrs = elaborate_maybe_attribute_anno(maybe_attr_anno.as_node().unwrap(), ctx)?;
-            let cmd = elaborate_command(command.as_node().unwrap(), cmd, scope, ctx)?;
-            Ok((cmd, attrs))
+            let (attrs, builtin_attrs) = elaborate_maybe_attribute_anno(maybe_attr_anno.as_node().unwrap(), ctx)?;
+            check_attribute_targets(&builtin_attrs, command.as_node().unwrap(), ctx)?;
+            ctx.command_attributes.insert(cmd, builtin_attrs);
+            let action = elaborate_command(command.as_node().unwrap(), cmd, scope, open_scope, ctx)?;
+            Ok((action, attrs))
         }
     }
 }
 
+type ElaboratedAttributes<'ctx> = (Vec<Attribute<'ctx>>, Vec<BuiltinAttribute>);
+
 fn elaborate_maybe_attribute_anno<'ctx>(
     maybe_anno: ParseTreeId<'ctx>,
-    ctx: &Ctx<'ctx>,
-) -> WResult<'ctx, Vec<Attribute<'ctx>>> {
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, ElaboratedAttributes<'ctx>> {
     // maybe_attribute_anno ::= (attribute_anno_some) attribute_anno
     //                        | (attribute_anno_none)
 
     match_rule! { (ctx, maybe_anno) =>
         attribute_anno_none ::= [] => {
-            Ok(Vec::new())
+            Ok((Vec::new(), Vec::new()))
         },
         attribute_anno_some ::= [anno] => {
             elaborate_attribute_anno(anno.as_node().unwrap(), ctx)
@@ -153,8 +185,8 @@ fn elaborate_maybe_attribute_anno<'ctx>(
 
 fn elaborate_attribute_anno<'ctx>(
     anno: ParseTreeId<'ctx>,
-    ctx: &Ctx<'ctx>,
-) -> WResult<'ctx, Vec<Attribute<'ctx>>> {
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, ElaboratedAttributes<'ctx>> {
     // attribute_anno ::= (attribute_anno) "@" "[" attributes "]"
 
     match_rule! { (ctx, anno) =>
@@ -166,23 +198,21 @@ fn elaborate_attribute_anno<'ctx>(
 
 fn elaborate_attributes<'ctx>(
     mut attrs: ParseTreeId<'ctx>,
-    ctx: &Ctx<'ctx>,
-) -> WResult<'ctx, Vec<Attribute<'ctx>>> {
-    // attributes ::= (attributes_one)  attribute
-    //              | (attributes_many) attribute "," attributes
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, ElaboratedAttributes<'ctx>> {
+    // attributes ::= (attributes_one)  attribute_item
+    //              | (attributes_many) attribute_item "," attributes
 
-    let mut result = Vec::new();
+    let mut result = (Vec::new(), Vec::new());
 
     loop {
         match_rule! { (ctx, attrs) =>
-            attributes_one ::= [attr] => {
-                let inst = elaborate_custom_grammar(attr.as_node().unwrap(), ctx)?;
-                result.push(Attribute(inst));
+            attributes_one ::= [item] => {
+                elaborate_attribute_item(item.as_node().unwrap(), &mut result, ctx)?;
                 break;
             },
-            attributes_many ::= [attr, _comma, rest] => {
-                let inst = elaborate_custom_grammar(attr.as_node().unwrap(), ctx)?;
-                result.push(Attribute(inst));
+            attributes_many ::= [item, _comma, rest] => {
+                elaborate_attribute_item(item.as_node().unwrap(), &mut result, ctx)?;
                 attrs = rest.as_node().unwrap();
             }
         }
@@ -191,27 +221,130 @@ fn elaborate_attributes<'ctx>(
     Ok(result)
 }
 
+fn elaborate_attribute_item<'ctx>(
+    item: ParseTreeId<'ctx>,
+    (attrs, builtin_attrs): &mut ElaboratedAttributes<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, ()> {
+    // attribute_item ::= (attribute_custom)  attribute
+    //                  | (attribute_builtin) name maybe_attribute_arg
+
+    // An attribute declared with an `attribute` command may also read as a
+    // builtin attribute without a value. The declared one wins.
+    let custom = item
+        .possibilities()
+        .find(|children| children.rule() == ctx.builtin_rules.attribute_custom);
+    let children = match custom {
+        Some(children) => children,
+        None => expect_unambiguous(item)?,
+    };
+
+    match children.children() {
+        [attr] => {
+            let inst = elaborate_custom_grammar(attr.as_node().unwrap(), ctx)?;
+            attrs.push(Attribute(inst));
+        }
+        [name_node, arg] => {
+            let name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
+            let value = elaborate_maybe_attribute_arg(arg.as_node().unwrap(), ctx)?;
+            match BuiltinAttributeKind::from_name(&name) {
+                Some(kind) if kind.accepts(value) => builtin_attrs.push(BuiltinAttribute {
+                    kind,
+                    value,
+                    span: item.span(),
+                }),
+                Some(kind) => return Diagnostic::err_attribute_value(kind, value, arg.span()),
+                None => {
+                    let diag = Diagnostic::warn_unknown_attribute(name, name_node.span(), ctx);
+                    ctx.diags.add_diag(diag);
+                }
+            }
+        }
+        _ => {
+            failed_to_match_builtin!(children.rule(), ctx);
+        }
+    }
+
+    Ok(())
+}
+
+fn elaborate_maybe_attribute_arg<'ctx>(
+    maybe_arg: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, AttributeValue> {
+    // maybe_attribute_arg ::= (attribute_arg_none)
+    //                       | (attribute_arg_str)  "(" str ")"
+    //                       | (attribute_arg_num)  "(" number ")"
+    //                       | (attribute_arg_name) "(" name ")"
+
+    match_rule! { (ctx, maybe_arg) =>
+        attribute_arg_none ::= [] => Ok(AttributeValue::None),
+        attribute_arg_str ::= [_lparen, str_lit, _rparen] => {
+            let str_lit = elaborate_str_lit(str_lit.as_node().unwrap(), ctx)?;
+            Ok(AttributeValue::Str(str_lit))
+        },
+        attribute_arg_num ::= [_lparen, num, _rparen] => {
+            Ok(AttributeValue::Num(num.as_num().unwrap()))
+        },
+        attribute_arg_name ::= [_lparen, name, _rparen] => {
+            let name = elaborate_name(name.as_node().unwrap(), ctx)?;
+            Ok(AttributeValue::Name(name))
+        }
+    }
+}
+
+/// Check each builtin attribute is given to a command it applies to.
+fn check_attribute_targets<'ctx>(
+    attrs: &[BuiltinAttribute],
+    command: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, ()> {
+    let rule = expect_unambiguous(command)?.rule();
+    let is_notation =
+        rule == ctx.builtin_rules.notation_command || rule == ctx.builtin_rules.alias_command;
+
+    for attr in attrs {
+        match attr.kind {
+            BuiltinAttributeKind::Deprecated if rule != ctx.builtin_rules.axiom_command => {
+                return Diagnostic::err_attribute_target(attr.kind, "axioms", attr.span);
+            }
+            BuiltinAttributeKind::Local if !is_notation => {
+                return Diagnostic::err_attribute_target(attr.kind, "notation", attr.span);
+            }
+            BuiltinAttributeKind::Local => {
+                return Diagnostic::err_local_notation_unsupported(attr.span);
+            }
+            BuiltinAttributeKind::Deprecated | BuiltinAttributeKind::Hidden => {}
+        }
+    }
+
+    Ok(())
+}
+
 pub fn elaborate_command<'ctx>(
     command: ParseTreeId<'ctx>,
     cmd: CommandId<'ctx>,
     scope: &Scope<'ctx>,
+    open_scope: Option<Span>,
     ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, ElaborateAction<'ctx>> {
     // command ::= (module_command)           module_command
     //           | (syntax_cat_command)       syntax_cat_command
     //           | (syntax_command)           syntax_command
     //           | (notation_command)         notation_command
+    //           | (prefer_command)           prefer_command
+    //           | (alias_command)            alias_command
     //           | (definition_command)       definition_command
     //           | (axiom_command)            axiom_command
     //           | (theorem_command)          theorem_command
     //           | (grammar_category_command) grammar_category_command
     //           | (tactic_command)           tactic_command
     //           | (attribute_command)        attribute_command
+    //           | (scope_command)            scope_command
 
     match_rule! { (ctx, command) =>
         module_command ::= [module_cmd] => {
-            let new_source = elaborate_module(module_cmd.as_node().unwrap(), ctx)?;
-            Ok(ElaborateAction::NewSource(new_source))
+            elaborate_module(module_cmd.as_node().unwrap(), open_scope, ctx)
         },
         syntax_cat_command ::= [cat_cmd] => {
             let cat = elaborate_syntax_cat(cat_cmd.as_node().unwrap(), ctx)?;
@@ -225,12 +358,20 @@ pub fn elaborate_command<'ctx>(
             let notation = elaborate_notation(notation_cmd.as_node().unwrap(), ctx)?;
             Ok(ElaborateAction::NewNotation(notation))
         },
+        prefer_command ::= [prefer_cmd] => {
+            let (preferred, other) = elaborate_prefer(prefer_cmd.as_node().unwrap(), scope, ctx)?;
+            Ok(ElaborateAction::NewPreference(preferred, other, prefer_cmd.span()))
+        },
+        alias_command ::= [alias_cmd] => {
+            let alias = elaborate_alias(alias_cmd.as_node().unwrap(), ctx)?;
+            Ok(ElaborateAction::NewNotation(alias))
+        },
         definition_command ::= [definition_cmd] => {
             let new_scope = elaborate_definition(definition_cmd.as_node().unwrap(), cmd, scope, ctx)?;
             Ok(ElaborateAction::NewDefinition(new_scope))
         },
         axiom_command ::= [axiom_cmd] => {
-            let (thm_id, proof) = elaborate_axiom(axiom_cmd.as_node().unwrap(), scope, ctx)?;
+            let (thm_id, proof) = elaborate_axiom(axiom_cmd.as_node().unwrap(), cmd, scope, ctx)?;
             Ok(ElaborateAction::NewTheorem(thm_id, proof))
         },
         theorem_command ::= [theorem_cmd] => {
@@ -249,10 +390,17 @@ pub fn elaborate_command<'ctx>(
             let rule = elaborate_attribute_def(attribute_cmd.as_node().unwrap(), scope, ctx)?;
             Ok(ElaborateAction::NewGrammarRule(rule))
         },
+        scope_command ::= [scope_cmd] => {
+            elaborate_scope(scope_cmd.as_node().unwrap(), ctx)
+        },
     }
 }
 
-fn elaborate_module<'ctx>(module: ParseTreeId<'ctx>, ctx: &Ctx<'ctx>) -> WResult<'ctx, SourceId> {
+fn elaborate_module<'ctx>(
+    module: ParseTreeId<'ctx>,
+    open_scope: Option<Span>,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, ElaborateAction<'ctx>> {
     // module_command ::= (module) kw"module" name
 
     match_rule! { (ctx, module) =>
@@ -260,45 +408,140 @@ fn elaborate_module<'ctx>(module: ParseTreeId<'ctx>, ctx: &Ctx<'ctx>) -> WResult
             debug_assert!(module_kw.is_kw(*strings::MODULE));
             let source_id_str = elaborate_name(source_id_name.as_node().unwrap(), ctx)?;
             let source_id = SourceId::new(source_id_str);
+            let importer = module.span().source();
+            let import = ModuleImport {
+                importer,
+                imported: source_id,
+                span: source_id_name.span(),
+                loaded: false,
+            };
+
+            // The sources still being parsed are the ones which loaded this
+            // one, so importing any of them would never finish.
+            if let Some(chain) = ctx.module_graph.cycle(importer, source_id) {
+                return Diagnostic::err_module_cycle(&chain, import);
+            }
+
+            // Roots which have already been parsed can be imported by later roots.
+            if ctx.root_scopes.contains_key(&source_id) {
+                ctx.module_graph.add(import);
+                return Ok(ElaborateAction::ImportRoot(source_id));
+            }
 
             if ctx.sources.has_source(source_id) {
+                // A module loaded elsewhere in this root has already added its
+                // definitions to the scope, which is shared by the whole root.
+                // This lets two modules import a third rather than it being a
+                // redeclaration, but a file importing the same module twice
+                // is most likely a mistake.
+                let graph = &ctx.module_graph;
+                let same_root = graph.root_of(source_id) == graph.root_of(importer);
+                let is_module = matches!(ctx.sources.get_decl(source_id), SourceDecl::Module(_));
+                if is_module && same_root {
+                    let previous = graph
+                        .imports_of(importer)
+                        .find(|previous| previous.imported == source_id)
+                        .map(|previous| previous.span);
+                    if let Some(previous) = previous {
+                        ctx.diags.add_diag(Diagnostic::warn_repeated_module_import(
+                            source_id,
+                            source_id_name.span(),
+                            previous,
+                        ));
+                    }
+                    ctx.module_graph.add(import);
+                    return Ok(ElaborateAction::ImportLoaded);
+                }
+
                 return Diagnostic::err_module_redeclaration(
                     source_id,
                     source_id_name.span(),
                     ctx.sources.get_decl(source_id),
+                    None,
                 );
             }
 
-            let (path1, path2) = source_id_to_path(source_id, ctx.config.math_dir());
-            let Ok(text) = std::fs::read_to_string(&path1).or_else(|_| std::fs::read_to_string(&path2)) else {
-                return Diagnostic::err_non_existent_file(&path1, &path2, source_id_name.span());
+            // A module's definitions are in scope for the rest of the root,
+            // since importing it again doesn't parse it again, so they can't
+            // be forgotten at the end of a block. It is rejected before it is
+            // registered so a later import outside the block still loads it.
+            if let Some(open) = open_scope {
+                return Diagnostic::err_module_in_scope(module.span(), open);
+            }
+
+            if !ctx.config.allow_fs() {
+                return Diagnostic::err_module_without_fs(source_id_name.span());
+            }
+
+            let Some((path, text)) = read_source_file(source_id, ctx.config.math_dir()) else {
+                let (path1, path2) = source_id_to_path(source_id, ctx.config.math_dir());
+                let similar = similar_sources(source_id, ctx.config.math_dir());
+                return Diagnostic::err_non_existent_file(&path1, &path2, Some(source_id_name.span()), &similar);
             };
 
+            // Different names can lead to the same file, like `a.main` and
+            // `a` to `a/main.wats`, which would load it twice.
+            if let Err(previous) = ctx.module_graph.add_file(source_id, &path) {
+                return Diagnostic::err_module_redeclaration(
+                    previous,
+                    source_id_name.span(),
+                    ctx.sources.get_decl(previous),
+                    Some((source_id, &path)),
+                );
+            }
+
             ctx.sources
                 .add(source_id, text, SourceDecl::Module(source_id_name.span()));
+            ctx.module_graph.add(ModuleImport { loaded: true, ..import });
 
-            Ok(source_id)
+            Ok(ElaborateAction::NewSource(source_id))
         }
 
     }
 }
 
+fn elaborate_scope<'ctx>(
+    scope_cmd: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, ElaborateAction<'ctx>> {
+    // scope_command ::= (scope_open)  kw"scope"
+    //                 | (scope_close) kw"end"
+
+    match_rule! { (ctx, scope_cmd) =>
+        scope_open ::= [scope_kw] => {
+            debug_assert!(scope_kw.is_kw(*strings::SCOPE));
+            Ok(ElaborateAction::PushScope(scope_cmd.span()))
+        },
+        scope_close ::= [end_kw] => {
+            debug_assert!(end_kw.is_kw(*strings::END));
+            Ok(ElaborateAction::PopScope(scope_cmd.span()))
+        }
+    }
+}
+
 fn elaborate_syntax_cat<'ctx>(
     cat: ParseTreeId<'ctx>,
-    ctx: &Ctx<'ctx>,
+    ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, FormalSyntaxCatId<'ctx>> {
     // syntax_cat_command ::= (syntax_cat) kw"syntax_cat" name
 
     match_rule! { (ctx, cat) =>
         syntax_cat ::= [syntax_kw, cat_name] => {
             debug_assert!(syntax_kw.is_kw(*strings::SYNTAX_CAT));
-            let cat_name = elaborate_name(cat_name.as_node().unwrap(), ctx)?;
+            let cat_name_node = cat_name.as_node().unwrap();
+            let cat_name = elaborate_name(cat_name_node, ctx)?;
 
-            if ctx.arenas.formal_cats.get(cat_name).is_some() {
-                return Diagnostic::err_duplicate_formal_syntax_cat();
+            if let Some(existing) = ctx.arenas.formal_cats.get(cat_name) {
+                return Diagnostic::err_duplicate_formal_syntax_cat(
+                    cat_name,
+                    cat_name_node.span(),
+                    existing.provenance(),
+                );
             }
+            warn_if_keyword(cat_name, cat_name_node.span(), ctx);
 
-            let formal_cat = FormalSyntaxCat::new(cat_name);
+            let provenance = Provenance::declared(cat.span(), DeclKind::SyntaxCategory);
+            let formal_cat = FormalSyntaxCat::new(cat_name, provenance);
             let formal_cat = ctx.arenas.formal_cats.alloc(cat_name, formal_cat);
             Ok(formal_cat)
         }
@@ -325,14 +568,19 @@ fn elaborate_syntax<'ctx>(
             pat.set_assoc(assoc);
 
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat.span(), ctx);
             };
 
-            if ctx.arenas.formal_rules.get(rule_name).is_some() {
-                return Diagnostic::err_duplicate_formal_syntax_rule();
+            if let Some(existing) = ctx.arenas.formal_rules.get(rule_name) {
+                return Diagnostic::err_duplicate_formal_syntax_rule(
+                    rule_name,
+                    rule_name_node.span(),
+                    existing.provenance(),
+                );
             }
 
-            let rule = FormalSyntaxRule::new(rule_name, cat, pat, rule_name_node.span());
+            let provenance = Provenance::declared(syntax.span(), DeclKind::Syntax);
+            let rule = FormalSyntaxRule::new(rule_name, cat, pat, provenance);
             let rule_id = ctx.arenas.formal_rules.alloc(rule_name, rule);
 
             Ok(rule_id)
@@ -400,20 +648,28 @@ fn elaborate_syntax_pat<'ctx>(
     //              | (syntax_pat_many) syntax_pat_part syntax_pat
 
     let mut parts = Vec::new();
+    let mut errs = Vec::new();
 
     loop {
-        match_rule! { (ctx, pat_list) =>
-            syntax_pat_one ::= [pat] => {
-                let pat = pat.as_node().unwrap();
-                parts.push(elaborate_syntax_pat_part(pat, ctx)?);
-                break;
-            },
-            syntax_pat_many ::= [pat, rest] => {
-                let pat = pat.as_node().unwrap();
-                parts.push(elaborate_syntax_pat_part(pat, ctx)?);
-                pat_list = rest.as_node().unwrap();
-            }
+        let (pat, rest) = match_rule! { (ctx, pat_list) =>
+            syntax_pat_one ::= [pat] => (pat.as_node().unwrap(), None),
+            syntax_pat_many ::= [pat, rest] => (pat.as_node().unwrap(), Some(rest.as_node().unwrap()))
+        };
+
+        // Each part is independent so report the errors from all of them.
+        match elaborate_syntax_pat_part(pat, ctx) {
+            Ok(part) => parts.push(part),
+            Err(part_errs) => errs.extend(part_errs),
         }
+
+        match rest {
+            Some(rest) => pat_list = rest,
+            None => break,
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(errs);
     }
 
     let pat = FormalSyntaxPat::new(parts);
@@ -433,7 +689,7 @@ fn elaborate_syntax_pat_part<'ctx>(
             let cat_name = elaborate_name(cat_name_node.as_node().unwrap(), ctx)?;
 
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span(), ctx);
             };
 
             Ok(FormalSyntaxPatPart::Cat(cat))
@@ -446,7 +702,7 @@ fn elaborate_syntax_pat_part<'ctx>(
 
             let cat_name = elaborate_name(cat_name_node.as_node().unwrap(), ctx)?;
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span(), ctx);
             };
 
             Ok(FormalSyntaxPatPart::Binding(cat))
@@ -476,7 +732,7 @@ fn elaborate_notation<'ctx>(
             let pat = elaborate_notation_pat(pat_list.as_node().unwrap(), ctx)?;
 
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat.span(), ctx);
             };
 
             let pat = NotationPattern::new(
@@ -485,13 +741,93 @@ fn elaborate_notation<'ctx>(
                 pat,
                 prec,
                 assoc,
-                NotationPatternSource::UserDeclared(rule_name_node.span()),
+                Provenance::declared(notation.span(), DeclKind::Notation),
             );
             Ok(ctx.arenas.notations.alloc(pat))
         }
     }
 }
 
+fn elaborate_prefer<'ctx>(
+    prefer: ParseTreeId<'ctx>,
+    scope: &Scope<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, (Ustr, Ustr)> {
+    // prefer_command ::= (prefer) kw"prefer" name kw"over" name
+
+    match_rule! { (ctx, prefer) =>
+        prefer ::= [prefer_kw, preferred_node, over_kw, other_node] => {
+            debug_assert!(prefer_kw.is_kw(*strings::PREFER));
+            debug_assert!(over_kw.is_kw(*strings::OVER));
+
+            // Fragments are parsed with the notation an alias stands for, so
+            // the preference is between those.
+            let mut names = [preferred_node, other_node].into_iter().map(|node| {
+                let name = elaborate_name(node.as_node().unwrap(), ctx)?;
+                match ctx.notations.get(&name) {
+                    Some(notation) => Ok(notation.primary().name()),
+                    None => Diagnostic::err_unknown_notation(name, node.span(), ctx),
+                }
+            });
+            let preferred = names.next().unwrap()?;
+            let other = names.next().unwrap()?;
+
+            if preferred == other {
+                return Diagnostic::err_cyclic_notation_preference(preferred, other, prefer.span(), Vec::new());
+            }
+            if let Some(chain) = scope.preferences().chain(other, preferred) {
+                return Diagnostic::err_cyclic_notation_preference(preferred, other, prefer.span(), chain);
+            }
+
+            Ok((preferred, other))
+        }
+    }
+}
+
+fn elaborate_alias<'ctx>(
+    alias: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, NotationPatternId<'ctx>> {
+    // alias_command ::= (alias) kw"alias" name kw"for" name "::=" notation_pat kw"end"
+
+    match_rule! { (ctx, alias) =>
+        alias ::= [alias_kw, alias_name_node, for_kw, target_node, bnf_replace, pat_list, end_kw] => {
+            debug_assert!(alias_kw.is_kw(*strings::ALIAS));
+            debug_assert!(for_kw.is_kw(*strings::FOR));
+            debug_assert!(bnf_replace.is_lit(*strings::BNF_REPLACE));
+            debug_assert!(end_kw.is_kw(*strings::END));
+
+            let alias_name = elaborate_name(alias_name_node.as_node().unwrap(), ctx)?;
+            let target_name = elaborate_name(target_node.as_node().unwrap(), ctx)?;
+            let pat = elaborate_notation_pat(pat_list.as_node().unwrap(), ctx)?;
+
+            let Some(&target) = ctx.notations.get(&target_name) else {
+                return Diagnostic::err_unknown_notation(target_name, target_node.span(), ctx);
+            };
+            // An alias of an alias is another spelling of the same notation.
+            let target = target.primary();
+
+            let pat = NotationPattern::new(
+                alias_name,
+                target.cat(),
+                pat,
+                target.prec(),
+                target.assoc(),
+                Provenance::declared(alias.span(), DeclKind::Alias),
+            )
+            .with_alias_of(target);
+
+            // Fragments parsed with the alias are built exactly as if they
+            // used the target so the holes, names and bindings must line up.
+            if !pat.slots().eq(target.slots()) {
+                return Diagnostic::err_alias_slot_mismatch(alias_name, target, pat_list.span());
+            }
+
+            Ok(ctx.arenas.notations.alloc(pat))
+        }
+    }
+}
+
 fn elaborate_notation_pat<'ctx>(
     mut pat_list: ParseTreeId<'ctx>,
     ctx: &Ctx<'ctx>,
@@ -515,20 +851,45 @@ fn elaborate_notation_pat<'ctx>(
     }
 
     // Extract the list of bindings
+    let mut errs = Vec::new();
+    let mut failed_parts = FxHashSet::default();
     let mut bindings = FxHashMap::default();
-    for &part in &parts {
-        let Some((name, cat)) = elaborate_notation_pat_binding(part, ctx)? else {
-            continue;
+    for (idx, &part) in parts.iter().enumerate() {
+        let (name, cat) = match elaborate_notation_pat_binding(part, ctx) {
+            Ok(Some(binding)) => binding,
+            Ok(None) => continue,
+            Err(binding_errs) => {
+                errs.extend(binding_errs);
+                failed_parts.insert(idx);
+                continue;
+            }
         };
 
         if bindings.insert(name, (bindings.len(), cat)).is_some() {
-            return Diagnostic::err_duplicate_pattern_binding(name, part.span());
+            errs.extend(
+                Diagnostic::err_duplicate_pattern_binding::<()>(name, part.span()).unwrap_err(),
+            );
         }
     }
 
+    // If a binding failed, references to it would be reported as unknown
+    // bindings on top of the real error so don't report those.
+    let bindings_complete = failed_parts.is_empty();
+
     let mut elaborated_parts = Vec::new();
-    for &part in &parts {
-        elaborated_parts.push(elaborate_notation_pat_part(part, &bindings, ctx)?);
+    for (idx, &part) in parts.iter().enumerate() {
+        if failed_parts.contains(&idx) {
+            continue;
+        }
+
+        match elaborate_notation_pat_part(part, &bindings, bindings_complete, ctx) {
+            Ok(part) => elaborated_parts.push(part),
+            Err(part_errs) => errs.extend(part_errs),
+        }
+    }
+
+    if !errs.is_empty() {
+        return Err(errs);
     }
 
     Ok(elaborated_parts)
@@ -556,7 +917,7 @@ fn elaborate_notation_pat_binding<'ctx>(
 
             let cat_name = elaborate_name(cat_name_node.as_node().unwrap(), ctx)?;
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span(), ctx);
             };
 
             Ok(Some((name, cat)))
@@ -579,6 +940,7 @@ fn elaborate_notation_pat_binding<'ctx>(
 fn elaborate_notation_pat_part<'ctx>(
     pat: ParseTreeId<'ctx>,
     bindings: &FxHashMap<Ustr, (usize, FormalSyntaxCatId<'ctx>)>,
+    bindings_complete: bool,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, NotationPatternPart<'ctx>> {
     // notation_pat ::= (notation_pat_lit)     str
@@ -609,10 +971,10 @@ fn elaborate_notation_pat_part<'ctx>(
             let cat_name = elaborate_name(cat_name_node.as_node().unwrap(), ctx)?;
 
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span(), ctx);
             };
 
-            let args = elaborate_maybe_notation_pat_args(maybe_args.as_node().unwrap(), bindings, ctx)?;
+            let args = elaborate_maybe_notation_pat_args(maybe_args.as_node().unwrap(), bindings, bindings_complete, ctx)?;
 
             let cat_part = NotationPatternPartCat::new(cat, args);
             Ok(NotationPatternPart::Cat(cat_part))
@@ -635,6 +997,7 @@ fn elaborate_notation_pat_part<'ctx>(
 fn elaborate_maybe_notation_pat_args<'ctx>(
     maybe_args: ParseTreeId<'ctx>,
     bindings: &FxHashMap<Ustr, (usize, FormalSyntaxCatId<'ctx>)>,
+    bindings_complete: bool,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, Vec<(usize, FormalSyntaxCatId<'ctx>)>> {
     // maybe_notation_pat_term_args ::= (maybe_notation_pat_term_args_none)
@@ -647,7 +1010,7 @@ fn elaborate_maybe_notation_pat_args<'ctx>(
         maybe_notation_pat_term_args_some ::= [l_paren, args, r_paren] => {
             debug_assert!(l_paren.is_lit(*strings::LEFT_PAREN));
             debug_assert!(r_paren.is_lit(*strings::RIGHT_PAREN));
-            elaborate_notation_pat_args(args.as_node().unwrap(), bindings, ctx)
+            elaborate_notation_pat_args(args.as_node().unwrap(), bindings, bindings_complete, ctx)
         }
     }
 }
@@ -655,12 +1018,14 @@ fn elaborate_maybe_notation_pat_args<'ctx>(
 fn elaborate_notation_pat_args<'ctx>(
     mut args: ParseTreeId<'ctx>,
     bindings: &FxHashMap<Ustr, (usize, FormalSyntaxCatId<'ctx>)>,
+    bindings_complete: bool,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, Vec<(usize, FormalSyntaxCatId<'ctx>)>> {
     // notation_pat_term_args ::= (notation_pat_term_args_one)  @name
     //                          | (notation_pat_term_args_many) @name notation_pat_term_args
 
     let mut elaborated_args = Vec::new();
+    let mut errs = Vec::new();
 
     loop {
         let (name_node, rest) = match_rule! { (ctx, args) =>
@@ -675,7 +1040,11 @@ fn elaborate_notation_pat_args<'ctx>(
         let name = elaborate_name(name_node, ctx)?;
         match bindings.get(&name) {
             Some(arg) => elaborated_args.push(*arg),
-            None => return Diagnostic::err_unknown_pattern_binding(name, name_node.span()),
+            // The binding may just have failed to elaborate.
+            None if !bindings_complete => {}
+            None => errs.extend(
+                Diagnostic::err_unknown_pattern_binding::<()>(name, name_node.span()).unwrap_err(),
+            ),
         }
 
         match rest {
@@ -684,12 +1053,16 @@ fn elaborate_notation_pat_args<'ctx>(
         }
     }
 
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+
     Ok(elaborated_args)
 }
 
 fn elaborate_grammar_category<'ctx>(
     cat: ParseTreeId<'ctx>,
-    ctx: &Ctx<'ctx>,
+    ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, CustomGrammarCatId<'ctx>> {
     // grammar_category_command ::= (grammar_category) kw"tactic_category" name
 
@@ -699,8 +1072,12 @@ fn elaborate_grammar_category<'ctx>(
             let cat_name_node = cat_name.as_node().unwrap();
             let cat_name = elaborate_name(cat_name_node, ctx)?;
 
-            if ctx.arenas.grammar_cats.get(cat_name).is_some() {
-                return Diagnostic::err_duplicate_grammar_cat();
+            if let Some(existing) = ctx.arenas.grammar_cats.get(cat_name) {
+                return Diagnostic::err_duplicate_grammar_cat(
+                    cat_name,
+                    cat_name_node.span(),
+                    existing.provenance(),
+                );
             }
 
             // Check if the category name conflicts with reserved Luau types
@@ -709,8 +1086,10 @@ fn elaborate_grammar_category<'ctx>(
             if RESERVED_LUAU_TYPES.contains(&lua_name.as_str()) {
                 return Diagnostic::err_reserved_grammar_cat_name(cat_name, cat_name_node.span());
             }
+            warn_if_keyword(cat_name, cat_name_node.span(), ctx);
 
-            let grammar_cat = CustomGrammarCat::new(cat_name);
+            let provenance = Provenance::declared(cat.span(), DeclKind::GrammarCategory);
+            let grammar_cat = CustomGrammarCat::new(cat_name, provenance);
             let grammar_cat = ctx.arenas.grammar_cats.alloc(cat_name, grammar_cat);
             Ok(grammar_cat)
         }
@@ -725,26 +1104,33 @@ fn elaborate_tactic_def<'ctx>(
     // tactic_command ::= (tactic) kw"tactic" name name prec_assoc "::=" tactic_pat kw"end"
 
     match_rule! { (ctx, tactic) =>
-        tactic ::= [tactic_kw, rule_name, cat, prec_assoc, bnf_replace, pat_list, end_kw] => {
+        tactic ::= [tactic_kw, rule_name_node, cat, prec_assoc, bnf_replace, pat_list, end_kw] => {
             debug_assert!(tactic_kw.is_kw(*strings::TACTIC));
             debug_assert!(bnf_replace.is_lit(*strings::BNF_REPLACE));
             debug_assert!(end_kw.is_kw(*strings::END));
 
-            let rule_name = elaborate_name(rule_name.as_node().unwrap(), ctx)?;
+            let rule_name = elaborate_name(rule_name_node.as_node().unwrap(), ctx)?;
             let cat_name = elaborate_name(cat.as_node().unwrap(), ctx)?;
             let (prec, assoc) = elaborate_prec_assoc(prec_assoc.as_node().unwrap(), ctx)?;
             let pat = elaborate_grammar_pat(pat_list.as_node().unwrap(), prec, assoc, ctx)?;
 
             let Some(cat) = ctx.arenas.grammar_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_grammar_cat(cat_name, cat.span());
+                return Diagnostic::err_unknown_grammar_cat(cat_name, cat.span(), ctx);
             };
 
-            if ctx.arenas.grammar_rules.get(rule_name).is_some() {
-                return Diagnostic::err_duplicate_grammar_rule();
+            if let Some(existing) = ctx.arenas.grammar_rules.get(rule_name) {
+                return Diagnostic::err_duplicate_grammar_rule(
+                    rule_name,
+                    rule_name_node.span(),
+                    existing.provenance(),
+                );
             }
 
+            check_tactic_pattern(rule_name, cat, &pat, tactic.span(), ctx)?;
+
             let scope = ctx.scopes.alloc(scope.clone());
-            let rule = CustomGrammarRule::new(rule_name, cat, pat, scope);
+            let provenance = Provenance::declared(tactic.span(), DeclKind::Tactic);
+            let rule = CustomGrammarRule::new(rule_name, cat, pat, scope, provenance);
             let rule_id = ctx.arenas.grammar_rules.alloc(rule_name, rule);
 
             Ok(rule_id)
@@ -752,6 +1138,78 @@ fn elaborate_tactic_def<'ctx>(
     }
 }
 
+/// Catch tactic patterns which can never be parsed or which will make uses of
+/// other tactics ambiguous before anyone tries to write a proof with them.
+/// Rules with no parts are fine; they are how lists and proofs end.
+fn check_tactic_pattern<'ctx>(
+    rule_name: Ustr,
+    cat: CustomGrammarCatId<'ctx>,
+    pat: &CustomGrammarPat<'ctx>,
+    span: Span,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, ()> {
+    let parts = grammar::custom_grammar_pat_to_parse_parts(pat, ctx);
+    if !parts.is_empty() && ctx.parse_state.pattern_can_be_empty(&parts) {
+        return Diagnostic::err_tactic_pattern_always_empty(rule_name, span);
+    }
+
+    for part in pat.parts() {
+        let mut core = part.part();
+        while let CustomGrammarPatPartCore::Many(repeated) = core {
+            core = repeated;
+        }
+        // Every category has a builtin rule for a single name, so only the
+        // rules someone declared count.
+        if let CustomGrammarPatPartCore::Frag(frag_cat) = core
+            && (ctx.parse_state.rules_for_cat(*frag_cat).iter())
+                .all(|rule| rule.source().provenance().span().is_none())
+        {
+            let provenance = frag_cat.source().provenance();
+            let warning =
+                Diagnostic::warn_frag_cat_without_rules(frag_cat.name(), span, provenance);
+            ctx.diags.add_diag(warning);
+        }
+    }
+
+    // A rule which starts with its own category extends the other rules rather
+    // than competing with them.
+    let parse_cat = ctx.parse_state.cat_for_tactic_cat(cat);
+    let is_left_recursive = |parts: &[RulePatternPart<'ctx>]| {
+        for part in parts {
+            match part {
+                RulePatternPart::Cat(id) if *id == parse_cat => return true,
+                RulePatternPart::Cat(id) if ctx.parse_state.can_be_empty(*id) => continue,
+                _ => return false,
+            }
+        }
+        false
+    };
+    if is_left_recursive(&parts) {
+        return Ok(());
+    }
+
+    let atoms = ctx.parse_state.pattern_initial_atoms(&parts);
+    let mut warnings = Vec::new();
+    for &existing in ctx.parse_state.rules_for_cat(parse_cat) {
+        let existing_parts = existing.pattern().parts();
+        if existing.pattern().precedence() != pat.precedence() || is_left_recursive(existing_parts)
+        {
+            continue;
+        }
+
+        if let ParseRuleSource::TacticRule(existing) = *existing.source()
+            && ctx.parse_state.pattern_initial_atoms(existing_parts) == atoms
+        {
+            warnings.push(Diagnostic::warn_ambiguous_tactic_rule(
+                rule_name, span, existing,
+            ));
+        }
+    }
+    ctx.diags.add_diags(warnings);
+
+    Ok(())
+}
+
 fn elaborate_attribute_def<'ctx>(
     attribute: ParseTreeId<'ctx>,
     scope: &Scope<'ctx>,
@@ -760,26 +1218,31 @@ fn elaborate_attribute_def<'ctx>(
     // tactic_command ::= (tactic) kw"tactic" name name prec_assoc "::=" tactic_pat kw"end"
 
     match_rule! { (ctx, attribute) =>
-        attribute ::= [attribute_kw, rule_name, cat, prec_assoc, bnf_replace, pat_list, end_kw] => {
+        attribute ::= [attribute_kw, rule_name_node, cat, prec_assoc, bnf_replace, pat_list, end_kw] => {
             debug_assert!(attribute_kw.is_kw(*strings::ATTRIBUTE));
             debug_assert!(bnf_replace.is_lit(*strings::BNF_REPLACE));
             debug_assert!(end_kw.is_kw(*strings::END));
 
-            let rule_name = elaborate_name(rule_name.as_node().unwrap(), ctx)?;
+            let rule_name = elaborate_name(rule_name_node.as_node().unwrap(), ctx)?;
             let cat_name = elaborate_name(cat.as_node().unwrap(), ctx)?;
             let (prec, assoc) = elaborate_prec_assoc(prec_assoc.as_node().unwrap(), ctx)?;
             let pat = elaborate_grammar_pat(pat_list.as_node().unwrap(), prec, assoc, ctx)?;
 
             let Some(cat) = ctx.arenas.grammar_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_grammar_cat(cat_name, cat.span());
+                return Diagnostic::err_unknown_grammar_cat(cat_name, cat.span(), ctx);
             };
 
-            if ctx.arenas.grammar_rules.get(rule_name).is_some() {
-                return Diagnostic::err_duplicate_grammar_rule();
+            if let Some(existing) = ctx.arenas.grammar_rules.get(rule_name) {
+                return Diagnostic::err_duplicate_grammar_rule(
+                    rule_name,
+                    rule_name_node.span(),
+                    existing.provenance(),
+                );
             }
 
             let scope = ctx.scopes.alloc(scope.clone());
-            let rule = CustomGrammarRule::new(rule_name, cat, pat, scope);
+            let provenance = Provenance::declared(attribute.span(), DeclKind::Attribute);
+            let rule = CustomGrammarRule::new(rule_name, cat, pat, scope, provenance);
             let rule_id = ctx.arenas.grammar_rules.alloc(rule_name, rule);
 
             Ok(rule_id)
@@ -797,6 +1260,7 @@ fn elaborate_grammar_pat<'ctx>(
     //               | (grammar_pat_many) grammar_pat_part grammar_pat
 
     let mut parts = Vec::new();
+    let mut errs = Vec::new();
 
     loop {
         match_rule! { (ctx, pat_list) =>
@@ -805,12 +1269,19 @@ fn elaborate_grammar_pat<'ctx>(
             },
             grammar_pat_many ::= [pat, rest] => {
                 let pat = pat.as_node().unwrap();
-                parts.push(elaborate_grammar_pat_part(pat, ctx)?);
+                match elaborate_grammar_pat_part(pat, ctx) {
+                    Ok(part) => parts.push(part),
+                    Err(part_errs) => errs.extend(part_errs),
+                }
                 pat_list = rest.as_node().unwrap();
             }
         }
     }
 
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+
     Ok(CustomGrammarPat::new(parts, prec, assoc))
 }
 
@@ -864,6 +1335,7 @@ fn elaborate_grammar_pat_part_core<'ctx>(
     //                         | (core_fragment)     "@" kw"fragment" "(" name ")"
     //                         | (core_any_fragment) "@" kw"any_fragment"
     //                         | (core_fact)         "@" kw"fact"
+    //                         | (core_many)         "@" kw"many" "(" grammar_pat_part_core ")"
 
     match_rule! { (ctx, core) =>
         core_lit ::= [lit] => {
@@ -887,7 +1359,7 @@ fn elaborate_grammar_pat_part_core<'ctx>(
             let cat_name = elaborate_name(cat_name_node.as_node().unwrap(), ctx)?;
 
             let Some(cat) = ctx.arenas.grammar_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_grammar_cat(cat_name, cat_name_node.span());
+                return Diagnostic::err_unknown_grammar_cat(cat_name, cat_name_node.span(), ctx);
             };
 
             Ok(CustomGrammarPatPartCore::Cat(cat))
@@ -901,7 +1373,7 @@ fn elaborate_grammar_pat_part_core<'ctx>(
             let cat_name = elaborate_name(cat_name_node.as_node().unwrap(), ctx)?;
 
             let Some(cat) = ctx.arenas.parse_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, cat_name_node.span(), ctx);
             };
 
             Ok(CustomGrammarPatPartCore::Frag(cat))
@@ -917,6 +1389,33 @@ fn elaborate_grammar_pat_part_core<'ctx>(
             debug_assert!(fact_kw.is_kw(*strings::FACT));
 
             Ok(CustomGrammarPatPartCore::Fact)
+        },
+        core_many ::= [at, many_kw, lparen, repeated, rparen] => {
+            debug_assert!(at.is_lit(*strings::AT));
+            debug_assert!(many_kw.is_kw(*strings::MANY));
+            debug_assert!(lparen.is_lit(*strings::LEFT_PAREN));
+            debug_assert!(rparen.is_lit(*strings::RIGHT_PAREN));
+
+            let repeated_span = repeated.span();
+            let repeated = elaborate_grammar_pat_part_core(repeated.as_node().unwrap(), ctx)?;
+            // Repeating a part which can match nothing would give endless
+            // parses of the same text.
+            let empty_cat = match &repeated {
+                CustomGrammarPatPartCore::Many(_) => {
+                    return Diagnostic::err_many_of_empty(repeated_span, None);
+                }
+                CustomGrammarPatPartCore::Cat(tactic_cat) => {
+                    Some(ctx.parse_state.cat_for_tactic_cat(*tactic_cat))
+                }
+                CustomGrammarPatPartCore::Frag(cat) => Some(*cat),
+                _ => None,
+            };
+            if let Some(cat) = empty_cat
+                && ctx.parse_state.can_be_empty(cat)
+            {
+                return Diagnostic::err_many_of_empty(repeated_span, Some(cat.name()));
+            }
+            Ok(CustomGrammarPatPartCore::Many(Box::new(repeated)))
         }
     }
 }
@@ -925,88 +1424,147 @@ fn elaborate_definition<'ctx>(
     definition: ParseTreeId<'ctx>,
     cmd: CommandId<'ctx>,
     scope: &Scope<'ctx>,
-    ctx: &Ctx<'ctx>,
+    ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, Scope<'ctx>> {
-    // definition_command ::= (definition) kw"definition" notation_binding ":=" fragment kw"end"
+    // definition_command ::= (definition)   kw"definition" notation_binding ":=" fragment kw"end"
+    //                      | (abbreviation) kw"abbreviation" notation_binding ":=" fragment kw"end"
 
-    match_rule! { (ctx, definition) =>
+    let (is_abbreviation, notation_binding, fragment_node) = match_rule! { (ctx, definition) =>
         definition ::= [definition_kw, notation_binding, assign, fragment_node, end_kw] => {
             debug_assert!(definition_kw.is_kw(*strings::DEFINITION));
             debug_assert!(assign.is_lit(*strings::ASSIGN));
             debug_assert!(end_kw.is_kw(*strings::END));
+            (false, notation_binding, fragment_node)
+        },
+        abbreviation ::= [abbreviation_kw, notation_binding, assign, fragment_node, end_kw] => {
+            debug_assert!(abbreviation_kw.is_kw(*strings::ABBREVIATION));
+            debug_assert!(assign.is_lit(*strings::ASSIGN));
+            debug_assert!(end_kw.is_kw(*strings::END));
+            (true, notation_binding, fragment_node)
+        }
+    };
 
-            let binding_possibilities = elaborate_notation_binding(notation_binding.as_node().unwrap(), None, None, ctx)?;
-            let possible_frag_cats = elaborate_any_fragment(fragment_node.as_node().unwrap());
+    let binding_possibilities =
+        elaborate_notation_binding(notation_binding.as_node().unwrap(), None, None, ctx)?;
+    let possible_frag_cats = elaborate_any_fragment(fragment_node.as_node().unwrap());
 
-            let mut best_priority = None;
-            let mut solutions = Vec::new();
-            let mut parse_errors = Vec::new();
+    let mut best_priority = None;
+    let mut solutions = Vec::new();
+    let mut parse_errors = Vec::new();
 
-            for possibility in binding_possibilities {
-                let Some(frag) = possible_frag_cats.get(&possibility.binding.pattern().cat()) else {
-                    continue;
-                };
+    for possibility in binding_possibilities {
+        let Some(frag) = possible_frag_cats.get(&possibility.binding.pattern().cat()) else {
+            continue;
+        };
 
-                let mut scope = scope.clone();
-                for (i, hole_binding) in possibility.holes.iter().enumerate() {
-                    let hole_cat = hole_binding.pattern().cat();
-                    let entry = ScopeEntry::new_hole(hole_cat, i, DefinitionSource::DefinitionHole);
-                    scope = scope.child_with(*hole_binding, entry);
-                }
+        let mut scope = scope.clone();
+        for (i, hole_binding) in possibility.holes.iter().enumerate() {
+            let hole_cat = hole_binding.pattern().cat();
+            let entry = ScopeEntry::new_hole(hole_cat, i, DefinitionSource::DefinitionHole);
+            scope = scope.child_with(*hole_binding, entry);
+        }
 
-                // Try parsing the fragment given these possibilities.
-                let parse = match parse_fragment(UnresolvedFrag(*frag), &scope, ctx)? {
-                    Ok(parse) => parse,
-                    Err(err) => {
-                        parse_errors.push((possibility.binding, err));
-                        continue
-                    },
-                };
+        // Try parsing the fragment given these possibilities.
+        let parse = match parse_fragment_with_warnings(UnresolvedFrag(*frag), &scope, ctx)? {
+            Ok(parse) => parse,
+            Err(err) => {
+                parse_errors.push((possibility.binding, err));
+                continue;
+            }
+        };
 
-                // prefer bindings with more names as they can't be disambiguated as easily.
-                let this_name_count = possibility.binding.name_instantiations().len();
-                if best_priority.is_none_or(|best_priority| this_name_count > best_priority)  {
-                    best_priority = Some(this_name_count);
-                    solutions.clear();
-                    parse_errors.clear();
-                }
+        // prefer bindings with more names as they can't be disambiguated as easily.
+        let this_name_count = possibility.binding.name_instantiations().len();
+        if best_priority.is_none_or(|best_priority| this_name_count > best_priority) {
+            best_priority = Some(this_name_count);
+            solutions.clear();
+            parse_errors.clear();
+        }
 
-                if Some(this_name_count) != best_priority {
-                    continue;
-                }
+        if Some(this_name_count) != best_priority {
+            continue;
+        }
 
-                solutions.push((possibility.binding, parse));
-            }
+        solutions.push((possibility.binding, parse));
+    }
 
-            match solutions.as_slice() {
-                [] => {
-                    let mut diags = Vec::new();
-                    for (binding, err) in parse_errors {
-                        let diag = Diagnostic::err_frag_parse_failure(fragment_node.span(), err)
-                            .with_info(
-                                &format!("assuming the intended notation was `{}`", binding.print()),
-                                vec![DiagnosticSpan::new_info("", notation_binding.span())]
-                            );
-                        diags.push(diag);
-                    }
-                    Err(diags)
-                }
-                [(binding, frag)] => {
-                    let entry = ScopeEntry::new(*frag, DefinitionSource::DefinitionCmd(cmd));
-                    Ok(scope.child_with(*binding, entry))
-                },
-                [..] => Diagnostic::err_multiple_notations(notation_binding.span(), solutions.iter().map(|s| s.0).collect()),
+    match solutions.as_slice() {
+        [] => {
+            let mut diags = Vec::new();
+            for (binding, err) in parse_errors {
+                let diag = Diagnostic::err_frag_parse_failure(fragment_node.span(), err).with_info(
+                    &format!("assuming the intended notation was `{}`", binding.print()),
+                    vec![DiagnosticSpan::new_info("", notation_binding.span())],
+                );
+                diags.push(diag);
             }
+            Err(diags)
         }
+        [(binding, (frag, warnings))] => {
+            ctx.diags.add_diags(warnings.clone());
+            if let Some(warning) = scope.check_shadowing(*binding, notation_binding.span()) {
+                ctx.diags.add_diag(warning);
+            }
+
+            let entry = if is_abbreviation {
+                let replacement =
+                    elaborate_abbreviation(*binding, *frag, notation_binding.span(), ctx)?;
+                ScopeEntry::new(replacement, DefinitionSource::AbbreviationCmd(cmd))
+            } else {
+                ScopeEntry::new(*frag, DefinitionSource::DefinitionCmd(cmd))
+            };
+            let entry = entry.with_origin(notation_binding.span());
+
+            // The replacement was parsed, and so fully expanded, in the scope
+            // from before this binding was added. A definition therefore can't
+            // refer back to itself, even through other definitions, and later
+            // redefinitions don't change what earlier ones expand to.
+            Ok(scope.child_with(*binding, entry))
+        }
+        [..] => Diagnostic::err_multiple_notations(
+            notation_binding.span(),
+            solutions.iter().map(|s| s.0).collect(),
+        ),
     }
 }
 
+/// Register an abbreviation for `definition` and return the fragment that the
+/// notation should stand for, which refers to the abbreviation rather than
+/// its definition. Like a definition, the abbreviation is only known through
+/// the scope the notation is bound in, so scopes can each have their own
+/// abbreviation with the same name.
+fn elaborate_abbreviation<'ctx>(
+    binding: NotationBindingId<'ctx>,
+    definition: PresFrag<'ctx>,
+    span: Span,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, PresFrag<'ctx>> {
+    let pattern = binding.pattern();
+    let holes = pattern.signature().holes();
+    if holes.iter().any(|hole| !hole.args().is_empty()) {
+        return Diagnostic::err_abbreviation_with_binders(span);
+    }
+
+    let name = match binding.name_instantiations() {
+        [name, ..] => *name,
+        [] => binding.pattern().name(),
+    };
+    let abbreviation = Abbreviation::new(name, definition.frag().cat(), definition);
+    let abbreviation = ctx.arenas.abbreviations.alloc(abbreviation);
+
+    let args = holes
+        .iter()
+        .enumerate()
+        .map(|(i, hole)| hole_frag(i, hole.cat(), Vec::new(), ctx))
+        .collect();
+    Ok(abbreviation_frag(abbreviation, args, ctx))
+}
+
 fn elaborate_any_fragment<'ctx>(
     any_frag: ParseTreeId<'ctx>,
 ) -> FxHashMap<FormalSyntaxCatId<'ctx>, ParseTreeId<'ctx>> {
     any_frag
         .possibilities()
-        .iter()
         .map(|possibility| {
             let frag = possibility.children()[0];
             let frag = frag.as_node().unwrap();
@@ -1080,8 +1638,9 @@ fn elaborate_notation_binding<'ctx>(
             .multi_cartesian_product()
             .collect_vec();
 
+        ctx.used_notations.borrow_mut().insert(pattern.primary());
         for holes in product {
-            let binding = NotationBinding::new(pattern, name_instantiations.clone());
+            let binding = NotationBinding::new(pattern.primary(), name_instantiations.clone());
             let binding = ctx.arenas.notation_bindings.intern(binding);
             let resolution = BindingResolution { binding, holes };
             resolved.push(resolution);
@@ -1114,7 +1673,7 @@ fn elaborate_notation_binding<'ctx>(
             continue;
         }
 
-        children_to_binding(&mut resolved, possibility, notation, ctx)?;
+        children_to_binding(&mut resolved, &possibility, notation, ctx)?;
     }
 
     Ok(resolved)
@@ -1124,15 +1683,19 @@ fn parse_hypotheses_and_conclusion<'ctx>(
     un_hypotheses: Vec<UnresolvedFact<'ctx>>,
     un_conclusion: UnresolvedFrag<'ctx>,
     scope: &Scope<'ctx>,
-    ctx: &Ctx<'ctx>,
+    ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, (Vec<PresFact<'ctx>>, PresFrag<'ctx>)> {
     let mut errs = Vec::new();
+    let mut warnings = Vec::new();
 
     let mut hypotheses = Vec::new();
     for un_hypothesis in un_hypotheses {
         let assumption = match un_hypothesis.assumption {
-            Some(assumption) => match parse_fragment(assumption, scope, ctx)? {
-                Ok(assumption) => Some(assumption),
+            Some(assumption) => match parse_fragment_with_warnings(assumption, scope, ctx)? {
+                Ok((assumption, frag_warnings)) => {
+                    warnings.extend(frag_warnings);
+                    Some(assumption)
+                }
                 Err(err) => {
                     errs.push(Diagnostic::err_frag_parse_failure(assumption.0.span(), err));
                     continue;
@@ -1140,8 +1703,11 @@ fn parse_hypotheses_and_conclusion<'ctx>(
             },
             None => None,
         };
-        let conclusion = match parse_fragment(un_hypothesis.conclusion, scope, ctx)? {
-            Ok(conclusion) => conclusion,
+        let conclusion = match parse_fragment_with_warnings(un_hypothesis.conclusion, scope, ctx)? {
+            Ok((conclusion, frag_warnings)) => {
+                warnings.extend(frag_warnings);
+                conclusion
+            }
             Err(err) => {
                 errs.push(Diagnostic::err_frag_parse_failure(
                     un_hypothesis.conclusion.0.span(),
@@ -1153,8 +1719,11 @@ fn parse_hypotheses_and_conclusion<'ctx>(
         hypotheses.push(PresFact::new(assumption, conclusion));
     }
 
-    let conclusion = match parse_fragment(un_conclusion, scope, ctx)? {
-        Ok(conclusion) => Some(conclusion),
+    let conclusion = match parse_fragment_with_warnings(un_conclusion, scope, ctx)? {
+        Ok((conclusion, frag_warnings)) => {
+            warnings.extend(frag_warnings);
+            Some(conclusion)
+        }
         Err(err) => {
             errs.push(Diagnostic::err_frag_parse_failure(
                 un_conclusion.0.span(),
@@ -1164,6 +1733,8 @@ fn parse_hypotheses_and_conclusion<'ctx>(
         }
     };
 
+    ctx.diags.add_diags(warnings);
+
     if errs.is_empty() {
         Ok((hypotheses, conclusion.unwrap()))
     } else {
@@ -1173,31 +1744,46 @@ fn parse_hypotheses_and_conclusion<'ctx>(
 
 fn elaborate_axiom<'ctx>(
     axiom: ParseTreeId<'ctx>,
+    cmd: CommandId<'ctx>,
     scope: &Scope<'ctx>,
     ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, (TheoremId<'ctx>, UnresolvedProof<'ctx>)> {
-    // axiom_command ::= (axiom) kw"axiom" name templates ":" hypotheses "|-" sentence kw"end"
+    // axiom_command ::= (axiom) axiom_kw name maybe_deprecated templates ":" hypotheses "|-" sentence kw"end"
 
     match_rule! { (ctx, axiom) =>
-        axiom ::= [axiom_kw, name_node, templates, colon, hypotheses, turnstile, conclusion, end_kw] => {
-            debug_assert!(axiom_kw.is_kw(*strings::AXIOM));
+        axiom ::= [_axiom_kw, name_node, deprecated, templates, colon, hypotheses, turnstile, conclusion, end_kw] => {
             debug_assert!(colon.is_lit(*strings::COLON));
             debug_assert!(turnstile.is_lit(*strings::TURNSTILE));
             debug_assert!(end_kw.is_kw(*strings::END));
 
             let name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
-            let templates = elaborate_templates(templates.as_node().unwrap(), ctx)?;
+            warn_if_keyword(name, name_node.span(), ctx);
+            let deprecated_attr = ctx.command_attributes.find(cmd, BuiltinAttributeKind::Deprecated);
+            let deprecated = elaborate_maybe_deprecated(deprecated.as_node().unwrap(), ctx)?
+                || deprecated_attr.is_some();
+            let deprecation_note = match deprecated_attr.map(|attr| attr.value) {
+                Some(AttributeValue::Str(note)) => Some(note),
+                _ => None,
+            };
+            let templates = elaborate_templates(templates.as_node().unwrap(), ctx);
+            let hypotheses = elaborate_hypotheses(hypotheses.as_node().unwrap(), ctx);
+            let ((templates, defaults), hypotheses) = both_or_all_errors(templates, hypotheses)?;
 
             let my_scope = add_templates_to_scope(&templates, scope, ctx);
+            let templates = elaborate_template_defaults(templates, defaults, &my_scope, ctx);
 
-            let hypotheses = elaborate_hypotheses(hypotheses.as_node().unwrap(), ctx)?;
             let conclusion = UnresolvedFrag(conclusion.as_node().unwrap());
+            let hypothesis_spans = hypotheses.iter().map(UnresolvedFact::span).collect();
 
-            let (hypotheses, conclusion) = parse_hypotheses_and_conclusion(hypotheses, conclusion, &my_scope, ctx)?;
+            let statement = parse_hypotheses_and_conclusion(hypotheses, conclusion, &my_scope, ctx);
+            let (templates, (hypotheses, conclusion)) = both_or_all_errors(templates, statement)?;
 
             let scope_id = ctx.scopes.alloc(my_scope);
 
-            let theorem_stmt = TheoremStatement::new(name, templates, hypotheses, conclusion, scope_id);
+            let theorem_stmt = TheoremStatement::new(name, name_node.span(), templates, hypotheses, conclusion, scope_id, TheoremKind::Axiom)
+                .with_hypothesis_spans(hypothesis_spans)
+                .with_deprecated(deprecated)
+                .with_deprecation_note(deprecation_note);
             let theorem_stmt = ctx.arenas.theorem_stmts.alloc(name, theorem_stmt);
 
             Ok((theorem_stmt, UnresolvedProof::Axiom))
@@ -1205,36 +1791,56 @@ fn elaborate_axiom<'ctx>(
     }
 }
 
+fn elaborate_maybe_deprecated<'ctx>(
+    maybe_deprecated: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, bool> {
+    // maybe_deprecated ::= (deprecated_none)
+    //                    | (deprecated_some) kw"deprecated"
+
+    match_rule! { (ctx, maybe_deprecated) =>
+        deprecated_none ::= [] => Ok(false),
+        deprecated_some ::= [_deprecated_kw] => Ok(true)
+    }
+}
+
 fn elaborate_theorem<'ctx>(
     theorem: ParseTreeId<'ctx>,
     scope: &Scope<'ctx>,
     ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, (TheoremId<'ctx>, UnresolvedProof<'ctx>)> {
-    // theorem_command ::= (theorem) kw"theorem" name templates ":" hypotheses "|-" sentence kw"proof" tactic kw"qed"
+    // theorem_command ::= (theorem) theorem_kw name templates ":" hypotheses "|-" sentence kw"proof" proof_body kw"qed"
 
     match_rule! { (ctx, theorem) =>
-        theorem ::= [_theorem_kw, name_node, templates, colon, hypotheses, turnstile, conclusion, proof_kw, tactic, qed_kw] => {
+        theorem ::= [theorem_kw, name_node, templates, colon, hypotheses, turnstile, conclusion, proof_kw, proof_body, qed_kw] => {
             debug_assert!(colon.is_lit(*strings::COLON));
             debug_assert!(turnstile.is_lit(*strings::TURNSTILE));
             debug_assert!(proof_kw.is_kw(*strings::PROOF));
             debug_assert!(qed_kw.is_kw(*strings::QED));
 
+            let kind = elaborate_theorem_kw(theorem_kw.as_node().unwrap(), ctx)?;
             let name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
-            let templates = elaborate_templates(templates.as_node().unwrap(), ctx)?;
+            warn_if_keyword(name, name_node.span(), ctx);
+            let templates = elaborate_templates(templates.as_node().unwrap(), ctx);
+            let hypotheses = elaborate_hypotheses(hypotheses.as_node().unwrap(), ctx);
+            let ((templates, defaults), hypotheses) = both_or_all_errors(templates, hypotheses)?;
 
             let my_scope = add_templates_to_scope(&templates, scope, ctx);
+            let templates = elaborate_template_defaults(templates, defaults, &my_scope, ctx);
 
-            let hypotheses = elaborate_hypotheses(hypotheses.as_node().unwrap(), ctx)?;
             let conclusion = UnresolvedFrag(conclusion.as_node().unwrap());
+            let hypothesis_spans = hypotheses.iter().map(UnresolvedFact::span).collect();
 
-            let (hypotheses, conclusion) = parse_hypotheses_and_conclusion(hypotheses, conclusion, &my_scope, ctx)?;
+            let statement = parse_hypotheses_and_conclusion(hypotheses, conclusion, &my_scope, ctx);
+            let (templates, (hypotheses, conclusion)) = both_or_all_errors(templates, statement)?;
 
             let scope_id = ctx.scopes.alloc(my_scope);
 
-            let theorem_stmt = TheoremStatement::new(name, templates, hypotheses, conclusion, scope_id);
+            let theorem_stmt = TheoremStatement::new(name, name_node.span(), templates, hypotheses, conclusion, scope_id, kind)
+                .with_hypothesis_spans(hypothesis_spans);
             let theorem_stmt = ctx.arenas.theorem_stmts.alloc(name, theorem_stmt);
 
-            let proof = elaborate_custom_grammar(tactic.as_node().unwrap(), ctx)?;
+            let proof = elaborate_proof_body(proof_body.as_node().unwrap(), ctx)?;
             let proof = UnresolvedProof::Theorem(proof);
 
             Ok((theorem_stmt, proof))
@@ -1242,44 +1848,130 @@ fn elaborate_theorem<'ctx>(
     }
 }
 
+fn elaborate_proof_body<'ctx>(
+    proof_body: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, CustomGrammarInst<'ctx>> {
+    // proof_body ::= (proof_body_default) tactic
+    //
+    // and for each tactic category
+    //
+    // proof_body ::= kw"using" kw"<tactic_cat>" <tactic_cat>
+
+    let children = expect_unambiguous(proof_body)?;
+    if let ParseRuleSource::ProofCategory(_) = children.rule().source() {
+        let [using_kw, _cat_kw, tactic] = children.children() else {
+            failed_to_match_builtin!(children.rule(), ctx);
+        };
+        debug_assert!(using_kw.is_kw(*strings::USING));
+        return elaborate_custom_grammar(tactic.as_node().unwrap(), ctx);
+    }
+
+    match_rule! { (ctx, proof_body) =>
+        proof_body_default ::= [tactic] => elaborate_custom_grammar(tactic.as_node().unwrap(), ctx)
+    }
+}
+
+fn elaborate_theorem_kw<'ctx>(
+    theorem_kw: ParseTreeId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, TheoremKind> {
+    // theorem_kw ::= (theorem_kw_theorem)     kw"theorem"
+    //              | (theorem_kw_lemma)       kw"lemma"
+    //              | (theorem_kw_proposition) kw"proposition"
+    //              | (theorem_kw_corollary)   kw"corollary"
+
+    match_rule! { (ctx, theorem_kw) =>
+        theorem_kw_theorem ::= [_kw] => Ok(TheoremKind::Theorem),
+        theorem_kw_lemma ::= [_kw] => Ok(TheoremKind::Lemma),
+        theorem_kw_proposition ::= [_kw] => Ok(TheoremKind::Proposition),
+        theorem_kw_corollary ::= [_kw] => Ok(TheoremKind::Corollary)
+    }
+}
+
+/// Names may be keywords since keywords are only matched where a command
+/// expects them, but the commands are then harder to read.
+fn warn_if_keyword<'ctx>(name: Ustr, span: Span, ctx: &mut Ctx<'ctx>) {
+    if strings::is_reserved_keyword(&name) {
+        ctx.diags
+            .add_diag(Diagnostic::warn_name_is_keyword(name, span));
+    }
+}
+
+/// Combine two independent results, reporting the errors from both if either
+/// failed.
+fn both_or_all_errors<'ctx, A, B>(
+    a: WResult<'ctx, A>,
+    b: WResult<'ctx, B>,
+) -> WResult<'ctx, (A, B)> {
+    match (a, b) {
+        (Ok(a), Ok(b)) => Ok((a, b)),
+        (a, b) => Err(a.err().into_iter().chain(b.err()).flatten().collect()),
+    }
+}
+
+type TemplatesWithDefaults<'ctx> = (Vec<Template<'ctx>>, Vec<(usize, ParseTreeId<'ctx>)>);
+
+/// Elaborate the templates along with the defaults that some of them have,
+/// keyed by the template's index. The defaults are parsed once the templates
+/// are in scope by [`elaborate_template_defaults`].
 fn elaborate_templates<'ctx>(
     mut templates: ParseTreeId<'ctx>,
     ctx: &Ctx<'ctx>,
-) -> WResult<'ctx, Vec<Template<'ctx>>> {
+) -> WResult<'ctx, TemplatesWithDefaults<'ctx>> {
     // templates ::= (template_none)
     //             | (template_many) template templates
 
     let mut seen_templates = FxHashSet::default();
     let mut templates_list = Vec::new();
+    let mut defaults = Vec::new();
+    let mut errs = Vec::new();
 
     loop {
         match_rule! { (ctx, templates) =>
             template_none ::= [] => {
-                return Ok(templates_list);
+                break;
             },
             template_many ::= [template, rest] => {
                 let template = template.as_node().unwrap();
+                templates = rest.as_node().unwrap();
 
-                for template in elaborate_template(template, ctx)? {
+                let (elaborated, default) = match elaborate_template(template, ctx) {
+                    Ok(elaborated) => elaborated,
+                    Err(template_errs) => {
+                        errs.extend(template_errs);
+                        continue;
+                    }
+                };
+
+                for template in elaborated {
                     if seen_templates.contains(&template.binding()) {
                         todo!("Error: duplicate template binding.");
                     }
 
                     seen_templates.insert(template.binding());
+                    if let Some(default) = default {
+                        defaults.push((templates_list.len(), default));
+                    }
                     templates_list.push(template);
                 }
-
-                templates = rest.as_node().unwrap();
             }
         }
     }
+
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+
+    Ok((templates_list, defaults))
 }
 
 fn elaborate_template<'ctx>(
     template: ParseTreeId<'ctx>,
     ctx: &Ctx<'ctx>,
-) -> WResult<'ctx, Vec<Template<'ctx>>> {
-    // template ::= (template) "[" template_bindings ":" name "]"
+) -> WResult<'ctx, (Vec<Template<'ctx>>, Option<ParseTreeId<'ctx>>)> {
+    // template ::= (template)         "[" template_bindings ":" template_cat "]"
+    //            | (template_default) "[" template_bindings ":=" any_fragment ":" template_cat "]"
 
     match_rule! { (ctx, template) =>
         template ::= [l_brack, names, colon, cat_node, r_brack] => {
@@ -1295,9 +1987,84 @@ fn elaborate_template<'ctx>(
 
             let bindings = elaborate_template_bindings(names.as_node().unwrap(), cat, &holes, ctx)?;
 
-            Ok(bindings)
+            Ok((bindings, None))
+        },
+        template_default ::= [l_brack, names, assign, default, colon, cat_node, r_brack] => {
+            debug_assert!(l_brack.is_lit(*strings::LEFT_BRACKET));
+            debug_assert!(assign.is_lit(*strings::ASSIGN));
+            debug_assert!(colon.is_lit(*strings::COLON));
+            debug_assert!(r_brack.is_lit(*strings::RIGHT_BRACKET));
+
+            let (cat, holes) = elaborate_template_cat(cat_node.as_node().unwrap(), ctx)?;
+            if !holes.is_empty() {
+                return Diagnostic::err_template_default_with_holes(default.span());
+            }
+
+            let bindings = elaborate_template_bindings(names.as_node().unwrap(), cat, &holes, ctx)?;
+
+            Ok((bindings, Some(default.as_node().unwrap())))
+        }
+    }
+}
+
+/// Parse the defaults of the templates in the theorem's scope. A default can
+/// only refer to the templates before it since those are filled in first.
+fn elaborate_template_defaults<'ctx>(
+    mut templates: Vec<Template<'ctx>>,
+    defaults: Vec<(usize, ParseTreeId<'ctx>)>,
+    scope: &Scope<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, Vec<Template<'ctx>>> {
+    let mut errs = Vec::new();
+
+    for (idx, default) in defaults {
+        let cat = templates[idx].cat();
+        let possible_frag_cats = elaborate_any_fragment(default);
+        let Some(&frag) = possible_frag_cats.get(&cat) else {
+            let found = possible_frag_cats.keys().map(|cat| cat.name()).collect();
+            errs.extend(
+                Diagnostic::err_template_default_wrong_cat::<()>(cat.name(), found, default.span())
+                    .unwrap_err(),
+            );
+            continue;
+        };
+
+        let frag = match parse_fragment_with_warnings(UnresolvedFrag(frag), scope, ctx)? {
+            Ok((frag, warnings)) => {
+                ctx.diags.add_diags(warnings);
+                frag
+            }
+            Err(err) => {
+                errs.push(Diagnostic::err_frag_parse_failure(default.span(), err));
+                continue;
+            }
+        };
+
+        if refers_to_template_from(frag.frag(), idx) {
+            errs.extend(
+                Diagnostic::err_template_default_refers_forward::<()>(default.span()).unwrap_err(),
+            );
+            continue;
         }
+
+        templates[idx] = templates[idx].clone().with_default(frag);
+    }
+
+    if !errs.is_empty() {
+        return Err(errs);
     }
+
+    Ok(templates)
+}
+
+/// Whether the fragment refers to the template at `idx` or any after it.
+fn refers_to_template_from(frag: FragmentId<'_>, idx: usize) -> bool {
+    frag.has_template()
+        && (matches!(frag.head(), FragHead::TemplateRef(i) if i >= idx)
+            || frag
+                .children()
+                .iter()
+                .any(|&child| refers_to_template_from(child, idx)))
 }
 
 fn elaborate_template_cat<'ctx>(
@@ -1308,7 +2075,7 @@ fn elaborate_template_cat<'ctx>(
         template_cat_no_holes ::= [name_node] => {
             let cat_name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span(), ctx);
             };
             Ok((cat, Vec::new()))
         },
@@ -1318,7 +2085,7 @@ fn elaborate_template_cat<'ctx>(
 
             let cat_name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
             let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span());
+                return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span(), ctx);
             };
 
             let hole_cats = elaborate_cat_list(cat_list.as_node().unwrap(), ctx)?;
@@ -1339,7 +2106,7 @@ fn elaborate_cat_list<'ctx>(
             cat_list_one ::= [name_node] => {
                 let cat_name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
                 let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                    return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span());
+                    return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span(), ctx);
                 };
                 result.push(cat);
                 break;
@@ -1348,7 +2115,7 @@ fn elaborate_cat_list<'ctx>(
                 debug_assert!(comma.is_lit(*strings::COMMA));
                 let cat_name = elaborate_name(name_node.as_node().unwrap(), ctx)?;
                 let Some(cat) = ctx.arenas.formal_cats.get(cat_name) else {
-                    return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span());
+                    return Diagnostic::err_unknown_formal_syntax_cat(cat_name, name_node.span(), ctx);
                 };
                 result.push(cat);
                 list = rest.as_node().unwrap();
@@ -1393,7 +2160,7 @@ fn elaborate_template_bindings<'ctx>(
                 }
 
                 let resolution = possibilities.swap_remove(0);
-                let template = Template::new(cat, resolution.binding, resolution.holes);
+                let template = Template::new(cat, resolution.binding, resolution.holes, binding.span());
 
                 binding_list.push(template);
                 bindings = rest.as_node().unwrap();
@@ -1410,6 +2177,7 @@ fn elaborate_hypotheses<'ctx>(
     //              | (hypotheses_many) hypothesis hypotheses
 
     let mut hypotheses_list = Vec::new();
+    let mut errs = Vec::new();
     let mut next_hypotheses = Some(hypotheses);
 
     while let Some(hypotheses) = next_hypotheses {
@@ -1421,12 +2189,19 @@ fn elaborate_hypotheses<'ctx>(
                 let hypothesis = hypothesis.as_node().unwrap();
                 let rest = rest.as_node().unwrap();
 
-                hypotheses_list.push(elaborate_hypothesis(hypothesis, ctx)?);
+                match elaborate_hypothesis(hypothesis, ctx) {
+                    Ok(hypothesis) => hypotheses_list.push(hypothesis),
+                    Err(hypothesis_errs) => errs.extend(hypothesis_errs),
+                }
                 next_hypotheses = Some(rest);
             }
         }
     }
 
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+
     Ok(hypotheses_list)
 }
 
@@ -1493,38 +2268,7 @@ fn elaborate_custom_grammar<'ctx>(
         .iter()
         .zip(children.children().iter())
     {
-        let g_child = match part.part() {
-            CustomGrammarPatPartCore::Kw(str) => {
-                let spanned_str = SpannedStr::new(*str, child.span());
-                CustomGrammarInstPart::Kw(spanned_str)
-            }
-            CustomGrammarPatPartCore::Lit(str) => {
-                let spanned_str = SpannedStr::new(*str, child.span());
-                CustomGrammarInstPart::Lit(spanned_str)
-            }
-            CustomGrammarPatPartCore::Name => {
-                let name = elaborate_name(child.as_node().unwrap(), ctx)?;
-                let spanned_str = SpannedStr::new(name, child.span());
-                CustomGrammarInstPart::Name(spanned_str)
-            }
-            CustomGrammarPatPartCore::Cat(_) => {
-                let inst = elaborate_custom_grammar(child.as_node().unwrap(), ctx)?;
-                CustomGrammarInstPart::SubInst(inst)
-            }
-            CustomGrammarPatPartCore::Frag(_) => {
-                let frag = UnresolvedFrag(child.as_node().unwrap());
-                CustomGrammarInstPart::Frag(frag)
-            }
-            CustomGrammarPatPartCore::AnyFrag => {
-                let frag = UnresolvedAnyFrag(child.as_node().unwrap());
-                CustomGrammarInstPart::AnyFrag(frag)
-            }
-            CustomGrammarPatPartCore::Fact => {
-                let fact = elaborate_fact(child.as_node().unwrap(), ctx)?;
-                CustomGrammarInstPart::Fact(fact)
-            }
-        };
-        grammar_children.push(g_child);
+        grammar_children.push(elaborate_custom_grammar_part(part.part(), child, ctx)?);
     }
 
     Ok(CustomGrammarInst::new(
@@ -1534,6 +2278,56 @@ fn elaborate_custom_grammar<'ctx>(
     ))
 }
 
+fn elaborate_custom_grammar_part<'ctx>(
+    part: &CustomGrammarPatPartCore<'ctx>,
+    child: &ParseTreePart<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, CustomGrammarInstPart<'ctx>> {
+    let g_child = match part {
+        CustomGrammarPatPartCore::Kw(str) => {
+            let spanned_str = SpannedStr::new(*str, child.span());
+            CustomGrammarInstPart::Kw(spanned_str)
+        }
+        CustomGrammarPatPartCore::Lit(str) => {
+            let spanned_str = SpannedStr::new(*str, child.span());
+            CustomGrammarInstPart::Lit(spanned_str)
+        }
+        CustomGrammarPatPartCore::Name => {
+            let name = elaborate_name(child.as_node().unwrap(), ctx)?;
+            let spanned_str = SpannedStr::new(name, child.span());
+            CustomGrammarInstPart::Name(spanned_str)
+        }
+        CustomGrammarPatPartCore::Cat(_) => {
+            let inst = elaborate_custom_grammar(child.as_node().unwrap(), ctx)?;
+            CustomGrammarInstPart::SubInst(inst)
+        }
+        CustomGrammarPatPartCore::Frag(_) => {
+            let frag = UnresolvedFrag(child.as_node().unwrap());
+            CustomGrammarInstPart::Frag(frag)
+        }
+        CustomGrammarPatPartCore::AnyFrag => {
+            let frag = UnresolvedAnyFrag(child.as_node().unwrap());
+            CustomGrammarInstPart::AnyFrag(frag)
+        }
+        CustomGrammarPatPartCore::Fact => {
+            let fact = elaborate_fact(child.as_node().unwrap(), ctx)?;
+            CustomGrammarInstPart::Fact(fact)
+        }
+        CustomGrammarPatPartCore::Many(repeated) => {
+            // many ::= (many_none)
+            //        | (many_some) item many
+            let mut items = Vec::new();
+            let mut rest = child.as_node().unwrap();
+            while let [item, tail] = expect_unambiguous(rest)?.children() {
+                items.push(elaborate_custom_grammar_part(repeated, item, ctx)?);
+                rest = tail.as_node().unwrap();
+            }
+            CustomGrammarInstPart::Many(items)
+        }
+    };
+    Ok(g_child)
+}
+
 pub fn elaborate_name<'ctx>(name: ParseTreeId<'ctx>, ctx: &Ctx<'ctx>) -> WResult<'ctx, Ustr> {
     match_rule! { (ctx, name) =>
         name ::= [name_atom] => {
@@ -1552,10 +2346,168 @@ pub fn elaborate_str_lit<'ctx>(str_lit: ParseTreeId<'ctx>, ctx: &Ctx<'ctx>) -> W
     }
 }
 
-fn expect_unambiguous<'ctx>(id: ParseTreeId<'ctx>) -> WResult<'ctx, &'ctx ParseTreeChildren<'ctx>> {
-    match id.0.possibilities() {
-        [] => unreachable!("No possibilities in parse tree."),
-        [possibility] => Ok(possibility),
-        _ => Diagnostic::err_ambiguous_parse(id.span()),
+fn expect_unambiguous<'ctx>(id: ParseTreeId<'ctx>) -> WResult<'ctx, ParseTreeChildren<'ctx>> {
+    let mut possibilities = id.possibilities();
+    match possibilities.len() {
+        0 => unreachable!("No possibilities in parse tree."),
+        1 => Ok(possibilities.next().unwrap()),
+        _ => {
+            let rules = possibilities.map(|p| p.rule()).collect_vec();
+            Diagnostic::err_ambiguous_parse(id.span(), &rules)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// The title of each diagnostic from parsing `main`, with whether it is
+    /// an error and the text its last info part points at.
+    fn diags(main: &str) -> Vec<(String, bool, Option<String>)> {
+        let project = TestProject::with_logic(&format!("module logic\n\n{main}"));
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let ctx = parsed.ctx();
+
+        (ctx.diags.iter())
+            .map(|diag| {
+                let decl = (diag.parts().skip(1).flat_map(|part| part.spans()))
+                    .last()
+                    .map(|span| {
+                        let text = ctx.sources.get_text(span.span().source());
+                        text[span.span().bytes()].to_string()
+                    });
+                (diag.title().to_string(), diag.is_error(), decl)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tactics_starting_the_same_way_are_ambiguous() {
+        let main = "tactic twice.fact\n    tactic ::= @kw\"twice\" f:@fact\nend\n\n\
+            tactic twice.name\n    tactic ::= @kw\"twice\" n:@name\nend\n";
+        let diags = diags(main);
+        assert_eq!(diags.len(), 1, "{diags:?}");
+        let (title, is_error, decl) = &diags[0];
+        assert_eq!(
+            title,
+            "tactic `twice.name` starts with the same tokens as `twice.fact`"
+        );
+        assert!(!is_error);
+        assert!(decl.as_ref().unwrap().starts_with("tactic twice.fact"));
+    }
+
+    #[test]
+    fn tactics_which_only_match_empty_input_are_errors() {
+        let main = "grammar_category opt\n\n\
+            tactic opt.none\n    opt ::=\nend\n\n\
+            tactic only.opt\n    tactic ::= o:opt\nend\n";
+        let diags = diags(main);
+        let titles: Vec<_> = diags
+            .iter()
+            .map(|(title, is_error, _)| (title.as_str(), *is_error))
+            .collect();
+        assert_eq!(
+            titles,
+            [("tactic `only.opt` only matches empty input", true)]
+        );
+    }
+
+    #[test]
+    fn fragments_of_categories_without_rules_are_reported() {
+        let main = "syntax_category widget\n\n\
+            tactic show.widget\n    tactic ::= @kw\"widget\" w:@fragment(widget)\nend\n";
+        let diags = diags(main);
+        assert_eq!(diags.len(), 1, "{diags:?}");
+        let (title, is_error, decl) = &diags[0];
+        assert_eq!(title, "category `widget` has no rules");
+        assert!(!is_error);
+        assert_eq!(decl.as_deref(), Some("syntax_category widget"));
+    }
+
+    #[test]
+    fn distinct_tactics_in_one_category_are_fine() {
+        let main = "grammar_category step\n\n\
+            tactic step.done\n    step ::=\nend\n\n\
+            tactic step.left\n    step ::= \"<\" rest:step\nend\n\n\
+            tactic step.right\n    step ::= \">\" rest:step\nend\n\n\
+            tactic step.jump\n    step ::= @kw\"jump\" to:@name rest:step\nend\n";
+        assert_eq!(diags(main), []);
+    }
+
+    #[test]
+    fn every_broken_hypothesis_is_reported() {
+        let main = "theorem broken [p q : sentence] : (a) (b → p) (c)\n    |- q\n\
+            proof\nqed\n";
+        let titles: Vec<_> = (diags(main).into_iter())
+            .map(|(title, is_error, _)| (title, is_error))
+            .collect();
+        let failure = (
+            "failed to parse fragment: no notation in scope matched".to_string(),
+            true,
+        );
+        assert_eq!(titles, [failure.clone(), failure.clone(), failure]);
+    }
+
+    #[test]
+    fn names_which_are_keywords_are_reported() {
+        let main = "axiom scope :\n    |- ⊥ → ⊥\nend\n\n\
+            theorem proof.again :\n    |- ⊥ → ⊥\nproof\n    by scope\nqed\n";
+        let diags = diags(main);
+        let titles: Vec<_> = (diags.iter())
+            .map(|(title, is_error, _)| (title.as_str(), *is_error))
+            .collect();
+        assert_eq!(titles, [("`scope` is a keyword", false)]);
+    }
+
+    #[test]
+    fn template_defaults_are_printed_with_the_statement() {
+        let project = TestProject::with_logic(
+            "module logic\n\naxiom id [p := ⊥ : sentence] [q : sentence] :\n    |- p → q\nend\n",
+        );
+        let arenas = Arenas::new();
+        let (ctx, report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        assert!(!ctx.diags.has_errors());
+        let (id, _) = report.roots[0].theorems.last().unwrap();
+        assert_eq!(id.print(), "[p := ⊥ : sentence] |- p→q");
+    }
+
+    #[test]
+    fn template_defaults_must_fit_the_template() {
+        let main = "syntax_category term\n\n\
+            syntax zero\n    term ::= \"0\"\nend\n\n\
+            axiom wrong.cat [x := ⊥ : term] :\n    |- ⊥\nend\n\n\
+            axiom forward [p := q : sentence] [q : sentence] :\n    |- p\nend\n";
+        let titles: Vec<_> = (diags(main).into_iter())
+            .filter(|(_, is_error, _)| *is_error)
+            .map(|(title, _, _)| title)
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                "default for a `term` template isn't a `term`",
+                "a template's default can only refer to the templates before it",
+            ]
+        );
+    }
+
+    #[test]
+    fn every_broken_template_is_reported_once() {
+        let main = "theorem broken [p : one] [q : two] [r : three] :\n    |- p\n\
+            proof\nqed\n";
+        let titles: Vec<_> = (diags(main).into_iter())
+            .map(|(title, is_error, _)| (title, is_error))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                ("unknown formal syntax category `one`".to_string(), true),
+                ("unknown formal syntax category `two`".to_string(), true),
+                ("unknown formal syntax category `three`".to_string(), true),
+            ]
+        );
     }
 }
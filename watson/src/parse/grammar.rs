@@ -3,18 +3,24 @@ use crate::{
         Ctx,
         arena::{NamedArena, PlainArena},
     },
-    parse::parse_state::{
-        Associativity, Category, CategoryId, ParseAtomPattern, ParseRuleSource, ParseState,
-        Precedence, Rule, RuleId, RulePattern, RulePatternPart, SyntaxCategorySource,
+    diagnostics::{Diagnostic, DiagnosticSpan},
+    parse::{
+        Span,
+        parse_state::{
+            Associativity, Category, CategoryId, ParseAtomPattern, ParseRuleSource, ParseState,
+            Precedence, Rule, RuleId, RulePattern, RulePatternPart, SyntaxCategorySource,
+        },
     },
     semant::{
         commands::CommandId,
-        custom_grammar::syntax::{CustomGrammarPatPartCore, CustomGrammarRuleId},
+        custom_grammar::syntax::{
+            CustomGrammarCatId, CustomGrammarPat, CustomGrammarPatPartCore, CustomGrammarRuleId,
+        },
         formal_syntax::{FormalSyntaxCatId, FormalSyntaxPatPart, FormalSyntaxRuleId},
         fragment::{FragHead, FragRuleApplication, Fragment, hole_frag, var_frag},
         notation::{
             NotationBinding, NotationBindingId, NotationPattern, NotationPatternId,
-            NotationPatternPart, NotationPatternPartCat, NotationPatternSource,
+            NotationPatternPart, NotationPatternPartCat,
         },
         presentation::{Pres, PresFrag, PresHead},
         scope::{DefinitionSource, ScopeEntry},
@@ -65,23 +71,34 @@ command ::= (module_command)           module_command
           | (syntax_cat_command)       syntax_cat_command
           | (syntax_command)           syntax_command
           | (notation_command)         notation_command
+          | (prefer_command)           prefer_command
+          | (alias_command)            alias_command
           | (definition_command)       definition_command
           | (axiom_command)            axiom_command
           | (theorem_command)          theorem_command
           | (grammar_category_command) grammar_category_command
           | (tactic_command)           tactic_command
           | (attribute_command)        attribute_command
+          | (scope_command)            scope_command
 
 maybe_attribute_anno ::= (attribute_anno_some) attribute_anno
                        | (attribute_anno_none)
 
 attribute_anno ::= (attribute_anno) "@" "[" attributes "]"
 
-attributes ::= (attributes_one)  attribute
-             | (attributes_many) attribute "," attributes
+attributes ::= (attributes_one)  attribute_item
+             | (attributes_many) attribute_item "," attributes
+
+attribute_item ::= (attribute_custom)  attribute
+                 | (attribute_builtin) name maybe_attribute_arg
 
 // attribute filled in dynamically
 
+maybe_attribute_arg ::= (attribute_arg_none)
+                      | (attribute_arg_str)  "(" str ")"
+                      | (attribute_arg_num)  "(" number ")"
+                      | (attribute_arg_name) "(" name ")"
+
 module_command ::= (module) kw"module" name
 
 syntax_cat_command ::= (syntax_cat) kw"syntax_cat" name
@@ -121,12 +138,21 @@ maybe_notation_pat_term_args ::= (maybe_notation_pat_term_args_none)
 notation_pat_term_args ::= (notation_pat_term_args_one)  @name
                          | (notation_pat_term_args_many) @name notation_pat_term_args
 
+// prefer only starts a command when the rest of its line names two notations
+prefer_command ::= (prefer) kw"prefer" name kw"over" name
+
+alias_command ::= (alias) kw"alias" name kw"for" name "::=" notation_pat kw"end"
+
 grammar_category_command ::= (grammar_category) kw"grammar_category" name
 
 tactic_command ::= (tactic) kw"tactic" name name prec_assoc "::=" grammar_pat kw"end"
 
 attribute_command ::= (attribute) kw"attribute" name name prec_assoc "::=" grammar_pat kw"end"
 
+// scope and end only start a command when they are alone on their line
+scope_command ::= (scope_open)  kw"scope"
+                | (scope_close) kw"end"
+
 grammar_pat ::= (grammar_pat_none)
               | (grammar_pat_many) grammar_pat_part grammar_pat
 
@@ -142,22 +168,37 @@ grammar_pat_part_core ::= (core_lit)          str
                         | (core_fragment)     "@" kw"fragment" "(" name ")"
                         | (core_any_fragment) "@" kw"any_fragment"
                         | (core_fact)         "@" kw"fact"
+                        | (core_many)         "@" kw"many" "(" grammar_pat_part_core ")"
 
-definition_command ::= (definition) kw"definition" notation_binding ":=" any_fragment kw"end"
+definition_command ::= (definition)   kw"definition" notation_binding ":=" any_fragment kw"end"
+                     | (abbreviation) kw"abbreviation" notation_binding ":=" any_fragment kw"end"
 
 // notation_binding is created from each notation command
 
-axiom_command ::= (axiom) kw"axiom" name templates ":" hypotheses "|-" sentence kw"end"
-theorem_command ::= (theorem) theorem_kw name templates ":" hypotheses "|-" sentence kw"proof" tactic kw"qed"
+axiom_command ::= (axiom) axiom_kw name maybe_deprecated templates ":" hypotheses "|-" sentence kw"end"
+
+axiom_kw ::= (axiom_kw_axiom)     kw"axiom"
+           | (axiom_kw_postulate) kw"postulate"
+
+maybe_deprecated ::= (deprecated_none)
+                   | (deprecated_some) kw"deprecated"
+
+theorem_command ::= (theorem) theorem_kw name templates ":" hypotheses "|-" sentence kw"proof" proof_body kw"qed"
 
 theorem_kw ::= (theorem_kw_theorem)   kw"theorem"
-             | (theorem_kw_lemma)     kw"lemma"
-             | (theorem_kw_corollary) kw"corollary"
+             | (theorem_kw_lemma)       kw"lemma"
+             | (theorem_kw_proposition) kw"proposition"
+             | (theorem_kw_corollary)   kw"corollary"
+
+proof_body ::= (proof_body_default) tactic
+
+// proof_body ::= kw"using" kw"<tactic_cat>" <tactic_cat> is created for each tactic category
 
 templates ::= (template_none)
             | (template_many) template templates
 
-template ::= (template) "[" template_bindings ":" template_cat "]"
+template ::= (template)         "[" template_bindings ":" template_cat "]"
+           | (template_default) "[" template_bindings ":=" any_fragment ":" template_cat "]"
 
 template_cat ::= (template_cat_no_holes) name
                | (template_cat_holes)    name "(" cat_list ")"
@@ -197,17 +238,25 @@ builtin_cats! {
         maybe_attribute_anno,
         attribute_anno,
         attributes,
+        attribute_item,
+        maybe_attribute_arg,
         module_command,
         syntax_cat_command,
         syntax_command,
         notation_command,
+        prefer_command,
+        alias_command,
         definition_command,
         axiom_command,
+        axiom_kw,
+        maybe_deprecated,
         theorem_command,
         theorem_kw,
+        proof_body,
         grammar_category_command,
         tactic_command,
         attribute_command,
+        scope_command,
         prec_assoc,
         maybe_prec,
         maybe_assoc,
@@ -246,25 +295,40 @@ builtin_rules! {
         attribute_anno,
         attributes_one,
         attributes_many,
+        attribute_custom,
+        attribute_builtin,
+        attribute_arg_none,
+        attribute_arg_str,
+        attribute_arg_num,
+        attribute_arg_name,
         module_command,
         syntax_cat_command,
         syntax_command,
         notation_command,
+        prefer_command,
+        alias_command,
         definition_command,
         axiom_command,
         theorem_command,
         theorem_kw_theorem,
         theorem_kw_lemma,
+        theorem_kw_proposition,
         theorem_kw_corollary,
+        proof_body_default,
+        axiom_kw_axiom,
+        axiom_kw_postulate,
         grammar_category_command,
         tactic_command,
         attribute_command,
+        scope_command,
         module,
         syntax_cat,
         syntax,
         grammar_category,
         tactic,
         attribute,
+        scope_open,
+        scope_close,
         prec_assoc_none,
         prec_assoc_some,
         prec_none,
@@ -278,6 +342,8 @@ builtin_rules! {
         syntax_pat_part_binding,
         syntax_pat_part_lit,
         notation,
+        prefer,
+        alias,
         notation_pat_one,
         notation_pat_many,
         notation_pat_lit,
@@ -301,12 +367,17 @@ builtin_rules! {
         core_fragment,
         core_any_fragment,
         core_fact,
+        core_many,
         definition,
+        abbreviation,
         theorem,
         axiom,
+        deprecated_none,
+        deprecated_some,
         template_none,
         template_many,
         template,
+        template_default,
         template_cat_no_holes,
         template_cat_holes,
         cat_list_one,
@@ -369,7 +440,7 @@ pub fn add_builtin_rules<'ctx>(
         }};
     }
 
-    let rules = BuiltinRules {
+    BuiltinRules {
         name: rule!(
             "name",
             cats.name,
@@ -405,17 +476,51 @@ pub fn add_builtin_rules<'ctx>(
         attributes_one: rule!(
             "attributes_one",
             cats.attributes,
-            vec![cat(attribute_parse_cat)],
+            vec![cat(cats.attribute_item)],
         ),
         attributes_many: rule!(
             "attributes_many",
             cats.attributes,
             vec![
-                cat(attribute_parse_cat),
+                cat(cats.attribute_item),
                 lit(*strings::COMMA),
                 cat(cats.attributes),
             ],
         ),
+        attribute_custom: rule!(
+            "attribute_custom",
+            cats.attribute_item,
+            vec![cat(attribute_parse_cat)],
+        ),
+        attribute_builtin: rule!(
+            "attribute_builtin",
+            cats.attribute_item,
+            vec![cat(cats.name), cat(cats.maybe_attribute_arg)],
+        ),
+        attribute_arg_none: rule!("attribute_arg_none", cats.maybe_attribute_arg, vec![]),
+        attribute_arg_str: rule!(
+            "attribute_arg_str",
+            cats.maybe_attribute_arg,
+            vec![
+                lit(*strings::LEFT_PAREN),
+                cat(cats.str),
+                lit(*strings::RIGHT_PAREN),
+            ],
+        ),
+        attribute_arg_num: rule!(
+            "attribute_arg_num",
+            cats.maybe_attribute_arg,
+            vec![lit(*strings::LEFT_PAREN), num(), lit(*strings::RIGHT_PAREN),],
+        ),
+        attribute_arg_name: rule!(
+            "attribute_arg_name",
+            cats.maybe_attribute_arg,
+            vec![
+                lit(*strings::LEFT_PAREN),
+                cat(cats.name),
+                lit(*strings::RIGHT_PAREN),
+            ],
+        ),
         module_command: rule!(
             "module_command",
             cats.command,
@@ -436,6 +541,12 @@ pub fn add_builtin_rules<'ctx>(
             cats.command,
             vec![cat(cats.notation_command)],
         ),
+        prefer_command: rule!(
+            "prefer_command",
+            cats.command,
+            vec![cat(cats.prefer_command)],
+        ),
+        alias_command: rule!("alias_command", cats.command, vec![cat(cats.alias_command)],),
         definition_command: rule!(
             "definition_command",
             cats.command,
@@ -462,6 +573,7 @@ pub fn add_builtin_rules<'ctx>(
             cats.command,
             vec![cat(cats.attribute_command)],
         ),
+        scope_command: rule!("scope_command", cats.command, vec![cat(cats.scope_command)]),
         module: rule!(
             "module",
             cats.module_command,
@@ -516,6 +628,8 @@ pub fn add_builtin_rules<'ctx>(
                 kw(*strings::END),
             ],
         ),
+        scope_open: rule!("scope_open", cats.scope_command, vec![kw(*strings::SCOPE)]),
+        scope_close: rule!("scope_close", cats.scope_command, vec![kw(*strings::END)]),
         notation: rule!(
             "notation",
             cats.notation_command,
@@ -529,6 +643,29 @@ pub fn add_builtin_rules<'ctx>(
                 kw(*strings::END),
             ],
         ),
+        prefer: rule!(
+            "prefer",
+            cats.prefer_command,
+            vec![
+                kw(*strings::PREFER),
+                cat(cats.name),
+                kw(*strings::OVER),
+                cat(cats.name),
+            ],
+        ),
+        alias: rule!(
+            "alias",
+            cats.alias_command,
+            vec![
+                kw(*strings::ALIAS),
+                cat(cats.name),
+                kw(*strings::FOR),
+                cat(cats.name),
+                lit(*strings::BNF_REPLACE),
+                cat(cats.notation_pat),
+                kw(*strings::END),
+            ],
+        ),
         definition: rule!(
             "definition",
             cats.definition_command,
@@ -540,12 +677,24 @@ pub fn add_builtin_rules<'ctx>(
                 kw(*strings::END),
             ],
         ),
+        abbreviation: rule!(
+            "abbreviation",
+            cats.definition_command,
+            vec![
+                kw(*strings::ABBREVIATION),
+                cat(cats.notation_binding),
+                lit(*strings::ASSIGN),
+                cat(cats.any_fragment),
+                kw(*strings::END),
+            ],
+        ),
         axiom: rule!(
             "axiom",
             cats.axiom_command,
             vec![
-                kw(*strings::AXIOM),
+                cat(cats.axiom_kw),
                 cat(cats.name),
+                cat(cats.maybe_deprecated),
                 cat(cats.templates),
                 lit(*strings::COLON),
                 cat(cats.hypotheses),
@@ -554,6 +703,12 @@ pub fn add_builtin_rules<'ctx>(
                 kw(*strings::END),
             ],
         ),
+        deprecated_none: rule!("deprecated_none", cats.maybe_deprecated, vec![]),
+        deprecated_some: rule!(
+            "deprecated_some",
+            cats.maybe_deprecated,
+            vec![kw(*strings::DEPRECATED)],
+        ),
         theorem: rule!(
             "theorem",
             cats.theorem_command,
@@ -566,10 +721,15 @@ pub fn add_builtin_rules<'ctx>(
                 lit(*strings::TURNSTILE),
                 cat(sentence_cat),
                 kw(*strings::PROOF),
-                cat(tactic_parse_cat),
+                cat(cats.proof_body),
                 kw(*strings::QED),
             ],
         ),
+        proof_body_default: rule!(
+            "proof_body_default",
+            cats.proof_body,
+            vec![cat(tactic_parse_cat)]
+        ),
 
         theorem_kw_theorem: rule!(
             "theorem_kw_theorem",
@@ -581,12 +741,24 @@ pub fn add_builtin_rules<'ctx>(
             cats.theorem_kw,
             vec![kw(*strings::LEMMA)]
         ),
+        theorem_kw_proposition: rule!(
+            "theorem_kw_proposition",
+            cats.theorem_kw,
+            vec![kw(*strings::PROPOSITION)]
+        ),
         theorem_kw_corollary: rule!(
             "theorem_kw_corollary",
             cats.theorem_kw,
             vec![kw(*strings::COROLLARY)]
         ),
 
+        axiom_kw_axiom: rule!("axiom_kw_axiom", cats.axiom_kw, vec![kw(*strings::AXIOM)]),
+        axiom_kw_postulate: rule!(
+            "axiom_kw_postulate",
+            cats.axiom_kw,
+            vec![kw(*strings::POSTULATE)]
+        ),
+
         prec_assoc_none: rule!("prec_assoc_none", cats.prec_assoc, vec![]),
         prec_assoc_some: rule!(
             "prec_assoc_some",
@@ -765,6 +937,17 @@ pub fn add_builtin_rules<'ctx>(
             cats.grammar_pat_part_core,
             vec![lit(*strings::AT), kw(*strings::FACT)],
         ),
+        core_many: rule!(
+            "core_many",
+            cats.grammar_pat_part_core,
+            vec![
+                lit(*strings::AT),
+                kw(*strings::MANY),
+                lit(*strings::LEFT_PAREN),
+                cat(cats.grammar_pat_part_core),
+                lit(*strings::RIGHT_PAREN),
+            ],
+        ),
 
         template_none: rule!("template_none", cats.templates, vec![]),
         template_many: rule!(
@@ -784,6 +967,19 @@ pub fn add_builtin_rules<'ctx>(
                 lit(*strings::RIGHT_BRACKET),
             ],
         ),
+        template_default: rule!(
+            "template_default",
+            cats.template,
+            vec![
+                lit(*strings::LEFT_BRACKET),
+                cat(cats.template_bindings),
+                lit(*strings::ASSIGN),
+                cat(cats.any_fragment),
+                lit(*strings::COLON),
+                cat(cats.template_cat),
+                lit(*strings::RIGHT_BRACKET),
+            ],
+        ),
         template_cat_no_holes: rule!(
             "template_cat_no_holes",
             cats.template_cat,
@@ -843,9 +1039,7 @@ pub fn add_builtin_rules<'ctx>(
             ],
         ),
         fact_sentence: rule!("fact_sentence", cats.fact, vec![cat(sentence_cat)]),
-    };
-    state.recompute_initial_atoms();
-    rules
+    }
 }
 
 pub fn add_parse_rules_for_formal_cat<'ctx>(
@@ -948,7 +1142,7 @@ pub fn formal_rule_to_notation<'ctx>(
             parts,
             rule.pattern().precedence(),
             rule.pattern().associativity(),
-            NotationPatternSource::UserDeclared(rule.span()),
+            rule.provenance(),
         );
         ctx.arenas.notations.alloc(pattern)
     }
@@ -1081,7 +1275,10 @@ fn binding_parse_rule_for_notation<'ctx>(
 }
 
 pub fn add_parse_rules_for_notation<'ctx>(notation: NotationPatternId<'ctx>, ctx: &mut Ctx<'ctx>) {
+    ctx.notations.insert(notation.name(), notation);
+
     let fragment_rule = fragment_parse_rule_for_notation(notation, ctx);
+    check_notation_overlap(notation, fragment_rule, ctx);
     ctx.parse_state.use_rule(fragment_rule);
 
     let (binding_rule1, binding_rule2) = binding_parse_rule_for_notation(notation, ctx);
@@ -1089,33 +1286,129 @@ pub fn add_parse_rules_for_notation<'ctx>(notation: NotationPatternId<'ctx>, ctx
     ctx.parse_state.use_rule(binding_rule2);
 }
 
-fn custom_grammar_rule_to_parse_rule<'ctx>(
-    grammar_rule: CustomGrammarRuleId<'ctx>,
-    ctx: &Ctx<'ctx>,
-) -> RuleId<'ctx> {
-    let mut parts = Vec::new();
-    for grammar_part in grammar_rule.pattern().parts() {
-        use CustomGrammarPatPartCore as PatPart;
+/// Warn if the new notation's rule has the same pattern as a rule already in
+/// its category, or one pattern starts with the other, at the same
+/// precedence. These are the common ways two notations end up parsing the
+/// same text, which otherwise only shows up as an ambiguous parse wherever
+/// the text is used. Other ambiguities aren't caught.
+fn check_notation_overlap<'ctx>(
+    notation: NotationPatternId<'ctx>,
+    rule: RuleId<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) {
+    let Some(span) = notation.provenance().span() else {
+        return;
+    };
+    let parts = rule.pattern().parts();
 
-        let part = match grammar_part.part() {
-            &PatPart::Lit(lit_str) => {
-                // Trim whitespace from literals for parsing, but preserve in presentation
-                let trimmed = Ustr::from(lit_str.trim());
-                lit(trimmed)
-            }
-            PatPart::Kw(kw_str) => kw(*kw_str),
-            PatPart::Name => cat(ctx.builtin_cats.name),
-            PatPart::Cat(tactic_cat) => {
-                let cat = ctx.parse_state.cat_for_tactic_cat(*tactic_cat);
-                RulePatternPart::Cat(cat)
-            }
-            PatPart::Frag(cat_id) => cat(*cat_id),
-            PatPart::AnyFrag => cat(ctx.builtin_cats.any_fragment),
-            PatPart::Fact => cat(ctx.builtin_cats.fact),
+    let mut diags = Vec::new();
+    for &existing in ctx.parse_state.rules_for_cat(rule.cat()) {
+        let &ParseRuleSource::Notation(other) = existing.source() else {
+            continue;
         };
-        parts.push(part);
+        // An alias is meant to overlap with the notation it spells.
+        if other.primary() == notation.primary()
+            || existing.pattern().precedence() != rule.pattern().precedence()
+        {
+            continue;
+        }
+
+        let other_parts = existing.pattern().parts();
+        let shared = parts.len().min(other_parts.len());
+        if parts[..shared] != other_parts[..shared] {
+            continue;
+        }
+        let same = parts.len() == other_parts.len();
+        diags.push(Diagnostic::warn_overlapping_notation(
+            notation, other, same, span,
+        ));
     }
+    ctx.diags.add_diags(diags);
+}
+
+/// The parts of the parse rule generated for a custom grammar pattern. This
+/// adds the categories the repeated parts of the pattern are parsed with if
+/// they don't exist yet.
+pub fn custom_grammar_pat_to_parse_parts<'ctx>(
+    pat: &CustomGrammarPat<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> Vec<RulePatternPart<'ctx>> {
+    pat.parts()
+        .iter()
+        .map(|grammar_part| custom_grammar_part_to_parse_part(grammar_part.part(), ctx))
+        .collect()
+}
 
+fn custom_grammar_part_to_parse_part<'ctx>(
+    part: &CustomGrammarPatPartCore<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> RulePatternPart<'ctx> {
+    use CustomGrammarPatPartCore as PatPart;
+
+    match part {
+        &PatPart::Lit(lit_str) => {
+            // Trim whitespace from literals for parsing, but preserve in presentation
+            let trimmed = Ustr::from(lit_str.trim());
+            lit(trimmed)
+        }
+        PatPart::Kw(kw_str) => kw(*kw_str),
+        PatPart::Name => cat(ctx.builtin_cats.name),
+        PatPart::Cat(tactic_cat) => {
+            let cat = ctx.parse_state.cat_for_tactic_cat(*tactic_cat);
+            RulePatternPart::Cat(cat)
+        }
+        PatPart::Frag(cat_id) => cat(*cat_id),
+        PatPart::AnyFrag => cat(ctx.builtin_cats.any_fragment),
+        PatPart::Fact => cat(ctx.builtin_cats.fact),
+        PatPart::Many(repeated) => {
+            let item = custom_grammar_part_to_parse_part(repeated, ctx);
+            cat(many_cat(item, ctx))
+        }
+    }
+}
+
+/// The category matching any number of `item` in a row. It is shared by every
+/// pattern repeating the same part.
+///
+/// many ::= (many_none)
+///        | (many_some) item many
+fn many_cat<'ctx>(item: RulePatternPart<'ctx>, ctx: &mut Ctx<'ctx>) -> CategoryId<'ctx> {
+    let item_name = match item {
+        RulePatternPart::Atom(ParseAtomPattern::Lit(lit)) => format!("\"{lit}\""),
+        RulePatternPart::Atom(ParseAtomPattern::Kw(kw)) => format!("@kw \"{kw}\""),
+        RulePatternPart::Atom(atom) => format!("{atom:?}"),
+        RulePatternPart::Cat(cat) => cat.name().to_string(),
+    };
+    // No user category can be named like this, so the name can't clash.
+    let name = Ustr::from(&format!("@{}({item_name})", *strings::MANY));
+    if let Some(many) = ctx.arenas.parse_cats.get(name) {
+        return many;
+    }
+
+    let many = Category::new(name, SyntaxCategorySource::Builtin);
+    let many = ctx.arenas.parse_cats.alloc(name, many);
+    ctx.parse_state.use_cat(many);
+
+    let none = RulePattern::new(vec![], Precedence::default(), Associativity::default());
+    let some = RulePattern::new(
+        vec![item, cat(many)],
+        Precedence::default(),
+        Associativity::default(),
+    );
+    for (rule_name, pattern) in [("many_none", none), ("many_some", some)] {
+        let rule = Rule::new(rule_name, many, ParseRuleSource::Builtin, pattern);
+        let rule = ctx.arenas.parse_rules.alloc(rule);
+        ctx.parse_state.use_rule(rule);
+    }
+
+    many
+}
+
+fn custom_grammar_rule_to_parse_rule<'ctx>(
+    grammar_rule: CustomGrammarRuleId<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> RuleId<'ctx> {
+    let parts = custom_grammar_pat_to_parse_parts(grammar_rule.pattern(), ctx);
     let parse_pat = RulePattern::new(
         parts,
         grammar_rule.pattern().precedence(),
@@ -1132,6 +1425,25 @@ fn custom_grammar_rule_to_parse_rule<'ctx>(
     ctx.arenas.parse_rules.alloc(parse_rule)
 }
 
+/// Let theorems be proved in the tactic category with `proof using <cat>`.
+pub fn add_parse_rules_for_grammar_cat<'ctx>(cat: CustomGrammarCatId<'ctx>, ctx: &mut Ctx<'ctx>) {
+    let rule = ctx.arenas.parse_rules.alloc(Rule::new(
+        "proof_body_using",
+        ctx.builtin_cats.proof_body,
+        ParseRuleSource::ProofCategory(cat),
+        RulePattern::new(
+            vec![
+                kw(*strings::USING),
+                kw(cat.name()),
+                RulePatternPart::Cat(ctx.parse_state.cat_for_tactic_cat(cat)),
+            ],
+            Precedence::default(),
+            Associativity::default(),
+        ),
+    ));
+    ctx.parse_state.use_rule(rule);
+}
+
 pub fn add_parse_rules_for_custom_grammar_rule<'ctx>(
     grammar_rule: CustomGrammarRuleId<'ctx>,
     ctx: &mut Ctx<'ctx>,
@@ -1139,3 +1451,42 @@ pub fn add_parse_rules_for_custom_grammar_rule<'ctx>(
     let parse_rule = custom_grammar_rule_to_parse_rule(grammar_rule, ctx);
     ctx.parse_state.use_rule(parse_rule);
 }
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_overlapping_notation(
+        notation: NotationPatternId<'ctx>,
+        other: NotationPatternId<'ctx>,
+        same: bool,
+        span: Span,
+    ) -> Diagnostic<'ctx> {
+        let overlap = if same {
+            "has the same pattern as"
+        } else {
+            "has a pattern starting like that of"
+        };
+        let title = format!(
+            "notation `{}` {overlap} `{}` at the same precedence",
+            notation.name(),
+            other.name()
+        );
+        let other_spans = other
+            .provenance()
+            .span()
+            .map(|other_span| DiagnosticSpan::new_info("declared here", other_span))
+            .into_iter()
+            .collect();
+
+        Diagnostic::new_warning(
+            &title,
+            vec![DiagnosticSpan::new_warning(
+                "may make fragments ambiguous",
+                span,
+            )],
+        )
+        .with_info(&format!("`{}` is declared here", other.name()), other_spans)
+        .with_info(
+            "give one a different precedence or use `prefer` to choose between them",
+            Vec::new(),
+        )
+    }
+}
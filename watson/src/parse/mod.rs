@@ -2,19 +2,28 @@ pub mod earley;
 pub mod elaborator;
 pub mod grammar;
 pub mod location;
+pub mod module_graph;
+pub mod parse_cache;
 pub mod parse_state;
 pub mod parse_tree;
+pub mod rewrite;
+pub mod scan;
 pub mod source_cache;
+pub mod source_files;
 
 pub use location::{Location, SourceId, Span};
+use rustc_hash::FxHashMap;
 pub use source_cache::SourceCache;
 use ustr::Ustr;
 
 use crate::{
     context::Ctx,
+    diagnostics::{Diagnostic, WResult},
     parse::{
         earley::parse_name,
         elaborator::ElaborateAction,
+        location::SourceOffset,
+        parse_cache::ParseCache,
         parse_state::{
             Associativity, Category, ParseAtomPattern, Precedence, SyntaxCategorySource,
         },
@@ -22,107 +31,198 @@ use crate::{
     },
     semant::{
         attributes::AttributeTracker,
-        commands::CommandInfo,
+        commands::{CommandId, CommandInfo},
         formal_syntax::FormalSyntaxCatId,
-        notation::{NotationPattern, NotationPatternPart, NotationPatternSource},
-        scope::Scope,
+        notation::{NotationBindingId, NotationPattern, NotationPatternPart, NotationPreferences},
+        provenance::Provenance,
+        scope::{Scope, ScopeEntry},
         tactic::unresolved_proof::UnresolvedProof,
         theorems::TheoremId,
     },
+    strings,
 };
 
 pub struct ParseReport<'ctx> {
-    pub theorems: Vec<(TheoremId<'ctx>, UnresolvedProof<'ctx>)>,
-    pub scope: Scope<'ctx>,
+    pub roots: Vec<RootReport<'ctx>>,
     pub attributes: AttributeTracker<'ctx>,
     pub entries: Vec<ParseEntry<'ctx>>,
+    /// The theorem elaborated from each theorem or axiom command.
+    pub command_theorems: FxHashMap<ParseTreeId<'ctx>, TheoremId<'ctx>>,
+}
+
+/// The result of parsing a single root source and the modules it declares.
+pub struct RootReport<'ctx> {
+    pub source: SourceId,
+    pub theorems: Vec<(TheoremId<'ctx>, UnresolvedProof<'ctx>)>,
+    pub scope: Scope<'ctx>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ParseEntry<'ctx> {
     Text(Span),
-    Command(ParseTreeId<'ctx>),
+    Command(ParseTreeId<'ctx>, CommandId<'ctx>),
+    /// A command found by [`scan::scan`] which was never parsed, so only its
+    /// extent is known.
+    RawCommand(Span),
 }
 
-pub fn parse<'ctx>(root: SourceId, ctx: &mut Ctx<'ctx>) -> ParseReport<'ctx> {
-    let mut sources_stack = Vec::new();
-    let mut scope = Scope::new();
-    let mut attributes = AttributeTracker::new();
-    sources_stack.push(root.start_loc());
+/// A `scope` block which hasn't reached its `end` yet.
+struct OpenScope<'ctx> {
+    /// The scope before the block, which its `end` goes back to.
+    outer: Scope<'ctx>,
+    /// The `scope` command which opened the block.
+    span: Span,
+    /// The bindings of the formal syntax rules declared in the block. The
+    /// rules stay in the grammar after the block, so their bindings have to
+    /// stay in scope too.
+    kept: Vec<(NotationBindingId<'ctx>, ScopeEntry<'ctx>)>,
+}
 
-    let mut theorems = Vec::new();
+/// Parse each of the roots in order.
+///
+/// All roots share the same arenas and parse state, but each root starts with
+/// a fresh scope. This means that while the syntax declared by one root can
+/// still be parsed in a later root, definitions and notation are only visible
+/// in a later root if it imports the earlier root with `module`.
+pub fn parse<'ctx>(roots: &[SourceId], ctx: &mut Ctx<'ctx>) -> ParseReport<'ctx> {
+    let mut attributes = AttributeTracker::new();
     let mut entries = Vec::new();
-    while let Some(next) = sources_stack.pop() {
-        parse_source(
-            next,
-            ctx,
-            &mut sources_stack,
-            &mut scope,
-            &mut attributes,
-            &mut theorems,
-            &mut entries,
-        );
+    let mut command_theorems = FxHashMap::default();
+    let mut root_reports = Vec::new();
+    let mut cache = ParseCache::load(ctx);
+
+    for &root in roots {
+        if !ctx.diags.should_continue() {
+            break;
+        }
+
+        let mut sources_stack = Vec::new();
+        let mut scope = Scope::new();
+        let mut open_scopes = Vec::new();
+        sources_stack.push(root.start_loc());
+
+        let mut theorems = Vec::new();
+        while ctx.diags.should_continue()
+            && let Some(next) = sources_stack.pop()
+        {
+            parse_source(
+                next,
+                ctx,
+                &mut sources_stack,
+                &mut scope,
+                &mut open_scopes,
+                &mut attributes,
+                &mut theorems,
+                &mut entries,
+                &mut command_theorems,
+                &mut cache,
+            );
+        }
+
+        // Save the final scope so later roots can import this one.
+        ctx.root_scopes.insert(root, scope.clone());
+
+        root_reports.push(RootReport {
+            source: root,
+            theorems,
+            scope,
+        });
     }
 
+    cache.save();
+
     ParseReport {
-        theorems,
-        scope,
+        roots: root_reports,
         attributes,
         entries,
+        command_theorems,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn parse_source<'ctx>(
     loc: Location,
     ctx: &mut Ctx<'ctx>,
     sources_stack: &mut Vec<Location>,
     scope: &mut Scope<'ctx>,
+    open_scopes: &mut Vec<OpenScope<'ctx>>,
     attribute_tracker: &mut AttributeTracker<'ctx>,
     theorems: &mut Vec<(TheoremId<'ctx>, UnresolvedProof<'ctx>)>,
     entries: &mut Vec<ParseEntry<'ctx>>,
+    command_theorems: &mut FxHashMap<ParseTreeId<'ctx>, TheoremId<'ctx>>,
+    cache: &mut ParseCache,
 ) {
     let source = loc.source();
     let text = ctx.sources.get_text(source).as_str();
 
+    if loc.byte_offset() == 0 {
+        cache.enter_source(source, text);
+    }
+
     if loc.byte_offset() >= text.len() {
-        // This file is finished so we don't need to do anything more.
+        // This file is finished. Blocks it didn't close end with it so their
+        // definitions don't reach the source which loaded it.
+        while let Some(open) = open_scopes.last()
+            && open.span.source() == source
+        {
+            ctx.diags
+                .add_diags(Diagnostic::err_unclosed_scope::<()>(open.span).unwrap_err());
+            close_scope(scope, open_scopes);
+        }
         return;
     }
 
-    // Check if the current line could possible start a command.
-    if can_start_command(text, loc, ctx) {
+    // Check if the current line could possible start a command. Commands in
+    // code blocks are examples in the prose so they aren't parsed.
+    let fence_end = skip_code_fence(text, loc);
+    if fence_end.is_none() && can_start_command(text, loc, ctx) {
         // The current line could start a command so we will assume it does.
 
-        let tree = match earley::parse(loc, ctx.builtin_cats.command_decl, ctx) {
-            Ok(tree) => tree,
-            Err(diags) => {
-                // We weren't able to parse a command. Add the diagnostics and
-                // move onto the next line.
-                ctx.diags.add_diags(diags);
-                sources_stack.push(next_line(text, loc));
-                return;
+        // Commands which haven't changed since the last run don't need to
+        // be parsed again.
+        let tree = if let Some(tree) = cache.get(loc, ctx) {
+            tree
+        } else {
+            match earley::parse(loc, ctx.builtin_cats.command_decl, scope.preferences(), ctx) {
+                Ok(tree) => {
+                    cache.add(tree, ctx);
+                    tree
+                }
+                Err(diags) => {
+                    // We weren't able to parse a command. Add the diagnostics
+                    // and skip the rest of the command, keeping it as prose
+                    // so the book still shows it.
+                    ctx.diags.add_diags(diags);
+                    let resume = recovery_point(text, loc, ctx);
+                    sources_stack.push(resume);
+                    let span = Span::new(loc, resume);
+                    ctx.sources.debug_assert_char_boundaries(span);
+                    push_text(entries, span);
+                    return;
+                }
             }
         };
 
-        entries.push(ParseEntry::Command(tree));
+        let cmd = ctx.arenas.commands.alloc(CommandInfo::new());
+        entries.push(ParseEntry::Command(tree, cmd));
 
         // Push the location after this command onto the stack so we can
         // continue parsing this source file later.
         let after_command = tree.span().end();
         sources_stack.push(after_command);
 
-        let cmd = ctx.arenas.commands.alloc(CommandInfo::new());
-
         // Now let's elaborate the command.
-        let (action, attributes) = match elaborator::elaborate_command_decl(tree, cmd, scope, ctx) {
-            Ok((action, attributes)) => (action, attributes),
-            Err(diags) => {
-                // There was an error elaborating the command. Add the diagnostics
-                // and continue.
-                ctx.diags.add_diags(diags);
-                return;
-            }
-        };
+        let open_scope = open_scopes.last().map(|open| open.span);
+        let (action, attributes) =
+            match elaborator::elaborate_command_decl(tree, cmd, scope, open_scope, ctx) {
+                Ok((action, attributes)) => (action, attributes),
+                Err(diags) => {
+                    // There was an error elaborating the command. Add the diagnostics
+                    // and continue.
+                    ctx.diags.add_diags(diags);
+                    return;
+                }
+            };
 
         *attribute_tracker = attribute_tracker.child_with(cmd, attributes);
 
@@ -134,6 +234,15 @@ fn parse_source<'ctx>(
                 let start_loc = new_source.start_loc();
                 sources_stack.push(start_loc);
             }
+            ElaborateAction::ImportRoot(root) => {
+                // This command imported a root which was already parsed. Rather
+                // than parsing it again we bring its final scope into this one.
+                *scope = scope.with_imported(&ctx.root_scopes[&root]);
+            }
+            ElaborateAction::ImportLoaded => {
+                // The module was parsed into this root's scope when it was
+                // first loaded, so there is nothing more to do.
+            }
             ElaborateAction::NewFormalCat(cat) => {
                 // The command created a new formal syntax category. We need to
                 // update the state of the parser to include this category.
@@ -149,15 +258,21 @@ fn parse_source<'ctx>(
                 let (pattern, binding, scope_entry) =
                     grammar::formal_rule_to_notation(rule, cmd, ctx);
                 grammar::add_parse_rules_for_notation(pattern, ctx);
-                ctx.parse_state.recompute_initial_atoms();
 
                 *scope = scope.child_with(binding, scope_entry);
+                if let Some(open) = open_scopes.last_mut() {
+                    open.kept.push((binding, scope_entry));
+                }
             }
             ElaborateAction::NewNotation(notation) => {
                 // The command created new notation. We need to update the state
                 // of the parser to include this notation.
                 grammar::add_parse_rules_for_notation(notation, ctx);
-                ctx.parse_state.recompute_initial_atoms();
+            }
+            ElaborateAction::NewPreference(preferred, other, span) => {
+                // Fragments which could use either notation will now use the
+                // preferred one, until the end of the scope.
+                *scope = scope.with_preference(preferred, other, span);
             }
             ElaborateAction::NewDefinition(new_scope) => {
                 // The definition added a new binding to the scope. Replace the
@@ -165,6 +280,12 @@ fn parse_source<'ctx>(
                 *scope = new_scope;
             }
             ElaborateAction::NewTheorem(new_theorem, proof) => {
+                // Handlers can be registered for the rules of any category a
+                // proof is written in.
+                if let UnresolvedProof::Theorem(proof) = &proof {
+                    ctx.custom_grammar_manager.use_proof_cat(proof.rule().cat());
+                }
+                command_theorems.insert(tree, new_theorem);
                 theorems.push((new_theorem, proof));
             }
             ElaborateAction::NewGrammarCat(cat) => {
@@ -173,7 +294,7 @@ fn parse_source<'ctx>(
                 let parse_cat = Category::new(cat.name(), SyntaxCategorySource::User(cat));
                 let parse_cat = ctx.arenas.parse_cats.alloc(cat.name(), parse_cat);
                 ctx.parse_state.use_cat(parse_cat);
-                ctx.parse_state.recompute_initial_atoms();
+                grammar::add_parse_rules_for_grammar_cat(cat, ctx);
 
                 ctx.custom_grammar_manager.use_cat(cat);
             }
@@ -181,27 +302,76 @@ fn parse_source<'ctx>(
                 // The command created a new custom grammar rule. We need to
                 // update the state of the parser to include this rule.
                 grammar::add_parse_rules_for_custom_grammar_rule(rule, ctx);
-                ctx.parse_state.recompute_initial_atoms();
 
                 ctx.custom_grammar_manager.use_rule(rule);
             }
+            ElaborateAction::PushScope(span) => {
+                // Theorems and syntax are global, so only the scope has to
+                // be restored when the block ends.
+                open_scopes.push(OpenScope {
+                    outer: scope.clone(),
+                    span,
+                    kept: Vec::new(),
+                });
+            }
+            ElaborateAction::PopScope(span) => {
+                // A block can only be closed by the source which opened it.
+                if open_scopes
+                    .last()
+                    .is_some_and(|open| open.span.source() == source)
+                {
+                    close_scope(scope, open_scopes);
+                } else {
+                    let diags = Diagnostic::err_unmatched_scope_end::<()>(span);
+                    ctx.diags.add_diags(diags.unwrap_err());
+                }
+            }
         }
     } else {
-        // This line doesn't start a command so we can skip to the next line.
-        let next_loc = next_line(text, loc);
-        sources_stack.push(next_loc);
-
-        if let Some(ParseEntry::Text(prev_span)) = entries.last()
-            && prev_span.end() == loc
+        // This line doesn't start a command so we can skip to the next line,
+        // or past the code block it opens.
+        let next_loc = fence_end.unwrap_or_else(|| next_line(text, loc));
+        if fence_end.is_none()
+            && let Some(diag) = check_misfired_command(text, loc, next_loc, ctx)
         {
-            // We can merge this text span with the previous one.
-            let span = Span::new(prev_span.start(), next_loc);
-            entries.pop();
-            entries.push(ParseEntry::Text(span));
-        } else {
-            let span = Span::new(loc, next_loc);
-            entries.push(ParseEntry::Text(span));
+            ctx.diags.add_diag(diag);
         }
+        sources_stack.push(next_loc);
+        let span = Span::new(loc, next_loc);
+        ctx.sources.debug_assert_char_boundaries(span);
+        push_text(entries, span);
+    }
+}
+
+/// Close the innermost `scope` block, going back to the scope from before it
+/// except for the bindings which outlive it.
+fn close_scope<'ctx>(scope: &mut Scope<'ctx>, open_scopes: &mut Vec<OpenScope<'ctx>>) {
+    let open = open_scopes.pop().unwrap();
+    *scope = open.outer;
+    for &(binding, entry) in &open.kept {
+        *scope = scope.child_with(binding, entry);
+    }
+    if let Some(enclosing) = open_scopes.last_mut() {
+        enclosing.kept.extend(open.kept);
+    }
+}
+
+/// Add a span of prose to the entries, merging it with the previous entry if
+/// that is prose which ends where this starts. Blank lines are prose too, so
+/// only a command or the end of a source separates two text entries. The
+/// last line of a source is included up to the end of the text whether or
+/// not it ends with a newline.
+fn push_text<'ctx>(entries: &mut Vec<ParseEntry<'ctx>>, span: Span) {
+    if span.start() == span.end() {
+        return;
+    }
+
+    if let Some(ParseEntry::Text(prev_span)) = entries.last_mut()
+        && prev_span.end() == span.start()
+    {
+        *prev_span = Span::new(prev_span.start(), span.end());
+    } else {
+        entries.push(ParseEntry::Text(span));
     }
 }
 
@@ -216,21 +386,53 @@ pub fn add_formal_cat<'ctx>(cat: FormalSyntaxCatId<'ctx>, ctx: &mut Ctx<'ctx>) {
     let prec = Precedence::default();
     let assoc = Associativity::default();
 
-    let notation = NotationPattern::new(
-        name,
-        cat,
-        parts,
-        prec,
-        assoc,
-        NotationPatternSource::Builtin,
-    );
+    let notation = NotationPattern::new(name, cat, parts, prec, assoc, Provenance::Builtin);
     let notation = ctx.arenas.notations.alloc(notation);
     grammar::add_parse_rules_for_notation(notation, ctx);
 
     ctx.single_name_notations.insert(cat, notation);
+}
 
-    // Update the parse state given all the rules we have added.
-    ctx.parse_state.recompute_initial_atoms();
+/// Parse and elaborate the command `source` starts with, which isn't part of
+/// the project, in `scope`. It is checked like any command in the project, so
+/// a generator can be sure what it writes elaborates before writing it.
+pub fn check_command<'ctx>(
+    source: SourceId,
+    scope: &Scope<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) -> WResult<'ctx, (ParseTreeId<'ctx>, ElaborateAction<'ctx>)> {
+    let tree = earley::parse(
+        source.start_loc(),
+        ctx.builtin_cats.command_decl,
+        scope.preferences(),
+        ctx,
+    )?;
+    let cmd = ctx.arenas.commands.alloc(CommandInfo::new());
+    let (action, _) = elaborator::elaborate_command_decl(tree, cmd, scope, None, ctx)?;
+    Ok((tree, action))
+}
+
+/// Parse the commands in an example from the book without elaborating them.
+/// Commands which don't parse are skipped and their errors are discarded.
+/// Examples aren't in any scope, so no `prefer` applies in them.
+pub fn parse_example<'ctx>(source: SourceId, ctx: &Ctx<'ctx>) -> Vec<ParseTreeId<'ctx>> {
+    let text = ctx.sources.get_text(source).as_str();
+    let mut trees = Vec::new();
+    let preferences = NotationPreferences::default();
+
+    let mut loc = source.start_loc();
+    while loc.byte_offset() < text.len() {
+        if can_start_command(text, loc, ctx)
+            && let Ok(tree) = earley::parse(loc, ctx.builtin_cats.command_decl, &preferences, ctx)
+        {
+            trees.push(tree);
+            loc = tree.span().end();
+        } else {
+            loc = next_line(text, loc);
+        }
+    }
+
+    trees
 }
 
 fn can_start_command(text: &str, loc: Location, ctx: &Ctx) -> bool {
@@ -238,14 +440,14 @@ fn can_start_command(text: &str, loc: Location, ctx: &Ctx) -> bool {
 
     for atom in ctx.parse_state.initial_atoms(ctx.builtin_cats.command_decl) {
         match atom {
-            ParseAtomPattern::Lit(lit) => {
-                if text[loc.byte_offset()..].starts_with(lit.as_str()) {
-                    return true;
-                }
+            ParseAtomPattern::Lit(lit) if text[loc.byte_offset()..].starts_with(lit.as_str()) => {
+                return true;
             }
             ParseAtomPattern::Kw(kw) => {
-                if let Some((_, parsed_name)) = name
+                if let Some((word_end, parsed_name)) = name
                     && parsed_name == kw.as_str()
+                    && (!is_bare_command_kw(*kw) || rest_of_line_is_empty(text, word_end))
+                    && (*kw != *strings::PREFER || rest_of_line_is_preference(text, word_end, ctx))
                 {
                     return true;
                 }
@@ -257,6 +459,157 @@ fn can_start_command(text: &str, loc: Location, ctx: &Ctx) -> bool {
     false
 }
 
+/// Whether the keyword is a whole command on its own, like `scope` and `end`.
+/// Those only start a command when they are alone on their line, since
+/// plenty of prose starts with the same words.
+fn is_bare_command_kw(kw: Ustr) -> bool {
+    kw == *strings::SCOPE || kw == *strings::END
+}
+
+/// Whether there is nothing but whitespace and a comment between `from` and
+/// the end of its line.
+fn rest_of_line_is_empty(text: &str, from: SourceOffset) -> bool {
+    let rest = text[from.byte_offset()..]
+        .lines()
+        .next()
+        .unwrap_or("")
+        .trim();
+    rest.is_empty() || rest.starts_with("--")
+}
+
+/// Whether the rest of a line starting with `prefer` is `a over b` for two
+/// notations. Prose starts with the word too, but hardly ever in that shape.
+fn rest_of_line_is_preference(text: &str, from: SourceOffset, ctx: &Ctx) -> bool {
+    let line = text[from.byte_offset()..].lines().next().unwrap_or("");
+    let line = line.split("--").next().unwrap_or("");
+    let is_notation = |word: &str| ctx.notations.contains_key(&Ustr::from(word));
+    let words: Vec<_> = line.split_whitespace().collect();
+    matches!(
+        words.as_slice(),
+        [preferred, over, other]
+            if *over == strings::OVER.as_str() && is_notation(preferred) && is_notation(other)
+    )
+}
+
+/// Ends a line of prose which shouldn't be reported as a misfired command.
+const IGNORE_COMMENT: &str = "-- watson: ignore";
+
+/// Warn about a line of prose which looks like it was meant to be a command:
+/// its first word is a command keyword with the wrong capitalization, or a
+/// keyword, reserved or from a rule of the grammar so far, which doesn't
+/// start a command here. Plenty of prose starts with words like "theorem",
+/// so the rest of the line also has to have something only commands have.
+fn check_misfired_command<'ctx>(
+    text: &str,
+    loc: Location,
+    line_end: Location,
+    ctx: &Ctx<'ctx>,
+) -> Option<Diagnostic<'ctx>> {
+    let line = text[loc.byte_offset()..line_end.byte_offset()].trim_end();
+    if line.ends_with(IGNORE_COMMENT) {
+        return None;
+    }
+
+    let (word_end, word) = parse_name(text, loc.offset())?;
+    let rest = &text[word_end.byte_offset()..loc.byte_offset() + line.len()];
+    let looks_like_command = rest.contains(strings::BNF_REPLACE.as_str())
+        || rest.contains(strings::TURNSTILE.as_str())
+        || rest.split_whitespace().any(|w| w == strings::END.as_str());
+    if !looks_like_command {
+        return None;
+    }
+
+    let span = Span::new(loc, loc.forward(line.len()));
+    let command_kw = ctx
+        .parse_state
+        .initial_atoms(ctx.builtin_cats.command_decl)
+        .iter()
+        .find_map(|atom| match atom {
+            ParseAtomPattern::Kw(kw) if kw.eq_ignore_ascii_case(word) => Some(*kw),
+            _ => None,
+        });
+    if let Some(kw) = command_kw {
+        Some(Diagnostic::warn_miscapitalized_command(word, kw, span))
+    } else if strings::is_reserved_keyword(word) || ctx.parse_state.knows_keyword(word) {
+        Some(Diagnostic::warn_keyword_not_command(word, span))
+    } else {
+        None
+    }
+}
+
+/// Where to carry on after the command at `loc` failed to parse: the next
+/// line which starts a command or a code block, or the line after one which
+/// starts with `end` or `qed`. The lines before that are most likely the
+/// rest of the broken command, and parsing them on their own would only
+/// report more errors.
+///
+/// A line with only `end` on it is also the command closing a `scope`
+/// block. It is kept for the broken command only if that command ends with
+/// `end` itself, so a block isn't left open by a broken theorem in it.
+fn recovery_point(text: &str, loc: Location, ctx: &Ctx) -> Location {
+    let needs_end = !parse_name(text, loc.offset()).is_some_and(|(_, word)| {
+        [
+            &strings::THEOREM,
+            &strings::LEMMA,
+            &strings::PROPOSITION,
+            &strings::COROLLARY,
+            &strings::MODULE,
+            &strings::SYNTAX_CAT,
+            &strings::GRAMMAR_CATEGORY,
+            &strings::PREFER,
+        ]
+        .iter()
+        .any(|kw| kw.as_str() == word)
+    });
+
+    let mut at = next_line(text, loc);
+    while at.byte_offset() < text.len() {
+        let line_end = next_line(text, at);
+        if let Some((word_end, word)) = parse_name(text, at.offset())
+            && (word == strings::END.as_str() || word == strings::QED.as_str())
+        {
+            let closes_scope = word == strings::END.as_str()
+                && !needs_end
+                && rest_of_line_is_empty(text, word_end);
+            return if closes_scope { at } else { line_end };
+        }
+        if can_start_command(text, at, ctx) || skip_code_fence(text, at).is_some() {
+            return at;
+        }
+        at = line_end;
+    }
+    at
+}
+
+/// If the line at `loc` opens a fenced code block with an info string, like
+/// ```` ```watson ```` for an example or ```` ```text ````, find the location
+/// after the line which closes it, or the end of the source if it is never
+/// closed. The commands in a fence without an info string are parsed.
+fn skip_code_fence(text: &str, loc: Location) -> Option<Location> {
+    let is_fence = |line: &str| line.trim_start().starts_with("```");
+
+    let mut at = next_line(text, loc);
+    let opening = text[loc.byte_offset()..at.byte_offset()].trim();
+    let info = opening
+        .strip_prefix("```")
+        .map(|rest| rest.trim_start_matches('`').trim());
+    if info.is_none_or(|info| info.is_empty()) {
+        return None;
+    }
+
+    while at.byte_offset() < text.len() {
+        let line_start = at.byte_offset();
+        at = next_line(text, at);
+        if is_fence(&text[line_start..at.byte_offset()]) {
+            break;
+        }
+    }
+
+    Some(at)
+}
+
+/// The start of the line after the one at `loc`, or the end of the text if
+/// this is the last line.
 fn next_line(text: &str, loc: Location) -> Location {
     let rest = &text[loc.byte_offset()..];
     if let Some(line) = rest.split_inclusive('\n').next() {
@@ -265,3 +618,292 @@ fn next_line(text: &str, loc: Location) -> Location {
         loc.forward(rest.len())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Arenas,
+        driver::Driver,
+        semant::fragment::{FragHead, FragmentId},
+        util::test_project::TestProject,
+    };
+
+    /// How many commands a project with `main` has, making sure it parses
+    /// without errors.
+    fn command_count(main: &str) -> usize {
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        assert!(!parsed.ctx().diags.has_errors());
+        let (_, report) = parsed.into_parts();
+        report
+            .entries
+            .iter()
+            .filter(|entry| matches!(entry, ParseEntry::Command(..)))
+            .count()
+    }
+
+    #[test]
+    fn prefer_between_notations_is_a_command() {
+        let base = command_count("module logic\n");
+        assert_eq!(
+            command_count("module logic\n\nprefer or over and\n"),
+            base + 1
+        );
+    }
+
+    #[test]
+    fn only_fences_with_an_info_string_are_skipped() {
+        let base = command_count("module logic\n");
+        let fenced = |info: &str| {
+            command_count(&format!(
+                "module logic\n\n```{info}\naxiom top : |- ⊥ → ⊥\nend\n```\n"
+            ))
+        };
+        assert_eq!(fenced(""), base + 1);
+        assert_eq!(fenced("watson"), base);
+        assert_eq!(fenced("text"), base);
+    }
+
+    #[test]
+    fn prose_starting_with_prefer_is_not_a_command() {
+        let base = command_count("module logic\n");
+        assert_eq!(
+            command_count("module logic\n\nprefer or over and, when in doubt.\n"),
+            base
+        );
+        assert_eq!(
+            command_count("module logic\n\nprefer clarity over brevity\n"),
+            base
+        );
+    }
+
+    /// The titles of the diagnostics for a project with `main` and modules
+    /// `a` and `b` which both import the propositional library.
+    fn import_titles(main: &str) -> Vec<&'static str> {
+        let project = TestProject::with_logic(main);
+        project.write("math/a.wats", "module logic\n");
+        project.write("math/b.wats", "module logic\n");
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        parsed.ctx().diags.iter().map(|diag| diag.title()).collect()
+    }
+
+    #[test]
+    fn modules_can_share_an_import() {
+        assert!(import_titles("module a\n\nmodule b\n").is_empty());
+    }
+
+    #[test]
+    fn importing_a_module_twice_is_reported() {
+        assert_eq!(
+            import_titles("module a\n\nmodule b\n\nmodule logic\n\nmodule logic\n"),
+            ["module `logic` is already imported"]
+        );
+    }
+
+    #[test]
+    fn syntax_literals_can_hold_escaped_quotes() {
+        let base = command_count("module logic\n");
+        let quote = "module logic\n\nsyntax quote_lit\n    sentence ::= \"\\\"\"\nend\n";
+        assert_eq!(command_count(quote), base + 1);
+
+        let project = TestProject::with_logic(
+            "module logic\n\nsyntax bad_lit\n    sentence ::= \"\\q\"\nend\n",
+        );
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let titles: Vec<_> = parsed.ctx().diags.iter().map(|diag| diag.title()).collect();
+        assert_eq!(titles, ["invalid escape `\\q` in string literal"]);
+    }
+
+    /// The titles of the errors from parsing a chain of 20 uses of a
+    /// non-associative operator, with `config` as the project's config, and
+    /// how long parsing took.
+    fn parse_chain(config: &str) -> (Vec<&'static str>, std::time::Duration) {
+        let chain = vec!["p"; 21].join(" ⊕ ");
+        let project = TestProject::with_logic(&format!(
+            "module logic\n\n\
+             syntax xor\n    sentence (300) ::= sentence \"⊕\" sentence\nend\n\n\
+             axiom chain [p : sentence] :\n    |- {chain}\nend\n"
+        ));
+        project.write("watson.toml", config);
+        let arenas = Arenas::new();
+        let start = std::time::Instant::now();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let elapsed = start.elapsed();
+        let errors = (parsed.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title())
+            .collect();
+        (errors, elapsed)
+    }
+
+    #[test]
+    fn a_long_chain_of_a_non_associative_operator_fails_quickly() {
+        let (errors, elapsed) = parse_chain("");
+        assert!(!errors.is_empty());
+        assert!(
+            !errors
+                .iter()
+                .any(|title| title.starts_with("gave up splitting")),
+            "{errors:?}"
+        );
+        assert!(elapsed < std::time::Duration::from_secs(1), "{elapsed:?}");
+    }
+
+    #[test]
+    fn splitting_past_the_step_limit_is_reported() {
+        // Enough steps for the commands of `logic` but not for the chain.
+        let (errors, elapsed) = parse_chain("[check]\nsplit_step_limit = 30\n");
+        assert_eq!(
+            errors,
+            ["gave up splitting `axiom` into its parts after 31 steps"]
+        );
+        assert!(elapsed < std::time::Duration::from_secs(1), "{elapsed:?}");
+    }
+
+    /// The grouping of the conclusion of a theorem `shape` about `a`, `b`
+    /// and `c`, with `+` and `-` at precedence 10 and `*` at 20, all left
+    /// associative. Each rule application is written `(rule operands...)`.
+    fn grouping(statement: &str) -> String {
+        fn shape(frag: FragmentId, out: &mut String) {
+            match frag.head() {
+                FragHead::RuleApplication(app) => {
+                    out.push_str(&format!("({}", app.rule().name()));
+                    for &child in frag.children() {
+                        out.push(' ');
+                        shape(child, out);
+                    }
+                    out.push(')');
+                }
+                FragHead::TemplateRef(idx) => out.push_str(["a", "b", "c"][idx]),
+                head => panic!("unexpected {head:?}"),
+            }
+        }
+
+        let project = TestProject::with_logic(&format!(
+            "module logic\n\n\
+             syntax plus\n    sentence (10<) ::= sentence \"+\" sentence\nend\n\n\
+             syntax minus\n    sentence (10<) ::= sentence \"-\" sentence\nend\n\n\
+             syntax times\n    sentence (20<) ::= sentence \"*\" sentence\nend\n\n\
+             axiom shape [a b c : sentence] :\n    |- {statement}\nend\n"
+        ));
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        assert!(!parsed.ctx().diags.has_errors());
+        let (_, report) = parsed.into_parts();
+        let (theorem, _) = (report.roots.iter())
+            .flat_map(|root| &root.theorems)
+            .find(|(theorem, _)| theorem.name() == "shape")
+            .unwrap();
+        let mut out = String::new();
+        shape(theorem.conclusion().frag(), &mut out);
+        out
+    }
+
+    #[test]
+    fn tighter_operators_group_first() {
+        assert_eq!(grouping("a + b * c"), "(plus a (times b c))");
+        assert_eq!(grouping("a * b + c"), "(plus (times a b) c)");
+    }
+
+    #[test]
+    fn left_associative_operators_group_from_the_left() {
+        assert_eq!(grouping("a - b - c"), "(minus (minus a b) c)");
+        assert_eq!(grouping("a - b + c"), "(plus (minus a b) c)");
+        assert_eq!(grouping("a - (b - c)"), "(minus a (minus b c))");
+    }
+
+    /// The titles of the errors for a tactic matching `pattern`.
+    fn tactic_errors(pattern: &str) -> Vec<&'static str> {
+        let project = TestProject::with_logic(&format!(
+            "module logic\n\ntactic repeat\n    tactic ::= {pattern}\nend\n"
+        ));
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        (parsed.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title())
+            .collect()
+    }
+
+    #[test]
+    fn many_of_a_part_which_can_be_empty_is_rejected() {
+        let empty = ["`@many` can't repeat a part which can be empty"];
+        assert!(tactic_errors("\"go\" @many(\"a\")").is_empty());
+        assert_eq!(tactic_errors("\"go\" @many(@many(\"a\"))"), empty);
+        assert_eq!(tactic_errors("\"go\" @many(@fragment(maybe_label))"), empty);
+    }
+
+    /// The notes of the warnings about prose which looks like a command in a
+    /// project with `main`.
+    fn misfire_notes(main: &str) -> Vec<&'static str> {
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        (parsed.ctx().diags.iter())
+            .filter(|diag| diag.title().ends_with("but was treated as prose"))
+            .flat_map(|diag| diag.parts().skip(1).map(|part| part.title()))
+            .collect()
+    }
+
+    #[test]
+    fn miscapitalized_command_is_reported() {
+        let notes = misfire_notes("module logic\n\nTheorem refl [p : sentence] : |- p → p\n");
+        assert_eq!(
+            notes.first(),
+            Some(&"commands are case-sensitive, so write `theorem` rather than `Theorem`")
+        );
+    }
+
+    #[test]
+    fn keyword_which_starts_no_command_is_reported() {
+        // `have` is only a keyword of a tactic declared in `logic`.
+        let notes = misfire_notes("module logic\n\nhave |- p → p by imp.intro\n");
+        assert_eq!(
+            notes.first(),
+            Some(&"`have` is a keyword, but no command starts with it here")
+        );
+
+        // Nothing has declared it yet before `logic` is imported.
+        assert_eq!(
+            misfire_notes("have |- p → p by imp.intro\n\nmodule logic\n"),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn prose_which_starts_like_a_command_is_not_reported() {
+        let prose = "module logic\n\n\
+            Theorem 3 is stated below.\n\
+            In theorem proving, |- is read as \"proves\".\n\
+            Have a look at the axioms before the end of the chapter.\n";
+        assert_eq!(misfire_notes(prose), Vec::<&str>::new());
+
+        let ignored = "module logic\n\nTheorem refl [p : sentence] : |- p → p -- watson: ignore\n";
+        assert_eq!(misfire_notes(ignored), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn proof_using_an_unknown_category_is_reported() {
+        let main = "module logic\n\n\
+            grammar_category calc_step\n\n\
+            theorem refl [p : sentence] :\n    |- p → p\nproof using calc_stp\nqed\n";
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let diags: Vec<Vec<_>> = (parsed.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.parts().map(|part| part.title()).collect())
+            .collect();
+        assert_eq!(
+            diags,
+            [[
+                "unknown grammar category `calc_stp`",
+                "did you mean `calc_step`?"
+            ]]
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use crate::parse::{SourceId, Span};
+use rustc_hash::FxHashMap;
+use std::path::{Path, PathBuf};
+
+/// Which sources load which with `module`. A source is parsed as soon as it
+/// is loaded, so the sources which are still being parsed are always the
+/// chain of imports that loaded the current one.
+#[derive(Debug, Default)]
+pub struct ModuleGraph {
+    imports: Vec<ModuleImport>,
+    /// The file each source was read from, for sources read from a file.
+    files: FxHashMap<SourceId, PathBuf>,
+    /// The source read from each file, keyed by its canonical path so two
+    /// names for the same file are caught.
+    sources_by_file: FxHashMap<PathBuf, SourceId>,
+}
+
+/// A `module` command in `importer` which imports `imported`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleImport {
+    pub importer: SourceId,
+    pub imported: SourceId,
+    /// The name of the module in the `module` command.
+    pub span: Span,
+    /// Whether this import loaded the module, rather than importing a module
+    /// or root which was already loaded.
+    pub loaded: bool,
+}
+
+impl ModuleGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, import: ModuleImport) {
+        self.imports.push(import);
+    }
+
+    /// Every import in the order the `module` commands were elaborated.
+    pub fn imports(&self) -> &[ModuleImport] {
+        &self.imports
+    }
+
+    /// The imports declared in `source`, in order.
+    pub fn imports_of(&self, source: SourceId) -> impl Iterator<Item = &ModuleImport> {
+        self.imports
+            .iter()
+            .filter(move |import| import.importer == source)
+    }
+
+    /// Record that `source` was read from `path`. If another source was
+    /// already read from the same file, returns that source instead.
+    pub fn add_file(&mut self, source: SourceId, path: &Path) -> Result<(), SourceId> {
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(&previous) = self.sources_by_file.get(&canonical)
+            && previous != source
+        {
+            return Err(previous);
+        }
+        self.sources_by_file.insert(canonical, source);
+        self.files.insert(source, path.to_path_buf());
+        Ok(())
+    }
+
+    /// The file `source` was read from, if it was read from one.
+    pub fn file_of(&self, source: SourceId) -> Option<&Path> {
+        self.files.get(&source).map(PathBuf::as_path)
+    }
+
+    /// The import which loaded `source`, or `None` if it is a root.
+    pub fn loaded_by(&self, source: SourceId) -> Option<&ModuleImport> {
+        self.imports
+            .iter()
+            .find(|import| import.loaded && import.imported == source)
+    }
+
+    /// The root whose parse loaded `source`.
+    pub fn root_of(&self, mut source: SourceId) -> SourceId {
+        while let Some(import) = self.loaded_by(source) {
+            source = import.importer;
+        }
+        source
+    }
+
+    /// If `importer` importing `imported` would make a cycle, the imports
+    /// which lead from `imported` back to `importer`. These are empty when a
+    /// source imports itself.
+    pub fn cycle(&self, importer: SourceId, imported: SourceId) -> Option<Vec<ModuleImport>> {
+        let mut chain = Vec::new();
+        let mut at = importer;
+        while at != imported {
+            let import = self.loaded_by(at)?;
+            chain.push(*import);
+            at = import.importer;
+        }
+        chain.reverse();
+        Some(chain)
+    }
+}
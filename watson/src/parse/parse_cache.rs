@@ -0,0 +1,221 @@
+use crate::{
+    context::Ctx,
+    parse::{
+        Location, SourceId,
+        parse_state::{ParseAtomPattern, Rule, RulePatternPart},
+        parse_tree::{CachedTree, ParseTreeId},
+    },
+};
+use rustc_hash::{FxHashMap, FxHasher};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    hash::{Hash, Hasher},
+    path::PathBuf,
+};
+
+/// Changed whenever the format of the cache file changes. Changes to the
+/// builtin grammar are caught by hashing its rules.
+const CACHE_VERSION: u32 = 3;
+
+/// The parse trees of the commands from the last run, so commands whose text
+/// hasn't changed can skip the Earley parse. They are still elaborated,
+/// which rebuilds everything else parsing produces, notes and warnings
+/// included. The Earley parse itself only reports errors, and commands with
+/// errors aren't cached, so a hit has no diagnostics of its own to replay.
+///
+/// Notation and syntax commands change how later commands parse, so a tree
+/// is only valid if every source entered before it is unchanged too. The
+/// cache keeps a hash of the text of every source entered so far, in order,
+/// and a tree is only reused if the hash matches the one it was saved with.
+pub struct ParseCache {
+    /// Where the cache is saved, or `None` if it is turned off.
+    path: Option<PathBuf>,
+    last_run: CacheFile,
+    this_run: CacheFile,
+    chain: u64,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// The commands of each source by the byte offset they start at.
+    sources: FxHashMap<String, FxHashMap<usize, CachedCommand>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCommand {
+    /// The hash of the sources entered before the command was parsed.
+    chain: u64,
+    tree: CachedTree,
+}
+
+impl ParseCache {
+    /// Load the trees saved by the last run if the config uses the cache.
+    /// A missing or unreadable cache is just empty.
+    pub fn load(ctx: &Ctx) -> Self {
+        let path = ctx
+            .config
+            .parse_cache()
+            .then(|| ctx.config.build_dir().join("cache").join("parse.json"));
+        let last_run = path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|text| serde_json::from_str::<CacheFile>(&text).ok())
+            .filter(|file| file.version == CACHE_VERSION)
+            .unwrap_or_default();
+
+        // Every source is parsed with the builtin grammar too, so changing
+        // it changes every chain.
+        let mut hasher = FxHasher::default();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        ctx.config.split_step_limit().hash(&mut hasher);
+        for rule in ctx.parse_state.rules() {
+            hash_rule(rule, &mut hasher);
+        }
+
+        Self {
+            path,
+            last_run,
+            this_run: CacheFile {
+                version: CACHE_VERSION,
+                sources: FxHashMap::default(),
+            },
+            chain: hasher.finish(),
+        }
+    }
+
+    /// Note that parsing has reached the start of `source`. Every command
+    /// parsed from now on may depend on its text.
+    pub fn enter_source(&mut self, source: SourceId, text: &str) {
+        let mut hasher = FxHasher::default();
+        self.chain.hash(&mut hasher);
+        source.name().as_str().hash(&mut hasher);
+        text.hash(&mut hasher);
+        self.chain = hasher.finish();
+    }
+
+    /// The tree of the command at `loc` from the last run, if nothing it
+    /// depends on has changed since.
+    pub fn get<'ctx>(&mut self, loc: Location, ctx: &Ctx<'ctx>) -> Option<ParseTreeId<'ctx>> {
+        let command = self
+            .last_run
+            .sources
+            .get_mut(loc.source().name().as_str())?
+            .remove(&loc.byte_offset())?;
+        if command.chain != self.chain {
+            return None;
+        }
+
+        let tree = ParseTreeId::from_cached(&command.tree, loc, ctx)?;
+        self.insert(loc, command);
+        Some(tree)
+    }
+
+    /// Save the tree of a command which was just parsed.
+    pub fn add<'ctx>(&mut self, tree: ParseTreeId<'ctx>, ctx: &Ctx<'ctx>) {
+        if self.path.is_none() {
+            return;
+        }
+
+        let command = CachedCommand {
+            chain: self.chain,
+            tree: tree.to_cached(ctx),
+        };
+        self.insert(tree.span().start(), command);
+    }
+
+    fn insert(&mut self, loc: Location, command: CachedCommand) {
+        self.this_run
+            .sources
+            .entry(loc.source().name().to_string())
+            .or_default()
+            .insert(loc.byte_offset(), command);
+    }
+
+    /// Write the trees of this run for the next one. Only the commands
+    /// parsed this run are kept, so the cache doesn't grow with old trees.
+    pub fn save(self) {
+        let Some(path) = self.path else {
+            return;
+        };
+
+        // The cache only saves time, so failing to write it isn't an error.
+        if let Some(dir) = path.parent() {
+            _ = fs::create_dir_all(dir);
+        }
+        if let Ok(text) = serde_json::to_string(&self.this_run) {
+            _ = fs::write(path, text);
+        }
+    }
+}
+
+/// Hash everything about a rule which decides the trees it builds. Categories
+/// are hashed by name since their ids differ from run to run.
+fn hash_rule(rule: &Rule, hasher: &mut FxHasher) {
+    rule.name().as_str().hash(hasher);
+    rule.cat().name().as_str().hash(hasher);
+
+    let pattern = rule.pattern();
+    pattern.precedence().hash(hasher);
+    pattern.associativity().hash(hasher);
+    for part in pattern.parts() {
+        match part {
+            RulePatternPart::Atom(atom) => {
+                0u8.hash(hasher);
+                match atom {
+                    ParseAtomPattern::Lit(lit) => (0u8, lit.as_str()).hash(hasher),
+                    ParseAtomPattern::Kw(kw) => (1u8, kw.as_str()).hash(hasher),
+                    ParseAtomPattern::Name => 2u8.hash(hasher),
+                    ParseAtomPattern::Str => 3u8.hash(hasher),
+                    ParseAtomPattern::Num => 4u8.hash(hasher),
+                }
+            }
+            RulePatternPart::Cat(cat) => (1u8, cat.name().as_str()).hash(hasher),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// `⋄ p ⋄` can be read with either notation outermost, so the preference
+    /// between them is noted when elaborating `diamonds`.
+    const MAIN: &str = "module logic\n\n\
+        notation post\n    sentence ::= sentence \"⋄\"\nend\n\n\
+        definition\n    p ⋄ := p → ⊥\nend\n\n\
+        notation pre\n    sentence ::= \"⋄\" sentence\nend\n\n\
+        definition\n    ⋄ p := ¬p\nend\n\n\
+        prefer post over pre\n\n\
+        axiom diamonds [p : sentence] :\n    |- ⋄ p ⋄\nend\n";
+
+    /// The titles of the diagnostics from parsing `project` with the cache.
+    fn cached_parse(project: &TestProject) -> Vec<String> {
+        let mut config = project.config();
+        config.set_parse_cache(true);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(config, &arenas).parse();
+        (parsed.ctx().diags.iter())
+            .map(|diag| diag.title().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn cached_commands_report_the_same_notes() {
+        let project = TestProject::with_logic(MAIN);
+        let first = cached_parse(&project);
+        assert!(project.path("build/cache/parse.json").exists());
+        assert!(
+            first
+                .iter()
+                .any(|title| title.starts_with("read using `post` rather than `pre`")),
+            "{first:?}"
+        );
+
+        // Only the Earley parse is skipped, and it doesn't report anything
+        // for a command it parses. The notes come from elaborating, which
+        // happens either way.
+        assert_eq!(cached_parse(&project), first);
+    }
+}
@@ -4,6 +4,7 @@ use crate::{
         custom_grammar::syntax::{CustomGrammarCatId, CustomGrammarRuleId},
         formal_syntax::FormalSyntaxCatId,
         notation::NotationPatternId,
+        provenance::Provenance,
     },
 };
 use rustc_hash::{FxHashMap, FxHashSet};
@@ -15,12 +16,21 @@ pub struct ParseState<'ctx> {
     categories_by_tactic_cat: FxHashMap<CustomGrammarCatId<'ctx>, CategoryId<'ctx>>,
 
     // Rule info
-    all_rules: FxHashSet<RuleId<'ctx>>,
+    /// Every rule in the order it was added, which is the same whenever the
+    /// same sources are parsed, so the parse cache can refer to rules by
+    /// their index.
+    all_rules: Vec<RuleId<'ctx>>,
+    rule_indices: FxHashMap<RuleId<'ctx>, usize>,
     rules_by_cat: FxHashMap<CategoryId<'ctx>, Vec<RuleId<'ctx>>>,
 
     // Grammar information
     can_be_empty: FxHashMap<CategoryId<'ctx>, bool>,
     initial_atoms: FxHashMap<CategoryId<'ctx>, FxHashSet<ParseAtomPattern>>,
+    /// The rules which mention each category in their pattern.
+    rules_using: FxHashMap<CategoryId<'ctx>, Vec<RuleId<'ctx>>>,
+    /// For each category, the categories whose initial atoms include its
+    /// initial atoms.
+    feeds: FxHashMap<CategoryId<'ctx>, FxHashSet<CategoryId<'ctx>>>,
 }
 
 impl<'ctx> ParseState<'ctx> {
@@ -28,10 +38,13 @@ impl<'ctx> ParseState<'ctx> {
         Self {
             categories_by_formal_cat: FxHashMap::default(),
             categories_by_tactic_cat: FxHashMap::default(),
-            all_rules: FxHashSet::default(),
+            all_rules: Vec::new(),
+            rule_indices: FxHashMap::default(),
             rules_by_cat: FxHashMap::default(),
             can_be_empty: FxHashMap::default(),
             initial_atoms: FxHashMap::default(),
+            rules_using: FxHashMap::default(),
+            feeds: FxHashMap::default(),
         }
     }
 
@@ -51,23 +64,129 @@ impl<'ctx> ParseState<'ctx> {
     }
 
     pub fn use_rule(&mut self, rule: RuleId<'ctx>) {
-        self.all_rules.insert(rule);
+        self.add_rule(rule);
+
+        // Recomputing everything for each rule is quadratic, so this only
+        // runs in tests. `bench_adding_rules` measures the difference.
+        #[cfg(test)]
+        assert!(
+            self.compute_from_scratch() == (self.can_be_empty.clone(), self.initial_atoms.clone()),
+            "incremental initial atoms diverged after adding `{}`",
+            rule.name()
+        );
+    }
+
+    fn add_rule(&mut self, rule: RuleId<'ctx>) {
+        self.rule_indices.insert(rule, self.all_rules.len());
+        self.all_rules.push(rule);
         self.rules_by_cat.get_mut(&rule.cat()).unwrap().push(rule);
+        for part in rule.pattern().parts() {
+            if let RulePatternPart::Cat(id) = part {
+                self.rules_using.entry(*id).or_default().push(rule);
+            }
+        }
+
+        self.propagate_rules(vec![rule]);
+    }
+
+    /// Update the grammar information to account for the given rules. Rules
+    /// only ever add to the initial atoms of a category and can only make a
+    /// category empty, so we only need to push their contribution forward.
+    fn propagate_rules(&mut self, mut rules: Vec<RuleId<'ctx>>) {
+        let mut changed_cats = Vec::new();
+
+        while let Some(rule) = rules.pop() {
+            let rule_cat = rule.cat();
+
+            if !self.can_be_empty[&rule_cat] && self.pattern_can_be_empty(rule.pattern().parts()) {
+                // Every rule which uses this category might now see further
+                // into its pattern.
+                self.can_be_empty.insert(rule_cat, true);
+                if let Some(users) = self.rules_using.get(&rule_cat) {
+                    rules.extend(users.iter().copied());
+                }
+            }
+
+            for part in rule.pattern().parts() {
+                match part {
+                    RulePatternPart::Atom(atom) => {
+                        if self.initial_atoms.get_mut(&rule_cat).unwrap().insert(*atom) {
+                            changed_cats.push(rule_cat);
+                        }
+                        break;
+                    }
+                    RulePatternPart::Cat(id) => {
+                        if *id != rule_cat {
+                            self.feeds.entry(*id).or_default().insert(rule_cat);
+                            if self.merge_initial_atoms(*id, rule_cat) {
+                                changed_cats.push(rule_cat);
+                            }
+                        }
+
+                        if !self.can_be_empty[id] {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Push any new atoms along to the categories which start with the
+        // changed ones.
+        while let Some(from) = changed_cats.pop() {
+            let Some(targets) = self.feeds.get(&from) else {
+                continue;
+            };
+            for to in targets.clone() {
+                if self.merge_initial_atoms(from, to) {
+                    changed_cats.push(to);
+                }
+            }
+        }
     }
 
-    pub fn recompute_initial_atoms(&mut self) {
+    /// Add the initial atoms of `from` to `to`. Returns whether `to` changed.
+    fn merge_initial_atoms(&mut self, from: CategoryId<'ctx>, to: CategoryId<'ctx>) -> bool {
+        let [from, to] = self.initial_atoms.get_disjoint_mut([&from, &to]);
+        let (from, to) = (from.unwrap(), to.unwrap());
+
+        let mut changed = false;
+        for &initial in from.iter() {
+            changed |= to.insert(initial);
+        }
+        changed
+    }
+
+    /// Compute the grammar information from scratch. Used to check the
+    /// incremental computation in tests.
+    #[cfg(test)]
+    #[allow(clippy::type_complexity)]
+    fn compute_from_scratch(
+        &self,
+    ) -> (
+        FxHashMap<CategoryId<'ctx>, bool>,
+        FxHashMap<CategoryId<'ctx>, FxHashSet<ParseAtomPattern>>,
+    ) {
+        let mut can_be_empty: FxHashMap<_, _> =
+            self.rules_by_cat.keys().map(|&cat| (cat, false)).collect();
+        let mut initial_atoms: FxHashMap<_, FxHashSet<_>> = self
+            .rules_by_cat
+            .keys()
+            .map(|&cat| (cat, FxHashSet::default()))
+            .collect();
+
         // The first step it to compute which categories have empty rules.
         let mut changed = true;
         while changed {
             changed = false;
             for rule in &self.all_rules {
-                if !self.can_be_empty[&rule.cat()]
+                if !can_be_empty[&rule.cat()]
                     && rule.pattern().parts().iter().all(|part| match part {
                         RulePatternPart::Atom(_) => false,
-                        RulePatternPart::Cat(id) => self.can_be_empty[id],
+                        RulePatternPart::Cat(id) => can_be_empty[id],
                     })
                 {
-                    self.can_be_empty.insert(rule.cat(), true);
+                    can_be_empty.insert(rule.cat(), true);
                     changed = true;
                 }
             }
@@ -81,20 +200,14 @@ impl<'ctx> ParseState<'ctx> {
                 for part in rule.0.pattern().parts() {
                     match part {
                         RulePatternPart::Atom(atom) => {
-                            if self
-                                .initial_atoms
-                                .get_mut(&rule.cat())
-                                .unwrap()
-                                .insert(*atom)
-                            {
+                            if initial_atoms.get_mut(&rule.cat()).unwrap().insert(*atom) {
                                 changed = true;
                             }
                             break;
                         }
                         RulePatternPart::Cat(id) => {
                             if *id != rule.cat() {
-                                let [from, to] =
-                                    self.initial_atoms.get_disjoint_mut([id, &rule.cat()]);
+                                let [from, to] = initial_atoms.get_disjoint_mut([id, &rule.cat()]);
                                 let from = from.unwrap();
                                 let to = to.unwrap();
 
@@ -105,7 +218,7 @@ impl<'ctx> ParseState<'ctx> {
                                 }
                             }
 
-                            if !self.can_be_empty[id] {
+                            if !can_be_empty[id] {
                                 break;
                             }
                         }
@@ -113,6 +226,16 @@ impl<'ctx> ParseState<'ctx> {
                 }
             }
         }
+
+        (can_be_empty, initial_atoms)
+    }
+
+    pub fn rules(&self) -> &[RuleId<'ctx>] {
+        &self.all_rules
+    }
+
+    pub fn rule_index(&self, rule: RuleId<'ctx>) -> usize {
+        self.rule_indices[&rule]
     }
 
     pub fn rules_for_cat(&self, cat: CategoryId<'ctx>) -> &[RuleId<'ctx>] {
@@ -131,9 +254,55 @@ impl<'ctx> ParseState<'ctx> {
         &self.initial_atoms[&cat]
     }
 
+    /// Whether some rule of the grammar, in any category, has the keyword
+    /// `kw`.
+    pub fn knows_keyword(&self, kw: &str) -> bool {
+        self.all_rules.iter().any(|rule| {
+            rule.pattern().parts().iter().any(|part| {
+                matches!(
+                    part,
+                    RulePatternPart::Atom(ParseAtomPattern::Kw(known)) if known.as_str() == kw
+                )
+            })
+        })
+    }
+
     pub fn can_be_empty(&self, cat: CategoryId<'ctx>) -> bool {
         self.can_be_empty[&cat]
     }
+
+    /// The atoms a rule with the given parts could start with, as if it were
+    /// added to the grammar.
+    pub fn pattern_initial_atoms(
+        &self,
+        parts: &[RulePatternPart<'ctx>],
+    ) -> FxHashSet<ParseAtomPattern> {
+        let mut atoms = FxHashSet::default();
+        for part in parts {
+            match part {
+                RulePatternPart::Atom(atom) => {
+                    atoms.insert(*atom);
+                    break;
+                }
+                RulePatternPart::Cat(id) => {
+                    atoms.extend(self.initial_atoms[id].iter().copied());
+                    if !self.can_be_empty[id] {
+                        break;
+                    }
+                }
+            }
+        }
+        atoms
+    }
+
+    /// Whether a rule with the given parts could match without consuming any
+    /// input.
+    pub fn pattern_can_be_empty(&self, parts: &[RulePatternPart<'ctx>]) -> bool {
+        parts.iter().all(|part| match part {
+            RulePatternPart::Atom(_) => false,
+            RulePatternPart::Cat(id) => self.can_be_empty[id],
+        })
+    }
 }
 
 generate_arena_handle!(CategoryId<'ctx> => Category<'ctx>);
@@ -151,7 +320,7 @@ impl<'ctx> Category<'ctx> {
         Self { name, source }
     }
 
-    pub fn _name(&self) -> Ustr {
+    pub fn name(&self) -> Ustr {
         self.name
     }
 
@@ -167,6 +336,17 @@ pub enum SyntaxCategorySource<'ctx> {
     User(CustomGrammarCatId<'ctx>),
 }
 
+impl<'ctx> SyntaxCategorySource<'ctx> {
+    /// Where the item this category was generated from was declared.
+    pub fn provenance(&self) -> Provenance {
+        match self {
+            SyntaxCategorySource::Builtin => Provenance::Builtin,
+            SyntaxCategorySource::FormalLang(cat) => cat.provenance(),
+            SyntaxCategorySource::User(cat) => cat.provenance(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Precedence(pub usize);
 
@@ -203,7 +383,7 @@ impl<'ctx> Rule<'ctx> {
             name: name.as_ref().into(),
             cat,
             source,
-            pattern,
+            pattern: pattern.with_operand_bounds(cat),
         }
     }
 
@@ -222,6 +402,23 @@ impl<'ctx> Rule<'ctx> {
     pub fn pattern(&self) -> &RulePattern<'ctx> {
         &self.pattern
     }
+
+    /// Whether `child` can be what the part at index `part` of this rule
+    /// matched. An operand at the start of the pattern only limits rules which
+    /// end with an operand, since those are the ones this rule's next part
+    /// could have taken the operand from, and likewise for the end.
+    pub fn admits(&self, part: usize, child: &Rule<'ctx>) -> bool {
+        let Some(min) = self.pattern.min_precedences[part] else {
+            return true;
+        };
+        let child_parts = child.pattern.parts();
+        let open_side = if part == 0 {
+            child_parts.last()
+        } else {
+            child_parts.first()
+        };
+        open_side != Some(&RulePatternPart::Cat(child.cat)) || child.pattern.precedence >= min
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -230,6 +427,8 @@ pub enum ParseRuleSource<'ctx> {
     AnyFrag(FormalSyntaxCatId<'ctx>),
     Notation(NotationPatternId<'ctx>),
     TacticRule(CustomGrammarRuleId<'ctx>),
+    /// The `using` clause choosing the tactic category a proof is in.
+    ProofCategory(CustomGrammarCatId<'ctx>),
 }
 
 impl<'ctx> ParseRuleSource<'ctx> {
@@ -246,6 +445,16 @@ impl<'ctx> ParseRuleSource<'ctx> {
             _ => panic!("ParseRuleSource is not TacticRule"),
         }
     }
+
+    /// Where the item this rule was generated from was declared.
+    pub fn provenance(&self) -> Provenance {
+        match self {
+            ParseRuleSource::Builtin | ParseRuleSource::AnyFrag(_) => Provenance::Builtin,
+            ParseRuleSource::Notation(notation) => notation.provenance(),
+            ParseRuleSource::TacticRule(rule) => rule.provenance(),
+            ParseRuleSource::ProofCategory(cat) => cat.provenance(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -253,6 +462,10 @@ pub struct RulePattern<'ctx> {
     parts: Vec<RulePatternPart<'ctx>>,
     precedence: Precedence,
     associativity: Associativity,
+    /// The least precedence of the rule each part can match, for the operands
+    /// of the rule's own category at either end of the pattern. Operands
+    /// elsewhere are delimited by the parts around them so any rule will do.
+    min_precedences: Vec<Option<Precedence>>,
 }
 
 impl<'ctx> RulePattern<'ctx> {
@@ -262,12 +475,41 @@ impl<'ctx> RulePattern<'ctx> {
         associativity: Associativity,
     ) -> Self {
         Self {
+            min_precedences: vec![None; parts.len()],
             parts,
             precedence,
             associativity,
         }
     }
 
+    /// Bound the operands at the ends of the pattern, as in precedence
+    /// climbing. An operand on the side the rule associates to can be a rule
+    /// of the same precedence while the other must bind tighter. Both
+    /// operands of a non-associative rule can have the same precedence, so
+    /// a chain of them is still reported as ambiguous.
+    fn with_operand_bounds(mut self, cat: CategoryId<'ctx>) -> Self {
+        let len = self.parts.len();
+        for (i, part) in self.parts.iter().enumerate() {
+            let at_start = i == 0;
+            let at_end = i + 1 == len;
+            if at_start == at_end || *part != RulePatternPart::Cat(cat) {
+                continue;
+            }
+
+            let same_level = match self.associativity {
+                Associativity::Left => at_start,
+                Associativity::Right => at_end,
+                Associativity::NonAssoc => true,
+            };
+            self.min_precedences[i] = Some(if same_level {
+                self.precedence
+            } else {
+                Precedence(self.precedence.0 + 1)
+            });
+        }
+        self
+    }
+
     pub fn parts(&self) -> &[RulePatternPart<'ctx>] {
         &self.parts
     }
@@ -295,3 +537,137 @@ pub enum ParseAtomPattern {
     Str,
     Num,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Arenas;
+    use std::{hint::black_box, time::Instant};
+
+    fn cat<'ctx>(name: &str, arenas: &'ctx Arenas<'ctx>) -> CategoryId<'ctx> {
+        let category = Category::new(Ustr::from(name), SyntaxCategorySource::Builtin);
+        arenas.parse_cats.alloc(Ustr::from(name), category)
+    }
+
+    fn rule<'ctx>(
+        cat: CategoryId<'ctx>,
+        parts: Vec<RulePatternPart<'ctx>>,
+        arenas: &'ctx Arenas<'ctx>,
+    ) -> RuleId<'ctx> {
+        let pattern = RulePattern::new(parts, Precedence::default(), Associativity::default());
+        let rule = Rule::new(cat.name(), cat, ParseRuleSource::Builtin, pattern);
+        arenas.parse_rules.alloc(rule)
+    }
+
+    fn lit<'ctx>(text: &str) -> RulePatternPart<'ctx> {
+        RulePatternPart::Atom(ParseAtomPattern::Lit(Ustr::from(text)))
+    }
+
+    /// `count` rules shaped like notations for a `sentence` category, which
+    /// starts with a `term`. Half are prefix operators and half are infix.
+    fn synthetic_rules<'ctx>(
+        count: usize,
+        arenas: &'ctx Arenas<'ctx>,
+    ) -> ([CategoryId<'ctx>; 2], Vec<RuleId<'ctx>>) {
+        let [sentence, term] = [cat("sentence", arenas), cat("term", arenas)];
+        let mut rules = vec![
+            rule(sentence, vec![RulePatternPart::Cat(term)], arenas),
+            rule(term, vec![lit("x")], arenas),
+        ];
+        for i in 0..count {
+            let op = lit(&format!("op{}", i % 20));
+            let operand = RulePatternPart::Cat(sentence);
+            let parts = match i % 2 {
+                0 => vec![op, operand],
+                _ => vec![operand, op, operand],
+            };
+            rules.push(rule(sentence, parts, arenas));
+        }
+        ([sentence, term], rules)
+    }
+
+    #[test]
+    fn initial_atoms_follow_categories_which_start_rules() {
+        let arenas = Arenas::new();
+        let ([sentence, term], rules) = synthetic_rules(40, &arenas);
+        let mut state = ParseState::new();
+        state.use_cat(sentence);
+        state.use_cat(term);
+        for rule in rules {
+            state.use_rule(rule);
+        }
+
+        assert_eq!(state.initial_atoms(term).len(), 1);
+        // `x` from `term`, and the ten distinct prefix operators.
+        assert_eq!(state.initial_atoms(sentence).len(), 11);
+        assert!(!state.can_be_empty(sentence));
+    }
+
+    #[test]
+    fn empty_rules_let_later_parts_start_a_category() {
+        let arenas = Arenas::new();
+        let [sentence, term] = [cat("sentence", &arenas), cat("term", &arenas)];
+        let mut state = ParseState::new();
+        state.use_cat(sentence);
+        state.use_cat(term);
+
+        state.use_rule(rule(
+            sentence,
+            vec![RulePatternPart::Cat(term), lit("+")],
+            &arenas,
+        ));
+        state.use_rule(rule(term, vec![lit("x")], &arenas));
+        assert!(
+            !state
+                .initial_atoms(sentence)
+                .contains(&ParseAtomPattern::Lit("+".into()))
+        );
+
+        // Once `term` can be empty `sentence` can start with what follows it.
+        state.use_rule(rule(term, vec![], &arenas));
+        assert!(state.can_be_empty(term));
+        assert!(!state.can_be_empty(sentence));
+        assert!(
+            state
+                .initial_atoms(sentence)
+                .contains(&ParseAtomPattern::Lit("+".into()))
+        );
+    }
+
+    /// Compare adding rules to recomputing the grammar information from
+    /// scratch after each one, as was done before it was incremental. Run
+    /// with `cargo test --release bench_adding_rules -- --ignored --nocapture`.
+    #[test]
+    #[ignore = "benchmark"]
+    fn bench_adding_rules() {
+        let arenas = Arenas::new();
+        let ([sentence, term], rules) = synthetic_rules(5000, &arenas);
+        let fresh_state = || {
+            let mut state = ParseState::new();
+            state.use_cat(sentence);
+            state.use_cat(term);
+            state
+        };
+
+        let start = Instant::now();
+        let mut state = fresh_state();
+        for &rule in &rules {
+            state.add_rule(rule);
+        }
+        let incremental = start.elapsed();
+
+        let start = Instant::now();
+        let mut state = fresh_state();
+        for &rule in &rules {
+            state.add_rule(rule);
+            black_box(state.compute_from_scratch());
+        }
+        let from_scratch = start.elapsed();
+
+        println!(
+            "{} rules: incremental {incremental:?}, recomputed {from_scratch:?}",
+            rules.len()
+        );
+        assert!(incremental < from_scratch);
+    }
+}
@@ -1,45 +1,366 @@
 use crate::{
+    context::{Ctx, arena::InternedArena},
     generate_arena_handle,
     parse::{
-        Span,
+        Location, Span,
+        location::SourceOffset,
         parse_state::{CategoryId, RuleId},
     },
 };
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
 use std::hash::Hash;
 use ustr::Ustr;
 
-generate_arena_handle!(ParseTreeId<'ctx> => ParseTree<'ctx>);
+generate_arena_handle!(ParseTreeShapeId<'ctx> => ParseTreeShape<'ctx>);
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub struct ParseTree<'ctx> {
-    span: Span,
-    cat: CategoryId<'ctx>,
-    possibilities: Vec<ParseTreeChildren<'ctx>>,
+/// A parse tree at a location in a source. The forest only interns the shape
+/// of each tree, with its spans relative to where it starts, so identical
+/// trees at different locations, like every use of the same name, share
+/// their nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParseTreeId<'ctx> {
+    shape: ParseTreeShapeId<'ctx>,
+    start: Location,
 }
 
-impl<'ctx> ParseTree<'ctx> {
+impl<'ctx> ParseTreeId<'ctx> {
     pub fn new(
         span: Span,
         cat: CategoryId<'ctx>,
         possibilities: Vec<ParseTreeChildren<'ctx>>,
+        forest: &'ctx InternedArena<ParseTreeShape<'ctx>, ParseTreeShapeId<'ctx>>,
     ) -> Self {
-        Self {
-            span,
+        let start = span.start();
+        let possibilities = possibilities
+            .into_iter()
+            .map(|possibility| ShapeChildren {
+                rule: possibility.rule,
+                children: possibility
+                    .children
+                    .into_iter()
+                    .map(|child| ShapePart::new(child, start))
+                    .collect(),
+            })
+            .collect();
+        let shape = ParseTreeShape {
+            len: span.bytes().len(),
             cat,
             possibilities,
+        };
+
+        Self {
+            shape: forest.intern(shape),
+            start,
         }
     }
 
     pub fn span(&self) -> Span {
-        self.span
+        let end = Location::new(
+            self.start.source(),
+            self.start.offset().forward(self.shape.len),
+        );
+        Span::new(self.start, end)
     }
 
     pub fn cat(&self) -> CategoryId<'ctx> {
-        self.cat
+        self.shape.cat
+    }
+
+    /// The possibilities, each rebuilt with spans in the source only when
+    /// the iterator reaches it, so looking at the first doesn't pay for the
+    /// rest.
+    pub fn possibilities(
+        &self,
+    ) -> impl ExactSizeIterator<Item = ParseTreeChildren<'ctx>> + use<'ctx> {
+        let start = self.start;
+        self.shape
+            .0
+            .possibilities
+            .iter()
+            .map(move |possibility| ParseTreeChildren {
+                rule: possibility.rule,
+                children: possibility
+                    .children
+                    .iter()
+                    .map(|child| child.at(start))
+                    .collect(),
+            })
+    }
+
+    /// The tree in a form which can be saved for a later run.
+    pub fn to_cached(self, ctx: &Ctx<'ctx>) -> CachedTree {
+        let mut cached = CachedTree { shapes: Vec::new() };
+        cached.add_shape(self.shape, &mut FxHashMap::default(), ctx);
+        cached
+    }
+
+    /// Rebuild a tree saved by [`Self::to_cached`] so it starts at `start`,
+    /// or return `None` if it uses rules or categories the parser doesn't
+    /// have.
+    pub fn from_cached(cached: &CachedTree, start: Location, ctx: &Ctx<'ctx>) -> Option<Self> {
+        let mut shapes: Vec<ParseTreeShapeId<'ctx>> = Vec::with_capacity(cached.shapes.len());
+        for shape in &cached.shapes {
+            let cat = ctx.arenas.parse_cats.get(Ustr::from(&shape.cat))?;
+            let possibilities = shape
+                .possibilities
+                .iter()
+                .map(|possibility| {
+                    let rule = *ctx.parse_state.rules().get(possibility.rule)?;
+                    if rule.name().as_str() != possibility.rule_name || rule.cat() != cat {
+                        return None;
+                    }
+                    let children = possibility
+                        .children
+                        .iter()
+                        .map(|child| match child {
+                            CachedPart::Atom {
+                                full_span,
+                                span,
+                                kind,
+                            } => Some(ShapePart::Atom {
+                                full_span: *full_span,
+                                span: *span,
+                                kind: kind.to_kind(),
+                            }),
+                            CachedPart::Node { shape, offset } => Some(ShapePart::Node {
+                                shape: *shapes.get(*shape)?,
+                                offset: *offset,
+                            }),
+                        })
+                        .collect::<Option<_>>()?;
+                    Some(ShapeChildren { rule, children })
+                })
+                .collect::<Option<_>>()?;
+
+            let shape = ParseTreeShape {
+                len: shape.len,
+                cat,
+                possibilities,
+            };
+            shapes.push(ctx.arenas.parse_forest.intern(shape));
+        }
+
+        Some(Self {
+            shape: *shapes.last()?,
+            start,
+        })
+    }
+}
+
+/// A parse tree which can be saved between runs. Its shapes are listed
+/// children first, so each only refers to earlier ones, and the last is the
+/// tree itself. Rules are given by their index in the parse state, which is
+/// only the same in a later run if everything parsed before is too, along
+/// with their name as a check.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedTree {
+    shapes: Vec<CachedShape>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedShape {
+    len: usize,
+    cat: String,
+    possibilities: Vec<CachedChildren>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedChildren {
+    rule: usize,
+    rule_name: String,
+    children: Vec<CachedPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum CachedPart {
+    Atom {
+        full_span: RelativeSpan,
+        span: RelativeSpan,
+        kind: CachedAtomKind,
+    },
+    Node {
+        shape: usize,
+        offset: isize,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum CachedAtomKind {
+    Lit(String),
+    Kw(String),
+    Name(String),
+    StrLit(String),
+    Num(usize),
+}
+
+impl CachedTree {
+    /// Add `shape` and the shapes below it which aren't already listed,
+    /// returning its index.
+    fn add_shape<'ctx>(
+        &mut self,
+        shape: ParseTreeShapeId<'ctx>,
+        indices: &mut FxHashMap<ParseTreeShapeId<'ctx>, usize>,
+        ctx: &Ctx<'ctx>,
+    ) -> usize {
+        if let Some(&index) = indices.get(&shape) {
+            return index;
+        }
+
+        let possibilities = shape
+            .possibilities
+            .iter()
+            .map(|possibility| CachedChildren {
+                rule: ctx.parse_state.rule_index(possibility.rule),
+                rule_name: possibility.rule.name().to_string(),
+                children: possibility
+                    .children
+                    .iter()
+                    .map(|child| match *child {
+                        ShapePart::Atom {
+                            full_span,
+                            span,
+                            kind,
+                        } => CachedPart::Atom {
+                            full_span,
+                            span,
+                            kind: CachedAtomKind::new(kind),
+                        },
+                        ShapePart::Node { shape, offset } => CachedPart::Node {
+                            shape: self.add_shape(shape, indices, ctx),
+                            offset,
+                        },
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        let index = self.shapes.len();
+        self.shapes.push(CachedShape {
+            len: shape.len,
+            cat: shape.cat.name().to_string(),
+            possibilities,
+        });
+        indices.insert(shape, index);
+        index
+    }
+}
+
+impl CachedAtomKind {
+    fn new(kind: ParseAtomKind) -> Self {
+        match kind {
+            ParseAtomKind::Lit(text) => Self::Lit(text.to_string()),
+            ParseAtomKind::Kw(text) => Self::Kw(text.to_string()),
+            ParseAtomKind::Name(text) => Self::Name(text.to_string()),
+            ParseAtomKind::StrLit(text) => Self::StrLit(text.to_string()),
+            ParseAtomKind::Num(num) => Self::Num(num),
+        }
+    }
+
+    fn to_kind(&self) -> ParseAtomKind {
+        match self {
+            Self::Lit(text) => ParseAtomKind::Lit(Ustr::from(text)),
+            Self::Kw(text) => ParseAtomKind::Kw(Ustr::from(text)),
+            Self::Name(text) => ParseAtomKind::Name(Ustr::from(text)),
+            Self::StrLit(text) => ParseAtomKind::StrLit(Ustr::from(text)),
+            Self::Num(num) => ParseAtomKind::Num(*num),
+        }
+    }
+}
+
+/// The contents of a parse tree without its location. Spans are stored as
+/// offsets from the start of the tree.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ParseTreeShape<'ctx> {
+    len: usize,
+    cat: CategoryId<'ctx>,
+    possibilities: Vec<ShapeChildren<'ctx>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ShapeChildren<'ctx> {
+    rule: RuleId<'ctx>,
+    children: Vec<ShapePart<'ctx>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ShapePart<'ctx> {
+    Atom {
+        full_span: RelativeSpan,
+        span: RelativeSpan,
+        kind: ParseAtomKind,
+    },
+    /// An empty child is left where it ends, which can be in whitespace
+    /// before the start of its parent, so its offset can be negative.
+    Node {
+        shape: ParseTreeShapeId<'ctx>,
+        offset: isize,
+    },
+}
+
+impl<'ctx> ShapePart<'ctx> {
+    fn new(part: ParseTreePart<'ctx>, start: Location) -> Self {
+        match part {
+            ParseTreePart::Atom(atom) => Self::Atom {
+                full_span: RelativeSpan::new(atom.full_span, start),
+                span: RelativeSpan::new(atom.span, start),
+                kind: atom.kind,
+            },
+            ParseTreePart::Node { id, .. } => Self::Node {
+                shape: id.shape,
+                offset: id.start.byte_offset() as isize - start.byte_offset() as isize,
+            },
+        }
+    }
+
+    fn at(&self, start: Location) -> ParseTreePart<'ctx> {
+        match *self {
+            Self::Atom {
+                full_span,
+                span,
+                kind,
+            } => ParseTreePart::Atom(ParseAtom::new(full_span.at(start), span.at(start), kind)),
+            Self::Node { shape, offset } => {
+                let id = ParseTreeId {
+                    shape,
+                    start: Location::new(
+                        start.source(),
+                        SourceOffset::new(start.byte_offset().checked_add_signed(offset).unwrap()),
+                    ),
+                };
+                ParseTreePart::Node {
+                    id,
+                    span: id.span(),
+                    cat: shape.cat,
+                }
+            }
+        }
+    }
+}
+
+/// A span given by its offset from the start of the tree containing it. The
+/// whitespace before a tree belongs to its first atom, so the offset can be
+/// negative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct RelativeSpan {
+    offset: isize,
+    len: usize,
+}
+
+impl RelativeSpan {
+    fn new(span: Span, start: Location) -> Self {
+        Self {
+            offset: span.start().byte_offset() as isize - start.byte_offset() as isize,
+            len: span.bytes().len(),
+        }
     }
 
-    pub fn possibilities(&self) -> &[ParseTreeChildren<'ctx>] {
-        &self.possibilities
+    fn at(self, start: Location) -> Span {
+        let span_start = start.byte_offset().checked_add_signed(self.offset).unwrap();
+        Span::new(
+            Location::new(start.source(), SourceOffset::new(span_start)),
+            Location::new(start.source(), SourceOffset::new(span_start + self.len)),
+        )
     }
 }
 
@@ -177,3 +498,122 @@ pub enum ParseAtomKind {
     StrLit(Ustr),
     Num(usize),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::Arenas,
+        driver::Driver,
+        parse::{ParseEntry, ParseReport},
+        util::test_project::TestProject,
+    };
+
+    /// A theorem whose name has the same length whatever `name` is, so the
+    /// only difference between two of them is the name.
+    fn theorem(name: char) -> String {
+        format!("\ntheorem t{name} [p q : sentence] : (p → q) (p)\n    |- q\nproof\nqed\n")
+    }
+
+    fn main_with(names: &str) -> String {
+        let theorems: String = names.chars().map(theorem).collect();
+        format!("module logic\n{theorems}")
+    }
+
+    /// Run `test` on the parse of a project whose `main.wats` is `main`.
+    fn with_parse(main: &str, test: impl for<'ctx> FnOnce(&Ctx<'ctx>, &ParseReport<'ctx>)) {
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let (ctx, report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        assert!(!ctx.diags.has_errors());
+        test(&ctx, &report);
+    }
+
+    fn shape_count(main: &str) -> usize {
+        let mut count = 0;
+        with_parse(main, |ctx, _| count = ctx.arenas.parse_forest.len());
+        count
+    }
+
+    /// The trees of the theorems in the source with the text `main`.
+    fn main_commands<'ctx>(ctx: &Ctx<'ctx>, report: &ParseReport<'ctx>) -> Vec<ParseTreeId<'ctx>> {
+        (report.entries.iter())
+            .filter_map(|entry| match entry {
+                ParseEntry::Command(tree, _) => Some(*tree),
+                _ => None,
+            })
+            .filter(|tree| {
+                let text = ctx.sources.get_text(tree.span().source());
+                text.contains("theorem ta") && text[tree.span().bytes()].starts_with("theorem")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn identical_trees_share_shapes() {
+        let base = shape_count(&main_with(""));
+        let one = shape_count(&main_with("a"));
+        let two = shape_count(&main_with("ab"));
+        let three = shape_count(&main_with("abc"));
+
+        // Each copy only adds the shapes which contain its name.
+        let first = one - base;
+        let copy = two - one;
+        assert_eq!(three - two, copy);
+        assert!(
+            copy < first,
+            "a copy added {copy} shapes, the first {first}"
+        );
+    }
+
+    #[test]
+    fn spans_are_in_the_source() {
+        fn check_spans(tree: ParseTreeId, text: &str) {
+            let span = tree.span();
+            let within = |part: Span| {
+                span.start().byte_offset() <= part.start().byte_offset()
+                    && part.end().byte_offset() <= span.end().byte_offset()
+            };
+            for possibility in tree.possibilities() {
+                for child in possibility.children() {
+                    match child {
+                        ParseTreePart::Atom(atom) => {
+                            assert!(within(atom.span()));
+                            assert!(!text[atom.span().bytes()].trim().is_empty());
+                        }
+                        ParseTreePart::Node { id, span, .. } => {
+                            assert_eq!(id.span(), *span);
+                            assert!(within(*span));
+                            check_spans(*id, text);
+                        }
+                    }
+                }
+            }
+        }
+
+        with_parse(&main_with("ab"), |ctx, report| {
+            let commands = main_commands(ctx, report);
+            let [a, b] = commands[..] else {
+                panic!("expected two theorems, found {}", commands.len());
+            };
+            let text = ctx.sources.get_text(a.span().source()).as_str();
+            assert_eq!(text[a.span().bytes()].trim(), theorem('a').trim());
+            assert_eq!(text[b.span().bytes()].trim(), theorem('b').trim());
+            check_spans(a, text);
+            check_spans(b, text);
+        });
+    }
+
+    #[test]
+    fn cached_trees_come_back_the_same() {
+        with_parse(&main_with("ab"), |ctx, report| {
+            for tree in main_commands(ctx, report) {
+                let cached = tree.to_cached(ctx);
+                let restored = ParseTreeId::from_cached(&cached, tree.span().start(), ctx);
+                assert_eq!(restored, Some(tree));
+            }
+        });
+    }
+}
@@ -0,0 +1,111 @@
+use crate::{
+    diagnostics::{Diagnostic, DiagnosticSpan, WResult},
+    parse::{SourceCache, SourceId, Span},
+    util::diff::{DiffOptions, LineDiff},
+};
+use rustc_hash::FxHashMap;
+use ustr::Ustr;
+
+/// A set of replacements of exact spans in the sources. Everything outside
+/// of the replaced spans is left byte for byte as it was, so this is what
+/// refactorings use to edit the user's files.
+#[derive(Debug, Default)]
+pub struct Rewrites {
+    by_source: FxHashMap<SourceId, Vec<(Span, String)>>,
+}
+
+impl Rewrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the text in `span` with `text`.
+    pub fn replace(&mut self, span: Span, text: &str) {
+        self.by_source
+            .entry(span.source())
+            .or_default()
+            .push((span, text.to_string()));
+    }
+
+    /// The number of replacements.
+    pub fn replacement_count(&self) -> usize {
+        self.by_source.values().map(Vec::len).sum()
+    }
+
+    /// Apply the replacements, giving the new text of each source which
+    /// changed. Replacements of the same span with the same text are merged,
+    /// but any other overlap is an error since there is no way to apply both.
+    pub fn apply<'ctx>(mut self, sources: &SourceCache) -> WResult<'ctx, Vec<RewrittenSource>> {
+        let mut rewritten = Vec::new();
+
+        for (source, mut replacements) in self.by_source.drain() {
+            replacements
+                .sort_by_key(|(span, _)| (span.start().byte_offset(), span.end().byte_offset()));
+            replacements.dedup();
+
+            for pair in replacements.windows(2) {
+                let [(first, _), (second, _)] = pair else {
+                    unreachable!()
+                };
+                if second.start().byte_offset() < first.end().byte_offset() {
+                    return Diagnostic::err_overlapping_rewrites(*first, *second);
+                }
+            }
+
+            let old_text = sources.get_text(source);
+            let mut new_text = String::with_capacity(old_text.len());
+            let mut at = 0;
+            for (span, text) in &replacements {
+                new_text.push_str(&old_text[at..span.start().byte_offset()]);
+                new_text.push_str(text);
+                at = span.end().byte_offset();
+            }
+            new_text.push_str(&old_text[at..]);
+
+            rewritten.push(RewrittenSource {
+                source,
+                old_text,
+                new_text,
+            });
+        }
+
+        rewritten.sort_by_key(|r| r.source.name());
+        Ok(rewritten)
+    }
+}
+
+#[derive(Debug)]
+pub struct RewrittenSource {
+    source: SourceId,
+    old_text: Ustr,
+    new_text: String,
+}
+
+impl RewrittenSource {
+    pub fn source(&self) -> SourceId {
+        self.source
+    }
+
+    pub fn new_text(&self) -> &str {
+        &self.new_text
+    }
+
+    /// A unified diff of the change.
+    pub fn unified_diff(&self, path: &str, options: &DiffOptions) -> String {
+        LineDiff::new(&self.old_text, &self.new_text, options)
+            .render(&format!("a/{path}"), &format!("b/{path}"))
+    }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_overlapping_rewrites<T>(first: Span, second: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            "can't rewrite overlapping parts of a source",
+            vec![
+                DiagnosticSpan::new_error("first rewrite", first),
+                DiagnosticSpan::new_error("overlaps this rewrite", second),
+            ],
+        );
+        Err(vec![diag])
+    }
+}
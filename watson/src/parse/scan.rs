@@ -0,0 +1,182 @@
+use crate::{
+    context::Ctx,
+    diagnostics::Diagnostic,
+    parse::{
+        Location, ParseEntry, SourceId, Span, can_start_command,
+        earley::{parse_name, parse_str, skip_ws_and_comments},
+        module_graph::ModuleImport,
+        next_line, push_text, skip_code_fence,
+        source_cache::{SourceDecl, read_source_file, source_id_to_path},
+        source_files::similar_sources,
+    },
+    strings,
+};
+
+/// Find the prose and commands in each of the roots without parsing or
+/// elaborating the commands. This is much faster than [`super::parse`] but
+/// only knows where each command starts and ends, which is enough to preview
+/// the prose of the book.
+///
+/// Commands are found with the same line-based check the parser uses. A
+/// command then extends to the `end` or `qed` which closes it, or to the end
+/// of its line for single line commands like `module`. Custom syntax which
+/// contains those keywords outside of a string can end a command early.
+pub fn scan<'ctx>(roots: &[SourceId], ctx: &mut Ctx<'ctx>) -> Vec<ParseEntry<'ctx>> {
+    let mut entries = Vec::new();
+
+    for &root in roots {
+        let mut sources_stack = vec![root.start_loc()];
+        while let Some(next) = sources_stack.pop() {
+            scan_source(next, ctx, &mut sources_stack, &mut entries);
+        }
+    }
+
+    entries
+}
+
+fn scan_source<'ctx>(
+    loc: Location,
+    ctx: &mut Ctx<'ctx>,
+    sources_stack: &mut Vec<Location>,
+    entries: &mut Vec<ParseEntry<'ctx>>,
+) {
+    let text = ctx.sources.get_text(loc.source()).as_str();
+
+    if loc.byte_offset() >= text.len() {
+        return;
+    }
+
+    let fence_end = skip_code_fence(text, loc);
+    if fence_end.is_some() || !can_start_command(text, loc, ctx) {
+        // Same as the parser, code blocks are text and consecutive lines of
+        // text are merged.
+        let next_loc = fence_end.unwrap_or_else(|| next_line(text, loc));
+        sources_stack.push(next_loc);
+        push_text(entries, Span::new(loc, next_loc));
+        return;
+    }
+
+    let (command_end, module) = find_command_end(text, loc);
+    let span = Span::new(loc, command_end);
+    entries.push(ParseEntry::RawCommand(span));
+    sources_stack.push(command_end);
+
+    if let Some((name, name_span)) = module {
+        // Load the module so its prose is included. Pushing it after the
+        // rest of this source means it is scanned first, like in the parser.
+        let source_id = SourceId::new(name.into());
+        let import = ModuleImport {
+            importer: loc.source(),
+            imported: source_id,
+            span: name_span,
+            loaded: false,
+        };
+        if ctx.sources.has_source(source_id) {
+            ctx.module_graph.add(import);
+            return;
+        }
+
+        let Some((path, module_text)) = read_source_file(source_id, ctx.config.math_dir()) else {
+            let (path1, path2) = source_id_to_path(source_id, ctx.config.math_dir());
+            let similar = similar_sources(source_id, ctx.config.math_dir());
+            let diags =
+                Diagnostic::err_non_existent_file::<()>(&path1, &path2, Some(name_span), &similar)
+                    .unwrap_err();
+            ctx.diags.add_diags(diags);
+            return;
+        };
+        // The file was already scanned under another name.
+        if ctx.module_graph.add_file(source_id, &path).is_err() {
+            ctx.module_graph.add(import);
+            return;
+        }
+
+        ctx.sources
+            .add(source_id, module_text, SourceDecl::Module(name_span));
+        ctx.module_graph.add(ModuleImport {
+            loaded: true,
+            ..import
+        });
+        sources_stack.push(source_id.start_loc());
+    }
+}
+
+/// Find where the command starting at `loc` ends. If the command is a module
+/// declaration, also return the name of the module and where it is.
+fn find_command_end(text: &str, loc: Location) -> (Location, Option<(&str, Span)>) {
+    let source = loc.source();
+    let at_loc = |offset| Location::new(source, offset);
+    let mut at = loc.offset();
+
+    // Skip the attribute annotation if there is one. It only contains names
+    // so it can't contain the closing bracket.
+    if text[at.byte_offset()..].starts_with('@')
+        && let Some(close) = text[at.byte_offset()..].find(']')
+    {
+        at = at.forward(close + 1);
+    }
+
+    let mut first_kw = None;
+    loop {
+        at = skip_ws_and_comments(text, at);
+        if at.byte_offset() >= text.len() {
+            return (at_loc(at), None);
+        }
+
+        if let Some((after, _)) = parse_str(text, at) {
+            at = after;
+            continue;
+        }
+
+        let Some((after, name)) = parse_name(text, at) else {
+            let next_char = text[at.byte_offset()..].chars().next().unwrap();
+            at = at.forward(next_char.len_utf8());
+            continue;
+        };
+        at = after;
+
+        let Some(kw) = first_kw else {
+            // The first name is the keyword which starts the command.
+            if name == *strings::MODULE {
+                let name_start = skip_ws_and_comments(text, at);
+                return match parse_name(text, name_start) {
+                    Some((name_end, module)) => {
+                        let name_span = Span::new(at_loc(name_start), at_loc(name_end));
+                        (at_loc(name_end), Some((module, name_span)))
+                    }
+                    None => (next_line(text, at_loc(at)), None),
+                };
+            }
+
+            let single_line = [
+                *strings::SYNTAX_CAT,
+                *strings::PREFER,
+                *strings::GRAMMAR_CATEGORY,
+                *strings::SCOPE,
+                *strings::END,
+            ];
+            if single_line.iter().any(|kw| name == kw.as_str()) {
+                return (next_line(text, at_loc(at)), None);
+            }
+
+            first_kw = Some(name);
+            continue;
+        };
+
+        let theorem_kws = [
+            *strings::THEOREM,
+            *strings::LEMMA,
+            *strings::PROPOSITION,
+            *strings::COROLLARY,
+        ];
+        let terminator = if theorem_kws.iter().any(|t| kw == t.as_str()) {
+            *strings::QED
+        } else {
+            *strings::END
+        };
+
+        if name == terminator.as_str() {
+            return (at_loc(at), None);
+        }
+    }
+}
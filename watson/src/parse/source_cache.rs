@@ -4,6 +4,7 @@ use crate::{
 };
 use rustc_hash::FxHashMap;
 use std::{
+    fs,
     path::{Path, PathBuf},
     sync::{OnceLock, RwLock},
 };
@@ -27,6 +28,10 @@ struct SourceInfo {
 pub enum SourceDecl {
     Root,
     LuaSnippet,
+    BookExample,
+    ReplInput,
+    /// Text a generator checks before writing it to the project.
+    Generated,
     Module(Span),
 }
 
@@ -62,25 +67,79 @@ impl SourceCache {
         self.sources.read().unwrap()[&id].decl
     }
 
-    /// Get the 1-indexed line number for a location in the source.
-    /// This method lazily builds a line start index on first use.
-    pub fn get_line_number(&self, location: Location) -> usize {
+    /// Check in debug builds that `span` starts and ends on character
+    /// boundaries of its source. Slicing the text at a location inside a
+    /// multi-byte character would panic much later, far from the code which
+    /// made it.
+    pub fn debug_assert_char_boundaries(&self, span: Span) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let sources = self.sources.read().unwrap();
+        if let Some(source_info) = sources.get(&span.source()) {
+            for location in [span.start(), span.end()] {
+                assert!(
+                    source_info.text.is_char_boundary(location.byte_offset()),
+                    "{location:?} is not on a character boundary"
+                );
+            }
+        }
+    }
+
+    /// Get the 1-indexed line and column for a location in the source.
+    /// Columns count characters rather than bytes.
+    pub fn line_col(&self, location: Location) -> (u32, u32) {
         let sources = self.sources.read().unwrap();
         let source_info = &sources[&location.source()];
+        let text = source_info.text.as_str();
+        let line_starts = source_info.line_starts();
 
-        // Lazily compute line starts if not already done
-        let line_starts = source_info
-            .line_starts
-            .get_or_init(|| compute_line_starts(source_info.text.as_str()));
-
-        // Binary search to find which line the byte offset falls into
-        let byte_offset = location.byte_offset();
-        match line_starts.binary_search(&byte_offset) {
-            // Exact match - this byte offset is the start of a line
-            Ok(line_index) => line_index + 1,
-            // Not an exact match - the byte offset is within a line
-            Err(line_index) => line_index,
+        let byte_offset = location.byte_offset().min(text.len());
+        let line = line_starts.partition_point(|&start| start <= byte_offset);
+        let col = text[line_starts[line - 1]..byte_offset].chars().count() + 1;
+        (line as u32, col as u32)
+    }
+
+    /// Get the line and column for many locations at once, in the same order.
+    /// The locations in each source are sorted and found in a single pass
+    /// over its lines, which beats calling [`Self::line_col`] for each one.
+    pub fn line_cols(&self, locations: &[Location]) -> Vec<(u32, u32)> {
+        let mut by_source: FxHashMap<SourceId, Vec<usize>> = FxHashMap::default();
+        for (i, location) in locations.iter().enumerate() {
+            by_source.entry(location.source()).or_default().push(i);
         }
+
+        let sources = self.sources.read().unwrap();
+        let mut line_cols = vec![(0, 0); locations.len()];
+        for (source, mut indices) in by_source {
+            let source_info = &sources[&source];
+            let text = source_info.text.as_str();
+            let line_starts = source_info.line_starts();
+            indices.sort_by_key(|&i| locations[i].byte_offset());
+
+            // Only the characters since the previous location are counted.
+            let (mut line, mut col, mut counted_to) = (1, 1, 0);
+            for i in indices {
+                let byte_offset = locations[i].byte_offset().min(text.len());
+                while line < line_starts.len() && line_starts[line] <= byte_offset {
+                    counted_to = line_starts[line];
+                    line += 1;
+                    col = 1;
+                }
+                col += text[counted_to..byte_offset].chars().count();
+                counted_to = byte_offset;
+                line_cols[i] = (line as u32, col as u32);
+            }
+        }
+        line_cols
+    }
+}
+
+impl SourceInfo {
+    /// The line start index, built on first use.
+    fn line_starts(&self) -> &[usize] {
+        self.line_starts
+            .get_or_init(|| compute_line_starts(self.text.as_str()))
     }
 }
 
@@ -104,13 +163,101 @@ pub fn source_id_to_path(source: SourceId, root_dir: &Path) -> (PathBuf, PathBuf
     for part in source.name().as_str().split('.') {
         path.push(part);
     }
-    
+
     let mut standalone = path.clone();
     standalone.set_extension(*strings::FILE_EXTENSION);
 
     let mut dir = path;
     dir.push(*strings::DIR_MOD_NAME);
     dir.set_extension(*strings::FILE_EXTENSION);
-    
+
     (standalone, dir)
 }
+
+/// Read the file for a source, preferring the standalone file to the
+/// directory's module file. Returns the path it was read from and its text.
+pub fn read_source_file(source: SourceId, root_dir: &Path) -> Option<(PathBuf, String)> {
+    let (standalone, dir) = source_id_to_path(source, root_dir);
+    [standalone, dir].into_iter().find_map(|path| {
+        let text = fs::read_to_string(&path).ok()?;
+        Some((path, text))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SourceCache, SourceDecl};
+    use crate::parse::{
+        Location,
+        location::{SourceId, SourceOffset},
+    };
+    use ustr::Ustr;
+
+    /// A cache holding `text` as the source `name`, and a function for the
+    /// locations in it.
+    fn source_cache(name: &str, text: &str) -> (SourceCache, impl Fn(usize) -> Location) {
+        let cache = SourceCache::new();
+        let source = SourceId::new(Ustr::from(name));
+        cache.add(source, text.to_string(), SourceDecl::Root);
+        (cache, move |offset| {
+            Location::new(source, SourceOffset::new(offset))
+        })
+    }
+
+    #[test]
+    fn first_byte_is_line_one_column_one() {
+        let (cache, at) = source_cache("line_col.first", "ab\ncd\n");
+        assert_eq!(cache.line_col(at(0)), (1, 1));
+
+        let (cache, at) = source_cache("line_col.empty", "");
+        assert_eq!(cache.line_col(at(0)), (1, 1));
+    }
+
+    #[test]
+    fn newline_belongs_to_the_line_it_ends() {
+        let (cache, at) = source_cache("line_col.newline", "ab\ncd\n");
+        assert_eq!(cache.line_col(at(2)), (1, 3));
+        assert_eq!(cache.line_col(at(3)), (2, 1));
+    }
+
+    #[test]
+    fn end_of_file() {
+        let (cache, at) = source_cache("line_col.eof_newline", "ab\ncd\n");
+        assert_eq!(cache.line_col(at(6)), (3, 1));
+
+        let (cache, at) = source_cache("line_col.eof", "ab\ncd");
+        assert_eq!(cache.line_col(at(5)), (2, 3));
+    }
+
+    #[test]
+    fn carriage_returns_are_the_end_of_the_line() {
+        let (cache, at) = source_cache("line_col.crlf", "ab\r\ncd\r\n");
+        assert_eq!(cache.line_col(at(2)), (1, 3));
+        assert_eq!(cache.line_col(at(3)), (1, 4));
+        assert_eq!(cache.line_col(at(4)), (2, 1));
+        assert_eq!(cache.line_col(at(8)), (3, 1));
+    }
+
+    #[test]
+    fn columns_count_characters() {
+        let (cache, at) = source_cache("line_col.chars", "é→x\ny");
+        assert_eq!(cache.line_col(at(2)), (1, 2));
+        assert_eq!(cache.line_col(at(5)), (1, 3));
+        assert_eq!(cache.line_col(at(7)), (2, 1));
+    }
+
+    #[test]
+    fn batched_lookups_match_single_ones() {
+        let text = "é→x\r\n\n  y\r\nz";
+        let (cache, at) = source_cache("line_col.batched", text);
+        let mut locations: Vec<_> = (0..=text.len())
+            .filter(|&offset| text.is_char_boundary(offset))
+            .map(at)
+            .collect();
+        locations.reverse();
+        locations.swap(0, 3);
+
+        let single: Vec<_> = locations.iter().map(|&loc| cache.line_col(loc)).collect();
+        assert_eq!(cache.line_cols(&locations), single);
+    }
+}
@@ -0,0 +1,285 @@
+use crate::{
+    context::Ctx,
+    diagnostics::Diagnostic,
+    parse::{SourceId, source_cache::source_id_to_path},
+    strings,
+    util::similar_names,
+};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use ustr::Ustr;
+
+/// Find the sources `source` may have been renamed to: those in the directory
+/// it would be in which have a similar name, or which start with a heading
+/// naming it.
+pub fn similar_sources(source: SourceId, math_dir: &Path) -> Vec<(SourceId, PathBuf)> {
+    let name = source.name();
+    let (prefix, last) = match name.rsplit_once('.') {
+        Some((prefix, last)) => (format!("{prefix}."), last),
+        None => (String::new(), name.as_str()),
+    };
+
+    let mut dir = math_dir.to_path_buf();
+    for part in prefix.split('.').filter(|part| !part.is_empty()) {
+        dir.push(part);
+    }
+
+    let siblings = sibling_sources(&dir);
+    let similar = similar_names(last, siblings.iter().map(|(name, _)| *name));
+    siblings
+        .into_iter()
+        .filter(|(name, path)| similar.contains(name) || heading_names(path, last))
+        .map(|(name, path)| (SourceId::new(format!("{prefix}{name}").into()), path))
+        .collect()
+}
+
+/// The sources directly in `dir` by the last part of their name: files with
+/// the source extension and directories with a main file.
+fn sibling_sources(dir: &Path) -> Vec<(Ustr, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut sources = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        if path.is_dir() {
+            let mut main = path.join(*strings::DIR_MOD_NAME);
+            main.set_extension(*strings::FILE_EXTENSION);
+            if main.is_file() {
+                sources.push((Ustr::from(stem), main));
+            }
+        } else if is_source_file(&path) {
+            sources.push((Ustr::from(stem), path));
+        }
+    }
+    sources.sort();
+    sources
+}
+
+/// Whether the first line of the file at `path` which isn't blank or a
+/// comment is a heading mentioning `name`.
+fn heading_names(path: &Path, name: &str) -> bool {
+    let Ok(text) = fs::read_to_string(path) else {
+        return false;
+    };
+    let first_line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with("--"));
+
+    match first_line.and_then(|line| line.strip_prefix('#')) {
+        Some(heading) => heading
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .any(|word| word == name.to_lowercase()),
+        None => false,
+    }
+}
+
+fn is_source_file(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext == strings::FILE_EXTENSION.as_str())
+}
+
+/// Warn about the source files in the math directory which no root or module
+/// command loaded, since they silently aren't part of the project.
+pub fn warn_unloaded_sources(ctx: &mut Ctx) {
+    let math_dir = ctx.config.math_dir().to_path_buf();
+    let mut files = Vec::new();
+    find_source_files(&math_dir, &math_dir, "", &mut files);
+
+    let mut unloaded: Vec<_> = files
+        .into_iter()
+        .filter(|(ids, _)| !ids.iter().any(|&id| ctx.sources.has_source(id)))
+        .map(|(_, path)| path)
+        .collect();
+    if unloaded.is_empty() {
+        return;
+    }
+
+    unloaded.sort();
+    let project_dir = math_dir.parent().unwrap_or(&math_dir);
+    let paths: Vec<_> = unloaded
+        .iter()
+        .map(|path| path.strip_prefix(project_dir).unwrap_or(path))
+        .collect();
+    ctx.diags
+        .add_diag(Diagnostic::warn_unloaded_sources(&paths));
+}
+
+/// Find the source files under `dir` along with the sources which would be
+/// read from each. `prefix` is the source name of `dir` followed by a dot.
+fn find_source_files(
+    math_dir: &Path,
+    dir: &Path,
+    prefix: &str,
+    files: &mut Vec<(Vec<SourceId>, PathBuf)>,
+) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if stem.starts_with('.') {
+            continue;
+        }
+
+        if path.is_dir() {
+            find_source_files(math_dir, &path, &format!("{prefix}{stem}."), files);
+        } else if is_source_file(&path) {
+            let mut ids = vec![SourceId::new(format!("{prefix}{stem}").into())];
+
+            // The main file of a directory is also the directory's source,
+            // unless a file next to the directory takes precedence.
+            let dir_id = prefix.strip_suffix('.').filter(|dir| !dir.is_empty());
+            if stem == strings::DIR_MOD_NAME.as_str()
+                && let Some(dir_id) = dir_id
+            {
+                let dir_id = SourceId::new(dir_id.into());
+                let (standalone, _) = source_id_to_path(dir_id, math_dir);
+                if !standalone.is_file() {
+                    ids.push(dir_id);
+                }
+            }
+
+            files.push((ids, path));
+        }
+    }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_unloaded_sources(paths: &[&Path]) -> Self {
+        let mut diag = Diagnostic::new_warning(
+            &format!(
+                "{} source file{} never loaded",
+                paths.len(),
+                if paths.len() == 1 { " is" } else { "s are" }
+            ),
+            vec![],
+        );
+        for path in paths {
+            diag = diag.with_info(&format!("`{}`", path.display()), Vec::new());
+        }
+        diag.with_info(
+            "only roots and the sources they load with `module` are checked",
+            Vec::new(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// The parts of each diagnostic with the given title from parsing
+    /// `project`.
+    fn parts(project: &TestProject, title: &str) -> Vec<Vec<String>> {
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        (parsed.ctx().diags.iter())
+            .filter(|diag| diag.title() == title)
+            .map(|diag| {
+                diag.parts()
+                    .skip(1)
+                    .map(|part| part.title().to_string())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The suggestions in the error for the one missing source.
+    fn suggestions(project: &TestProject) -> Vec<String> {
+        let mut parts = parts(project, "source does not exist");
+        assert_eq!(parts.len(), 1);
+        parts.remove(0).split_off(2)
+    }
+
+    #[test]
+    fn similar_names_are_suggested() {
+        let project = TestProject::new("module orders\n");
+        project.write("math/order.wats", "");
+        project.write("math/groups.wats", "");
+        let found = project.path("math/order.wats");
+        assert_eq!(
+            suggestions(&project),
+            [format!(
+                "did you mean `module order`? found `{}`",
+                found.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn headings_naming_the_module_are_suggested() {
+        let project = TestProject::new("module orders\n");
+        project.write("math/posets.wats", "-- moved here\n\n# Partial orders\n");
+        project.write(
+            "math/lattices.wats",
+            "# Lattices\n\nEvery lattice orders its elements.\n",
+        );
+        let found = project.path("math/posets.wats");
+        assert_eq!(
+            suggestions(&project),
+            [format!(
+                "did you mean `module posets`? found `{}`",
+                found.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn suggestions_stay_in_the_module_directory() {
+        let project = TestProject::new("module algebra.rngs\n");
+        project.write("math/algebra/rings/main.wats", "");
+        project.write("math/rings.wats", "");
+        let found = project.path("math/algebra/rings/main.wats");
+        assert_eq!(
+            suggestions(&project),
+            [format!(
+                "did you mean `module algebra.rings`? found `{}`",
+                found.display()
+            )]
+        );
+    }
+
+    #[test]
+    fn unloaded_sources_are_listed_once() {
+        let project = TestProject::new("module loaded\nmodule dir\n");
+        project.write("math/loaded.wats", "");
+        project.write("math/dir/main.wats", "");
+        project.write("math/orphan.wats", "");
+        project.write("math/nested/orphan.wats", "");
+        project.write("math/notes.txt", "");
+        project.write("math/.draft.wats", "");
+        assert_eq!(
+            parts(&project, "2 source files are never loaded"),
+            [[
+                "`math/nested/orphan.wats`",
+                "`math/orphan.wats`",
+                "only roots and the sources they load with `module` are checked",
+            ]]
+        );
+    }
+
+    #[test]
+    fn no_warning_when_every_source_is_loaded() {
+        let project = TestProject::new("module loaded\n");
+        project.write("math/loaded.wats", "");
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        assert!((parsed.ctx().diags.iter()).all(|diag| !diag.title().ends_with("never loaded")));
+    }
+}
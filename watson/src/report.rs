@@ -1,12 +1,20 @@
+use crate::context::Ctx;
+use crate::diagnostics::print_frag;
+use crate::parse::SourceId;
 use crate::semant::proof_status::ProofStatuses;
-use crate::semant::theorems::TheoremId;
+use crate::semant::theorems::{TheoremId, TheoremKind};
 use crate::util::ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_GREEN, ANSI_RED, ANSI_RESET, ANSI_YELLOW};
-use crate::util::plural;
-use std::time::Duration;
+use crate::util::{plural, similar_names};
+use rustc_hash::FxHashSet;
+use serde::Serialize;
+use std::{fmt::Write, fs, io, path::Path, str::FromStr, time::Duration};
+use ustr::Ustr;
 
 pub struct ProofReport<'ctx> {
     pub statuses: ProofStatuses<'ctx>,
     pub circularities: Vec<Vec<TheoremId<'ctx>>>,
+    /// The theorems declared by each root, in the order the roots were checked.
+    pub roots: Vec<(SourceId, Vec<TheoremId<'ctx>>)>,
 }
 
 pub fn display_report(
@@ -18,6 +26,7 @@ pub fn display_report(
     let ProofReport {
         statuses,
         circularities,
+        roots,
     } = report;
 
     let iter_info = match (iteration, elapsed) {
@@ -30,36 +39,119 @@ pub fn display_report(
     };
 
     println!(
-        "{ANSI_BOLD}{ANSI_GREEN}Checked{ANSI_RESET} {} theorem{} ({} axiom{}, {} theorem{}): {}",
+        "{ANSI_BOLD}{ANSI_GREEN}Checked{ANSI_RESET} {} theorem{} ({}): {}",
         statuses.total_cnt(),
         plural(statuses.total_cnt()),
-        statuses.axiom_cnt(),
-        plural(statuses.axiom_cnt()),
-        statuses.theorem_cnt(),
-        plural(statuses.theorem_cnt()),
+        kind_counts(statuses),
         iter_info
     );
+    if let Some((filtered_out, skipped)) = statuses.filtered() {
+        println!(
+            " {ANSI_GRAY}-{ANSI_RESET} {ANSI_BOLD}{}{ANSI_RESET} theorem{} filtered out, {ANSI_BOLD}{}{ANSI_RESET} used theorem{} skipped.",
+            filtered_out,
+            plural(filtered_out),
+            skipped,
+            plural(skipped)
+        );
+        if skipped > 0 {
+            println!(
+                "     {ANSI_GRAY}pass --with-deps to check the skipped theorems too{ANSI_RESET}"
+            );
+        }
+    }
 
+    let fully_proven = if statuses.todo_cnt() > 0 {
+        format!(
+            ", {ANSI_BOLD}{}{ANSI_RESET} fully proven",
+            statuses.fully_proven_cnt()
+        )
+    } else {
+        String::new()
+    };
     println!(
-        " {ANSI_GREEN}✓{ANSI_RESET} {ANSI_BOLD}{}{ANSI_RESET} theorem{} correct. ",
+        " {ANSI_GREEN}✓{ANSI_RESET} {ANSI_BOLD}{}{ANSI_RESET} theorem{} correct{fully_proven}. ",
         statuses.correct_cnt(),
         plural(statuses.correct_cnt())
     );
+    if roots.len() > 1 {
+        for (root, theorems) in roots {
+            let correct = theorems
+                .iter()
+                .filter(|&&thm| {
+                    statuses
+                        .get(thm)
+                        .is_some_and(|s| s.correct() && !s.is_sentinel())
+                })
+                .count();
+            let total = theorems
+                .iter()
+                .filter(|&&thm| statuses.get(thm).is_none_or(|s| !s.is_sentinel()))
+                .count();
+            println!(
+                "     - {ANSI_BOLD}{}{ANSI_RESET}: {}/{} correct",
+                root.name(),
+                correct,
+                total
+            );
+        }
+    }
     if statuses.todo_cnt() > 0 {
         println!(
             " {ANSI_YELLOW}~{ANSI_RESET} {ANSI_BOLD}{}{ANSI_RESET} theorem{} with todo.",
             statuses.todo_cnt(),
             plural(statuses.todo_cnt())
         );
-        let mut reasons: Vec<_> = statuses.todo_by_reason().iter().collect();
-        reasons.sort_by(|(_, a), (_, b)| a.cmp(b).reverse());
-        for (reason, count) in reasons {
-            let label = reason.as_deref().unwrap_or("unimplemented");
+        // Tracked tickets first, most used first, then the untracked todos.
+        let mut tickets: Vec<_> = statuses.todo_by_ticket().iter().collect();
+        tickets.sort_by(|(a, a_group), (b, b_group)| {
+            (a.is_none(), b_group.theorems(), a).cmp(&(b.is_none(), a_group.theorems(), b))
+        });
+        for (ticket, group) in tickets {
+            let ticket = ticket.as_deref().unwrap_or("untracked");
+            let reasons = group
+                .reasons()
+                .iter()
+                .map(|reason| reason.as_deref().unwrap_or("unimplemented"))
+                .collect::<Vec<_>>()
+                .join("; ");
+            println!(
+                "     - {ANSI_BOLD}{}{ANSI_RESET}: {} theorem{} {ANSI_YELLOW}({}){ANSI_RESET}",
+                ticket,
+                group.theorems(),
+                plural(group.theorems()),
+                reasons
+            );
+        }
+        if statuses.tainted_cnt() > 0 {
+            println!(
+                " {ANSI_YELLOW}~{ANSI_RESET} {ANSI_BOLD}{}{ANSI_RESET} more theorem{} depend{} on a theorem with todo.",
+                statuses.tainted_cnt(),
+                plural(statuses.tainted_cnt()),
+                if statuses.tainted_cnt() == 1 { "s" } else { "" }
+            );
+        }
+    }
+    if statuses.fragile_cnt() > 0 {
+        println!(
+            " {ANSI_YELLOW}≈{ANSI_RESET} {ANSI_BOLD}{}{ANSI_RESET} fragile proof{}.",
+            statuses.fragile_cnt(),
+            plural(statuses.fragile_cnt())
+        );
+        let mut fragile: Vec<_> = statuses
+            .iter()
+            .filter(|(_, status)| status.correct())
+            .filter_map(|(thm, status)| Some((thm, status.fragile()?, status.duration())))
+            .collect();
+        fragile.sort_by_key(|(thm, _, _)| thm.name());
+        for (thm, reason, duration) in fragile {
+            let duration = match duration {
+                Some(duration) => format!(" {ANSI_GRAY}({}ms){ANSI_RESET}", duration.as_millis()),
+                None => String::new(),
+            };
             println!(
-                "     - {ANSI_BOLD}{}{ANSI_RESET} theorem{}: {ANSI_YELLOW}{}{ANSI_RESET}",
-                count,
-                plural(*count),
-                label
+                "     - {ANSI_BOLD}{}{ANSI_RESET}: {ANSI_YELLOW}{}{ANSI_RESET}{duration}",
+                thm.name(),
+                reason
             );
         }
     }
@@ -69,6 +161,18 @@ pub fn display_report(
             statuses.error_cnt(),
             plural(statuses.error_cnt())
         );
+        let mut failed: Vec<_> = statuses
+            .iter()
+            .filter(|(_, status)| !status.correct() && !status.is_sentinel())
+            .collect();
+        failed.sort_by_key(|(thm, _)| thm.name());
+        for (thm, status) in failed {
+            println!(
+                "     - {ANSI_BOLD}{}{ANSI_RESET}: {ANSI_GRAY}⊢{ANSI_RESET} {}",
+                thm.name(),
+                print_frag(thm.conclusion(), status.frag_renderings())
+            );
+        }
     }
 
     if !circularities.is_empty() {
@@ -90,7 +194,21 @@ pub fn display_report(
         }
     }
 
-    let all_ok = statuses.error_cnt() == 0 && circularities.is_empty() && !errors;
+    match statuses.sentinel() {
+        Some((_, true)) => {
+            println!(" {ANSI_GREEN}✓{ANSI_RESET} consistency sentinel: OK");
+        }
+        Some((sentinel, false)) => {
+            println!(
+                " {ANSI_RED}✗{ANSI_RESET} {ANSI_BOLD}{ANSI_RED}consistency sentinel `{}` was proved!{ANSI_RESET} The axioms or tactics are unsound.",
+                sentinel.name()
+            );
+        }
+        None => {}
+    }
+
+    let sentinel_ok = statuses.sentinel().is_none_or(|(_, held)| held);
+    let all_ok = statuses.error_cnt() == 0 && circularities.is_empty() && sentinel_ok && !errors;
 
     if all_ok {
         println!();
@@ -99,3 +217,390 @@ pub fn display_report(
 
     all_ok
 }
+
+/// How many statements of each kind there are, like "2 axioms, 5 theorems,
+/// 1 lemma". Axioms and theorems are always listed, the other kinds only if
+/// there are any.
+fn kind_counts(statuses: &ProofStatuses) -> String {
+    let kinds = [
+        TheoremKind::Axiom,
+        TheoremKind::Theorem,
+        TheoremKind::Lemma,
+        TheoremKind::Proposition,
+        TheoremKind::Corollary,
+    ];
+
+    kinds
+        .into_iter()
+        .filter_map(|kind| {
+            let count = statuses
+                .iter()
+                .filter(|(thm, _)| thm.kind() == kind)
+                .count();
+            let always = matches!(kind, TheoremKind::Axiom | TheoremKind::Theorem);
+            if count == 0 && !always {
+                return None;
+            }
+
+            let word = match kind {
+                TheoremKind::Corollary if count != 1 => "corollaries".to_string(),
+                _ => format!("{}{}", kind.keyword(), plural(count)),
+            };
+            Some(format!("{count} {word}"))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Print the axioms a theorem depends on, directly or through the theorems it
+/// uses. Returns false if there is no theorem with that name.
+pub fn display_axiom_audit<'ctx>(name: Ustr, report: &ProofReport<'ctx>, ctx: &Ctx<'ctx>) -> bool {
+    let Some(theorem) = ctx.arenas.theorem_stmts.get(name) else {
+        println!();
+        println!("{ANSI_RED}{ANSI_BOLD}error:{ANSI_RESET} no theorem named `{name}` to audit");
+        for candidate in similar_names(&name, ctx.arenas.theorem_stmts.names()) {
+            println!("     did you mean `{candidate}`?");
+        }
+        return false;
+    };
+    let Some(status) = report.statuses.get(theorem) else {
+        return true;
+    };
+
+    let axioms = status.axioms_used();
+    println!();
+    println!(
+        "{ANSI_BOLD}Axioms used by {} `{name}`{ANSI_RESET} ({} axiom{}):",
+        theorem.kind().keyword(),
+        axioms.len(),
+        plural(axioms.len())
+    );
+    let starts: Vec<_> = axioms
+        .iter()
+        .map(|axiom| axiom.name_span().start())
+        .collect();
+    let line_cols = ctx.sources.line_cols(&starts);
+    for (axiom, (line, _)) in axioms.iter().zip(line_cols) {
+        let deprecated = match (axiom.deprecated(), axiom.deprecation_note()) {
+            (false, _) => String::new(),
+            (true, None) => format!(" {ANSI_YELLOW}(deprecated){ANSI_RESET}"),
+            (true, Some(note)) => format!(" {ANSI_YELLOW}(deprecated: {note}){ANSI_RESET}"),
+        };
+        println!(
+            "     - {ANSI_BOLD}{}{ANSI_RESET}{deprecated} {ANSI_GRAY}{}:{line}{ANSI_RESET}",
+            axiom.name(),
+            axiom.name_span().source().name()
+        );
+        println!("         {}", axiom.print());
+    }
+    true
+}
+
+/// Write the `limit` theorems which took longest to check to `out`, with
+/// where the time went, and how the time checking proofs compares to the
+/// whole run.
+pub fn write_profile(report: &ProofReport, limit: usize, elapsed: Duration, out: &mut String) {
+    let mut timed: Vec<_> = report
+        .statuses
+        .iter()
+        .filter_map(|(thm, status)| Some((thm, status, status.timing()?)))
+        .collect();
+    timed.sort_by(|(a, _, a_timing), (b, _, b_timing)| {
+        (b_timing.total, a.name()).cmp(&(a_timing.total, b.name()))
+    });
+    let total: Duration = timed.iter().map(|(_, _, timing)| timing.total).sum();
+
+    writeln!(out).unwrap();
+    writeln!(
+        out,
+        "{ANSI_BOLD}Profile{ANSI_RESET} ({} of {} proof{}, {} checking proofs of {} in total):",
+        timed.len().min(limit),
+        timed.len(),
+        plural(timed.len()),
+        millis(total),
+        millis(elapsed)
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "     {ANSI_GRAY}{:>10} {:>10} {:>10} {:>6}  theorem{ANSI_RESET}",
+        "total", "lua", "kernel", "steps"
+    )
+    .unwrap();
+    for (thm, status, timing) in timed.into_iter().take(limit) {
+        let name = if status.correct() {
+            format!("{ANSI_BOLD}{}{ANSI_RESET}", thm.name())
+        } else {
+            format!("{ANSI_RED}{ANSI_BOLD}{}{ANSI_RESET}", thm.name())
+        };
+        writeln!(
+            out,
+            "     {:>10} {:>10} {:>10} {:>6}  {name}",
+            millis(timing.total),
+            millis(timing.lua),
+            millis(timing.kernel),
+            status.audit_log().len()
+        )
+        .unwrap();
+    }
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Print every use of todo with where it was used and what the tactic said
+/// about it, ordered by theorem.
+pub fn display_todos(report: &ProofReport, ctx: &Ctx) {
+    let mut theorems: Vec<_> = report
+        .statuses
+        .iter()
+        .filter(|(_, status)| status.correct() && status.todo_used())
+        .collect();
+    theorems.sort_by_key(|(thm, _)| thm.name());
+
+    let todo_cnt: usize = theorems
+        .iter()
+        .map(|(_, status)| status.todos().len())
+        .sum();
+    println!();
+    println!(
+        "{ANSI_BOLD}Todos{ANSI_RESET} ({} todo{} in {} theorem{}):",
+        todo_cnt,
+        plural(todo_cnt),
+        theorems.len(),
+        plural(theorems.len())
+    );
+    let starts: Vec<_> = theorems
+        .iter()
+        .flat_map(|(_, status)| status.todos())
+        .filter_map(|todo| todo.span.map(|span| span.start()))
+        .collect();
+    let mut line_cols = ctx.sources.line_cols(&starts).into_iter();
+    for (thm, status) in theorems {
+        for todo in status.todos() {
+            let location = match todo.span {
+                Some(span) => {
+                    let (line, _) = line_cols.next().unwrap();
+                    format!("{}:{line}", span.source().name())
+                }
+                None => "unknown location".to_string(),
+            };
+            let ticket = match &todo.ticket {
+                Some(ticket) => format!(" {ANSI_YELLOW}[{ticket}]{ANSI_RESET}"),
+                None => String::new(),
+            };
+            println!(
+                "     - {ANSI_BOLD}{}{ANSI_RESET} {ANSI_GRAY}{}{ANSI_RESET}{}: {}",
+                thm.name(),
+                location,
+                ticket,
+                todo.reason.as_deref().unwrap_or("unimplemented")
+            );
+        }
+    }
+}
+
+/// Print every correct theorem which depends on todo, directly or through the
+/// theorems it uses, with the uses of todo it depends on.
+pub fn display_todo_taint(report: &ProofReport, ctx: &Ctx) {
+    let mut theorems: Vec<_> = report
+        .statuses
+        .iter()
+        .filter(|(_, status)| status.correct() && !status.todo_sources().is_empty())
+        .collect();
+    theorems.sort_by_key(|(thm, _)| thm.name());
+
+    println!();
+    println!(
+        "{ANSI_BOLD}Theorems depending on todo{ANSI_RESET} ({} theorem{}):",
+        theorems.len(),
+        plural(theorems.len())
+    );
+    // The todos are listed under each theorem depending on them, in this
+    // order.
+    let starts: Vec<_> = theorems
+        .iter()
+        .flat_map(|(_, status)| status.todo_sources())
+        .flat_map(|&source| report.statuses[source].todos())
+        .filter_map(|todo| todo.span.map(|span| span.start()))
+        .collect();
+    let mut line_cols = ctx.sources.line_cols(&starts).into_iter();
+    for (&thm, status) in theorems {
+        let through: Vec<_> = status
+            .todo_sources()
+            .iter()
+            .filter(|&&source| source != thm)
+            .map(|source| source.name().to_string())
+            .collect();
+        let through = if through.is_empty() {
+            String::new()
+        } else {
+            format!(" {ANSI_GRAY}through {}{ANSI_RESET}", through.join(", "))
+        };
+        println!("     - {ANSI_BOLD}{}{ANSI_RESET}{through}", thm.name());
+
+        for &source in status.todo_sources() {
+            for todo in report.statuses[source].todos() {
+                let Some(span) = todo.span else {
+                    println!("         {ANSI_GRAY}unknown location{ANSI_RESET}");
+                    continue;
+                };
+                let (line, col) = line_cols.next().unwrap();
+                let text = ctx.sources.get_text(span.source());
+                let text = &text[span.start().byte_offset()..span.end().byte_offset()];
+                let text = match text.split_once('\n') {
+                    Some((first, _)) => format!("{}…", first.trim_end()),
+                    None => text.to_string(),
+                };
+                println!(
+                    "         {ANSI_GRAY}{}:{line}:{col}{ANSI_RESET} {ANSI_YELLOW}{text}{ANSI_RESET}",
+                    span.source().name()
+                );
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepsFormat {
+    Dot,
+    Json,
+}
+
+impl FromStr for DepsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dot" => Ok(Self::Dot),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("expected `dot` or `json`, found `{s}`")),
+        }
+    }
+}
+
+/// What the check found for a theorem in the dependency graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DepStatus {
+    Axiom,
+    Proved,
+    Todo,
+    Failed,
+    /// Left out by a filter on which theorems were checked.
+    Unchecked,
+}
+
+#[derive(Serialize)]
+struct JsonDeps {
+    theorems: Vec<JsonTheorem>,
+    uses: Vec<JsonUse>,
+}
+
+#[derive(Serialize)]
+struct JsonTheorem {
+    name: String,
+    kind: &'static str,
+    status: DepStatus,
+}
+
+#[derive(Serialize)]
+struct JsonUse {
+    /// The theorem whose proof uses `used`.
+    theorem: String,
+    used: String,
+}
+
+/// Write the graph of which theorems each proof uses to `path`, with every
+/// theorem as a node and an edge from each theorem to the theorems whose
+/// proofs use it.
+pub fn write_deps(path: &Path, report: &ProofReport, format: DepsFormat) -> io::Result<()> {
+    let theorems = deps_in_order(report);
+    let uses = deps_uses(&theorems, report);
+    let out = match format {
+        DepsFormat::Dot => render_deps_dot(&theorems, &uses),
+        DepsFormat::Json => render_deps_json(&theorems, &uses),
+    };
+    fs::write(path, out)
+}
+
+/// Every theorem with its status, in the order they were declared.
+fn deps_in_order<'ctx>(report: &ProofReport<'ctx>) -> Vec<(TheoremId<'ctx>, DepStatus)> {
+    let mut seen = FxHashSet::default();
+    report
+        .roots
+        .iter()
+        .flat_map(|(_, theorems)| theorems)
+        .filter(|&&thm| seen.insert(thm))
+        .map(|&thm| {
+            let status = match report.statuses.get(thm) {
+                None => DepStatus::Unchecked,
+                Some(status) if status.is_axiom() => DepStatus::Axiom,
+                Some(status) if !status.correct() => DepStatus::Failed,
+                Some(status) if status.todo_used() => DepStatus::Todo,
+                Some(_) => DepStatus::Proved,
+            };
+            (thm, status)
+        })
+        .collect()
+}
+
+/// Each theorem paired with the theorems its proof uses, sorted by name.
+fn deps_uses<'ctx>(
+    theorems: &[(TheoremId<'ctx>, DepStatus)],
+    report: &ProofReport<'ctx>,
+) -> Vec<(TheoremId<'ctx>, TheoremId<'ctx>)> {
+    let mut uses = Vec::new();
+    for &(thm, _) in theorems {
+        let Some(status) = report.statuses.get(thm) else {
+            continue;
+        };
+        let mut used: Vec<_> = status.theorems_used().iter().copied().collect();
+        used.sort_by_key(|used| used.name());
+        uses.extend(used.into_iter().map(|used| (thm, used)));
+    }
+    uses
+}
+
+fn render_deps_dot(theorems: &[(TheoremId, DepStatus)], uses: &[(TheoremId, TheoremId)]) -> String {
+    let mut out = String::from("digraph theorems {\n");
+    for (thm, status) in theorems {
+        let attrs = match status {
+            DepStatus::Axiom => "shape=box, style=filled, fillcolor=lightblue",
+            DepStatus::Proved => "shape=ellipse",
+            DepStatus::Todo => "shape=ellipse, style=\"filled,dashed\", fillcolor=orange",
+            DepStatus::Failed => "shape=octagon, color=red, fontcolor=red",
+            DepStatus::Unchecked => "shape=ellipse, style=dotted, fontcolor=gray",
+        };
+        writeln!(out, "    \"{}\" [{attrs}];", thm.name()).unwrap();
+    }
+    for (thm, used) in uses {
+        writeln!(out, "    \"{}\" -> \"{}\";", used.name(), thm.name()).unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_deps_json(
+    theorems: &[(TheoremId, DepStatus)],
+    uses: &[(TheoremId, TheoremId)],
+) -> String {
+    let theorems = theorems
+        .iter()
+        .map(|&(thm, status)| JsonTheorem {
+            name: thm.name().to_string(),
+            kind: thm.kind().keyword(),
+            status,
+        })
+        .collect();
+    let uses = uses
+        .iter()
+        .map(|(thm, used)| JsonUse {
+            theorem: thm.name().to_string(),
+            used: used.name().to_string(),
+        })
+        .collect();
+
+    serde_json::to_string_pretty(&JsonDeps { theorems, uses }).unwrap()
+}
@@ -1,4 +1,9 @@
-use crate::semant::{commands::CommandId, custom_grammar::inst::CustomGrammarInst};
+use crate::{
+    parse::Span,
+    semant::{commands::CommandId, custom_grammar::inst::CustomGrammarInst},
+};
+use rustc_hash::FxHashMap;
+use ustr::Ustr;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Attribute<'ctx>(pub CustomGrammarInst<'ctx>);
@@ -28,3 +33,232 @@ impl<'ctx> AttributeTracker<'ctx> {
         }
     }
 }
+
+/// The attributes Watson itself understands, as opposed to those declared
+/// with an `attribute` command which are only read by Lua.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinAttributeKind {
+    /// Theorems should stop depending on the axiom. Takes an optional note
+    /// saying what to use instead.
+    Deprecated,
+    /// The command is left out of the book.
+    Hidden,
+    /// The notation can only be used in the source declaring it.
+    Local,
+}
+
+impl BuiltinAttributeKind {
+    const ALL: [Self; 3] = [Self::Deprecated, Self::Hidden, Self::Local];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Deprecated => "deprecated",
+            Self::Hidden => "hidden",
+            Self::Local => "local",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+
+    pub fn names() -> impl Iterator<Item = Ustr> {
+        Self::ALL.into_iter().map(|kind| Ustr::from(kind.name()))
+    }
+
+    pub fn accepts(self, value: AttributeValue) -> bool {
+        match self {
+            Self::Deprecated => matches!(value, AttributeValue::None | AttributeValue::Str(_)),
+            Self::Hidden | Self::Local => value == AttributeValue::None,
+        }
+    }
+
+    /// The values the attribute accepts, for error messages.
+    pub fn expects(self) -> &'static str {
+        match self {
+            Self::Deprecated => "no value or a string",
+            Self::Hidden | Self::Local => "no value",
+        }
+    }
+}
+
+/// The value given to an attribute in parentheses, like the note in
+/// `@[deprecated("use `foo`")]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttributeValue {
+    None,
+    Str(Ustr),
+    Num(usize),
+    Name(Ustr),
+}
+
+impl AttributeValue {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Self::None => "no value",
+            Self::Str(_) => "a string",
+            Self::Num(_) => "a number",
+            Self::Name(_) => "a name",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinAttribute {
+    pub kind: BuiltinAttributeKind,
+    pub value: AttributeValue,
+    pub span: Span,
+}
+
+/// The builtin attributes given to each command.
+#[derive(Debug, Default)]
+pub struct BuiltinAttributes<'ctx> {
+    attrs: FxHashMap<CommandId<'ctx>, Vec<BuiltinAttribute>>,
+}
+
+impl<'ctx> BuiltinAttributes<'ctx> {
+    pub fn insert(&mut self, cmd: CommandId<'ctx>, attrs: Vec<BuiltinAttribute>) {
+        if !attrs.is_empty() {
+            self.attrs.insert(cmd, attrs);
+        }
+    }
+
+    pub fn find(
+        &self,
+        cmd: CommandId<'ctx>,
+        kind: BuiltinAttributeKind,
+    ) -> Option<BuiltinAttribute> {
+        self.attrs
+            .get(&cmd)?
+            .iter()
+            .find(|attr| attr.kind == kind)
+            .copied()
+    }
+
+    pub fn has(&self, cmd: CommandId<'ctx>, kind: BuiltinAttributeKind) -> bool {
+        self.find(cmd, kind).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, parse::Span, util::test_project::TestProject};
+    use ustr::Ustr;
+
+    const THEOREM: &str = "theorem refl [p : sentence] : |- p → p\nproof\nqed\n";
+    const AXIOM: &str = "axiom old [p : sentence] : |- p → p\nend\n";
+    const NOTATION: &str = "notation top\n    sentence ::= \"⊤\"\nend\n";
+
+    /// Each diagnostic in `main.wats` from parsing a project where `command`
+    /// follows `module logic`, with whether it is an error and the titles of
+    /// its parts.
+    fn diags(command: &str) -> Vec<(bool, Vec<&'static str>)> {
+        let project = TestProject::with_logic(&format!("module logic\n\n{command}"));
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let in_main = |span: Span| span.source().name() == "main";
+        (parsed.ctx().diags.iter())
+            .filter(|diag| diag.primary_span().is_some_and(in_main))
+            .map(|diag| {
+                (
+                    diag.is_error(),
+                    diag.parts().map(|part| part.title()).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn known_attributes_are_accepted() {
+        for command in [
+            format!("@[hidden] {THEOREM}"),
+            format!("@[deprecated] {AXIOM}"),
+            format!("@[deprecated(\"use mp\")] {AXIOM}"),
+        ] {
+            let diags = diags(&command);
+            assert!(diags.is_empty(), "{command}: {diags:?}");
+        }
+    }
+
+    #[test]
+    fn deprecated_marks_the_axiom() {
+        let project = TestProject::with_logic(&format!(
+            "module logic\n\n@[deprecated(\"use mp\")] {AXIOM}"
+        ));
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let old = parsed
+            .ctx()
+            .arenas
+            .theorem_stmts
+            .get(Ustr::from("old"))
+            .unwrap();
+        assert!(old.deprecated());
+        assert_eq!(old.deprecation_note(), Some(Ustr::from("use mp")));
+    }
+
+    #[test]
+    fn unknown_attribute_warns_with_a_suggestion() {
+        assert_eq!(
+            diags(&format!("@[hiden] {THEOREM}")),
+            [(
+                false,
+                vec![
+                    "unknown attribute `hiden`",
+                    "the attribute is ignored",
+                    "did you mean `hidden`?"
+                ]
+            )]
+        );
+        assert_eq!(
+            diags(&format!("@[frobnicate] {THEOREM}")),
+            [(
+                false,
+                vec!["unknown attribute `frobnicate`", "the attribute is ignored"]
+            )]
+        );
+    }
+
+    #[test]
+    fn wrong_value_is_an_error() {
+        assert_eq!(
+            diags(&format!("@[hidden(3)] {THEOREM}")),
+            [(
+                true,
+                vec!["attribute `hidden` takes no value, but was given a number"]
+            )]
+        );
+        assert_eq!(
+            diags(&format!("@[deprecated(mp)] {AXIOM}")),
+            [(
+                true,
+                vec!["attribute `deprecated` takes no value or a string, but was given a name"]
+            )]
+        );
+    }
+
+    #[test]
+    fn attribute_on_the_wrong_command_is_an_error() {
+        assert_eq!(
+            diags(&format!("@[deprecated] {THEOREM}")),
+            [(
+                true,
+                vec!["attribute `deprecated` can only be used on axioms"]
+            )]
+        );
+        assert_eq!(
+            diags(&format!("@[local] {THEOREM}")),
+            [(true, vec!["attribute `local` can only be used on notation"])]
+        );
+        assert_eq!(
+            diags(&format!("@[local] {NOTATION}")),
+            [(
+                true,
+                vec![
+                    "local notation is not yet supported",
+                    "notation is visible everywhere after it is declared"
+                ]
+            )]
+        );
+    }
+}
@@ -0,0 +1,167 @@
+use rustc_hash::{FxHashMap, FxHashSet};
+use ustr::Ustr;
+
+use crate::{
+    context::Ctx,
+    diagnostics::{Diagnostic, DiagnosticSpan},
+    parse::Span,
+    semant::{
+        check_circularity::strongly_connected_components, proof_status::ProofStatuses,
+        theorems::TheoremId,
+    },
+};
+
+/// Find the axioms each theorem depends on, directly or through the theorems
+/// it uses.
+pub fn compute_axiom_closures<'ctx>(statuses: &mut ProofStatuses<'ctx>) {
+    // Each component comes after the components it depends on, so their
+    // closures are known by the time we reach it. Theorems in the same
+    // component depend on each other, so they share a closure.
+    let mut closures: FxHashMap<TheoremId<'ctx>, Vec<TheoremId<'ctx>>> = FxHashMap::default();
+
+    for scc in strongly_connected_components(statuses) {
+        let mut axioms = FxHashSet::default();
+        for &theorem in &scc {
+            let status = &statuses[theorem];
+            if status.is_axiom() {
+                axioms.insert(theorem);
+            }
+            for used in status.theorems_used() {
+                if let Some(closure) = closures.get(used) {
+                    axioms.extend(closure.iter().copied());
+                }
+            }
+        }
+
+        let mut axioms: Vec<_> = axioms.into_iter().collect();
+        axioms.sort_by_key(|axiom| axiom.name());
+        for theorem in scc {
+            closures.insert(theorem, axioms.clone());
+        }
+    }
+
+    for (theorem, axioms) in closures {
+        statuses.set_axioms_used(theorem, axioms);
+    }
+}
+
+/// Find the correct theorems using todo which each theorem depends on,
+/// directly or through the theorems it uses, the same way as
+/// [`compute_axiom_closures`].
+pub fn compute_todo_closures<'ctx>(statuses: &mut ProofStatuses<'ctx>) {
+    let mut closures: FxHashMap<TheoremId<'ctx>, Vec<TheoremId<'ctx>>> = FxHashMap::default();
+
+    for scc in strongly_connected_components(statuses) {
+        let mut sources = FxHashSet::default();
+        for &theorem in &scc {
+            let status = &statuses[theorem];
+            if status.correct() && status.todo_used() {
+                sources.insert(theorem);
+            }
+            for used in status.theorems_used() {
+                if let Some(closure) = closures.get(used) {
+                    sources.extend(closure.iter().copied());
+                }
+            }
+        }
+
+        let mut sources: Vec<_> = sources.into_iter().collect();
+        sources.sort_by_key(|source| source.name());
+        for theorem in scc {
+            closures.insert(theorem, sources.clone());
+        }
+    }
+
+    for (theorem, sources) in closures {
+        statuses.set_todo_sources(theorem, sources);
+    }
+}
+
+/// Warn about each theorem which depends on a deprecated axiom.
+pub fn warn_deprecated_axioms<'ctx>(statuses: &ProofStatuses<'ctx>, ctx: &mut Ctx<'ctx>) {
+    let mut theorems: Vec<_> = statuses
+        .iter()
+        .filter(|(_, status)| !status.is_axiom())
+        .collect();
+    theorems.sort_by_key(|(theorem, _)| theorem.name());
+
+    for (theorem, status) in theorems {
+        let deprecated: Vec<_> = status
+            .axioms_used()
+            .iter()
+            .filter(|axiom| axiom.deprecated())
+            .map(|axiom| axiom.name())
+            .collect();
+
+        if !deprecated.is_empty() {
+            ctx.diags.add_diag(Diagnostic::warn_uses_deprecated_axioms(
+                theorem.name(),
+                &deprecated,
+                theorem.name_span(),
+            ));
+        }
+    }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_uses_deprecated_axioms(thm: Ustr, axioms: &[Ustr], span: Span) -> Self {
+        let axioms = axioms
+            .iter()
+            .map(|axiom| format!("`{axiom}`"))
+            .collect::<Vec<_>>();
+        Diagnostic::new_warning(
+            &format!(
+                "theorem `{thm}` depends on deprecated axiom{} {}",
+                if axioms.len() == 1 { "" } else { "s" },
+                axioms.join(", ")
+            ),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    #[test]
+    fn closures_of_a_diamond_are_deduplicated() {
+        // `top` reaches `one` through both `left` and `right`.
+        let main = "module logic\n\n\
+            axiom one :\n    |- ⊥ → ⊥\nend\n\n\
+            axiom two deprecated :\n    |- ¬⊥\nend\n\n\
+            theorem left :\n    |- ⊥ → ⊥\nproof\n    by one\nqed\n\n\
+            theorem right :\n    |- ¬⊥\nproof\n    have ⊥ → ⊥ by one;\n    by two\nqed\n\n\
+            theorem top :\n    |- ¬⊥\nproof\n    have ⊥ → ⊥ by left;\n    by right\nqed\n";
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        assert!(!checked.ctx().diags.has_errors());
+
+        let closure = |name: &str| -> Vec<_> {
+            let (_, status) = (checked.proof_report().statuses.iter())
+                .find(|(thm, _)| thm.name() == name)
+                .unwrap();
+            (status.axioms_used().iter())
+                .map(|axiom| axiom.name().as_str())
+                .collect()
+        };
+        assert_eq!(closure("one"), ["one"]);
+        assert_eq!(closure("left"), ["one"]);
+        assert_eq!(closure("right"), ["one", "two"]);
+        assert_eq!(closure("top"), ["one", "two"]);
+
+        let warnings: Vec<_> = (checked.ctx().diags.iter())
+            .map(|diag| diag.title())
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "theorem `right` depends on deprecated axiom `two`",
+                "theorem `top` depends on deprecated axiom `two`",
+            ]
+        );
+    }
+}
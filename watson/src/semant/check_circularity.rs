@@ -7,7 +7,23 @@ pub fn find_circular_dependency_groups<'ctx>(
 ) -> Vec<Vec<TheoremId<'ctx>>> {
     // To check for circularity, we find the strongly connected components of the
     // theorem dependency graph.
+    let mut sccs = strongly_connected_components(statuses);
 
+    sccs.retain(|scc| {
+        scc.len() > 1 || {
+            let id = scc[0];
+            statuses[id].theorems_used().contains(&id)
+        }
+    });
+
+    sccs
+}
+
+/// The strongly connected components of the theorem dependency graph. Each
+/// component comes after every component it depends on.
+pub fn strongly_connected_components<'ctx>(
+    statuses: &ProofStatuses<'ctx>,
+) -> Vec<Vec<TheoremId<'ctx>>> {
     let mut index = 0;
     let mut indices: FxHashMap<TheoremId, usize> = FxHashMap::default();
     let mut lowlinks: FxHashMap<TheoremId, usize> = FxHashMap::default();
@@ -39,6 +55,12 @@ pub fn find_circular_dependency_groups<'ctx>(
         *index += 1;
 
         for to in statuses[at].theorems_used() {
+            // Theorems left out by a filter weren't checked, so we don't
+            // know what they use.
+            if statuses.get(*to).is_none() {
+                continue;
+            }
+
             if !visited.contains(to) {
                 dfs(
                     *to, visited, stack, on_stack, indices, lowlinks, index, sccs, statuses,
@@ -83,12 +105,5 @@ pub fn find_circular_dependency_groups<'ctx>(
         );
     }
 
-    sccs.retain(|scc| {
-        scc.len() > 1 || {
-            let id = scc[0];
-            statuses[id].theorems_used().contains(&id)
-        }
-    });
-
     sccs
 }
@@ -1,6 +1,15 @@
 use crate::{
     diagnostics::{Diagnostic, DiagnosticSpan},
-    semant::check_proofs::{LuaTheoremInfo, lua_api::tactic_info_to_lua::LuaTacticInfo},
+    semant::{
+        check_proofs::{
+            LuaTheoremInfo,
+            lua_api::{
+                ctx_to_lua::LuaCtx, proof_to_lua::LuaProofState, render_hook::render_frags,
+                tactic_info_to_lua::LuaTacticInfo,
+            },
+        },
+        tactic::tactic_info::kernel_steps,
+    },
 };
 use mlua::{FromLua, UserData, Variadic};
 
@@ -65,13 +74,33 @@ impl UserData for LuaDiagnostic {
             },
         );
 
-        methods.add_method("withTacticInfo", |lua, this, tactic_info: LuaTacticInfo| {
-            let info = lua.app_data_ref::<LuaTheoremInfo>().unwrap();
-            let thm = info.borrow().thm.out();
-            let tactic_info = tactic_info.out_ref().clone();
-            let new_diag = this.clone().out().in_proof(thm, tactic_info);
-            Ok(LuaDiagnostic::new(new_diag))
-        });
+        methods.add_method(
+            "withTacticInfo",
+            |lua, this, (tactic_info, proof): (LuaTacticInfo, Option<LuaProofState>)| {
+                let info = lua.app_data_ref::<LuaTheoremInfo>().unwrap();
+                let thm = info.borrow().thm.out();
+                let tactic_info = tactic_info.out_ref().clone();
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                let kernel_steps = proof.map(|proof| kernel_steps(proof.out_ref(), ctx));
+
+                // Render the proof state now, since the hook can't be called
+                // once the Lua runtime is gone.
+                let steps = kernel_steps.as_ref().unwrap_or(tactic_info.steps());
+                let frags = steps
+                    .iter()
+                    .flat_map(|step| step.frags())
+                    .chain([tactic_info.goal()])
+                    .chain(tactic_info.previous_goal());
+                let renderings = render_frags(lua, frags);
+
+                let new_diag = this
+                    .clone()
+                    .out()
+                    .in_proof(thm, tactic_info, kernel_steps)
+                    .with_frag_renderings(renderings);
+                Ok(LuaDiagnostic::new(new_diag))
+            },
+        );
 
         methods.add_method("report", |lua, this, _: ()| {
             let info = lua.app_data_ref::<LuaTheoremInfo>().unwrap();
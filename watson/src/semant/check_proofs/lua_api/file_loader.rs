@@ -57,6 +57,26 @@ impl LuaFileRequirer {
         components.into_iter().collect()
     }
 
+    /// Check that a normalized path stays inside the source folder. Normalizing
+    /// only removes `..` where there is a folder for it to undo, so a path
+    /// which escapes starts with `..` or is absolute.
+    fn check_contained(path: &Path) -> Result<(), NavigateError> {
+        let escapes = path.components().any(|comp| {
+            matches!(
+                comp,
+                Component::ParentDir | Component::RootDir | Component::Prefix(..)
+            )
+        });
+        if escapes {
+            let message = format!(
+                "can't require `{}` since it is outside the script folder",
+                path.display()
+            );
+            return Err(NavigateError::Other(mlua::Error::runtime(message)));
+        }
+        Ok(())
+    }
+
     /// Resolve a Luau module path to a physical file or directory.
     ///
     /// Empty directories without init files are considered valid as "intermediate" directories.
@@ -84,6 +104,7 @@ impl Require for LuaFileRequirer {
     fn reset(&mut self, chunk_name: &str) -> Result<(), NavigateError> {
         // Slice to remove the @ from the start.
         let chunk_path = Self::normalize_path(chunk_name[1..].as_ref());
+        Self::check_contained(&chunk_path)?;
 
         let abs_path = self.src_folder.join(chunk_path.clone());
         let resolved = Self::resolve_module(&abs_path)?;
@@ -116,7 +137,8 @@ impl Require for LuaFileRequirer {
     }
 
     fn to_child(&mut self, name: &str) -> Result<(), NavigateError> {
-        let rel_path = self.rel_path.join(name);
+        let rel_path = Self::normalize_path(&self.rel_path.join(name));
+        Self::check_contained(&rel_path)?;
         let abs = self.src_folder.join(rel_path.clone());
         let resolved = Self::resolve_module(&abs)?;
 
@@ -149,3 +171,30 @@ impl Require for LuaFileRequirer {
             .into_function()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::LuaFileRequirer;
+    use std::path::Path;
+
+    fn contained(path: &str) -> bool {
+        let path = LuaFileRequirer::normalize_path(Path::new(path));
+        LuaFileRequirer::check_contained(&path).is_ok()
+    }
+
+    #[test]
+    fn paths_inside_the_folder_are_allowed() {
+        assert!(contained("main"));
+        assert!(contained("./lib/util"));
+        assert!(contained("lib/../util"));
+        assert!(contained("./a/./b/../c"));
+    }
+
+    #[test]
+    fn paths_outside_the_folder_are_refused() {
+        assert!(!contained("../util"));
+        assert!(!contained("../../etc/passwd"));
+        assert!(!contained("lib/../../etc/passwd"));
+        assert!(!contained("/etc/passwd"));
+    }
+}
@@ -0,0 +1,111 @@
+//! `Frag.apply`, for building fragments out of formal syntax rules directly
+//! rather than by writing them out and parsing them.
+
+use crate::{
+    context::Ctx,
+    semant::{
+        check_proofs::lua_api::{
+            ctx_to_lua::LuaCtx,
+            frag_to_lua::{LuaPresFrag, LuaPresFragMeta},
+        },
+        formal_syntax::{FormalSyntaxPatPart, FormalSyntaxRuleId},
+        fragment::rule_frag,
+        presentation::PresFrag,
+    },
+    util::{plural, similar_names},
+};
+use itertools::Itertools;
+use mlua::{FromLua, Lua, Value, Variadic};
+use ustr::Ustr;
+
+pub fn add_frag_builder<M: mlua::UserDataMethods<LuaPresFragMeta>>(methods: &mut M) {
+    methods.add_function("apply", |lua, (rule, args): (String, Variadic<Value>)| {
+        let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+        apply_rule(lua, &rule, args, ctx).map(LuaPresFrag::new)
+    });
+}
+
+/// Apply the rule named `name` to `args`, one for each part of its pattern
+/// which isn't a literal. A binding takes the name of the variable, which is
+/// only for the reader since formal syntax doesn't name variables, and a
+/// category takes a fragment of that category.
+fn apply_rule<'ctx>(
+    lua: &Lua,
+    name: &str,
+    args: Variadic<Value>,
+    ctx: &Ctx<'ctx>,
+) -> mlua::Result<PresFrag<'ctx>> {
+    let Some(rule) = ctx.arenas.formal_rules.get(Ustr::from(name)) else {
+        let mut msg = format!("no formal syntax rule named `{name}`");
+        if let Some(candidate) = similar_names(name, ctx.arenas.formal_rules.names()).first() {
+            msg += &format!("; did you mean `{candidate}`?");
+        }
+        return Err(mlua::Error::runtime(msg));
+    };
+
+    let parts: Vec<_> = rule
+        .pattern()
+        .parts()
+        .iter()
+        .filter(|part| !matches!(part, FormalSyntaxPatPart::Lit(_)))
+        .collect();
+    if args.len() != parts.len() {
+        return Err(mlua::Error::runtime(format!(
+            "`{}` takes {} argument{} but was given {}",
+            signature(rule),
+            parts.len(),
+            plural(parts.len()),
+            args.len()
+        )));
+    }
+
+    let mut binders = 0;
+    let mut children = Vec::new();
+    for (i, (part, arg)) in parts.into_iter().zip(args).enumerate() {
+        match *part {
+            FormalSyntaxPatPart::Binding(_) => {
+                if !matches!(arg, Value::String(_)) {
+                    return Err(mlua::Error::runtime(format!(
+                        "argument {} of `{}` binds a variable, so it should be its name but was a {}",
+                        i + 1,
+                        signature(rule),
+                        arg.type_name()
+                    )));
+                }
+                binders += 1;
+            }
+            FormalSyntaxPatPart::Cat(cat) => {
+                let child = LuaPresFrag::from_lua(arg, lua)?.out();
+                if child.frag().cat() != cat {
+                    return Err(mlua::Error::runtime(format!(
+                        "argument {} of `{}` should be a {} but was a {}",
+                        i + 1,
+                        signature(rule),
+                        cat.name(),
+                        child.frag().cat().name()
+                    )));
+                }
+                children.push(child);
+            }
+            FormalSyntaxPatPart::Lit(_) => unreachable!("literals take no argument"),
+        }
+    }
+
+    Ok(rule_frag(rule, binders, children, ctx))
+}
+
+/// The rule written like a function of the arguments `Frag.apply` expects,
+/// e.g. `exists(@binding(term), sentence)`.
+fn signature(rule: FormalSyntaxRuleId) -> String {
+    let params = rule
+        .pattern()
+        .parts()
+        .iter()
+        .filter_map(|part| match part {
+            FormalSyntaxPatPart::Cat(cat) => Some(cat.name().to_string()),
+            FormalSyntaxPatPart::Binding(cat) => Some(format!("@binding({})", cat.name())),
+            FormalSyntaxPatPart::Lit(_) => None,
+        })
+        .join(", ");
+    format!("{}({params})", rule.name())
+}
@@ -1,18 +1,25 @@
-use crate::semant::{
-    check_proofs::lua_api::{
-        command_to_lua::LuaCommandId, ctx_to_lua::LuaCtx, formal_to_lua::LuaFormalCat,
+use crate::{
+    context::Ctx,
+    semant::{
+        check_proofs::lua_api::{
+            command_to_lua::LuaCommandId, ctx_to_lua::LuaCtx, formal_to_lua::LuaFormalCat,
+            frag_builder_to_lua::add_frag_builder,
+        },
+        fragment::{
+            _debug_fragment, FragHead, FragMatch, Fragment, FragmentId, formal_frag, hole_frag,
+            match_frag, substitute_frag, var_frag,
+        },
+        presentation::{
+            BindingNameHints, Pres, PresFrag, PresHead, PresId, change_name_hints,
+            instantiate_holes, instantiate_templates, instantiate_vars, match_presentation,
+            reduce_frag, replace_all, wrap_frag_with_name,
+        },
+        scope::DefinitionSource,
+        theorems::PresFact,
     },
-    fragment::{_debug_fragment, FragHead, Fragment, FragmentId, hole_frag, var_frag},
-    presentation::{
-        BindingNameHints, Pres, PresFrag, PresHead, PresId, change_name_hints, instantiate_holes,
-        instantiate_templates, instantiate_vars, match_presentation, reduce_frag,
-        wrap_frag_with_name,
-    },
-    scope::DefinitionSource,
-    theorems::PresFact,
 };
 use itertools::Itertools;
-use mlua::{FromLua, MetaMethod, UserData};
+use mlua::{FromLua, MetaMethod, UserData, Value};
 use rustc_hash::FxHashMap;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, FromLua)]
@@ -137,20 +144,48 @@ impl UserData for LuaPresFrag {
             Ok(LuaPresFrag::new(reduced))
         });
 
+        methods.add_method("match", |_, this, pattern: LuaPresFrag| {
+            Ok(match_holes(this.out(), pattern.out()))
+        });
+
         methods.add_method(
-            "match",
-            |_, this, pattern: LuaPresFrag| match match_presentation(this.out(), pattern.out()) {
-                Some(matches) => {
-                    let map = matches
-                        .into_iter()
-                        .map(|(idx, f)| (idx, LuaPresFrag::new(f)))
-                        .collect::<FxHashMap<usize, LuaPresFrag>>();
-                    Ok(Some(map))
+            "replaceAll",
+            |lua, this, (pattern, replacement): (LuaPresFrag, LuaPresFrag)| {
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                let (pattern, replacement) = (pattern.out(), replacement.out());
+                if pattern.frag().cat() != replacement.frag().cat() {
+                    return Err(mlua::Error::runtime(format!(
+                        "can't replace a `{}` with a `{}`",
+                        pattern.frag().cat().name(),
+                        replacement.frag().cat().name()
+                    )));
                 }
-                None => Ok(None),
+                let (frag, count) =
+                    replace_all(this.out(), pattern, replacement, ctx).map_err(|idx| {
+                        mlua::Error::runtime(format!(
+                            "the replacement uses the hole `_{idx}` which isn't in the pattern"
+                        ))
+                    })?;
+                Ok((LuaPresFrag::new(frag), count))
             },
         );
 
+        methods.add_method("substitute", |lua, this, mapping: mlua::Table| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            let substitution = frag_match_from_lua(lua, mapping)?;
+            let frag = substitute_frag(this.out().frag(), &substitution, ctx).map_err(|head| {
+                let name = match head {
+                    FragHead::TemplateRef(idx) => format!("${idx}"),
+                    FragHead::Hole(idx) => format!("_{idx}"),
+                    _ => unreachable!("only holes and templates are substituted"),
+                };
+                mlua::Error::runtime(format!(
+                    "can't substitute `{name}` since it is applied to arguments or has a different category"
+                ))
+            })?;
+            Ok(LuaPresFrag::new(formal_frag(frag, ctx)))
+        });
+
         methods.add_meta_method(MetaMethod::ToString, |_, this, _args: ()| {
             Ok(this.out().print())
         });
@@ -161,6 +196,59 @@ impl UserData for LuaPresFrag {
     }
 }
 
+/// Match `haystack` against `pattern` with [`match_presentation`], giving
+/// what each hole matched as a table from hole indices to fragments.
+pub fn match_holes<'ctx>(
+    haystack: PresFrag<'ctx>,
+    pattern: PresFrag<'ctx>,
+) -> Option<FxHashMap<usize, LuaPresFrag>> {
+    let matches = match_presentation(haystack, pattern)?;
+    let holes = matches
+        .into_iter()
+        .map(|(idx, frag)| (idx, LuaPresFrag::new(frag)))
+        .collect();
+    Some(holes)
+}
+
+/// Holes are keyed by their index and templates by `$` followed by their
+/// index, matching how they are printed for debugging.
+fn frag_match_to_lua<'ctx>(
+    lua: &mlua::Lua,
+    found: &FragMatch<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> mlua::Result<mlua::Table> {
+    let table = lua.create_table()?;
+    for (&idx, &frag) in &found.holes {
+        table.set(idx, LuaPresFrag::new(formal_frag(frag, ctx)))?;
+    }
+    for (&idx, &frag) in &found.templates {
+        table.set(format!("${idx}"), LuaPresFrag::new(formal_frag(frag, ctx)))?;
+    }
+    Ok(table)
+}
+
+fn frag_match_from_lua<'ctx>(lua: &mlua::Lua, table: mlua::Table) -> mlua::Result<FragMatch<'ctx>> {
+    let mut found = FragMatch::default();
+    for pair in table.pairs::<Value, LuaPresFrag>() {
+        let (key, frag) = pair?;
+        let frag = frag.out().frag();
+        match key {
+            Value::String(key) => {
+                let key = key.to_string_lossy();
+                let idx = key
+                    .strip_prefix('$')
+                    .and_then(|idx| idx.parse().ok())
+                    .ok_or_else(|| mlua::Error::runtime(format!("invalid template key `{key}`")))?;
+                found.templates.insert(idx, frag);
+            }
+            key => {
+                found.holes.insert(usize::from_lua(key, lua)?, frag);
+            }
+        }
+    }
+    Ok(found)
+}
+
 pub struct LuaPresFragMeta;
 
 impl UserData for LuaPresFragMeta {
@@ -176,6 +264,19 @@ impl UserData for LuaPresFragMeta {
             let frag = hole_frag(idx, cat.out(), Vec::new(), ctx);
             Ok(LuaPresFrag::new(frag))
         });
+
+        methods.add_function(
+            "match",
+            |lua, (pattern, subject): (LuaPresFrag, LuaPresFrag)| {
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                match match_frag(pattern.out().frag(), subject.out().frag(), ctx) {
+                    Some(found) => Ok(Some(frag_match_to_lua(lua, &found, ctx)?)),
+                    None => Ok(None),
+                }
+            },
+        );
+
+        add_frag_builder(methods);
     }
 }
 
@@ -331,6 +432,14 @@ impl UserData for LuaDefinitionSource {
             Ok(cmd)
         });
 
+        fields.add_field_method_get("isAbbreviationCmd", |_, this| {
+            let cmd = match this.out() {
+                DefinitionSource::AbbreviationCmd(cmd) => Some(LuaCommandId::new(cmd)),
+                _ => None,
+            };
+            Ok(cmd)
+        });
+
         fields.add_field_method_get("isSyntaxCmd", |_, this| {
             let cmd = match this.out() {
                 DefinitionSource::SyntaxCmd(cmd) => Some(LuaCommandId::new(cmd)),
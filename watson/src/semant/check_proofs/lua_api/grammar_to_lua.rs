@@ -1,4 +1,5 @@
 use crate::{
+    parse::parse_state::{Associativity, Precedence},
     semant::{
         check_proofs::lua_api::{
             span_to_lua::LuaSpan,
@@ -7,12 +8,13 @@ use crate::{
         custom_grammar::{
             inst::{CustomGrammarInst, CustomGrammarInstPart, SpannedStr},
             manager::CustomGrammarManager,
-            syntax::CustomGrammarPatPartCore,
+            syntax::{CustomGrammarPatPartCore, CustomGrammarRuleId},
         },
     },
     strings,
 };
 use mlua::{IntoLua, Lua, UserData, Value};
+use ustr::Ustr;
 
 impl<'ctx> IntoLua for &CustomGrammarInst<'ctx> {
     fn into_lua(self, lua: &Lua) -> mlua::Result<Value> {
@@ -65,6 +67,10 @@ impl<'ctx> IntoLua for &CustomGrammarInstPart<'ctx> {
                 let lua_fact = LuaUnresolvedFact::new(fact);
                 lua_fact.into_lua(lua)
             }
+            CustomGrammarInstPart::Many(items) => {
+                // Repeated parts are passed as a list, in source order.
+                lua.create_sequence_from(items.iter()).map(Value::Table)
+            }
         }
     }
 }
@@ -90,32 +96,76 @@ pub fn generate_luau_grammar_types<'ctx>(grammar: &CustomGrammarManager<'ctx>) -
         }
 
         out.push_str(&format!("export type {name} =\n"));
-        for rule in rules {
-            let rule_name = rule.name();
-            out.push_str(&format!("  | {{ _rule: \"{rule_name}\", _span: Span"));
-
-            for part in rule.pattern().parts() {
-                use CustomGrammarPatPartCore as C;
-
-                let Some(label) = part.label() else {
-                    continue;
-                };
-
-                let luau_type = match part.part() {
-                    C::Lit(_) | C::Kw(_) | C::Name => *strings::SPANNED_STRING,
-                    C::Cat(cat) => cat.lua_name(),
-                    C::Frag(_) => *strings::UN_FRAG,
-                    C::AnyFrag => *strings::UN_ANY_FRAG,
-                    C::Fact => *strings::UN_FACT,
-                };
-
-                out.push_str(&format!(", {label}: {luau_type}"));
-            }
-
-            out.push_str(" }\n");
+        for &rule in rules {
+            let pat = rule.pattern();
+            out.push_str(&format!(
+                "  | {} -- {}\n",
+                rule_luau_type(rule),
+                describe_prec_assoc(pat.precedence(), pat.associativity())
+            ));
         }
         out.push('\n');
     }
 
+    // The optional per-rule handlers the main module can register in its
+    // `tactics` table, for the rules of each category a proof is written in.
+    out.push_str("export type TacticHandlers = {\n");
+    let proof_rules = grammar
+        .proof_cats()
+        .iter()
+        .flat_map(|&cat| grammar.rules_for_cat(cat));
+    for &rule in proof_rules {
+        out.push_str(&format!(
+            "  [\"{}\"]: ((tactic: {}, proofState: ProofState, tacticInfo: TacticInfo) -> ProofState)?,\n",
+            rule.name(),
+            rule_luau_type(rule)
+        ));
+    }
+    out.push_str("}\n");
+
     out
 }
+
+/// How a rule takes part in parsing, so that the precedence and
+/// associativity the parser actually uses can be checked in the types file.
+fn describe_prec_assoc(prec: Precedence, assoc: Associativity) -> String {
+    let assoc = match assoc {
+        Associativity::Left => "left associative",
+        Associativity::Right => "right associative",
+        Associativity::NonAssoc => "non-associative",
+    };
+    format!("precedence {}, {assoc}", prec.0)
+}
+
+/// The Luau type of the table a rule is passed to Lua as.
+pub fn rule_luau_type<'ctx>(rule: CustomGrammarRuleId<'ctx>) -> String {
+    let rule_name = rule.name();
+    let mut out = format!("{{ _rule: \"{rule_name}\", _span: Span");
+    for (label, luau_type) in rule_luau_fields(rule) {
+        out.push_str(&format!(", {label}: {luau_type}"));
+    }
+    out.push_str(" }");
+    out
+}
+
+/// The labeled parts of a rule with their Luau types, in order.
+pub fn rule_luau_fields<'ctx>(rule: CustomGrammarRuleId<'ctx>) -> Vec<(Ustr, Ustr)> {
+    rule.pattern()
+        .parts()
+        .iter()
+        .filter_map(|part| Some((part.label()?, part_luau_type(part.part()))))
+        .collect()
+}
+
+fn part_luau_type(part: &CustomGrammarPatPartCore<'_>) -> Ustr {
+    use CustomGrammarPatPartCore as C;
+
+    match part {
+        C::Lit(_) | C::Kw(_) | C::Name => *strings::SPANNED_STRING,
+        C::Cat(cat) => cat.lua_name(),
+        C::Frag(_) => *strings::UN_FRAG,
+        C::AnyFrag => *strings::UN_ANY_FRAG,
+        C::Fact => *strings::UN_FACT,
+        C::Many(repeated) => Ustr::from(&format!("{{{}}}", part_luau_type(repeated))),
+    }
+}
@@ -1,35 +1,51 @@
+//! The Lua runtime tactics run in.
+//!
+//! Tactics are trusted to be correct but not to be harmless: a proof is only
+//! accepted once the kernel has checked it, so a tactic can fail to prove
+//! something but can't prove something false. What the runtime limits is what
+//! a tactic can do to the machine checking it, so that it is safe to check
+//! contributions nobody has read yet. Only the parts of the standard library
+//! in `[check] lua_stdlib` are loaded, none of which touch the file system or
+//! other processes, and `require` only loads modules from inside the script
+//! folder. Everything else a tactic can reach goes through the API below.
+
 use crate::{
+    config::LuaStdLib,
     context::{Arenas, Ctx},
     diagnostics::{Diagnostic, WResult},
     semant::{
         attributes::AttributeTracker,
-        check_proofs::{
-            LuaTheoremInfo,
-            lua_api::{
-                attributes_to_lua::{LuaAttributeTracker, LuaAttributeTrackerMeta},
-                ctx_to_lua::LuaCtx,
-                diag_to_lua::LuaDiagnosticMeta,
-                file_loader::LuaFileRequirer,
-                formal_to_lua::LuaFormalCatMeta,
-                frag_map_to_lua::{LuaFactMapMeta, LuaFragMapMeta},
-                frag_to_lua::{LuaPresFactMeta, LuaPresFragMeta},
-                grammar_to_lua::generate_luau_grammar_types,
-                notation_to_lua::LuaNotationBindingMeta,
-                scope_to_lua::{LuaScope, LuaScopeMeta},
-                theorem_to_lua::LuaTheoremMeta,
-                unresolved_to_lua::LuaUnResFragMeta,
-                vampire_to_lua::{
-                    LuaVFormulaMeta, LuaVFunctionMeta, LuaVOptionsMeta, LuaVPredicateMeta,
-                    LuaVProblemMeta, LuaVTermMeta,
-                },
+        check_proofs::lua_api::{
+            attributes_to_lua::{LuaAttributeTracker, LuaAttributeTrackerMeta},
+            ctx_to_lua::LuaCtx,
+            diag_to_lua::LuaDiagnosticMeta,
+            file_loader::LuaFileRequirer,
+            formal_to_lua::LuaFormalCatMeta,
+            frag_map_to_lua::{LuaFactMapMeta, LuaFragMapMeta},
+            frag_to_lua::{LuaPresFactMeta, LuaPresFragMeta},
+            grammar_to_lua::generate_luau_grammar_types,
+            notation_to_lua::LuaNotationBindingMeta,
+            render_hook::LuaRenderHook,
+            scope_to_lua::{LuaScope, LuaScopeMeta},
+            source_to_lua::LuaSourceMeta,
+            theorem_to_lua::{LuaTheoremMeta, LuaTheoremOrder},
+            unresolved_to_lua::LuaUnResFragMeta,
+            vampire_to_lua::{
+                LuaVFormulaMeta, LuaVFunctionMeta, LuaVOptionsMeta, LuaVPredicateMeta,
+                LuaVProblemMeta, LuaVTermMeta,
             },
         },
         scope::Scope,
     },
-    util::ansi::{ANSI_BOLD, ANSI_RESET, ANSI_YELLOW},
+    util::{
+        ansi::{ANSI_BOLD, ANSI_RESET, ANSI_YELLOW},
+        similar_names,
+    },
 };
 use mlua::{Lua, LuaOptions, StdLib};
-use std::{fs, ops::Deref};
+use rustc_hash::FxHashMap;
+use std::{fs, ops::Deref, path::Path, time::Instant};
+use ustr::Ustr;
 
 pub mod attributes_to_lua;
 pub mod command_to_lua;
@@ -37,12 +53,15 @@ pub mod ctx_to_lua;
 pub mod diag_to_lua;
 mod file_loader;
 pub mod formal_to_lua;
+pub mod frag_builder_to_lua;
 pub mod frag_map_to_lua;
 pub mod frag_to_lua;
 pub mod grammar_to_lua;
 pub mod notation_to_lua;
 pub mod proof_to_lua;
+pub mod render_hook;
 pub mod scope_to_lua;
+pub mod source_to_lua;
 pub mod span_to_lua;
 pub mod tactic_info_to_lua;
 pub mod theorem_to_lua;
@@ -81,7 +100,8 @@ impl<'ctx> Diagnostic<'ctx> {
         let diag = Diagnostic::new(
             &format!(
                 "bad return value from main lua module.
-Expected main module to return a lua table with field `handleTactic`.
+Expected main module to return a lua table with field `handleTactic`, field
+`tactics` mapping tactic rule names to handlers, or both.
 Instead it returned:
 {got:#?}"
             ),
@@ -90,6 +110,20 @@ Instead it returned:
         Err(vec![diag])
     }
 
+    pub fn err_unknown_tactic_handler<T>(name: &str, ctx: &Ctx<'ctx>) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!("handler registered in `tactics` for unknown tactic rule `{name}`"),
+            vec![],
+        );
+
+        let rules = tactic_rule_names(ctx);
+        for candidate in similar_names(name, rules) {
+            diag = diag.with_info(&format!("did you mean `{candidate}`?"), Vec::new());
+        }
+
+        Err(vec![diag])
+    }
+
     pub fn err_lua_execution_error<T>(lua_ctx: &str, error: mlua::Error) -> WResult<'ctx, T> {
         let diag = Diagnostic::new(&format!("lua error executing {lua_ctx}:\n{error}"), vec![]);
 
@@ -100,33 +134,43 @@ Instead it returned:
 #[derive(Debug)]
 pub struct LuaInfo<'ctx> {
     pub runtime: WLua<'ctx>,
-    pub handle_tactic_fn: mlua::Function,
+    handle_tactic_fn: Option<mlua::Function>,
+    tactic_handlers: FxHashMap<Ustr, mlua::Function>,
+}
+
+impl<'ctx> LuaInfo<'ctx> {
+    /// The handler registered for the given top-level tactic rule, falling
+    /// back to `handleTactic`.
+    pub fn handler_for(&self, rule: Ustr) -> Option<&mlua::Function> {
+        self.tactic_handlers
+            .get(&rule)
+            .or(self.handle_tactic_fn.as_ref())
+    }
 }
 
 pub fn setup_lua<'ctx>(
     ctx: &Ctx<'ctx>,
     scope: Scope<'ctx>,
     attributes: AttributeTracker<'ctx>,
+    theorem_order: LuaTheoremOrder,
+    lua_entry: &Path,
 ) -> WResult<'ctx, LuaInfo<'ctx>> {
     // Write out types
     write_luau_types(ctx);
 
     // Initialize the Lua runtime.
-    let lua = Lua::new_with(
-        StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::BIT | StdLib::MATH,
-        LuaOptions::new(),
-    )
-    .or_else(Diagnostic::err_lua_load_error)?;
+    let lua = new_lua(ctx.config.lua_stdlib()).or_else(Diagnostic::err_lua_load_error)?;
 
     // Add the ctx as app data.
     let lua_ctx = LuaCtx::new(ctx);
     lua.set_app_data(lua_ctx);
 
-    // Add the final scope & attributes as app data
+    // Add the final scope, attributes & theorems as app data
     let lua_scope = LuaScope::new(scope);
     lua.set_app_data(lua_scope);
     let lua_attribute_tracker = LuaAttributeTracker::new(attributes);
     lua.set_app_data(lua_attribute_tracker);
+    lua.set_app_data(theorem_order);
 
     // Set up the custom log function
     add_log_fn(&lua);
@@ -147,6 +191,7 @@ pub fn setup_lua<'ctx>(
     lua.globals().set("FactMap", LuaFactMapMeta).unwrap();
     lua.globals().set("Theorem", LuaTheoremMeta).unwrap();
     lua.globals().set("FormalCat", LuaFormalCatMeta).unwrap();
+    lua.globals().set("Source", LuaSourceMeta).unwrap();
 
     // Set up vampire theorem prover metatables.
     lua.globals().set("VFunction", LuaVFunctionMeta).unwrap();
@@ -162,34 +207,87 @@ pub fn setup_lua<'ctx>(
     let require = lua.create_require_function(require).unwrap();
     lua.globals().set("require", require).unwrap();
 
-    // Load the root file
-    let lua_root = src_folder.join("main.luau");
-    let chunk = lua.load(lua_root).set_name("@main");
-    let result = chunk.call(()).or_else(Diagnostic::err_lua_load_error)?;
+    // Load the root file. Anything logged while loading it is shown under
+    // its own header.
+    lua.set_app_data(LuaLogs::new("logs while loading tactics".to_string()));
+    let chunk = lua.load(lua_entry).set_name("@main");
+    let result = chunk.call(());
+    if let Some(logs) = lua.remove_app_data::<LuaLogs>() {
+        logs.flush(ctx.config.quiet_tactics() && result.is_ok());
+    }
+    let result = result.or_else(Diagnostic::err_lua_load_error)?;
 
     let wlua = WLua {
         lua,
         _arenas: ctx.arenas,
     };
 
-    read_main_module(wlua, result)
+    read_main_module(ctx, wlua, result)
+}
+
+/// A Lua runtime with only the given parts of the standard library.
+fn new_lua(stdlib: &[LuaStdLib]) -> mlua::Result<Lua> {
+    let mut libs = StdLib::NONE;
+    for lib in stdlib {
+        libs |= match lib {
+            LuaStdLib::Table => StdLib::TABLE,
+            LuaStdLib::String => StdLib::STRING,
+            LuaStdLib::Utf8 => StdLib::UTF8,
+            LuaStdLib::Bit => StdLib::BIT,
+            LuaStdLib::Math => StdLib::MATH,
+            LuaStdLib::OsClock => StdLib::NONE,
+        };
+    }
+    let lua = Lua::new_with(libs, LuaOptions::new())?;
+
+    if stdlib.contains(&LuaStdLib::OsClock) {
+        // The seconds since the runtime started, which is all `os.clock` is
+        // good for.
+        let start = Instant::now();
+        let clock = lua.create_function(move |_, ()| Ok(start.elapsed().as_secs_f64()))?;
+        let os = lua.create_table()?;
+        os.set("clock", clock)?;
+        lua.globals().set("os", os)?;
+    }
+
+    Ok(lua)
+}
+
+/// The output of `log` and `print` while running some Lua code. It is
+/// buffered so that it is shown together under a header saying what produced
+/// it, and so that it can be hidden unless something fails.
+pub struct LuaLogs {
+    header: String,
+    lines: Vec<String>,
+}
+
+impl LuaLogs {
+    pub fn new(header: String) -> Self {
+        Self {
+            header,
+            lines: Vec::new(),
+        }
+    }
+
+    /// Print the logs, unless there are none or they are `quiet`.
+    pub fn flush(self, quiet: bool) {
+        if quiet || self.lines.is_empty() {
+            return;
+        }
+
+        eprintln!("{ANSI_BOLD}{ANSI_YELLOW}{}{ANSI_RESET}", self.header);
+        for line in self.lines {
+            eprintln!("{line}");
+        }
+        // Add blank space after logs.
+        eprintln!();
+    }
 }
 
 fn add_log_fn(lua: &Lua) {
     // Create custom log function
     let log_fn = lua
         .create_function(move |lua, args: mlua::Variadic<mlua::Value>| {
-            let info = lua.app_data_ref::<LuaTheoremInfo>().unwrap();
-            let mut info = info.borrow_mut();
-            if !info.has_logs {
-                eprintln!(
-                    "{ANSI_BOLD}{ANSI_YELLOW}logs for {}{ANSI_RESET}",
-                    info.thm.out().name()
-                );
-                info.has_logs = true;
-            }
-            drop(info);
-
             let mut log_parts = Vec::new();
             for arg in args.iter() {
                 let formatted = match arg {
@@ -202,29 +300,82 @@ fn add_log_fn(lua: &Lua) {
                 log_parts.push(formatted);
             }
             let str = log_parts.join(" ");
-            eprintln!("{str}");
+            match lua.app_data_mut::<LuaLogs>() {
+                Some(mut logs) => logs.lines.push(str),
+                None => eprintln!("{str}"),
+            }
             Ok(())
         })
         .unwrap();
 
-    // Set up global functions
-    lua.globals().set("log", log_fn).unwrap();
+    // Set up global functions. `print` is the same as `log` so that its
+    // output is attributed to the theorem being checked.
+    lua.globals().set("log", log_fn.clone()).unwrap();
+    lua.globals().set("print", log_fn).unwrap();
 }
 
-fn read_main_module<'ctx>(lua: WLua<'ctx>, module: mlua::Value) -> WResult<'ctx, LuaInfo<'ctx>> {
-    let table = module
-        .as_table()
-        .ok_or_else(|| Diagnostic::err_bad_module_ret::<()>(&module).unwrap_err())?;
-    let handle_tactic_fn: mlua::Function = table
-        .get("handleTactic")
-        .map_err(|_| Diagnostic::err_bad_module_ret::<()>(&module).unwrap_err())?;
+fn read_main_module<'ctx>(
+    ctx: &Ctx<'ctx>,
+    lua: WLua<'ctx>,
+    module: mlua::Value,
+) -> WResult<'ctx, LuaInfo<'ctx>> {
+    let bad_ret = || Diagnostic::err_bad_module_ret::<()>(&module).unwrap_err();
+
+    let table = module.as_table().ok_or_else(bad_ret)?;
+    let handle_tactic_fn: Option<mlua::Function> =
+        table.get("handleTactic").map_err(|_| bad_ret())?;
+    let tactics: Option<mlua::Table> = table.get("tactics").map_err(|_| bad_ret())?;
+    let render_frag: Option<mlua::Function> = table.get("renderFrag").map_err(|_| bad_ret())?;
+
+    if handle_tactic_fn.is_none() && tactics.is_none() {
+        return Err(bad_ret());
+    }
+
+    // Handlers are keyed by the name of the top-level tactic rule they handle.
+    // Check the names now so a typo doesn't silently fall through to
+    // `handleTactic` for every proof using that rule.
+    let mut tactic_handlers = FxHashMap::default();
+    let mut diags = Vec::new();
+    let known_rules = tactic_rule_names(ctx);
+    for pair in tactics
+        .iter()
+        .flat_map(|t| t.pairs::<String, mlua::Function>())
+    {
+        let (name, handler) = pair.map_err(|_| bad_ret())?;
+        if !known_rules.iter().any(|rule| rule.as_str() == name) {
+            diags.extend(Diagnostic::err_unknown_tactic_handler::<()>(&name, ctx).unwrap_err());
+            continue;
+        }
+        tactic_handlers.insert(Ustr::from(&name), handler);
+    }
+
+    if !diags.is_empty() {
+        return Err(diags);
+    }
+
+    if let Some(render_frag) = render_frag {
+        lua.set_app_data(LuaRenderHook::new(render_frag));
+    }
 
     Ok(LuaInfo {
         runtime: lua,
         handle_tactic_fn,
+        tactic_handlers,
     })
 }
 
+/// The rules a proof can start with, which are those handlers can be
+/// registered for.
+fn tactic_rule_names<'ctx>(ctx: &Ctx<'ctx>) -> Vec<Ustr> {
+    let grammar = &ctx.custom_grammar_manager;
+    grammar
+        .proof_cats()
+        .iter()
+        .flat_map(|&cat| grammar.rules_for_cat(cat))
+        .map(|rule| rule.name())
+        .collect()
+}
+
 fn write_luau_types<'ctx>(ctx: &Ctx<'ctx>) {
     let definitions_file = include_str!("./definitions.d.luau");
     let types_content = generate_luau_grammar_types(&ctx.custom_grammar_manager);
@@ -241,3 +392,250 @@ fn write_luau_types<'ctx>(ctx: &Ctx<'ctx>) {
         fs::write(types_path, new_def_file).expect("TODO");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::new_lua;
+    use crate::{
+        config::LuaStdLib, context::Arenas, driver::Driver, util::test_project::TestProject,
+    };
+
+    /// The globals which would let a tactic reach the file system, other
+    /// processes or the internals of the runtime.
+    const UNSAFE: &[&str] = &["io", "debug", "package", "loadfile", "dofile", "load"];
+
+    #[test]
+    fn default_stdlib_leaves_out_unsafe_globals() {
+        let config = TestProject::new("").config();
+        let lua = new_lua(config.lua_stdlib()).unwrap();
+        for name in UNSAFE.iter().chain(&["os"]) {
+            assert!(
+                lua.globals().get::<mlua::Value>(*name).unwrap().is_nil(),
+                "{name}"
+            );
+        }
+        for name in ["table", "string", "utf8", "bit32", "math"] {
+            assert!(
+                !lua.globals().get::<mlua::Value>(name).unwrap().is_nil(),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn os_clock_is_all_of_os() {
+        let lua = new_lua(&[LuaStdLib::OsClock]).unwrap();
+        for name in UNSAFE {
+            assert!(
+                lua.globals().get::<mlua::Value>(*name).unwrap().is_nil(),
+                "{name}"
+            );
+        }
+        let os: mlua::Table = lua.globals().get("os").unwrap();
+        let keys: Vec<String> = os
+            .pairs::<String, mlua::Value>()
+            .map(|p| p.unwrap().0)
+            .collect();
+        assert_eq!(keys, ["clock"]);
+        let clock: f64 = lua.load("return os.clock()").eval().unwrap();
+        assert!(clock >= 0.0);
+    }
+
+    /// The titles of the errors from checking a project whose main script
+    /// first requires `lib/util.luau`, which is `util`.
+    fn load_errors(util: &str) -> Vec<String> {
+        let project = TestProject::new("");
+        project.write("script/lib/util.luau", util);
+        let main = project.read("script/main.luau");
+        project.write(
+            "script/main.luau",
+            &format!("local _ = require(\"./lib/util\")\n{main}"),
+        );
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        (checked.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn require_loads_modules_in_the_script_folder() {
+        assert_eq!(load_errors("return {}\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn require_cannot_leave_the_script_folder() {
+        let errors = load_errors("return require(\"../../../etc/passwd\")\n");
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("lua error while loading"),
+            "{}",
+            errors[0]
+        );
+    }
+
+    /// Two theorems, proved with the tactic rules `plain_done` and `boxed`.
+    const TWO_RULES: &str = "module logic\n\n\
+        tactic plain_done\n    tactic ::= \"□\"\nend\n\n\
+        tactic boxed\n    tactic ::= \"■\"\nend\n\n\
+        theorem plain [p : sentence] :\n    |- p → p\nproof\n    □\nqed\n\n\
+        theorem filled [p : sentence] :\n    |- p → p\nproof\n    ■\nqed\n";
+
+    /// Check `TWO_RULES` with a main module which has `handle_tactic` as its
+    /// `handleTactic`, if given, and `tactics` as its table of handlers.
+    /// Returns each error with its notes, and the theorems which were proved.
+    fn check_handlers(
+        handle_tactic: Option<&str>,
+        tactics: &str,
+    ) -> (Vec<Vec<String>>, Vec<String>) {
+        let handle_tactic = handle_tactic
+            .map(|body| {
+                format!(
+                    "function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+                     \x20   {body}\n\
+                     end\n\n"
+                )
+            })
+            .unwrap_or_default();
+        let project = TestProject::with_logic(TWO_RULES);
+        project.write(
+            "script/main.luau",
+            &format!(
+                "local M = {{}}\n\n\
+                 local function say(msg: string, tactic: Tactic, proofState: ProofState)\n\
+                 \x20   Diagnostic:new(msg, tactic._span:label(\"\")):report()\n\
+                 \x20   return proofState:applyTodo(proofState.goal)\n\
+                 end\n\n\
+                 {handle_tactic}\
+                 M.tactics = {tactics} :: TacticHandlers\n\n\
+                 return M\n"
+            ),
+        );
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let errors = (checked.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.parts().map(|part| part.title().to_string()).collect())
+            .collect();
+        // Leave out the axioms of `logic`.
+        let proved = (checked.proof_report().statuses.iter())
+            .filter(|(thm, status)| {
+                status.correct() && ["plain", "filled"].contains(&thm.name().as_str())
+            })
+            .map(|(thm, _)| thm.name().to_string())
+            .collect();
+        (errors, proved)
+    }
+
+    #[test]
+    fn unregistered_rules_fall_back_to_handle_tactic() {
+        let (errors, proved) = check_handlers(
+            Some("return say(`fallback for {tactic._rule}`, tactic, proofState)"),
+            "{\n\
+             \x20   boxed = function(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20       return say(\"registered\", tactic, proofState)\n\
+             \x20   end,\n\
+             }",
+        );
+        assert_eq!(errors, [["fallback for plain_done"], ["registered"]]);
+        assert_eq!(proved, ["plain", "filled"]);
+    }
+
+    #[test]
+    fn handlers_for_unknown_rules_are_rejected_at_setup() {
+        // The handler is never run, so it doesn't matter that it is wrong.
+        let (errors, proved) = check_handlers(
+            None,
+            "{\n\
+             \x20   plain_don = function(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20       return proofState\n\
+             \x20   end,\n\
+             }",
+        );
+        assert_eq!(
+            errors,
+            [[
+                "handler registered in `tactics` for unknown tactic rule `plain_don`",
+                "did you mean `plain_done`?",
+            ]]
+        );
+        assert_eq!(proved, Vec::<String>::new());
+    }
+
+    #[test]
+    fn handler_errors_are_reported_in_their_theorem() {
+        let (errors, proved) = check_handlers(
+            None,
+            "{\n\
+             \x20   plain_done = function(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20       return proofState:applyTodo(proofState.goal)\n\
+             \x20   end,\n\
+             \x20   boxed = function(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20       error(\"stuck\")\n\
+             \x20   end,\n\
+             }",
+        );
+        assert_eq!(errors.len(), 1);
+        let error = &errors[0][0];
+        assert!(
+            error.starts_with("lua error executing tactic `boxed` in proof of theorem `filled`:\n"),
+            "{error}"
+        );
+        assert!(error.contains("stuck"), "{error}");
+        assert_eq!(proved, ["plain"]);
+    }
+
+    #[test]
+    fn sources_can_be_read_but_not_files() {
+        // Columns count characters, so the `¬`s and the `→` count as one
+        // each even though they take more than one byte.
+        let main = "module logic\n\n\
+            theorem double.neg [p : sentence] : (¬¬p)\n    |- p\n\
+            proof\n    have ¬¬p → p by dne;\nqed\n";
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local function say(msg: string)\n\
+            \x20       Diagnostic:new(msg, tactic._span:label(\"\")):report()\n\
+            \x20   end\n\
+            \x20   local pos = Source:lineCol(tactic.thm.span)\n\
+            \x20   say(`at {pos.startLine}:{pos.startCol}-{pos.endLine}:{pos.endCol}`)\n\
+            \x20   say(`snippet:\\n{Source:snippet(tactic.thm.span, 1)}`)\n\
+            \x20   say(`no context: {Source:snippet(tactic.thm.span)}`)\n\
+            \x20   say(`main has {#Source:text(\"main\")} bytes`)\n\
+            \x20   say(`unknown: {Source:text(\"nope\")}`)\n\
+            \x20   say(`file: {Source:text(\"../watson.toml\")}`)\n\
+            \x20   return proofState:applyTodo(proofState.goal)\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let said: Vec<_> = (checked.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title().to_string())
+            .collect();
+        assert_eq!(
+            said,
+            [
+                "at 6:21-6:24".to_string(),
+                "snippet:\nproof\n    have ¬¬p → p by dne;\nqed".to_string(),
+                "no context:     have ¬¬p → p by dne;".to_string(),
+                format!("main has {} bytes", main.len()),
+                "unknown: nil".to_string(),
+                "file: nil".to_string(),
+            ]
+        );
+    }
+}
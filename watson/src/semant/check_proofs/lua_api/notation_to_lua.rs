@@ -41,6 +41,19 @@ impl UserData for LuaNotationBinding {
                 .collect_vec();
             Ok(names)
         });
+
+        fields.add_field_method_get("aliases", |lua, this| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            let pattern = this.out().pattern();
+            let aliases = ctx
+                .notations
+                .values()
+                .filter(|notation| notation.alias_of() == Some(pattern))
+                .map(|notation| notation.name().to_string())
+                .sorted()
+                .collect_vec();
+            Ok(aliases)
+        });
     }
 }
 
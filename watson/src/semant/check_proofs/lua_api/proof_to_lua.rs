@@ -1,11 +1,26 @@
-use crate::semant::{
-    check_proofs::lua_api::{
-        ctx_to_lua::LuaCtx, frag_to_lua::LuaPresFrag, theorem_to_lua::LuaTheorem,
+use crate::{
+    context::Ctx,
+    parse::Span,
+    semant::{
+        check_proofs::{
+            LuaTheoremInfo,
+            lua_api::{
+                ctx_to_lua::LuaCtx,
+                frag_to_lua::{LuaPresFact, LuaPresFrag, match_holes},
+                span_to_lua::LuaSpan,
+                theorem_to_lua::LuaTheorem,
+            },
+        },
+        fragment::{AbbreviationId, FragmentId, find_abbreviation, formal_frag},
+        presentation::PresFrag,
+        proof_kernel::{AuditEntry, AuditStep, ProofError, ProofState, TodoNote},
+        theorems::PresFact,
     },
-    proof_kernel::ProofState,
 };
 use itertools::Itertools;
-use mlua::{FromLua, UserData};
+use mlua::{FromLua, UserData, Value};
+use std::time::Instant;
+use ustr::Ustr;
 
 #[derive(Debug, Clone, FromLua)]
 pub struct LuaProofState {
@@ -37,69 +52,329 @@ impl UserData for LuaProofState {
         fields.add_field_method_get("theorem", |_, this| {
             Ok(LuaTheorem::new(this.out_ref().theorem()))
         });
+        fields.add_field_method_get("goal", |lua, this| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            Ok(LuaPresFrag::new(formal_frag(this.out_ref().goal(), ctx)))
+        });
     }
 
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
-        methods.add_method("addAssumption", |lua, this, assumption: LuaPresFrag| {
-            let assumption = assumption.out();
+        methods.add_method("auditLog", |lua, this, _: ()| {
             let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
-
-            let new_state = this
+            let entries: mlua::Result<Vec<_>> = this
                 .out_ref()
-                .add_assumption(assumption.frag(), ctx)
-                .expect("TODO");
-            Ok(LuaProofState::new(new_state))
+                .audit_log()
+                .iter()
+                .map(|entry| audit_entry_to_lua(lua, entry, ctx))
+                .collect();
+            lua.create_sequence_from(entries?)
         });
 
-        methods.add_method("popAssumption", |lua, this, justifying: LuaPresFrag| {
-            let justifying = justifying.out();
+        methods.add_method("knows", |lua, this, fact: LuaPresFact| {
             let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            Ok(this.out_ref().knows(fact.out().fact(), ctx))
+        });
 
-            let new_state = this
-                .out_ref()
-                .pop_assumption(justifying.frag(), ctx)
-                .expect("TODO");
-            Ok(LuaProofState::new(new_state))
+        // The goal is in formal syntax, so the pattern is too.
+        methods.add_method("goalMatches", |lua, this, pattern: LuaPresFrag| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            let goal = formal_frag(this.out_ref().goal(), ctx);
+            Ok(match_holes(goal, pattern.out().formal()))
         });
 
+        // Each operation takes an optional span for the part of the tactic
+        // which caused it, which is shown next to the facts it adds.
+        methods.add_method(
+            "addAssumption",
+            |lua, this, (assumption, span): (LuaPresFrag, Option<LuaSpan>)| {
+                let assumption = assumption.out();
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .add_assumption(assumption.frag(), span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua("adding the assumption", err))?;
+                Ok(LuaProofState::new(new_state))
+            },
+        );
+
+        methods.add_method(
+            "popAssumption",
+            |lua, this, (justifying, span): (LuaPresFrag, Option<LuaSpan>)| {
+                let justifying = justifying.out();
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .pop_assumption(justifying.frag(), span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua("popping the assumption", err))?;
+                Ok(LuaProofState::new(new_state))
+            },
+        );
+
         methods.add_method(
             "applyTheorem",
-            |lua, this, (thm, templates): (LuaTheorem, Vec<LuaPresFrag>)| {
+            |lua, this, (thm, templates, span): (LuaTheorem, mlua::Table, Option<LuaSpan>)| {
                 let thm = thm.out();
-                let templates = templates.into_iter().map(|t| t.out().frag()).collect_vec();
                 let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                let (positional, named) = template_args_from_lua(lua, templates)?;
+                let templates = thm
+                    .fill_templates(&positional, &named, ctx)
+                    .map_err(|err| {
+                        mlua::Error::runtime(format!("applying `{}`: {err}", thm.name()))
+                    })?;
+                let templates = templates.iter().map(|t| t.frag()).collect_vec();
 
-                let new_state = this
-                    .out_ref()
-                    .apply_theorem(thm, &templates, ctx)
-                    .expect("TODO");
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .apply_theorem(thm, &templates, span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua(&format!("applying `{}`", thm.name()), err))?;
                 Ok(LuaProofState::new(new_state))
             },
         );
 
         methods.add_method(
             "applyTodo",
-            |lua, this, (justifying, reason): (LuaPresFrag, Option<String>)| {
+            |lua, this, (justifying, note, span): (LuaPresFrag, Value, Option<LuaSpan>)| {
                 let justifying = justifying.out();
+                let note = todo_note_from_lua(note)?;
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .apply_todo(justifying.frag(), note, span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua("applying todo", err))?;
+                Ok(LuaProofState::new(new_state))
+            },
+        );
+
+        methods.add_method(
+            "applyError",
+            |lua, this, (justifying, span): (LuaPresFrag, Option<LuaSpan>)| {
+                let justifying = justifying.out();
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .apply_error(justifying.frag(), span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua("applying error", err))?;
+                Ok(LuaProofState::new(new_state))
+            },
+        );
+
+        methods.add_method(
+            "unfold",
+            |lua, this, (known, name, span): (LuaPresFrag, String, Option<LuaSpan>)| {
+                let known = known.out();
                 let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                let abbreviation = lookup_abbreviation(&name, known.frag())?;
 
-                let new_state = this
-                    .out_ref()
-                    .apply_todo(justifying.frag(), reason, ctx)
-                    .expect("TODO");
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .unfold(known.frag(), abbreviation, span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua("unfolding", err))?;
                 Ok(LuaProofState::new(new_state))
             },
         );
 
-        methods.add_method("applyError", |lua, this, justifying: LuaPresFrag| {
-            let justifying = justifying.out();
+        methods.add_method("withGoal", |lua, this, goal: LuaPresFrag| {
+            let goal = goal.out();
             let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
 
-            let new_state = this
-                .out_ref()
-                .apply_error(justifying.frag(), ctx)
-                .expect("TODO");
+            let new_state = in_kernel(lua, || this.out_ref().with_goal(goal.frag(), ctx))
+                .map_err(|err| proof_error_to_lua("starting the subgoal", err))?;
             Ok(LuaProofState::new(new_state))
         });
+
+        methods.add_method(
+            "merge",
+            |lua, this, (subgoal, span): (LuaProofState, Option<LuaSpan>)| {
+                let new_state = in_kernel(lua, || {
+                    this.out_ref().merge(subgoal.out_ref(), span_out(span))
+                })
+                .map_err(|err| match err {
+                    ProofError::SubgoalIncomplete => {
+                        mlua::Error::runtime("the subgoal's goal isn't proved yet")
+                    }
+                    _ => mlua::Error::runtime(
+                        "the subgoal is of another theorem or under other assumptions",
+                    ),
+                })?;
+                Ok(LuaProofState::new(new_state))
+            },
+        );
+
+        methods.add_method(
+            "fold",
+            |lua, this, (justifying, name, span): (LuaPresFrag, String, Option<LuaSpan>)| {
+                let justifying = justifying.out();
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                let abbreviation = lookup_abbreviation(&name, justifying.frag())?;
+
+                let new_state = in_kernel(lua, || {
+                    this.out_ref()
+                        .fold(justifying.frag(), abbreviation, span_out(span), ctx)
+                })
+                .map_err(|err| proof_error_to_lua("folding", err))?;
+                Ok(LuaProofState::new(new_state))
+            },
+        );
+    }
+}
+
+/// Describe a kernel error from `op` to the tactic which caused it, so a
+/// mistake in a tactic fails the theorem rather than the whole check.
+fn proof_error_to_lua(op: &str, err: ProofError) -> mlua::Error {
+    let reason = match err {
+        ProofError::FragNotSentence => "the fragment isn't a sentence",
+        ProofError::FragHasHoles => "the fragment has holes",
+        ProofError::FragUnclosed => "the fragment has unbound variables",
+        ProofError::NoAssumption => "there is no assumption to pop",
+        ProofError::ProofIncomplete => "the proof isn't complete",
+        ProofError::StillHasAssumptions => "the proof is still under assumptions",
+        ProofError::MissingHypothesis => "a hypothesis isn't known",
+        ProofError::MissingFact => "the fact isn't known",
+        ProofError::WrongTemplateCount => "the number of templates is wrong",
+        ProofError::SubgoalIncomplete => "the subgoal's goal isn't proved yet",
+        ProofError::SubgoalElsewhere => {
+            "the subgoal is of another theorem or under other assumptions"
+        }
+    };
+    mlua::Error::runtime(format!("{op}: {reason}"))
+}
+
+/// Run a kernel operation, adding its time to the theorem's kernel time so
+/// the profile can tell it apart from the tactic's own.
+fn in_kernel<T>(lua: &mlua::Lua, op: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let out = op();
+    if let Some(info) = lua.app_data_ref::<LuaTheoremInfo>() {
+        info.borrow_mut().kernel_time += start.elapsed();
     }
+    out
+}
+
+fn audit_entry_to_lua<'ctx>(
+    lua: &mlua::Lua,
+    entry: &AuditEntry<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> mlua::Result<mlua::Table> {
+    let table = lua.create_table()?;
+    table.set("depth", entry.depth())?;
+    table.set("span", entry.span().map(LuaSpan::new))?;
+
+    let fact = entry.deduced();
+    let fact = PresFact::new(
+        fact.assumption().map(|a| formal_frag(a, ctx)),
+        formal_frag(fact.conclusion(), ctx),
+    );
+    table.set("fact", LuaPresFact::new(fact))?;
+
+    let kind = match entry.step() {
+        AuditStep::Assume(_) => "assume",
+        AuditStep::Discharge { .. } => "discharge",
+        AuditStep::ApplyTheorem {
+            theorem, templates, ..
+        } => {
+            table.set("theorem", LuaTheorem::new(*theorem))?;
+            let templates = templates
+                .iter()
+                .map(|&t| LuaPresFrag::new(formal_frag(t, ctx)))
+                .collect_vec();
+            table.set("templates", templates)?;
+            "theorem"
+        }
+        AuditStep::Todo { note, .. } => {
+            table.set("reason", note.reason.clone())?;
+            table.set("ticket", note.ticket.clone())?;
+            "todo"
+        }
+        AuditStep::Error(_) => "error",
+        AuditStep::Unfold { abbreviation, .. } => {
+            table.set("abbreviation", abbreviation.name().as_str())?;
+            "unfold"
+        }
+        AuditStep::Fold { abbreviation, .. } => {
+            table.set("abbreviation", abbreviation.name().as_str())?;
+            "fold"
+        }
+        AuditStep::Subgoal(_) => "subgoal",
+    };
+    table.set("kind", kind)?;
+
+    Ok(table)
+}
+
+type TemplateArgs<'ctx> = (Vec<PresFrag<'ctx>>, Vec<(Ustr, PresFrag<'ctx>)>);
+
+/// Templates are given by position in the array part of the table and by the
+/// name of their binding in the rest.
+fn template_args_from_lua<'ctx>(
+    lua: &mlua::Lua,
+    table: mlua::Table,
+) -> mlua::Result<TemplateArgs<'ctx>> {
+    let mut positional = Vec::new();
+    let mut named = Vec::new();
+    for pair in table.pairs::<Value, LuaPresFrag>() {
+        let (key, frag) = pair?;
+        match key {
+            Value::String(key) => named.push((Ustr::from(&key.to_string_lossy()), frag.out())),
+            key => positional.push((usize::from_lua(key, lua)?, frag.out())),
+        }
+    }
+
+    positional.sort_by_key(|&(idx, _)| idx);
+    if positional
+        .iter()
+        .enumerate()
+        .any(|(i, &(idx, _))| idx != i + 1)
+    {
+        return Err(mlua::Error::runtime(
+            "templates given by position can't skip any, name the later ones instead",
+        ));
+    }
+    let positional = positional.into_iter().map(|(_, frag)| frag).collect();
+
+    Ok((positional, named))
+}
+
+/// A todo is given either just a reason or a table with the fields `reason`,
+/// `ticket` and `span`, all optional.
+fn todo_note_from_lua(value: Value) -> mlua::Result<TodoNote> {
+    match value {
+        Value::Nil => Ok(TodoNote::default()),
+        Value::String(reason) => Ok(TodoNote {
+            reason: Some(reason.to_str()?.to_string()),
+            ..TodoNote::default()
+        }),
+        Value::Table(table) => Ok(TodoNote {
+            reason: table.get("reason")?,
+            ticket: table.get("ticket")?,
+            span: table.get::<Option<LuaSpan>>("span")?.map(|span| span.out()),
+        }),
+        value => Err(mlua::Error::runtime(format!(
+            "expected a reason or a table for todo, found {}",
+            value.type_name()
+        ))),
+    }
+}
+
+fn span_out(span: Option<LuaSpan>) -> Option<Span> {
+    span.map(|span| span.out())
+}
+
+/// The abbreviation named `name` which `frag` uses.
+fn lookup_abbreviation<'ctx>(
+    name: &str,
+    frag: FragmentId<'ctx>,
+) -> mlua::Result<AbbreviationId<'ctx>> {
+    find_abbreviation(frag, name.into()).ok_or_else(|| {
+        mlua::Error::runtime(format!("the fact doesn't use an abbreviation `{name}`"))
+    })
 }
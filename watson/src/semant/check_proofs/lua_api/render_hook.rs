@@ -0,0 +1,185 @@
+use crate::{
+    diagnostics::FragRenderings,
+    semant::{
+        check_proofs::lua_api::frag_to_lua::LuaPresFrag, presentation::PresFrag,
+        theorems::TheoremId,
+    },
+};
+use mlua::{Lua, VmState};
+use std::{
+    cell::{Cell, RefCell},
+    mem,
+    time::{Duration, Instant},
+};
+
+/// How long `renderFrag` may take to render one fragment before the default
+/// rendering is used instead.
+const RENDER_BUDGET: Duration = Duration::from_millis(50);
+
+/// The `renderFrag` function the main module can export to change how
+/// fragments are printed in the proof state of diagnostics.
+pub struct LuaRenderHook {
+    render_frag: mlua::Function,
+    /// Set while the hook runs, so a diagnostic it builds is printed the
+    /// default way instead of calling the hook again.
+    running: Cell<bool>,
+    /// Why the hook failed since the failures were last taken, to report
+    /// them as diagnostics.
+    failures: RefCell<Vec<String>>,
+}
+
+impl LuaRenderHook {
+    pub fn new(render_frag: mlua::Function) -> Self {
+        Self {
+            render_frag,
+            running: Cell::new(false),
+            failures: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+/// Take the errors of the calls to `renderFrag` which failed, once each.
+pub fn take_render_failures(lua: &Lua) -> Vec<String> {
+    let Some(hook) = lua.app_data_ref::<LuaRenderHook>() else {
+        return Vec::new();
+    };
+    let mut failures = mem::take(&mut *hook.failures.borrow_mut());
+    failures.dedup();
+    failures
+}
+
+/// Render the hypotheses and conclusion of `thm`, which the summary and the
+/// check report print.
+pub fn render_statement<'ctx>(lua: &Lua, thm: TheoremId<'ctx>) -> FragRenderings<'ctx> {
+    let frags = (thm.hypotheses().iter())
+        .flat_map(|h| h.assumption().into_iter().chain([h.conclusion()]))
+        .chain([thm.conclusion()]);
+    render_frags(lua, frags)
+}
+
+/// Render `frags` with the main module's `renderFrag`. Without the hook this
+/// does nothing. Fragments the hook fails to render in time are left out so
+/// they are printed the default way, and the error is kept for
+/// `take_render_failures`.
+pub fn render_frags<'ctx>(
+    lua: &Lua,
+    frags: impl IntoIterator<Item = PresFrag<'ctx>>,
+) -> FragRenderings<'ctx> {
+    let mut renderings = FragRenderings::default();
+    let render_frag = match lua.app_data_ref::<LuaRenderHook>() {
+        Some(hook) if !hook.running.replace(true) => hook.render_frag.clone(),
+        _ => return renderings,
+    };
+
+    for frag in frags {
+        if renderings.contains_key(&frag) {
+            continue;
+        }
+
+        let deadline = Instant::now() + RENDER_BUDGET;
+        lua.set_interrupt(move |_| {
+            if Instant::now() < deadline {
+                Ok(VmState::Continue)
+            } else {
+                Err(mlua::Error::runtime(format!(
+                    "took longer than {}ms",
+                    RENDER_BUDGET.as_millis()
+                )))
+            }
+        });
+        let result = render_frag.call::<String>((LuaPresFrag::new(frag), frag.print()));
+        lua.remove_interrupt();
+
+        match result {
+            Ok(rendered) => {
+                renderings.insert(frag, rendered);
+            }
+            Err(e) => {
+                if let Some(hook) = lua.app_data_ref::<LuaRenderHook>() {
+                    hook.failures.borrow_mut().push(e.to_string());
+                }
+            }
+        }
+    }
+
+    if let Some(hook) = lua.app_data_ref::<LuaRenderHook>() {
+        hook.running.set(false);
+    }
+    renderings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        context::Arenas,
+        diagnostics::print_frag,
+        driver::Driver,
+        util::{ansi::strip_ansi, test_project::TestProject},
+    };
+    use annotate_snippets::Renderer;
+
+    const MAIN: &str = "module logic\n\ntheorem refl [p : sentence] :\n    |- p → p\nproof\nqed\n";
+
+    /// A handler which reports the proof state in a warning and leaves the
+    /// proof as a todo, with `render` as the body of `renderFrag`.
+    fn handler(render: &str) -> String {
+        format!(
+            "local M = {{}}\n\n\
+             function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20   Diagnostic:newWarning(\"stuck\"):withTacticInfo(tacticInfo, proofState):report()\n\
+             \x20   return proofState:applyTodo(proofState.goal)\n\
+             end\n\n\
+             function M.renderFrag(frag: Frag, defaultRendered: string): string\n\
+             \x20   {render}\n\
+             end\n\n\
+             M.tactics = {{}} :: TacticHandlers\n\n\
+             return M\n"
+        )
+    }
+
+    /// Check the project, returning each diagnostic as printed and the
+    /// theorem's conclusion as the summary prints it.
+    fn check(render: &str) -> (Vec<String>, String) {
+        let project = TestProject::with_logic(MAIN);
+        project.write("script/main.luau", &handler(render));
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let ctx = checked.ctx();
+
+        let diags = ctx
+            .diags
+            .iter()
+            .map(|diag| {
+                let msg = diag.to_message(&ctx.sources, ctx.config.proof_state());
+                strip_ansi(&Renderer::plain().render(msg).to_string())
+            })
+            .collect();
+        let (&thm, status) = (checked.proof_report().statuses.iter())
+            .find(|(thm, _)| thm.name() == "refl")
+            .unwrap();
+        let conclusion = print_frag(thm.conclusion(), status.frag_renderings());
+        (diags, conclusion)
+    }
+
+    #[test]
+    fn hook_renders_footers_and_statements() {
+        let (diags, conclusion) = check("return \"[\" .. defaultRendered .. \"]\"");
+        assert_eq!(diags.len(), 1);
+        assert!(diags[0].contains("⊢ ["), "{}", diags[0]);
+        assert!(conclusion.starts_with('[') && conclusion.ends_with(']'));
+    }
+
+    #[test]
+    fn failing_hook_is_reported_and_falls_back() {
+        let (diags, conclusion) = check("error(\"no rendering\")");
+        assert!(diags.iter().all(|diag| !diag.contains("⊢ [")));
+        assert!(
+            diags
+                .iter()
+                .any(|diag| diag.contains("`renderFrag` failed") && diag.contains("no rendering"))
+        );
+        assert!(!conclusion.starts_with('['));
+    }
+}
@@ -0,0 +1,64 @@
+use crate::{
+    context::Ctx,
+    parse::{SourceId, Span},
+    semant::check_proofs::lua_api::{ctx_to_lua::LuaCtx, span_to_lua::LuaSpan},
+};
+use mlua::UserData;
+use ustr::Ustr;
+
+/// Read-only access to the sources which have already been loaded. Tactics
+/// can't use this to read arbitrary files.
+pub struct LuaSourceMeta;
+
+impl UserData for LuaSourceMeta {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("text", |lua, _, name: String| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            let source = SourceId::new(Ustr::from(&name));
+            if !ctx.sources.has_source(source) {
+                return Ok(None);
+            }
+            Ok(Some(ctx.sources.get_text(source).to_string()))
+        });
+
+        methods.add_method("lineCol", |lua, _, span: LuaSpan| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            let span = span.out();
+            let line_cols = ctx.sources.line_cols(&[span.start(), span.end()]);
+
+            let table = lua.create_table()?;
+            table.set("startLine", line_cols[0].0)?;
+            table.set("startCol", line_cols[0].1)?;
+            table.set("endLine", line_cols[1].0)?;
+            table.set("endCol", line_cols[1].1)?;
+            Ok(table)
+        });
+
+        methods.add_method(
+            "snippet",
+            |lua, _, (span, context_lines): (LuaSpan, Option<usize>)| {
+                let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+                Ok(snippet(span.out(), context_lines.unwrap_or(0), ctx))
+            },
+        );
+    }
+}
+
+/// The full lines covered by a span along with `context_lines` lines either
+/// side.
+fn snippet(span: Span, context_lines: usize, ctx: &Ctx) -> String {
+    let text = ctx.sources.get_text(span.source());
+    let line_cols = ctx.sources.line_cols(&[span.start(), span.end()]);
+    let (start_line, end_line) = (line_cols[0].0 as usize, line_cols[1].0 as usize);
+
+    let first = start_line.saturating_sub(context_lines).max(1);
+    let last = end_line + context_lines;
+
+    text.lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(num, _)| first <= *num && *num <= last)
+        .map(|(_, line)| line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -1,7 +1,10 @@
 use crate::semant::{
-    check_proofs::lua_api::{
-        frag_to_lua::{LuaPresFact, LuaPresFrag},
-        notation_to_lua::LuaNotationBinding,
+    check_proofs::{
+        LuaTheoremInfo,
+        lua_api::{
+            frag_to_lua::{LuaPresFact, LuaPresFrag},
+            notation_to_lua::LuaNotationBinding,
+        },
     },
     tactic::tactic_info::TacticInfo,
 };
@@ -54,5 +57,14 @@ impl UserData for LuaTacticInfo {
             let new_info = this.out_ref().clone().with_goal(f.out());
             Ok(LuaTacticInfo::new(new_info))
         });
+
+        methods.add_method("markFragile", |lua, _, reason: String| {
+            let info = lua.app_data_ref::<LuaTheoremInfo>().unwrap();
+            let mut info = info.borrow_mut();
+            if info.fragile.is_none() {
+                info.fragile = Some(reason);
+            }
+            Ok(())
+        });
     }
 }
@@ -1,15 +1,23 @@
-use crate::semant::{
-    check_proofs::lua_api::{
-        ctx_to_lua::LuaCtx,
-        formal_to_lua::LuaFormalCat,
-        frag_to_lua::{LuaPresFact, LuaPresFrag},
-        notation_to_lua::LuaNotationBinding,
-        scope_to_lua::LuaScope,
+use crate::{
+    semant::{
+        check_proofs::{
+            LuaTheoremInfo,
+            lua_api::{
+                ctx_to_lua::LuaCtx,
+                formal_to_lua::LuaFormalCat,
+                frag_to_lua::{LuaPresFact, LuaPresFrag},
+                notation_to_lua::LuaNotationBinding,
+                scope_to_lua::LuaScope,
+            },
+        },
+        theorems::{Template, TheoremId, TheoremStatement},
     },
-    theorems::{Template, TheoremId, TheoremStatement},
+    util::similar_names,
 };
 use itertools::Itertools;
-use mlua::{FromLua, UserData};
+use mlua::{FromLua, Lua, UserData};
+use rustc_hash::FxHashMap;
+use ustr::Ustr;
 
 #[derive(Debug, Clone, Copy, FromLua)]
 pub struct LuaTheorem {
@@ -39,6 +47,8 @@ impl UserData for LuaTheorem {
     fn add_fields<F: mlua::UserDataFields<Self>>(fields: &mut F) {
         fields.add_field_method_get("name", |_, this| Ok(this.out().name().to_string()));
 
+        fields.add_field_method_get("kind", |_, this| Ok(this.out().kind().keyword()));
+
         fields.add_field_method_get("templates", |_, this| {
             let vec = this
                 .out()
@@ -49,6 +59,8 @@ impl UserData for LuaTheorem {
             Ok(vec)
         });
 
+        fields.add_field_method_get("templateCount", |_, this| Ok(this.out().templates().len()));
+
         fields.add_field_method_get("hypotheses", |_, this| {
             let vec = this
                 .out()
@@ -80,6 +92,61 @@ impl UserData for LuaTheoremMeta {
             let thm = ctx.arenas.theorem_stmts.get(name.into());
             Ok(thm.map(|t| LuaTheorem::new(t)))
         });
+
+        methods.add_method("similarNames", |lua, _, name: String| {
+            let ctx = lua.app_data_ref::<LuaCtx>().unwrap().out();
+            let similar = similar_names(&name, ctx.arenas.theorem_stmts.names());
+            Ok(similar.iter().map(|name| name.to_string()).collect_vec())
+        });
+
+        methods.add_function("lookup", |lua, name: String| {
+            let order = lua.app_data_ref::<LuaTheoremOrder>().unwrap();
+            Ok(order.lookup(lua, Ustr::from(&name)))
+        });
+
+        methods.add_function("all", |lua, ()| {
+            let order = lua.app_data_ref::<LuaTheoremOrder>().unwrap();
+            Ok(order.visible(lua).to_vec())
+        });
+    }
+}
+
+/// Every theorem in the order it was elaborated. A tactic can only list and
+/// look up the theorems before the one it is proving, so a search for lemmas
+/// never finds the theorem itself or a later theorem proved with it.
+#[derive(Clone)]
+pub struct LuaTheoremOrder {
+    theorems: Vec<LuaTheorem>,
+    positions: FxHashMap<Ustr, usize>,
+}
+
+impl LuaTheoremOrder {
+    pub fn new<'ctx>(theorems: impl IntoIterator<Item = TheoremId<'ctx>>) -> Self {
+        let theorems = theorems.into_iter().map(LuaTheorem::new).collect_vec();
+        let positions = theorems
+            .iter()
+            .enumerate()
+            .map(|(i, thm)| (thm.out().name(), i))
+            .collect();
+        Self {
+            theorems,
+            positions,
+        }
+    }
+
+    /// The theorems before the one being checked, or all of them when no
+    /// theorem is, like while the tactics are loaded.
+    fn visible(&self, lua: &Lua) -> &[LuaTheorem] {
+        let cutoff = lua
+            .app_data_ref::<LuaTheoremInfo>()
+            .and_then(|info| self.positions.get(&info.borrow().thm.out().name()).copied())
+            .unwrap_or(self.theorems.len());
+        &self.theorems[..cutoff]
+    }
+
+    fn lookup(&self, lua: &Lua, name: Ustr) -> Option<LuaTheorem> {
+        let &position = self.positions.get(&name)?;
+        (position < self.visible(lua).len()).then_some(self.theorems[position])
     }
 }
 
@@ -124,5 +191,9 @@ impl UserData for LuaTemplate {
                 .collect_vec();
             Ok(bindings)
         });
+
+        fields.add_field_method_get("default", |_, this| {
+            Ok(this.out_ref().default().map(LuaPresFrag::new))
+        });
     }
 }
@@ -1,10 +1,6 @@
 use crate::{
     diagnostics::Diagnostic,
-    parse::{
-        SourceId, earley,
-        parse_tree::{ParseTree, ParseTreeId},
-        source_cache::SourceDecl,
-    },
+    parse::{SourceId, earley, source_cache::SourceDecl},
     semant::{
         check_proofs::lua_api::{
             ctx_to_lua::LuaCtx,
@@ -14,6 +10,7 @@ use crate::{
             scope_to_lua::LuaScope,
             span_to_lua::LuaSpan,
         },
+        notation::NotationPreferences,
         parse_fragment::{
             UnresolvedAnyFrag, UnresolvedFact, UnresolvedFrag, parse_any_fragment, parse_fragment,
         },
@@ -24,26 +21,23 @@ use mlua::{FromLua, UserData};
 
 #[derive(Debug, Clone, Copy, FromLua)]
 pub struct LuaUnresolvedFrag {
-    ptr: *const ParseTree<'static>,
+    frag: UnresolvedFrag<'static>,
 }
 
 impl LuaUnresolvedFrag {
     pub fn new<'ctx>(frag: UnresolvedFrag<'ctx>) -> Self {
-        let ptr = std::ptr::from_ref(frag.0.0);
-        // SAFETY: We don't use this ptr here so this isn't really unsafe. See
-        // where we dereference for the actual safety details.
-        let ptr: *const ParseTree<'static> = unsafe { std::mem::transmute(ptr) };
+        // SAFETY: We don't use the tree here so this isn't really unsafe. See
+        // `out` for the actual safety details.
+        let frag: UnresolvedFrag<'static> = unsafe { std::mem::transmute(frag) };
 
-        Self { ptr }
+        Self { frag }
     }
 
     pub fn out<'ctx>(&self) -> UnresolvedFrag<'ctx> {
         // SAFETY: This isn't actually safe the way we have set this up. But!
         // as long as we only use these objects inside lua, since the lua
         // runtime doesn't live for as long as context, this is safe.
-        let parse_tree = unsafe { &*self.ptr };
-        let parse_tree_id = ParseTreeId(parse_tree);
-        UnresolvedFrag(parse_tree_id)
+        unsafe { std::mem::transmute(self.frag) }
     }
 }
 
@@ -86,9 +80,15 @@ impl UserData for LuaUnResFragMeta {
             let text_len = text.len();
             ctx.sources.add(source_id, text, SourceDecl::LuaSnippet);
 
-            // Now parse the snippet.
+            // Now parse the snippet. The scope it is resolved in isn't known
+            // yet, so preferences only apply when it is resolved.
             let sentence_syntax_cat = ctx.parse_state.cat_for_formal_cat(cat.out());
-            let parse = earley::parse(source_id.start_loc(), sentence_syntax_cat, ctx);
+            let parse = earley::parse(
+                source_id.start_loc(),
+                sentence_syntax_cat,
+                &NotationPreferences::default(),
+                ctx,
+            );
 
             match parse {
                 Ok(tree) => {
@@ -111,21 +111,18 @@ impl UserData for LuaUnResFragMeta {
 
 #[derive(Debug, Clone, Copy, FromLua)]
 pub struct LuaUnresolvedAnyFrag {
-    ptr: *const ParseTree<'static>,
+    frag: UnresolvedAnyFrag<'static>,
 }
 
 impl LuaUnresolvedAnyFrag {
     pub fn new<'ctx>(frag: UnresolvedAnyFrag<'ctx>) -> Self {
-        let ptr = std::ptr::from_ref(frag.0.0);
-        let ptr: *const ParseTree<'static> = unsafe { std::mem::transmute(ptr) };
+        let frag: UnresolvedAnyFrag<'static> = unsafe { std::mem::transmute(frag) };
 
-        Self { ptr }
+        Self { frag }
     }
 
     pub fn out<'ctx>(&self) -> UnresolvedAnyFrag<'ctx> {
-        let parse_tree = unsafe { &*self.ptr };
-        let parse_tree_id = ParseTreeId(parse_tree);
-        UnresolvedAnyFrag(parse_tree_id)
+        unsafe { std::mem::transmute(self.frag) }
     }
 }
 
@@ -1,21 +1,32 @@
 use std::time::Duration;
 
-use mlua::{FromLua, MetaMethod, UserData, Variadic};
-use vampire_prover::{
-    Formula, Function, Options, Predicate, Problem, Proof, ProofRes, ProofStep, Term,
+use crate::semant::check_proofs::{
+    LuaTheoremInfo,
+    lua_api::ctx_to_lua::LuaCtx,
+    vampire_portfolio::{DEFAULT_PORTFOLIO, Outcome, TptpFormula, TptpTerm, VampireProblem},
 };
+use mlua::{FromLua, MetaMethod, UserData, Variadic};
+use vampire_prover::{Formula, Function, Options, Predicate, Proof, ProofRes, ProofStep, Term};
 
 #[derive(Debug, Clone, FromLua)]
 pub struct LuaVFunction {
     function: Function,
+    name: String,
 }
 
 impl UserData for LuaVFunction {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("with", |_, this, args: Vec<LuaVTerm>| {
-            let terms: Vec<Term> = args.into_iter().map(|t| t.term).collect();
+            let terms: Vec<Term> = args.iter().map(|t| t.term).collect();
             let result_term = this.function.with(&terms);
-            Ok(LuaVTerm { term: result_term })
+            let tptp = TptpTerm::App(
+                this.name.clone(),
+                args.into_iter().map(|t| t.tptp).collect(),
+            );
+            Ok(LuaVTerm {
+                term: result_term,
+                tptp,
+            })
         });
 
         methods.add_meta_method(MetaMethod::Eq, |_, this, other: LuaVFunction| {
@@ -30,7 +41,7 @@ impl UserData for LuaVFunctionMeta {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("new", |_, _, (name, arity): (String, u32)| {
             let function = Function::new(&name, arity);
-            Ok(LuaVFunction { function })
+            Ok(LuaVFunction { function, name })
         });
     }
 }
@@ -38,15 +49,21 @@ impl UserData for LuaVFunctionMeta {
 #[derive(Debug, Clone, FromLua)]
 pub struct LuaVPredicate {
     predicate: Predicate,
+    name: String,
 }
 
 impl UserData for LuaVPredicate {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("with", |_, this, args: Vec<LuaVTerm>| {
-            let terms: Vec<Term> = args.into_iter().map(|t| t.term).collect();
+            let terms: Vec<Term> = args.iter().map(|t| t.term).collect();
             let result_formula = this.predicate.with(&terms);
+            let tptp = TptpFormula::Pred(
+                this.name.clone(),
+                args.into_iter().map(|t| t.tptp).collect(),
+            );
             Ok(LuaVFormula {
                 formula: result_formula,
+                tptp: Some(tptp),
             })
         });
 
@@ -62,7 +79,7 @@ impl UserData for LuaVPredicateMeta {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("new", |_, _, (name, arity): (String, u32)| {
             let predicate = Predicate::new(&name, arity);
-            Ok(LuaVPredicate { predicate })
+            Ok(LuaVPredicate { predicate, name })
         });
     }
 }
@@ -70,6 +87,7 @@ impl UserData for LuaVPredicateMeta {
 #[derive(Debug, Clone, FromLua)]
 pub struct LuaVTerm {
     term: Term,
+    tptp: TptpTerm,
 }
 
 impl UserData for LuaVTerm {
@@ -90,7 +108,10 @@ impl UserData for LuaVTermMeta {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("newVar", |_, _, idx: u32| {
             let term = Term::new_var(idx);
-            Ok(LuaVTerm { term })
+            Ok(LuaVTerm {
+                term,
+                tptp: TptpTerm::Var(idx),
+            })
         });
     }
 }
@@ -98,6 +119,18 @@ impl UserData for LuaVTermMeta {
 #[derive(Debug, Clone, FromLua)]
 pub struct LuaVFormula {
     formula: Formula,
+    /// `None` for formulas read back from a proof.
+    tptp: Option<TptpFormula>,
+}
+
+impl LuaVFormula {
+    fn new(formula: Formula, tptp: Option<TptpFormula>) -> Self {
+        Self { formula, tptp }
+    }
+}
+
+fn boxed(f: &LuaVFormula) -> Option<Box<TptpFormula>> {
+    f.tptp.clone().map(Box::new)
 }
 
 impl UserData for LuaVFormula {
@@ -118,54 +151,65 @@ impl UserData for LuaVFormulaMeta {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("newEq", |_, _, (t1, t2): (LuaVTerm, LuaVTerm)| {
             let formula = t1.term.eq(t2.term);
-            Ok(LuaVFormula { formula })
+            Ok(LuaVFormula::new(
+                formula,
+                Some(TptpFormula::Eq(t1.tptp, t2.tptp)),
+            ))
         });
 
         methods.add_method("newAnd", |_, _, formulas: Variadic<LuaVFormula>| {
+            let tptp: Option<Vec<_>> = formulas.iter().map(|f| f.tptp.clone()).collect();
             let formulas: Vec<Formula> = formulas.into_iter().map(|f| f.formula).collect();
             let formula = Formula::new_and(&formulas);
-            Ok(LuaVFormula { formula })
+            Ok(LuaVFormula::new(formula, tptp.map(TptpFormula::And)))
         });
 
         methods.add_method("newOr", |_, _, formulas: Variadic<LuaVFormula>| {
+            let tptp: Option<Vec<_>> = formulas.iter().map(|f| f.tptp.clone()).collect();
             let formulas: Vec<Formula> = formulas.into_iter().map(|f| f.formula).collect();
             let formula = Formula::new_or(&formulas);
-            Ok(LuaVFormula { formula })
+            Ok(LuaVFormula::new(formula, tptp.map(TptpFormula::Or)))
         });
 
         methods.add_method("newNot", |_, _, f: LuaVFormula| {
-            let formula = !f.formula;
-            Ok(LuaVFormula { formula })
+            let tptp = boxed(&f).map(TptpFormula::Not);
+            Ok(LuaVFormula::new(!f.formula, tptp))
         });
 
         methods.add_method("newImp", |_, _, (ant, cons): (LuaVFormula, LuaVFormula)| {
-            let formula = ant.formula >> cons.formula;
-            Ok(LuaVFormula { formula })
+            let tptp = boxed(&ant).zip(boxed(&cons));
+            let tptp = tptp.map(|(ant, cons)| TptpFormula::Imp(ant, cons));
+            Ok(LuaVFormula::new(ant.formula >> cons.formula, tptp))
         });
 
         methods.add_method("newIff", |_, _, (f1, f2): (LuaVFormula, LuaVFormula)| {
-            let formula = f1.formula.iff(f2.formula);
-            Ok(LuaVFormula { formula })
+            let tptp = boxed(&f1).zip(boxed(&f2));
+            let tptp = tptp.map(|(f1, f2)| TptpFormula::Iff(f1, f2));
+            Ok(LuaVFormula::new(f1.formula.iff(f2.formula), tptp))
         });
 
         methods.add_method("newForall", |_, _, (var, f): (u32, LuaVFormula)| {
-            let formula = Formula::new_forall(var, f.formula);
-            Ok(LuaVFormula { formula })
+            let tptp = boxed(&f).map(|f| TptpFormula::Forall(var, f));
+            Ok(LuaVFormula::new(Formula::new_forall(var, f.formula), tptp))
         });
 
         methods.add_method("newExists", |_, _, (var, f): (u32, LuaVFormula)| {
-            let formula = Formula::new_exists(var, f.formula);
-            Ok(LuaVFormula { formula })
+            let tptp = boxed(&f).map(|f| TptpFormula::Exists(var, f));
+            Ok(LuaVFormula::new(Formula::new_exists(var, f.formula), tptp))
         });
 
         methods.add_method("newTrue", |_, _, _: ()| {
-            let formula = Formula::new_true();
-            Ok(LuaVFormula { formula })
+            Ok(LuaVFormula::new(
+                Formula::new_true(),
+                Some(TptpFormula::True),
+            ))
         });
 
         methods.add_method("newFalse", |_, _, _: ()| {
-            let formula = Formula::new_false();
-            Ok(LuaVFormula { formula })
+            Ok(LuaVFormula::new(
+                Formula::new_false(),
+                Some(TptpFormula::False),
+            ))
         });
     }
 }
@@ -196,23 +240,23 @@ impl UserData for LuaVOptionsMeta {
 }
 
 // ============================================================================
-// VProblem - Wrapper for vampire_prover::Problem
+// VProblem - Wrapper for VampireProblem
 // ============================================================================
 
 #[derive(Debug, Clone, FromLua)]
 pub struct LuaVProblem {
-    problem: Problem,
+    problem: VampireProblem,
 }
 
 impl UserData for LuaVProblem {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method_mut("addAxiom", |_, this, axiom: LuaVFormula| {
-            this.problem.with_axiom(axiom.formula);
+            this.problem.add_axiom(axiom.formula, axiom.tptp);
             Ok(())
         });
 
         methods.add_method_mut("setConjecture", |_, this, conj: LuaVFormula| {
-            this.problem.conjecture(conj.formula);
+            this.problem.set_conjecture(conj.formula, conj.tptp);
             Ok(())
         });
 
@@ -227,6 +271,53 @@ impl UserData for LuaVProblem {
             Ok((proof, result_str))
         });
 
+        methods.add_method("solvePortfolio", |lua, this, budget_ms: u64| {
+            let binary = lua
+                .app_data_ref::<LuaCtx>()
+                .unwrap()
+                .out()
+                .config
+                .vampire_binary();
+            let result = this
+                .problem
+                .solve_portfolio(DEFAULT_PORTFOLIO, Duration::from_millis(budget_ms), binary)
+                .map_err(|e| {
+                    mlua::Error::runtime(format!("failed to run the Vampire binary: {e}"))
+                })?;
+
+            let attempts = lua.create_table()?;
+            for attempt in &result.attempts {
+                let entry = lua.create_table()?;
+                entry.set("strategy", attempt.strategy)?;
+                entry.set("status", attempt.outcome.name())?;
+                entry.set("timeMs", attempt.duration.as_millis() as u64)?;
+                attempts.push(entry)?;
+            }
+
+            let table = lua.create_table()?;
+            table.set("attempts", attempts)?;
+            match result.winner() {
+                Some(winner) => {
+                    table.set("status", winner.outcome.name())?;
+                    table.set("strategy", winner.strategy)?;
+                    table.set("timeMs", winner.duration.as_millis() as u64)?;
+
+                    // Remember what proved the theorem so the report can show it.
+                    if winner.outcome == Outcome::Proved
+                        && let Some(info) = lua.app_data_ref::<LuaTheoremInfo>()
+                    {
+                        let mut info = info.borrow_mut();
+                        if info.prover.is_none() {
+                            info.prover = Some((winner.strategy.to_string(), winner.duration));
+                        }
+                    }
+                }
+                None => table.set("status", "unknown")?,
+            }
+            table.set("proof", result.proof.map(|proof| LuaVProof { proof }))?;
+            Ok(table)
+        });
+
         methods.add_meta_method(MetaMethod::ToString, |_, this, _: ()| {
             Ok(format!("{:#?}", this.problem))
         });
@@ -238,7 +329,7 @@ pub struct LuaVProblemMeta;
 impl UserData for LuaVProblemMeta {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("new", |_, _, options: LuaVOptions| {
-            let problem = Problem::new(options.options);
+            let problem = VampireProblem::new(options.options);
             Ok(LuaVProblem { problem })
         });
     }
@@ -276,9 +367,7 @@ impl UserData for LuaVProofStep {
         fields.add_field_method_get("discoveryOrder", |_, this| Ok(this.step.discovery_order()));
 
         fields.add_field_method_get("conclusion", |_, this| {
-            Ok(LuaVFormula {
-                formula: this.step.conclusion(),
-            })
+            Ok(LuaVFormula::new(this.step.conclusion(), None))
         });
 
         fields.add_field_method_get("rule", |_, this| {
@@ -1,71 +1,411 @@
 use crate::{
     context::Ctx,
     diagnostics::{Diagnostic, DiagnosticSpan, WResult},
-    parse::Span,
+    parse::{RootReport, Span},
     semant::{
         attributes::AttributeTracker,
         check_proofs::lua_api::{
-            LuaInfo, diag_to_lua::LuaDiagnostic, proof_to_lua::LuaProofState, setup_lua,
-            tactic_info_to_lua::LuaTacticInfo, theorem_to_lua::LuaTheorem,
+            LuaInfo, LuaLogs,
+            diag_to_lua::LuaDiagnostic,
+            proof_to_lua::LuaProofState,
+            render_hook::{render_statement, take_render_failures},
+            setup_lua,
+            tactic_info_to_lua::LuaTacticInfo,
+            theorem_to_lua::{LuaTheorem, LuaTheoremOrder},
         },
         custom_grammar::inst::CustomGrammarInst,
-        proof_kernel::ProofState,
-        proof_status::{ProofStatus, ProofStatuses},
-        scope::Scope,
+        fragment::{FragHead, FragmentId},
+        proof_kernel::{ProofCertificate, ProofState, TodoNote},
+        proof_status::{ProofStatus, ProofStatuses, ProofTiming},
         tactic::{tactic_info::TacticInfo, unresolved_proof::UnresolvedProof},
         theorems::TheoremId,
     },
 };
 use mlua::IntoLua;
-use std::{cell::RefCell, rc::Rc, vec};
+use rustc_hash::FxHashSet;
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+    vec,
+};
 use ustr::Ustr;
 
 mod lua_api;
+mod vampire_portfolio;
+
+pub use lua_api::grammar_to_lua::{rule_luau_fields, rule_luau_type};
 
 pub fn check_proofs<'ctx>(
-    theorems: &[(TheoremId<'ctx>, UnresolvedProof<'ctx>)],
-    scope: Scope<'ctx>,
+    roots: &[RootReport<'ctx>],
     attributes: AttributeTracker<'ctx>,
     ctx: &mut Ctx<'ctx>,
 ) -> ProofStatuses<'ctx> {
     let mut statuses = ProofStatuses::new();
 
+    // Don't start Lua if parsing already failed and we are stopping at the
+    // first error, since tactics would only report follow-on errors.
+    if !ctx.diags.should_continue() {
+        return statuses;
+    }
+
     // reset Vampire. safety: we aren't holding any vampire handles at the
     // moment, because we haven't run any lua code.
     unsafe {
         vampire_sys::vampire_reset();
     }
 
-    let info = match setup_lua(ctx, scope, attributes) {
-        Ok(info) => info,
-        Err(diags) => {
-            // Failed to set up Lua. Add the diagnostics and return.
-            ctx.diags.add_diags(diags);
-            return statuses;
-        }
+    // Without a filter every theorem is selected.
+    let filter = ctx.config.theorem_filter().cloned();
+    let mut selected = match &filter {
+        Some(filter) => match filter.select(roots, ctx) {
+            Ok(selected) => Some(selected),
+            Err(diags) => {
+                ctx.diags.add_diags(diags);
+                return statuses;
+            }
+        },
+        None => None,
     };
 
-    for (theorem, proof) in theorems {
-        let status = match proof {
-            UnresolvedProof::Axiom => ProofStatus::new_axiom(),
-            UnresolvedProof::Theorem(proof) => {
-                match check_theorem(*theorem, proof, &info, ctx) {
-                    Ok(status) => status,
-                    Err(diags) => {
-                        // Error checking theorem. Add the diagnostics and continue.
-                        ctx.diags.add_diags(diags);
-                        ProofStatus::new_error()
+    // Tactics can list the theorems elaborated before the one they are
+    // proving, which is every theorem of the earlier roots and the earlier
+    // theorems of its own.
+    let order = LuaTheoremOrder::new(
+        roots
+            .iter()
+            .flat_map(|root| root.theorems.iter().map(|(theorem, _)| *theorem)),
+    );
+
+    // With `--with-deps` the theorems used by the selected theorems are only
+    // known once they have been checked, so we keep checking until no new
+    // theorems are used.
+    'checking: loop {
+        let mut used = FxHashSet::default();
+
+        // Each root gets its own Lua runtime since tactics see the final
+        // scope of the root they are checking.
+        for root in roots {
+            let pending: Vec<_> = root
+                .theorems
+                .iter()
+                .filter(|(theorem, _)| {
+                    statuses.get(*theorem).is_none()
+                        && selected.as_ref().is_none_or(|s| s.contains(theorem))
+                })
+                .collect();
+            if pending.is_empty() && selected.is_some() {
+                continue;
+            }
+
+            // Without a Lua entry point there is nothing to run tactics with,
+            // so only axioms can be checked.
+            let info = match ctx.config.lua_entry().map(|p| p.to_path_buf()) {
+                Some(lua_entry) => {
+                    match setup_lua(
+                        ctx,
+                        root.scope.clone(),
+                        attributes.clone(),
+                        order.clone(),
+                        &lua_entry,
+                    ) {
+                        Ok(info) => Some(info),
+                        Err(diags) => {
+                            // Failed to set up Lua. Add the diagnostics and return.
+                            ctx.diags.add_diags(diags);
+                            return statuses;
+                        }
+                    }
+                }
+                None => None,
+            };
+
+            for (theorem, proof) in pending {
+                let status = match proof {
+                    UnresolvedProof::Axiom => ProofStatus::new_axiom(),
+                    UnresolvedProof::Theorem(proof) => {
+                        let mut timing = ProofTiming::default();
+                        let reported_before = ctx.diags.count();
+                        let mut result = match &info {
+                            Some(info) => check_theorem(*theorem, proof, info, &mut timing, ctx),
+                            None => Diagnostic::err_no_tactic_handler(theorem.name(), proof.span()),
+                        };
+                        let is_sentinel = Some(theorem.name()) == ctx.config.sentinel_theorem();
+                        // A denied proof fails, except that the sentinel is
+                        // judged by whether it was proved at all.
+                        if ctx.config.deny_fragile()
+                            && !is_sentinel
+                            && let Ok(status) = &mut result
+                            && let Some(reason) = status.fragile()
+                        {
+                            let diags = Diagnostic::err_fragile_proof::<()>(
+                                theorem.name(),
+                                reason,
+                                proof.span(),
+                            )
+                            .unwrap_err();
+                            ctx.diags.add_diags(diags);
+                            status.mark_failed();
+                        }
+                        if !is_sentinel
+                            && let Some(sentinel) = ctx.config.sentinel_theorem()
+                            && let Ok(status) = &mut result
+                            && status
+                                .theorems_used()
+                                .iter()
+                                .any(|used| used.name() == sentinel)
+                        {
+                            let diags = Diagnostic::err_uses_sentinel::<()>(
+                                theorem.name(),
+                                sentinel,
+                                proof.span(),
+                            )
+                            .unwrap_err();
+                            ctx.diags.add_diags(diags);
+                            status.mark_failed();
+                        }
+                        if let Ok(status) = &mut result {
+                            let mut denied = false;
+                            for todo in status.todos() {
+                                if ctx.config.todo_denied(todo.ticket.as_deref()) {
+                                    let diags = Diagnostic::err_denied_todo::<()>(
+                                        theorem.name(),
+                                        todo,
+                                        todo.span.unwrap_or(proof.span()),
+                                    )
+                                    .unwrap_err();
+                                    ctx.diags.add_diags(diags);
+                                    denied = true;
+                                }
+                            }
+                            if denied {
+                                status.mark_failed();
+                            }
+                        }
+                        let status = if is_sentinel {
+                            let reported = ctx.diags.split_off(reported_before);
+                            check_sentinel(*theorem, proof.span(), result, reported, ctx)
+                        } else {
+                            match result {
+                                Ok(status) => status,
+                                Err(diags) => {
+                                    // Error checking theorem. Add the diagnostics and continue.
+                                    ctx.diags.add_diags(diags);
+                                    ProofStatus::new_error()
+                                }
+                            }
+                        };
+                        let status = status.with_timing(timing);
+
+                        // The Lua runtime is gone by the time the summary and
+                        // report print the statement, so render it now.
+                        match &info {
+                            Some(info) => {
+                                let renderings = render_statement(&info.runtime, *theorem);
+                                for failure in take_render_failures(&info.runtime) {
+                                    ctx.diags.add_diag(Diagnostic::warn_render_frag_failed(
+                                        theorem.name(),
+                                        &failure,
+                                        proof.span(),
+                                    ));
+                                }
+                                status.with_frag_renderings(renderings)
+                            }
+                            None => status,
+                        }
                     }
+                };
+                used.extend(status.theorems_used().iter().copied());
+                statuses.add(*theorem, status);
+
+                // Stop at the first error with `--fail-fast`, but still do
+                // the bookkeeping below for the theorems checked so far.
+                if !ctx.diags.should_continue() {
+                    break 'checking;
                 }
             }
-        };
-        statuses.add(*theorem, status);
+        }
+
+        match &mut selected {
+            Some(selected) if filter.as_ref().is_some_and(|f| f.with_deps()) => {
+                let before = selected.len();
+                selected.extend(used);
+                if selected.len() == before {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+
+    if selected.is_some() {
+        count_filtered(roots, &mut statuses);
+    } else if let Some(sentinel) = ctx.config.sentinel_theorem()
+        && !roots
+            .iter()
+            .flat_map(|root| &root.theorems)
+            .any(|(theorem, proof)| {
+                theorem.name() == sentinel && matches!(proof, UnresolvedProof::Theorem(_))
+            })
+    {
+        let diags = Diagnostic::err_sentinel_not_found::<()>(sentinel).unwrap_err();
+        ctx.diags.add_diags(diags);
     }
 
     statuses
 }
 
+/// Count the theorems a filter left out. Those used by a checked theorem
+/// were skipped, and the rest were filtered out.
+fn count_filtered<'ctx>(roots: &[RootReport<'ctx>], statuses: &mut ProofStatuses<'ctx>) {
+    let total: usize = roots.iter().map(|root| root.theorems.len()).sum();
+    let checked = statuses.iter().count();
+    let skipped = statuses
+        .iter()
+        .flat_map(|(_, status)| status.theorems_used())
+        .filter(|&&used| statuses.get(used).is_none())
+        .collect::<FxHashSet<_>>()
+        .len();
+    statuses.set_filtered(total - checked - skipped, skipped);
+}
+
+/// The sentinel is expected to fail, so the errors explaining why, including
+/// those its tactic reported, are only listed in a note. It only
+/// counts as proved if the proof was complete and didn't use todo.
+fn check_sentinel<'ctx>(
+    theorem: TheoremId<'ctx>,
+    span: Span,
+    result: WResult<'ctx, ProofStatus<'ctx>>,
+    reported: Vec<Diagnostic<'ctx>>,
+    ctx: &mut Ctx<'ctx>,
+) -> ProofStatus<'ctx> {
+    let proved = result
+        .as_ref()
+        .is_ok_and(|status| status.correct() && !status.todo_used());
+    if proved {
+        ctx.diags.add_diags(reported);
+        let diags = Diagnostic::err_sentinel_proved::<()>(theorem.name(), span).unwrap_err();
+        ctx.diags.add_diags(diags);
+    } else {
+        let errors = result.as_ref().err().into_iter().flatten();
+        let reasons: Vec<_> = reported.iter().chain(errors).collect();
+        ctx.diags.add_diag(Diagnostic::note_sentinel_held(
+            theorem.name(),
+            span,
+            &reasons,
+        ));
+    }
+    let theorems_used = result
+        .map(|status| status.theorems_used().clone())
+        .unwrap_or_default();
+    ProofStatus::new_sentinel(!proved, theorems_used)
+}
+
 impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_sentinel_proved<T>(thm: Ustr, span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("consistency sentinel `{thm}` was proved"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "the sentinel should be unprovable, so the axioms or tactics are unsound",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    /// The sentinel failed to prove as it should. The errors it failed with
+    /// are listed so that a sentinel which only fails because its proof is
+    /// broken doesn't go unnoticed.
+    pub fn note_sentinel_held(thm: Ustr, span: Span, reasons: &[&Diagnostic<'ctx>]) -> Self {
+        let mut diag = Diagnostic::new_info(
+            &format!("consistency sentinel `{thm}` failed to prove, as expected"),
+            vec![DiagnosticSpan::new_info("", span)],
+        );
+        for reason in reasons {
+            let spans = reason.primary_span().into_iter();
+            let spans = spans
+                .map(|span| DiagnosticSpan::new_info("", span))
+                .collect();
+            diag = diag.with_info(reason.title(), spans);
+        }
+        diag
+    }
+
+    pub fn err_uses_sentinel<T>(thm: Ustr, sentinel: Ustr, span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("theorem `{thm}` uses the consistency sentinel `{sentinel}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "the sentinel should be unprovable, so nothing can rely on it",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_sentinel_not_found<T>(thm: Ustr) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("consistency sentinel `{thm}` is not a theorem"),
+            vec![],
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_fragile_proof<T>(thm: Ustr, reason: &str, span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("proof of theorem `{thm}` is fragile: {reason}"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "fragile proofs are errors with `--deny-fragile`",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
+
+    pub fn err_denied_todo<T>(thm: Ustr, todo: &TodoNote, span: Span) -> WResult<'ctx, T> {
+        let label = match &todo.reason {
+            Some(reason) => format!("todo: {reason}"),
+            None => "todo".to_string(),
+        };
+        let diag = Diagnostic::new(
+            &format!("proof of theorem `{thm}` uses todo"),
+            vec![DiagnosticSpan::new_error(&label, span)],
+        );
+        let diag = match &todo.ticket {
+            Some(ticket) => diag.with_info(
+                &format!("tracked by `{ticket}`, which `--deny-todo-except` doesn't allow"),
+                Vec::new(),
+            ),
+            None => diag.with_info("todos are errors with `--deny-todo`", Vec::new()),
+        };
+        Err(vec![diag])
+    }
+
+    pub fn warn_unused_hypothesis(thm: Ustr, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("proof of theorem `{thm}` never uses this hypothesis"),
+            vec![DiagnosticSpan::new_warning("unused hypothesis", span)],
+        )
+    }
+
+    pub fn warn_unused_template(thm: Ustr, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("theorem `{thm}` never refers to this template"),
+            vec![DiagnosticSpan::new_warning("unused template", span)],
+        )
+    }
+
+    pub fn warn_render_frag_failed(thm: Ustr, error: &str, span: Span) -> Diagnostic<'ctx> {
+        Diagnostic::new_warning(
+            &format!("`renderFrag` failed while printing theorem `{thm}`"),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+        .with_info(error, Vec::new())
+        .with_info("the fragment was printed the default way", Vec::new())
+    }
+
     pub fn err_tactic_did_not_prove<T>(thm: Ustr, span: Span) -> WResult<'ctx, T> {
         let diag = Diagnostic::new(
             &format!("tactic for theorem `{thm}` did not prove goal"),
@@ -73,12 +413,38 @@ impl<'ctx> Diagnostic<'ctx> {
         );
         Err(vec![diag])
     }
+
+    pub fn err_no_tactic_handler<T>(thm: Ustr, span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("can't check proof of theorem `{thm}` without a tactic handler"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info("pass `--lua <file>` to load a tactic handler", Vec::new());
+        Err(vec![diag])
+    }
+
+    pub fn err_unhandled_tactic_rule<T>(thm: Ustr, rule: Ustr, span: Span) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("no handler for tactic `{rule}` used in proof of theorem `{thm}`"),
+            vec![DiagnosticSpan::new_error("", span)],
+        )
+        .with_info(
+            "register one in the `tactics` table of the main module or define `handleTactic`",
+            Vec::new(),
+        );
+        Err(vec![diag])
+    }
 }
 
 struct LuaTheoremInfoInner {
     thm: LuaTheorem,
-    has_logs: bool,
     diags: Vec<LuaDiagnostic>,
+    /// The reason given by the first call to `markFragile`, if any.
+    fragile: Option<String>,
+    /// The first prover strategy which proved a goal and how long it took.
+    prover: Option<(String, Duration)>,
+    /// The time spent in kernel operations called by the tactic.
+    kernel_time: Duration,
 }
 type LuaTheoremInfo = Rc<RefCell<LuaTheoremInfoInner>>;
 
@@ -86,6 +452,7 @@ fn check_theorem<'ctx>(
     thm: TheoremId<'ctx>,
     tactic: &CustomGrammarInst<'ctx>,
     lua: &LuaInfo,
+    timing: &mut ProofTiming,
     ctx: &mut Ctx<'ctx>,
 ) -> WResult<'ctx, ProofStatus<'ctx>> {
     let proof_state =
@@ -103,31 +470,506 @@ fn check_theorem<'ctx>(
 
     let theorem_info = LuaTheoremInfoInner {
         thm: LuaTheorem::new(thm),
-        has_logs: false,
         diags: Vec::new(),
+        fragile: None,
+        prover: None,
+        kernel_time: Duration::ZERO,
     };
     let theorem_info = Rc::new(RefCell::new(theorem_info));
     lua.runtime.set_app_data(theorem_info.clone());
+    lua.runtime
+        .set_app_data(LuaLogs::new(format!("logs for {}", thm.name())));
 
-    // Call the tactic handler
-    let proof = lua
-        .handle_tactic_fn
+    // Call the handler registered for this tactic, or the catch-all.
+    let Some(handler) = lua.handler_for(tactic.rule().name()) else {
+        return Diagnostic::err_unhandled_tactic_rule(
+            thm.name(),
+            tactic.rule().name(),
+            tactic.span(),
+        );
+    };
+    let start = Instant::now();
+    let result = handler
         .call::<LuaProofState>((lua_tactic, lua_proof_state, lua_tactic_info))
-        .or_else(|e| Diagnostic::err_lua_execution_error("tactic", e))?;
-    let proof = proof.out::<'ctx>();
-    let cert = proof
-        .complete(ctx)
-        .or_else(|_| Diagnostic::err_tactic_did_not_prove(thm.name(), tactic.span()))?;
+        .or_else(|e| {
+            let lua_ctx = format!(
+                "tactic `{}` in proof of theorem `{}`",
+                tactic.rule().name(),
+                thm.name()
+            );
+            Diagnostic::err_lua_execution_error(&lua_ctx, e)
+        });
+    let called = start.elapsed();
+    let result = result.and_then(|proof| {
+        proof
+            .out::<'ctx>()
+            .complete(ctx)
+            .or_else(|_| Diagnostic::err_tactic_did_not_prove(thm.name(), tactic.span()))
+    });
+    let duration = start.elapsed();
+
+    // Checking the finished proof is kernel time too.
+    let kernel_in_lua = theorem_info.borrow().kernel_time;
+    timing.total = duration;
+    timing.lua = called.saturating_sub(kernel_in_lua);
+    timing.kernel = kernel_in_lua + (duration - called);
+
+    // The logs are shown for failed theorems even with `--quiet-tactics`.
+    if let Some(logs) = lua.runtime.remove_app_data::<LuaLogs>() {
+        logs.flush(ctx.config.quiet_tactics() && result.is_ok());
+    }
+    let cert = result?;
 
     // Add diagnostics reported by the tactic.
     for diag in theorem_info.borrow_mut().diags.drain(..) {
         ctx.diags.add_diag(diag.out());
     }
 
-    // Add blank space after logs.
-    if theorem_info.borrow().has_logs {
-        eprintln!();
+    // A proof resting on todo or error may need more of the statement once
+    // it is finished.
+    if !cert.uses_error() && cert.todos().is_empty() {
+        warn_unused_statement_parts(thm, &cert, ctx);
+    }
+
+    let fragile = theorem_info.borrow_mut().fragile.take();
+    let prover = theorem_info.borrow_mut().prover.take();
+    Ok(ProofStatus::from_cert(cert, tactic.span())
+        .with_fragile(fragile)
+        .with_prover(prover))
+}
+
+/// Warn about the hypotheses the proof never relied on and the templates the
+/// statement never refers to.
+fn warn_unused_statement_parts<'ctx>(
+    thm: TheoremId<'ctx>,
+    cert: &ProofCertificate<'ctx>,
+    ctx: &mut Ctx<'ctx>,
+) {
+    for idx in cert.unused_hypotheses(ctx) {
+        let span = thm.hypothesis_span(idx).unwrap_or(thm.name_span());
+        ctx.diags
+            .add_diag(Diagnostic::warn_unused_hypothesis(thm.name(), span));
+    }
+
+    // A template can also be used by the default of a later one.
+    let mut used = FxHashSet::default();
+    let frags = (thm.hypotheses().iter())
+        .flat_map(|h| h.assumption().into_iter().chain([h.conclusion()]))
+        .chain([thm.conclusion()])
+        .chain(thm.templates().iter().filter_map(|t| t.default()));
+    for frag in frags {
+        templates_referred_to(frag.frag(), &mut used);
+    }
+    for (idx, template) in thm.templates().iter().enumerate() {
+        if !used.contains(&idx) {
+            ctx.diags.add_diag(Diagnostic::warn_unused_template(
+                thm.name(),
+                template.span(),
+            ));
+        }
+    }
+}
+
+fn templates_referred_to(frag: FragmentId<'_>, used: &mut FxHashSet<usize>) {
+    if !frag.has_template() {
+        return;
+    }
+    if let FragHead::TemplateRef(idx) = frag.head() {
+        used.insert(idx);
+    }
+    for &child in frag.children() {
+        templates_referred_to(child, used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        config::WatsonConfig, context::Arenas, driver::Driver, util::test_project::TestProject,
+    };
+
+    /// A theorem whose proof is left to the tactic handler.
+    const MAIN: &str = "module logic\n\ntheorem refl [p : sentence] :\n    |- p → p\nproof\nqed\n";
+
+    /// A tactic handler which leaves every proof as a todo after running
+    /// `body`, which can set the todo's `note`.
+    fn handler(body: &str) -> String {
+        format!(
+            "local M = {{}}\n\n\
+             function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+             \x20   local note = nil\n\
+             \x20   {body}\n\
+             \x20   return proofState:applyTodo(proofState.goal, note)\n\
+             end\n\n\
+             M.tactics = {{}} :: TacticHandlers\n\n\
+             return M\n"
+        )
+    }
+
+    /// Check a project whose tactic handler runs `body`, returning whether
+    /// there were errors and whether `refl` counts as correct.
+    fn check(body: &str, configure: impl FnOnce(&mut WatsonConfig)) -> (bool, bool) {
+        let project = TestProject::with_logic(MAIN);
+        project.write("script/main.luau", &handler(body));
+        let mut config = project.config();
+        configure(&mut config);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(config, &arenas).parse().check();
+        let correct = (checked.proof_report().statuses.iter())
+            .find(|(thm, _)| thm.name() == "refl")
+            .is_some_and(|(_, status)| status.correct());
+        (checked.ctx().diags.has_errors(), correct)
+    }
+
+    #[test]
+    fn fragile_proofs_are_correct_by_default() {
+        let fragile = "tacticInfo:markFragile(\"slow search\")";
+        assert_eq!(check(fragile, |_| {}), (false, true));
+    }
+
+    #[test]
+    fn deny_fragile_fails_fragile_proofs() {
+        let fragile = "tacticInfo:markFragile(\"slow search\")";
+        let deny = |config: &mut WatsonConfig| config.set_deny_fragile(true);
+        assert_eq!(check(fragile, deny), (true, false));
+        assert_eq!(check("", deny), (false, true));
+    }
+
+    #[test]
+    fn deny_todo_fails_proofs_using_todo() {
+        let deny = |config: &mut WatsonConfig| config.set_deny_todo(Vec::new());
+        assert_eq!(check("", |_| {}), (false, true));
+        assert_eq!(check("", deny), (true, false));
     }
 
-    Ok(ProofStatus::from_cert(cert))
+    #[test]
+    fn deny_todo_except_allows_the_listed_tickets() {
+        let allow = |config: &mut WatsonConfig| config.set_deny_todo(vec!["MATH-42".to_string()]);
+        let ticket = |ticket: &str| format!("note = {{reason = \"later\", ticket = \"{ticket}\"}}");
+        assert_eq!(check(&ticket("MATH-42"), allow), (false, true));
+        assert_eq!(check(&ticket("MATH-7"), allow), (true, false));
+        assert_eq!(check("", allow), (true, false));
+    }
+
+    #[test]
+    fn proofs_can_use_another_tactic_category() {
+        let main = "module logic\n\n\
+            grammar_category calc_step\n\n\
+            tactic calc_done\n    calc_step ::= \"∎\"\nend\n\n\
+            tactic plain_done\n    tactic ::= \"□\"\nend\n\n\
+            theorem plain [p : sentence] :\n    |- p → p\nproof\n    □\nqed\n\n\
+            theorem calc [p : sentence] :\n    |- p → p\nproof using calc_step\n    ∎\nqed\n";
+        // Without `handleTactic`, a proof whose rule has no handler fails.
+        let handlers = "local M = {}\n\n\
+            local function todo(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   return proofState:applyTodo(proofState.goal)\n\
+            end\n\n\
+            M.tactics = {\n\
+            \x20   plain_done = todo,\n\
+            \x20   calc_done = todo,\n\
+            } :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handlers);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        assert!(!checked.ctx().diags.has_errors());
+        let mut correct: Vec<_> = (checked.proof_report().statuses.iter())
+            .filter(|(_, status)| status.correct())
+            .map(|(thm, _)| thm.name().to_string())
+            .collect();
+        correct.sort();
+        // The axioms of `logic` are correct too.
+        assert_eq!(correct, ["calc", "dne", "imp.intro", "mp", "plain"]);
+    }
+
+    /// A sentinel which only the axiom `bad` can prove.
+    const SENTINEL: &str = "theorem false_is_not_provable :\n    |- ⊥\nproof\n    by bad\nqed\n";
+
+    /// Check `main` with `false_is_not_provable` as the consistency sentinel,
+    /// returning the titles of the errors and of the other diagnostics about
+    /// the sentinel, and whether it held.
+    fn check_sentinel(main: &str) -> (Vec<String>, Vec<String>, Option<bool>) {
+        let project = TestProject::with_logic(main);
+        project.write(
+            "watson.toml",
+            "[check]\nsentinel_theorem = \"false_is_not_provable\"\n",
+        );
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let titles = |errors: bool| {
+            (checked.ctx().diags.iter())
+                .filter(|diag| diag.is_error() == errors)
+                .filter(|diag| errors || diag.title().starts_with("consistency sentinel"))
+                .map(|diag| diag.title().to_string())
+                .collect()
+        };
+        let held = checked
+            .proof_report()
+            .statuses
+            .sentinel()
+            .map(|(_, held)| held);
+        (titles(true), titles(false), held)
+    }
+
+    #[test]
+    fn an_unprovable_sentinel_holds() {
+        let (errors, notes, held) = check_sentinel(&format!("module logic\n\n{SENTINEL}"));
+        assert_eq!(errors, Vec::<String>::new());
+        assert_eq!(
+            notes,
+            ["consistency sentinel `false_is_not_provable` failed to prove, as expected"]
+        );
+        assert_eq!(held, Some(true));
+    }
+
+    #[test]
+    fn a_bad_axiom_flips_the_sentinel() {
+        let main = format!(
+            "module logic\n\n\
+             axiom bad :\n    |- ⊥\nend\n\n\
+             {SENTINEL}\n\
+             theorem relies.on.sentinel :\n    |- ⊥\nproof\n    by false_is_not_provable\nqed\n"
+        );
+        let (errors, _, held) = check_sentinel(&main);
+        assert_eq!(
+            errors,
+            [
+                "consistency sentinel `false_is_not_provable` was proved",
+                "theorem `relies.on.sentinel` uses the consistency sentinel `false_is_not_provable`",
+            ]
+        );
+        assert_eq!(held, Some(false));
+    }
+
+    #[test]
+    fn abbreviations_stay_folded_until_unfolded() {
+        // Each scope has its own abbreviation named `top`.
+        let main = "module logic\n\n\
+            notation top\n    sentence ::= \"⊤\"\nend\n\n\
+            tactic unfold\n    tactic ::= @kw\"unfold\" abbr:@name @kw\"of\" thm:@name\nend\n\n\
+            scope\n\n\
+            abbreviation\n    ⊤ := ⊥ → ⊥\nend\n\n\
+            axiom top.intro :\n    |- ⊤\nend\n\n\
+            theorem folded :\n    |- ⊥ → ⊥\nproof\n    by top.intro\nqed\n\n\
+            theorem unfolded :\n    |- ⊥ → ⊥\nproof\n    unfold top of top.intro\nqed\n\n\
+            end\n\n\
+            scope\n\n\
+            abbreviation\n    ⊤ := ⊥\nend\n\n\
+            axiom top.false :\n    |- ⊤\nend\n\n\
+            theorem unfolded.other :\n    |- ⊥\nproof\n    unfold top of top.false\nqed\n\n\
+            end\n";
+        // Apply the theorem, and unfold its conclusion for `unfold`.
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local thm = Theorem.lookup(tactic.thm.str)\n\
+            \x20   local state = proofState:applyTheorem(thm, {}, tactic._span)\n\
+            \x20   if tactic._rule == \"unfold\" then\n\
+            \x20       state = state:unfold(thm.conclusion, tactic.abbr.str, tactic._span)\n\
+            \x20   end\n\
+            \x20   return state\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let correct = |name: &str| {
+            (checked.proof_report().statuses.iter())
+                .find(|(thm, _)| thm.name() == name)
+                .map(|(_, status)| status.correct())
+        };
+        assert_eq!(correct("folded"), Some(false));
+        assert_eq!(correct("unfolded"), Some(true));
+        assert_eq!(correct("unfolded.other"), Some(true));
+    }
+
+    #[test]
+    fn cases_proves_each_branch_as_a_subgoal() {
+        let main = "module logic\n\n\
+            tactic cases\n    tactic ::= @kw\"cases\" disj:@fragment(sentence) \
+            \"{\" left:tactic \"}\" \"{\" right:tactic \"}\"\nend\n\n\
+            axiom or.elim [p q r : sentence] : (p ∨ q) (p → r) (q → r)\n    |- r\nend\n\n\
+            axiom refl [p : sentence] :\n    |- p → p\nend\n\n\
+            theorem or.idem [a : sentence] : (a ∨ a)\n    |- a\nproof\n    \
+            cases a ∨ a { by refl [a] } { by refl [a] }\nqed\n\n\
+            theorem or.wrong [a b : sentence] : (a ∨ b)\n    |- a\nproof\n    \
+            cases a ∨ b { by refl [a] } { by refl [b] }\nqed\n";
+        // `cases` proves `p → r` and `q → r` as subgoals, merges them and
+        // then applies `or.elim`.
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local scope = proofState.theorem.scope\n\
+            \x20   if tactic._rule == \"by\" then\n\
+            \x20       local args = {}\n\
+            \x20       for i, arg in tactic.args do\n\
+            \x20           args[i] = arg.frag:resolve(scope, FormalCat:sentence())\n\
+            \x20       end\n\
+            \x20       return proofState:applyTheorem(Theorem.lookup(tactic.thm.str), args, tactic._span)\n\
+            \x20   end\n\
+            \x20   local elim = Theorem.lookup(\"or.elim\")\n\
+            \x20   local disj = Frag.match(elim.hypotheses[1].conclusion, tactic.disj:resolve(scope))\n\
+            \x20   local templates = { disj[\"$0\"], disj[\"$1\"], proofState.goal }\n\
+            \x20   local state = proofState\n\
+            \x20   for i, branch in { tactic.left, tactic.right } do\n\
+            \x20       local goal = elim.hypotheses[i + 1].conclusion:instantiateTemplates(templates)\n\
+            \x20       local case = M.handleTactic(branch, state:withGoal(goal), tacticInfo)\n\
+            \x20       state = state:merge(case, branch._span)\n\
+            \x20   end\n\
+            \x20   return state:applyTheorem(elim, templates, tactic._span)\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let correct = |name: &str| {
+            (checked.proof_report().statuses.iter())
+                .find(|(thm, _)| thm.name() == name)
+                .map(|(_, status)| status.correct())
+        };
+        assert_eq!(correct("or.idem"), Some(true));
+        // The second branch proves `b → b`, not `b → a`, so it can't be merged.
+        assert_eq!(correct("or.wrong"), Some(false));
+        assert!(checked.ctx().diags.iter().any(|diag| {
+            diag.is_error() && diag.title().contains("the subgoal's goal isn't proved yet")
+        }));
+    }
+
+    #[test]
+    fn modus_ponens_by_matching_hypotheses() {
+        let main = "module logic\n\n\
+            tactic mp_auto\n    tactic ::= @kw\"mp_auto\"\nend\n\n\
+            theorem chain [a b : sentence] : (¬a → b) (¬a)\n    |- b\nproof\n    mp_auto\nqed\n\n\
+            theorem wrong [a b : sentence] : (b → a) (a)\n    |- b\nproof\n    mp_auto\nqed\n";
+        // Find a hypothesis matching the implication `mp` needs whose
+        // antecedent is known, and apply `mp` if that proves the goal.
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local mp = Theorem.lookup(\"mp\")\n\
+            \x20   for _, hyp in proofState.theorem.hypotheses do\n\
+            \x20       local found = Frag.match(mp.hypotheses[1].conclusion, hyp.conclusion)\n\
+            \x20       local antecedent = found and mp.hypotheses[2].conclusion:substitute(found)\n\
+            \x20       if found ~= nil and proofState:knows(Fact:newC(antecedent)) then\n\
+            \x20           local state = proofState:applyTheorem(mp, { found[\"$0\"], found[\"$1\"] }, tactic._span)\n\
+            \x20           if state:knows(Fact:newC(proofState.goal)) then\n\
+            \x20               return state\n\
+            \x20           end\n\
+            \x20       end\n\
+            \x20   end\n\
+            \x20   error(\"no hypothesis proves the goal by modus ponens\")\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let correct = |name: &str| {
+            (checked.proof_report().statuses.iter())
+                .find(|(thm, _)| thm.name() == name)
+                .map(|(_, status)| status.correct())
+        };
+        // `¬a → b` is `(a → ⊥) → b`, so `mp` is applied with `a → ⊥` and `b`.
+        assert_eq!(correct("chain"), Some(true));
+        assert_eq!(correct("wrong"), Some(false));
+        let errors: Vec<_> = (checked.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title())
+            .collect();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].contains("no hypothesis proves the goal by modus ponens"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn omitted_templates_take_their_default() {
+        let main = "module logic\n\n\
+            tactic use\n    tactic ::= @kw\"use\" thm:@name\nend\n\n\
+            tactic use.at\n    tactic ::= @kw\"use\" thm:@name \"[\" frag:@fragment(sentence) \"]\"\nend\n\n\
+            tactic use.named\n    tactic ::= @kw\"use\" thm:@name \"[\" name:@name \":=\" frag:@fragment(sentence) \"]\"\nend\n\n\
+            axiom id [p := ⊥ : sentence] :\n    |- p → p\nend\n\n\
+            theorem by.default :\n    |- ⊥ → ⊥\nproof\n    use id\nqed\n\n\
+            theorem by.position :\n    |- (⊥ → ⊥) → ⊥ → ⊥\nproof\n    use id [⊥ → ⊥]\nqed\n\n\
+            theorem by.name :\n    |- (⊥ → ⊥) → ⊥ → ⊥\nproof\n    use id [p := ⊥ → ⊥]\nqed\n\n\
+            theorem by.wrong.name :\n    |- ⊥ → ⊥\nproof\n    use id [q := ⊥]\nqed\n";
+        // Apply the theorem with no templates, one by position or one by name.
+        let handler = "local M = {}\n\n\
+            function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)\n\
+            \x20   local thm = Theorem.lookup(tactic.thm.str)\n\
+            \x20   local templates = {}\n\
+            \x20   if tactic._rule ~= \"use\" then\n\
+            \x20       local frag = tactic.frag:resolve(proofState.theorem.scope)\n\
+            \x20       if tactic._rule == \"use.at\" then\n\
+            \x20           templates = { frag }\n\
+            \x20       else\n\
+            \x20           templates = { [tactic.name.str] = frag }\n\
+            \x20       end\n\
+            \x20   end\n\
+            \x20   return proofState:applyTheorem(thm, templates, tactic._span)\n\
+            end\n\n\
+            M.tactics = {} :: TacticHandlers\n\n\
+            return M\n";
+        let project = TestProject::with_logic(main);
+        project.write("script/main.luau", handler);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        let correct = |name: &str| {
+            (checked.proof_report().statuses.iter())
+                .find(|(thm, _)| thm.name() == name)
+                .map(|(_, status)| status.correct())
+        };
+        assert_eq!(correct("by.default"), Some(true));
+        assert_eq!(correct("by.position"), Some(true));
+        assert_eq!(correct("by.name"), Some(true));
+        assert_eq!(correct("by.wrong.name"), Some(false));
+
+        let errors: Vec<_> = (checked.ctx().diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| diag.title())
+            .collect();
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].contains("applying `id`: no template named `q`"),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn fail_fast_stops_at_the_first_error() {
+        let second = "\ntheorem refl2 [p : sentence] :\n    |- p → p\nproof\nqed\n";
+        let project = TestProject::with_logic(&format!("{MAIN}{second}"));
+        project.write("script/main.luau", &handler("error(\"stuck\")"));
+        let mut config = project.config();
+        config.set_fail_fast(true);
+
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(config, &arenas).parse().check();
+        // Only the axioms of `logic` and the first theorem were checked.
+        assert_eq!(checked.proof_report().statuses.iter().count(), 4);
+        assert_eq!(checked.ctx().diags.count(), 1);
+    }
 }
@@ -0,0 +1,673 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::CString,
+    fmt::{self, Write},
+    fs,
+    io::{self, Read},
+    path::Path,
+    process::{self, Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+use vampire_prover::{Formula, Options, Problem, Proof, ProofRes, UnknownReason};
+
+/// How often to check whether a strategy has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The saturation algorithm the embedded prover normally uses.
+const DEFAULT_SATURATION: &str = "lrs";
+
+/// A way of running Vampire, tried alongside the others in a portfolio.
+#[derive(Debug, Clone, Copy)]
+pub struct Strategy {
+    pub name: &'static str,
+    /// The arguments to run the Vampire binary with.
+    args: &'static [&'static str],
+    /// The saturation algorithm to run the embedded prover with, or `None`
+    /// if the strategy needs the binary.
+    saturation: Option<&'static str>,
+}
+
+/// The strategies tried when a Vampire binary is configured.
+pub const DEFAULT_PORTFOLIO: &[Strategy] = &[
+    Strategy {
+        name: "casc mode",
+        args: &["--mode", "casc"],
+        saturation: None,
+    },
+    Strategy {
+        name: "default",
+        args: &[],
+        saturation: Some(DEFAULT_SATURATION),
+    },
+    Strategy {
+        name: "lrs saturation",
+        args: &["--saturation_algorithm", "lrs"],
+        saturation: Some("lrs"),
+    },
+    Strategy {
+        name: "discount saturation",
+        args: &["--saturation_algorithm", "discount"],
+        saturation: Some("discount"),
+    },
+];
+
+/// How a single strategy ended. Only `Proved` and `Unprovable` settle the
+/// problem, the rest are normal outcomes for a strategy which didn't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Proved,
+    Unprovable,
+    Timeout,
+    /// Stopped because another strategy settled the problem first.
+    Cancelled,
+    /// Exited without an answer, including by crashing.
+    Failed,
+    /// Not run, because the strategy needs the Vampire binary.
+    Skipped,
+}
+
+impl Outcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Proved => "proved",
+            Outcome::Unprovable => "unprovable",
+            Outcome::Timeout => "timeout",
+            Outcome::Cancelled => "cancelled",
+            Outcome::Failed => "failed",
+            Outcome::Skipped => "skipped",
+        }
+    }
+
+    fn settles(self) -> bool {
+        matches!(self, Outcome::Proved | Outcome::Unprovable)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Attempt {
+    pub strategy: &'static str,
+    pub outcome: Outcome,
+    /// How long after the portfolio started the strategy finished.
+    pub duration: Duration,
+}
+
+#[derive(Debug)]
+pub struct PortfolioResult {
+    /// Every strategy tried, in the order they finished.
+    pub attempts: Vec<Attempt>,
+    /// The index of the attempt which settled the problem, if any did.
+    pub winner: Option<usize>,
+    /// The proof found, which only the embedded prover gives.
+    pub proof: Option<Proof>,
+}
+
+impl PortfolioResult {
+    pub fn winner(&self) -> Option<&Attempt> {
+        self.winner.map(|idx| &self.attempts[idx])
+    }
+}
+
+/// A Vampire problem which remembers its axioms and conjecture so it can be
+/// handed to a Vampire binary as well as the embedded prover. Formulas are
+/// kept alongside their TPTP form for the binary, which formulas read back
+/// from a proof don't have.
+#[derive(Debug, Clone)]
+pub struct VampireProblem {
+    options: Options,
+    axioms: Vec<(Formula, Option<TptpFormula>)>,
+    conjecture: Option<(Formula, Option<TptpFormula>)>,
+}
+
+impl VampireProblem {
+    pub fn new(options: Options) -> Self {
+        Self {
+            options,
+            axioms: Vec::new(),
+            conjecture: None,
+        }
+    }
+
+    pub fn add_axiom(&mut self, axiom: Formula, tptp: Option<TptpFormula>) {
+        self.axioms.push((axiom, tptp));
+    }
+
+    pub fn set_conjecture(&mut self, conjecture: Formula, tptp: Option<TptpFormula>) {
+        self.conjecture = Some((conjecture, tptp));
+    }
+
+    fn to_problem(&self, options: Options) -> Problem {
+        let mut problem = Problem::new(options);
+        for &(axiom, _) in &self.axioms {
+            problem.with_axiom(axiom);
+        }
+        if let Some((conjecture, _)) = self.conjecture {
+            problem.conjecture(conjecture);
+        }
+        problem
+    }
+
+    pub fn solve_and_prove(&self) -> (ProofRes, Option<Proof>) {
+        self.to_problem(self.options.clone()).solve_and_prove()
+    }
+
+    /// Run `strategies` at once with a shared wall-clock `budget`, stopping
+    /// the rest as soon as one settles the problem. Each strategy is a
+    /// separate process, so a crash only ends that strategy.
+    ///
+    /// The embedded prover can only run one proof at a time, so without a
+    /// `binary` the strategies it can run are tried one after another, each
+    /// with an equal share of what is left of the budget.
+    pub fn solve_portfolio(
+        &self,
+        strategies: &[Strategy],
+        budget: Duration,
+        binary: Option<&Path>,
+    ) -> io::Result<PortfolioResult> {
+        match binary {
+            Some(binary) => self.solve_with_binary(binary, strategies, budget),
+            None => Ok(self.solve_embedded(strategies, budget)),
+        }
+    }
+
+    fn solve_embedded(&self, strategies: &[Strategy], budget: Duration) -> PortfolioResult {
+        let start = Instant::now();
+        let mut attempts = Vec::new();
+        let mut winner = None;
+        let mut proof = None;
+
+        let mut runnable: Vec<_> = strategies
+            .iter()
+            .filter(|s| s.saturation.is_some())
+            .collect();
+        for strategy in strategies.iter().filter(|s| s.saturation.is_none()) {
+            attempts.push(Attempt {
+                strategy: strategy.name,
+                outcome: Outcome::Skipped,
+                duration: Duration::ZERO,
+            });
+        }
+
+        while winner.is_none() && !runnable.is_empty() {
+            let strategy = runnable.remove(0);
+            let share = budget.saturating_sub(start.elapsed()) / (runnable.len() as u32 + 1);
+            let outcome = if share.is_zero() {
+                Outcome::Timeout
+            } else {
+                let mut options = self.options.clone();
+                options.timeout(share);
+                set_saturation(strategy.saturation.unwrap());
+                let (result, found) = self.to_problem(options).solve_and_prove();
+                proof = found;
+                match result {
+                    ProofRes::Proved => Outcome::Proved,
+                    ProofRes::Unprovable => Outcome::Unprovable,
+                    ProofRes::Unknown(UnknownReason::Timeout) => Outcome::Timeout,
+                    ProofRes::Unknown(_) => Outcome::Failed,
+                }
+            };
+            attempts.push(Attempt {
+                strategy: strategy.name,
+                outcome,
+                duration: start.elapsed(),
+            });
+            if outcome.settles() {
+                winner = Some(attempts.len() - 1);
+            }
+        }
+        set_saturation(DEFAULT_SATURATION);
+
+        for strategy in runnable {
+            attempts.push(Attempt {
+                strategy: strategy.name,
+                outcome: Outcome::Cancelled,
+                duration: start.elapsed(),
+            });
+        }
+
+        PortfolioResult {
+            attempts,
+            winner,
+            proof: if winner.is_some() { proof } else { None },
+        }
+    }
+
+    fn solve_with_binary(
+        &self,
+        binary: &Path,
+        strategies: &[Strategy],
+        budget: Duration,
+    ) -> io::Result<PortfolioResult> {
+        static NEXT_PROBLEM: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir().join(format!(
+            "watson-vampire-{}-{}.p",
+            process::id(),
+            NEXT_PROBLEM.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, self.to_tptp()?)?;
+
+        let start = Instant::now();
+        // Vampire reads a `d` suffix as deciseconds. It is also stopped here
+        // once the budget runs out in case it overshoots.
+        let time_limit = format!("{}d", budget.as_millis().div_ceil(100).max(1));
+        let mut running: Vec<(_, process::Child, _)> = Vec::new();
+        for strategy in strategies {
+            let spawned = Command::new(binary)
+                .args(strategy.args)
+                .arg("--time_limit")
+                .arg(&time_limit)
+                .arg(&path)
+                .stdin(Stdio::null())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn();
+            let mut child = match spawned {
+                Ok(child) => child,
+                Err(err) => {
+                    for (_, mut child, _) in running {
+                        _ = child.kill();
+                        _ = child.wait();
+                    }
+                    _ = fs::remove_file(&path);
+                    return Err(err);
+                }
+            };
+
+            // Read the output as it comes so a long proof can't fill the pipe
+            // and block the prover.
+            let mut stdout = child.stdout.take().unwrap();
+            let output = thread::spawn(move || {
+                let mut output = String::new();
+                _ = stdout.read_to_string(&mut output);
+                output
+            });
+            running.push((strategy, child, output));
+        }
+
+        let mut attempts = Vec::new();
+        let mut winner = None;
+        while !running.is_empty() && winner.is_none() && start.elapsed() < budget {
+            let finished = running
+                .iter_mut()
+                .position(|(_, child, _)| !matches!(child.try_wait(), Ok(None)));
+            let Some(idx) = finished else {
+                thread::sleep(POLL_INTERVAL);
+                continue;
+            };
+
+            let (strategy, _, output) = running.swap_remove(idx);
+            let outcome = read_outcome(&output.join().unwrap_or_default());
+            attempts.push(Attempt {
+                strategy: strategy.name,
+                outcome,
+                duration: start.elapsed(),
+            });
+            if outcome.settles() {
+                winner = Some(attempts.len() - 1);
+            }
+        }
+
+        let outcome = match winner {
+            Some(_) => Outcome::Cancelled,
+            None => Outcome::Timeout,
+        };
+        for (strategy, mut child, output) in running {
+            _ = child.kill();
+            _ = child.wait();
+            _ = output.join();
+            attempts.push(Attempt {
+                strategy: strategy.name,
+                outcome,
+                duration: start.elapsed(),
+            });
+        }
+        _ = fs::remove_file(&path);
+
+        Ok(PortfolioResult {
+            attempts,
+            winner,
+            proof: None,
+        })
+    }
+
+    /// The problem in TPTP for the Vampire binary.
+    fn to_tptp(&self) -> io::Result<String> {
+        let no_tptp = || {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "formulas read back from a proof can't be given to the Vampire binary",
+            )
+        };
+
+        let mut out = String::new();
+        for (i, (_, axiom)) in self.axioms.iter().enumerate() {
+            let axiom = axiom.as_ref().ok_or_else(no_tptp)?;
+            writeln!(out, "fof(axiom_{i}, axiom, {}).", axiom.closed()).unwrap();
+        }
+        if let Some((_, conjecture)) = &self.conjecture {
+            let conjecture = conjecture.as_ref().ok_or_else(no_tptp)?;
+            writeln!(out, "fof(goal, conjecture, {}).", conjecture.closed()).unwrap();
+        }
+        Ok(out)
+    }
+}
+
+/// Switch the saturation algorithm of the embedded prover. It is global
+/// state which preparing the next proof doesn't reset.
+fn set_saturation(algorithm: &str) {
+    let algorithm = CString::new(algorithm).unwrap();
+    // SAFETY: the string outlives the call, and proofs are only checked on
+    // one thread so no proof is running.
+    unsafe {
+        vampire_sys::vampire_set_saturation_algorithm(algorithm.as_ptr());
+    }
+}
+
+/// A term as written to TPTP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TptpTerm {
+    Var(u32),
+    App(String, Vec<TptpTerm>),
+}
+
+/// A first-order formula as written to TPTP. The embedded prover's formulas
+/// can't be taken apart again, so the Lua API builds one of these alongside
+/// each of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TptpFormula {
+    Pred(String, Vec<TptpTerm>),
+    Eq(TptpTerm, TptpTerm),
+    And(Vec<TptpFormula>),
+    Or(Vec<TptpFormula>),
+    Not(Box<TptpFormula>),
+    Imp(Box<TptpFormula>, Box<TptpFormula>),
+    Iff(Box<TptpFormula>, Box<TptpFormula>),
+    Forall(u32, Box<TptpFormula>),
+    Exists(u32, Box<TptpFormula>),
+    True,
+    False,
+}
+
+impl TptpTerm {
+    fn free_vars(&self, bound: &mut Vec<u32>, free: &mut BTreeSet<u32>) {
+        match self {
+            TptpTerm::Var(idx) if !bound.contains(idx) => {
+                free.insert(*idx);
+            }
+            TptpTerm::Var(_) => {}
+            TptpTerm::App(_, args) => args.iter().for_each(|arg| arg.free_vars(bound, free)),
+        }
+    }
+}
+
+impl TptpFormula {
+    /// The formula with its free variables bound universally, as TPTP
+    /// doesn't allow free variables in `fof` formulas.
+    pub fn closed(&self) -> TptpFormula {
+        let mut free = BTreeSet::new();
+        self.free_vars(&mut Vec::new(), &mut free);
+        free.into_iter()
+            .rev()
+            .fold(self.clone(), |f, var| TptpFormula::Forall(var, Box::new(f)))
+    }
+
+    fn free_vars(&self, bound: &mut Vec<u32>, free: &mut BTreeSet<u32>) {
+        match self {
+            TptpFormula::Pred(_, args) => args.iter().for_each(|arg| arg.free_vars(bound, free)),
+            TptpFormula::Eq(lhs, rhs) => {
+                lhs.free_vars(bound, free);
+                rhs.free_vars(bound, free);
+            }
+            TptpFormula::And(fs) | TptpFormula::Or(fs) => {
+                fs.iter().for_each(|f| f.free_vars(bound, free))
+            }
+            TptpFormula::Not(f) => f.free_vars(bound, free),
+            TptpFormula::Imp(lhs, rhs) | TptpFormula::Iff(lhs, rhs) => {
+                lhs.free_vars(bound, free);
+                rhs.free_vars(bound, free);
+            }
+            TptpFormula::Forall(var, f) | TptpFormula::Exists(var, f) => {
+                bound.push(*var);
+                f.free_vars(bound, free);
+                bound.pop();
+            }
+            TptpFormula::True | TptpFormula::False => {}
+        }
+    }
+}
+
+/// Write a function or predicate name, quoting it unless it is a TPTP
+/// lower word.
+fn write_name(f: &mut fmt::Formatter<'_>, name: &str) -> fmt::Result {
+    let mut chars = name.chars();
+    let lower_word = chars.next().is_some_and(|c| c.is_ascii_lowercase())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if lower_word {
+        return f.write_str(name);
+    }
+
+    f.write_char('\'')?;
+    for c in name.chars() {
+        if matches!(c, '\'' | '\\') {
+            f.write_char('\\')?;
+        }
+        f.write_char(c)?;
+    }
+    f.write_char('\'')
+}
+
+fn write_app<T: fmt::Display>(f: &mut fmt::Formatter<'_>, name: &str, args: &[T]) -> fmt::Result {
+    write_name(f, name)?;
+    if args.is_empty() {
+        return Ok(());
+    }
+    f.write_char('(')?;
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            f.write_char(',')?;
+        }
+        write!(f, "{arg}")?;
+    }
+    f.write_char(')')
+}
+
+fn write_joined(f: &mut fmt::Formatter<'_>, fs: &[TptpFormula], op: &str) -> fmt::Result {
+    f.write_char('(')?;
+    for (i, formula) in fs.iter().enumerate() {
+        if i > 0 {
+            write!(f, " {op} ")?;
+        }
+        write!(f, "{formula}")?;
+    }
+    f.write_char(')')
+}
+
+impl fmt::Display for TptpTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TptpTerm::Var(idx) => write!(f, "X{idx}"),
+            TptpTerm::App(name, args) => write_app(f, name, args),
+        }
+    }
+}
+
+impl fmt::Display for TptpFormula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TptpFormula::Pred(name, args) => write_app(f, name, args),
+            TptpFormula::Eq(lhs, rhs) => write!(f, "{lhs} = {rhs}"),
+            TptpFormula::And(fs) if fs.is_empty() => f.write_str("$true"),
+            TptpFormula::Or(fs) if fs.is_empty() => f.write_str("$false"),
+            TptpFormula::And(fs) => write_joined(f, fs, "&"),
+            TptpFormula::Or(fs) => write_joined(f, fs, "|"),
+            TptpFormula::Not(inner) => write!(f, "~ ({inner})"),
+            TptpFormula::Imp(lhs, rhs) => write!(f, "({lhs} => {rhs})"),
+            TptpFormula::Iff(lhs, rhs) => write!(f, "({lhs} <=> {rhs})"),
+            TptpFormula::Forall(var, inner) => write!(f, "(! [X{var}] : {inner})"),
+            TptpFormula::Exists(var, inner) => write!(f, "(? [X{var}] : {inner})"),
+            TptpFormula::True => f.write_str("$true"),
+            TptpFormula::False => f.write_str("$false"),
+        }
+    }
+}
+
+/// Read the verdict from Vampire's output. In casc mode it is given as an
+/// SZS status, otherwise as the reason Vampire terminated.
+fn read_outcome(output: &str) -> Outcome {
+    let status = output
+        .lines()
+        .find_map(|line| line.split("SZS status ").nth(1))
+        .and_then(|rest| rest.split_whitespace().next());
+    match status {
+        Some("Theorem" | "Unsatisfiable" | "ContradictoryAxioms") => Outcome::Proved,
+        Some("CounterSatisfiable" | "Satisfiable") => Outcome::Unprovable,
+        Some("Timeout") => Outcome::Timeout,
+        _ if output.contains("Termination reason: Refutation") => Outcome::Proved,
+        _ if output.contains("Termination reason: Satisfiable") => Outcome::Unprovable,
+        _ if output.contains("Termination reason: Time limit") => Outcome::Timeout,
+        _ => Outcome::Failed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// A stand-in for the Vampire binary which runs `script` with the
+    /// strategy's arguments. Long-running scripts `exec` so stopping the
+    /// stub closes its output.
+    struct StubProver {
+        path: std::path::PathBuf,
+    }
+
+    impl StubProver {
+        fn new(name: &str, script: &str) -> Self {
+            let path = env::temp_dir().join(format!("watson-stub-{}-{name}", process::id()));
+            fs::write(&path, format!("#!/bin/sh\n{script}\n")).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+            Self { path }
+        }
+    }
+
+    impl Drop for StubProver {
+        fn drop(&mut self) {
+            _ = fs::remove_file(&self.path);
+        }
+    }
+
+    fn problem() -> VampireProblem {
+        let mut problem = VampireProblem::new(Options::new());
+        problem.set_conjecture(Formula::new_true(), Some(TptpFormula::True));
+        problem
+    }
+
+    fn outcomes(result: &PortfolioResult) -> Vec<(&str, Outcome)> {
+        let mut outcomes: Vec<_> = result
+            .attempts
+            .iter()
+            .map(|attempt| (attempt.strategy, attempt.outcome))
+            .collect();
+        outcomes.sort_by_key(|&(strategy, _)| strategy);
+        outcomes
+    }
+
+    #[test]
+    fn first_strategy_to_settle_wins() {
+        let stub = StubProver::new(
+            "success",
+            r#"if [ "$2" = casc ]; then echo "% SZS status Theorem for goal"; else exec sleep 5; fi"#,
+        );
+        let result = problem()
+            .solve_portfolio(DEFAULT_PORTFOLIO, Duration::from_secs(3), Some(&stub.path))
+            .unwrap();
+
+        let winner = result.winner().unwrap();
+        assert_eq!(
+            (winner.strategy, winner.outcome),
+            ("casc mode", Outcome::Proved)
+        );
+        assert_eq!(
+            outcomes(&result),
+            [
+                ("casc mode", Outcome::Proved),
+                ("default", Outcome::Cancelled),
+                ("discount saturation", Outcome::Cancelled),
+                ("lrs saturation", Outcome::Cancelled),
+            ]
+        );
+    }
+
+    #[test]
+    fn failing_strategies_settle_nothing() {
+        let stub = StubProver::new("fail", "echo 'Segmentation fault'; exit 1");
+        let result = problem()
+            .solve_portfolio(DEFAULT_PORTFOLIO, Duration::from_secs(3), Some(&stub.path))
+            .unwrap();
+
+        assert!(result.winner().is_none());
+        assert!(result.attempts.iter().all(|a| a.outcome == Outcome::Failed));
+        assert_eq!(result.attempts.len(), DEFAULT_PORTFOLIO.len());
+    }
+
+    #[test]
+    fn strategies_are_stopped_when_the_budget_runs_out() {
+        let stub = StubProver::new("timeout", "exec sleep 5");
+        let start = Instant::now();
+        let result = problem()
+            .solve_portfolio(
+                DEFAULT_PORTFOLIO,
+                Duration::from_millis(200),
+                Some(&stub.path),
+            )
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(result.winner().is_none());
+        assert!(
+            result
+                .attempts
+                .iter()
+                .all(|a| a.outcome == Outcome::Timeout)
+        );
+        assert_eq!(result.attempts.len(), DEFAULT_PORTFOLIO.len());
+    }
+
+    #[test]
+    fn formulas_from_proofs_are_not_sent_to_the_binary() {
+        let stub = StubProver::new("unused", "exit 1");
+        let mut problem = problem();
+        problem.add_axiom(Formula::new_true(), None);
+        let result =
+            problem.solve_portfolio(DEFAULT_PORTFOLIO, Duration::from_secs(1), Some(&stub.path));
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn writes_tptp() {
+        let x = || TptpTerm::Var(0);
+        let zero = TptpTerm::App("0".to_string(), Vec::new());
+        let le = |a, b| TptpFormula::Pred("Le".to_string(), vec![a, b]);
+        let formula = TptpFormula::Imp(
+            Box::new(TptpFormula::And(vec![
+                le(x(), TptpTerm::App("succ".to_string(), vec![zero.clone()])),
+                TptpFormula::Not(Box::new(TptpFormula::Eq(x(), zero.clone()))),
+            ])),
+            Box::new(TptpFormula::Exists(
+                1,
+                Box::new(TptpFormula::Pred(
+                    "it's\\".to_string(),
+                    vec![TptpTerm::Var(1)],
+                )),
+            )),
+        );
+
+        assert_eq!(
+            formula.closed().to_string(),
+            "(! [X0] : (('Le'(X0,succ('0')) & ~ (X0 = '0')) => (? [X1] : 'it\\'s\\\\'(X1))))"
+        );
+        assert_eq!(TptpFormula::Or(Vec::new()).to_string(), "$false");
+    }
+}
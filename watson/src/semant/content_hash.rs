@@ -0,0 +1,261 @@
+use crate::semant::{
+    formal_syntax::{FormalSyntaxCatId, FormalSyntaxPatPart, FormalSyntaxRuleId},
+    fragment::{FragHead, FragmentId},
+    theorems::{Template, TheoremStatement},
+};
+use std::fmt;
+
+/// A hash of what a theorem states, which stays the same across runs and when
+/// the theorem is renamed. Templates are hashed by their position and
+/// category, bound variables are already de Bruijn indices and syntax rules
+/// are hashed by their category, name and pattern rather than where they are
+/// in the arena. Abbreviations are hashed with their definition so changing
+/// one changes the hash of the statements using it. The order of the
+/// hypotheses matters, since reordering them changes how the theorem is
+/// applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash(u64);
+
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+impl<'ctx> TheoremStatement<'ctx> {
+    pub fn content_hash(&self) -> ContentHash {
+        let mut hasher = ContentHasher::new();
+
+        hasher.usize(self.templates().len());
+        for template in self.templates() {
+            hasher.template(template);
+        }
+
+        hasher.usize(self.hypotheses().len());
+        for hypothesis in self.hypotheses() {
+            match hypothesis.assumption() {
+                Some(assumption) => {
+                    hasher.tag(1);
+                    hasher.frag(assumption.frag());
+                }
+                None => hasher.tag(0),
+            }
+            hasher.frag(hypothesis.conclusion().frag());
+        }
+
+        hasher.frag(self.conclusion().frag());
+
+        ContentHash(hasher.finish())
+    }
+}
+
+impl ContentHash {
+    /// A hash of one template of a statement, in the same way as the whole
+    /// statement is hashed.
+    pub fn of_template(template: &Template) -> Self {
+        let mut hasher = ContentHasher::new();
+        hasher.template(template);
+        ContentHash(hasher.finish())
+    }
+
+    /// A hash of one fragment of a statement, in the same way as the whole
+    /// statement is hashed.
+    pub fn of_frag(frag: FragmentId) -> Self {
+        let mut hasher = ContentHasher::new();
+        hasher.frag(frag);
+        ContentHash(hasher.finish())
+    }
+}
+
+/// 64 bit FNV-1a. The hashers in the standard library and `rustc_hash` are
+/// allowed to change between versions so they can't be used for hashes which
+/// are stored.
+struct ContentHasher(u64);
+
+impl ContentHasher {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    fn new() -> Self {
+        Self(Self::OFFSET_BASIS)
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(Self::PRIME);
+        }
+    }
+
+    fn tag(&mut self, tag: u8) {
+        self.bytes(&[tag]);
+    }
+
+    fn usize(&mut self, n: usize) {
+        self.bytes(&(n as u64).to_le_bytes());
+    }
+
+    /// Strings are prefixed with their length so that adjacent strings can't
+    /// run together.
+    fn str(&mut self, s: &str) {
+        self.usize(s.len());
+        self.bytes(s.as_bytes());
+    }
+
+    fn cat(&mut self, cat: FormalSyntaxCatId) {
+        self.str(&cat.name());
+    }
+
+    fn template(&mut self, template: &Template) {
+        self.cat(template.cat());
+        self.usize(template.holes().len());
+        for hole in template.holes() {
+            self.cat(hole.pattern().cat());
+        }
+        match template.default() {
+            Some(default) => {
+                self.tag(1);
+                self.frag(default.frag());
+            }
+            None => self.tag(0),
+        }
+    }
+
+    fn rule(&mut self, rule: FormalSyntaxRuleId) {
+        self.cat(rule.cat());
+        self.str(&rule.name());
+        let parts = rule.pattern().parts();
+        self.usize(parts.len());
+        for part in parts {
+            match part {
+                FormalSyntaxPatPart::Cat(cat) => {
+                    self.tag(0);
+                    self.cat(*cat);
+                }
+                FormalSyntaxPatPart::Binding(cat) => {
+                    self.tag(1);
+                    self.cat(*cat);
+                }
+                FormalSyntaxPatPart::Lit(lit) => {
+                    self.tag(2);
+                    self.str(lit);
+                }
+            }
+        }
+    }
+
+    fn frag(&mut self, frag: FragmentId) {
+        self.cat(frag.cat());
+        match frag.head() {
+            FragHead::RuleApplication(app) => {
+                self.tag(0);
+                self.rule(app.rule());
+                self.usize(app.bindings_added());
+            }
+            FragHead::Var(idx) => {
+                self.tag(1);
+                self.usize(idx);
+            }
+            FragHead::TemplateRef(idx) => {
+                self.tag(2);
+                self.usize(idx);
+            }
+            FragHead::Hole(idx) => {
+                self.tag(3);
+                self.usize(idx);
+            }
+            FragHead::Abbreviation(abbreviation) => {
+                // The arguments are the children, so hashing the definition
+                // too covers what the abbreviation expands to.
+                self.tag(4);
+                self.str(&abbreviation.name());
+                self.frag(abbreviation.definition().frag());
+            }
+        }
+
+        self.usize(frag.children().len());
+        for &child in frag.children() {
+            self.frag(child);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// The hash of each theorem declared in `main`, after the propositional
+    /// library, by name.
+    fn hashes(main: &str) -> Vec<(String, String)> {
+        let project = TestProject::with_logic(&format!("module logic\n\n{main}"));
+        let arenas = Arenas::new();
+        let (ctx, report) = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .into_parts();
+        assert!(!ctx.diags.has_errors());
+
+        (report.roots.iter())
+            .flat_map(|root| &root.theorems)
+            .filter(|(thm, _)| thm.name_span().source() == report.roots[0].source)
+            .map(|(thm, _)| (thm.name().to_string(), thm.content_hash().to_string()))
+            .collect()
+    }
+
+    fn hash_of(main: &str, name: &str) -> String {
+        let hashes = hashes(main);
+        let hash = hashes.iter().find(|(thm, _)| thm == name);
+        hash.unwrap().1.clone()
+    }
+
+    #[test]
+    fn renaming_keeps_the_hash() {
+        let hashes = hashes(
+            "axiom one [p : sentence] : (p)\n    |- p → p\nend\n\n\
+             axiom two [q : sentence] : (q)\n    |- q → q\nend\n",
+        );
+        assert_eq!(hashes[0].1, hashes[1].1);
+    }
+
+    #[test]
+    fn changing_a_hypothesis_changes_the_hash() {
+        let hashes = hashes(
+            "axiom one [p : sentence] : (p)\n    |- p\nend\n\n\
+             axiom two [p : sentence] : (p → p)\n    |- p\nend\n",
+        );
+        assert_ne!(hashes[0].1, hashes[1].1);
+    }
+
+    #[test]
+    fn reordering_hypotheses_changes_the_hash() {
+        let hashes = hashes(
+            "axiom one [p q : sentence] : (p) (q)\n    |- p\nend\n\n\
+             axiom two [p q : sentence] : (q) (p)\n    |- p\nend\n",
+        );
+        assert_ne!(hashes[0].1, hashes[1].1);
+    }
+
+    #[test]
+    fn changing_an_abbreviation_changes_the_hash() {
+        let statement = |definition: &str| {
+            let main = format!(
+                "notation top\n    sentence ::= \"⊤\"\nend\n\n\
+                 abbreviation\n    ⊤ := {definition}\nend\n\n\
+                 axiom top.intro :\n    |- ⊤\nend\n"
+            );
+            hash_of(&main, "top.intro")
+        };
+        assert_eq!(statement("⊥ → ⊥"), statement("⊥ → ⊥"));
+        assert_ne!(statement("⊥ → ⊥"), statement("⊥"));
+    }
+
+    /// The hash is stored, so it must not change between runs or versions.
+    #[test]
+    fn the_hash_is_stable() {
+        let main = "axiom mp2 [p q : sentence] : (p → q) (p)\n    |- q\nend\n";
+        assert_eq!(hash_of(main, "mp2"), "a68d28513c01db6b");
+    }
+}
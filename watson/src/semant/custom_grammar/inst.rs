@@ -39,6 +39,41 @@ impl<'ctx> CustomGrammarInst<'ctx> {
     pub fn children(&self) -> &[CustomGrammarInstPart<'ctx>] {
         &self.children
     }
+
+    /// Every name in this instance and the instances inside it, in source
+    /// order, with the part of the rule each one was written for.
+    pub fn names(&self) -> Vec<(NamePosition<'ctx>, SpannedStr)> {
+        let mut names = Vec::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names(&self, names: &mut Vec<(NamePosition<'ctx>, SpannedStr)>) {
+        for (idx, child) in self.children.iter().enumerate() {
+            collect_part_names(child, (self.rule, idx), names);
+        }
+    }
+}
+
+/// A `@name` part of a rule, as the rule and the index of the part in its
+/// pattern. The items of a `@many` share the position of the part.
+pub type NamePosition<'ctx> = (CustomGrammarRuleId<'ctx>, usize);
+
+fn collect_part_names<'ctx>(
+    part: &CustomGrammarInstPart<'ctx>,
+    position: NamePosition<'ctx>,
+    names: &mut Vec<(NamePosition<'ctx>, SpannedStr)>,
+) {
+    match part {
+        CustomGrammarInstPart::Name(name) => names.push((position, *name)),
+        CustomGrammarInstPart::SubInst(inst) => inst.collect_names(names),
+        CustomGrammarInstPart::Many(items) => {
+            for item in items {
+                collect_part_names(item, position, names);
+            }
+        }
+        _ => {}
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, FromLua)]
@@ -70,4 +105,5 @@ pub enum CustomGrammarInstPart<'ctx> {
     Frag(UnresolvedFrag<'ctx>),
     AnyFrag(UnresolvedAnyFrag<'ctx>),
     Fact(UnresolvedFact<'ctx>),
+    Many(Vec<CustomGrammarInstPart<'ctx>>),
 }
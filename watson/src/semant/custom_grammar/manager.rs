@@ -8,6 +8,9 @@ pub struct CustomGrammarManager<'ctx> {
     cats_by_lua_name: FxHashMap<Ustr, CustomGrammarCatId<'ctx>>,
     rules_by_name: FxHashMap<Ustr, CustomGrammarRuleId<'ctx>>,
     rules_by_cat: FxHashMap<CustomGrammarCatId<'ctx>, Vec<CustomGrammarRuleId<'ctx>>>,
+    /// The categories proofs are written in, in the order they were first
+    /// used.
+    proof_cats: Vec<CustomGrammarCatId<'ctx>>,
 }
 
 impl<'ctx> CustomGrammarManager<'ctx> {
@@ -18,6 +21,7 @@ impl<'ctx> CustomGrammarManager<'ctx> {
             cats_by_lua_name: FxHashMap::default(),
             rules_by_name: FxHashMap::default(),
             rules_by_cat: FxHashMap::default(),
+            proof_cats: Vec::new(),
         }
     }
 
@@ -33,10 +37,20 @@ impl<'ctx> CustomGrammarManager<'ctx> {
         self.rules_by_cat.entry(rule.cat()).or_default().push(rule);
     }
 
+    pub fn use_proof_cat(&mut self, cat: CustomGrammarCatId<'ctx>) {
+        if !self.proof_cats.contains(&cat) {
+            self.proof_cats.push(cat);
+        }
+    }
+
     pub fn cats(&self) -> &[CustomGrammarCatId<'ctx>] {
         &self.all_cats
     }
 
+    pub fn proof_cats(&self) -> &[CustomGrammarCatId<'ctx>] {
+        &self.proof_cats
+    }
+
     pub fn rules_for_cat(&self, cat: CustomGrammarCatId<'ctx>) -> &[CustomGrammarRuleId<'ctx>] {
         &self.rules_by_cat[&cat]
     }
@@ -2,6 +2,7 @@ use crate::{
     context::arena::ScopeId,
     generate_arena_handle,
     parse::parse_state::{Associativity, CategoryId, Precedence},
+    semant::provenance::Provenance,
     util::name_to_lua,
 };
 use ustr::Ustr;
@@ -13,13 +14,15 @@ generate_arena_handle!(CustomGrammarRuleId<'ctx> => CustomGrammarRule<'ctx>);
 pub struct CustomGrammarCat {
     name: Ustr,
     lua_name: Ustr,
+    provenance: Provenance,
 }
 
 impl CustomGrammarCat {
-    pub fn new(name: Ustr) -> Self {
+    pub fn new(name: Ustr, provenance: Provenance) -> Self {
         Self {
             name,
             lua_name: Ustr::from(&name_to_lua(&name)),
+            provenance,
         }
     }
 
@@ -30,6 +33,10 @@ impl CustomGrammarCat {
     pub fn lua_name(&self) -> Ustr {
         self.lua_name
     }
+
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -38,6 +45,7 @@ pub struct CustomGrammarRule<'ctx> {
     cat: CustomGrammarCatId<'ctx>,
     pat: CustomGrammarPat<'ctx>,
     scope: ScopeId,
+    provenance: Provenance,
 }
 
 impl<'ctx> CustomGrammarRule<'ctx> {
@@ -46,12 +54,14 @@ impl<'ctx> CustomGrammarRule<'ctx> {
         cat: CustomGrammarCatId<'ctx>,
         pat: CustomGrammarPat<'ctx>,
         scope: ScopeId,
+        provenance: Provenance,
     ) -> Self {
         Self {
             name,
             cat,
             pat,
             scope,
+            provenance,
         }
     }
 
@@ -70,6 +80,10 @@ impl<'ctx> CustomGrammarRule<'ctx> {
     pub fn scope(&self) -> ScopeId {
         self.scope
     }
+
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -105,7 +119,7 @@ impl<'ctx> CustomGrammarPat<'ctx> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CustomGrammarPatPart<'ctx> {
     label: Option<Ustr>,
     part: CustomGrammarPatPartCore<'ctx>,
@@ -125,7 +139,7 @@ impl<'ctx> CustomGrammarPatPart<'ctx> {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum CustomGrammarPatPartCore<'ctx> {
     Lit(Ustr),
     Kw(Ustr),
@@ -134,4 +148,6 @@ pub enum CustomGrammarPatPartCore<'ctx> {
     Frag(CategoryId<'ctx>),
     AnyFrag,
     Fact,
+    /// Any number of the part in a row, including none.
+    Many(Box<CustomGrammarPatPartCore<'ctx>>),
 }
@@ -2,10 +2,8 @@ use ustr::Ustr;
 
 use crate::{
     generate_arena_handle,
-    parse::{
-        Span,
-        parse_state::{Associativity, Precedence},
-    },
+    parse::parse_state::{Associativity, Precedence},
+    semant::provenance::Provenance,
 };
 
 generate_arena_handle! { FormalSyntaxCatId => FormalSyntaxCat }
@@ -14,16 +12,21 @@ generate_arena_handle! { FormalSyntaxRuleId<'ctx> => FormalSyntaxRule<'ctx> }
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FormalSyntaxCat {
     name: Ustr,
+    provenance: Provenance,
 }
 
 impl FormalSyntaxCat {
-    pub fn new(name: Ustr) -> Self {
-        Self { name }
+    pub fn new(name: Ustr, provenance: Provenance) -> Self {
+        Self { name, provenance }
     }
 
     pub fn name(&self) -> Ustr {
         self.name
     }
+
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -31,7 +34,7 @@ pub struct FormalSyntaxRule<'ctx> {
     name: Ustr,
     cat: FormalSyntaxCatId<'ctx>,
     pat: FormalSyntaxPat<'ctx>,
-    span: Span,
+    provenance: Provenance,
 }
 
 impl<'ctx> FormalSyntaxRule<'ctx> {
@@ -39,13 +42,13 @@ impl<'ctx> FormalSyntaxRule<'ctx> {
         name: Ustr,
         cat: FormalSyntaxCatId<'ctx>,
         pat: FormalSyntaxPat<'ctx>,
-        span: Span,
+        provenance: Provenance,
     ) -> Self {
         Self {
             name,
             cat,
             pat,
-            span,
+            provenance,
         }
     }
 
@@ -61,8 +64,8 @@ impl<'ctx> FormalSyntaxRule<'ctx> {
         &self.pat
     }
 
-    pub fn span(&self) -> Span {
-        self.span
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
     }
 }
 
@@ -3,11 +3,13 @@ use crate::{
     generate_arena_handle,
     semant::{
         formal_syntax::{FormalSyntaxCatId, FormalSyntaxPatPart, FormalSyntaxRuleId},
-        presentation::{Pres, PresFrag, PresHead},
+        presentation::{Pres, PresFrag, PresHead, shift_frag},
         theorems::PresFact,
     },
 };
 use itertools::Itertools;
+use rustc_hash::FxHashMap;
+use ustr::Ustr;
 
 generate_arena_handle! { FragmentId<'ctx> => Fragment<'ctx> }
 
@@ -96,6 +98,7 @@ pub enum FragHead<'ctx> {
     Var(usize),
     TemplateRef(usize),
     Hole(usize),
+    Abbreviation(AbbreviationId<'ctx>),
 }
 
 impl<'ctx> FragHead<'ctx> {
@@ -105,6 +108,7 @@ impl<'ctx> FragHead<'ctx> {
             FragHead::Var(_) => 0,
             FragHead::TemplateRef(_) => 0,
             FragHead::Hole(_) => 0,
+            FragHead::Abbreviation(_) => 0,
         }
     }
 }
@@ -132,6 +136,41 @@ impl<'ctx> FragRuleApplication<'ctx> {
     }
 }
 
+generate_arena_handle! { AbbreviationId<'ctx> => Abbreviation<'ctx> }
+
+/// A definition which the kernel treats as opaque. Fragments refer to it by a
+/// dedicated head and it is only replaced by its definition when a proof
+/// explicitly unfolds it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Abbreviation<'ctx> {
+    name: Ustr,
+    cat: FormalSyntaxCatId<'ctx>,
+    /// The definition in terms of holes for each argument.
+    definition: PresFrag<'ctx>,
+}
+
+impl<'ctx> Abbreviation<'ctx> {
+    pub fn new(name: Ustr, cat: FormalSyntaxCatId<'ctx>, definition: PresFrag<'ctx>) -> Self {
+        Self {
+            name,
+            cat,
+            definition,
+        }
+    }
+
+    pub fn name(&self) -> Ustr {
+        self.name
+    }
+
+    pub fn cat(&self) -> FormalSyntaxCatId<'ctx> {
+        self.cat
+    }
+
+    pub fn definition(&self) -> PresFrag<'ctx> {
+        self.definition
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Fact<'ctx> {
     assumption: Option<FragmentId<'ctx>>,
@@ -172,6 +211,67 @@ pub fn hole_frag<'ctx>(
     PresFrag::new(frag, pres, formal)
 }
 
+/// Apply a formal syntax rule which binds `binders` variables to its
+/// children, presented with only formal syntax at the top.
+pub fn rule_frag<'ctx>(
+    rule: FormalSyntaxRuleId<'ctx>,
+    binders: usize,
+    children: Vec<PresFrag<'ctx>>,
+    ctx: &Ctx<'ctx>,
+) -> PresFrag<'ctx> {
+    let head = FragHead::RuleApplication(FragRuleApplication::new(rule, binders));
+    let frag_children = children.iter().map(|c| c.frag()).collect();
+    let frag = Fragment::new(rule.cat(), head, frag_children);
+    let frag = ctx.arenas.fragments.intern(frag);
+    let pres = Pres::new(PresHead::FormalFrag(head), children.clone());
+    let pres = ctx.arenas.presentations.intern(pres);
+    let formal = Pres::new(pres.head(), children.iter().map(|c| c.formal()).collect());
+    let formal = ctx.arenas.presentations.intern(formal);
+
+    PresFrag::new(frag, pres, formal)
+}
+
+pub fn abbreviation_frag<'ctx>(
+    abbreviation: AbbreviationId<'ctx>,
+    children: Vec<PresFrag<'ctx>>,
+    ctx: &Ctx<'ctx>,
+) -> PresFrag<'ctx> {
+    let head = FragHead::Abbreviation(abbreviation);
+    let frag_children = children.iter().map(|c| c.frag()).collect();
+    let frag = Fragment::new(abbreviation.cat(), head, frag_children);
+    let frag = ctx.arenas.fragments.intern(frag);
+    let pres = Pres::new(PresHead::FormalFrag(head), children.clone());
+    let pres = ctx.arenas.presentations.intern(pres);
+    let formal = Pres::new(pres.head(), children.iter().map(|c| c.formal()).collect());
+    let formal = ctx.arenas.presentations.intern(formal);
+
+    PresFrag::new(frag, pres, formal)
+}
+
+/// The abbreviation named `name` which `frag` uses, if there is one. Each
+/// scope can have its own abbreviation with a name, so they are found by
+/// where they are used rather than by name alone.
+pub fn find_abbreviation<'ctx>(frag: FragmentId<'ctx>, name: Ustr) -> Option<AbbreviationId<'ctx>> {
+    match frag.head() {
+        FragHead::Abbreviation(abbreviation) if abbreviation.name() == name => Some(abbreviation),
+        _ => (frag.children().iter()).find_map(|&child| find_abbreviation(child, name)),
+    }
+}
+
+/// Present a fragment using only formal syntax. Used for fragments which
+/// don't have a presentation, like those recorded by the proof kernel.
+pub fn formal_frag<'ctx>(frag: FragmentId<'ctx>, ctx: &Ctx<'ctx>) -> PresFrag<'ctx> {
+    let children = frag
+        .children()
+        .iter()
+        .map(|&child| formal_frag(child, ctx))
+        .collect();
+    let pres = Pres::new(PresHead::FormalFrag(frag.head()), children);
+    let pres = ctx.arenas.presentations.intern(pres);
+
+    PresFrag::new(frag, pres, pres)
+}
+
 pub fn var_frag<'ctx>(idx: usize, cat: FormalSyntaxCatId<'ctx>, ctx: &Ctx<'ctx>) -> PresFrag<'ctx> {
     let frag = Fragment::new(cat, FragHead::Var(idx), Vec::new());
     let frag = ctx.arenas.fragments.intern(frag);
@@ -183,6 +283,149 @@ pub fn var_frag<'ctx>(idx: usize, cat: FormalSyntaxCatId<'ctx>, ctx: &Ctx<'ctx>)
     PresFrag::new(frag, pres, pres)
 }
 
+/// The fragments assigned to each hole and template of a pattern by
+/// [`match_frag`]. Assignments are relative to the root of the pattern.
+#[derive(Debug, Clone, Default)]
+pub struct FragMatch<'ctx> {
+    pub holes: FxHashMap<usize, FragmentId<'ctx>>,
+    pub templates: FxHashMap<usize, FragmentId<'ctx>>,
+}
+
+/// Match `subject` against `pattern` on the formal structure of the fragments,
+/// so notation is ignored. Holes and templates in the pattern match any
+/// subterm of the same category which doesn't mention a variable bound inside
+/// the pattern, and repeated holes must be assigned the same fragment. Holes
+/// and templates applied to arguments would need higher order matching so
+/// they never match.
+pub fn match_frag<'ctx>(
+    pattern: FragmentId<'ctx>,
+    subject: FragmentId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> Option<FragMatch<'ctx>> {
+    fn inner<'ctx>(
+        pattern: FragmentId<'ctx>,
+        subject: FragmentId<'ctx>,
+        binding_depth: usize,
+        found: &mut FragMatch<'ctx>,
+        ctx: &Ctx<'ctx>,
+    ) -> bool {
+        let assignments = match pattern.head() {
+            FragHead::Hole(idx) => Some((&mut found.holes, idx)),
+            FragHead::TemplateRef(idx) => Some((&mut found.templates, idx)),
+            _ => None,
+        };
+        if let Some((assignments, idx)) = assignments {
+            if !pattern.children().is_empty() || pattern.cat() != subject.cat() {
+                return false;
+            }
+            let Some(assignment) = lower_frag(subject, binding_depth, 0, ctx) else {
+                // The subterm refers to a binder inside the pattern.
+                return false;
+            };
+            let previous = assignments.entry(idx).or_insert(assignment);
+            return *previous == assignment;
+        }
+
+        // The head includes how many bindings a rule adds so this also rejects
+        // binder arity mismatches.
+        if pattern.head() != subject.head() || pattern.children().len() != subject.children().len()
+        {
+            return false;
+        }
+
+        let binding_depth = binding_depth + pattern.head().bindings_added();
+        pattern
+            .children()
+            .iter()
+            .zip(subject.children())
+            .all(|(&p_child, &s_child)| inner(p_child, s_child, binding_depth, found, ctx))
+    }
+
+    let mut found = FragMatch::default();
+    inner(pattern, subject, 0, &mut found, ctx).then_some(found)
+}
+
+/// Replace the holes and templates in `frag` which have an assignment in
+/// `substitution`. Fails with the head of the first hole or template which
+/// can't be replaced because it is applied to arguments or its assignment has
+/// a different category.
+pub fn substitute_frag<'ctx>(
+    frag: FragmentId<'ctx>,
+    substitution: &FragMatch<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> Result<FragmentId<'ctx>, FragHead<'ctx>> {
+    fn inner<'ctx>(
+        frag: FragmentId<'ctx>,
+        substitution: &FragMatch<'ctx>,
+        binding_depth: usize,
+        ctx: &Ctx<'ctx>,
+    ) -> Result<FragmentId<'ctx>, FragHead<'ctx>> {
+        if !frag.has_hole() && !frag.has_template() {
+            return Ok(frag);
+        }
+
+        let assignment = match frag.head() {
+            FragHead::Hole(idx) => substitution.holes.get(&idx),
+            FragHead::TemplateRef(idx) => substitution.templates.get(&idx),
+            _ => None,
+        };
+        if let Some(&assignment) = assignment {
+            if !frag.children().is_empty() || assignment.cat() != frag.cat() {
+                return Err(frag.head());
+            }
+            let shifted = shift_frag(assignment, binding_depth, 0, ctx, &mut FxHashMap::default());
+            return Ok(shifted);
+        }
+
+        let binding_depth = binding_depth + frag.head().bindings_added();
+        let children = frag
+            .children()
+            .iter()
+            .map(|&child| inner(child, substitution, binding_depth, ctx))
+            .collect::<Result<Vec<_>, _>>()?;
+        let frag = Fragment::new(frag.cat(), frag.head(), children);
+        Ok(ctx.arenas.fragments.intern(frag))
+    }
+
+    inner(frag, substitution, 0, ctx)
+}
+
+/// Shift the variables of `frag` which aren't bound inside it down by
+/// `amount`. Fails if one of them refers to one of the `amount` innermost
+/// binders since it would escape its scope.
+fn lower_frag<'ctx>(
+    frag: FragmentId<'ctx>,
+    amount: usize,
+    closed_count: usize,
+    ctx: &Ctx<'ctx>,
+) -> Option<FragmentId<'ctx>> {
+    if amount == 0 || frag.unclosed_vars() <= closed_count {
+        return Some(frag);
+    }
+
+    match frag.head() {
+        FragHead::Var(idx) => {
+            // The condition above ensures this variable isn't bound inside
+            // the original fragment.
+            if idx - closed_count < amount {
+                return None;
+            }
+            let frag = Fragment::new(frag.cat(), FragHead::Var(idx - amount), Vec::new());
+            Some(ctx.arenas.fragments.intern(frag))
+        }
+        _ => {
+            let closed_count = closed_count + frag.head().bindings_added();
+            let children = frag
+                .children()
+                .iter()
+                .map(|&child| lower_frag(child, amount, closed_count, ctx))
+                .collect::<Option<Vec<_>>>()?;
+            let frag = Fragment::new(frag.cat(), frag.head(), children);
+            Some(ctx.arenas.fragments.intern(frag))
+        }
+    }
+}
+
 pub fn _debug_fact<'ctx>(fact: &PresFact<'ctx>) -> String {
     let conclusion = _debug_fragment(fact.conclusion().frag());
     match fact.assumption() {
@@ -204,7 +447,7 @@ pub fn _debug_fragment<'ctx>(frag: FragmentId<'ctx>) -> String {
                         out.push_str(&_debug_fragment(*child));
                         child_idx += 1;
                     }
-                    FormalSyntaxPatPart::Binding(_) => out.push_str("_"),
+                    FormalSyntaxPatPart::Binding(_) => out.push('_'),
                     FormalSyntaxPatPart::Lit(str) => out.push_str(str),
                 }
             }
@@ -213,7 +456,7 @@ pub fn _debug_fragment<'ctx>(frag: FragmentId<'ctx>) -> String {
         }
         FragHead::Var(idx) => format!("'{}", idx),
         FragHead::TemplateRef(idx) => {
-            if frag.children().len() > 0 {
+            if !frag.children().is_empty() {
                 let children = frag
                     .children()
                     .iter()
@@ -225,7 +468,7 @@ pub fn _debug_fragment<'ctx>(frag: FragmentId<'ctx>) -> String {
             }
         }
         FragHead::Hole(idx) => {
-            if frag.children().len() > 0 {
+            if !frag.children().is_empty() {
                 let children = frag
                     .children()
                     .iter()
@@ -236,5 +479,133 @@ pub fn _debug_fragment<'ctx>(frag: FragmentId<'ctx>) -> String {
                 format!("_{}", idx)
             }
         }
+        FragHead::Abbreviation(abbreviation) => {
+            let children = frag
+                .children()
+                .iter()
+                .map(|&c| _debug_fragment(c))
+                .join(", ");
+            format!("{}({})", abbreviation.name(), children)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// Patterns as the hypotheses of `patterns` and subjects to match them
+    /// against as the hypotheses of `subjects`.
+    const MAIN: &str = "module logic\n\n\
+        syntax_category term\n\n\
+        syntax equals\n    sentence (500) ::= term \"=\" term\nend\n\n\
+        syntax forall\n    sentence (50) ::= \"∀\" @binding(term) \",\" sentence\nend\n\n\
+        notation app\n    sentence (1000) ::= @name \"(\" term \")\"\nend\n\n\
+        axiom patterns [a b : sentence] [P(x) : sentence(term)] :\n    \
+        (a → a) (a → b) (∀x, a) (∀x, P(x))\n    |- ⊥\nend\n\n\
+        axiom subjects :\n    (⊥ → ⊥) (⊥ → ¬⊥) (¬⊥) (∀y, ⊥) (∀y, y = y)\n    |- ⊥\nend\n";
+
+    /// Run `test` on the hypotheses of `patterns` and of `subjects`.
+    fn with_frags(
+        test: impl for<'ctx> FnOnce(&Ctx<'ctx>, Vec<FragmentId<'ctx>>, Vec<FragmentId<'ctx>>),
+    ) {
+        let project = TestProject::with_logic(MAIN);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let ctx = parsed.ctx();
+        assert!(!ctx.diags.has_errors());
+
+        let hypotheses = |name: &str| {
+            let theorem = ctx.arenas.theorem_stmts.get(Ustr::from(name)).unwrap();
+            (theorem.hypotheses().iter())
+                .map(|hyp| hyp.fact().conclusion())
+                .collect()
+        };
+        test(ctx, hypotheses("patterns"), hypotheses("subjects"));
+    }
+
+    /// The templates assigned by matching `subject` against `pattern`.
+    fn templates<'ctx>(
+        pattern: FragmentId<'ctx>,
+        subject: FragmentId<'ctx>,
+        ctx: &Ctx<'ctx>,
+    ) -> Option<Vec<(usize, FragmentId<'ctx>)>> {
+        let found = match_frag(pattern, subject, ctx)?;
+        assert!(found.holes.is_empty());
+        Some(found.templates.into_iter().sorted().collect())
+    }
+
+    #[test]
+    fn repeated_templates_must_match_equal_fragments() {
+        with_frags(|ctx, patterns, subjects| {
+            let [a_to_a, a_to_b, ..] = patterns[..] else {
+                panic!()
+            };
+            let [bot_to_bot, bot_to_not_bot, not_bot, ..] = subjects[..] else {
+                panic!()
+            };
+            let bot = bot_to_bot.children()[0];
+
+            assert_eq!(templates(a_to_a, bot_to_bot, ctx), Some(vec![(0, bot)]));
+            assert_eq!(templates(a_to_a, bot_to_not_bot, ctx), None);
+            assert_eq!(
+                templates(a_to_b, bot_to_not_bot, ctx),
+                Some(vec![(0, bot), (1, not_bot)])
+            );
+        });
+    }
+
+    #[test]
+    fn matching_ignores_notation() {
+        with_frags(|ctx, patterns, subjects| {
+            // `¬⊥` is `⊥ → ⊥` by definition.
+            let [a_to_a, ..] = patterns[..] else { panic!() };
+            let [bot_to_bot, _, not_bot, ..] = subjects[..] else {
+                panic!()
+            };
+            let bot = bot_to_bot.children()[0];
+            assert_eq!(not_bot, bot_to_bot);
+            assert_eq!(templates(a_to_a, not_bot, ctx), Some(vec![(0, bot)]));
+        });
+    }
+
+    #[test]
+    fn bound_variables_and_applied_templates_do_not_match() {
+        with_frags(|ctx, patterns, subjects| {
+            let [_, _, forall_a, forall_p] = patterns[..] else {
+                panic!()
+            };
+            let [bot_to_bot, _, _, forall_bot, forall_eq] = subjects[..] else {
+                panic!()
+            };
+            let bot = bot_to_bot.children()[0];
+
+            assert_eq!(templates(forall_a, forall_bot, ctx), Some(vec![(0, bot)]));
+            // `y = y` mentions the variable bound by `∀`.
+            assert_eq!(templates(forall_a, forall_eq, ctx), None);
+            // Matching `P(x)` would need higher order matching.
+            assert_eq!(templates(forall_p, forall_bot, ctx), None);
+            assert_eq!(templates(forall_p, forall_eq, ctx), None);
+            assert_eq!(templates(forall_a, bot_to_bot, ctx), None);
+        });
+    }
+
+    #[test]
+    fn substituting_a_match_gives_the_subject() {
+        with_frags(|ctx, patterns, subjects| {
+            for (pattern, subject) in [(patterns[1], subjects[1]), (patterns[2], subjects[3])] {
+                let found = match_frag(pattern, subject, ctx).unwrap();
+                assert_eq!(substitute_frag(pattern, &found, ctx), Ok(subject));
+            }
+
+            // `P` is applied to `x` so it can't be replaced.
+            let found = FragMatch {
+                holes: FxHashMap::default(),
+                templates: [(2, subjects[0])].into_iter().collect(),
+            };
+            let pattern = patterns[3];
+            assert!(substitute_frag(pattern, &found, ctx).is_err());
+        });
     }
 }
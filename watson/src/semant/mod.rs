@@ -1,15 +1,20 @@
 pub mod attributes;
+pub mod axiom_closure;
 pub mod check_circularity;
 pub mod check_proofs;
 pub mod commands;
+pub mod content_hash;
 pub mod custom_grammar;
 pub mod formal_syntax;
 pub mod fragment;
 pub mod notation;
+pub mod occurrences;
 pub mod parse_fragment;
 pub mod presentation;
 pub mod proof_kernel;
 pub mod proof_status;
+pub mod provenance;
 pub mod scope;
 pub mod tactic;
+pub mod theorem_filter;
 pub mod theorems;
@@ -1,21 +1,21 @@
 use crate::{
+    context::Ctx,
+    diagnostics::{Diagnostic, DiagnosticSpan},
     generate_arena_handle,
     parse::{
         Span,
         parse_state::{Associativity, Precedence},
     },
-    semant::formal_syntax::FormalSyntaxCatId,
+    semant::{
+        formal_syntax::FormalSyntaxCatId,
+        provenance::{DeclKind, Provenance},
+    },
 };
+use rustc_hash::FxHashSet;
 use ustr::Ustr;
 
 generate_arena_handle!(NotationPatternId<'ctx> => NotationPattern<'ctx>);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum NotationPatternSource {
-    UserDeclared(Span),
-    Builtin,
-}
-
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NotationPattern<'ctx> {
     name: Ustr,
@@ -23,8 +23,11 @@ pub struct NotationPattern<'ctx> {
     parts: Vec<NotationPatternPart<'ctx>>,
     prec: Precedence,
     assoc: Associativity,
-    source: NotationPatternSource,
+    provenance: Provenance,
     signature: NotationSignature<'ctx>,
+    /// The notation this is another spelling of if it was declared with
+    /// `alias`.
+    alias_of: Option<NotationPatternId<'ctx>>,
 }
 
 impl<'ctx> NotationPattern<'ctx> {
@@ -52,7 +55,7 @@ impl<'ctx> NotationPattern<'ctx> {
         parts: Vec<NotationPatternPart<'ctx>>,
         prec: Precedence,
         assoc: Associativity,
-        source: NotationPatternSource,
+        provenance: Provenance,
     ) -> Self {
         let signature = Self::make_signature(cat, &parts);
 
@@ -62,11 +65,17 @@ impl<'ctx> NotationPattern<'ctx> {
             parts,
             prec,
             assoc,
-            source,
+            provenance,
             signature,
+            alias_of: None,
         }
     }
 
+    pub fn with_alias_of(mut self, primary: NotationPatternId<'ctx>) -> Self {
+        self.alias_of = Some(primary);
+        self
+    }
+
     pub fn name(&self) -> Ustr {
         self.name
     }
@@ -87,13 +96,37 @@ impl<'ctx> NotationPattern<'ctx> {
         self.assoc
     }
 
-    pub fn source(&self) -> NotationPatternSource {
-        self.source
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
     }
 
     pub fn signature(&self) -> &NotationSignature<'ctx> {
         &self.signature
     }
+
+    pub fn alias_of(&self) -> Option<NotationPatternId<'ctx>> {
+        self.alias_of
+    }
+
+    /// The parts which become part of a binding or fragment, i.e. everything
+    /// but the literals and keywords. An alias must have the same slots as
+    /// the notation it is an alias of.
+    pub fn slots(&self) -> impl Iterator<Item = &NotationPatternPart<'ctx>> {
+        self.parts.iter().filter(|part| {
+            !matches!(
+                part,
+                NotationPatternPart::Lit(_) | NotationPatternPart::Kw(_)
+            )
+        })
+    }
+}
+
+impl<'ctx> NotationPatternId<'ctx> {
+    /// The notation bindings and fragments parsed with this notation use.
+    /// This is the notation itself unless it is an alias.
+    pub fn primary(self) -> Self {
+        self.alias_of().unwrap_or(self)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -219,6 +252,109 @@ impl<'ctx> NotationBinding<'ctx> {
     }
 }
 
+/// Which notation wins when a fragment can be read using either of two
+/// notations. Declared with `prefer a over b`, which like a definition lasts
+/// until the end of the scope it is in. Preferences are between the names of
+/// primary notations and are never allowed to form a cycle.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NotationPreferences {
+    /// For each notation, the notations it is preferred over and where that
+    /// preference was declared.
+    over: im::HashMap<Ustr, im::HashMap<Ustr, Span>>,
+}
+
+impl NotationPreferences {
+    pub fn with(&self, preferred: Ustr, other: Ustr, span: Span) -> Self {
+        let mut over = self.over.clone();
+        over.entry(preferred).or_default().insert(other, span);
+        Self { over }
+    }
+
+    /// These preferences together with those of `other`.
+    pub fn with_imported(&self, other: &NotationPreferences) -> Self {
+        let mut over = self.over.clone();
+        for (preferred, others) in &other.over {
+            let entry = over.entry(*preferred).or_default();
+            for (other, span) in others {
+                entry.insert(*other, *span);
+            }
+        }
+        Self { over }
+    }
+
+    /// Whether either notation was declared to win over the other.
+    pub fn relates(&self, a: Ustr, b: Ustr) -> bool {
+        self.prefers(a, b) || self.prefers(b, a)
+    }
+
+    /// Whether `preferred` was directly declared to win over `other`.
+    pub fn prefers(&self, preferred: Ustr, other: Ustr) -> bool {
+        self.over
+            .get(&preferred)
+            .is_some_and(|over| over.contains_key(&other))
+    }
+
+    /// A chain of declared preferences leading from `from` to `to`, if there is
+    /// one. Each step is the preferred notation, the one it is preferred over
+    /// and where that was declared.
+    pub fn chain(&self, from: Ustr, to: Ustr) -> Option<Vec<(Ustr, Ustr, Span)>> {
+        fn search(
+            prefs: &NotationPreferences,
+            at: Ustr,
+            to: Ustr,
+            visited: &mut FxHashSet<Ustr>,
+            chain: &mut Vec<(Ustr, Ustr, Span)>,
+        ) -> bool {
+            if at == to {
+                return true;
+            }
+            if !visited.insert(at) {
+                return false;
+            }
+            for (&next, &span) in prefs.over.get(&at).into_iter().flatten() {
+                chain.push((at, next, span));
+                if search(prefs, next, to, visited, chain) {
+                    return true;
+                }
+                chain.pop();
+            }
+            false
+        }
+
+        let mut chain = Vec::new();
+        search(self, from, to, &mut FxHashSet::default(), &mut chain).then_some(chain)
+    }
+}
+
+/// Warn about each declared notation which no definition or fragment used.
+/// Using any of a notation's aliases counts as using the notation.
+pub fn warn_unused_notations(ctx: &mut Ctx) {
+    let used = ctx.used_notations.borrow();
+    let mut unused: Vec<_> = ctx
+        .notations
+        .values()
+        .filter(|notation| notation.alias_of().is_none() && !used.contains(*notation))
+        .filter(|notation| notation.provenance().kind() == Some(DeclKind::Notation))
+        .filter_map(|notation| Some((notation.name(), notation.provenance().span()?)))
+        .collect();
+    drop(used);
+    unused.sort_by_key(|(name, _)| *name);
+
+    for (name, span) in unused {
+        ctx.diags
+            .add_diag(Diagnostic::warn_unused_notation(name, span));
+    }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_unused_notation(name: Ustr, span: Span) -> Self {
+        Diagnostic::new_warning(
+            &format!("notation `{name}` is never used"),
+            vec![DiagnosticSpan::new_warning("", span)],
+        )
+    }
+}
+
 pub fn _debug_binding<'ctx>(binding: NotationBindingId<'ctx>) -> String {
     let mut out = String::new();
     let mut names = 0;
@@ -245,3 +381,38 @@ pub fn _debug_binding<'ctx>(binding: NotationBindingId<'ctx>) -> String {
     }
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// The titles of the diagnostics for checking a project which declares
+    /// `decls` after the propositional library.
+    fn titles(decls: &str) -> Vec<&'static str> {
+        let project = TestProject::with_logic(&format!("module logic\n\n{decls}"));
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(project.config(), &arenas)
+            .parse()
+            .check();
+        checked
+            .ctx()
+            .diags
+            .iter()
+            .map(|diag| diag.title())
+            .collect()
+    }
+
+    #[test]
+    fn unused_notations_are_reported() {
+        let top = "notation top\n    sentence ::= \"⊤\"\nend\n";
+        assert_eq!(titles(top), ["notation `top` is never used"]);
+    }
+
+    #[test]
+    fn using_an_alias_uses_the_notation() {
+        let top = "notation top\n    sentence ::= \"⊤\"\nend\n\n\
+            alias top_alt for top ::= \"⊺\" end\n\n\
+            definition\n    ⊺ := ⊥ → ⊥\nend\n";
+        assert!(titles(top).is_empty());
+    }
+}
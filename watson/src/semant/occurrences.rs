@@ -0,0 +1,140 @@
+use crate::{
+    parse::ParseReport,
+    semant::{
+        formal_syntax::FormalSyntaxRuleId,
+        fragment::FragHead,
+        notation::NotationPatternId,
+        presentation::{PresFrag, PresHead, PresId},
+        scope::DefinitionSource,
+        theorems::TheoremId,
+    },
+};
+use rustc_hash::FxHashMap;
+use std::ops::Range;
+
+/// Something which can be mentioned in the statement of a theorem.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UseTarget<'ctx> {
+    /// The definitions and abbreviations declared by these commands. Uses
+    /// are found by the command which declared what a name resolved to, so
+    /// a template or binding which shadows the definition isn't a use of it.
+    Definition(Vec<DefinitionSource<'ctx>>),
+    /// A notation, through any of the definitions which use it.
+    Notation(NotationPatternId<'ctx>),
+    /// A formal syntax rule, whether it is written directly or through
+    /// notation.
+    Rule(FormalSyntaxRuleId<'ctx>),
+}
+
+/// A place where a [`UseTarget`] is mentioned in a theorem statement.
+#[derive(Debug, Clone)]
+pub struct Occurrence<'ctx> {
+    theorem: TheoremId<'ctx>,
+    /// The subterm enclosing the occurrence, printed.
+    context: String,
+    /// Where the occurrence is in `context`.
+    highlight: Range<usize>,
+}
+
+impl<'ctx> Occurrence<'ctx> {
+    pub fn theorem(&self) -> TheoremId<'ctx> {
+        self.theorem
+    }
+
+    pub fn context(&self) -> &str {
+        &self.context
+    }
+
+    pub fn highlight(&self) -> Range<usize> {
+        self.highlight.clone()
+    }
+}
+
+impl<'ctx> UseTarget<'ctx> {
+    /// Find every occurrence of the target in the hypotheses and conclusions
+    /// of the theorems, in the order the theorems were declared.
+    pub fn find_occurrences(&self, parse_report: &ParseReport<'ctx>) -> Vec<Occurrence<'ctx>> {
+        let mut occurrences = Vec::new();
+        for root in &parse_report.roots {
+            for &(theorem, _) in &root.theorems {
+                let facts = theorem
+                    .hypotheses()
+                    .iter()
+                    .flat_map(|hyp| hyp.assumption().into_iter().chain([hyp.conclusion()]))
+                    .chain([theorem.conclusion()]);
+                for frag in facts {
+                    self.find_in_frag(theorem, frag, &mut occurrences);
+                }
+            }
+        }
+        occurrences
+    }
+
+    fn find_in_frag(
+        &self,
+        theorem: TheoremId<'ctx>,
+        frag: PresFrag<'ctx>,
+        out: &mut Vec<Occurrence<'ctx>>,
+    ) {
+        // Rules are hidden behind notation in the normal presentation, so
+        // they are found in the formal one.
+        let pres = match self {
+            UseTarget::Rule(_) => frag.formal_pres(),
+            _ => frag.pres(),
+        };
+
+        let mut paths = Vec::new();
+        self.find_paths(pres, &mut Vec::new(), &mut paths);
+        if paths.is_empty() {
+            return;
+        }
+
+        let mut ranges = FxHashMap::default();
+        let text = pres.print_with(&mut |path, range| {
+            ranges.insert(path.to_vec(), range);
+        });
+
+        for path in paths {
+            // A node the printer doesn't reach, like an argument a notation
+            // leaves out, has no text to show.
+            let parent = &path[..path.len().saturating_sub(1)];
+            let (Some(range), Some(enclosing)) = (ranges.get(&path), ranges.get(parent)) else {
+                continue;
+            };
+            out.push(Occurrence {
+                theorem,
+                context: text[enclosing.clone()].to_string(),
+                highlight: range.start - enclosing.start..range.end - enclosing.start,
+            });
+        }
+    }
+
+    fn find_paths(&self, pres: PresId<'ctx>, path: &mut Vec<usize>, out: &mut Vec<Vec<usize>>) {
+        if self.matches(pres.head()) {
+            out.push(path.clone());
+        }
+
+        // The replacement of a notation is what it expands to rather than
+        // what the statement says, so only its arguments are searched.
+        for (i, child) in pres.children().iter().enumerate() {
+            path.push(i);
+            self.find_paths(child.pres(), path, out);
+            path.pop();
+        }
+    }
+
+    fn matches(&self, head: PresHead<'ctx>) -> bool {
+        match (self, head) {
+            (UseTarget::Definition(sources), PresHead::Notation { def_source, .. }) => {
+                sources.contains(&def_source)
+            }
+            (UseTarget::Notation(notation), PresHead::Notation { binding, .. }) => {
+                binding.pattern().primary() == notation.primary()
+            }
+            (UseTarget::Rule(rule), PresHead::FormalFrag(FragHead::RuleApplication(app))) => {
+                app.rule() == *rule
+            }
+            _ => false,
+        }
+    }
+}
@@ -2,7 +2,7 @@ use ustr::Ustr;
 
 use crate::{
     context::Ctx,
-    diagnostics::WResult,
+    diagnostics::{Diagnostic, WResult},
     parse::{
         Span, elaborator::elaborate_name, parse_state::ParseRuleSource, parse_tree::ParseTreeId,
     },
@@ -29,6 +29,16 @@ pub struct UnresolvedFact<'ctx> {
     pub conclusion: UnresolvedFrag<'ctx>,
 }
 
+impl<'ctx> UnresolvedFact<'ctx> {
+    /// From the start of the assumption, if there is one, to the end of the
+    /// conclusion.
+    pub fn span(&self) -> Span {
+        let conclusion = self.conclusion.0.span();
+        let start = self.assumption.map_or(conclusion, |a| a.0.span()).start();
+        Span::new(start, conclusion.end())
+    }
+}
+
 pub fn parse_any_fragment<'ctx>(
     frag: UnresolvedAnyFrag<'ctx>,
     cat: FormalSyntaxCatId<'ctx>,
@@ -57,7 +67,20 @@ pub fn parse_fragment<'ctx>(
     scope: &Scope<'ctx>,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, Result<PresFrag<'ctx>, ParseResultErr>> {
-    parse_fragment_impl(frag.0, 0, scope, ctx)
+    let mut warnings = Vec::new();
+    parse_fragment_impl(frag.0, 0, 0, scope, &mut warnings, ctx)
+}
+
+/// Like [`parse_fragment`] but also returns warnings for binders in the
+/// fragment which shadow bindings that are already in scope.
+pub fn parse_fragment_with_warnings<'ctx>(
+    frag: UnresolvedFrag<'ctx>,
+    scope: &Scope<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> WResult<'ctx, Result<(PresFrag<'ctx>, Vec<Diagnostic<'ctx>>), ParseResultErr>> {
+    let mut warnings = Vec::new();
+    let parse = parse_fragment_impl(frag.0, 0, 0, scope, &mut warnings, ctx)?;
+    Ok(parse.map(|frag| (frag, warnings)))
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -120,40 +143,57 @@ fn child_spans_for_possibility<'ctx>(
         .collect()
 }
 
+/// How deeply notations can be nested in one fragment. Each level is parsed
+/// and expanded recursively, so a runaway expansion is reported before it
+/// overflows the stack.
+const MAX_NOTATION_DEPTH: usize = 256;
+
+/// `depth` is how many notations this fragment is nested in.
 fn parse_fragment_impl<'ctx>(
     frag: ParseTreeId<'ctx>,
     binding_depth: usize,
+    depth: usize,
     scope: &Scope<'ctx>,
+    warnings: &mut Vec<Diagnostic<'ctx>>,
     ctx: &Ctx<'ctx>,
 ) -> WResult<'ctx, Result<PresFrag<'ctx>, ParseResultErr>> {
     let my_span = frag.span();
+    if depth > MAX_NOTATION_DEPTH {
+        return Diagnostic::err_notation_too_deep(my_span, MAX_NOTATION_DEPTH);
+    }
     // Notations that were syntactically possible at this level but not in scope.
     let mut not_in_scope: Vec<String> = Vec::new();
     // The deepest child error encountered. When all possibilities fail, we return this
     // instead of a generic "no solutions" at the current level, so that the error
     // points at the specific sub-expression that actually went wrong.
     let mut deepest_child_err: Option<ParseResultErr> = None;
-    let mut solution: Result<PresFrag<'ctx>, ()> = Err(());
-    let mut first_solution: Option<AmbiguousSolution> = None;
+    // Every reading of the fragment found so far. Two readings are only
+    // allowed if a `prefer` command says which one wins.
+    let mut solutions: Vec<(PresFrag<'ctx>, Ustr, AmbiguousSolution)> = Vec::new();
 
     'possibility: for possibility in frag.possibilities() {
         let rule = possibility.rule();
         let notation = rule.source().get_notation();
+        // Warnings from a possibility that fails shouldn't be reported.
+        let warnings_start = warnings.len();
 
         // First let's create the binding that this syntax represented and
         // look it up in our scope. If it doesn't exist we can move on.
         let mut name_instantiations = Vec::new();
         let mut binder_names = Vec::new();
+        let mut binders = Vec::new();
         for (child, part) in possibility.children().iter().zip(notation.parts()) {
             if let NotationPatternPart::Name = part {
                 let name = elaborate_name(child.as_node().unwrap(), ctx)?;
                 name_instantiations.push(name);
-            } else if let NotationPatternPart::Binding(_) = part {
+            } else if let NotationPatternPart::Binding(cat) = part {
                 let name = elaborate_name(child.as_node().unwrap(), ctx)?;
                 binder_names.push(name);
+                binders.push((name, *cat, child.span()));
             }
         }
-        let binding = NotationBinding::new(notation, name_instantiations);
+        // Aliases only change how the notation is written.
+        let binding = NotationBinding::new(notation.primary(), name_instantiations);
         let binding = ctx.arenas.notation_bindings.intern(binding);
 
         let Some(replacement) = scope.lookup(binding) else {
@@ -163,6 +203,16 @@ fn parse_fragment_impl<'ctx>(
             continue;
         };
 
+        // Warn about any binders which shadow something already in scope.
+        for (name, cat, span) in binders {
+            let single_name_notation = ctx.single_name_notations[&cat];
+            let single_name_binding = NotationBinding::new(single_name_notation, vec![name]);
+            let single_name_binding = ctx.arenas.notation_bindings.intern(single_name_binding);
+            if let Some(warning) = scope.check_shadowing(single_name_binding, span) {
+                warnings.push(warning);
+            }
+        }
+
         // Now we want to evaluate each of the child nodes in the context of
         // the new scope that we created.
         let mut children = Vec::new();
@@ -180,8 +230,14 @@ fn parse_fragment_impl<'ctx>(
                 let new_binding_depth = binding_depth + child_cat.args().len();
 
                 let child_node = child.as_node().unwrap();
-                let child_parse =
-                    parse_fragment_impl(child_node, new_binding_depth, &new_scope, ctx)?;
+                let child_parse = parse_fragment_impl(
+                    child_node,
+                    new_binding_depth,
+                    depth + 1,
+                    &new_scope,
+                    warnings,
+                    ctx,
+                )?;
                 match child_parse {
                     Ok(parse) => children.push(parse),
                     Err(child_err) => {
@@ -189,12 +245,15 @@ fn parse_fragment_impl<'ctx>(
                         // end is the most specific failure (furthest into the source).
                         let is_deeper = deepest_child_err
                             .as_ref()
-                            .map_or(true, |prev| child_err.span_end() > prev.span_end());
+                            .is_none_or(|prev| child_err.span_end() > prev.span_end());
                         if is_deeper {
                             deepest_child_err = Some(child_err.clone());
                         }
                         match child_err {
-                            ParseResultErr::NoSolutions { .. } => continue 'possibility,
+                            ParseResultErr::NoSolutions { .. } => {
+                                warnings.truncate(warnings_start);
+                                continue 'possibility;
+                            }
                             ParseResultErr::MultipleSolutions { .. } => {
                                 multiple_solutions = true;
                             }
@@ -214,7 +273,7 @@ fn parse_fragment_impl<'ctx>(
                 span: my_span,
                 solutions: vec![AmbiguousSolution {
                     notation: binding.print(),
-                    child_spans: child_spans_for_possibility(possibility, notation),
+                    child_spans: child_spans_for_possibility(&possibility, notation),
                 }],
             }));
         }
@@ -255,29 +314,44 @@ fn parse_fragment_impl<'ctx>(
             ScopeReplacement::Hole(cat, idx) => hole_frag(idx, cat, children, ctx),
         };
 
-        if let Ok(_alternate) = solution {
-            let solutions = vec![
-                first_solution.unwrap(),
-                AmbiguousSolution {
-                    notation: binding.print(),
-                    child_spans: child_spans_for_possibility(possibility, notation),
-                },
-            ];
+        let this_solution = AmbiguousSolution {
+            notation: binding.print(),
+            child_spans: child_spans_for_possibility(&possibility, notation),
+        };
+        ctx.used_notations.borrow_mut().insert(notation.primary());
+        solutions.push((instantiated, notation.primary().name(), this_solution));
+
+        // A preference can only settle an ambiguity between exactly two
+        // notations. Anything else is reported straight away.
+        let resolvable = match solutions.as_slice() {
+            [_] => true,
+            [(_, a, _), (_, b, _)] => scope.preferences().relates(*a, *b),
+            _ => false,
+        };
+        if !resolvable {
             return Ok(Err(ParseResultErr::MultipleSolutions {
                 span: my_span,
-                solutions,
+                solutions: solutions.into_iter().map(|(_, _, s)| s).collect(),
             }));
         }
-
-        first_solution = Some(AmbiguousSolution {
-            notation: binding.print(),
-            child_spans: child_spans_for_possibility(possibility, notation),
-        });
-        solution = Ok(instantiated);
     }
 
-    if let Ok(frag) = solution {
-        return Ok(Ok(frag));
+    match solutions.as_slice() {
+        [(frag, _, _)] => return Ok(Ok(*frag)),
+        [(first, a, _), (second, b, _)] => {
+            let (frag, preferred, other) = if scope.preferences().prefers(*a, *b) {
+                (*first, *a, *b)
+            } else {
+                (*second, *b, *a)
+            };
+            if ctx.config.preference_notes() {
+                warnings.push(Diagnostic::info_notation_preference_applied(
+                    preferred, other, my_span,
+                ));
+            }
+            return Ok(Ok(frag));
+        }
+        _ => {}
     }
 
     // Prefer the deepest child error over the current-level error: it points to the
@@ -326,3 +400,38 @@ fn extend_scope_with_args<'ctx>(
 
     scope
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+    use std::thread;
+
+    /// The titles of the diagnostics for an axiom stating `statement`.
+    fn titles(statement: &str) -> Vec<&'static str> {
+        let project = TestProject::with_logic(&format!(
+            "module logic\n\naxiom deep [p : sentence] :\n    |- {statement}\nend\n"
+        ));
+        let arenas = Arenas::new();
+        let driver = Driver::for_project(project.config(), &arenas).parse();
+        driver.ctx().diags.iter().map(|diag| diag.title()).collect()
+    }
+
+    #[test]
+    fn deeply_nested_notations_are_reported() {
+        // Reaching the limit takes more stack than a test thread has.
+        let (shallow, deep) = thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(|| {
+                (
+                    titles(&format!("{}p", "¬".repeat(100))),
+                    titles(&format!("{}p", "¬".repeat(300))),
+                )
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+
+        assert!(shallow.is_empty());
+        assert_eq!(deep, ["notations are nested more than 256 deep"]);
+    }
+}
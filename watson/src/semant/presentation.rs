@@ -1,15 +1,18 @@
 use crate::{
     context::Ctx,
     generate_arena_handle,
+    parse::parse_state::{Associativity, Precedence},
     semant::{
-        formal_syntax::FormalSyntaxPatPart,
+        formal_syntax::{FormalSyntaxCatId, FormalSyntaxPatPart},
         fragment::{FragHead, Fragment, FragmentId},
         notation::{NotationBinding, NotationBindingId, NotationPatternPart},
         scope::DefinitionSource,
     },
+    strings,
 };
 use itertools::Itertools;
 use rustc_hash::{FxHashMap, FxHashSet};
+use std::ops::Range;
 use ustr::Ustr;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -53,6 +56,269 @@ impl<'ctx> PresFrag<'ctx> {
     pub fn print(&self) -> String {
         self.pres().print()
     }
+
+    /// Print the fragment for people to read: with spaces between the parts
+    /// of each pattern and with just the parentheses needed to read it back
+    /// the same way. Parentheses written with a grouping notation like
+    /// `(p) := p` are dropped and added back only where precedence needs them.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        pretty_into(self.ungrouped(), &mut Vec::new(), &mut out);
+        out
+    }
+
+    /// The fragment inside any grouping parentheses around it.
+    fn ungrouped(self) -> Self {
+        use NotationPatternPart as P;
+
+        let mut frag = self;
+        while let PresHead::Notation { binding, .. } = frag.pres().head()
+            && let [P::Lit(open), P::Cat(_), P::Lit(close)] = binding.pattern().parts()
+            && *open == *strings::LEFT_PAREN
+            && *close == *strings::RIGHT_PAREN
+            && let [inner] = frag.pres().children()
+            && inner.frag() == frag.frag()
+        {
+            frag = *inner;
+        }
+        frag
+    }
+}
+
+/// A part of a pattern as the pretty printer lays it out.
+enum PrettyPart<'a> {
+    /// Literal punctuation or an operator.
+    Lit(&'a str),
+    /// A keyword or a name, which always has spaces around it.
+    Word(&'a str),
+    /// The next child of the node, which can refer to the variables with
+    /// these names.
+    Child(Vec<&'a str>),
+}
+
+/// How a node's pattern binds, for deciding whether a child needs
+/// parentheses.
+#[derive(Clone, Copy)]
+struct Fixity {
+    prec: Precedence,
+    assoc: Associativity,
+    /// Whether the pattern starts with a child, so text before it could be
+    /// read as part of that child.
+    open_left: bool,
+    open_right: bool,
+}
+
+impl Fixity {
+    fn of(frag: PresFrag) -> Option<Self> {
+        match frag.pres().head() {
+            PresHead::FormalFrag(FragHead::RuleApplication(rule_app)) => {
+                let rule = rule_app.rule();
+                let pattern = rule.pattern();
+                let is_cat =
+                    |part: &FormalSyntaxPatPart| matches!(part, FormalSyntaxPatPart::Cat(_));
+                Some(Self {
+                    prec: pattern.precedence(),
+                    assoc: pattern.associativity(),
+                    open_left: pattern.parts().first().is_some_and(is_cat),
+                    open_right: pattern.parts().last().is_some_and(is_cat),
+                })
+            }
+            PresHead::Notation { binding, .. } => {
+                let pattern = binding.pattern();
+                let is_cat =
+                    |part: &NotationPatternPart| matches!(part, NotationPatternPart::Cat(_));
+                Some(Self {
+                    prec: pattern.prec(),
+                    assoc: pattern.assoc(),
+                    open_left: pattern.parts().first().is_some_and(is_cat),
+                    open_right: pattern.parts().last().is_some_and(is_cat),
+                })
+            }
+            // Everything else is printed like a function call.
+            PresHead::FormalFrag(_) => None,
+        }
+    }
+
+    /// Whether `child`, printed at the start or end of this pattern, would be
+    /// read differently without parentheses. The parser puts the rule with
+    /// the lowest precedence at the top, breaking ties by associativity.
+    fn needs_parens(self, child: Self, first: bool, last: bool) -> bool {
+        let exposed = (first && !last && child.open_right) || (last && !first && child.open_left);
+        let assoc_allows = (first && self.assoc == Associativity::Left)
+            || (last && self.assoc == Associativity::Right);
+        exposed && (child.prec < self.prec || (child.prec == self.prec && !assoc_allows))
+    }
+}
+
+/// Pretty print `frag` inside binders with the given names, innermost last.
+fn pretty_into(frag: PresFrag, binders: &mut Vec<String>, out: &mut String) {
+    let pres = frag.pres();
+    match pres.head() {
+        PresHead::FormalFrag(FragHead::Hole(idx)) => {
+            out.push_str(&format!("_{idx}"));
+            pretty_args(pres.children(), binders, out);
+        }
+        PresHead::FormalFrag(FragHead::Var(idx)) => push_var(idx, binders, out),
+        PresHead::FormalFrag(FragHead::TemplateRef(idx)) => {
+            out.push_str(&format!("${idx}"));
+            pretty_args(pres.children(), binders, out);
+        }
+        PresHead::FormalFrag(FragHead::Abbreviation(abbreviation)) => {
+            out.push_str(&abbreviation.name());
+            pretty_args(pres.children(), binders, out);
+        }
+        PresHead::FormalFrag(FragHead::RuleApplication(rule_app)) => {
+            use FormalSyntaxPatPart as P;
+
+            let names = binder_names(&[], rule_app.bindings_added(), binders.len());
+            let bound = names.iter().map(String::as_str).collect_vec();
+            let mut names_left = bound.iter();
+            let rule = rule_app.rule();
+            let parts = rule.pattern().parts().iter().map(|part| match part {
+                P::Lit(lit) => PrettyPart::Lit(lit),
+                P::Binding(_) => PrettyPart::Word(names_left.next().unwrap_or(&"_")),
+                P::Cat(_) => PrettyPart::Child(bound.clone()),
+            });
+            pretty_pattern(frag, parts.collect(), binders, out);
+        }
+        PresHead::Notation {
+            binding,
+            binding_names,
+            ..
+        } => {
+            use NotationPatternPart as P;
+
+            let pattern = binding.pattern();
+            let parts = pattern.parts();
+            let count = parts
+                .iter()
+                .filter(|part| matches!(part, P::Binding(_)))
+                .count();
+            let names = binder_names(binding_names.names(), count, binders.len());
+            let mut name_instantiations = binding.name_instantiations().iter();
+            let mut names_left = names.iter();
+            let parts = parts.iter().map(|part| match part {
+                P::Lit(lit) => PrettyPart::Lit(lit),
+                P::Kw(kw) => PrettyPart::Word(kw),
+                P::Name => PrettyPart::Word(name_instantiations.next().unwrap()),
+                P::Cat(cat) => PrettyPart::Child(args_names(cat.args(), &names)),
+                P::Binding(_) => PrettyPart::Word(names_left.next().map_or("_", String::as_str)),
+            });
+            pretty_pattern(frag, parts.collect(), binders, out);
+        }
+    }
+}
+
+fn pretty_args(children: &[PresFrag], binders: &mut Vec<String>, out: &mut String) {
+    if children.is_empty() {
+        return;
+    }
+    out.push('(');
+    for (i, child) in children.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        pretty_into(child.ungrouped(), binders, out);
+    }
+    out.push(')');
+}
+
+fn pretty_pattern(
+    frag: PresFrag,
+    parts: Vec<PrettyPart>,
+    binders: &mut Vec<String>,
+    out: &mut String,
+) {
+    let fixity = Fixity::of(frag);
+    let pres = frag.pres();
+    let mut children = pres.children().iter();
+
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 && spaced(&parts[i - 1], i - 1, part) {
+            out.push(' ');
+        }
+
+        match part {
+            PrettyPart::Lit(text) | PrettyPart::Word(text) => out.push_str(text),
+            PrettyPart::Child(bound) => {
+                let child = children.next().unwrap().ungrouped();
+                // Precedence only decides between rules of the same category.
+                let parens = child.frag().cat() == frag.frag().cat()
+                    && fixity
+                        .zip(Fixity::of(child))
+                        .is_some_and(|(parent, child)| {
+                            parent.needs_parens(child, i == 0, i == parts.len() - 1)
+                        });
+
+                if parens {
+                    out.push('(');
+                }
+                let outer = binders.len();
+                binders.extend(bound.iter().rev().map(|name| name.to_string()));
+                pretty_into(child, binders, out);
+                binders.truncate(outer);
+                if parens {
+                    out.push(')');
+                }
+            }
+        }
+    }
+}
+
+/// The names of the variables a node binds. Those written with a name keep
+/// it, and the rest get a fresh name numbered by how many variables are bound
+/// around them, so nested binders never get the same name.
+fn binder_names(known: &[Ustr], count: usize, depth: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| match known.get(i) {
+            Some(name) => name.to_string(),
+            None => {
+                let subscript: String = (depth + i)
+                    .to_string()
+                    .chars()
+                    .map(|digit| SUBSCRIPT_DIGITS[digit.to_digit(10).unwrap() as usize])
+                    .collect();
+                format!("x{subscript}")
+            }
+        })
+        .collect()
+}
+
+const SUBSCRIPT_DIGITS: [char; 10] = ['₀', '₁', '₂', '₃', '₄', '₅', '₆', '₇', '₈', '₉'];
+
+/// The names of the binders a child of a notation can refer to, in the
+/// order of its de Bruijn indices.
+fn args_names<'a>(args: &[(usize, FormalSyntaxCatId)], names: &'a [String]) -> Vec<&'a str> {
+    args.iter()
+        .map(|&(binder, _)| names.get(binder).map_or("_", String::as_str))
+        .collect()
+}
+
+/// Print a variable as the name of its binder, the innermost binder being
+/// last. A variable bound outside of what is printed has no name here, so
+/// its index is printed instead.
+fn push_var(idx: usize, binders: &[String], out: &mut String) {
+    match binders.len().checked_sub(idx + 1) {
+        Some(level) => out.push_str(&binders[level]),
+        None => out.push_str(&format!("'{idx}")),
+    }
+}
+
+/// Whether there is a space between two adjacent parts of a pattern. Brackets
+/// and commas hug what they are next to, as do prefix operators like `¬`.
+fn spaced(prev: &PrettyPart, prev_idx: usize, next: &PrettyPart) -> bool {
+    if let PrettyPart::Lit(lit) = prev
+        && (["(", "[", "{"].contains(lit)
+            || (prev_idx == 0 && !lit.starts_with(char::is_alphanumeric)))
+    {
+        return false;
+    }
+    if let PrettyPart::Lit(lit) = next
+        && [")", "]", "}", ","].contains(lit)
+    {
+        return false;
+    }
+    true
 }
 
 generate_arena_handle! {PresId<'ctx> => Pres<'ctx>}
@@ -79,73 +345,159 @@ impl<'ctx> Pres<'ctx> {
     }
 
     pub fn print(&self) -> String {
+        self.print_with(&mut |_, _| {})
+    }
+
+    /// Print the presentation, calling `on_node` with the path of child
+    /// indices to each node and the byte range it was printed to.
+    pub fn print_with(&self, on_node: &mut dyn FnMut(&[usize], Range<usize>)) -> String {
+        let mut out = String::new();
+        self.print_into(&mut out, &mut Vec::new(), &mut Vec::new(), on_node);
+        out
+    }
+
+    /// Print into `out` inside binders with the given names, innermost last.
+    fn print_into(
+        &self,
+        out: &mut String,
+        path: &mut Vec<usize>,
+        binders: &mut Vec<String>,
+        on_node: &mut dyn FnMut(&[usize], Range<usize>),
+    ) {
+        let start = out.len();
+        let mut children = self.children().iter().enumerate();
+        let mut print_child = |out: &mut String, binders: &mut Vec<String>, bound: &[&str]| {
+            let (idx, child) = children.next().unwrap();
+            let outer = binders.len();
+            binders.extend(bound.iter().rev().map(|name| name.to_string()));
+            path.push(idx);
+            child.pres().print_into(out, path, binders, on_node);
+            path.pop();
+            binders.truncate(outer);
+        };
+
         match self.head() {
             PresHead::FormalFrag(FragHead::Hole(idx)) => {
+                out.push_str(&format!("_{idx}"));
                 if !self.children().is_empty() {
-                    format!(
-                        "_{idx}({})",
-                        self.children().iter().map(|c| c.print()).join(", ")
-                    )
-                } else {
-                    format!("_{idx}")
+                    out.push('(');
+                    for i in 0..self.children().len() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        print_child(out, binders, &[]);
+                    }
+                    out.push(')');
                 }
             }
-            PresHead::FormalFrag(FragHead::Var(idx)) => {
-                format!("'{idx}")
-            }
+            PresHead::FormalFrag(FragHead::Var(idx)) => push_var(idx, binders, out),
             PresHead::FormalFrag(FragHead::TemplateRef(idx)) => {
+                out.push_str(&format!("${idx}"));
                 if !self.children().is_empty() {
-                    format!(
-                        "${idx}({})",
-                        self.children().iter().map(|c| c.print()).join(", ")
-                    )
-                } else {
-                    format!("${idx}")
+                    out.push('(');
+                    for i in 0..self.children().len() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        print_child(out, binders, &[]);
+                    }
+                    out.push(')');
+                }
+            }
+            PresHead::FormalFrag(FragHead::Abbreviation(abbreviation)) => {
+                out.push_str(&abbreviation.name());
+                if !self.children().is_empty() {
+                    out.push('(');
+                    for i in 0..self.children().len() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        print_child(out, binders, &[]);
+                    }
+                    out.push(')');
                 }
             }
             PresHead::FormalFrag(FragHead::RuleApplication(rule_app)) => {
-                let mut out = String::new();
-                let mut children = self.children().iter();
+                // Every child is inside all of the binders.
+                let names = binder_names(&[], rule_app.bindings_added(), binders.len());
+                let bound = names.iter().map(String::as_str).collect_vec();
+                let mut names_left = bound.iter();
 
                 for part in rule_app.rule().pattern().parts() {
                     use FormalSyntaxPatPart as P;
 
                     match part {
                         P::Lit(lit) => out.push_str(lit),
-                        P::Binding(_) => out.push('_'),
-                        P::Cat(_) => out.push_str(&children.next().unwrap().print()),
+                        P::Binding(_) => out.push_str(names_left.next().unwrap_or(&"_")),
+                        P::Cat(_) => print_child(out, binders, &bound),
                     }
                 }
-
-                out
             }
             PresHead::Notation {
                 binding,
                 binding_names,
                 ..
             } => {
-                let mut out = String::new();
-                let mut children = self.children().iter();
+                use NotationPatternPart as P;
+
+                let pattern = binding.pattern();
+                let parts = pattern.parts();
+                let count = parts
+                    .iter()
+                    .filter(|part| matches!(part, P::Binding(_)))
+                    .count();
+                let names = binder_names(binding_names.names(), count, binders.len());
                 let mut name_instantiations = binding.name_instantiations().iter();
-                let mut binding_names = binding_names.names().iter();
-
-                for part in binding.pattern().parts() {
-                    use NotationPatternPart as P;
+                let mut names_left = names.iter();
 
+                for part in parts {
                     match part {
                         P::Lit(lit) => out.push_str(lit),
                         P::Kw(kw) => out.push_str(kw),
                         P::Name => out.push_str(name_instantiations.next().unwrap()),
-                        P::Cat(_) => out.push_str(&children.next().unwrap().print()),
+                        P::Cat(cat) => print_child(out, binders, &args_names(cat.args(), &names)),
                         P::Binding(_) => {
-                            let name = binding_names.next().map(|s| s.as_str()).unwrap_or("?");
-                            out.push_str(name);
+                            out.push_str(names_left.next().map_or("_", String::as_str));
                         }
                     }
                 }
+            }
+        }
+
+        on_node(path, start..out.len());
+    }
+}
+
+/// Find the smallest subterm in which `old` and `new` differ, as a path of
+/// child indices. Returns `None` if they are the same.
+pub fn differing_subterm<'ctx>(old: PresId<'ctx>, new: PresId<'ctx>) -> Option<Vec<usize>> {
+    if old == new {
+        return None;
+    }
+
+    let mut path = Vec::new();
+    let (mut old, mut new) = (old, new);
+    loop {
+        if old.head() != new.head() || old.children().len() != new.children().len() {
+            return Some(path);
+        }
+
+        let mut differing = old
+            .children()
+            .iter()
+            .zip(new.children())
+            .enumerate()
+            .filter(|(_, (o, n))| o.pres() != n.pres());
 
-                out
+        match (differing.next(), differing.next()) {
+            (Some((idx, (o, n))), None) => {
+                path.push(idx);
+                old = o.pres();
+                new = n.pres();
             }
+            // Several children changed so this node is the smallest subterm
+            // containing all the changes.
+            _ => return Some(path),
         }
     }
 }
@@ -273,7 +625,7 @@ pub fn reduce_frag<'ctx>(frag: PresFrag<'ctx>, ctx: &Ctx<'ctx>) -> PresFrag<'ctx
     new
 }
 
-fn shift_frag<'ctx>(
+pub fn shift_frag<'ctx>(
     frag: FragmentId<'ctx>,
     shift: usize,
     closed_count: usize,
@@ -485,7 +837,10 @@ fn instantiate_frag_vars<'ctx>(
                 frag
             }
         }
-        FragHead::RuleApplication(_) | FragHead::TemplateRef(_) | FragHead::Hole(_) => {
+        FragHead::RuleApplication(_)
+        | FragHead::TemplateRef(_)
+        | FragHead::Hole(_)
+        | FragHead::Abbreviation(_) => {
             // TODO: closed count per child.
             let closed_count = closed_count + frag.head().bindings_added();
             let new_children = frag
@@ -511,6 +866,7 @@ fn instantiate_frag_vars<'ctx>(
     new_frag
 }
 
+#[allow(clippy::too_many_arguments)]
 fn instantiate_pres_vars<'ctx>(
     pres: PresId<'ctx>,
     closed_count: usize,
@@ -613,6 +969,7 @@ fn instantiate_pres_vars<'ctx>(
     new_pres
 }
 
+#[allow(clippy::too_many_arguments)]
 fn instantiate_vars_impl<'ctx>(
     pres_frag: PresFrag<'ctx>,
     closed_count: usize,
@@ -684,6 +1041,7 @@ pub fn instantiate_vars<'ctx>(
     )
 }
 
+#[allow(clippy::only_used_in_recursion)]
 fn instantiate_frag_holes<'ctx>(
     frag: FragmentId<'ctx>,
     binding_depth: usize,
@@ -733,7 +1091,7 @@ fn instantiate_frag_holes<'ctx>(
                 &mut FxHashMap::default(),
             )
         }
-        FragHead::RuleApplication(_) | FragHead::TemplateRef(_) => {
+        FragHead::RuleApplication(_) | FragHead::TemplateRef(_) | FragHead::Abbreviation(_) => {
             let binding_depth = binding_depth + frag.head().bindings_added();
             let new_children = new_children(binding_depth);
             let frag = Fragment::new(frag.cat(), frag.head(), new_children);
@@ -748,6 +1106,7 @@ fn instantiate_frag_holes<'ctx>(
     new_frag
 }
 
+#[allow(clippy::too_many_arguments)]
 fn instantiate_pres_holes<'ctx>(
     pres: PresId<'ctx>,
     binding_depth: usize,
@@ -825,6 +1184,7 @@ fn instantiate_pres_holes<'ctx>(
     new_pres
 }
 
+#[allow(clippy::too_many_arguments)]
 fn instantiate_holes_impl<'ctx>(
     pres_frag: PresFrag<'ctx>,
     binding_depth: usize,
@@ -942,7 +1302,7 @@ fn instantiate_frag_templates<'ctx>(
                 &mut FxHashMap::default(),
             )
         }
-        FragHead::RuleApplication(_) | FragHead::Hole { .. } => {
+        FragHead::RuleApplication(_) | FragHead::Hole { .. } | FragHead::Abbreviation(_) => {
             let binding_depth = binding_depth + frag.head().bindings_added();
             let new_children = new_children(binding_depth);
             let frag = Fragment::new(frag.cat(), frag.head(), new_children);
@@ -1110,10 +1470,22 @@ pub fn instantiate_templates<'ctx>(
 pub fn match_presentation<'ctx>(
     haystack: PresFrag<'ctx>,
     pattern: PresFrag<'ctx>,
+) -> Option<FxHashMap<usize, PresFrag<'ctx>>> {
+    match_presentation_with_wildcard(haystack, pattern, None)
+}
+
+/// Like [`match_presentation`], but the hole `wildcard` matches any fragment
+/// wherever it appears rather than the same one each time, and isn't part of
+/// the solution.
+pub fn match_presentation_with_wildcard<'ctx>(
+    haystack: PresFrag<'ctx>,
+    pattern: PresFrag<'ctx>,
+    wildcard: Option<usize>,
 ) -> Option<FxHashMap<usize, PresFrag<'ctx>>> {
     fn inner<'ctx>(
         haystack: PresFrag<'ctx>,
         pattern: PresFrag<'ctx>,
+        wildcard: Option<usize>,
         found_holes: &mut FxHashMap<usize, PresFrag<'ctx>>,
         already_checked: &mut FxHashSet<(PresFrag<'ctx>, PresFrag<'ctx>)>,
     ) -> bool {
@@ -1122,6 +1494,10 @@ pub fn match_presentation<'ctx>(
         }
 
         if let PresHead::FormalFrag(FragHead::Hole(idx)) = pattern.pres().head() {
+            if Some(idx) == wildcard {
+                return true;
+            }
+
             // Insert the haystack as the solution for this hole or get the
             // previous solution.
             let previous = found_holes.entry(idx).or_insert(haystack);
@@ -1142,7 +1518,7 @@ pub fn match_presentation<'ctx>(
             .iter()
             .zip(pattern.pres().children())
         {
-            if !inner(*h_child, *p_child, found_holes, already_checked) {
+            if !inner(*h_child, *p_child, wildcard, found_holes, already_checked) {
                 return false;
             }
         }
@@ -1151,7 +1527,141 @@ pub fn match_presentation<'ctx>(
     }
 
     let mut holes = FxHashMap::default();
-    let matches = inner(haystack, pattern, &mut holes, &mut FxHashSet::default());
+    let matches = inner(
+        haystack,
+        pattern,
+        wildcard,
+        &mut holes,
+        &mut FxHashSet::default(),
+    );
 
     matches.then_some(holes)
 }
+
+/// Replace every subfragment of `frag` matching `pattern` with `replacement`,
+/// filling the holes of the replacement with what the holes of the pattern
+/// matched. Matches are replaced outermost first and the replacements aren't
+/// searched again. Returns the new fragment and the number of replacements,
+/// or a hole of the replacement which isn't in the pattern.
+///
+/// The pattern and replacement are relative to the root of `frag`, so they are
+/// shifted past the binders above each subfragment. A match which uses a
+/// variable bound inside `frag` is skipped, since the equation justifying the
+/// rewrite can't be stated where that variable isn't bound.
+pub fn replace_all<'ctx>(
+    frag: PresFrag<'ctx>,
+    pattern: PresFrag<'ctx>,
+    replacement: PresFrag<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> Result<(PresFrag<'ctx>, usize), usize> {
+    let mut pattern_holes = FxHashSet::default();
+    pres_holes(pattern.pres(), &mut pattern_holes);
+    let mut replacement_holes = FxHashSet::default();
+    pres_holes(replacement.formal_pres(), &mut replacement_holes);
+    if let Some(&idx) = replacement_holes.difference(&pattern_holes).min() {
+        return Err(idx);
+    }
+
+    struct Rewriter<'a, 'ctx> {
+        pattern: PresFrag<'ctx>,
+        replacement: PresFrag<'ctx>,
+        ctx: &'a Ctx<'ctx>,
+        /// The pattern and replacement shifted to each binding depth.
+        shifted: FxHashMap<usize, (PresFrag<'ctx>, PresFrag<'ctx>)>,
+        count: usize,
+    }
+
+    impl<'ctx> Rewriter<'_, 'ctx> {
+        fn rewrite(&mut self, frag: PresFrag<'ctx>, depth: usize) -> PresFrag<'ctx> {
+            let (pattern, replacement) = *self.shifted.entry(depth).or_insert_with(|| {
+                (
+                    shift_pres_frag(self.pattern, depth, self.ctx),
+                    shift_pres_frag(self.replacement, depth, self.ctx),
+                )
+            });
+
+            if !uses_bound_var(frag.frag(), depth, 0)
+                && let Some(holes) = match_presentation(frag, pattern)
+            {
+                self.count += 1;
+                return instantiate_holes(replacement, &|idx| holes[&idx], 0, true, self.ctx);
+            }
+
+            let pres = frag.pres();
+            let children = pres
+                .children()
+                .iter()
+                .enumerate()
+                .map(|(i, &child)| self.rewrite(child, depth + pres.head().bindings_added(i)))
+                .collect_vec();
+            if children == pres.children() {
+                return frag;
+            }
+            with_children(frag, children, self.ctx)
+        }
+    }
+
+    let mut rewriter = Rewriter {
+        pattern,
+        replacement,
+        ctx,
+        shifted: FxHashMap::default(),
+        count: 0,
+    };
+    let frag = rewriter.rewrite(frag, 0);
+    Ok((frag, rewriter.count))
+}
+
+fn pres_holes(pres: PresId, holes: &mut FxHashSet<usize>) {
+    if let PresHead::FormalFrag(FragHead::Hole(idx)) = pres.head() {
+        holes.insert(idx);
+    }
+    for child in pres.children() {
+        pres_holes(child.pres(), holes);
+    }
+}
+
+/// Whether `frag` uses one of the `bound` innermost variables in scope at its
+/// root, ignoring the `closed_count` variables bound within it.
+fn uses_bound_var(frag: FragmentId, bound: usize, closed_count: usize) -> bool {
+    if frag.unclosed_vars() <= closed_count {
+        return false;
+    }
+
+    match frag.head() {
+        FragHead::Var(idx) => idx >= closed_count && idx < closed_count + bound,
+        head => {
+            let closed_count = closed_count + head.bindings_added();
+            frag.children()
+                .iter()
+                .any(|&child| uses_bound_var(child, bound, closed_count))
+        }
+    }
+}
+
+/// The same node as `frag` with new children in its presentation. The formal
+/// fragment is rebuilt from them, expanding the notation if there is one.
+fn with_children<'ctx>(
+    frag: PresFrag<'ctx>,
+    children: Vec<PresFrag<'ctx>>,
+    ctx: &Ctx<'ctx>,
+) -> PresFrag<'ctx> {
+    let head = frag.pres().head();
+    let (formal_frag, formal) = match head {
+        PresHead::FormalFrag(frag_head) => {
+            let frag_children = children.iter().map(|c| c.frag()).collect();
+            let formal_frag = Fragment::new(frag.frag().cat(), frag_head, frag_children);
+            let formal_frag = ctx.arenas.fragments.intern(formal_frag);
+            let formal_children = children.iter().map(|c| c.formal()).collect();
+            let formal = Pres::new(head, formal_children);
+            (formal_frag, ctx.arenas.presentations.intern(formal))
+        }
+        PresHead::Notation { replacement, .. } => {
+            let expanded = instantiate_holes(replacement, &|idx| children[idx], 0, true, ctx);
+            (expanded.frag(), expanded.formal_pres())
+        }
+    };
+
+    let pres = ctx.arenas.presentations.intern(Pres::new(head, children));
+    PresFrag::new(formal_frag, pres, formal)
+}
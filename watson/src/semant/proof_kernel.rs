@@ -1,7 +1,31 @@
+//! The proof kernel, which is the only code that can add to the facts known
+//! in a proof. Tactics drive it but can't get around its checks.
+//!
+//! A hypothesis of an applied theorem is satisfied by exactly these rules:
+//!
+//! 1. The instantiated hypothesis is a known fact.
+//! 2. The hypothesis is `assume A |- B`, `A` is the innermost open
+//!    assumption and `B` is known. Discharging `A` would deduce the
+//!    hypothesis outside the assumption, and what is known outside an
+//!    assumption is known inside it too, so this only saves a step.
+//!
+//! Nothing else satisfies a hypothesis. In particular `B` being known under
+//! any assumption other than the innermost, or `A |- B` being provable by
+//! some other theorem, isn't enough.
+//!
+//! A subgoal is proved in a state of its own and then merged back. Merging
+//! only adds the subgoal's goal, and only if that state knows it under the
+//! same assumptions.
+//!
+//! The state also records every known fact a step relied on, so once a
+//! proof is complete we can tell which of the theorem's hypotheses it never
+//! needed.
+
 use crate::{
     context::Ctx,
+    parse::Span,
     semant::{
-        fragment::{Fact, FragHead, Fragment, FragmentId},
+        fragment::{AbbreviationId, Fact, FragHead, Fragment, FragmentId},
         proof_kernel::safe::{SafeFact, SafeFrag},
         theorems::TheoremId,
     },
@@ -12,16 +36,15 @@ pub struct ProofCertificate<'ctx> {
 }
 
 impl<'ctx> ProofCertificate<'ctx> {
-    fn new(proof: ProofState<'ctx>, ctx: &Ctx<'ctx>) -> Result<Self, ProofError> {
+    fn new(mut proof: ProofState<'ctx>, ctx: &Ctx<'ctx>) -> Result<Self, ProofError> {
         let conclusion = proof.theorem.conclusion();
         let conclusion = SafeFrag::new(conclusion.frag(), ctx)?;
+        let conclusion = SafeFact::new_conclusion_safe(conclusion);
 
-        if !proof
-            .knowns
-            .contains(&SafeFact::new_conclusion_safe(conclusion))
-        {
+        if !proof.knowns.contains(&conclusion) {
             return Err(ProofError::ProofIncomplete);
         }
+        proof.facts_read.insert(conclusion);
 
         if !proof.assumptions.is_empty() {
             return Err(ProofError::StillHasAssumptions);
@@ -35,31 +58,149 @@ impl<'ctx> ProofCertificate<'ctx> {
     }
 
     pub fn _uses_todo(&self) -> bool {
-        !self.proof.todo_reasons.is_empty()
+        !self.proof.todos.is_empty()
     }
 
-    pub fn todo_reasons(&self) -> &im::HashSet<Option<String>> {
-        &self.proof.todo_reasons
+    pub fn todos(&self) -> &im::HashSet<TodoNote> {
+        &self.proof.todos
     }
 
     pub fn uses_error(&self) -> bool {
         self.proof.uses_error
     }
+
+    pub fn audit_log(&self) -> &im::Vector<AuditEntry<'ctx>> {
+        &self.proof.audit_log
+    }
+
+    /// The indices of the theorem's hypotheses which no step of the proof
+    /// relied on.
+    pub fn unused_hypotheses(&self, ctx: &Ctx<'ctx>) -> Vec<usize> {
+        let theorem = self.proof.theorem;
+        (0..theorem.hypotheses().len())
+            .filter(|&i| {
+                SafeFact::new(theorem.hypotheses()[i].fact(), ctx)
+                    .is_ok_and(|hypothesis| !self.proof.facts_read.contains(&hypothesis))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ProofState<'ctx> {
     /// The theorem this is a proof of.
     theorem: TheoremId<'ctx>,
+    /// What the proof is working towards. This is the theorem's conclusion
+    /// except in the states made by [`ProofState::with_goal`].
+    goal: SafeFrag<'ctx>,
     /// Theorems that were invoked to create the proof
     theorems_used: im::HashSet<TheoremId<'ctx>>,
     /// Facts that are known given all the assumptions
     knowns: im::HashSet<SafeFact<'ctx>>,
+    /// Known facts which a step relied on.
+    facts_read: im::HashSet<SafeFact<'ctx>>,
     /// Stack of assumptions and the set of known facts before the assumption.
     assumptions: im::Vector<(im::HashSet<SafeFact<'ctx>>, SafeFrag<'ctx>)>,
 
-    todo_reasons: im::HashSet<Option<String>>,
+    /// Every use of todo, with what the tactic said about it.
+    todos: im::HashSet<TodoNote>,
     uses_error: bool,
+    /// Every step taken to reach this state, in order. Since states are
+    /// persistent, abandoned branches never show up here.
+    audit_log: im::Vector<AuditEntry<'ctx>>,
+}
+
+/// What a tactic said about a fact it proved with todo.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct TodoNote {
+    /// Why the fact isn't proved yet.
+    pub reason: Option<String>,
+    /// The issue or ticket tracking the gap, like `MATH-42`.
+    pub ticket: Option<String>,
+    /// The part of the tactic which used todo.
+    pub span: Option<Span>,
+}
+
+/// A step recorded by the kernel in a proof's audit log.
+#[derive(Debug, Clone)]
+pub struct AuditEntry<'ctx> {
+    /// The number of assumptions the deduced fact is known under.
+    depth: usize,
+    step: AuditStep<'ctx>,
+    /// The part of the tactic which took the step, if the tactic said. It is
+    /// only for showing where a fact came from, so it is ignored when entries
+    /// are compared.
+    span: Option<Span>,
+}
+
+impl PartialEq for AuditEntry<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.depth == other.depth && self.step == other.step
+    }
+}
+
+impl Eq for AuditEntry<'_> {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditStep<'ctx> {
+    Assume(FragmentId<'ctx>),
+    Discharge {
+        assumption: FragmentId<'ctx>,
+        justifying: FragmentId<'ctx>,
+    },
+    ApplyTheorem {
+        theorem: TheoremId<'ctx>,
+        templates: Vec<FragmentId<'ctx>>,
+        conclusion: FragmentId<'ctx>,
+    },
+    Todo {
+        justifying: FragmentId<'ctx>,
+        note: TodoNote,
+    },
+    Error(FragmentId<'ctx>),
+    Unfold {
+        abbreviation: AbbreviationId<'ctx>,
+        known: FragmentId<'ctx>,
+        unfolded: FragmentId<'ctx>,
+    },
+    Fold {
+        abbreviation: AbbreviationId<'ctx>,
+        justifying: FragmentId<'ctx>,
+    },
+    /// A subgoal proved by a state made with [`ProofState::with_goal`]. Its
+    /// steps come right before this one.
+    Subgoal(FragmentId<'ctx>),
+}
+
+impl<'ctx> AuditEntry<'ctx> {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn step(&self) -> &AuditStep<'ctx> {
+        &self.step
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.span
+    }
+
+    /// The fact this step added to the known facts.
+    pub fn deduced(&self) -> Fact<'ctx> {
+        match &self.step {
+            AuditStep::Assume(assumption) => Fact::new(None, *assumption),
+            AuditStep::Discharge {
+                assumption,
+                justifying,
+            } => Fact::new(Some(*assumption), *justifying),
+            AuditStep::ApplyTheorem { conclusion, .. } => Fact::new(None, *conclusion),
+            AuditStep::Todo { justifying, .. } => Fact::new(None, *justifying),
+            AuditStep::Error(justifying) => Fact::new(None, *justifying),
+            AuditStep::Unfold { unfolded, .. } => Fact::new(None, *unfolded),
+            AuditStep::Fold { justifying, .. } => Fact::new(None, *justifying),
+            AuditStep::Subgoal(goal) => Fact::new(None, *goal),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -71,6 +212,10 @@ pub enum ProofError {
     ProofIncomplete,
     StillHasAssumptions,
     MissingHypothesis,
+    MissingFact,
+    WrongTemplateCount,
+    SubgoalIncomplete,
+    SubgoalElsewhere,
 }
 
 mod safe {
@@ -126,6 +271,14 @@ mod safe {
             }
         }
 
+        pub fn assumption(&self) -> Option<SafeFrag<'ctx>> {
+            self.assumption
+        }
+
+        pub fn conclusion(&self) -> SafeFrag<'ctx> {
+            self.conclusion
+        }
+
         pub fn _fact(&self) -> Fact<'ctx> {
             Fact::new(self.assumption.map(|a| a.frag()), self.conclusion.frag())
         }
@@ -140,19 +293,24 @@ impl<'ctx> ProofState<'ctx> {
             .map(|h| SafeFact::new(h.fact(), ctx))
             .collect();
         let knowns = knowns?;
+        let goal = SafeFrag::new(theorem.conclusion().frag(), ctx)?;
         Ok(Self {
             assumptions: im::Vector::new(),
             knowns,
+            facts_read: im::HashSet::new(),
             theorem,
+            goal,
             theorems_used: im::HashSet::new(),
-            todo_reasons: im::HashSet::new(),
+            todos: im::HashSet::new(),
             uses_error: false,
+            audit_log: im::Vector::new(),
         })
     }
 
     pub fn add_assumption(
         &self,
         assumption: FragmentId<'ctx>,
+        span: Option<Span>,
         ctx: &Ctx<'ctx>,
     ) -> Result<Self, ProofError> {
         let assumption = SafeFrag::new(assumption, ctx)?;
@@ -163,12 +321,14 @@ impl<'ctx> ProofState<'ctx> {
         let old_knowns = new.knowns;
         new.assumptions.push_back((old_knowns, assumption));
         new.knowns = new_knowns;
+        new.log(AuditStep::Assume(assumption.frag()), span);
         Ok(new)
     }
 
     pub fn pop_assumption(
         &self,
         justifying: FragmentId<'ctx>,
+        span: Option<Span>,
         ctx: &Ctx<'ctx>,
     ) -> Result<Self, ProofError> {
         let mut new = self.clone();
@@ -176,8 +336,17 @@ impl<'ctx> ProofState<'ctx> {
             new.assumptions.pop_back().ok_or(ProofError::NoAssumption)?;
         let new_fact = Fact::new(Some(assumption.frag()), justifying);
         let new_fact = SafeFact::new(new_fact, ctx)?;
+        let justifying_fact = SafeFact::new(Fact::new(None, justifying), ctx)?;
         old_knowns.insert(new_fact);
         new.knowns = old_knowns;
+        new.facts_read.insert(justifying_fact);
+        new.log(
+            AuditStep::Discharge {
+                assumption: assumption.frag(),
+                justifying,
+            },
+            span,
+        );
         Ok(new)
     }
 
@@ -185,8 +354,13 @@ impl<'ctx> ProofState<'ctx> {
         &self,
         theorem: TheoremId<'ctx>,
         templates: &[FragmentId<'ctx>],
+        span: Option<Span>,
         ctx: &Ctx<'ctx>,
     ) -> Result<Self, ProofError> {
+        // Defaults are filled in before the kernel sees the templates.
+        if templates.len() != theorem.templates().len() {
+            return Err(ProofError::WrongTemplateCount);
+        }
         for template in templates {
             if !template.is_closed() {
                 return Err(ProofError::FragUnclosed);
@@ -201,37 +375,52 @@ impl<'ctx> ProofState<'ctx> {
             .collect();
         let hypotheses = hypotheses?;
 
+        let mut new = self.clone();
         for hypothesis in hypotheses {
-            if !self.knowns.contains(&hypothesis) {
-                return Err(ProofError::MissingHypothesis);
-            }
+            let known = self
+                .satisfied_by(&hypothesis)
+                .ok_or(ProofError::MissingHypothesis)?;
+            new.facts_read.insert(known);
         }
 
         let conclusion = instantiate_frag(theorem.conclusion().frag(), templates, ctx);
         let conclusion = SafeFrag::new(conclusion, ctx)?;
-        let mut new = self.clone();
         new.knowns.insert(SafeFact::new_conclusion_safe(conclusion));
         new.theorems_used.insert(theorem);
+        new.log(
+            AuditStep::ApplyTheorem {
+                theorem,
+                templates: templates.to_vec(),
+                conclusion: conclusion.frag(),
+            },
+            span,
+        );
         Ok(new)
     }
 
     pub fn apply_todo(
         &self,
         justifying: FragmentId<'ctx>,
-        reason: Option<String>,
+        note: TodoNote,
+        span: Option<Span>,
         ctx: &Ctx<'ctx>,
     ) -> Result<Self, ProofError> {
         let mut new = self.clone();
         let new_fact = Fact::new(None, justifying);
         let new_fact = SafeFact::new(new_fact, ctx)?;
         new.knowns.insert(new_fact);
-        new.todo_reasons.insert(reason);
+        new.todos.insert(note.clone());
+        // The note's span says where the todo is, which is as good a place as
+        // any to say the fact came from.
+        let span = span.or(note.span);
+        new.log(AuditStep::Todo { justifying, note }, span);
         Ok(new)
     }
 
     pub fn apply_error(
         &self,
         justifying: FragmentId<'ctx>,
+        span: Option<Span>,
         ctx: &Ctx<'ctx>,
     ) -> Result<Self, ProofError> {
         let mut new = self.clone();
@@ -239,6 +428,111 @@ impl<'ctx> ProofState<'ctx> {
         let new_fact = SafeFact::new(new_fact, ctx)?;
         new.knowns.insert(new_fact);
         new.uses_error = true;
+        new.log(AuditStep::Error(justifying), span);
+        Ok(new)
+    }
+
+    /// Given a known fact, deduce the same fact with every use of the
+    /// abbreviation replaced by its definition.
+    pub fn unfold(
+        &self,
+        known: FragmentId<'ctx>,
+        abbreviation: AbbreviationId<'ctx>,
+        span: Option<Span>,
+        ctx: &Ctx<'ctx>,
+    ) -> Result<Self, ProofError> {
+        let known_fact = SafeFact::new(Fact::new(None, known), ctx)?;
+        if !self.knowns.contains(&known_fact) {
+            return Err(ProofError::MissingFact);
+        }
+
+        let unfolded = unfold_frag(known, abbreviation, ctx);
+        let unfolded_fact = SafeFact::new(Fact::new(None, unfolded), ctx)?;
+        let mut new = self.clone();
+        new.knowns.insert(unfolded_fact);
+        new.facts_read.insert(known_fact);
+        new.log(
+            AuditStep::Unfold {
+                abbreviation,
+                known,
+                unfolded,
+            },
+            span,
+        );
+        Ok(new)
+    }
+
+    /// Deduce `justifying` given that it is known with every use of the
+    /// abbreviation replaced by its definition.
+    pub fn fold(
+        &self,
+        justifying: FragmentId<'ctx>,
+        abbreviation: AbbreviationId<'ctx>,
+        span: Option<Span>,
+        ctx: &Ctx<'ctx>,
+    ) -> Result<Self, ProofError> {
+        let unfolded = unfold_frag(justifying, abbreviation, ctx);
+        let unfolded = SafeFact::new(Fact::new(None, unfolded), ctx)?;
+        if !self.knowns.contains(&unfolded) {
+            return Err(ProofError::MissingFact);
+        }
+
+        let new_fact = SafeFact::new(Fact::new(None, justifying), ctx)?;
+        let mut new = self.clone();
+        new.knowns.insert(new_fact);
+        new.facts_read.insert(unfolded);
+        new.log(
+            AuditStep::Fold {
+                abbreviation,
+                justifying,
+            },
+            span,
+        );
+        Ok(new)
+    }
+
+    /// Start proving `goal` on its own. The new state knows everything this
+    /// one does and, once it knows its goal too, [`Self::merge`] adds the
+    /// goal to this state.
+    pub fn with_goal(&self, goal: FragmentId<'ctx>, ctx: &Ctx<'ctx>) -> Result<Self, ProofError> {
+        let mut new = self.clone();
+        new.goal = SafeFrag::new(goal, ctx)?;
+        Ok(new)
+    }
+
+    /// Add the goal of a state made by [`Self::with_goal`] once it is known
+    /// there. The subgoal has to be of the same theorem under the same
+    /// assumptions, so everything it knows follows from what this state can
+    /// know, even if it was split off an earlier or later state. The
+    /// theorems, todos and errors it used carry over, as do its steps.
+    pub fn merge(&self, subgoal: &Self, span: Option<Span>) -> Result<Self, ProofError> {
+        let same_assumptions = (subgoal.assumptions.iter().map(|(_, a)| a))
+            .eq(self.assumptions.iter().map(|(_, a)| a));
+        if subgoal.theorem != self.theorem || !same_assumptions {
+            return Err(ProofError::SubgoalElsewhere);
+        }
+        let goal = SafeFact::new_conclusion_safe(subgoal.goal);
+        if !subgoal.knowns.contains(&goal) {
+            return Err(ProofError::SubgoalIncomplete);
+        }
+
+        let mut new = self.clone();
+        new.knowns.insert(goal);
+        new.theorems_used = new.theorems_used.union(subgoal.theorems_used.clone());
+        new.facts_read = new.facts_read.union(subgoal.facts_read.clone());
+        new.todos = new.todos.union(subgoal.todos.clone());
+        new.uses_error |= subgoal.uses_error;
+
+        // The subgoal's log starts with the steps of the state it was split
+        // from, which this state may already have.
+        let shared = self
+            .audit_log
+            .iter()
+            .zip(subgoal.audit_log.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+        new.audit_log.append(subgoal.audit_log.skip(shared));
+        new.log(AuditStep::Subgoal(subgoal.goal.frag()), span);
         Ok(new)
     }
 
@@ -251,6 +545,38 @@ impl<'ctx> ProofState<'ctx> {
     pub fn theorem(&self) -> TheoremId<'ctx> {
         self.theorem
     }
+
+    pub fn audit_log(&self) -> &im::Vector<AuditEntry<'ctx>> {
+        &self.audit_log
+    }
+
+    pub fn goal(&self) -> FragmentId<'ctx> {
+        self.goal.frag()
+    }
+
+    /// Whether `fact` holds here, in the same way a hypothesis of an applied
+    /// theorem must. This doesn't count as using the fact.
+    pub fn knows(&self, fact: Fact<'ctx>, ctx: &Ctx<'ctx>) -> bool {
+        SafeFact::new(fact, ctx).is_ok_and(|fact| self.satisfied_by(&fact).is_some())
+    }
+
+    /// The known fact a hypothesis holds by, if it holds by the rules in the
+    /// module docs.
+    fn satisfied_by(&self, hypothesis: &SafeFact<'ctx>) -> Option<SafeFact<'ctx>> {
+        if self.knowns.contains(hypothesis) {
+            return Some(*hypothesis);
+        }
+
+        let assumption = hypothesis.assumption()?;
+        let innermost = self.assumptions.back().map(|(_, innermost)| *innermost);
+        let conclusion = SafeFact::new_conclusion_safe(hypothesis.conclusion());
+        (innermost == Some(assumption) && self.knowns.contains(&conclusion)).then_some(conclusion)
+    }
+
+    fn log(&mut self, step: AuditStep<'ctx>, span: Option<Span>) {
+        let depth = self.assumptions.len();
+        self.audit_log.push_back(AuditEntry { depth, step, span });
+    }
 }
 
 fn instantiate_fact<'ctx>(
@@ -282,7 +608,7 @@ fn instantiate_frag<'ctx>(
     };
 
     match frag.head() {
-        FragHead::RuleApplication(_) | FragHead::Hole(_) => {
+        FragHead::RuleApplication(_) | FragHead::Hole(_) | FragHead::Abbreviation(_) => {
             let new_children = new_children();
             let frag = Fragment::new(frag.cat(), frag.head(), new_children);
             ctx.arenas.fragments.intern(frag)
@@ -300,6 +626,30 @@ fn instantiate_frag<'ctx>(
     }
 }
 
+fn unfold_frag<'ctx>(
+    frag: FragmentId<'ctx>,
+    abbreviation: AbbreviationId<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> FragmentId<'ctx> {
+    let new_children: Vec<_> = frag
+        .children()
+        .iter()
+        .map(|&c| unfold_frag(c, abbreviation, ctx))
+        .collect();
+
+    match frag.head() {
+        FragHead::Abbreviation(abbr) if abbr == abbreviation => {
+            // The definition is closed apart from its holes so only the
+            // arguments need shifting.
+            fill_holes(abbreviation.definition().frag(), &new_children, 0, ctx)
+        }
+        _ => {
+            let frag = Fragment::new(frag.cat(), frag.head(), new_children);
+            ctx.arenas.fragments.intern(frag)
+        }
+    }
+}
+
 fn fill_holes<'ctx>(
     frag: FragmentId<'ctx>,
     children: &[FragmentId<'ctx>],
@@ -375,3 +725,194 @@ fn shift<'ctx>(frag: FragmentId<'ctx>, shift: usize, ctx: &Ctx<'ctx>) -> Fragmen
     // bindings inside the terms are closed and shouldn't be shifted.
     inner(frag, shift, 0, ctx)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+    use ustr::Ustr;
+
+    /// Theorems to start proof states from. `known` has `q` as a hypothesis
+    /// and `assumed` has `assume p |- q`; both have `r` so we can name it.
+    const MAIN: &str = "module logic\n\n\
+        theorem known [p q r : sentence] : (q) (r)\n    |- p → q\nproof\nqed\n\n\
+        theorem assumed [p q r : sentence] : (assume p |- q) (r)\n    |- p → q\nproof\nqed\n";
+
+    /// Run `test` on the state at the start of a proof of `theorem` and the
+    /// templates `p`, `q` and `r` of that theorem.
+    fn with_state(
+        theorem: &str,
+        test: impl for<'ctx> FnOnce(&Ctx<'ctx>, ProofState<'ctx>, [FragmentId<'ctx>; 3]),
+    ) {
+        let project = TestProject::with_logic(MAIN);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let ctx = parsed.ctx();
+        assert!(!ctx.diags.has_errors());
+
+        let theorem = ctx.arenas.theorem_stmts.get(Ustr::from(theorem)).unwrap();
+        let conclusion = theorem.conclusion().frag();
+        let conclusion = conclusion.children();
+        let r = theorem.hypotheses()[1].fact().conclusion();
+        let state = ProofState::new_from_theorem(theorem, ctx).unwrap();
+        test(ctx, state, [conclusion[0], conclusion[1], r]);
+    }
+
+    /// Apply `imp.intro` to prove `p → q`, which has the hypothesis
+    /// `assume p |- q`.
+    fn intro<'ctx>(
+        state: &ProofState<'ctx>,
+        [p, q, _]: [FragmentId<'ctx>; 3],
+        ctx: &Ctx<'ctx>,
+    ) -> Result<ProofState<'ctx>, ProofError> {
+        let intro = ctx
+            .arenas
+            .theorem_stmts
+            .get(Ustr::from("imp.intro"))
+            .unwrap();
+        state.apply_theorem(intro, &[p, q], None, ctx)
+    }
+
+    #[test]
+    fn exact_fact_satisfies_hypothesis() {
+        with_state("assumed", |ctx, state, frags @ [p, q, r]| {
+            assert!(intro(&state, frags, ctx).is_ok());
+            assert!(state.knows(Fact::new(Some(p), q), ctx));
+
+            // The exact fact holds under any assumption.
+            let state = state.add_assumption(r, None, ctx).unwrap();
+            assert!(intro(&state, frags, ctx).is_ok());
+        });
+    }
+
+    #[test]
+    fn missing_fact_does_not_satisfy_hypothesis() {
+        with_state("assumed", |ctx, state, [p, q, r]| {
+            // `assume r |- q` isn't known, and neither is `q`.
+            assert_eq!(
+                intro(&state, [r, q, p], ctx).err(),
+                Some(ProofError::MissingHypothesis)
+            );
+            assert!(!state.knows(Fact::new(Some(r), q), ctx));
+        });
+    }
+
+    #[test]
+    fn conclusion_under_innermost_assumption_satisfies_hypothesis() {
+        with_state("known", |ctx, state, frags @ [p, q, _]| {
+            let state = state.add_assumption(p, None, ctx).unwrap();
+            assert!(state.knows(Fact::new(Some(p), q), ctx));
+            let state = intro(&state, frags, ctx).unwrap();
+
+            // Using the rule reads `q`, not the hypothesis.
+            assert!(
+                state
+                    .facts_read
+                    .contains(&SafeFact::new(Fact::new(None, q), ctx).unwrap())
+            );
+        });
+    }
+
+    #[test]
+    fn conclusion_without_assumption_does_not_satisfy_hypothesis() {
+        with_state("known", |ctx, state, frags @ [p, q, _]| {
+            assert!(!state.knows(Fact::new(Some(p), q), ctx));
+            assert_eq!(
+                intro(&state, frags, ctx).err(),
+                Some(ProofError::MissingHypothesis)
+            );
+        });
+    }
+
+    #[test]
+    fn conclusion_under_unrelated_assumption_does_not_satisfy_hypothesis() {
+        with_state("known", |ctx, state, frags @ [p, q, r]| {
+            let state = state.add_assumption(r, None, ctx).unwrap();
+            assert!(!state.knows(Fact::new(Some(p), q), ctx));
+            assert_eq!(
+                intro(&state, frags, ctx).err(),
+                Some(ProofError::MissingHypothesis)
+            );
+        });
+    }
+
+    #[test]
+    fn conclusion_under_outer_assumption_does_not_satisfy_hypothesis() {
+        with_state("known", |ctx, state, frags @ [p, q, r]| {
+            let state = state.add_assumption(p, None, ctx).unwrap();
+            let state = state.add_assumption(r, None, ctx).unwrap();
+            assert!(!state.knows(Fact::new(Some(p), q), ctx));
+            assert_eq!(
+                intro(&state, frags, ctx).err(),
+                Some(ProofError::MissingHypothesis)
+            );
+
+            // Once `r` is discharged `p` is innermost again.
+            let state = state.pop_assumption(q, None, ctx).unwrap();
+            assert!(intro(&state, frags, ctx).is_ok());
+        });
+    }
+
+    #[test]
+    fn audit_log_only_has_the_committed_branch() {
+        with_state("known", |ctx, state, frags @ [p, _, r]| {
+            // Try assuming `r` first, which doesn't let us introduce `p → q`.
+            let tried = state.add_assumption(r, None, ctx).unwrap();
+            assert!(intro(&tried, frags, ctx).is_err());
+
+            let state = state.add_assumption(p, None, ctx).unwrap();
+            let state = intro(&state, frags, ctx).unwrap();
+            let steps: Vec<_> = state.audit_log.iter().map(|entry| &entry.step).collect();
+            assert!(matches!(
+                steps[..],
+                [
+                    AuditStep::Assume(assumed),
+                    AuditStep::ApplyTheorem { theorem, .. },
+                ] if *assumed == p && theorem.name() == "imp.intro"
+            ));
+        });
+    }
+
+    #[test]
+    fn merging_a_proved_subgoal_adds_its_goal() {
+        with_state("known", |ctx, state, [_, q, _]| {
+            let subgoal = state.with_goal(q, ctx).unwrap();
+            let state = state.merge(&subgoal, None).unwrap();
+            assert!(state.knows(Fact::new(None, q), ctx));
+            assert!(matches!(
+                state.audit_log.last(),
+                Some(AuditEntry { step: AuditStep::Subgoal(goal), .. }) if *goal == q
+            ));
+        });
+    }
+
+    #[test]
+    fn merging_an_unproved_subgoal_fails() {
+        with_state("known", |ctx, state, [p, _, _]| {
+            let subgoal = state.with_goal(p, ctx).unwrap();
+            assert_eq!(
+                state.merge(&subgoal, None).err(),
+                Some(ProofError::SubgoalIncomplete)
+            );
+            assert!(!state.knows(Fact::new(None, p), ctx));
+        });
+    }
+
+    #[test]
+    fn merging_a_subgoal_under_other_assumptions_fails() {
+        with_state("known", |ctx, state, [p, q, _]| {
+            // `q` is known under the assumption too, but the subgoal's
+            // conclusion would only hold under `p`.
+            let assumed = state.add_assumption(p, None, ctx).unwrap();
+            let subgoal = assumed.with_goal(q, ctx).unwrap();
+            assert_eq!(
+                state.merge(&subgoal, None).err(),
+                Some(ProofError::SubgoalElsewhere)
+            );
+            assert_eq!(
+                subgoal.merge(&state.with_goal(q, ctx).unwrap(), None).err(),
+                Some(ProofError::SubgoalElsewhere)
+            );
+        });
+    }
+}
@@ -1,8 +1,15 @@
-use std::ops::Index;
+use std::{ops::Index, time::Duration};
 
 use rustc_hash::{FxHashMap, FxHashSet};
 
-use crate::semant::{proof_kernel::ProofCertificate, theorems::TheoremId};
+use crate::{
+    diagnostics::FragRenderings,
+    parse::Span,
+    semant::{
+        proof_kernel::{AuditEntry, ProofCertificate, TodoNote},
+        theorems::TheoremId,
+    },
+};
 
 #[derive(Debug)]
 pub struct ProofStatuses<'ctx> {
@@ -11,8 +18,20 @@ pub struct ProofStatuses<'ctx> {
     axiom_cnt: usize,
     correct_cnt: usize,
     todo_cnt: usize,
-    /// Maps each todo reason to the number of theorems that used todo with that reason.
-    todo_by_reason: FxHashMap<Option<String>, usize>,
+    /// Correct proofs which don't use todo themselves but depend on a theorem
+    /// which does.
+    tainted_cnt: usize,
+    /// Correct proofs whose tactic marked them as fragile.
+    fragile_cnt: usize,
+    /// The theorems which used todo, grouped by the ticket tracking them.
+    todo_by_ticket: FxHashMap<Option<String>, TodoGroup>,
+    /// The consistency sentinel, if one was checked, and whether it failed to
+    /// prove as expected.
+    sentinel: Option<(TheoremId<'ctx>, bool)>,
+    /// When a filter limited which theorems were checked, how many were
+    /// filtered out and how many were skipped even though a checked theorem
+    /// uses them.
+    filtered: Option<(usize, usize)>,
 }
 
 impl<'ctx> ProofStatuses<'ctx> {
@@ -23,34 +42,45 @@ impl<'ctx> ProofStatuses<'ctx> {
             axiom_cnt: 0,
             correct_cnt: 0,
             todo_cnt: 0,
-            todo_by_reason: FxHashMap::default(),
+            tainted_cnt: 0,
+            fragile_cnt: 0,
+            todo_by_ticket: FxHashMap::default(),
+            sentinel: None,
+            filtered: None,
         }
     }
 
     pub fn add(&mut self, theorem: TheoremId<'ctx>, status: ProofStatus<'ctx>) {
+        if status.is_sentinel {
+            // The sentinel isn't counted alongside the real theorems.
+            self.sentinel = Some((theorem, status.correct));
+            self.statuses.insert(theorem, status);
+            return;
+        }
+
         self.theorem_cnt += !status.is_axiom as usize;
         self.axiom_cnt += status.is_axiom as usize;
         self.correct_cnt += status.correct as usize;
-        let uses_todo = status.correct && !status.todo_reasons.is_empty();
+        self.fragile_cnt += (status.correct && status.fragile.is_some()) as usize;
+        let uses_todo = status.correct && !status.todos.is_empty();
         self.todo_cnt += uses_todo as usize;
         if uses_todo {
-            for reason in &status.todo_reasons {
-                *self.todo_by_reason.entry(reason.clone()).or_insert(0) += 1;
+            let tickets: FxHashSet<_> = status.todos.iter().map(|todo| &todo.ticket).collect();
+            for ticket in tickets {
+                let group = self.todo_by_ticket.entry(ticket.clone()).or_default();
+                group.theorems += 1;
+                for todo in status.todos.iter().filter(|todo| &todo.ticket == ticket) {
+                    if !group.reasons.contains(&todo.reason) {
+                        group.reasons.push(todo.reason.clone());
+                    }
+                }
             }
         }
         self.statuses.insert(theorem, status);
     }
 
     pub fn total_cnt(&self) -> usize {
-        self.statuses.len()
-    }
-
-    pub fn theorem_cnt(&self) -> usize {
-        self.theorem_cnt
-    }
-
-    pub fn axiom_cnt(&self) -> usize {
-        self.axiom_cnt
+        self.theorem_cnt + self.axiom_cnt
     }
 
     pub fn correct_cnt(&self) -> usize {
@@ -61,14 +91,58 @@ impl<'ctx> ProofStatuses<'ctx> {
         self.todo_cnt
     }
 
-    pub fn todo_by_reason(&self) -> &FxHashMap<Option<String>, usize> {
-        &self.todo_by_reason
+    pub fn tainted_cnt(&self) -> usize {
+        self.tainted_cnt
+    }
+
+    /// Correct theorems which depend on no todo, directly or through the
+    /// theorems they use.
+    pub fn fully_proven_cnt(&self) -> usize {
+        self.correct_cnt - self.todo_cnt - self.tainted_cnt
+    }
+
+    pub fn fragile_cnt(&self) -> usize {
+        self.fragile_cnt
+    }
+
+    pub fn todo_by_ticket(&self) -> &FxHashMap<Option<String>, TodoGroup> {
+        &self.todo_by_ticket
     }
 
     pub fn error_cnt(&self) -> usize {
         self.total_cnt() - self.correct_cnt()
     }
 
+    pub fn sentinel(&self) -> Option<(TheoremId<'ctx>, bool)> {
+        self.sentinel
+    }
+
+    pub fn filtered(&self) -> Option<(usize, usize)> {
+        self.filtered
+    }
+
+    pub fn set_filtered(&mut self, filtered_out: usize, skipped: usize) {
+        self.filtered = Some((filtered_out, skipped));
+    }
+
+    pub fn get(&self, theorem: TheoremId<'ctx>) -> Option<&ProofStatus<'ctx>> {
+        self.statuses.get(&theorem)
+    }
+
+    pub fn set_axioms_used(&mut self, theorem: TheoremId<'ctx>, axioms: Vec<TheoremId<'ctx>>) {
+        if let Some(status) = self.statuses.get_mut(&theorem) {
+            status.axioms_used = axioms;
+        }
+    }
+
+    pub fn set_todo_sources(&mut self, theorem: TheoremId<'ctx>, sources: Vec<TheoremId<'ctx>>) {
+        if let Some(status) = self.statuses.get_mut(&theorem) {
+            let tainted = status.correct && !status.is_sentinel && !status.todo_used();
+            self.tainted_cnt += (tainted && !sources.is_empty()) as usize;
+            status.todo_sources = sources;
+        }
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&TheoremId<'ctx>, &ProofStatus<'ctx>)> {
         self.statuses.iter()
     }
@@ -82,55 +156,240 @@ impl<'ctx> Index<TheoremId<'ctx>> for ProofStatuses<'ctx> {
     }
 }
 
+/// The theorems which used todo for the same ticket.
+#[derive(Debug, Default)]
+pub struct TodoGroup {
+    theorems: usize,
+    /// The distinct reasons given, in the order they were first seen.
+    reasons: Vec<Option<String>>,
+}
+
+impl TodoGroup {
+    pub fn theorems(&self) -> usize {
+        self.theorems
+    }
+
+    pub fn reasons(&self) -> &[Option<String>] {
+        &self.reasons
+    }
+}
+
 #[derive(Debug)]
 pub struct ProofStatus<'ctx> {
     correct: bool,
-    todo_reasons: FxHashSet<Option<String>>,
+    /// Every use of todo, in the order they appear in the source.
+    todos: Vec<TodoNote>,
     is_axiom: bool,
+    /// For the consistency sentinel `correct` means it failed to prove.
+    is_sentinel: bool,
     theorems_used: FxHashSet<TheoremId<'ctx>>,
+    /// The steps the kernel took to prove the theorem.
+    audit_log: im::Vector<AuditEntry<'ctx>>,
+    /// Every axiom the theorem depends on, directly or through other
+    /// theorems, sorted by name.
+    axioms_used: Vec<TheoremId<'ctx>>,
+    /// Every correct theorem using todo which the theorem depends on,
+    /// including itself, sorted by name.
+    todo_sources: Vec<TheoremId<'ctx>>,
+    /// Why the tactic thinks the proof might break, like a search which only
+    /// just finished within its time limit.
+    fragile: Option<String>,
+    /// How long the tactic took and where the time went, for proofs which
+    /// aren't axioms.
+    duration: Option<ProofTiming>,
+    /// The prover strategy which proved a goal and how long it took, for
+    /// tactics which ran a prover portfolio.
+    prover: Option<(String, Duration)>,
+    /// How the project's `renderFrag` hook rendered the statement.
+    renderings: FragRenderings<'ctx>,
+}
+
+/// How long checking a proof took, split between the tactic's Lua code and
+/// the kernel operations it called.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProofTiming {
+    /// Running the tactic and checking the proof it built.
+    pub total: Duration,
+    /// Running the tactic, not counting the kernel operations it called.
+    pub lua: Duration,
+    /// The kernel operations, including checking the finished proof.
+    pub kernel: Duration,
 }
 
 impl<'ctx> ProofStatus<'ctx> {
     pub fn new_axiom() -> Self {
         Self {
             is_axiom: true,
+            is_sentinel: false,
             correct: true,
-            todo_reasons: FxHashSet::default(),
+            todos: Vec::new(),
             theorems_used: FxHashSet::default(),
+            audit_log: im::Vector::new(),
+            axioms_used: Vec::new(),
+            todo_sources: Vec::new(),
+            fragile: None,
+            duration: None,
+            prover: None,
+            renderings: FragRenderings::default(),
         }
     }
 
     pub fn new_error() -> Self {
         Self {
             is_axiom: false,
+            is_sentinel: false,
             correct: false,
-            todo_reasons: FxHashSet::default(),
+            todos: Vec::new(),
             theorems_used: FxHashSet::default(),
+            audit_log: im::Vector::new(),
+            axioms_used: Vec::new(),
+            todo_sources: Vec::new(),
+            fragile: None,
+            duration: None,
+            prover: None,
+            renderings: FragRenderings::default(),
         }
     }
 
-    pub fn from_cert(cert: ProofCertificate<'ctx>) -> Self {
+    /// Todos which don't say which part of the tactic used them are placed at
+    /// `tactic_span`.
+    pub fn from_cert(cert: ProofCertificate<'ctx>, tactic_span: Span) -> Self {
+        let mut todos: Vec<_> = cert
+            .todos()
+            .iter()
+            .map(|todo| TodoNote {
+                span: Some(todo.span.unwrap_or(tactic_span)),
+                ..todo.clone()
+            })
+            .collect();
+        todos.sort_by_key(|todo| todo.span.map(|span| span.start().byte_offset()));
+
         Self {
             is_axiom: false,
+            is_sentinel: false,
             correct: !cert.uses_error(),
-            todo_reasons: cert.todo_reasons().iter().cloned().collect(),
+            todos,
             theorems_used: cert.theorems_used().iter().copied().collect(),
+            audit_log: cert.audit_log().clone(),
+            axioms_used: Vec::new(),
+            todo_sources: Vec::new(),
+            fragile: None,
+            duration: None,
+            prover: None,
+            renderings: FragRenderings::default(),
         }
     }
 
-    pub fn _correct(&self) -> bool {
+    /// The status of the consistency sentinel. `held` is true when it failed
+    /// to prove, as it should. The theorems its proof used are kept so it
+    /// still takes part in the circularity and closure checks.
+    pub fn new_sentinel(held: bool, theorems_used: FxHashSet<TheoremId<'ctx>>) -> Self {
+        Self {
+            is_axiom: false,
+            is_sentinel: true,
+            correct: held,
+            todos: Vec::new(),
+            theorems_used,
+            audit_log: im::Vector::new(),
+            axioms_used: Vec::new(),
+            todo_sources: Vec::new(),
+            fragile: None,
+            duration: None,
+            prover: None,
+            renderings: FragRenderings::default(),
+        }
+    }
+
+    /// Record whether the tactic marked the proof as fragile.
+    pub fn with_fragile(mut self, fragile: Option<String>) -> Self {
+        self.fragile = fragile;
+        self
+    }
+
+    pub fn with_prover(mut self, prover: Option<(String, Duration)>) -> Self {
+        self.prover = prover;
+        self
+    }
+
+    pub fn with_timing(mut self, timing: ProofTiming) -> Self {
+        self.duration = Some(timing);
+        self
+    }
+
+    /// Count a proof which checked as failed, like a fragile proof with
+    /// `--deny-fragile` or one using todo with `--deny-todo`.
+    pub fn mark_failed(&mut self) {
+        self.correct = false;
+    }
+
+    pub fn with_frag_renderings(mut self, renderings: FragRenderings<'ctx>) -> Self {
+        self.renderings = renderings;
+        self
+    }
+
+    pub fn correct(&self) -> bool {
         self.correct
     }
 
-    pub fn _todo_used(&self) -> bool {
-        !self.todo_reasons.is_empty()
+    pub fn todo_used(&self) -> bool {
+        !self.todos.is_empty()
+    }
+
+    pub fn todos(&self) -> &[TodoNote] {
+        &self.todos
     }
 
-    pub fn _is_axiom(&self) -> bool {
+    /// Why the proof is fragile, if its tactic marked it as fragile.
+    pub fn fragile(&self) -> Option<&str> {
+        self.fragile.as_deref()
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration.map(|timing| timing.total)
+    }
+
+    /// The prover strategy which proved a goal and how long it took.
+    pub fn prover(&self) -> Option<(&str, Duration)> {
+        self.prover
+            .as_ref()
+            .map(|(strategy, duration)| (strategy.as_str(), *duration))
+    }
+
+    pub fn timing(&self) -> Option<ProofTiming> {
+        self.duration
+    }
+
+    pub fn frag_renderings(&self) -> &FragRenderings<'ctx> {
+        &self.renderings
+    }
+
+    pub fn is_axiom(&self) -> bool {
         self.is_axiom
     }
 
+    pub fn is_sentinel(&self) -> bool {
+        self.is_sentinel
+    }
+
     pub fn theorems_used(&self) -> &FxHashSet<TheoremId<'ctx>> {
         &self.theorems_used
     }
+
+    pub fn audit_log(&self) -> &im::Vector<AuditEntry<'ctx>> {
+        &self.audit_log
+    }
+
+    /// Empty until [`compute_axiom_closures`] has run.
+    ///
+    /// [`compute_axiom_closures`]: crate::semant::axiom_closure::compute_axiom_closures
+    pub fn axioms_used(&self) -> &[TheoremId<'ctx>] {
+        &self.axioms_used
+    }
+
+    /// Empty until [`compute_todo_closures`] has run.
+    ///
+    /// [`compute_todo_closures`]: crate::semant::axiom_closure::compute_todo_closures
+    pub fn todo_sources(&self) -> &[TheoremId<'ctx>] {
+        &self.todo_sources
+    }
 }
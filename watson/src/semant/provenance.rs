@@ -0,0 +1,169 @@
+use std::hash::{Hash, Hasher};
+
+use crate::parse::{SourceId, Span};
+
+/// The kind of command which declared a grammar item.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DeclKind {
+    SyntaxCategory,
+    Syntax,
+    Notation,
+    Alias,
+    GrammarCategory,
+    Tactic,
+    Attribute,
+}
+
+impl DeclKind {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            DeclKind::SyntaxCategory => "syntax_category",
+            DeclKind::Syntax => "syntax",
+            DeclKind::Notation => "notation",
+            DeclKind::Alias => "alias",
+            DeclKind::GrammarCategory => "grammar_category",
+            DeclKind::Tactic => "tactic",
+            DeclKind::Attribute => "attribute",
+        }
+    }
+}
+
+/// Where a grammar item was declared.
+///
+/// Provenance is not part of an item's identity, so it is ignored by equality
+/// and hashing.
+#[derive(Debug, Clone, Copy)]
+pub enum Provenance {
+    Builtin,
+    Declared { span: Span, kind: DeclKind },
+}
+
+impl Provenance {
+    pub fn declared(span: Span, kind: DeclKind) -> Self {
+        Self::Declared { span, kind }
+    }
+
+    /// The span of the declaring command.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Provenance::Builtin => None,
+            Provenance::Declared { span, .. } => Some(*span),
+        }
+    }
+
+    pub fn _source(&self) -> Option<SourceId> {
+        self.span().map(|span| span.source())
+    }
+
+    pub fn kind(&self) -> Option<DeclKind> {
+        match self {
+            Provenance::Builtin => None,
+            Provenance::Declared { kind, .. } => Some(*kind),
+        }
+    }
+}
+
+impl PartialEq for Provenance {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Provenance {}
+
+impl Hash for Provenance {
+    fn hash<H: Hasher>(&self, _state: &mut H) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// An info part of a diagnostic: its title, and the label and text of
+    /// the span it points at if it has one.
+    type Info = (String, Option<(String, String)>);
+
+    /// Each error from parsing `main` with its info parts.
+    fn errors(main: &str) -> Vec<(String, Vec<Info>)> {
+        let project = TestProject::with_logic(main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let ctx = parsed.ctx();
+
+        let info = |part: &crate::diagnostics::DiagnosticPart| {
+            let span = part.spans().first().map(|span| {
+                let text = ctx.sources.get_text(span.span().source());
+                (
+                    span.label().to_string(),
+                    text[span.span().bytes()].to_string(),
+                )
+            });
+            (part.title().to_string(), span)
+        };
+        (ctx.diags.iter())
+            .filter(|diag| diag.is_error())
+            .map(|diag| {
+                (
+                    diag.title().to_string(),
+                    diag.parts().skip(1).map(info).collect(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn duplicate_syntax_rules_point_at_the_first_declaration() {
+        let errors = errors(
+            "module logic\n\nsyntax implies\n    sentence ::= sentence \"⇒\" sentence\nend\n",
+        );
+        assert_eq!(errors.len(), 1);
+        let (title, infos) = &errors[0];
+        assert_eq!(title, "duplicate syntax rule `implies`");
+        let (info, span) = &infos[0];
+        assert_eq!(info, "`implies` is already declared");
+        let (label, text) = span.as_ref().unwrap();
+        assert_eq!(label, "declared by this `syntax` command");
+        assert!(
+            text.starts_with("syntax implies\n    sentence (100>)"),
+            "{text}"
+        );
+    }
+
+    #[test]
+    fn unknown_categories_suggest_declared_ones() {
+        let errors = errors(
+            "module logic\n\nsyntax_category term\n\n\
+             syntax equals\n    sentence ::= terms \"=\" term\nend\n",
+        );
+        let (title, infos) = &errors[0];
+        assert_eq!(title, "unknown formal syntax category `terms`");
+        let (info, span) = &infos[0];
+        assert_eq!(info, "did you mean `term`?");
+        let (label, text) = span.as_ref().unwrap();
+        assert_eq!(label, "declared by this `syntax_category` command");
+        assert_eq!(text, "syntax_category term");
+    }
+
+    #[test]
+    fn ambiguous_parses_list_where_each_rule_was_declared() {
+        let errors = errors(
+            "module logic\n\n\
+             tactic go.frag\n    tactic ::= @kw\"go\" f:@fragment(sentence)\nend\n\n\
+             tactic go.name\n    tactic ::= @kw\"go\" n:@name\nend\n\n\
+             theorem refl [p : sentence] :\n    |- p → p\nproof\n    go p\nqed\n",
+        );
+        let (title, infos) = &errors[0];
+        assert_eq!(title, "ambiguous parse");
+        let mut declared: Vec<_> = (infos.iter())
+            .map(|(info, span)| (info.as_str(), span.as_ref().unwrap().1.lines().next()))
+            .collect();
+        declared.sort();
+        assert_eq!(
+            declared,
+            [
+                ("could be parsed as `go.frag`", Some("tactic go.frag")),
+                ("could be parsed as `go.name`", Some("tactic go.name")),
+            ]
+        );
+    }
+}
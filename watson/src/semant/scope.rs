@@ -1,18 +1,27 @@
-use crate::semant::{
-    commands::CommandId, formal_syntax::FormalSyntaxCatId, notation::NotationBindingId,
-    presentation::PresFrag,
+use crate::{
+    diagnostics::{Diagnostic, DiagnosticSpan},
+    parse::Span,
+    semant::{
+        commands::CommandId,
+        formal_syntax::FormalSyntaxCatId,
+        notation::{NotationBindingId, NotationPreferences},
+        presentation::PresFrag,
+    },
 };
+use ustr::Ustr;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Scope<'ctx> {
     // TODO
     bindings: im::HashMap<NotationBindingId<'ctx>, Vec<ScopeEntry<'ctx>>>,
+    preferences: NotationPreferences,
 }
 
 impl<'ctx> Scope<'ctx> {
     pub fn new() -> Self {
         Self {
             bindings: im::HashMap::new(),
+            preferences: NotationPreferences::default(),
         }
     }
 
@@ -30,6 +39,67 @@ impl<'ctx> Scope<'ctx> {
             .or_insert_with(|| vec![entry]);
         Self {
             bindings: new_bindings,
+            preferences: self.preferences.clone(),
+        }
+    }
+
+    /// How ambiguities between notations are resolved in this scope.
+    pub fn preferences(&self) -> &NotationPreferences {
+        &self.preferences
+    }
+
+    pub fn with_preference(&self, preferred: Ustr, other: Ustr, span: Span) -> Self {
+        Self {
+            bindings: self.bindings.clone(),
+            preferences: self.preferences.with(preferred, other, span),
+        }
+    }
+
+    /// Check whether introducing `binding` at `span` would shadow a binding
+    /// that already resolves in this scope, returning a warning if it would.
+    /// Bindings whose names start with an underscore are allowed to shadow.
+    pub fn check_shadowing(
+        &self,
+        binding: NotationBindingId<'ctx>,
+        span: Span,
+    ) -> Option<Diagnostic<'ctx>> {
+        let intentional = binding
+            .name_instantiations()
+            .iter()
+            .any(|name| name.starts_with('_'));
+        if intentional {
+            return None;
+        }
+
+        let outer = self.lookup(binding)?;
+        Some(Diagnostic::warn_shadowed_binding(
+            binding,
+            span,
+            outer.origin(),
+        ))
+    }
+
+    /// Every binding with each entry it has had, including the ones which
+    /// have since been shadowed.
+    pub fn entries(&self) -> impl Iterator<Item = (NotationBindingId<'ctx>, &ScopeEntry<'ctx>)> {
+        self.bindings
+            .iter()
+            .flat_map(|(binding, entries)| entries.iter().map(|entry| (*binding, entry)))
+    }
+
+    /// Create a new scope with all the bindings and preferences of `other` on
+    /// top of the ones in this scope.
+    pub fn with_imported(&self, other: &Scope<'ctx>) -> Self {
+        let mut new_bindings = self.bindings.clone();
+        for (binding, entries) in &other.bindings {
+            new_bindings
+                .entry(*binding)
+                .and_modify(|existing| existing.extend(entries.iter().copied()))
+                .or_insert_with(|| entries.clone());
+        }
+        Self {
+            bindings: new_bindings,
+            preferences: self.preferences.with_imported(&other.preferences),
         }
     }
 }
@@ -39,11 +109,14 @@ pub struct ScopeEntry<'ctx> {
     replacement: ScopeReplacement<'ctx>,
     binding_depth: usize,
     source: DefinitionSource<'ctx>,
+    /// Where this entry was introduced, if it came from source text.
+    origin: Option<Span>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum DefinitionSource<'ctx> {
     DefinitionCmd(CommandId<'ctx>),
+    AbbreviationCmd(CommandId<'ctx>),
     SyntaxCmd(CommandId<'ctx>),
     Template,
     DefinitionHole,
@@ -63,6 +136,7 @@ impl<'ctx> ScopeEntry<'ctx> {
             replacement: ScopeReplacement::Frag(frag),
             binding_depth: 0,
             source,
+            origin: None,
         }
     }
 
@@ -75,6 +149,7 @@ impl<'ctx> ScopeEntry<'ctx> {
             replacement: ScopeReplacement::Hole(cat, idx),
             binding_depth: 0,
             source,
+            origin: None,
         }
     }
 
@@ -83,6 +158,11 @@ impl<'ctx> ScopeEntry<'ctx> {
         self
     }
 
+    pub fn with_origin(mut self, origin: Span) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
     pub fn replacement(&self) -> ScopeReplacement<'ctx> {
         self.replacement
     }
@@ -94,4 +174,93 @@ impl<'ctx> ScopeEntry<'ctx> {
     pub fn source(&self) -> DefinitionSource<'ctx> {
         self.source
     }
+
+    pub fn origin(&self) -> Option<Span> {
+        self.origin
+    }
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn warn_shadowed_binding(
+        binding: NotationBindingId<'ctx>,
+        span: Span,
+        outer: Option<Span>,
+    ) -> Diagnostic<'ctx> {
+        let mut diag = Diagnostic::new_warning(
+            &format!("`{}` shadows an existing binding", binding.print()),
+            vec![DiagnosticSpan::new_warning("", span)],
+        );
+
+        if let Some(outer) = outer {
+            diag = diag.with_info(
+                "previous binding introduced here",
+                vec![DiagnosticSpan::new_info("", outer)],
+            );
+        }
+
+        diag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+
+    /// Terms and a binder over them, and a sentence `q` defined as `⊥`.
+    const MAIN: &str = "module logic\n\n\
+        syntax_category term\n\n\
+        syntax equals\n    sentence (500) ::= term \"=\" term\nend\n\n\
+        syntax forall\n    sentence (50) ::= \"∀\" @binding(term) \",\" sentence\nend\n\n\
+        definition\n    q := ⊥\nend\n";
+
+    /// The warnings from parsing `MAIN` followed by `rest`, with the text
+    /// of their primary spans and of the spans they point at for the
+    /// previous binding.
+    fn warnings(rest: &str) -> Vec<(String, String, Option<String>)> {
+        let main = format!("{MAIN}\n{rest}");
+        let project = TestProject::with_logic(&main);
+        let arenas = Arenas::new();
+        let parsed = Driver::for_project(project.config(), &arenas).parse();
+        let ctx = parsed.ctx();
+        assert!(!ctx.diags.has_errors());
+
+        let text = |span: crate::parse::Span| main[span.bytes()].to_string();
+        (ctx.diags.iter())
+            .filter(|diag| !diag.is_error())
+            .map(|diag| {
+                let previous = diag.parts().nth(1).map(|part| part.spans()[0].span());
+                (
+                    diag.title().to_string(),
+                    text(diag.primary_span().unwrap()),
+                    previous.map(text),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn template_shadowing_a_definition_is_reported() {
+        let warnings = warnings("axiom shadow [q : sentence] :\n    |- q\nend\n");
+        assert_eq!(
+            warnings,
+            [(
+                "`q` shadows an existing binding".to_string(),
+                "q".to_string(),
+                Some("q".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn binder_shadowing_a_template_is_reported() {
+        let warnings = warnings("axiom shadow [x : term] :\n    |- ∀x, x = x\nend\n");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, "`x` shadows an existing binding");
+        assert_eq!(warnings[0].1, "x");
+    }
+
+    #[test]
+    fn underscored_names_may_shadow() {
+        assert!(warnings("axiom shadow [_x : term] :\n    |- ∀_x, _x = _x\nend\n").is_empty());
+    }
 }
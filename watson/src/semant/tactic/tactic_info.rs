@@ -1,13 +1,21 @@
-use crate::semant::{
-    notation::NotationBindingId,
-    presentation::PresFrag,
-    theorems::{PresFact, TheoremId},
+use crate::{
+    context::Ctx,
+    parse::Span,
+    semant::{
+        fragment::formal_frag,
+        notation::NotationBindingId,
+        presentation::PresFrag,
+        proof_kernel::{AuditStep, ProofState},
+        theorems::{PresFact, TheoremId},
+    },
 };
 
 #[derive(Debug, Clone)]
 pub struct TacticInfo<'ctx> {
     steps: im::Vector<TacticInfoStep<'ctx>>,
     goal: PresFrag<'ctx>,
+    /// The goal before it was last changed, if it has been.
+    previous_goal: Option<PresFrag<'ctx>>,
 }
 
 impl<'ctx> TacticInfo<'ctx> {
@@ -19,6 +27,7 @@ impl<'ctx> TacticInfo<'ctx> {
                 .map(|&h| TacticInfoStep::Hypothesis(h))
                 .collect(),
             goal: thm.conclusion(),
+            previous_goal: None,
         }
     }
 
@@ -30,21 +39,26 @@ impl<'ctx> TacticInfo<'ctx> {
         self.goal
     }
 
+    pub fn previous_goal(&self) -> Option<PresFrag<'ctx>> {
+        self.previous_goal
+    }
+
     fn add_step(&self, step: TacticInfoStep<'ctx>) -> Self {
         let mut new_steps = self.steps.clone();
         new_steps.push_back(step);
         Self {
             steps: new_steps,
             goal: self.goal,
+            previous_goal: self.previous_goal,
         }
     }
 
     pub fn with_assume(&self, f: PresFrag<'ctx>) -> Self {
-        self.add_step(TacticInfoStep::Assume(f))
+        self.add_step(TacticInfoStep::Assume(f, None))
     }
 
     pub fn with_deduce(&self, f: PresFact<'ctx>) -> Self {
-        self.add_step(TacticInfoStep::Deduce(f))
+        self.add_step(TacticInfoStep::Deduce(f, None))
     }
 
     pub fn with_let(
@@ -59,14 +73,74 @@ impl<'ctx> TacticInfo<'ctx> {
         Self {
             steps: self.steps.clone(),
             goal: f,
+            previous_goal: Some(self.goal),
         }
     }
 }
 
+/// The steps recorded by the kernel for a proof state. Unlike the steps in a
+/// `TacticInfo` these can't be left out by a tactic, but they only use formal
+/// syntax.
+pub fn kernel_steps<'ctx>(
+    proof: &ProofState<'ctx>,
+    ctx: &Ctx<'ctx>,
+) -> im::Vector<TacticInfoStep<'ctx>> {
+    let theorem = proof.theorem();
+    let hypotheses = theorem
+        .hypotheses()
+        .iter()
+        .map(|&h| TacticInfoStep::Hypothesis(h));
+    let steps = proof.audit_log().iter().map(|entry| {
+        // The theorem says more about an application than where it was.
+        let origin = match entry.step() {
+            AuditStep::ApplyTheorem { theorem, .. } => Some(StepOrigin::Theorem(*theorem)),
+            _ => entry.span().map(StepOrigin::Tactic),
+        };
+        match entry.step() {
+            AuditStep::Assume(assumption) => {
+                TacticInfoStep::Assume(formal_frag(*assumption, ctx), origin)
+            }
+            _ => {
+                let fact = entry.deduced();
+                let assumption = fact.assumption().map(|a| formal_frag(a, ctx));
+                let conclusion = formal_frag(fact.conclusion(), ctx);
+                TacticInfoStep::Deduce(PresFact::new(assumption, conclusion), origin)
+            }
+        }
+    });
+    hypotheses.chain(steps).collect()
+}
+
 #[derive(Debug, Clone)]
 pub enum TacticInfoStep<'ctx> {
     Hypothesis(PresFact<'ctx>),
-    Assume(PresFrag<'ctx>),
-    Deduce(PresFact<'ctx>),
+    /// An assumption and where it came from, if that is known. Only steps
+    /// recorded by the kernel know.
+    Assume(PresFrag<'ctx>, Option<StepOrigin<'ctx>>),
+    Deduce(PresFact<'ctx>, Option<StepOrigin<'ctx>>),
     Let(NotationBindingId<'ctx>, Option<PresFrag<'ctx>>),
 }
+
+/// What added a fact to the proof state, shown as a short tag after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOrigin<'ctx> {
+    /// A theorem the kernel applied.
+    Theorem(TheoremId<'ctx>),
+    /// The part of the tactic which took the step.
+    Tactic(Span),
+}
+
+impl<'ctx> TacticInfoStep<'ctx> {
+    /// The fragments printed when the step is shown in a proof state.
+    pub fn frags(&self) -> Vec<PresFrag<'ctx>> {
+        match self {
+            TacticInfoStep::Hypothesis(fact) | TacticInfoStep::Deduce(fact, _) => fact
+                .assumption()
+                .into_iter()
+                .chain([fact.conclusion()])
+                .collect(),
+            TacticInfoStep::Assume(frag, _) => vec![*frag],
+            TacticInfoStep::Let(_, replacement) => replacement.iter().copied().collect(),
+        }
+    }
+}
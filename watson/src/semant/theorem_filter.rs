@@ -0,0 +1,355 @@
+use crate::{
+    context::Ctx,
+    diagnostics::{Diagnostic, WResult},
+    parse::{RootReport, SourceId, source_cache::source_id_to_path},
+    semant::theorems::TheoremId,
+    util::{glob_match, similar_names},
+};
+use rustc_hash::{FxHashMap, FxHashSet};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+use ustr::Ustr;
+
+/// Limits which theorems are checked. A theorem is checked if any of the
+/// filters select it.
+#[derive(Debug, Clone)]
+pub struct TheoremFilter {
+    /// Globs over theorem names.
+    globs: Vec<String>,
+    /// Files whose theorems are checked.
+    files: Vec<PathBuf>,
+    /// Whether to check the theorems declared in files changed since the
+    /// last git commit.
+    changed: bool,
+    /// Whether to also check the theorems the selected theorems use.
+    with_deps: bool,
+}
+
+impl TheoremFilter {
+    /// A filter from the given options, or `None` if none of them limit
+    /// which theorems are checked.
+    pub fn new(
+        globs: Vec<String>,
+        files: Vec<PathBuf>,
+        changed: bool,
+        with_deps: bool,
+    ) -> Option<Self> {
+        if globs.is_empty() && files.is_empty() && !changed {
+            return None;
+        }
+
+        Some(Self {
+            globs,
+            files,
+            changed,
+            with_deps,
+        })
+    }
+
+    pub fn with_deps(&self) -> bool {
+        self.with_deps
+    }
+
+    /// Find the theorems selected by the filter. A glob or file which selects
+    /// nothing is an error since it is most likely a typo. Having no changed
+    /// files is not.
+    pub fn select<'ctx>(
+        &self,
+        roots: &[RootReport<'ctx>],
+        ctx: &Ctx<'ctx>,
+    ) -> WResult<'ctx, FxHashSet<TheoremId<'ctx>>> {
+        let theorems: Vec<_> = roots
+            .iter()
+            .flat_map(|root| root.theorems.iter().map(|(thm, _)| *thm))
+            .collect();
+
+        let mut paths = FxHashMap::default();
+        let mut path_of = |thm: TheoremId<'ctx>| {
+            let source = thm.name_span().source();
+            paths
+                .entry(source)
+                .or_insert_with(|| source_path(source, ctx.config.math_dir()))
+                .clone()
+        };
+
+        let mut selected = FxHashSet::default();
+        let mut errors = Vec::new();
+
+        for glob in &self.globs {
+            let matches: Vec<_> = theorems
+                .iter()
+                .filter(|thm| glob_match(glob, &thm.name()))
+                .collect();
+            if matches.is_empty() {
+                let names = theorems.iter().map(|thm| thm.name());
+                let mut examples = similar_names(glob, names.clone());
+                if examples.is_empty() {
+                    examples = names.collect();
+                    examples.sort_by_key(|name| name.as_str());
+                    examples.truncate(3);
+                }
+                errors.extend(
+                    Diagnostic::err_glob_matches_nothing::<()>(glob, &examples).unwrap_err(),
+                );
+            }
+            selected.extend(matches);
+        }
+
+        for file in &self.files {
+            let file = file.canonicalize().unwrap_or_else(|_| file.clone());
+            let matches: Vec<_> = theorems
+                .iter()
+                .filter(|&&thm| path_of(thm).as_ref() == Some(&file))
+                .collect();
+            if matches.is_empty() {
+                let mut examples: Vec<_> = theorems
+                    .iter()
+                    .filter_map(|&thm| path_of(thm))
+                    .collect::<FxHashSet<_>>()
+                    .into_iter()
+                    .collect();
+                examples.sort();
+                examples.truncate(3);
+                errors.extend(
+                    Diagnostic::err_file_matches_nothing::<()>(&file, &examples).unwrap_err(),
+                );
+            }
+            selected.extend(matches);
+        }
+
+        if self.changed {
+            match changed_files(ctx.config.math_dir()) {
+                Ok(files) => {
+                    let matches = theorems
+                        .iter()
+                        .filter(|&&thm| path_of(thm).is_some_and(|path| files.contains(&path)));
+                    selected.extend(matches);
+                }
+                Err(reason) => {
+                    errors.extend(Diagnostic::err_changed_files::<()>(&reason).unwrap_err());
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(selected)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// The file a source was loaded from, if it was loaded from a file.
+fn source_path(source: SourceId, math_dir: &Path) -> Option<PathBuf> {
+    let (path1, path2) = source_id_to_path(source, math_dir);
+    path1.canonicalize().or_else(|_| path2.canonicalize()).ok()
+}
+
+/// The files in `dir` which have changed since the last git commit, using
+/// `git diff`.
+fn changed_files(dir: &Path) -> Result<FxHashSet<PathBuf>, String> {
+    let output = Command::new("git")
+        .args(["diff", "--name-only", "--relative", "HEAD"])
+        .current_dir(dir)
+        .output()
+        .map_err(|e| format!("failed to run git: {e}"))?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr.lines().next().unwrap_or("git diff failed");
+        return Err(reason.trim_start_matches("fatal: ").to_string());
+    }
+
+    // Deleted files can't be canonicalized, but they don't declare any
+    // theorems either.
+    let files = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| dir.join(line).canonicalize().ok())
+        .collect();
+    Ok(files)
+}
+
+fn quoted_list(items: impl IntoIterator<Item = String>) -> String {
+    items
+        .into_iter()
+        .map(|item| format!("`{item}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl<'ctx> Diagnostic<'ctx> {
+    pub fn err_glob_matches_nothing<T>(glob: &str, examples: &[Ustr]) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(&format!("no theorems match `--only {glob}`"), vec![]);
+        if !examples.is_empty() {
+            let examples = quoted_list(examples.iter().map(|name| name.to_string()));
+            diag = diag.with_info(&format!("theorems include {examples}"), Vec::new());
+        }
+        Err(vec![diag])
+    }
+
+    pub fn err_file_matches_nothing<T>(file: &Path, examples: &[PathBuf]) -> WResult<'ctx, T> {
+        let mut diag = Diagnostic::new(
+            &format!("no theorems are declared in `{}`", file.display()),
+            vec![],
+        );
+        if !examples.is_empty() {
+            let examples = quoted_list(examples.iter().map(|path| path.display().to_string()));
+            diag = diag.with_info(&format!("theorems are declared in {examples}"), Vec::new());
+        }
+        Err(vec![diag])
+    }
+
+    pub fn err_changed_files<T>(reason: &str) -> WResult<'ctx, T> {
+        let diag = Diagnostic::new(
+            &format!("can't find changed files for `--changed`: {reason}"),
+            vec![],
+        );
+        Err(vec![diag])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TheoremFilter;
+    use crate::{context::Arenas, driver::Driver, util::test_project::TestProject};
+    use std::{path::PathBuf, process::Command};
+
+    const MAIN: &str = "module logic\n\nmodule order\n\n\
+        theorem main.thm :\n    |- ⊥ → ⊥\nproof\n    by order.trans\nqed\n";
+
+    const ORDER: &str = "module logic\n\n\
+        axiom order.ax :\n    |- ⊥ → ⊥\nend\n\n\
+        theorem order.refl :\n    |- ⊥ → ⊥\nproof\n    by order.ax\nqed\n\n\
+        theorem order.trans :\n    |- ⊥ → ⊥\nproof\n    by order.refl\nqed\n";
+
+    /// A project with the theorems of `MAIN` and `ORDER`, and the three
+    /// axioms of the propositional library.
+    fn project() -> TestProject {
+        let project = TestProject::with_logic(MAIN);
+        project.write("math/order.wats", ORDER);
+        project
+    }
+
+    /// What checking `project` with `filter` did.
+    #[derive(Debug, PartialEq)]
+    struct Checked {
+        /// The names of the checked theorems, sorted.
+        theorems: Vec<String>,
+        /// How many theorems were filtered out and how many skipped.
+        filtered: Option<(usize, usize)>,
+        /// The titles of the diagnostics.
+        diags: Vec<String>,
+    }
+
+    fn check(project: &TestProject, filter: TheoremFilter) -> Checked {
+        let mut config = project.config();
+        config.set_theorem_filter(filter);
+        let arenas = Arenas::new();
+        let checked = Driver::for_project(config, &arenas).parse().check();
+
+        let statuses = &checked.proof_report().statuses;
+        let mut theorems: Vec<_> = statuses
+            .iter()
+            .map(|(thm, _)| thm.name().to_string())
+            .collect();
+        theorems.sort();
+        let diags = (checked.ctx().diags.iter())
+            .map(|diag| diag.title().to_string())
+            .collect();
+        Checked {
+            theorems,
+            filtered: statuses.filtered(),
+            diags,
+        }
+    }
+
+    fn globs(globs: &[&str]) -> TheoremFilter {
+        let globs = globs.iter().map(|glob| glob.to_string()).collect();
+        TheoremFilter::new(globs, Vec::new(), false, false).unwrap()
+    }
+
+    #[test]
+    fn globs_select_theorems_by_name() {
+        let checked = check(&project(), globs(&["order.*"]));
+        assert_eq!(checked.theorems, ["order.ax", "order.refl", "order.trans"]);
+        assert_eq!(checked.diags, Vec::<String>::new());
+
+        let checked = check(&project(), globs(&["main.t?m"]));
+        assert_eq!(checked.theorems, ["main.thm"]);
+        assert_eq!(checked.filtered, Some((5, 1)));
+    }
+
+    #[test]
+    fn files_select_the_theorems_declared_in_them() {
+        let project = project();
+        let files = vec![project.path("math/order.wats")];
+        let filter = TheoremFilter::new(Vec::new(), files, false, false).unwrap();
+        let checked = check(&project, filter);
+        assert_eq!(checked.theorems, ["order.ax", "order.refl", "order.trans"]);
+        assert_eq!(checked.filtered, Some((4, 0)));
+    }
+
+    #[test]
+    fn filters_select_the_union_of_their_theorems() {
+        let project = project();
+        let globs = vec!["order.refl".to_string()];
+        let files = vec![project.path("math/main.wats")];
+        let filter = TheoremFilter::new(globs, files, false, false).unwrap();
+        let checked = check(&project, filter);
+        assert_eq!(checked.theorems, ["main.thm", "order.refl"]);
+        assert_eq!(checked.filtered, Some((3, 2)));
+    }
+
+    #[test]
+    fn with_deps_checks_the_theorems_used() {
+        let filter = TheoremFilter::new(vec!["main.thm".to_string()], Vec::new(), false, true);
+        let checked = check(&project(), filter.unwrap());
+        assert_eq!(
+            checked.theorems,
+            ["main.thm", "order.ax", "order.refl", "order.trans"]
+        );
+        assert_eq!(checked.filtered, Some((3, 0)));
+    }
+
+    #[test]
+    fn filters_which_match_nothing_are_errors() {
+        let project = project();
+        let checked = check(&project, globs(&["ordr.*"]));
+        assert_eq!(checked.theorems, Vec::<String>::new());
+        assert_eq!(checked.diags, ["no theorems match `--only ordr.*`"]);
+
+        let files = vec![PathBuf::from("/nowhere.wats")];
+        let filter = TheoremFilter::new(Vec::new(), files, false, false).unwrap();
+        let checked = check(&project, filter);
+        assert_eq!(
+            checked.diags,
+            ["no theorems are declared in `/nowhere.wats`"]
+        );
+    }
+
+    #[test]
+    fn changed_selects_the_theorems_of_changed_files() {
+        let project = project();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(project.path(""))
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?}");
+        };
+        git(&["init", "-q"]);
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "init"]);
+        project.write("math/order.wats", &format!("{ORDER}\n"));
+
+        let filter = TheoremFilter::new(Vec::new(), Vec::new(), true, false).unwrap();
+        let checked = check(&project, filter);
+        assert_eq!(checked.theorems, ["order.ax", "order.refl", "order.trans"]);
+        assert_eq!(checked.diags, Vec::<String>::new());
+    }
+}
@@ -1,15 +1,18 @@
 use crate::{
     context::{Ctx, arena::ScopeId},
     generate_arena_handle,
+    parse::Span,
     semant::{
         formal_syntax::FormalSyntaxCatId,
         fragment::{Fact, FragHead, Fragment, hole_frag},
         notation::{_debug_binding, NotationBindingId},
-        presentation::{Pres, PresFrag, PresHead},
+        presentation::{Pres, PresFrag, PresHead, instantiate_templates},
         scope::{DefinitionSource, Scope, ScopeEntry},
     },
+    strings,
 };
 use itertools::Itertools;
+use std::{fmt, iter};
 use ustr::Ustr;
 
 generate_arena_handle!(TheoremId<'ctx> => TheoremStatement<'ctx>);
@@ -17,33 +20,105 @@ generate_arena_handle!(TheoremId<'ctx> => TheoremStatement<'ctx>);
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TheoremStatement<'ctx> {
     name: Ustr,
+    name_span: Span,
     templates: Vec<Template<'ctx>>,
     hypotheses: Vec<PresFact<'ctx>>,
+    /// Where each hypothesis is in the declaration.
+    hypothesis_spans: Vec<Span>,
     conclusion: PresFrag<'ctx>,
     scope: ScopeId,
+    /// Which keyword declared the statement.
+    kind: TheoremKind,
+    /// Whether this is an axiom which theorems should stop depending on.
+    deprecated: bool,
+    /// What to use instead, given with `@[deprecated("...")]`.
+    deprecation_note: Option<Ustr>,
+}
+
+/// The kinds of statement. Apart from axioms, which have no proof, they all
+/// behave the same and only differ in how they are presented.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TheoremKind {
+    Axiom,
+    Theorem,
+    Lemma,
+    Proposition,
+    Corollary,
+}
+
+impl TheoremKind {
+    /// The keyword which declares this kind of statement. Synonyms like
+    /// `postulate` give the same kind as the main keyword.
+    pub fn keyword(self) -> &'static str {
+        match self {
+            TheoremKind::Axiom => "axiom",
+            TheoremKind::Theorem => "theorem",
+            TheoremKind::Lemma => "lemma",
+            TheoremKind::Proposition => "proposition",
+            TheoremKind::Corollary => "corollary",
+        }
+    }
+
+    /// The name of the kind as it is shown to readers.
+    pub fn label(self) -> &'static str {
+        match self {
+            TheoremKind::Axiom => "Axiom",
+            TheoremKind::Theorem => "Theorem",
+            TheoremKind::Lemma => "Lemma",
+            TheoremKind::Proposition => "Proposition",
+            TheoremKind::Corollary => "Corollary",
+        }
+    }
 }
 
 impl<'ctx> TheoremStatement<'ctx> {
     pub fn new(
         name: Ustr,
+        name_span: Span,
         templates: Vec<Template<'ctx>>,
         hypotheses: Vec<PresFact<'ctx>>,
         conclusion: PresFrag<'ctx>,
         scope: ScopeId,
+        kind: TheoremKind,
     ) -> Self {
         Self {
             name,
+            name_span,
             templates,
             hypotheses,
+            hypothesis_spans: Vec::new(),
             conclusion,
             scope,
+            kind,
+            deprecated: false,
+            deprecation_note: None,
         }
     }
 
+    pub fn with_hypothesis_spans(mut self, spans: Vec<Span>) -> Self {
+        self.hypothesis_spans = spans;
+        self
+    }
+
+    pub fn with_deprecated(mut self, deprecated: bool) -> Self {
+        self.deprecated = deprecated;
+        self
+    }
+
+    pub fn with_deprecation_note(mut self, note: Option<Ustr>) -> Self {
+        self.deprecation_note = note;
+        self
+    }
+
     pub fn name(&self) -> Ustr {
         self.name
     }
 
+    /// Where the name is in the theorem's declaration.
+    pub fn name_span(&self) -> Span {
+        self.name_span
+    }
+
     pub fn templates(&self) -> &[Template<'ctx>] {
         &self.templates
     }
@@ -52,13 +127,143 @@ impl<'ctx> TheoremStatement<'ctx> {
         &self.hypotheses
     }
 
+    /// Where the hypothesis at `idx` is in the declaration, if the statement
+    /// was declared in a source.
+    pub fn hypothesis_span(&self, idx: usize) -> Option<Span> {
+        self.hypothesis_spans.get(idx).copied()
+    }
+
     pub fn conclusion(&self) -> PresFrag<'ctx> {
         self.conclusion
     }
 
+    pub fn kind(&self) -> TheoremKind {
+        self.kind
+    }
+
+    pub fn deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    pub fn deprecation_note(&self) -> Option<Ustr> {
+        self.deprecation_note
+    }
+
+    /// The parts of the statement in the order they are written in the
+    /// declaration: the templates, then the hypotheses, then the conclusion.
+    pub fn parts(&self) -> impl Iterator<Item = StatementPart<'_, 'ctx>> {
+        let templates = self.templates.iter().map(StatementPart::Template);
+        let hypotheses = self.hypotheses.iter().map(StatementPart::Hypothesis);
+        templates
+            .chain(hypotheses)
+            .chain(iter::once(StatementPart::Conclusion(self.conclusion)))
+    }
+
+    /// The templates which have defaults, followed by the hypotheses and
+    /// conclusion as they are written in the declaration.
+    pub fn print(&self) -> String {
+        let mut out = String::new();
+        for template in &self.templates {
+            if let Some(default) = template.default() {
+                out.push_str(&format!(
+                    "[{} := {} : {}] ",
+                    template.binding().print(),
+                    default.print(),
+                    template.cat().name()
+                ));
+            }
+        }
+        for hypothesis in &self.hypotheses {
+            out.push_str(&format!("({}) ", hypothesis.print()));
+        }
+        out.push_str(&format!("|- {}", self.conclusion.print()));
+        out
+    }
+
     pub fn scope(&self) -> ScopeId {
         self.scope
     }
+
+    /// The templates to apply the theorem with when some are given by
+    /// position and some by name. Templates which aren't given take their
+    /// default, which may refer to the templates before them.
+    pub fn fill_templates(
+        &self,
+        positional: &[PresFrag<'ctx>],
+        named: &[(Ustr, PresFrag<'ctx>)],
+        ctx: &Ctx<'ctx>,
+    ) -> Result<Vec<PresFrag<'ctx>>, TemplateArgsError> {
+        if positional.len() > self.templates.len() {
+            return Err(TemplateArgsError::TooMany {
+                expected: self.templates.len(),
+                found: positional.len(),
+            });
+        }
+
+        let mut args = positional.iter().copied().map(Some).collect_vec();
+        args.resize(self.templates.len(), None);
+        for &(name, frag) in named {
+            let Some(idx) = self.templates.iter().position(|t| t.is_named(name)) else {
+                return Err(TemplateArgsError::UnknownName(name));
+            };
+            if args[idx].is_some() {
+                return Err(TemplateArgsError::GivenTwice(name));
+            }
+            args[idx] = Some(frag);
+        }
+
+        let mut filled = Vec::new();
+        for (template, arg) in self.templates.iter().zip(args) {
+            let arg = match (arg, template.default()) {
+                (Some(arg), _) => arg,
+                (None, Some(default)) => instantiate_templates(default, &|idx| filled[idx], ctx),
+                (None, None) => {
+                    return Err(TemplateArgsError::Missing(template.binding().print()));
+                }
+            };
+            filled.push(arg);
+        }
+        Ok(filled)
+    }
+}
+
+/// A part of a theorem's statement, as walked by [`TheoremStatement::parts`].
+#[derive(Debug, Clone, Copy)]
+pub enum StatementPart<'a, 'ctx> {
+    Template(&'a Template<'ctx>),
+    Hypothesis(&'a PresFact<'ctx>),
+    Conclusion(PresFrag<'ctx>),
+}
+
+/// Why the templates given when applying a theorem couldn't be filled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateArgsError {
+    TooMany {
+        expected: usize,
+        found: usize,
+    },
+    UnknownName(Ustr),
+    GivenTwice(Ustr),
+    /// A template without a default wasn't given.
+    Missing(String),
+}
+
+impl fmt::Display for TemplateArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateArgsError::TooMany { expected, found } => {
+                write!(f, "expected at most {expected} templates but found {found}")
+            }
+            TemplateArgsError::UnknownName(name) => write!(f, "no template named `{name}`"),
+            TemplateArgsError::GivenTwice(name) => {
+                write!(f, "template `{name}` was given more than once")
+            }
+            TemplateArgsError::Missing(binding) => write!(
+                f,
+                "template `{binding}` has no default so it must be given, by position or by name"
+            ),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -66,6 +271,10 @@ pub struct Template<'ctx> {
     cat: FormalSyntaxCatId<'ctx>,
     binding: NotationBindingId<'ctx>,
     holes: Vec<NotationBindingId<'ctx>>,
+    span: Span,
+    /// What the template is instantiated with when an application doesn't
+    /// give it. It is parsed in the theorem's scope.
+    default: Option<PresFrag<'ctx>>,
 }
 
 impl<'ctx> Template<'ctx> {
@@ -73,14 +282,22 @@ impl<'ctx> Template<'ctx> {
         cat: FormalSyntaxCatId<'ctx>,
         binding: NotationBindingId<'ctx>,
         holes: Vec<NotationBindingId<'ctx>>,
+        span: Span,
     ) -> Self {
         Self {
             cat,
             binding,
             holes,
+            span,
+            default: None,
         }
     }
 
+    pub fn with_default(mut self, default: PresFrag<'ctx>) -> Self {
+        self.default = Some(default);
+        self
+    }
+
     pub fn binding(&self) -> NotationBindingId<'ctx> {
         self.binding
     }
@@ -92,6 +309,19 @@ impl<'ctx> Template<'ctx> {
     pub fn holes(&self) -> &[NotationBindingId<'ctx>] {
         &self.holes
     }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn default(&self) -> Option<PresFrag<'ctx>> {
+        self.default
+    }
+
+    /// Whether an application can give this template as `name`.
+    pub fn is_named(&self, name: Ustr) -> bool {
+        self.binding.name_instantiations().contains(&name)
+    }
 }
 
 pub fn add_templates_to_scope<'ctx>(
@@ -123,8 +353,12 @@ pub fn add_templates_to_scope<'ctx>(
     let mut my_scope = parent_scope.clone();
 
     for (i, template) in templates.iter().enumerate() {
+        if let Some(warning) = my_scope.check_shadowing(template.binding(), template.span()) {
+            ctx.diags.add_diag(warning);
+        }
+
         let frag = template_to_frag(template, i, ctx);
-        let entry = ScopeEntry::new(frag, DefinitionSource::Template);
+        let entry = ScopeEntry::new(frag, DefinitionSource::Template).with_origin(template.span());
         my_scope = my_scope.child_with(template.binding(), entry)
     }
 
@@ -173,17 +407,39 @@ impl<'ctx> PresFact<'ctx> {
             None => self.conclusion().print(),
         }
     }
+
+    /// Print the fact for people to read, as it is written in a declaration.
+    pub fn pretty(&self) -> String {
+        match self.assumption() {
+            Some(assumption) => format!(
+                "{} {} {} {}",
+                *strings::ASSUME,
+                assumption.pretty(),
+                *strings::TURNSTILE,
+                self.conclusion().pretty()
+            ),
+            None => self.conclusion().pretty(),
+        }
+    }
 }
 
 pub fn _debug_theorem<'ctx>(theorem: TheoremId<'ctx>) -> String {
     let mut out = String::new();
     out.push_str(&format!("Theorem: {}\n", theorem.name()));
     for template in theorem.templates() {
-        out.push_str(&format!(
-            "  [{} : {}]\n",
-            _debug_binding(template.binding()),
-            template.cat().name(),
-        ));
+        match template.default() {
+            Some(default) => out.push_str(&format!(
+                "  [{} := {} : {}]\n",
+                _debug_binding(template.binding()),
+                default.print(),
+                template.cat().name(),
+            )),
+            None => out.push_str(&format!(
+                "  [{} : {}]\n",
+                _debug_binding(template.binding()),
+                template.cat().name(),
+            )),
+        }
     }
     for hypothesis in theorem.hypotheses() {
         out.push_str(&format!("  ({})\n", hypothesis.print()));
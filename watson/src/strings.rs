@@ -7,44 +7,129 @@ macro_rules! str_const {
     };
 }
 
-// Keywords:
-str_const! {
-    END = "end";
-    MODULE = "module";
-    SYNTAX_CAT = "syntax_category";
-    SYNTAX = "syntax";
-    NOTATION = "notation";
-    DEFINITION = "definition";
-    AXIOM = "axiom";
-    THEOREM = "theorem";
-    LEMMA = "lemma";
-    COROLLARY = "corollary";
-    GRAMMAR_CATEGORY = "grammar_category";
-    TACTIC = "tactic";
-    NAME = "name";
-    KW = "kw";
-    PROOF = "proof";
-    QED = "qed";
-    ASSUME = "assume";
-    FRAGMENT = "fragment";
-    ANY_FRAGMENT = "any_fragment";
-    FACT = "fact";
+/// Declares the reserved words of the surface language, grouped by kind. Each
+/// gets a constant like [`str_const!`] and an entry in [`all_reserved`].
+macro_rules! reserved {
+    ($($kind:ident { $($name:ident = $str:literal;)* })*) => {
+        $($(pub static $name: LazyLock<Ustr> = LazyLock::new(|| Ustr::from($str));)*)*
+
+        const RESERVED: &[Reserved] = &[
+            $($(Reserved { text: $str, kind: ReservedKind::$kind },)*)*
+        ];
+    };
 }
 
-// Symbols:
-str_const! {
-    BNF_REPLACE = "::=";
-    ASSIGN = ":=";
-    AT = "@";
-    COLON = ":";
-    COMMA = ",";
-    TURNSTILE = "|-";
-    LEFT_PAREN = "(";
-    RIGHT_PAREN = ")";
-    LEFT_BRACKET = "[";
-    RIGHT_BRACKET = "]";
-    LEFT_ARROW = "<";
-    RIGHT_ARROW = ">";
+/// What part of the surface language a reserved word belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReservedKind {
+    /// A word which starts, ends or separates the parts of a command.
+    Keyword,
+    /// Punctuation matched as is.
+    Literal,
+    /// A word naming a builtin part of a pattern, like `@fragment`.
+    Structural,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Reserved {
+    pub text: &'static str,
+    pub kind: ReservedKind,
+}
+
+reserved! {
+    Keyword {
+        END = "end";
+        MODULE = "module";
+        SYNTAX_CAT = "syntax_category";
+        SYNTAX = "syntax";
+        NOTATION = "notation";
+        PREFER = "prefer";
+        OVER = "over";
+        ALIAS = "alias";
+        FOR = "for";
+        DEFINITION = "definition";
+        ABBREVIATION = "abbreviation";
+        AXIOM = "axiom";
+        POSTULATE = "postulate";
+        DEPRECATED = "deprecated";
+        THEOREM = "theorem";
+        LEMMA = "lemma";
+        PROPOSITION = "proposition";
+        COROLLARY = "corollary";
+        GRAMMAR_CATEGORY = "grammar_category";
+        TACTIC = "tactic";
+        ATTRIBUTE = "attribute";
+        PROOF = "proof";
+        QED = "qed";
+        USING = "using";
+        SCOPE = "scope";
+    }
+    Literal {
+        BNF_REPLACE = "::=";
+        ASSIGN = ":=";
+        AT = "@";
+        COLON = ":";
+        COMMA = ",";
+        TURNSTILE = "|-";
+        LEFT_PAREN = "(";
+        RIGHT_PAREN = ")";
+        LEFT_BRACKET = "[";
+        RIGHT_BRACKET = "]";
+        LEFT_ARROW = "<";
+        RIGHT_ARROW = ">";
+    }
+    Structural {
+        NAME = "name";
+        KW = "kw";
+        ASSUME = "assume";
+        FRAGMENT = "fragment";
+        ANY_FRAGMENT = "any_fragment";
+        FACT = "fact";
+        MANY = "many";
+        BINDING = "binding";
+        SENTENCE = "sentence";
+    }
+}
+
+// A word can only be reserved once, since the constants are compared to
+// tell which one the parser matched.
+const _: () = {
+    let mut i = 0;
+    while i < RESERVED.len() {
+        let mut j = i + 1;
+        while j < RESERVED.len() {
+            if same_text(RESERVED[i].text, RESERVED[j].text) {
+                panic!("a reserved word is declared twice");
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+};
+
+const fn same_text(a: &str, b: &str) -> bool {
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut i = 0;
+    while i < a.len() {
+        if a[i] != b[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Every reserved word of the surface language.
+pub fn all_reserved() -> impl Iterator<Item = Reserved> {
+    RESERVED.iter().copied()
+}
+
+/// Whether `text` is a command keyword, which names shouldn't reuse.
+pub fn is_reserved_keyword(text: &str) -> bool {
+    all_reserved().any(|reserved| reserved.kind == ReservedKind::Keyword && reserved.text == text)
 }
 
 // Lua names:
@@ -57,13 +142,35 @@ str_const! {
     UN_FACT = "UnResFact";
 }
 
-str_const! {
-    SENTENCE = "sentence";
-    BINDING = "binding";
-    ATTRIBUTE = "attribute";
-}
-
 str_const! {
     DIR_MOD_NAME = "main";
     FILE_EXTENSION = "wats";
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ReservedKind, all_reserved, is_reserved_keyword};
+
+    #[test]
+    fn only_command_keywords_are_reserved_names() {
+        assert!(is_reserved_keyword("axiom"));
+        assert!(is_reserved_keyword("qed"));
+        assert!(!is_reserved_keyword("sentence"));
+        assert!(!is_reserved_keyword("|-"));
+        assert!(!is_reserved_keyword("mp"));
+    }
+
+    #[test]
+    fn every_kind_has_reserved_words() {
+        for kind in [
+            ReservedKind::Keyword,
+            ReservedKind::Literal,
+            ReservedKind::Structural,
+        ] {
+            assert!(
+                all_reserved().any(|reserved| reserved.kind == kind),
+                "{kind:?}"
+            );
+        }
+    }
+}
@@ -1,4 +1,9 @@
 use convert_case::ccase;
+use ustr::Ustr;
+
+pub mod diff;
+#[cfg(test)]
+pub mod test_project;
 
 pub fn plural(n: usize) -> &'static str {
     if n == 1 { "" } else { "s" }
@@ -8,6 +13,68 @@ pub fn name_to_lua(name: &str) -> String {
     ccase!(snake -> pascal, name.replace('.', "_"))
 }
 
+/// The Levenshtein distance between two strings.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let sub = prev[j] + (ca != cb) as usize;
+            cur[j + 1] = sub.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// The candidates close enough to `name` to be likely typos of it, closest
+/// first.
+pub fn similar_names(name: &str, candidates: impl IntoIterator<Item = Ustr>) -> Vec<Ustr> {
+    let max_dist = (name.chars().count() / 3).max(1);
+    let mut similar: Vec<_> = candidates
+        .into_iter()
+        .map(|candidate| (edit_distance(name, &candidate), candidate))
+        .filter(|&(dist, _)| dist <= max_dist)
+        .collect();
+    similar.sort_by_key(|&(dist, candidate)| (dist, candidate.as_str()));
+    similar.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+/// Whether `text` matches the glob `pattern`, where `*` matches any run of
+/// characters and `?` matches any one character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // The position of the last `*` and the text it was matched against, so
+    // that we can backtrack and let it match one more character.
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
 pub mod ansi {
     pub const ANSI_RESET: &str = "\x1b[0m";
     pub const ANSI_RED: &str = "\x1b[91m";
@@ -15,4 +82,24 @@ pub mod ansi {
     pub const ANSI_YELLOW: &str = "\x1b[93m";
     pub const ANSI_GRAY: &str = "\x1b[90m";
     pub const ANSI_BOLD: &str = "\x1b[1m";
+
+    /// Remove the color and style escape codes from text meant for a
+    /// terminal.
+    pub fn strip_ansi(text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                // Skip to the end of the escape code.
+                for c in chars.by_ref() {
+                    if c == 'm' {
+                        break;
+                    }
+                }
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
 }
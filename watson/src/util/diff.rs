@@ -0,0 +1,367 @@
+//! Line-based diffs for showing how one version of a text differs from
+//! another.
+
+use crate::util::{
+    ansi::{ANSI_BOLD, ANSI_GRAY, ANSI_GREEN, ANSI_RED, ANSI_RESET},
+    plural,
+};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// One step of an edit script turning `old` into `new`, with the indices of
+/// the items it is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Keep(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// The shortest edit script turning `old` into `new`, found with Myers'
+/// algorithm.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let (n, m) = (old.len() as isize, new.len() as isize);
+    let max = n + m;
+    // `v[k]` is the furthest `x` reached on diagonal `k = x - y`.
+    let idx = |k: isize| (k + max + 1) as usize;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+
+    // The state before each round, to walk the path back from the end.
+    let mut trace = Vec::new();
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+                v[idx(k + 1)]
+            } else {
+                v[idx(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let prev_k = if k == -d || (k != d && v[idx(k - 1)] < v[idx(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[idx(prev_k)];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Keep(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit::Insert(prev_y as usize));
+            } else {
+                edits.push(Edit::Delete(prev_x as usize));
+            }
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    edits.reverse();
+    edits
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiffOptions {
+    /// The unchanged lines shown around each change.
+    pub context: usize,
+    /// The hunks shown before the rest are only counted.
+    pub max_hunks: usize,
+    pub color: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            context: 3,
+            max_hunks: 20,
+            color: false,
+        }
+    }
+}
+
+/// The differences between two texts as the hunks of a unified diff.
+#[derive(Debug, Clone, Serialize)]
+pub struct LineDiff {
+    hunks: Vec<Hunk>,
+    /// The hunks left out to keep the diff short.
+    more_hunks: usize,
+    #[serde(skip)]
+    color: bool,
+}
+
+/// A run of changed lines with the context around them. Lines are counted
+/// from 1 and keep their newline, so a change to only the newline at the end
+/// of the text still shows up.
+#[derive(Debug, Clone, Serialize)]
+pub struct Hunk {
+    old_start: usize,
+    old_len: usize,
+    new_start: usize,
+    new_len: usize,
+    lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "text", rename_all = "snake_case")]
+pub enum DiffLine {
+    Context(String),
+    Removed(String),
+    Added(String),
+}
+
+impl LineDiff {
+    pub fn new(old: &str, new: &str, options: &DiffOptions) -> Self {
+        let old_lines: Vec<_> = old.split_inclusive('\n').collect();
+        let new_lines: Vec<_> = new.split_inclusive('\n').collect();
+        let edits = diff(&old_lines, &new_lines);
+
+        // Group the changes whose context would touch or overlap.
+        let mut groups: Vec<(usize, usize)> = Vec::new();
+        for (i, edit) in edits.iter().enumerate() {
+            if matches!(edit, Edit::Keep(..)) {
+                continue;
+            }
+            let start = i.saturating_sub(options.context);
+            let end = (i + 1 + options.context).min(edits.len());
+            match groups.last_mut() {
+                Some((_, last_end)) if start <= *last_end => *last_end = end,
+                _ => groups.push((start, end)),
+            }
+        }
+
+        // The lines of each text before each edit, which is where a hunk
+        // starting at the edit starts even if it has no lines in that text.
+        let mut positions = Vec::with_capacity(edits.len());
+        let (mut old_pos, mut new_pos) = (0, 0);
+        for edit in &edits {
+            positions.push((old_pos, new_pos));
+            match edit {
+                Edit::Keep(..) => (old_pos, new_pos) = (old_pos + 1, new_pos + 1),
+                Edit::Delete(_) => old_pos += 1,
+                Edit::Insert(_) => new_pos += 1,
+            }
+        }
+
+        let more_hunks = groups.len().saturating_sub(options.max_hunks);
+        groups.truncate(options.max_hunks);
+        let hunks = groups
+            .into_iter()
+            .map(|(start, end)| {
+                make_hunk(&edits[start..end], positions[start], &old_lines, &new_lines)
+            })
+            .collect();
+
+        Self {
+            hunks,
+            more_hunks,
+            color: options.color,
+        }
+    }
+
+    /// The diff in the unified format, with `old_name` and `new_name` in the
+    /// header.
+    pub fn render(&self, old_name: &str, new_name: &str) -> String {
+        let paint = |color: &str, text: &str| {
+            if self.color {
+                format!("{color}{text}{ANSI_RESET}")
+            } else {
+                text.to_string()
+            }
+        };
+
+        let mut out = String::new();
+        writeln!(out, "{}", paint(ANSI_BOLD, &format!("--- {old_name}"))).unwrap();
+        writeln!(out, "{}", paint(ANSI_BOLD, &format!("+++ {new_name}"))).unwrap();
+        for hunk in &self.hunks {
+            let header = format!(
+                "@@ -{} +{} @@",
+                hunk_range(hunk.old_start, hunk.old_len),
+                hunk_range(hunk.new_start, hunk.new_len)
+            );
+            writeln!(out, "{}", paint(ANSI_GRAY, &header)).unwrap();
+
+            for line in &hunk.lines {
+                let (prefix, color, text) = match line {
+                    DiffLine::Context(text) => (' ', "", text),
+                    DiffLine::Removed(text) => ('-', ANSI_RED, text),
+                    DiffLine::Added(text) => ('+', ANSI_GREEN, text),
+                };
+                let body = format!("{prefix}{}", text.strip_suffix('\n').unwrap_or(text));
+                if color.is_empty() {
+                    writeln!(out, "{body}").unwrap();
+                } else {
+                    writeln!(out, "{}", paint(color, &body)).unwrap();
+                }
+                if !text.ends_with('\n') {
+                    writeln!(out, "\\ No newline at end of file").unwrap();
+                }
+            }
+        }
+
+        if self.more_hunks > 0 {
+            let trailer = format!(
+                "… {} more differing hunk{}",
+                self.more_hunks,
+                plural(self.more_hunks)
+            );
+            writeln!(out, "{}", paint(ANSI_GRAY, &trailer)).unwrap();
+        }
+        out
+    }
+}
+
+fn make_hunk(
+    edits: &[Edit],
+    start: (usize, usize),
+    old_lines: &[&str],
+    new_lines: &[&str],
+) -> Hunk {
+    let mut hunk = Hunk {
+        old_start: start.0 + 1,
+        old_len: 0,
+        new_start: start.1 + 1,
+        new_len: 0,
+        lines: Vec::new(),
+    };
+    for &edit in edits {
+        let line = match edit {
+            Edit::Keep(i, j) => {
+                debug_assert_eq!(old_lines[i], new_lines[j]);
+                hunk.old_len += 1;
+                hunk.new_len += 1;
+                DiffLine::Context(old_lines[i].to_string())
+            }
+            Edit::Delete(i) => {
+                hunk.old_len += 1;
+                DiffLine::Removed(old_lines[i].to_string())
+            }
+            Edit::Insert(j) => {
+                hunk.new_len += 1;
+                DiffLine::Added(new_lines[j].to_string())
+            }
+        };
+        hunk.lines.push(line);
+    }
+    hunk
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    // An empty range names the line before it.
+    match len {
+        0 => format!("{},0", start - 1),
+        1 => format!("{start}"),
+        n => format!("{start},{n}"),
+    }
+}
+
+/// Assert that `actual` is `expected`, showing how they differ as a diff
+/// rather than as two long strings when they don't. For golden tests.
+#[cfg(test)]
+#[track_caller]
+pub fn assert_text_eq(expected: &str, actual: &str) {
+    if expected != actual {
+        let diff = LineDiff::new(expected, actual, &DiffOptions::default());
+        panic!(
+            "text differs from expected\n{}",
+            diff.render("expected", "actual")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(old: &str, new: &str, options: &DiffOptions) -> String {
+        LineDiff::new(old, new, options).render("old", "new")
+    }
+
+    #[test]
+    fn empty_texts() {
+        assert_eq!(diff::<&str>(&[], &[]), Vec::new());
+        assert_text_eq(
+            "--- old\n+++ new\n",
+            &render("", "", &DiffOptions::default()),
+        );
+        assert_text_eq(
+            "--- old\n+++ new\n@@ -0,0 +1 @@\n+a\n",
+            &render("", "a\n", &DiffOptions::default()),
+        );
+    }
+
+    #[test]
+    fn identical_texts() {
+        let lines = ["x", "y"];
+        assert_eq!(
+            diff(&lines, &lines),
+            vec![Edit::Keep(0, 0), Edit::Keep(1, 1)]
+        );
+        assert_text_eq(
+            "--- old\n+++ new\n",
+            &render("x\ny\n", "x\ny\n", &DiffOptions::default()),
+        );
+    }
+
+    #[test]
+    fn single_line_change() {
+        assert_eq!(
+            diff(&["a", "b", "c"], &["a", "B", "c"]),
+            vec![
+                Edit::Keep(0, 0),
+                Edit::Delete(1),
+                Edit::Insert(1),
+                Edit::Keep(2, 2)
+            ]
+        );
+        assert_text_eq(
+            "--- old\n+++ new\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n",
+            &render("a\nb\nc\n", "a\nB\nc\n", &DiffOptions::default()),
+        );
+    }
+
+    #[test]
+    fn trailing_newline_change() {
+        assert_text_eq(
+            "--- old\n+++ new\n@@ -1 +1 @@\n-a\n+a\n\\ No newline at end of file\n",
+            &render("a\n", "a", &DiffOptions::default()),
+        );
+    }
+
+    #[test]
+    fn hunks_past_the_limit_are_counted() {
+        let options = DiffOptions {
+            context: 1,
+            max_hunks: 1,
+            color: false,
+        };
+        assert_text_eq(
+            "--- old\n+++ new\n@@ -1,2 +1,2 @@\n-a\n+A\n b\n… 1 more differing hunk\n",
+            &render("a\nb\nc\nd\ne\n", "A\nb\nc\nd\nE\n", &options),
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "-two\n+2")]
+    fn assert_text_eq_shows_a_diff() {
+        assert_text_eq("one\ntwo\n", "one\n2\n");
+    }
+}
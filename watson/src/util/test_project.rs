@@ -0,0 +1,87 @@
+//! Projects on disk for tests which run the whole pipeline.
+
+use crate::config::WatsonConfig;
+use std::{
+    env, fs,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// The propositional library `watson new` starts projects with.
+const LOGIC_WATS: &str = include_str!("../cli/new_templates/logic.wats");
+const LOGIC_LUAU: &str = include_str!("../cli/new_templates/main.luau");
+
+/// A handler which leaves every proof as a todo.
+const TODO_LUAU: &str = r#"local M = {}
+
+function M.handleTactic(tactic: Tactic, proofState: ProofState, tacticInfo: TacticInfo)
+    return proofState:applyTodo(proofState.goal)
+end
+
+M.tactics = {} :: TacticHandlers
+
+return M
+"#;
+
+/// A project in a fresh temporary directory, removed when it is dropped.
+pub struct TestProject {
+    dir: PathBuf,
+}
+
+impl TestProject {
+    /// A project whose `main.wats` is `main`, with a tactic handler which
+    /// leaves every proof as a todo.
+    pub fn new(main: &str) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let dir = env::temp_dir().join(format!("watson-test-{}-{id}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let project = Self { dir };
+        project.write("watson.toml", "");
+        project.write("script/main.luau", TODO_LUAU);
+        project.write("math/main.wats", main);
+        project
+    }
+
+    /// A project with the propositional library as `math/logic.wats` and
+    /// the tactic handler for it.
+    pub fn with_logic(main: &str) -> Self {
+        let project = Self::new(main);
+        project.write("script/main.luau", LOGIC_LUAU);
+        project.write("math/logic.wats", LOGIC_WATS);
+        project
+    }
+
+    /// Write `content` to `path` in the project, creating the directories
+    /// on the way.
+    pub fn write(&self, path: &str, content: &str) {
+        let path = self.dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// The absolute path of `path` in the project.
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.dir.join(path)).unwrap()
+    }
+
+    /// The project's config, without the parse cache so each run starts
+    /// from the files as they are.
+    pub fn config(&self) -> WatsonConfig {
+        let mut config = WatsonConfig::from_file(&self.dir.join("watson.toml")).unwrap();
+        config.set_parse_cache(false);
+        config
+    }
+}
+
+impl Drop for TestProject {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}